@@ -0,0 +1,179 @@
+// The .gitignore rules, matched here instead of asking git so a directory listing stays cheap.
+// Paths are relative to the top of the work tree with / separators, patterns from a .gitignore
+// deeper down only apply below it. Like git the last rule that matches wins.
+
+#[derive(Clone, Debug, Default)]
+pub struct GitIgnore {
+    pub rules: Vec<GitIgnoreRule>
+}
+
+#[derive(Clone, Debug)]
+pub struct GitIgnoreRule {
+    // the directory of the .gitignore, empty or ending in a /
+    pub base: String,
+    pub pattern: String,
+    pub negate: bool,
+    pub dir_only: bool,
+    // a / at the start or in the middle ties the pattern to base, otherwise it matches any name
+    pub anchored: bool
+}
+
+impl GitIgnore {
+    pub fn add_patterns(&mut self, base: &str, text: &str) {
+        for line in text.lines() {
+            let line = line.trim_end_matches('\r');
+            // trailing spaces dont count unless escaped
+            let line = if line.ends_with("\\ ") {line} else {line.trim_end_matches(' ')};
+            if line.len() == 0 || line.starts_with('#') {
+                continue;
+            }
+            let (negate, line) = if line.starts_with('!') {(true, &line[1..])} else {(false, line)};
+            let line = if line.starts_with("\\#") || line.starts_with("\\!") {&line[1..]} else {line};
+            let (dir_only, line) = if line.ends_with('/') {(true, line.trim_end_matches('/'))} else {(false, line)};
+            if line.len() == 0 {
+                continue;
+            }
+            let anchored = line.contains('/');
+            self.rules.push(GitIgnoreRule {
+                base: base.to_string(),
+                pattern: line.trim_start_matches('/').to_string(),
+                negate: negate,
+                dir_only: dir_only,
+                anchored: anchored
+            });
+        }
+    }
+    
+    // only looks at path itself, a listing that doesnt descend into ignored directories
+    // gets what is below them for free
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_end_matches('/');
+        for rule in self.rules.iter().rev() {
+            if rule.dir_only && !is_dir || !path.starts_with(&rule.base) {
+                continue;
+            }
+            let rel = &path[rule.base.len()..];
+            let matched = if rule.anchored {
+                glob_match(&rule.pattern, rel)
+            }
+            else {
+                glob_match(&rule.pattern, rel.rsplit('/').next().unwrap_or(rel))
+            };
+            if matched {
+                return !rule.negate
+            }
+        }
+        false
+    }
+}
+
+// * and ? stay within a path segment, ** spans any number of them, [a-z] and [!a] are classes
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_chars(&pattern, &text)
+}
+
+fn glob_match_chars(pattern: &[char], text: &[char]) -> bool {
+    if pattern.len() == 0 {
+        return text.len() == 0
+    }
+    match pattern[0] {
+        '*' if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            // **/ also matches nothing at all
+            if rest.first() == Some(&'/') && glob_match_chars(&rest[1..], text) {
+                return true
+            }
+            (0..=text.len()).any( | skip | glob_match_chars(rest, &text[skip..]))
+        },
+        '*' => {
+            let rest = &pattern[1..];
+            for skip in 0..=text.len() {
+                if glob_match_chars(rest, &text[skip..]) {
+                    return true
+                }
+                if skip < text.len() && text[skip] == '/' {
+                    break;
+                }
+            }
+            false
+        },
+        '?' => text.len() > 0 && text[0] != '/' && glob_match_chars(&pattern[1..], &text[1..]),
+        '[' => {
+            if text.len() == 0 || text[0] == '/' {
+                return false
+            }
+            match match_class(&pattern[1..], text[0]) {
+                Some((matched, len)) => matched && glob_match_chars(&pattern[1 + len..], &text[1..]),
+                // no closing ], its just a [
+                None => text[0] == '[' && glob_match_chars(&pattern[1..], &text[1..])
+            }
+        },
+        '\\' if pattern.len() > 1 => text.len() > 0 && text[0] == pattern[1] && glob_match_chars(&pattern[2..], &text[1..]),
+        c => text.len() > 0 && text[0] == c && glob_match_chars(&pattern[1..], &text[1..])
+    }
+}
+
+// whether c is in the class and how much of the pattern the class took, the ] included
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let (negate, start) = match pattern.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0)
+    };
+    let mut i = start;
+    let mut matched = false;
+    while i < pattern.len() {
+        if pattern[i] == ']' && i > start {
+            return Some((matched != negate, i + 1))
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            matched |= c >= pattern[i] && c <= pattern[i + 2];
+            i += 3;
+        }
+        else {
+            matched |= c == pattern[i];
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("src/*.rs", "src/main.rs"));
+        assert!(glob_match("**/main.rs", "main.rs"));
+        assert!(glob_match("**/main.rs", "a/b/main.rs"));
+        assert!(glob_match("a/**/b", "a/x/y/b"));
+        assert!(glob_match("a/**", "a/x/y"));
+        assert!(glob_match("file?.[ch]", "file1.c"));
+        assert!(!glob_match("file?.[!ch]", "file1.c"));
+        assert!(glob_match("[0-9]x", "7x"));
+        assert!(glob_match("a[", "a["));
+    }
+    
+    #[test]
+    fn last_rule_wins() {
+        let mut git_ignore = GitIgnore::default();
+        git_ignore.add_patterns("", "# build output\ntarget/\n*.log\n!keep.log\n/index.json\n");
+        git_ignore.add_patterns("web/", "node_modules\ndist/*.js\n");
+        assert!(git_ignore.is_ignored("target", true));
+        assert!(git_ignore.is_ignored("render/target", true));
+        // target/ only ignores directories
+        assert!(!git_ignore.is_ignored("target", false));
+        assert!(git_ignore.is_ignored("a/b.log", false));
+        assert!(!git_ignore.is_ignored("a/keep.log", false));
+        assert!(git_ignore.is_ignored("index.json", false));
+        assert!(!git_ignore.is_ignored("makepad/index.json", false));
+        assert!(git_ignore.is_ignored("web/node_modules", true));
+        assert!(!git_ignore.is_ignored("node_modules", true));
+        assert!(git_ignore.is_ignored("web/dist/app.js", false));
+        assert!(!git_ignore.is_ignored("dist/app.js", false));
+    }
+}
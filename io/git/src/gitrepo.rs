@@ -0,0 +1,277 @@
+// Reads what the editor needs from a git repository by running the git command line tool,
+// that way we get the exact behaviour of git (packs, worktrees, filters) for free.
+// Paths given to GitRepo are relative to the directory it was opened on, like TextBuffers paths.
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Debug)]
+pub struct GitRepo {
+    // the top of the work tree, all commands run there
+    pub root: String,
+    // where the directory we were opened on sits in the work tree, empty or ending in a /
+    pub prefix: String
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GitBlameLine {
+    // abbreviated hash
+    pub commit: String,
+    pub author: String,
+    // seconds since the epoch
+    pub time: u64,
+    pub summary: String
+}
+
+impl GitRepo {
+    // None when dir is not inside a git work tree or there is no git
+    pub fn open(dir: &str) -> Option<GitRepo> {
+        let output = Command::new("git")
+            .args(&["rev-parse", "--show-toplevel", "--show-prefix"])
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .output()
+            .ok() ?;
+        if !output.status.success() {
+            return None
+        }
+        let text = String::from_utf8(output.stdout).ok() ?;
+        let mut lines = text.lines();
+        let root = lines.next() ?.to_string();
+        let prefix = lines.next().unwrap_or("").to_string();
+        Some(GitRepo {
+            root: root,
+            prefix: prefix
+        })
+    }
+    
+    pub fn repo_path(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path.trim_start_matches("./"))
+    }
+    
+    pub fn run(&self, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+        let mut child = Command::new("git")
+            .args(args)
+            .current_dir(&self.root)
+            .stdin(if input.is_some() {Stdio::piped()} else {Stdio::null()})
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err( | err | format!("Cannot run git: {}", err)) ?;
+        if let Some(input) = input {
+            // dropping stdin after the write closes it so git sees the end
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(input).map_err( | err | format!("Cannot write to git: {}", err)) ?;
+        }
+        let output = child.wait_with_output().map_err( | err | format!("Cannot run git: {}", err)) ?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+        Ok(output.stdout)
+    }
+    
+    // the bytes of the file as it is in a revision, "" is the index. None if its not in there,
+    // decoding them is up to the caller so a file that isnt utf8 is not mistaken for a missing one
+    pub fn show_blob(&self, rev: &str, path: &str) -> Option<Vec<u8>> {
+        let object = format!("{}:{}", rev, self.repo_path(path));
+        self.run(&["cat-file", "blob", &object], None).ok()
+    }
+    
+    pub fn head_blob(&self, path: &str) -> Option<Vec<u8>> {
+        self.show_blob("HEAD", path)
+    }
+    
+    pub fn index_blob(&self, path: &str) -> Option<Vec<u8>> {
+        self.show_blob("", path)
+    }
+    
+    // who last touched every line of the file in HEAD
+    pub fn blame(&self, path: &str) -> Result<Vec<GitBlameLine>, String> {
+        let repo_path = self.repo_path(path);
+        let data = self.run(&["blame", "--porcelain", "HEAD", "--", &repo_path], None) ?;
+        Ok(parse_blame_porcelain(&String::from_utf8_lossy(&data)))
+    }
+    
+    // who last touched the line at row of the file in HEAD, blaming one line is much quicker
+    pub fn blame_line(&self, path: &str, row: usize) -> Result<Option<GitBlameLine>, String> {
+        let repo_path = self.repo_path(path);
+        let range = format!("{},{}", row + 1, row + 1);
+        let data = self.run(&["blame", "--porcelain", "-L", &range, "HEAD", "--", &repo_path], None) ?;
+        Ok(parse_blame_porcelain(&String::from_utf8_lossy(&data)).pop())
+    }
+    
+    // makes data the staged version of the file, without touching the work tree. data is what
+    // the file would hold in the work tree, git applies its filters (autocrlf and such) to it
+    pub fn stage_blob(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let repo_path = self.repo_path(path);
+        // keep the mode of whats staged now, an executable stays one
+        let staged = self.run(&["ls-files", "--stage", "--", &repo_path], None) ?;
+        let staged = String::from_utf8_lossy(&staged);
+        let mode = staged.split(' ').next().filter( | mode | mode.len() == 6).unwrap_or("100644").to_string();
+        let hash = self.run(&["hash-object", "-w", "--stdin", &format!("--path={}", repo_path)], Some(data)) ?;
+        let hash = String::from_utf8_lossy(&hash).trim().to_string();
+        self.run(&["update-index", "--add", "--cacheinfo", &format!("{},{},{}", mode, hash, repo_path)], None) ?;
+        Ok(())
+    }
+}
+
+// every line gets a header "<hash> <orig line> <final line> [<lines in group>]", the first time a
+// commit comes by its author and summary follow, and then the line itself after a tab
+pub fn parse_blame_porcelain(text: &str) -> Vec<GitBlameLine> {
+    let mut commits: HashMap<String, GitBlameLine> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut hash: Option<String> = None;
+    for line in text.lines() {
+        if line.starts_with('\t') {
+            if let Some(hash) = hash.take() {
+                lines.push(commits.get(&hash).cloned().unwrap_or_default());
+            }
+            continue
+        }
+        match &hash {
+            None => {
+                let new_hash = line.split(' ').next().unwrap_or("").to_string();
+                commits.entry(new_hash.clone()).or_insert_with( || GitBlameLine {
+                    commit: new_hash.chars().take(8).collect(),
+                    ..Default::default()
+                });
+                hash = Some(new_hash);
+            },
+            Some(hash) => {
+                let commit = commits.get_mut(hash).unwrap();
+                if let Some(author) = line.strip_prefix("author ") {
+                    commit.author = author.to_string();
+                }
+                else if let Some(time) = line.strip_prefix("author-time ") {
+                    commit.time = time.parse().unwrap_or(0);
+                }
+                else if let Some(summary) = line.strip_prefix("summary ") {
+                    commit.summary = summary.to_string();
+                }
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    
+    // a fresh repository in the temp dir, removed again when dropped
+    struct TempRepo {
+        dir: PathBuf
+    }
+    
+    impl TempRepo {
+        fn new(name: &str) -> TempRepo {
+            let dir = std::env::temp_dir().join(format!("makepad_git_{}_{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            let temp_repo = TempRepo {dir: dir};
+            temp_repo.git(&["init", "-q"]);
+            temp_repo.git(&["config", "user.name", "Tester"]);
+            temp_repo.git(&["config", "user.email", "tester@example.com"]);
+            temp_repo
+        }
+        
+        fn git(&self, args: &[&str]) {
+            let status = Command::new("git").args(args).current_dir(&self.dir).stdout(Stdio::null()).status().unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        }
+        
+        fn write(&self, path: &str, text: &str) {
+            std::fs::write(self.dir.join(path), text).unwrap();
+        }
+        
+        fn write_bytes(&self, path: &str, data: &[u8]) {
+            std::fs::write(self.dir.join(path), data).unwrap();
+        }
+        
+        fn commit(&self, message: &str) {
+            self.git(&["add", "-A"]);
+            self.git(&["commit", "-q", "-m", message]);
+        }
+    }
+    
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+    
+    #[test]
+    fn reads_head_index_and_blame() {
+        let temp_repo = TempRepo::new("read");
+        temp_repo.write("src/a.rs", "fn a() {}\nfn b() {}\n");
+        temp_repo.commit("first");
+        temp_repo.write("src/a.rs", "fn a() {}\nfn b() { 2 }\nfn c() {}\n");
+        temp_repo.commit("second");
+        temp_repo.write("src/a.rs", "changed in the work tree\n");
+        temp_repo.write("src/new.rs", "fn new() {}\n");
+        
+        // opened on a subdirectory, paths are relative to it
+        let repo = GitRepo::open(temp_repo.dir.join("src").to_str().unwrap()).unwrap();
+        assert_eq!(repo.prefix, "src/");
+        assert_eq!(repo.head_blob("a.rs").unwrap(), b"fn a() {}\nfn b() { 2 }\nfn c() {}\n");
+        assert_eq!(repo.index_blob("a.rs").unwrap(), b"fn a() {}\nfn b() { 2 }\nfn c() {}\n");
+        assert_eq!(repo.head_blob("new.rs"), None);
+        
+        let blame = repo.blame("a.rs").unwrap();
+        let summaries: Vec<&str> = blame.iter().map( | line | line.summary.as_str()).collect();
+        assert_eq!(summaries, vec!["first", "second", "second"]);
+        assert_eq!(blame[0].author, "Tester");
+        assert_eq!(blame[0].commit.len(), 8);
+        assert!(blame[0].time > 0);
+        assert!(repo.blame("new.rs").is_err());
+        assert_eq!(repo.blame_line("a.rs", 2).unwrap().unwrap().summary, "second");
+        assert_eq!(repo.blame_line("a.rs", 0).unwrap().unwrap(), blame[0]);
+    }
+    
+    #[test]
+    fn stages_text_without_touching_the_work_tree() {
+        let temp_repo = TempRepo::new("stage");
+        temp_repo.write("a.txt", "one\ntwo\n");
+        temp_repo.commit("first");
+        let repo = GitRepo::open(temp_repo.dir.to_str().unwrap()).unwrap();
+        
+        repo.stage_blob("a.txt", b"one\n2\n").unwrap();
+        assert_eq!(repo.index_blob("a.txt").unwrap(), b"one\n2\n");
+        assert_eq!(repo.head_blob("a.txt").unwrap(), b"one\ntwo\n");
+        assert_eq!(std::fs::read_to_string(temp_repo.dir.join("a.txt")).unwrap(), "one\ntwo\n");
+        
+        // a file git didnt know yet gets added
+        repo.stage_blob("b.txt", b"new\n").unwrap();
+        assert_eq!(repo.index_blob("b.txt").unwrap(), b"new\n");
+        
+        // bytes that arent utf8 are still there
+        temp_repo.write_bytes("c.txt", b"caf\xe9\r\n");
+        temp_repo.commit("latin1");
+        assert_eq!(repo.head_blob("c.txt").unwrap(), b"caf\xe9\r\n");
+    }
+    
+    #[test]
+    fn parses_porcelain_with_repeated_commits() {
+        let porcelain = "\
+1111111111111111111111111111111111111111 1 1 2
+author A
+author-time 100
+summary one
+filename a.txt
+\tline 1
+1111111111111111111111111111111111111111 2 2
+\tline 2
+2222222222222222222222222222222222222222 3 3 1
+author B
+author-time 200
+summary two
+filename a.txt
+\tline 3
+";
+        let blame = parse_blame_porcelain(porcelain);
+        assert_eq!(blame.len(), 3);
+        assert_eq!(blame[1], GitBlameLine {commit: "11111111".to_string(), author: "A".to_string(), time: 100, summary: "one".to_string()});
+        assert_eq!(blame[2].author, "B");
+    }
+}
@@ -0,0 +1,4 @@
+mod gitrepo;
+pub use crate::gitrepo::*;
+mod gitignore;
+pub use crate::gitignore::*;
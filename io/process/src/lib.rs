@@ -12,3 +12,8 @@ pub use crate::process_conpty::*;
 mod process_dummy;
 #[cfg(target_arch = "wasm32")]
 pub use crate::process_dummy::*; 
+
+#[cfg(not(target_arch = "wasm32"))]
+mod process_stdio;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::process_stdio::*;
@@ -15,3 +15,28 @@ impl Process {
         
     }
 }
+
+// no child processes in the browser
+pub struct StdioProcess {
+}
+
+impl StdioProcess {
+    pub fn spawn(_cmd: &str, _args: &[&str], _current_dir: &str, _signal: render::Signal, _signal_value: usize) -> std::io::Result<StdioProcess> {
+        Err(std::io::Error::new(std::io::ErrorKind::Other, "not supported in the browser"))
+    }
+    
+    pub fn write(&mut self, _data: &[u8]) -> std::io::Result<()> {
+        Ok(())
+    }
+    
+    pub fn read(&mut self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+    
+    pub fn read_timeout(&mut self, _timeout: std::time::Duration) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+    
+    pub fn kill(&mut self) {
+    }
+}
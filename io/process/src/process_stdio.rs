@@ -0,0 +1,85 @@
+// A child process talking over its stdin/stdout pipes, for tools that speak a protocol
+// like language servers. A thread reads stdout and hands the data over a channel, the
+// signal pokes the event loop so the owner knows to call read.
+use render::*;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+
+pub struct StdioProcess {
+    pub child: Child,
+    pub stdin: ChildStdin,
+    pub rx: mpsc::Receiver<Vec<u8>>
+}
+
+impl StdioProcess {
+    // an empty signal means nobody gets poked, read has to be polled
+    pub fn spawn(cmd: &str, args: &[&str], current_dir: &str, signal: Signal, signal_value: usize) -> std::io::Result<StdioProcess> {
+        let mut child = Command::new(cmd)
+            .args(args)
+            .current_dir(current_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn() ?;
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            loop {
+                let mut data = vec![0; 16384];
+                let n_bytes_read = stdout.read(&mut data).unwrap_or(0);
+                data.truncate(n_bytes_read);
+                if tx.send(data).is_err() {
+                    return
+                }
+                if !signal.is_empty() {
+                    Cx::post_signal(signal, signal_value);
+                }
+                if n_bytes_read == 0 {
+                    return
+                }
+            }
+        });
+        Ok(StdioProcess {
+            child: child,
+            stdin: stdin,
+            rx: rx
+        })
+    }
+    
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.stdin.write_all(data) ?;
+        self.stdin.flush()
+    }
+    
+    // everything read since the last call, an empty chunk means stdout got closed
+    pub fn read(&mut self) -> Vec<Vec<u8>> {
+        let mut datas = Vec::new();
+        while let Ok(data) = self.rx.try_recv() {
+            datas.push(data);
+        }
+        datas
+    }
+    
+    // waits for at most timeout for something to read, for when there is no event loop
+    pub fn read_timeout(&mut self, timeout: std::time::Duration) -> Vec<Vec<u8>> {
+        if let Ok(data) = self.rx.recv_timeout(timeout) {
+            let mut datas = vec![data];
+            datas.extend(self.read());
+            return datas
+        }
+        Vec::new()
+    }
+    
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for StdioProcess {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
@@ -0,0 +1,92 @@
+// A language server that only does what its script says, for testing the LspClient.
+// Usage: fake_lsp_server <script>, where every line of the script is one of
+//   method => result-json             answer requests of method with this result
+//   method -> notify-method params    send this notification when method comes in
+// $uri in the json is replaced by the textDocument uri of the incoming message.
+// initialize and shutdown get answered without a script line, exit exits.
+use editor::*;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+
+enum Rule {
+    Result {method: String, result: String},
+    Notify {method: String, notify_method: String, params: String}
+}
+
+fn parse_script(script: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in script.lines() {
+        let line = line.trim();
+        if line.len() == 0 || line.starts_with('#') {
+            continue
+        }
+        if let Some(pos) = line.find(" => ") {
+            rules.push(Rule::Result {method: line[0..pos].to_string(), result: line[pos + 4..].to_string()});
+        }
+        else if let Some(pos) = line.find(" -> ") {
+            let rest = &line[pos + 4..];
+            let split = rest.find(' ').unwrap_or(rest.len());
+            rules.push(Rule::Notify {
+                method: line[0..pos].to_string(),
+                notify_method: rest[0..split].to_string(),
+                params: rest[split..].trim().to_string()
+            });
+        }
+    }
+    rules
+}
+
+fn send(message: Value) {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = stdout.write_all(&lsp_frame(&message.to_string()));
+    let _ = stdout.flush();
+}
+
+fn fill(json: &str, uri: &str) -> Value {
+    serde_json::from_str(&json.replace("$uri", uri)).unwrap_or(Value::Null)
+}
+
+fn main() {
+    let script_path = std::env::args().nth(1).expect("usage: fake_lsp_server <script>");
+    let rules = parse_script(&std::fs::read_to_string(&script_path).expect("cannot read script"));
+    let mut reader = LspReader::default();
+    let mut stdin = std::io::stdin();
+    let mut data = vec![0; 4096];
+    loop {
+        let n_bytes_read = stdin.read(&mut data).unwrap_or(0);
+        if n_bytes_read == 0 {
+            return
+        }
+        reader.push(&data[0..n_bytes_read]);
+        while let Some(body) = reader.next_message() {
+            let message: Value = match serde_json::from_str(&body) {
+                Ok(message) => message,
+                Err(_) => continue
+            };
+            let method = message["method"].as_str().unwrap_or("");
+            let uri = message["params"]["textDocument"]["uri"].as_str().unwrap_or("");
+            let id = message["id"].clone();
+            if method == "exit" {
+                return
+            }
+            let mut answered = false;
+            for rule in &rules {
+                match rule {
+                    Rule::Result {method: rule_method, result} if rule_method == method && !id.is_null() && !answered => {
+                        send(json!({"jsonrpc": "2.0", "id": id, "result": fill(result, uri)}));
+                        answered = true;
+                    },
+                    Rule::Notify {method: rule_method, notify_method, params} if rule_method == method => {
+                        send(json!({"jsonrpc": "2.0", "method": notify_method, "params": fill(params, uri)}));
+                    },
+                    _ => ()
+                }
+            }
+            if !answered && !id.is_null() && method.len() > 0 {
+                let result = if method == "initialize" {json!({"capabilities": {}})} else {Value::Null};
+                send(json!({"jsonrpc": "2.0", "id": id, "result": result}));
+            }
+        }
+    }
+}
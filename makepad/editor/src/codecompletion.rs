@@ -0,0 +1,484 @@
+use render::*;
+use widget::*;
+use std::rc::Rc;
+use std::collections::HashSet;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::lspclient::*;
+use crate::lsppopup::*;
+
+// The completion list of the CodeEditor. It opens under the last cursor while you type a word,
+// asks its CompletionProviders for candidates and fuzzy filters them on the word so far.
+// Accepting replaces the word at every cursor through TextCursorSet::replace_text, snippets
+// get their placeholders expanded and Tab walks the tabstops afterwards.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompletionItem {
+    pub label: String,
+    pub detail: String,
+    pub insert_text: String,
+    // insert_text has $1 ${2:name} style tabstops
+    pub is_snippet: bool
+}
+
+// a source of candidates, start..cursor is the word being completed and prefix its text
+pub trait CompletionProvider {
+    fn completions(&self, text_buffer: &TextBuffer, start: usize, prefix: &str, items: &mut Vec<CompletionItem>);
+}
+
+// every identifier-like token in the buffer
+pub struct BufferWordCompletions;
+
+impl CompletionProvider for BufferWordCompletions {
+    fn completions(&self, text_buffer: &TextBuffer, start: usize, _prefix: &str, items: &mut Vec<CompletionItem>) {
+        let mut seen = HashSet::new();
+        for token_chunk in &text_buffer.token_chunks {
+            match token_chunk.token_type {
+                TokenType::Identifier | TokenType::Call | TokenType::TypeName | TokenType::Keyword
+                    | TokenType::Flow | TokenType::Looping | TokenType::Fn | TokenType::TypeDef => (),
+                _ => continue
+            }
+            // the word being typed isnt a candidate for itself
+            if token_chunk.offset == start || token_chunk.offset + token_chunk.len > text_buffer.flat_text.len() {
+                continue
+            }
+            let word: String = text_buffer.flat_text[token_chunk.offset..token_chunk.offset + token_chunk.len].iter().collect();
+            if word.len() > 1 && seen.insert(word.clone()) {
+                items.push(CompletionItem {
+                    label: word.clone(),
+                    detail: String::new(),
+                    insert_text: word,
+                    is_snippet: false
+                });
+            }
+        }
+    }
+}
+
+// whatever the language server answered for this word
+pub struct LspCompletions;
+
+impl CompletionProvider for LspCompletions {
+    fn completions(&self, text_buffer: &TextBuffer, start: usize, _prefix: &str, items: &mut Vec<CompletionItem>) {
+        if let Some(LspResponse::Completion {offset, items: lsp_items}) = &text_buffer.lsp_response {
+            if *offset > text_buffer.lines.char_count() || word_range_at(text_buffer, *offset).0 != start {
+                return
+            }
+            for item in lsp_items {
+                items.push(CompletionItem {
+                    label: item.label.clone(),
+                    detail: item.detail.clone(),
+                    insert_text: item.insert_text.clone(),
+                    is_snippet: item.is_snippet
+                });
+            }
+        }
+    }
+}
+
+// Expands $1, ${2:default}, ${3|one,two|} and $0 into plain text and the tabstop ranges in it,
+// in the order Tab visits them. Lines after the first get indent in front of them.
+pub fn expand_snippet(snippet: &str, indent: &str) -> (String, Vec<(usize, usize)>) {
+    let mut out = String::new();
+    let mut stops: Vec<(usize, usize, usize)> = Vec::new();
+    expand_snippet_into(&snippet.chars().collect::<Vec<char>>(), indent, &mut out, &mut stops);
+    stops.sort_by_key( | (number, _, _) | if *number == 0 {std::usize::MAX} else {*number});
+    let mut ranges: Vec<(usize, usize)> = stops.iter().map( | (_, start, end) | (*start, *end)).collect();
+    // without a $0 the cursor ends up after the snippet
+    if ranges.len() > 0 && stops.iter().all( | (number, _, _) | *number != 0) {
+        let end = out.chars().count();
+        ranges.push((end, end));
+    }
+    (out, ranges)
+}
+
+fn expand_snippet_into(chars: &[char], indent: &str, out: &mut String, stops: &mut Vec<(usize, usize, usize)>) {
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push(chars[i + 1]);
+                i += 2;
+            },
+            '\n' => {
+                out.push('\n');
+                out.push_str(indent);
+                i += 1;
+            },
+            '$' if i + 1 < chars.len() && chars[i + 1].is_ascii_digit() => {
+                let mut end = i + 1;
+                while end < chars.len() && chars[end].is_ascii_digit() {
+                    end += 1;
+                }
+                let number = chars[i + 1..end].iter().collect::<String>().parse().unwrap_or(0);
+                let pos = out.chars().count();
+                add_stop(stops, number, pos, pos);
+                i = end;
+            },
+            '$' if i + 1 < chars.len() && chars[i + 1] == '{' => {
+                // find the matching brace, placeholders can nest
+                let mut depth = 0;
+                let mut end = i;
+                while end < chars.len() {
+                    match chars[end] {
+                        '\\' => end += 1,
+                        '$' if end + 1 < chars.len() && chars[end + 1] == '{' => {
+                            depth += 1;
+                            end += 1;
+                        },
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break
+                            }
+                        },
+                        _ => ()
+                    }
+                    end += 1;
+                }
+                let inner = &chars[i + 2..end.min(chars.len())];
+                let digits = inner.iter().take_while( | ch | ch.is_ascii_digit()).count();
+                let start = out.chars().count();
+                if digits > 0 {
+                    let number = inner[0..digits].iter().collect::<String>().parse().unwrap_or(0);
+                    let rest = &inner[digits..];
+                    if rest.first() == Some(&':') {
+                        expand_snippet_into(&rest[1..], indent, out, stops);
+                    }
+                    else if rest.first() == Some(&'|') { // a choice, we take the first
+                        out.extend(rest[1..].iter().take_while( | ch | **ch != ',' && **ch != '|'));
+                    }
+                    add_stop(stops, number, start, out.chars().count());
+                }
+                else if let Some(colon) = inner.iter().position( | ch | *ch == ':') {
+                    // a variable with a default, we dont know any variables
+                    expand_snippet_into(&inner[colon + 1..], indent, out, stops);
+                }
+                i = end + 1;
+            },
+            '$' if i + 1 < chars.len() && (chars[i + 1].is_alphabetic() || chars[i + 1] == '_') => {
+                // a variable like $TM_FILENAME
+                i += 1;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+            },
+            ch => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+}
+
+// a number used twice is a mirror, we only visit the first
+fn add_stop(stops: &mut Vec<(usize, usize, usize)>, number: usize, start: usize, end: usize) {
+    if stops.iter().all( | (n, _, _) | *n != number) {
+        stops.push((number, start, end));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodeCompletionEvent {
+    None,
+    Handled,
+    CursorsChanged
+}
+
+#[derive(Clone)]
+pub struct CodeCompletion {
+    pub bg: Quad,
+    pub text: Text,
+    pub padding: f32,
+    pub max_items: usize,
+    pub max_cols: usize,
+    pub bg_color: Color,
+    pub label_color: Color,
+    pub selected_color: Color,
+    pub detail_color: Color,
+    pub providers: Vec<Rc<dyn CompletionProvider>>,
+    
+    pub is_open: bool,
+    // opened on request, stays open without a word typed
+    pub is_explicit: bool,
+    pub start: usize,
+    pub prefix: String,
+    pub items: Vec<CompletionItem>,
+    pub selected: usize,
+    // the tabstops of the last snippet that are still ahead
+    pub snippet_stops: Vec<(usize, usize)>,
+    pub _snippet_char_count: usize,
+    pub _snippet_anchor: usize
+}
+
+impl CodeCompletion {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            bg: Quad {
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 20.,
+                ..Quad::style(cx)
+            },
+            text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                do_dpi_dilate: true,
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 21.,
+                wrapping: Wrapping::Line,
+                ..Text::style(cx)
+            },
+            padding: 4.,
+            max_items: 10,
+            max_cols: 60,
+            bg_color: color256(50, 50, 50),
+            label_color: color256(212, 212, 212),
+            selected_color: color256(220, 220, 174),
+            detail_color: color256(136, 136, 136),
+            providers: vec![Rc::new(BufferWordCompletions), Rc::new(LspCompletions)],
+            is_open: false,
+            is_explicit: false,
+            start: 0,
+            prefix: String::new(),
+            items: Vec::new(),
+            selected: 0,
+            snippet_stops: Vec::new(),
+            _snippet_char_count: 0,
+            _snippet_anchor: 0
+        }
+    }
+    
+    pub fn open(&mut self, cursors: &TextCursorSet, text_buffer: &TextBuffer) {
+        self.is_open = true;
+        self.is_explicit = true;
+        self.update(cursors, text_buffer);
+    }
+    
+    pub fn close(&mut self) {
+        self.is_open = false;
+        self.is_explicit = false;
+        self.items.truncate(0);
+        self.selected = 0;
+    }
+    
+    // the text changed or the cursor moved, refilter on the word at the cursor
+    pub fn update(&mut self, cursors: &TextCursorSet, text_buffer: &TextBuffer) {
+        let cursor = &cursors.set[cursors.last_cursor];
+        if cursor.head != cursor.tail {
+            self.close();
+            return
+        }
+        let (start, _) = word_range_at(text_buffer, cursor.head);
+        let mut prefix = String::new();
+        text_buffer.get_range_as_string(start, cursor.head - start, &mut prefix);
+        if prefix.len() == 0 && !self.is_explicit {
+            self.close();
+            return
+        }
+        let mut candidates = Vec::new();
+        for provider in &self.providers {
+            provider.completions(text_buffer, start, &prefix, &mut candidates);
+        }
+        let mut seen = HashSet::new();
+        let mut scored: Vec<(i32, CompletionItem)> = candidates.into_iter().filter_map( | item | {
+            if !seen.insert(item.label.clone()) {
+                return None
+            }
+            fuzzy_match(&prefix, &item.label).map( | (score, _) | (score, item))
+        }).collect();
+        scored.sort_by( | a, b | b.0.cmp(&a.0).then(a.1.label.len().cmp(&b.1.label.len())).then(a.1.label.cmp(&b.1.label)));
+        let selected_label = self.items.get(self.selected).map( | item | item.label.clone());
+        self.items = scored.into_iter().map( | (_, item) | item).collect();
+        // nothing to add to a word thats already complete
+        if self.items.len() == 1 && self.items[0].label == prefix && !self.items[0].is_snippet {
+            self.items.truncate(0);
+        }
+        if self.items.len() == 0 && !self.is_explicit {
+            self.close();
+            return
+        }
+        self.selected = selected_label.and_then( | label | self.items.iter().position( | item | item.label == label)).unwrap_or(0);
+        self.start = start;
+        self.prefix = prefix;
+        self.is_open = true;
+    }
+    
+    pub fn accept(&mut self, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> CodeCompletionEvent {
+        let item = match self.items.get(self.selected) {
+            Some(item) => item.clone(),
+            None => {
+                self.close();
+                return CodeCompletionEvent::Handled
+            }
+        };
+        self.close();
+        // every cursor gets its word replaced, the last cursor decides what the word is
+        let ranges: Vec<(usize, usize)> = cursors.set.iter().map( | cursor | (word_range_at(text_buffer, cursor.head).0, cursor.head)).collect();
+        let last_cursor = cursors.last_cursor;
+        cursors.select_ranges(&ranges, last_cursor, text_buffer);
+        if !item.is_snippet {
+            cursors.replace_text(&item.insert_text, text_buffer);
+            return CodeCompletionEvent::CursorsChanged
+        }
+        let pos = cursors.get_last_cursor_text_pos(text_buffer);
+        let indent: String = text_buffer.lines[pos.row].iter().take_while( | ch | **ch == ' ' || **ch == '\t').collect();
+        let (text, stops) = expand_snippet(&item.insert_text, &indent);
+        cursors.replace_text(&text, text_buffer);
+        if stops.len() > 0 {
+            let inserted_at = cursors.set[cursors.last_cursor].head - text.chars().count();
+            self.snippet_stops = stops.iter().map( | (start, end) | (inserted_at + start, inserted_at + end)).collect();
+            self._snippet_char_count = text_buffer.lines.char_count();
+            self._snippet_anchor = inserted_at;
+            self.next_snippet_stop(cursors, text_buffer);
+        }
+        CodeCompletionEvent::CursorsChanged
+    }
+    
+    // selects the next tabstop. Whatever got typed since went into the current one, so the
+    // stops after it move along by the change in length
+    fn next_snippet_stop(&mut self, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) {
+        if self.snippet_stops.len() == 0 {
+            return
+        }
+        let char_count = text_buffer.lines.char_count();
+        let delta = char_count as isize - self._snippet_char_count as isize;
+        self._snippet_char_count = char_count;
+        for stop in &mut self.snippet_stops {
+            if stop.0 >= self._snippet_anchor {
+                stop.0 = (stop.0 as isize + delta).max(0) as usize;
+                stop.1 = (stop.1 as isize + delta).max(0) as usize;
+            }
+        }
+        let (start, end) = self.snippet_stops.remove(0);
+        let end = end.min(char_count);
+        self._snippet_anchor = end;
+        cursors.select_ranges(&[(start.min(end), end)], 0, text_buffer);
+    }
+    
+    pub fn handle_key_down(&mut self, ke: &KeyEvent, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> CodeCompletionEvent {
+        if !self.is_open {
+            if self.snippet_stops.len() > 0 {
+                match ke.key_code {
+                    KeyCode::Tab if !ke.modifiers.shift => {
+                        self.next_snippet_stop(cursors, text_buffer);
+                        return CodeCompletionEvent::CursorsChanged
+                    },
+                    KeyCode::Escape => {
+                        self.snippet_stops.truncate(0);
+                    },
+                    _ => ()
+                }
+            }
+            return CodeCompletionEvent::None
+        }
+        match ke.key_code {
+            KeyCode::ArrowUp | KeyCode::PageUp => {
+                let step = if ke.key_code == KeyCode::PageUp {self.max_items} else {1};
+                self.selected = if self.selected == 0 {self.items.len().max(1) - 1} else {self.selected.max(step) - step};
+                CodeCompletionEvent::Handled
+            },
+            KeyCode::ArrowDown | KeyCode::PageDown => {
+                let step = if ke.key_code == KeyCode::PageDown {self.max_items} else {1};
+                self.selected = if self.selected + 1 >= self.items.len() {0} else {(self.selected + step).min(self.items.len() - 1)};
+                CodeCompletionEvent::Handled
+            },
+            KeyCode::Return | KeyCode::Tab => {
+                self.snippet_stops.truncate(0);
+                self.accept(cursors, text_buffer)
+            },
+            KeyCode::Escape => {
+                self.close();
+                CodeCompletionEvent::Handled
+            },
+            // the editor deletes, we refilter after
+            KeyCode::Backspace | KeyCode::Shift | KeyCode::Control | KeyCode::Logo => CodeCompletionEvent::None,
+            _ => {
+                self.close();
+                CodeCompletionEvent::None
+            }
+        }
+    }
+    
+    // pos is the top left of the word being completed, relative to the editor view
+    pub fn draw_code_completion(&mut self, cx: &mut Cx, pos: Vec2) {
+        if !self.is_open {
+            return
+        }
+        let first = (self.selected + 1).max(self.max_items) - self.max_items;
+        let mut lines: Vec<(Color, String, String)> = self.items.iter().enumerate().skip(first).take(self.max_items).map( | (index, item) | {
+            let color = if index == self.selected {self.selected_color} else {self.label_color};
+            (color, item.label.chars().take(self.max_cols).collect(), item.detail.chars().take(self.max_cols).collect())
+        }).collect();
+        if lines.len() == 0 {
+            lines.push((self.detail_color, "No completions".to_string(), String::new()));
+        }
+        let origin = cx.get_turtle_origin();
+        let glyph = self.text.get_monospace_base(cx);
+        let glyph_w = glyph.x * self.text.font_size;
+        let line_height = glyph.y * self.text.font_size;
+        let label_cols = lines.iter().map( | (_, label, _) | label.chars().count()).max().unwrap_or(0);
+        let detail_cols = lines.iter().map( | (_, _, detail) | detail.chars().count()).max().unwrap_or(0);
+        let cols = if detail_cols > 0 {label_cols + 2 + detail_cols} else {label_cols};
+        let w = cols as f32 * glyph_w + 2. * self.padding;
+        let h = lines.len() as f32 * line_height + 2. * self.padding;
+        // flip above the cursor line when there is no room below
+        let x = pos.x.min(cx.get_width_total() - w).max(0.);
+        let y = if pos.y + h > cx.get_height_total() {(pos.y - h - line_height).max(0.)} else {pos.y};
+        let rect = Rect {x: origin.x + x, y: origin.y + y, w: w, h: h};
+        
+        cx.new_instance_draw_call(&self.bg.shader, 0);
+        self.bg.color = self.bg_color;
+        self.bg.draw_quad_abs(cx, rect);
+        
+        cx.new_instance_draw_call(&self.text.shader, 0);
+        let mut aligned = self.text.begin_text(cx);
+        for (index, (color, label, detail)) in lines.iter().enumerate() {
+            let y = rect.y + self.padding + index as f32 * line_height;
+            let label: Vec<char> = label.chars().collect();
+            self.text.color = *color;
+            self.text.add_text(cx, rect.x + self.padding, y, 0, &mut aligned, &label, | _, _, _, _ | {0.});
+            if detail.len() > 0 {
+                let detail: Vec<char> = detail.chars().collect();
+                self.text.color = self.detail_color;
+                self.text.add_text(cx, rect.x + self.padding + (label_cols + 2) as f32 * glyph_w, y, 0, &mut aligned, &detail, | _, _, _, _ | {0.});
+            }
+        }
+        self.text.end_text(cx, &aligned);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn fuzzy_prefers_prefixes_and_word_starts() {
+        let score = | pattern, word | fuzzy_match(pattern, word).map( | (score, _) | score);
+        assert_eq!(score("xyz", "text_buffer"), None);
+        let mut words = vec!["tab", "text_buffer", "TextBuffer", "tb", "the_best"];
+        words.sort_by_key( | word | -score("tb", word).unwrap());
+        assert_eq!(words[0], "tb");
+        // word starts beat scattered letters
+        assert!(score("tb", "text_buffer").unwrap() > score("tb", "attrib").unwrap());
+        assert!(score("TB", "TextBuffer").unwrap() > score("TB", "text_buffer").unwrap());
+        assert!(score("gas", "get_as_string").is_some());
+    }
+    
+    #[test]
+    fn snippets_expand_to_tabstops() {
+        let (text, stops) = expand_snippet("for ${1:item} in ${2:iter} {\n    $0\n}", "  ");
+        assert_eq!(text, "for item in iter {\n      \n  }");
+        assert_eq!(stops, vec![(4, 8), (12, 16), (25, 25)]);
+        
+        let (text, stops) = expand_snippet("println!(\"${1:{\\}}\", $2)", "");
+        assert_eq!(text, "println!(\"{}\", )");
+        assert_eq!(stops, vec![(10, 12), (15, 15), (16, 16)]);
+        
+        let (text, stops) = expand_snippet("${1|a,b|} $TM_FILENAME ${VAR:x} $1", "");
+        assert_eq!(text, "a  x ");
+        assert_eq!(stops, vec![(0, 1), (5, 5)]);
+        assert_eq!(expand_snippet("plain", ""), ("plain".to_string(), vec![]));
+    }
+}
@@ -3,6 +3,21 @@ use widget::*;
 use crate::textbuffer::*;
 use crate::textcursor::*;
 use crate::codeicon::*;
+use crate::textsearch::*;
+use crate::findbar::*;
+use crate::lspclient::*;
+use crate::lsppopup::*;
+use crate::codecompletion::*;
+use crate::textfold::*;
+use crate::textwrap::*;
+use crate::textgit::*;
+use crate::textformat::*;
+use crate::textencoding::*;
+use crate::language::*;
+use crate::keymap::*;
+use crate::vim::*;
+use crate::minimap::*;
+use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct CodeEditor {
@@ -19,24 +34,57 @@ pub struct CodeEditor {
     pub indent_lines: Quad,
     pub code_icon: CodeIcon,
     pub message_marker: Quad,
+    pub search_marker: Quad,
+    pub fold_marker: Quad,
+    pub fold_ellipsis: Quad,
+    pub message_block: Quad,
+    pub git_marker: Quad,
     pub text: Text,
     pub line_number_text: Text,
+    pub message_text: Text,
     pub cursors: TextCursorSet,
+    pub find_bar: FindBar,
+    pub lsp_popup: LspPopup,
+    pub code_completion: CodeCompletion,
+    // shared with the app, set_keymap swaps it when the keys file loads
+    pub keymap: Rc<Keymap>,
+    pub vim: Vim,
+    pub vim_status: VimStatus,
+    pub encoding_status: EncodingStatus,
+    pub minimap: Minimap,
     
     pub open_font_size: f32,
     pub folded_font_size: f32,
     pub line_number_width: f32,
+    pub fold_marker_width: f32,
     pub top_padding: f32,
     pub colors: CodeEditorColors,
     pub cursor_blink_speed: f64,
+    pub hover_delay: f64,
+    // the git markers wait for the typing to stop before diffing the file again
+    pub git_diff_delay: f64,
+    // the full message text under the cursor row, ToggleMessageDetail flips it
+    pub show_message_detail: bool,
+    // wrap long rows at wrap_column, or at the view width without one
+    pub soft_wrap: bool,
+    pub wrap_column: Option<usize>,
+    pub format_on_save: bool,
 
     pub folding_depth: usize,
+    // set from the Language of the file
+    pub indent: LanguageIndent,
+    pub line_comment: Option<&'static str>,
+    pub language: Rc<dyn Language>,
     //pub _bg_area: Area,
     pub _view_area: Area,
     pub _highlight_area: Area,
     pub _text_inst: Option<AlignedInstance>,
     pub _line_number_inst: Option<AlignedInstance>,
     pub _line_number_chunk: Vec<char>,
+    pub _message_inst: Option<AlignedInstance>,
+    // the messages to draw at the end of their line, by row
+    pub _inline_messages: Vec<InlineMessage>,
+    pub _inline_message_next: usize,
     pub _text_area: Area,
     pub _scroll_pos: Vec2,
     pub _last_finger_move: Option<Vec2>,
@@ -68,6 +116,18 @@ pub struct CodeEditor {
     
     pub _tokens_on_line: usize,
     pub _line_was_folded: bool,
+    pub _line_is_hidden: bool,
+    // the folds of the text_buffer we are drawing
+    pub _folds: TextFolds,
+    // shared with the cursors, which go up and down by them
+    pub _wraps: Rc<TextWraps>,
+    pub _git_rows: Vec<TextGitRow>,
+    // who last changed the cursor row, drawn after it
+    pub _blame: Vec<char>,
+    // where the row being drawn started, the column we are at and how often it wrapped so far
+    pub _line_top: f32,
+    pub _line_col: usize,
+    pub _line_wrap: usize,
     //pub _line_was_visible: bool,
     pub _final_fill_height: f32,
     pub _draw_cursors: DrawCursors,
@@ -84,6 +144,20 @@ pub struct CodeEditor {
     
     pub _jump_to_offset_id: u64,
     
+    pub _hover_timer: Timer,
+    pub _git_diff_timer: Timer,
+    // the edit the git diff timer runs for
+    pub _git_diff_mutation_id: u64,
+    pub _hover_abs: Option<Vec2>,
+    // what we asked the language server last, answers to anything else are for another editor
+    pub _lsp_query: Option<LspQuery>,
+    pub _lsp_response_id: u64,
+    // the word start we last asked the language server completions for
+    pub _lsp_completion_start: Option<usize>,
+    
+    // the keys of a sequence like ctrl+k ctrl+c typed so far
+    pub _key_pending: Vec<KeyEvent>,
+    
     pub _last_lag_mutation_id: u64
 }
 
@@ -109,6 +183,18 @@ pub struct CodeEditorColors {
     pub marker_error: Color,
     pub marker_warning: Color,
     pub marker_log: Color,
+    pub marker_fix: Color,
+    pub search_match: Color,
+    pub fold_marker: Color,
+    pub fold_ellipsis: Color,
+    pub message_error: Color,
+    pub message_warning: Color,
+    pub message_log: Color,
+    pub message_block: Color,
+    pub git_added: Color,
+    pub git_modified: Color,
+    pub git_deleted: Color,
+    pub git_blame: Color,
     
     pub whitespace: Color,
     pub keyword: Color,
@@ -128,18 +214,60 @@ pub struct CodeEditorColors {
     pub unexpected: Color
 }
 
+impl CodeEditorColors {
+    // the plain color of a token, draw_chunk adds the paren depth and highlights
+    pub fn token_color(&self, token_type: TokenType) -> Color {
+        match token_type {
+            TokenType::Whitespace | TokenType::Newline => self.whitespace,
+            TokenType::BuiltinType | TokenType::Keyword | TokenType::Bool | TokenType::TypeDef | TokenType::Fn => self.keyword,
+            TokenType::Flow => self.flow,
+            TokenType::Looping => self.looping,
+            TokenType::Identifier => self.identifier,
+            TokenType::Call => self.call,
+            TokenType::TypeName => self.type_name,
+            TokenType::Regex | TokenType::String => self.string,
+            TokenType::Number => self.number,
+            TokenType::CommentMultiBegin | TokenType::CommentMultiEnd | TokenType::CommentLine | TokenType::CommentChunk => self.comment,
+            TokenType::ParenOpen | TokenType::ParenClose => self.paren_d1,
+            TokenType::Operator | TokenType::Namespace | TokenType::Hash | TokenType::Splat => self.operator,
+            TokenType::Delimiter | TokenType::Colon => self.delimiter,
+            TokenType::Eof | TokenType::Unexpected => self.unexpected
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub enum CodeEditorEvent {
     None,
     AutoFormat,
+    // an explicit save, formats first with format_on_save
+    Save,
     LagChange,
-    Change
+    Change,
+    ProjectSearch(TextSearch),
+    Lsp(LspQuery),
+    FoldChange,
+    NextMessage {offset: usize, forward: bool},
+    VimModeChange(bool),
+    SoftWrapChange(bool),
+    MinimapChange(bool),
+    FormatOnSaveChange(bool),
+    GitStageHunk(usize),
+    GitDiff {index: bool}
 }
 
 impl CodeEditor {
     pub     fn style(cx: &mut Cx) -> Self {
         Self {
             cursors: TextCursorSet::new(),
+            find_bar: FindBar::style(cx),
+            lsp_popup: LspPopup::style(cx),
+            keymap: Rc::new(Keymap::default_keymap()),
+            vim: Vim::new(),
+            vim_status: VimStatus::style(cx),
+            encoding_status: EncodingStatus::style(cx),
+            minimap: Minimap::style(cx),
+            code_completion: CodeCompletion::style(cx),
             colors: CodeEditorColors {
                 bg: color256(30, 30, 30),
                 gutter_bg: color256(30, 30, 30),
@@ -160,6 +288,18 @@ impl CodeEditor {
                 marker_error: color256(200, 0, 0),
                 marker_warning: color256(0, 200, 0),
                 marker_log: color256(200, 200, 200),
+                marker_fix: color256(255, 204, 0),
+                search_match: color256a(120, 100, 40, 200),
+                fold_marker: color256(136, 136, 136),
+                fold_ellipsis: color256(60, 60, 60),
+                message_error: color256(240, 110, 110),
+                message_warning: color256(130, 190, 110),
+                message_log: color256(150, 150, 150),
+                message_block: color256(40, 40, 40),
+                git_added: color256(88, 150, 80),
+                git_modified: color256(70, 120, 180),
+                git_deleted: color256(190, 70, 70),
+                git_blame: color256(100, 100, 100),
                 line_number_normal: color256(136, 136, 136),
                 line_number_highlight: color256(212, 212, 212),
                 
@@ -238,6 +378,30 @@ impl CodeEditor {
                 shader: cx.add_shader(Self::def_message_marker_shader(), "Editor.message_marker"),
                 ..Quad::style(cx)
             },
+            search_marker: Quad {
+                shader: cx.add_shader(Self::def_search_marker_shader(), "Editor.search_marker"),
+                ..Quad::style(cx)
+            },
+            fold_marker: Quad {
+                shader: cx.add_shader(Self::def_fold_marker_shader(), "Editor.fold_marker"),
+                z: 9.5,
+                do_h_scroll: false,
+                ..Quad::style(cx)
+            },
+            fold_ellipsis: Quad {
+                shader: cx.add_shader(Self::def_fold_ellipsis_shader(), "Editor.fold_ellipsis"),
+                ..Quad::style(cx)
+            },
+            message_block: Quad {
+                shader: cx.add_shader(Quad::def_quad_shader(), "Editor.message_block"),
+                ..Quad::style(cx)
+            },
+            git_marker: Quad {
+                shader: cx.add_shader(Quad::def_quad_shader(), "Editor.git_marker"),
+                z: 9.5,
+                do_h_scroll: false,
+                ..Quad::style(cx)
+            },
             code_icon: CodeIcon {
                 ..CodeIcon::style(cx)
             },
@@ -270,16 +434,40 @@ impl CodeEditor {
                 wrapping: Wrapping::Line,
                 ..Text::style(cx)
             },
+            message_text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                brightness: 1.0,
+                z: 2.00,
+                do_dpi_dilate: true,
+                wrapping: Wrapping::Line,
+                ..Text::style(cx)
+            },
             open_font_size: 8.0,
             folded_font_size: 0.5,
             line_number_width: 45.,
+            fold_marker_width: 10.,
             cursor_blink_speed: 0.5,
+            hover_delay: 0.6,
+            git_diff_delay: 0.3,
+            show_message_detail: false,
+            soft_wrap: false,
+            wrap_column: None,
+            format_on_save: false,
             top_padding: 27.,
             _monospace_size: Vec2::zero(),
             _monospace_base: Vec2::zero(),
             _last_finger_move: None,
             _tokens_on_line: 0,
             _line_was_folded: false,
+            _line_is_hidden: false,
+            _folds: TextFolds::default(),
+            _wraps: Rc::new(TextWraps::default()),
+            _git_rows: Vec::new(),
+            _blame: Vec::new(),
+            _line_top: 0.,
+            _line_col: 0,
+            _line_wrap: 0,
             //_line_was_visible: false,
             _scroll_pos: Vec2::zero(),
             _visible_lines: 0,
@@ -299,11 +487,17 @@ impl CodeEditor {
             _text_area: Area::Empty,
             _line_number_inst: None,
             _line_number_chunk: Vec::new(),
+            _message_inst: None,
+            _inline_messages: Vec::new(),
+            _inline_message_next: 0,
             
             _anim_font_size: 11.0,
             _line_largest_font: 0.,
             _final_fill_height: 0.,
             folding_depth: 2,
+            indent: LanguageIndent::default(),
+            line_comment: None,
+            language: Rc::new(PlainTextLanguage),
             _anim_folding: AnimFolding {
                 state: AnimFoldingState::Open,
                 focussed_line: 0,
@@ -330,9 +524,17 @@ impl CodeEditor {
             _cursor_blink_flipflop: 0.,
             _cursor_area: Area::Empty,
             _last_lag_mutation_id: 0,
+            _key_pending: Vec::new(),
             _last_tabs: 0,
             _newline_tabs: 0,
-            _jump_to_offset_id: 0
+            _jump_to_offset_id: 0,
+            _hover_timer: Timer::empty(),
+            _git_diff_timer: Timer::empty(),
+            _git_diff_mutation_id: 0,
+            _hover_abs: None,
+            _lsp_query: None,
+            _lsp_response_id: 0,
+            _lsp_completion_start: None
         }
     }
     
@@ -477,6 +679,52 @@ impl CodeEditor {
         }))
     }
     
+    pub fn def_search_marker_shader() -> ShaderGen {
+        Quad::def_quad_shader().compose(shader_ast!({
+            fn pixel() -> vec4 {
+                df_viewport(pos * vec2(w, h));
+                df_box(0., 0., w, h, 1.);
+                return df_fill(color);
+            }
+        }))
+    }
+    
+    pub fn def_fold_marker_shader() -> ShaderGen {
+        Quad::def_quad_shader().compose(shader_ast!({
+            let folded: float<Instance>;
+            fn pixel() -> vec4 {
+                df_viewport(pos * vec2(10., 10.));
+                if folded > 0.5 { // pointing right
+                    df_move_to(3., 1.);
+                    df_line_to(8., 5.);
+                    df_line_to(3., 9.);
+                }
+                else { // pointing down
+                    df_move_to(1., 3.);
+                    df_line_to(9., 3.);
+                    df_line_to(5., 8.);
+                }
+                df_close_path();
+                return df_fill(color);
+            }
+        }))
+    }
+    
+    pub fn def_fold_ellipsis_shader() -> ShaderGen {
+        Quad::def_quad_shader().compose(shader_ast!({
+            fn pixel() -> vec4 {
+                df_viewport(pos * vec2(w, h));
+                df_box(0., 1., w, h - 2., 2.);
+                df_fill(color);
+                let r = 0.06 * h;
+                df_circle(w * 0.25, h * 0.5, r);
+                df_circle(w * 0.5, h * 0.5, r);
+                df_circle(w * 0.75, h * 0.5, r);
+                return df_fill(color("#aaa"));
+            }
+        }))
+    }
+    
     fn reset_highlight_visible(&mut self, cx: &mut Cx) {
         self._highlight_visibility = 0.0;
         self._highlight_area.write_uniform_float(cx, "visible", self._highlight_visibility);
@@ -533,7 +781,7 @@ impl CodeEditor {
                     }
                 },
                 _ => {
-                    //let range = (0, text_buffer.calc_char_count());
+                    //let range = (0, text_buffer.lines.char_count());
                     //self.cursors.set_last_clamp_range(range);
                 }
             }
@@ -602,143 +850,177 @@ impl CodeEditor {
         self.reset_cursor_blinker(cx);
     }
     
-    fn handle_key_down(&mut self, cx: &mut Cx, ke: &KeyEvent, text_buffer: &mut TextBuffer) {
-        let cursor_moved = match ke.key_code {
-            KeyCode::ArrowUp => {
-                if self._anim_folding.state.is_folded() && self.cursors.set.len() == 1 {
-                    // compute the nearest nonfolded line up
-                    let delta = self.compute_next_unfolded_line_up(text_buffer);
-                    self.cursors.move_up(delta, ke.modifiers.shift, text_buffer);
+    pub fn run_command(&mut self, cx: &mut Cx, command: Command, text_buffer: &mut TextBuffer) -> CodeEditorEvent {
+        match command {
+            Command::Find | Command::Replace | Command::ProjectSearch => {
+                // from an open bar the search goes as it is, a selected match would become the query
+                if command != Command::ProjectSearch || !self.find_bar.is_open {
+                    self.find_bar.open(command == Command::Replace, &self.cursors, text_buffer);
                 }
-                else {
-                    self.cursors.move_up(1, ke.modifiers.shift, text_buffer);
+                self.view.redraw_view_area(cx);
+                if command == Command::ProjectSearch {
+                    return CodeEditorEvent::ProjectSearch(self.find_bar.search.clone())
                 }
-                true
             },
-            KeyCode::ArrowDown => {
-                if self._anim_folding.state.is_folded() && self.cursors.set.len() == 1 {
-                    // compute the nearest nonfolded line down
-                    let delta = self.compute_next_unfolded_line_down(text_buffer);
-                    self.cursors.move_down(delta, ke.modifiers.shift, text_buffer);
-                }
-                else {
-                    self.cursors.move_down(1, ke.modifiers.shift, text_buffer);
-                }
-                true
+            // these come from the palette, the open bar runs its keys itself
+            Command::FindNext | Command::FindPrev | Command::FindSelectAll | Command::FindToggleCase | Command::FindToggleWholeWord |
+            Command::FindToggleRegex | Command::FindSwitchField | Command::ReplaceNext | Command::ReplaceAll => {
+                if !self.find_bar.is_open {
+                    self.find_bar.open(command == Command::ReplaceNext || command == Command::ReplaceAll, &self.cursors, text_buffer);
+                }
+                let event = self.find_bar.run_command(command, &mut self.cursors, text_buffer);
+                self.handle_find_bar_event(cx, event, text_buffer);
+            },
+            Command::AutoFormat => {
+                return CodeEditorEvent::AutoFormat
             },
-            KeyCode::ArrowLeft => {
-                if ke.modifiers.logo || ke.modifiers.control { // token skipping
-                    self.cursors.move_left_nearest_token(ke.modifiers.shift, text_buffer)
+            Command::Save => {
+                // the lag save wont come for this one anymore
+                self._last_lag_mutation_id = text_buffer.mutation_id;
+                return CodeEditorEvent::Save
+            },
+            Command::Complete => {
+                self.code_completion.open(&self.cursors, text_buffer);
+                self.view.redraw_view_area(cx);
+                return self.lsp_completion_query(text_buffer, true)
+            },
+            Command::FoldAtCursor | Command::UnfoldAtCursor => {
+                let row = self.cursors.get_last_cursor_text_pos(text_buffer).row;
+                let changed = if command == Command::FoldAtCursor {
+                    text_buffer.folds.fold_at(row)
                 }
                 else {
-                    self.cursors.move_left(1, ke.modifiers.shift, text_buffer);
-                }
-                true
+                    text_buffer.folds.unfold_at(row)
+                };
+                return self.handle_fold_change(cx, text_buffer, changed)
             },
-            KeyCode::ArrowRight => {
-                if ke.modifiers.logo || ke.modifiers.control { // token skipping
-                    self.cursors.move_right_nearest_token(ke.modifiers.shift, text_buffer)
+            Command::FoldAll | Command::UnfoldAll => {
+                let changed = if command == Command::FoldAll {
+                    text_buffer.folds.fold_all()
                 }
                 else {
-                    self.cursors.move_right(1, ke.modifiers.shift, text_buffer);
-                }
-                true
+                    text_buffer.folds.unfold_all()
+                };
+                return self.handle_fold_change(cx, text_buffer, changed)
             },
-            KeyCode::PageUp => {
-                
-                self.cursors.move_up(self._visible_lines.max(5) - 4, ke.modifiers.shift, text_buffer);
-                true
+            Command::ZoomFold | Command::ZoomFoldHalfway => {
+                // how do we find the center line of the view
+                // its simply the top line
+                self.start_code_folding(cx, text_buffer, command == Command::ZoomFoldHalfway);
             },
-            KeyCode::PageDown => {
-                self.cursors.move_down(self._visible_lines.max(5) - 4, ke.modifiers.shift, text_buffer);
-                true
+            Command::GotoDefinition => {
+                let offset = self.cursors.set[self.cursors.last_cursor].head;
+                return self.lsp_query(LspQuery::Definition {offset: offset})
             },
-            KeyCode::Home => {
-                self.cursors.move_home(ke.modifiers.shift, text_buffer);
-                true
+            Command::Rename => {
+                self.lsp_popup.open_rename(&self.cursors, text_buffer);
+                self.view.redraw_view_area(cx);
             },
-            KeyCode::End => {
-                self.cursors.move_end(ke.modifiers.shift, text_buffer);
-                true
+            Command::QuickFix => {
+                let offset = self.cursors.set[self.cursors.last_cursor].head;
+                self.lsp_popup.open_quick_fixes(text_buffer.quick_fixes_at(offset));
+                self.view.redraw_view_area(cx);
             },
-            KeyCode::Backspace => {
-                self.cursors.backspace(text_buffer);
-                true
+            Command::ApplyAllFixes => { // every fix rustc is sure about, in the whole file
+                let fixes = text_buffer.machine_applicable_fixes();
+                if self.cursors.apply_quick_fixes(&fixes, text_buffer) {
+                    self.handle_find_bar_event(cx, FindBarEvent::CursorsChanged, text_buffer);
+                }
             },
-            KeyCode::Delete => {
-                self.cursors.delete(text_buffer);
-                true
+            Command::NextMessage | Command::PrevMessage => {
+                // the next message can be in another file, so whoever holds the text buffers decides
+                let offset = self.cursors.set[self.cursors.last_cursor].head;
+                return CodeEditorEvent::NextMessage {offset: offset, forward: command == Command::NextMessage}
             },
-            KeyCode::KeyZ => {
-                if ke.modifiers.logo || ke.modifiers.control {
-                    if ke.modifiers.shift { // redo
-                        text_buffer.redo(true, &mut self.cursors);
-                        true
-                    }
-                    else { // undo
-                        text_buffer.undo(true, &mut self.cursors);
-                        true
-                    }
-                }
-                else {
-                    false
-                }
+            Command::ToggleMessageDetail => {
+                self.show_message_detail = !self.show_message_detail;
+                self.view.redraw_view_area(cx);
             },
-            KeyCode::KeyX => { // cut, the actual copy comes from the TextCopy event from the platform layer
-                if ke.modifiers.logo || ke.modifiers.control { // cut
-                    self.cursors.replace_text("", text_buffer);
-                    true
-                }
-                else {
-                    false
-                }
+            Command::ToggleVimMode => {
+                self.vim.set_enabled(!self.vim.enabled);
+                self.view.redraw_view_area(cx);
+                return CodeEditorEvent::VimModeChange(self.vim.enabled)
             },
-            KeyCode::KeyA => { // select all
-                if ke.modifiers.logo || ke.modifiers.control { // cut
-                    self.cursors.select_all(text_buffer);
-                    // don't scroll!
-                    self.view.redraw_view_area(cx);
-                    false
-                }
-                else {
-                    false
-                }
+            Command::ToggleSoftWrap => {
+                self.soft_wrap = !self.soft_wrap;
+                self.view.redraw_view_area(cx);
+                return CodeEditorEvent::SoftWrapChange(self.soft_wrap)
             },
-            KeyCode::Escape => {
-                self.start_code_folding(cx, text_buffer, ke.modifiers.shift);
-                false
+            Command::ToggleMinimap => {
+                self.minimap.enabled = !self.minimap.enabled;
+                self.view.redraw_view_area(cx);
+                return CodeEditorEvent::MinimapChange(self.minimap.enabled)
             },
-            KeyCode::Alt => {
-                // how do we find the center line of the view
-                // its simply the top line
-                self.start_code_folding(cx, text_buffer, ke.modifiers.shift);
-                false
-                //return CodeEditorEvent::FoldStart
+            Command::ToggleFormatOnSave => {
+                self.format_on_save = !self.format_on_save;
+                return CodeEditorEvent::FormatOnSaveChange(self.format_on_save)
             },
-            KeyCode::Tab => {
-                if ke.modifiers.shift {
-                    self.cursors.remove_tab(text_buffer, 4);
-                }
-                else {
-                    self.cursors.insert_tab(text_buffer, "    ");
+            Command::GitStageHunk => {
+                // the repository is with the text buffers
+                return CodeEditorEvent::GitStageHunk(self.cursors.get_last_cursor_text_pos(text_buffer).row)
+            },
+            Command::GitRevertHunk => {
+                let row = self.cursors.get_last_cursor_text_pos(text_buffer).row;
+                if let Some(offset) = text_buffer.revert_git_hunk(row, &self.cursors) {
+                    self.cursors.clear_and_set_last_cursor_head_and_tail(offset, text_buffer);
+                    self.handle_cursor_moved(cx, text_buffer);
                 }
-                true
             },
-            KeyCode::Return => {
-                if !ke.modifiers.control && !ke.modifiers.logo {
-                    self.cursors.insert_newline_with_indent(text_buffer);
+            Command::GitDiffHead | Command::GitDiffIndex => {
+                return CodeEditorEvent::GitDiff {index: command == Command::GitDiffIndex}
+            },
+            Command::ConvertToUtf8 | Command::ConvertToUtf8Bom | Command::ConvertToUtf16le | Command::ConvertToUtf16be |
+            Command::ConvertToLatin1 | Command::ConvertToHex | Command::UseLfLineEndings | Command::UseCrlfLineEndings => {
+                let result = match command {
+                    Command::ConvertToUtf8 => text_buffer.convert_encoding(TextEncoding::Utf8),
+                    Command::ConvertToUtf8Bom => text_buffer.convert_encoding(TextEncoding::Utf8Bom),
+                    Command::ConvertToUtf16le => text_buffer.convert_encoding(TextEncoding::Utf16Le),
+                    Command::ConvertToUtf16be => text_buffer.convert_encoding(TextEncoding::Utf16Be),
+                    Command::ConvertToLatin1 => text_buffer.convert_encoding(TextEncoding::Latin1),
+                    Command::ConvertToHex => text_buffer.convert_encoding(TextEncoding::Hex),
+                    _ => {
+                        text_buffer.convert_line_endings(command == Command::UseCrlfLineEndings);
+                        Ok(())
+                    }
+                };
+                // the next save clears the error, like any other save that went through
+                if let Err(err) = result {
+                    text_buffer.messages.set_file_error(Some(err), text_buffer.mutation_id);
+                    cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
                 }
-                true
+                // to or from hex the text is new, every editor on it has to put its cursors back in it
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_RELOADED);
             },
-            _ => false
-        };
-        if cursor_moved {
-            self.update_highlight(cx, text_buffer);
-            self.scroll_last_cursor_visible(cx, text_buffer, 0.);
-            self.view.redraw_view_area(cx);
-            self.reset_cursor_blinker(cx);
-            
+            Command::CursorUp | Command::SelectUp if self._anim_folding.state.is_folded() && self.cursors.set.len() == 1 => {
+                // compute the nearest nonfolded line up
+                let delta = self.compute_next_unfolded_line_up(text_buffer);
+                self.cursors.move_up(delta, command == Command::SelectUp, text_buffer);
+                self.handle_cursor_moved(cx, text_buffer);
+            },
+            Command::CursorDown | Command::SelectDown if self._anim_folding.state.is_folded() && self.cursors.set.len() == 1 => {
+                // compute the nearest nonfolded line down
+                let delta = self.compute_next_unfolded_line_down(text_buffer);
+                self.cursors.move_down(delta, command == Command::SelectDown, text_buffer);
+                self.handle_cursor_moved(cx, text_buffer);
+            },
+            _ => {
+                let page_lines = self._visible_lines.max(5) - 4;
+                match command.apply_to_text(&mut self.cursors, text_buffer, &self.indent, self.line_comment, page_lines) {
+                    Some(true) => self.handle_cursor_moved(cx, text_buffer),
+                    Some(false) => self.view.redraw_view_area(cx),
+                    None => ()
+                }
+            }
         }
+        CodeEditorEvent::None
+    }
+            
+    fn handle_cursor_moved(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer) {
+        self.reveal_cursors(text_buffer);
+        self.update_highlight(cx, text_buffer);
+        self.scroll_last_cursor_visible(cx, text_buffer, 0.);
+        self.view.redraw_view_area(cx);
+        self.reset_cursor_blinker(cx);
     }
     
     fn handle_text_input(&mut self, cx: &mut Cx, te: &TextInputEvent, text_buffer: &mut TextBuffer) {
@@ -761,13 +1043,13 @@ impl CodeEditor {
                     self.cursors.insert_around("\"", "\"", text_buffer);
                 },
                 ')' => {
-                    self.cursors.overwrite_if_exists_or_deindent(")", 4, text_buffer);
+                    self.cursors.overwrite_if_exists_or_deindent(")", self.indent.width, text_buffer);
                 },
                 ']' => {
-                    self.cursors.overwrite_if_exists_or_deindent("]", 4, text_buffer);
+                    self.cursors.overwrite_if_exists_or_deindent("]", self.indent.width, text_buffer);
                 },
                 '}' => {
-                    self.cursors.overwrite_if_exists_or_deindent("}", 4, text_buffer);
+                    self.cursors.overwrite_if_exists_or_deindent("}", self.indent.width, text_buffer);
                 },
                 _ => {
                     self.cursors.replace_text(&te.input, text_buffer);
@@ -778,6 +1060,7 @@ impl CodeEditor {
         else {
             self.cursors.replace_text(&te.input, text_buffer);
         }
+        self.reveal_cursors(text_buffer);
         self.update_highlight(cx, text_buffer);
         self.scroll_last_cursor_visible(cx, text_buffer, 0.);
         self.view.redraw_view_area(cx);
@@ -824,6 +1107,20 @@ impl CodeEditor {
                         return CodeEditorEvent::LagChange;
                     }
                 }
+            }
+            else if self._git_diff_timer.is_timer(te) {
+                self._git_diff_timer = Timer::empty();
+                text_buffer.git.update(&text_buffer.lines, text_buffer.mutation_id);
+                self.view.redraw_view_area(cx);
+            }
+            else if self._hover_timer.is_timer(te) {
+                self._hover_timer = Timer::empty();
+                if let Some(hover_abs) = self._hover_abs {
+                    if !text_buffer.load_file_read.is_pending() {
+                        let offset = self.text.find_closest_offset(cx, &self._text_area, hover_abs);
+                        return self.lsp_query(LspQuery::Hover {offset: offset})
+                    }
+                }
             },
             Event::Signal(se) => if text_buffer.signal.is_signal(se) {
                 match se.value {
@@ -833,6 +1130,15 @@ impl CodeEditor {
                     SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET => {
                         self.do_jump_to_offset(cx, text_buffer);
                     },
+                    SIGNAL_TEXTBUFFER_RELOADED => {
+                        self.cursors.clamp_to_text_buffer(text_buffer);
+                        self.update_highlight(cx, text_buffer);
+                        self.view.redraw_view_area(cx);
+                    },
+                    SIGNAL_TEXTBUFFER_LSP_RESPONSE => if self._lsp_response_id != text_buffer.lsp_response_id {
+                        self._lsp_response_id = text_buffer.lsp_response_id;
+                        self.handle_lsp_response(cx, text_buffer);
+                    },
                     SIGNAL_TEXTBUFFER_KEYBOARD_UPDATE => {
                         if let Some(key_down) = &text_buffer.keyboard.key_down {
                             match key_down {
@@ -862,22 +1168,108 @@ impl CodeEditor {
                 self.view.redraw_view_area(cx)
             },
             Event::FingerDown(fe) => {
+                if self.minimap.hit(fe.abs) {
+                    let row = self.minimap.handle_finger_down(fe.abs);
+                    self.scroll_to_row(cx, row);
+                    return CodeEditorEvent::None
+                }
+                self.lsp_popup.close();
+                self.code_completion.close();
+                self.code_completion.snippet_stops.truncate(0);
+                // the fold markers sit on the right edge of the gutter
+                if fe.rel.x >= self.line_number_width - self.fold_marker_width && fe.rel.x < self.line_number_width {
+                    let offset = self.compute_offset_from_ypos(cx, fe.abs.y, text_buffer, false);
+                    let row = text_buffer.offset_to_text_pos(offset).row;
+                    if text_buffer.folds.range_at(row).is_some() {
+                        let changed = text_buffer.folds.toggle(row);
+                        return self.handle_fold_change(cx, text_buffer, changed)
+                    }
+                }
                 self.handle_finger_down(cx, &fe, text_buffer);
             },
-            Event::FingerHover(_fe) => {
+            Event::FingerHover(fe) => {
+                if self.minimap.hit(fe.abs) {
+                    cx.set_hover_mouse_cursor(MouseCursor::Default);
+                    return CodeEditorEvent::None
+                }
                 cx.set_hover_mouse_cursor(MouseCursor::Text);
+                // the mouse has to rest a moment before we ask what its pointing at
+                cx.stop_timer(&mut self._hover_timer);
+                if self.lsp_popup.is_hover() {
+                    self.lsp_popup.close();
+                    self.view.redraw_view_area(cx);
+                }
+                if fe.hover_state == HoverState::Out || fe.any_down {
+                    self._hover_abs = None;
+                }
+                else {
+                    self._hover_abs = Some(fe.abs);
+                    self._hover_timer = cx.start_timer(self.hover_delay, false);
+                }
             },
             Event::FingerUp(fe) => {
-                self.handle_finger_up(cx, &fe, text_buffer);
+                if self.minimap._drag.take().is_none() {
+                    self.handle_finger_up(cx, &fe, text_buffer);
+                }
             },
             Event::FingerMove(fe) => {
-                self.handle_finger_move(cx, &fe, text_buffer);
+                if self.minimap._drag.is_some() {
+                    let row = self.minimap.handle_finger_move(fe.abs);
+                    self.scroll_to_row(cx, row);
+                }
+                else {
+                    self.handle_finger_move(cx, &fe, text_buffer);
+                }
             },
             Event::KeyDown(ke) => {
-                if ke.key_code == KeyCode::Return && (ke.modifiers.logo || ke.modifiers.control) {
-                    return CodeEditorEvent::AutoFormat
+                match self.lsp_popup.handle_key_down(&ke) {
+                    LspPopupEvent::None => (),
+                    LspPopupEvent::Query(query) => {
+                        self.view.redraw_view_area(cx);
+                        return self.lsp_query(query)
+                    },
+                    LspPopupEvent::ApplyFix(fix) => {
+                        self.cursors.apply_quick_fixes(&[fix], text_buffer);
+                        self.handle_find_bar_event(cx, FindBarEvent::CursorsChanged, text_buffer);
+                        return CodeEditorEvent::None
+                    },
+                    LspPopupEvent::Handled => {
+                        self.view.redraw_view_area(cx);
+                        return CodeEditorEvent::None
+                    }
+                }
+                let was_completing = self.code_completion.is_open;
+                match self.code_completion.handle_key_down(&ke, &mut self.cursors, text_buffer) {
+                    CodeCompletionEvent::None => if was_completing != self.code_completion.is_open {
+                        self.view.redraw_view_area(cx);
+                    },
+                    CodeCompletionEvent::CursorsChanged => {
+                        self.handle_find_bar_event(cx, FindBarEvent::CursorsChanged, text_buffer);
+                        return CodeEditorEvent::None
+                    },
+                    CodeCompletionEvent::Handled => {
+                        self.view.redraw_view_area(cx);
+                        return CodeEditorEvent::None
+                    }
+                }
+                match self.find_bar.handle_key_down(&ke, &self.keymap, &mut self.cursors, text_buffer) {
+                    FindBarEvent::None => (),
+                    find_bar_event => {
+                        self.handle_find_bar_event(cx, find_bar_event, text_buffer);
+                        return CodeEditorEvent::None
+                    }
+                }
+                match self.vim.handle_key_down(&ke, &mut self.cursors, text_buffer, &*self.language) {
+                    VimEvent::None => (),
+                    vim_event => return self.handle_vim_event(cx, vim_event, text_buffer)
+                }
+                if let KeymapEvent::Command(command) = self.keymap.handle_key(&mut self._key_pending, &ke) {
+                    let event = self.run_command(cx, command, text_buffer);
+                    if self.code_completion.is_open {
+                        self.code_completion.update(&self.cursors, text_buffer);
+                    }
+                    return event
                 }
-                self.handle_key_down(cx, &ke, text_buffer);
             },
             Event::KeyUp(ke) => {
                 match ke.key_code {
@@ -892,7 +1284,30 @@ impl CodeEditor {
                 self.reset_cursor_blinker(cx);
             },
             Event::TextInput(te) => {
-                self.handle_text_input(cx, &te, text_buffer);
+                if self.lsp_popup.handle_text_input(&te) != LspPopupEvent::None {
+                    self.view.redraw_view_area(cx);
+                    return CodeEditorEvent::None
+                }
+                match self.find_bar.handle_text_input(&te, &mut self.cursors, text_buffer) {
+                    FindBarEvent::None => {
+                        match self.vim.handle_text_input(&te, &mut self.cursors, text_buffer, &*self.language) {
+                            VimEvent::None => (),
+                            vim_event => return self.handle_vim_event(cx, vim_event, text_buffer)
+                        }
+                        self.handle_text_input(cx, &te, text_buffer);
+                        // typing a word brings up completions, anything else puts them away
+                        if !te.was_paste && te.input.chars().all( | ch | ch.is_alphanumeric() || ch == '_') {
+                            self.code_completion.update(&self.cursors, text_buffer);
+                            if self.code_completion.is_open {
+                                return self.lsp_completion_query(text_buffer, false)
+                            }
+                        }
+                        else {
+                            self.code_completion.close();
+                        }
+                    },
+                    find_bar_event => self.handle_find_bar_event(cx, find_bar_event, text_buffer)
+                }
             },
             Event::TextCopy(_) => match event { // access the original event
                 Event::TextCopy(req) => {
@@ -905,16 +1320,149 @@ impl CodeEditor {
         CodeEditorEvent::None
     }
     
+    fn handle_fold_change(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer, changed: bool) -> CodeEditorEvent {
+        if !changed {
+            return CodeEditorEvent::None
+        }
+        self.cursors.move_out_of_folds(text_buffer);
+        self.update_highlight(cx, text_buffer);
+        self.view.redraw_view_area(cx);
+        // other editors on this file draw the same folds
+        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_DATA_UPDATE);
+        CodeEditorEvent::FoldChange
+    }
+    
+    // edits and jumps open up the folds they land in
+    fn reveal_cursors(&mut self, text_buffer: &mut TextBuffer) {
+        for cursor in &self.cursors.set {
+            let row = text_buffer.offset_to_text_pos(cursor.head).row;
+            text_buffer.folds.reveal(row);
+        }
+    }
+    
+    fn lsp_query(&mut self, query: LspQuery) -> CodeEditorEvent {
+        self._lsp_query = Some(query.clone());
+        CodeEditorEvent::Lsp(query)
+    }
+    
+    // asks the server once per word, after that we filter what it gave us. Forced asks again
+    fn lsp_completion_query(&mut self, text_buffer: &TextBuffer, force: bool) -> CodeEditorEvent {
+        let head = self.cursors.set[self.cursors.last_cursor].head;
+        let start = word_range_at(text_buffer, head).0;
+        if !force && self._lsp_completion_start == Some(start) {
+            return CodeEditorEvent::None
+        }
+        self._lsp_completion_start = Some(start);
+        // not through lsp_query, a file without a server shouldnt complain on every word
+        CodeEditorEvent::Lsp(LspQuery::Completion {offset: head})
+    }
+    
+    // shows the answer to our last query, if this is it
+    fn handle_lsp_response(&mut self, cx: &mut Cx, text_buffer: &TextBuffer) {
+        if let Some(LspResponse::Completion {..}) = text_buffer.lsp_response {
+            if self.code_completion.is_open {
+                self.code_completion.update(&self.cursors, text_buffer);
+                self.view.redraw_view_area(cx);
+            }
+            return
+        }
+        let kind = match (&self._lsp_query, &text_buffer.lsp_response) {
+            (Some(LspQuery::Hover {offset}), Some(LspResponse::Hover {offset: response_offset, text})) if offset == response_offset => {
+                LspPopupKind::Hover {text: text.clone()}
+            },
+            // failing hovers would just be noise
+            (Some(LspQuery::Hover {..}), _) | (None, _) => return,
+            (Some(_), Some(LspResponse::Error {message})) => LspPopupKind::Error {message: message.clone()},
+            _ => return
+        };
+        self._lsp_query = None;
+        self.lsp_popup.kind = Some(kind);
+        self.view.redraw_view_area(cx);
+    }
+    
+    fn handle_find_bar_event(&mut self, cx: &mut Cx, event: FindBarEvent, text_buffer: &mut TextBuffer) {
+        if event == FindBarEvent::CursorsChanged {
+            self.reveal_cursors(text_buffer);
+            self.update_highlight(cx, text_buffer);
+            self.scroll_last_cursor_visible(cx, text_buffer, 0.);
+            self.reset_cursor_blinker(cx);
+            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_DATA_UPDATE);
+        }
+        self.view.redraw_view_area(cx);
+    }
+    
+    fn handle_vim_event(&mut self, cx: &mut Cx, event: VimEvent, text_buffer: &mut TextBuffer) -> CodeEditorEvent {
+        match event {
+            VimEvent::Save => {
+                // the same as the lag save, without waiting for the blink timer
+                self._last_lag_mutation_id = text_buffer.mutation_id;
+                self.view.redraw_view_area(cx);
+                return CodeEditorEvent::Save
+            },
+            VimEvent::CursorsChanged => self.handle_find_bar_event(cx, FindBarEvent::CursorsChanged, text_buffer),
+            _ => self.view.redraw_view_area(cx)
+        }
+        CodeEditorEvent::None
+    }
+    
     pub fn has_key_focus(&self, cx: &Cx) -> bool {
         cx.has_key_focus(self._view_area)
     }
     
+    pub fn set_keymap(&mut self, keymap: &Rc<Keymap>) {
+        if !Rc::ptr_eq(&self.keymap, keymap) {
+            self.keymap = keymap.clone();
+            self._key_pending.truncate(0);
+        }
+    }
+    
+    pub fn set_vim_mode(&mut self, enabled: bool) {
+        if self.vim.enabled != enabled {
+            self.vim.set_enabled(enabled);
+        }
+    }
+    
+    pub fn set_soft_wrap(&mut self, soft_wrap: bool, wrap_column: Option<usize>) {
+        self.soft_wrap = soft_wrap;
+        self.wrap_column = wrap_column;
+    }
+    
+    pub fn set_minimap(&mut self, enabled: bool) {
+        self.minimap.enabled = enabled;
+    }
+    
+    pub fn set_format_on_save(&mut self, enabled: bool) {
+        self.format_on_save = enabled;
+    }
+    
+    // swaps in the formatted text, lines coming and going above the view dont move the code in it
+    pub fn apply_formatted(&mut self, cx: &mut Cx, formatted: &str, text_buffer: &mut TextBuffer) {
+        let first_visible = self.compute_visible_rows(cx).first_visible;
+        let hunks = self.cursors.replace_lines_diffed(formatted, text_buffer);
+        if hunks.len() == 0 {
+            return
+        }
+        let delta = map_row_through_hunks(&hunks, first_visible) as f32 - first_visible as f32;
+        let scroll_pos = self.view.get_scroll_pos(cx);
+        self.view.set_scroll_pos(cx, Vec2 {x: scroll_pos.x, y: (scroll_pos.y + delta * self._monospace_size.y).max(0.)});
+        self.update_highlight(cx, text_buffer);
+        self.view.redraw_view_area(cx);
+        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_DATA_UPDATE);
+    }
+    
+    pub fn set_language(&mut self, language: &Rc<dyn Language>) {
+        self.language = language.clone();
+        self.indent = language.indent();
+        self.line_comment = language.line_comment();
+        self.folding_depth = language.folding_depth();
+    }
+    
     pub fn set_key_focus(&mut self, cx: &mut Cx) {
         cx.set_key_focus(self._view_area);
         self.reset_cursor_blinker(cx);
     }
     
-    pub fn begin_code_editor(&mut self, cx: &mut Cx, text_buffer: &TextBuffer) -> Result<(), ()> {
+    pub fn begin_code_editor(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer) -> Result<(), ()> {
         // adjust dilation based on DPI factor
         self.view.begin_view(cx, Layout {..Default::default()}) ?;
         
@@ -953,7 +1501,10 @@ impl CodeEditor {
             cx.new_instance_draw_call(&self.cursor_row.shader, 0);
             cx.new_instance_draw_call(&self.selection.shader, 0);
             cx.new_instance_draw_call(&self.message_marker.shader, 0);
+            cx.new_instance_draw_call(&self.search_marker.shader, 0);
             cx.new_instance_draw_call(&self.paren_pair.shader, 0);
+            cx.new_instance_draw_call(&self.fold_ellipsis.shader, 0);
+            cx.new_instance_draw_call(&self.message_block.shader, 0);
             
             // force next begin_text in another drawcall
             self._text_inst = Some(self.text.begin_text(cx));
@@ -962,8 +1513,12 @@ impl CodeEditor {
             self._cursor_area = cx.new_instance_draw_call(&self.cursor.shader, 0).into_area();
             
             self.gutter_bg.draw_quad(cx, Rect {x: 0., y: 0., w: self.line_number_width, h: cx.get_height_total()});
+            cx.new_instance_draw_call(&self.fold_marker.shader, 0);
+            cx.new_instance_draw_call(&self.git_marker.shader, 0);
             cx.new_instance_draw_call(&self.text.shader, 0);
             self._line_number_inst = Some(self.line_number_text.begin_text(cx));
+            cx.new_instance_draw_call(&self.message_text.shader, 0);
+            self._message_inst = Some(self.message_text.begin_text(cx));
             
             if let Some(select_scroll) = &mut self._select_scroll {
                 let scroll_pos = self.view.get_scroll_pos(cx);
@@ -983,6 +1538,7 @@ impl CodeEditor {
             self.set_font_size(cx, self.open_font_size);
             self._draw_cursors = DrawCursors::new();
             self._draw_messages = DrawCursors::new();
+            self._draw_search = DrawCursors::new();
             self._tokens_on_line = 0;
             self._line_largest_font = 0.;
             self._visible_lines = 0;
@@ -991,17 +1547,62 @@ impl CodeEditor {
             self._indent_stack.truncate(0);
             self._indent_id_alloc = 1.0;
             self._paren_stack.truncate(0);
+            self._folds = text_buffer.folds.clone();
+            self._line_is_hidden = false;
+            let wrap_width = self.compute_wrap_width(cx);
+            // with the cursors letting go they update in place
+            self.cursors.wraps = Rc::default();
+            Rc::make_mut(&mut self._wraps).update(wrap_width, &text_buffer.lines, text_buffer.mutation_id);
+            self.cursors.wraps = self._wraps.clone();
+            self._line_col = 0;
+            self._line_wrap = 0;
             self._draw_cursors.set_next(&self.cursors.set);
+            self._inline_messages.truncate(0);
+            self._inline_message_next = 0;
             if text_buffer.messages.mutation_id != text_buffer.mutation_id {
                 self._draw_messages.term(&text_buffer.messages.cursors);
             }
             else {
                 self._draw_messages.set_next(&text_buffer.messages.cursors);
+                for (cursor, body) in text_buffer.messages.cursors.iter().zip(text_buffer.messages.bodies.iter()) {
+                    self._inline_messages.push(InlineMessage {
+                        row: text_buffer.offset_to_text_pos(cursor.order().0).row,
+                        level: body.level.clone(),
+                        body: body.body.clone(),
+                        detail: body.detail.clone()
+                    });
+                }
+                self._inline_messages.sort_by_key( | message | message.row);
             }
+            self.find_bar.update_matches(text_buffer);
+            self._draw_search.set_next(&self.find_bar.match_cursors);
             self._last_cursor_pos = self.cursors.get_last_cursor_text_pos(text_buffer);
+            if text_buffer.git.is_stale(text_buffer.mutation_id) {
+                // right away after a load, while typing once it stops for a bit
+                if text_buffer.git.mutation_id == 0 {
+                    text_buffer.git.update(&text_buffer.lines, text_buffer.mutation_id);
+                }
+                else if self._git_diff_mutation_id != text_buffer.mutation_id {
+                    self._git_diff_mutation_id = text_buffer.mutation_id;
+                    cx.stop_timer(&mut self._git_diff_timer);
+                    self._git_diff_timer = cx.start_timer(self.git_diff_delay, false);
+                }
+            }
+            self._git_rows = text_buffer.git.rows.clone();
+            // a message on the row takes the place of the blame
+            let row = self._last_cursor_pos.row;
+            let blame_shown = !text_buffer.git.is_stale(text_buffer.mutation_id) && !self._inline_messages.iter().any( | message | message.row == row);
+            self._blame = match if blame_shown {text_buffer.git.blame_at(row)} else {None} {
+                Some(blame) => {
+                    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map( | d | d.as_secs()).unwrap_or(0);
+                    format!("{}, {} - {}", blame.author, format_git_age(now.saturating_sub(blame.time)), blame.summary).chars().take(120).collect()
+                },
+                None => Vec::new()
+            };
             
             // indent
             cx.move_turtle(self.line_number_width, self.top_padding);
+            self._line_top = cx.get_rel_turtle_walk().y;
             
             // lets compute our scroll line position and keep it where it is
             self.do_folding_animation_step(cx);
@@ -1038,7 +1639,10 @@ impl CodeEditor {
                     if focus_line == line {
                         ypos_at_line = ypos;
                     }
-                    ypos += if geom.was_folded {
+                    if geom.was_hidden {
+                        continue
+                    }
+                    ypos += geom.block_height + (geom.wraps + 1) as f32 * if geom.was_folded {
                         self._monospace_base.y * self._anim_font_size
                     }
                     else {
@@ -1046,7 +1650,7 @@ impl CodeEditor {
                     }
                 }
                 ypos += self._final_fill_height;
-                let dy = self._line_geometry[focus_line].walk.y - ypos_at_line;
+                let dy = self._line_geometry[focus_line].top - ypos_at_line;
                 let sv = self.view.get_scroll_view_total();
                 self.view.set_scroll_view_total(cx, Vec2 {x: sv.x, y: ypos});
                 let scroll_pos = self.view.get_scroll_pos(cx);
@@ -1070,16 +1674,25 @@ impl CodeEditor {
     
     fn draw_new_line(&mut self, cx: &mut Cx) {
         // line geometry is used for scrolling look up of cursors
-        let line_geom = LineGeom {
+        let mut line_geom = LineGeom {
             walk: cx.get_rel_turtle_walk(),
+            top: self._line_top,
+            wraps: self._line_wrap,
             font_size: self._line_largest_font,
             was_folded: self._line_was_folded,
+            was_hidden: false,
+            block_height: 0.,
             indent_id: if let Some((_, id)) = self._indent_stack.last() {*id}else {0.}
         };
+        let row = self._line_geometry.len();
+        let messages = self.inline_messages_at(row);
         
         // draw a linenumber if we are visible
         let origin = cx.get_turtle_origin();
-        if cx.turtle_line_is_visible(self._monospace_size.y, self._scroll_pos) {
+        // a wrapped row is visible when its first or its last visual line is
+        let wrap_height = line_geom.walk.y - line_geom.top;
+        let scroll_top = Vec2 {x: self._scroll_pos.x, y: self._scroll_pos.y + wrap_height};
+        if cx.turtle_line_is_visible(self._monospace_size.y, self._scroll_pos) || wrap_height > 0. && cx.turtle_line_is_visible(self._monospace_size.y, scroll_top) {
             // lets format a number, we go to 4 numbers
             // yes this is dumb as rocks. but we need to be cheapnfast
             let chunk = &mut self._line_number_chunk;
@@ -1110,20 +1723,45 @@ impl CodeEditor {
                 self.line_number_text.color = self.colors.line_number_normal;
             }
             let chunk_width = self._monospace_size.x * 5.0;
-            self.line_number_text.add_text(cx, origin.x + (self.line_number_width - chunk_width - 10.), origin.y + line_geom.walk.y, 0, self._line_number_inst.as_mut().unwrap(), chunk, | _, _, _, _ | {0.});
+            self.line_number_text.add_text(cx, origin.x + (self.line_number_width - chunk_width - 10.), origin.y + line_geom.top, 0, self._line_number_inst.as_mut().unwrap(), chunk, | _, _, _, _ | {0.});
+            self.draw_fold_marker(cx, origin, &line_geom);
+            self.draw_git_marker(cx, origin, &line_geom);
+            if messages.0 != messages.1 && !self._line_was_folded {
+                self.draw_inline_message(cx, origin, &line_geom, messages);
+            }
+            else if row == self._last_cursor_pos.row && self._blame.len() > 0 && !self._line_was_folded {
+                self.draw_blame(cx, origin, &line_geom);
+            }
         }
         
         cx.turtle_new_line_min_height(self._monospace_size.y);
+        
+        if self.show_message_detail && messages.0 != messages.1 && row == self._last_cursor_pos.row {
+            line_geom.block_height = self.draw_message_detail(cx, messages);
+        }
 
         cx.move_turtle(self.line_number_width, 0.);
+        self._line_top = cx.get_rel_turtle_walk().y;
+        self._line_col = 0;
+        self._line_wrap = 0;
         
         self._tokens_on_line = 0;
         //self._line_was_visible = false;
         
         self._draw_cursors.process_newline();
         self._draw_messages.process_newline();
+        self._draw_search.process_newline();
+        
+        self.draw_selection_highlight(cx, line_geom.walk.y);
         
-        // highlighting the selection
+        // search for all markings
+        self._line_geometry.push(line_geom);
+        self._line_largest_font = self.text.font_size;
+        self._line_is_hidden = self._folds.is_hidden(self._line_geometry.len());
+    }
+    
+    // the other places the selected text is, on the visual line at y
+    fn draw_selection_highlight(&mut self, cx: &mut Cx, y: f32) {
         let hl_len = self._highlight_selection.len();
         if hl_len != 0 {
             for bp in 0..self._line_chunk.len().max(hl_len) - hl_len {
@@ -1140,7 +1778,7 @@ impl CodeEditor {
                     let max_x = self._line_chunk[bp + hl_len].0;
                     self.draw_token_highlight_quad(cx, Rect {
                         x: min_x,
-                        y: line_geom.walk.y + origin.y,
+                        y: y + origin.y,
                         w: max_x - min_x,
                         h: self._monospace_size.y,
                     });
@@ -1148,16 +1786,158 @@ impl CodeEditor {
             }
             self._line_chunk.truncate(0);
         }
+    }
         
-        // search for all markings
+    // a soft wrapped row goes on at its indent on the next visual line
+    fn draw_wrap_line(&mut self, cx: &mut Cx) {
+        let walk_y = cx.get_rel_turtle_walk().y;
+        self.draw_selection_highlight(cx, walk_y);
+        cx.turtle_new_line_min_height(self._monospace_size.y);
+        self._draw_cursors.process_newline();
+        self._draw_messages.process_newline();
+        self._draw_search.process_newline();
+        self._line_wrap += 1;
+        let indent = self._wraps.line_indent(self._line_geometry.len(), self._line_wrap);
+        cx.move_turtle(self.line_number_width + indent as f32 * self._monospace_size.x, 0.);
+    }
+    
+    // the range of _inline_messages on row, rows come in order so we only walk forward
+    fn inline_messages_at(&mut self, row: usize) -> (usize, usize) {
+        while self._inline_message_next < self._inline_messages.len() && self._inline_messages[self._inline_message_next].row < row {
+            self._inline_message_next += 1;
+        }
+        let start = self._inline_message_next;
+        let mut end = start;
+        while end < self._inline_messages.len() && self._inline_messages[end].row == row {
+            end += 1;
+        }
+        (start, end)
+    }
+    
+    fn message_color(&self, level: &TextBufferMessageLevel) -> Color {
+        match level {
+            TextBufferMessageLevel::Error => self.colors.message_error,
+            TextBufferMessageLevel::Warning => self.colors.message_warning,
+            TextBufferMessageLevel::Log => self.colors.message_log,
+        }
+    }
+    
+    // the first line of the first message on the row, after the code
+    fn draw_inline_message(&mut self, cx: &mut Cx, origin: Vec2, line_geom: &LineGeom, messages: (usize, usize)) {
+        let message = &self._inline_messages[messages.0];
+        let mut chunk: Vec<char> = message.body.lines().next().unwrap_or("").chars().take(120).collect();
+        if messages.1 - messages.0 > 1 {
+            chunk.extend(format!("  (+{} more)", messages.1 - messages.0 - 1).chars());
+        }
+        // a folded row has the ellipsis box there
+        let gap = if self._folds.is_folded(self._line_geometry.len()) {5.} else {2.};
+        self.message_text.color = self.message_color(&message.level);
+        self.message_text.font_size = self.text.font_size;
+        self.message_text.add_text(cx, origin.x + line_geom.walk.x + gap * self._monospace_size.x, origin.y + line_geom.walk.y, 0, self._message_inst.as_mut().unwrap(), &chunk, | _, _, _, _ | {0.});
+    }
+    
+    // the full text of the messages on the row in a block under it, returns the height it took
+    fn draw_message_detail(&mut self, cx: &mut Cx, messages: (usize, usize)) -> f32 {
+        let mut lines = Vec::new();
+        for index in messages.0..messages.1 {
+            let message = &self._inline_messages[index];
+            let color = self.message_color(&message.level);
+            let text = message.detail.as_ref().unwrap_or(&message.body);
+            for line in text.trim_end().lines().take(40) {
+                lines.push((color, line.chars().collect::<Vec<char>>()));
+            }
+        }
+        let line_height = self._monospace_base.y * self.open_font_size;
+        let padding = 0.5 * line_height;
+        let height = lines.len() as f32 * line_height + 2. * padding;
+        let origin = cx.get_turtle_origin();
+        let walk = cx.get_rel_turtle_walk();
+        self.message_block.color = self.colors.message_block;
+        self.message_block.draw_quad_abs(cx, Rect {
+            x: origin.x + self.line_number_width,
+            y: origin.y + walk.y,
+            w: cx.get_width_total().max(self.view.get_scroll_view_total().x),
+            h: height
+        });
+        self.message_text.font_size = self.open_font_size;
+        for (index, (color, chunk)) in lines.iter().enumerate() {
+            self.message_text.color = *color;
+            self.message_text.add_text(cx, origin.x + self.line_number_width + 2. * self._monospace_size.x, origin.y + walk.y + padding + index as f32 * line_height, 0, self._message_inst.as_mut().unwrap(), chunk, | _, _, _, _ | {0.});
+        }
+        cx.walk_turtle(Bounds::Fix(0.), Bounds::Fix(height), Margin::zero(), None);
+        cx.turtle_new_line();
+        height
+    }
+    
+    fn draw_fold_marker(&mut self, cx: &mut Cx, origin: Vec2, line_geom: &LineGeom) {
+        let row = self._line_geometry.len();
+        if self._folds.range_at(row).is_none() {
+            return
+        }
+        let folded = self._folds.is_folded(row);
+        let size = (self.fold_marker_width - 2.).min(self._monospace_size.y);
+        self.fold_marker.color = self.colors.fold_marker;
+        let inst = self.fold_marker.draw_quad_abs(cx, Rect {
+            x: origin.x + self.line_number_width - self.fold_marker_width,
+            y: origin.y + line_geom.top + 0.5 * (self._monospace_size.y - size),
+            w: size,
+            h: size
+        });
+        inst.push_float(cx, if folded {1.} else {0.});
+        // and a little box with dots where the folded rows went
+        if folded {
+            self.fold_ellipsis.color = self.colors.fold_ellipsis;
+            self.fold_ellipsis.draw_quad_abs(cx, Rect {
+                x: origin.x + line_geom.walk.x + self._monospace_size.x,
+                y: origin.y + line_geom.walk.y,
+                w: self._monospace_size.x * 3.,
+                h: self._monospace_size.y
+            });
+        }
+    }
+    
+    // a bar in front of the line numbers for rows that differ from HEAD
+    fn draw_git_marker(&mut self, cx: &mut Cx, origin: Vec2, line_geom: &LineGeom) {
+        let row = self._line_geometry.len();
+        let git_row = self._git_rows.get(row).cloned().unwrap_or_default();
+        self.git_marker.color = match git_row {
+            TextGitRow::Unchanged => return,
+            TextGitRow::Added => self.colors.git_added,
+            TextGitRow::Modified => self.colors.git_modified,
+            TextGitRow::DeletedAbove => self.colors.git_deleted
+        };
+        let rect = if git_row == TextGitRow::DeletedAbove {
+            Rect {x: origin.x + 1., y: origin.y + line_geom.top - 1., w: 8., h: 2.}
+        }
+        else {
+            Rect {x: origin.x + 2., y: origin.y + line_geom.top, w: 3., h: line_geom.walk.y - line_geom.top + self._monospace_size.y}
+        };
+        self.git_marker.draw_quad_abs(cx, rect);
+    }
+    
+    fn draw_blame(&mut self, cx: &mut Cx, origin: Vec2, line_geom: &LineGeom) {
+        let gap = if self._folds.is_folded(self._line_geometry.len()) {5.} else {4.};
+        self.message_text.color = self.colors.git_blame;
+        self.message_text.font_size = self.text.font_size;
+        self.message_text.add_text(cx, origin.x + line_geom.walk.x + gap * self._monospace_size.x, origin.y + line_geom.walk.y, 0, self._message_inst.as_mut().unwrap(), &self._blame, | _, _, _, _ | {0.});
+    }
+    
+    // rows inside a folded block take no space, their geometry is that of the row they folded into
+    fn draw_hidden_line(&mut self) {
+        let line_geom = LineGeom {
+            was_hidden: true,
+            block_height: 0.,
+            ..self._line_geometry.last().cloned().unwrap_or_default()
+        };
         self._line_geometry.push(line_geom);
-        self._line_largest_font = self.text.font_size;
+        self._line_is_hidden = self._folds.is_hidden(self._line_geometry.len());
     }
     
     fn draw_indent_lines(&mut self, cx: &mut Cx, geom_y: f32, tabs: usize) {
         let y_pos = geom_y - cx.get_turtle_origin().y;
-        let tab_variable_width = self._monospace_base.x * 4. * self._anim_font_size;
-        let tab_fixed_width = self._monospace_base.x * 4. * self.open_font_size;
+        let indent_width = self.indent.width as f32;
+        let tab_variable_width = self._monospace_base.x * indent_width * self._anim_font_size;
+        let tab_fixed_width = self._monospace_base.x * indent_width * self.open_font_size;
         let mut off = self.line_number_width;
         for i in 0..tabs {
             let (indent_color, indent_id) = if i < self._indent_stack.len() {self._indent_stack[i]}else {(self.colors.indent_line_unknown, 0.)};
@@ -1184,6 +1964,17 @@ impl CodeEditor {
         let offset = token_chunk.offset; // end_offset - chunk.len() - 1;
         let next_char = token_chunk.next;
         
+        // folded away, just keep the paren stack and the line count going
+        if self._line_is_hidden {
+            match token_type {
+                TokenType::ParenOpen => self.draw_paren_open(token_chunks_index, offset, next_char, chunk),
+                TokenType::ParenClose => self.draw_paren_close(cx, token_chunks_index, offset, next_char, chunk),
+                TokenType::Newline => self.draw_hidden_line(),
+                _ => ()
+            }
+            return
+        }
+        
         // maintain paren stack
         if token_type == TokenType::ParenOpen {
             self.draw_paren_open(token_chunks_index, offset, next_char, chunk);
@@ -1193,7 +1984,7 @@ impl CodeEditor {
         if self._tokens_on_line == 0 {
             let font_size = match token_type {
                 TokenType::Whitespace => {
-                    let tabs = chunk.len()>>2;
+                    let tabs = chunk.len() / self.indent.width.max(1);
                     while tabs > self._indent_stack.len() {
                         self._indent_stack.push((self._last_indent_color, self._indent_id_alloc));
                         // allocating an indent_id, we also need to
@@ -1209,7 +2000,8 @@ impl CodeEditor {
                     // lets change the fontsize
                     if tabs >= self.folding_depth || next_char == '\n' {
                         // ok lets think. we need to move it over by the delta of 8 spaces * _anim_font_size
-                        let dx = (self._monospace_base.x * self.open_font_size * 4. * (self.folding_depth as f32)) - (self._monospace_base.x * self._anim_font_size * 4. * (self.folding_depth as f32));
+                        let indent_width = self.indent.width as f32;
+                        let dx = (self._monospace_base.x * self.open_font_size * indent_width * (self.folding_depth as f32)) - (self._monospace_base.x * self._anim_font_size * indent_width * (self.folding_depth as f32));
                         cx.move_turtle(dx, 0.0);
                         self._line_was_folded = true;
                         self._anim_font_size
@@ -1249,143 +2041,164 @@ impl CodeEditor {
                 _ => ()
             }
         }
-        // lets check if the geom is visible
-        if let Some(geom) = cx.walk_turtle_right_no_wrap(
-            self._monospace_size.x * (chunk.len() as f32),
-            self._monospace_size.y,
-            self._scroll_pos
-        ) {
-            let mut mark_spaces = 0.0;
-            // determine chunk color
-            self.text.color = match token_type {
-                TokenType::Whitespace => {
-                    if self._tokens_on_line == 0 && chunk[0] == ' ' {
-                        let tabs = chunk.len()>>2;
-                        // if self._last_tabs
-                        self._last_tabs = tabs;
-                        self._newline_tabs = tabs;
-                        self.draw_indent_lines(cx, geom.y, tabs);
+        // a soft wrapped row goes to the next visual line before or inside the chunk
+        let row = self._line_geometry.len();
+        let mut piece_start = 0;
+        while piece_start < chunk.len() {
+            let mut piece_end = chunk.len();
+            if let Some(brk) = self._wraps.row_breaks(row).get(self._line_wrap).cloned() {
+                if brk <= self._line_col {
+                    self.draw_wrap_line(cx);
+                    continue
+                }
+                piece_end = piece_end.min(piece_start + brk - self._line_col);
+            }
+            let piece = &chunk[piece_start..piece_end];
+            let piece_offset = offset + piece_start;
+            self._line_col += piece.len();
+            piece_start = piece_end;
+            
+            // lets check if the geom is visible
+            if let Some(geom) = cx.walk_turtle_right_no_wrap(
+                self._monospace_size.x * (piece.len() as f32),
+                self._monospace_size.y,
+                self._scroll_pos
+            ) {
+                let mut mark_spaces = 0.0;
+                // determine chunk color
+                self.text.color = match token_type {
+                    TokenType::Whitespace => {
+                        if self._tokens_on_line == 0 && chunk[0] == ' ' {
+                            let tabs = chunk.len() / self.indent.width.max(1);
+                            // if self._last_tabs
+                            self._last_tabs = tabs;
+                            self._newline_tabs = tabs;
+                            self.draw_indent_lines(cx, geom.y, tabs);
+                        }
+                        else if next_char == '\n' {
+                            mark_spaces = 1.0;
+                        }
+                        self.colors.whitespace
+                    },
+                    TokenType::Newline => {
+                        if self._tokens_on_line == 0 {
+                            self._newline_tabs = 0;
+                            self.draw_indent_lines(cx, geom.y, self._last_tabs);
+                        }
+                        else {
+                            self._last_tabs = self._newline_tabs;
+                            self._newline_tabs = 0;
+                        }
+                        self.colors.whitespace
+                    },
+                    TokenType::BuiltinType => self.colors.keyword,
+                    TokenType::Keyword => self.colors.keyword,
+                    TokenType::Bool => self.colors.keyword,
+                    TokenType::Flow => {
+                        self.colors.flow
                     }
-                    else if next_char == '\n' {
-                        mark_spaces = 1.0;
+                    TokenType::Looping => {
+                        self.colors.looping
                     }
-                    self.colors.whitespace
-                },
-                TokenType::Newline => {
-                    if self._tokens_on_line == 0 {
-                        self._newline_tabs = 0;
-                        self.draw_indent_lines(cx, geom.y, self._last_tabs);
+                    TokenType::TypeDef => {
+                        self.colors.keyword
                     }
-                    else {
-                        self._last_tabs = self._newline_tabs;
-                        self._newline_tabs = 0;
+                    TokenType::Fn => {
+                        self.colors.keyword
                     }
-                    self.colors.whitespace
-                },
-                TokenType::BuiltinType => self.colors.keyword,
-                TokenType::Keyword => self.colors.keyword,
-                TokenType::Bool => self.colors.keyword,
-                TokenType::Flow => {
-                    self.colors.flow
-                }
-                TokenType::Looping => {
-                    self.colors.looping
-                }
-                TokenType::TypeDef => {
-                    self.colors.keyword
-                }
-                TokenType::Fn => {
-                    self.colors.keyword
-                }
-                TokenType::Identifier => {
-                    if chunk == &self._highlight_token[0..] {
-                        self.draw_token_highlight_quad(cx, geom);
+                    TokenType::Identifier => {
+                        if chunk == &self._highlight_token[0..] {
+                            self.draw_token_highlight_quad(cx, geom);
                         
+                        }
+                        self.colors.identifier
                     }
-                    self.colors.identifier
-                }
-                TokenType::Call => {
-                    if chunk == &self._highlight_token[0..] {
-                        self.draw_token_highlight_quad(cx, geom);
-                    }
-                    self.colors.call
-                },
-                TokenType::TypeName => {
-                    if chunk == &self._highlight_token[0..] {
-                        self.draw_token_highlight_quad(cx, geom);
-                    }
-                    self.colors.type_name
-                },
-                TokenType::Regex => self.colors.string,
-                TokenType::String => self.colors.string,
-                TokenType::Number => self.colors.number,
-                TokenType::CommentMultiBegin => self.colors.comment,
-                TokenType::CommentMultiEnd => self.colors.comment,
-                TokenType::CommentLine => self.colors.comment,
-                TokenType::CommentChunk => self.colors.comment,
-                TokenType::ParenOpen => {
-                    let depth = self._paren_stack.len();
-                    self._paren_stack.last_mut().unwrap().geom_open = Some(geom);
-                    match depth % 2 {
-                        0 => self.colors.paren_d1,
-                        _ => self.colors.paren_d2,
-                    }
-                },
-                TokenType::ParenClose => {
-                    if let Some(paren) = self._paren_stack.last_mut() {
-                        paren.geom_close = Some(geom);
-                    }
-                    else {
-                        self.paren_pair.color = self.colors.paren_pair_fail;
-                        self.paren_pair.draw_quad_abs(cx, geom);
-                    }
-                    let depth = self._paren_stack.len();
-                    match depth % 2 {
-                        0 => self.colors.paren_d1,
-                        _ => self.colors.paren_d2,
-                        //_=>self.colors.paren_d3
-                    }
-                },
-                TokenType::Operator => self.colors.operator,
-                TokenType::Namespace => self.colors.operator,
-                TokenType::Hash => self.colors.operator,
-                TokenType::Delimiter => self.colors.delimiter,
-                TokenType::Colon => self.colors.delimiter,
-                TokenType::Splat => self.colors.operator,
-                TokenType::Eof => self.colors.unexpected,
-                TokenType::Unexpected => self.colors.unexpected
-            };
+                    TokenType::Call => {
+                        if chunk == &self._highlight_token[0..] {
+                            self.draw_token_highlight_quad(cx, geom);
+                        }
+                        self.colors.call
+                    },
+                    TokenType::TypeName => {
+                        if chunk == &self._highlight_token[0..] {
+                            self.draw_token_highlight_quad(cx, geom);
+                        }
+                        self.colors.type_name
+                    },
+                    TokenType::Regex => self.colors.string,
+                    TokenType::String => self.colors.string,
+                    TokenType::Number => self.colors.number,
+                    TokenType::CommentMultiBegin => self.colors.comment,
+                    TokenType::CommentMultiEnd => self.colors.comment,
+                    TokenType::CommentLine => self.colors.comment,
+                    TokenType::CommentChunk => self.colors.comment,
+                    TokenType::ParenOpen => {
+                        let depth = self._paren_stack.len();
+                        self._paren_stack.last_mut().unwrap().geom_open = Some(geom);
+                        match depth % 2 {
+                            0 => self.colors.paren_d1,
+                            _ => self.colors.paren_d2,
+                        }
+                    },
+                    TokenType::ParenClose => {
+                        if let Some(paren) = self._paren_stack.last_mut() {
+                            paren.geom_close = Some(geom);
+                        }
+                        else {
+                            self.paren_pair.color = self.colors.paren_pair_fail;
+                            self.paren_pair.draw_quad_abs(cx, geom);
+                        }
+                        let depth = self._paren_stack.len();
+                        match depth % 2 {
+                            0 => self.colors.paren_d1,
+                            _ => self.colors.paren_d2,
+                            //_=>self.colors.paren_d3
+                        }
+                    },
+                    TokenType::Operator => self.colors.operator,
+                    TokenType::Namespace => self.colors.operator,
+                    TokenType::Hash => self.colors.operator,
+                    TokenType::Delimiter => self.colors.delimiter,
+                    TokenType::Colon => self.colors.delimiter,
+                    TokenType::Splat => self.colors.operator,
+                    TokenType::Eof => self.colors.unexpected,
+                    TokenType::Unexpected => self.colors.unexpected
+                };
             
-            if self._tokens_on_line == 0 {
-                self._visible_lines += 1;
-                //self._line_was_visible = true;
-            }
+                if self._tokens_on_line == 0 {
+                    self._visible_lines += 1;
+                    //self._line_was_visible = true;
+                }
             
-            let cursors = &self.cursors.set;
-            //let messages_cursors = &text_buffer.message_cursors;
-            let last_cursor = self.cursors.last_cursor;
-            let draw_cursors = &mut self._draw_cursors;
-            let draw_messages = &mut self._draw_messages;
-            let height = self._monospace_size.y;
+                let cursors = &self.cursors.set;
+                //let messages_cursors = &text_buffer.message_cursors;
+                let last_cursor = self.cursors.last_cursor;
+                let draw_cursors = &mut self._draw_cursors;
+                let draw_messages = &mut self._draw_messages;
+                let search_cursors = &self.find_bar.match_cursors;
+                let draw_search = &mut self._draw_search;
+                let height = self._monospace_size.y;
             
-            // actually generate the GPU data for the text
-            let z = 2.0 + self._paren_stack.len() as f32;
-            self.text.z = z;
-            if self._highlight_selection.len() > 0 { // slow loop
-                //let draw_search = &mut self._draw_search;
-                let line_chunk = &mut self._line_chunk;
-                self.text.add_text(cx, geom.x, geom.y, offset, self._text_inst.as_mut().unwrap(), &chunk, | ch, offset, x, w | {
-                    line_chunk.push((x, ch));
-                    //draw_search.mark_text_select_only(cursors, offset, x, geom.y, w, height);
-                    draw_messages.mark_text_select_only(message_cursors, offset, x, geom.y, w, height);
-                    draw_cursors.mark_text_with_cursor(cursors, ch, offset, x, geom.y, w, height, z, last_cursor, mark_spaces)
-                });
-            }
-            else { // fast loop
-                self.text.add_text(cx, geom.x, geom.y, offset, self._text_inst.as_mut().unwrap(), &chunk, | ch, offset, x, w | {
-                    draw_messages.mark_text_select_only(message_cursors, offset, x, geom.y, w, height);
-                    draw_cursors.mark_text_with_cursor(cursors, ch, offset, x, geom.y, w, height, z, last_cursor, mark_spaces)
-                });
+                // actually generate the GPU data for the text
+                let z = 2.0 + self._paren_stack.len() as f32;
+                self.text.z = z;
+                if self._highlight_selection.len() > 0 { // slow loop
+                    //let draw_search = &mut self._draw_search;
+                    let line_chunk = &mut self._line_chunk;
+                    self.text.add_text(cx, geom.x, geom.y, piece_offset, self._text_inst.as_mut().unwrap(), &piece, | ch, offset, x, w | {
+                        line_chunk.push((x, ch));
+                        draw_search.mark_text_select_only(search_cursors, offset, x, geom.y, w, height);
+                        draw_messages.mark_text_select_only(message_cursors, offset, x, geom.y, w, height);
+                        draw_cursors.mark_text_with_cursor(cursors, ch, offset, x, geom.y, w, height, z, last_cursor, mark_spaces)
+                    });
+                }
+                else { // fast loop
+                    self.text.add_text(cx, geom.x, geom.y, piece_offset, self._text_inst.as_mut().unwrap(), &piece, | ch, offset, x, w | {
+                        draw_search.mark_text_select_only(search_cursors, offset, x, geom.y, w, height);
+                        draw_messages.mark_text_select_only(message_cursors, offset, x, geom.y, w, height);
+                        draw_cursors.mark_text_with_cursor(cursors, ch, offset, x, geom.y, w, height, z, last_cursor, mark_spaces)
+                    });
+                }
             }
         }
         self._tokens_on_line += 1;
@@ -1494,6 +2307,7 @@ impl CodeEditor {
         self.text.end_text(cx, self._text_inst.as_ref().unwrap());
         self._text_area = self._text_inst.take().unwrap().inst.into_area();
         self.line_number_text.end_text(cx, self._line_number_inst.as_ref().unwrap());
+        self.message_text.end_text(cx, self._message_inst.as_ref().unwrap());
         
         // unmatched highlighting
         self.draw_paren_unmatched(cx);
@@ -1501,6 +2315,7 @@ impl CodeEditor {
         //self.do_selection_animations(cx);
         self.draw_selections(cx);
         self.draw_message_markers(cx, text_buffer);
+        self.draw_search_markers(cx);
         
         // inject a final page
         self._final_fill_height = cx.get_height_total() - self._monospace_size.y;
@@ -1510,14 +2325,61 @@ impl CodeEditor {
         self.do_selection_scrolling(cx, text_buffer);
         self.place_ime_and_draw_cursor_row(cx);
         self.set_indent_line_highlight_id(cx);
+        let rows = self.compute_visible_rows(cx);
+        self.minimap.draw_minimap(cx, text_buffer, &self.colors, rows, &self.cursors, &self.find_bar.match_cursors);
+        self.find_bar.draw_find_bar(cx);
+        self.vim_status.draw_vim_status(cx, &self.vim);
+        let minimap_width = if self.minimap.enabled {self.minimap.width + self.minimap.right_pad} else {0.};
+        self.encoding_status.draw_encoding_status(cx, text_buffer, minimap_width);
+        self.draw_lsp_popup(cx);
+        self.draw_code_completion(cx);
         
         self.view.end_view(cx);
     }
     
-    fn do_jump_to_offset(&mut self, cx: &mut Cx, text_buffer: &TextBuffer) {
+    fn draw_lsp_popup(&mut self, cx: &mut Cx) {
+        if !self.lsp_popup.is_open() {
+            return
+        }
+        let origin = cx.get_turtle_origin();
+        // hovers sit under the mouse, the rest under the last cursor
+        let pos = if self.lsp_popup.is_hover() {
+            match self._hover_abs {
+                Some(hover_abs) => Vec2 {x: hover_abs.x - origin.x, y: hover_abs.y - origin.y + self._monospace_size.y},
+                None => return
+            }
+        }
+        else {
+            match self._draw_cursors.last_cursor {
+                Some(last_cursor) => {
+                    let rc = &self._draw_cursors.cursors[last_cursor];
+                    Vec2 {x: rc.x - origin.x - self._scroll_pos.x, y: rc.y + rc.h - origin.y - self._scroll_pos.y}
+                },
+                None => return
+            }
+        };
+        self.lsp_popup.draw_lsp_popup(cx, pos);
+    }
+    
+    fn draw_code_completion(&mut self, cx: &mut Cx) {
+        if !self.code_completion.is_open {
+            return
+        }
+        if let Some(last_cursor) = self._draw_cursors.last_cursor {
+            let origin = cx.get_turtle_origin();
+            let rc = &self._draw_cursors.cursors[last_cursor];
+            // line the list up with the start of the word
+            let word_w = self.code_completion.prefix.chars().count() as f32 * self._monospace_size.x;
+            let pos = Vec2 {x: rc.x - origin.x - self._scroll_pos.x - word_w, y: rc.y + rc.h - origin.y - self._scroll_pos.y};
+            self.code_completion.draw_code_completion(cx, pos);
+        }
+    }
+    
+    fn do_jump_to_offset(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer) {
         let offset = text_buffer.messages.jump_to_offset;
         // make one cursor, and start scrolling towards it
         self.cursors.clear_and_set_last_cursor_head_and_tail(offset, text_buffer);
+        self.reveal_cursors(text_buffer);
         self.scroll_last_cursor_visible(cx, text_buffer, self._final_fill_height * 0.8);
         self.view.redraw_view_area(cx);
     }
@@ -1550,6 +2412,19 @@ impl CodeEditor {
                 TextBufferMessageLevel::Log => self.colors.marker_log,
             };
             self.message_marker.draw_quad(cx, Rect {x: mark.rc.x - origin.x, y: mark.rc.y - origin.y, w: mark.rc.w, h: mark.rc.h});
+            // a message with quick fixes gets a tick at its end, ctrl+. opens them
+            if body.fixes.len() > 0 {
+                self.message_marker.color = self.colors.marker_fix;
+                self.message_marker.draw_quad(cx, Rect {x: mark.rc.x + mark.rc.w - origin.x, y: mark.rc.y - origin.y - 3., w: 3., h: mark.rc.h + 3.});
+            }
+        }
+    }
+    
+    fn draw_search_markers(&mut self, cx: &mut Cx) {
+        let origin = cx.get_turtle_origin();
+        self.search_marker.color = self.colors.search_match;
+        for mark in &self._draw_search.selections {
+            self.search_marker.draw_quad(cx, Rect {x: mark.rc.x - origin.x, y: mark.rc.y - origin.y, w: mark.rc.w, h: mark.rc.h});
         }
     }
     
@@ -1705,6 +2580,56 @@ impl CodeEditor {
         self._monospace_size.y = self._monospace_base.y * font_size;
     }
     
+    // the rows the view shows right now, hidden ones count too
+    fn compute_visible_rows(&self, cx: &Cx) -> MinimapRows {
+        let scroll = self.view.get_scroll_pos(cx);
+        let rect = self.view.get_view_area(cx).get_rect(cx, false);
+        let line_height = self._monospace_size.y;
+        let first_visible = self._line_geometry.partition_point( | geom | geom.walk.y + line_height <= scroll.y);
+        let end_visible = self._line_geometry.partition_point( | geom | geom.top < scroll.y + rect.h);
+        MinimapRows {
+            first_visible: first_visible,
+            visible: end_visible.max(first_visible + 1) - first_visible
+        }
+    }
+    
+    // scrolls the view so row is at the top, in between rows for the fraction
+    fn scroll_to_row(&mut self, cx: &mut Cx, row: f32) {
+        if self._line_geometry.len() == 0 {
+            return
+        }
+        let index = (row.max(0.) as usize).min(self._line_geometry.len() - 1);
+        let geom = &self._line_geometry[index];
+        let next_top = match self._line_geometry.get(index + 1) {
+            Some(next) => next.top,
+            None => geom.walk.y + self._monospace_size.y
+        };
+        let y = geom.top + (next_top - geom.top) * row.fract() - self.top_padding;
+        let scroll_pos = self.view.get_scroll_pos(cx);
+        if self.view.set_scroll_pos(cx, Vec2 {x: scroll_pos.x, y: y.max(0.)}) {
+            self.view.redraw_view_area(cx);
+        }
+    }
+    
+    // in columns of the open font, 0 when not wrapping
+    fn compute_wrap_width(&self, cx: &Cx) -> usize {
+        if !self.soft_wrap {
+            return 0
+        }
+        if let Some(wrap_column) = self.wrap_column {
+            return wrap_column.max(1)
+        }
+        let mono_width = self._monospace_base.x * self.open_font_size;
+        let mut width = cx.get_width_total() - self.line_number_width - 2. * mono_width;
+        if self.minimap.enabled {
+            width -= self.minimap.width + self.minimap.right_pad;
+        }
+        if mono_width <= 0. || width <= 0. {
+            return 0
+        }
+        (width / mono_width).max(1.) as usize
+    }
+    
     fn scroll_last_cursor_visible(&mut self, cx: &mut Cx, text_buffer: &TextBuffer, height_pad: f32) {
         // so we have to compute (approximately) the rect of our cursor
         if self.cursors.last_cursor >= self.cursors.set.len() {
@@ -1719,9 +2644,10 @@ impl CodeEditor {
             let geom = &self._line_geometry[row];
             let mono_size = Vec2 {x: self._monospace_base.x * geom.font_size, y: self._monospace_base.y * geom.font_size};
             //self.text.get_monospace_size(cx, geom.font_size);
+            let (line, col) = self._wraps.visual_pos(row, pos.col);
             let rect = Rect {
-                x: (pos.col as f32) * mono_size.x + self.line_number_width,
-                y: geom.walk.y - mono_size.y * 1. - 0.5 * height_pad,
+                x: (col as f32) * mono_size.x + self.line_number_width,
+                y: geom.top + (line as f32) * mono_size.y - mono_size.y * 1. - 0.5 * height_pad,
                 w: mono_size.x * 4.,
                 h: mono_size.y * 4. + height_pad
             };
@@ -1737,10 +2663,12 @@ impl CodeEditor {
         for (row, geom) in self._line_geometry.iter().enumerate() {
             //let geom = &self._line_geometry[pos.row];
             mono_size = Vec2 {x: self._monospace_base.x * geom.font_size, y: self._monospace_base.y * geom.font_size};
-            if rel.y < geom.walk.y || rel.y >= geom.walk.y && rel.y <= geom.walk.y + mono_size.y { // its on the right line
-                let col = ((rel.x - self.line_number_width).max(0.) / mono_size.x) as usize;
+            if rel.y < geom.top || rel.y >= geom.top && rel.y <= geom.walk.y + mono_size.y { // its on the right line
+                // on a soft wrapped row find the visual line too
+                let line = ((rel.y - geom.top).max(0.) / mono_size.y) as usize;
+                let visual_col = ((rel.x - self.line_number_width).max(0.) / mono_size.x) as usize;
                 // do a dumb calc
-                return TextPos {row: row, col: col};
+                return TextPos {row: row, col: self._wraps.col_at_visual(row, line, visual_col)};
             }
         }
         // otherwise the file is too short, lets use the last line
@@ -1755,7 +2683,7 @@ impl CodeEditor {
         for (row, geom) in self._line_geometry.iter().enumerate() {
             //let geom = &self._line_geometry[pos.row];
             mono_size = Vec2 {x: self._monospace_base.x * geom.font_size, y: self._monospace_base.y * geom.font_size};
-            if rel.y < geom.walk.y || rel.y >= geom.walk.y && rel.y <= geom.walk.y + mono_size.y { // its on the right line
+            if rel.y < geom.top || rel.y >= geom.top && rel.y <= geom.walk.y + mono_size.y { // its on the right line
                 return text_buffer.text_pos_to_offset(TextPos {row: row, col: end_col})
             }
         }
@@ -1833,10 +2761,15 @@ impl CodeEditor {
         let mut delta = 1;
         if pos.row > 0 && pos.row < self._line_geometry.len() {
             let mut scan = pos.row - 1;
+            // move_up steps over rows in folded blocks by itself
+            let mut steps = 0;
             while scan >0 {
-                if !self._line_geometry[scan].was_folded {
-                    delta = pos.row - scan;
-                    break;
+                if !self._line_geometry[scan].was_hidden {
+                    steps += 1;
+                    if !self._line_geometry[scan].was_folded {
+                        delta = steps;
+                        break;
+                    }
                 }
                 scan -= 1;
             }
@@ -1848,10 +2781,14 @@ impl CodeEditor {
         let pos = self.cursors.get_last_cursor_text_pos(text_buffer);
         let mut delta = 1;
         let mut scan = pos.row + 1;
+        let mut steps = 0;
         while scan < self._line_geometry.len() {
-            if !self._line_geometry[scan].was_folded {
-                delta = scan - pos.row;
-                break;
+            if !self._line_geometry[scan].was_hidden {
+                steps += 1;
+                if !self._line_geometry[scan].was_folded {
+                    delta = steps;
+                    break;
+                }
             }
             scan += 1;
         }
@@ -1868,7 +2805,7 @@ impl CodeEditor {
         if pos.row < self._line_geometry.len() {
             let geom = &self._line_geometry[pos.row];
             // check if cursor is visible
-            if geom.walk.y - scroll.y > 0. && geom.walk.y - scroll.y <rect.h { // visible
+            if geom.top - scroll.y > 0. && geom.top - scroll.y <rect.h { // visible
                 //println!("FOUND");
                 return pos.row
             }
@@ -1878,7 +2815,7 @@ impl CodeEditor {
         let scroll = self.view.get_scroll_pos(cx);
         let center_y = rect.h * 0.5 + scroll.y;
         for (line, geom) in self._line_geometry.iter().enumerate() {
-            if geom.walk.y > center_y {
+            if geom.top > center_y {
                 //println!("CENTER");
                 return line
             }
@@ -1886,7 +2823,7 @@ impl CodeEditor {
         
         // if we cant find the centerline, use the view top
         for (line, geom) in self._line_geometry.iter().enumerate() {
-            if geom.walk.y > scroll.y {
+            if geom.top > scroll.y {
                 //println!("TOP");
                 return line
             }
@@ -1924,12 +2861,26 @@ pub struct AnimSelect {
 
 #[derive(Clone, Default)]
 pub struct LineGeom {
+    // where the row ended, top is where it started when it soft wrapped
     walk: Vec2,
+    top: f32,
+    wraps: usize,
     was_folded: bool,
+    was_hidden: bool,
+    // the message detail block drawn under the row
+    block_height: f32,
     font_size: f32,
     indent_id: f32
 }
 
+#[derive(Clone)]
+pub struct InlineMessage {
+    row: usize,
+    level: TextBufferMessageLevel,
+    body: String,
+    detail: Option<String>
+}
+
 #[derive(Clone, Default)]
 pub struct SelectScroll {
     // pub margin:Margin,
@@ -1947,6 +2898,71 @@ pub struct ParenItem {
     exp_paren: char
 }
 
+// how the file is stored, in the bottom right corner so you know what saving it writes
+#[derive(Clone)]
+pub struct EncodingStatus {
+    pub bg: Quad,
+    pub text: Text,
+    pub padding: f32,
+    pub bg_color: Color,
+    pub label_color: Color
+}
+
+impl EncodingStatus {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            bg: Quad {
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 20.,
+                ..Quad::style(cx)
+            },
+            text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                do_dpi_dilate: true,
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 21.,
+                wrapping: Wrapping::Line,
+                ..Text::style(cx)
+            },
+            padding: 4.,
+            bg_color: color256(50, 50, 50),
+            label_color: color256(150, 150, 150)
+        }
+    }
+    
+    pub fn draw_encoding_status(&mut self, cx: &mut Cx, text_buffer: &TextBuffer, right_pad: f32) {
+        let status = if text_buffer.encoding == TextEncoding::Hex {
+            text_buffer.encoding.name().to_string()
+        }
+        else {
+            format!("{} {}", text_buffer.encoding.name(), text_buffer.line_endings.name())
+        };
+        let chunk: Vec<char> = status.chars().collect();
+        let origin = cx.get_turtle_origin();
+        let glyph_w = self.text.get_monospace_base(cx).x * self.text.font_size;
+        let line_height = self.text.get_monospace_base(cx).y * self.text.font_size;
+        let w = chunk.len() as f32 * glyph_w + 2. * self.padding;
+        let rect = Rect {
+            x: origin.x + (cx.get_width_total() - right_pad - w).max(0.),
+            y: origin.y + cx.get_height_total() - line_height - 2. * self.padding,
+            w: w,
+            h: line_height + 2. * self.padding
+        };
+        cx.new_instance_draw_call(&self.bg.shader, 0);
+        self.bg.color = self.bg_color;
+        self.bg.draw_quad_abs(cx, rect);
+        
+        cx.new_instance_draw_call(&self.text.shader, 0);
+        let mut aligned = self.text.begin_text(cx);
+        self.text.color = self.label_color;
+        self.text.add_text(cx, rect.x + self.padding, rect.y + self.padding, 0, &mut aligned, &chunk, | _, _, _, _ | {0.});
+        self.text.end_text(cx, &aligned);
+    }
+}
+
 impl AnimFoldingState {
     fn is_animating(&self) -> bool {
         match self {
@@ -0,0 +1,369 @@
+use render::*;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::textsearch::*;
+use crate::keymap::*;
+
+// The find/replace bar of the CodeEditor. It lives in the top right corner of the editor view
+// and takes the keyboard while open, all editing goes through the TextCursorSet of the editor
+// so matches become real cursors and replacing is a normal undo step. Its keys come from the
+// find bar commands of the Keymap.
+
+#[derive(Clone)]
+pub struct FindBar {
+    pub bg: Quad,
+    pub text: Text,
+    pub width: f32,
+    pub padding: f32,
+    pub bg_color: Color,
+    pub label_color: Color,
+    pub input_color: Color,
+    pub option_on_color: Color,
+    pub option_off_color: Color,
+    pub error_color: Color,
+    
+    pub search: TextSearch,
+    pub replace: String,
+    pub is_open: bool,
+    pub show_replace: bool,
+    pub replace_focus: bool,
+    pub matches: Vec<TextSearchMatch>,
+    pub match_cursors: Vec<TextCursor>,
+    pub current: Option<usize>,
+    pub error: Option<String>,
+    
+    pub _anchor: usize,
+    pub _key_pending: Vec<KeyEvent>,
+    pub _matches_mutation_id: u64,
+    pub _matches_search: TextSearch,
+    pub _matches_replace: String
+}
+
+#[derive(Clone, PartialEq)]
+pub enum FindBarEvent {
+    None,
+    // the bar ate the key, only needs a redraw
+    Handled,
+    // cursors got moved or the text got replaced, the editor should scroll to the last cursor
+    CursorsChanged
+}
+
+impl FindBar {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            bg: Quad {
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 20.,
+                ..Quad::style(cx)
+            },
+            text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                do_dpi_dilate: true,
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 21.,
+                wrapping: Wrapping::Line,
+                ..Text::style(cx)
+            },
+            width: 360.,
+            padding: 6.,
+            bg_color: color256(50, 50, 50),
+            label_color: color256(136, 136, 136),
+            input_color: color256(212, 212, 212),
+            option_on_color: color256(220, 220, 174),
+            option_off_color: color256(90, 90, 90),
+            error_color: color256(200, 0, 0),
+            search: TextSearch::default(),
+            replace: String::new(),
+            is_open: false,
+            show_replace: false,
+            replace_focus: false,
+            matches: Vec::new(),
+            match_cursors: Vec::new(),
+            current: None,
+            error: None,
+            _anchor: 0,
+            _key_pending: Vec::new(),
+            _matches_mutation_id: 0,
+            _matches_search: TextSearch::default(),
+            _matches_replace: String::new()
+        }
+    }
+    
+    pub fn open(&mut self, show_replace: bool, cursors: &TextCursorSet, text_buffer: &TextBuffer) {
+        let (start, end) = cursors.set[cursors.last_cursor].order();
+        // a single line selection becomes the query
+        if start != end {
+            let mut selected = String::new();
+            text_buffer.get_range_as_string(start, end - start, &mut selected);
+            if selected.find('\n').is_none() {
+                self.search.query = selected;
+            }
+        }
+        self._anchor = start;
+        self.is_open = true;
+        self.show_replace = show_replace;
+        self.replace_focus = false;
+        self._matches_mutation_id = 0;
+        self.update_matches(text_buffer);
+    }
+    
+    pub fn close(&mut self) {
+        self.is_open = false;
+        self.matches.truncate(0);
+        self.match_cursors.truncate(0);
+        self.current = None;
+        self.error = None;
+    }
+    
+    // recomputes the matches when the text or the query changed since last time
+    pub fn update_matches(&mut self, text_buffer: &TextBuffer) -> bool {
+        if !self.is_open
+            || self._matches_mutation_id == text_buffer.mutation_id
+            && self._matches_search == self.search
+            && self._matches_replace == self.replace {
+            return false
+        }
+        self._matches_mutation_id = text_buffer.mutation_id;
+        self._matches_search = self.search.clone();
+        self._matches_replace = self.replace.clone();
+        match self.search.find_in_text_buffer(text_buffer, &self.replace) {
+            Ok(matches) => {
+                self.matches = matches;
+                self.error = None;
+            },
+            Err(err) => {
+                self.matches.truncate(0);
+                self.error = Some(err);
+            }
+        }
+        self.match_cursors = self.matches.iter().map( | m | TextCursor {head: m.end, tail: m.start, max: 0}).collect();
+        if let Some(current) = self.current {
+            if current >= self.matches.len() {
+                self.current = None;
+            }
+        }
+        true
+    }
+    
+    // first match at or after offset, wrapping around
+    fn match_from(&self, offset: usize) -> Option<usize> {
+        if self.matches.len() == 0 {
+            return None
+        }
+        Some(self.matches.iter().position( | m | m.start >= offset).unwrap_or(0))
+    }
+    
+    fn select_current(&mut self, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) -> FindBarEvent {
+        if let Some(current) = self.current {
+            let m = &self.matches[current];
+            cursors.select_ranges(&[(m.start, m.end)], 0, text_buffer);
+            return FindBarEvent::CursorsChanged
+        }
+        FindBarEvent::Handled
+    }
+    
+    // the query changed, jump to the first match from where we started searching
+    fn search_changed(&mut self, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) -> FindBarEvent {
+        self.update_matches(text_buffer);
+        self.current = self.match_from(self._anchor);
+        self.select_current(cursors, text_buffer)
+    }
+    
+    pub fn find_next(&mut self, backwards: bool, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) -> FindBarEvent {
+        self.update_matches(text_buffer);
+        if self.matches.len() == 0 {
+            self.current = None;
+            return FindBarEvent::Handled
+        }
+        let (start, end) = cursors.set[cursors.last_cursor].order();
+        self.current = if backwards {
+            match self.matches.iter().rposition( | m | m.start < start) {
+                Some(index) => Some(index),
+                None => Some(self.matches.len() - 1)
+            }
+        }
+        else {
+            self.match_from(if start == end {start} else {start + 1})
+        };
+        if let Some(current) = self.current {
+            self._anchor = self.matches[current].start;
+        }
+        self.select_current(cursors, text_buffer)
+    }
+    
+    pub fn select_all_matches(&mut self, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) -> FindBarEvent {
+        self.update_matches(text_buffer);
+        let ranges: Vec<(usize, usize)> = self.matches.iter().map( | m | (m.start, m.end)).collect();
+        cursors.select_ranges(&ranges, self.current.unwrap_or(0), text_buffer);
+        FindBarEvent::CursorsChanged
+    }
+    
+    pub fn replace_current(&mut self, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> FindBarEvent {
+        self.update_matches(text_buffer);
+        if let Some(current) = self.current {
+            // only replace what the user is looking at
+            let (start, end) = cursors.set[cursors.last_cursor].order();
+            let m = self.matches[current].clone();
+            if m.start == start && m.end == end {
+                cursors.replace_matches(&[m.clone()], text_buffer);
+                self._anchor = m.start + m.replace.chars().count();
+                self.update_matches(text_buffer);
+                self.current = self.match_from(self._anchor);
+                return self.select_current(cursors, text_buffer)
+            }
+        }
+        self.find_next(false, cursors, text_buffer)
+    }
+    
+    pub fn replace_all(&mut self, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> FindBarEvent {
+        self.update_matches(text_buffer);
+        if self.matches.len() == 0 {
+            return FindBarEvent::Handled
+        }
+        let matches = self.matches.clone();
+        cursors.replace_matches(&matches, text_buffer);
+        self.current = None;
+        self.update_matches(text_buffer);
+        FindBarEvent::CursorsChanged
+    }
+    
+    // Return in the replace field replaces, elsewhere it goes to the next match
+    pub fn run_command(&mut self, command: Command, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> FindBarEvent {
+        match command {
+            Command::FindNext if self.replace_focus => self.replace_current(cursors, text_buffer),
+            Command::FindNext | Command::FindPrev => self.find_next(command == Command::FindPrev, cursors, text_buffer),
+            Command::FindSelectAll => self.select_all_matches(cursors, text_buffer),
+            Command::ReplaceNext => self.replace_current(cursors, text_buffer),
+            Command::ReplaceAll => self.replace_all(cursors, text_buffer),
+            Command::FindToggleCase => {
+                self.search.case_sensitive = !self.search.case_sensitive;
+                self.search_changed(cursors, text_buffer)
+            },
+            Command::FindToggleWholeWord => {
+                self.search.whole_word = !self.search.whole_word;
+                self.search_changed(cursors, text_buffer)
+            },
+            Command::FindToggleRegex => {
+                self.search.is_regex = !self.search.is_regex;
+                self.search_changed(cursors, text_buffer)
+            },
+            Command::FindSwitchField => {
+                if self.show_replace {
+                    self.replace_focus = !self.replace_focus;
+                }
+                FindBarEvent::Handled
+            },
+            _ => FindBarEvent::None
+        }
+    }
+    
+    pub fn handle_key_down(&mut self, ke: &KeyEvent, keymap: &Keymap, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> FindBarEvent {
+        if !self.is_open {
+            return FindBarEvent::None
+        }
+        match ke.key_code {
+            KeyCode::Escape => {
+                self.close();
+                return FindBarEvent::Handled
+            },
+            KeyCode::Alt => { // no code folding while typing a query
+                return FindBarEvent::Handled
+            },
+            KeyCode::Backspace => {
+                if self.replace_focus {
+                    self.replace.pop();
+                    return FindBarEvent::Handled
+                }
+                self.search.query.pop();
+                return self.search_changed(cursors, text_buffer)
+            },
+            _ => ()
+        }
+        match keymap.handle_find_bar_key(&mut self._key_pending, ke) {
+            KeymapEvent::Command(command) => self.run_command(command, cursors, text_buffer),
+            KeymapEvent::Pending => FindBarEvent::Handled,
+            KeymapEvent::None => FindBarEvent::None
+        }
+    }
+    
+    pub fn handle_text_input(&mut self, te: &TextInputEvent, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) -> FindBarEvent {
+        if !self.is_open {
+            return FindBarEvent::None
+        }
+        // the bar is single line, a pasted newline is as far as we go
+        let input = te.input.split('\n').next().unwrap_or("");
+        if self.replace_focus {
+            self.replace.push_str(input);
+            FindBarEvent::Handled
+        }
+        else {
+            self.search.query.push_str(input);
+            self.search_changed(cursors, text_buffer)
+        }
+    }
+    
+    fn draw_segment(&mut self, cx: &mut Cx, aligned: &mut AlignedInstance, x: &mut f32, y: f32, color: Color, segment: &str) {
+        let chunk: Vec<char> = segment.chars().collect();
+        self.text.color = color;
+        let glyph_w = self.text.get_monospace_base(cx).x * self.text.font_size;
+        self.text.add_text(cx, *x, y, 0, aligned, &chunk, | _, _, _, _ | {0.});
+        *x += glyph_w * chunk.len() as f32;
+    }
+    
+    pub fn draw_find_bar(&mut self, cx: &mut Cx) {
+        if !self.is_open {
+            return
+        }
+        let origin = cx.get_turtle_origin();
+        let line_height = self.text.get_monospace_base(cx).y * self.text.font_size;
+        let lines = if self.show_replace {2.} else {1.};
+        let rect = Rect {
+            x: origin.x + (cx.get_width_total() - self.width - 20.).max(0.),
+            y: origin.y,
+            w: self.width,
+            h: lines * line_height + 2. * self.padding
+        };
+        
+        cx.new_instance_draw_call(&self.bg.shader, 0);
+        self.bg.color = self.bg_color;
+        self.bg.draw_quad_abs(cx, rect);
+        
+        cx.new_instance_draw_call(&self.text.shader, 0);
+        let mut aligned = self.text.begin_text(cx);
+        let focus = | focussed: bool | if focussed {"_"} else {" "};
+        
+        let mut x = rect.x + self.padding;
+        let y = rect.y + self.padding;
+        let query = format!("{}{}", self.search.query, focus(!self.replace_focus));
+        let status = if let Some(error) = &self.error {
+            error.lines().last().unwrap_or("").to_string()
+        }
+        else if let Some(current) = self.current {
+            format!("{}/{}", current + 1, self.matches.len())
+        }
+        else {
+            format!("{}", self.matches.len())
+        };
+        let label_color = self.label_color;
+        let input_color = self.input_color;
+        self.draw_segment(cx, &mut aligned, &mut x, y, label_color, "Find    ");
+        self.draw_segment(cx, &mut aligned, &mut x, y, input_color, &query);
+        for (is_on, label) in &[(self.search.case_sensitive, " Aa"), (self.search.whole_word, " W"), (self.search.is_regex, " .*")] {
+            let color = if *is_on {self.option_on_color} else {self.option_off_color};
+            self.draw_segment(cx, &mut aligned, &mut x, y, color, label);
+        }
+        let status_color = if self.error.is_some() {self.error_color} else {label_color};
+        self.draw_segment(cx, &mut aligned, &mut x, y, status_color, &format!("  {}", status));
+        
+        if self.show_replace {
+            let mut x = rect.x + self.padding;
+            let replace = format!("{}{}", self.replace, focus(self.replace_focus));
+            self.draw_segment(cx, &mut aligned, &mut x, y + line_height, label_color, "Replace ");
+            self.draw_segment(cx, &mut aligned, &mut x, y + line_height, input_color, &replace);
+        }
+        self.text.end_text(cx, &aligned);
+    }
+}
@@ -1,76 +1,41 @@
-use render::*; 
-
 use crate::textbuffer::*;
-use crate::codeeditor::*;
+use crate::language::*;
+
+pub struct JSLanguage;
 
-#[derive(Clone)]
-pub struct JSEditor {
-    pub code_editor: CodeEditor,
+impl Language for JSLanguage {
+    fn name(&self) -> &'static str {"js"}
+    fn aliases(&self) -> &'static [&'static str] {&["javascript"]}
+    fn extensions(&self) -> &'static [&'static str] {&["js", "mjs", "cjs"]}
+    fn shebangs(&self) -> &'static [&'static str] {&["node", "nodejs", "deno", "bun"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(JSTokenizer::new())}
+    fn auto_format(&self, text_buffer: &mut TextBuffer) -> Option<Vec<Vec<char>>> {
+        Some(JSTokenizer::auto_format(text_buffer).out_lines)
+    }
+    fn line_comment(&self) -> Option<&'static str> {Some("//")}
+    fn folding_depth(&self) -> usize {3}
 }
 
-#[derive(Clone, PartialEq)]
-pub enum JSEditorEvent {
-    None,
-    Change
+pub struct JSTokenizer {
+    pub comment_single: bool,
+    pub comment_depth: usize
 }
 
-impl JSEditor {
-    pub fn style(cx: &mut Cx) -> Self {
-        let rust_editor = Self {
-            code_editor: CodeEditor{
-                folding_depth: 3,
-                ..CodeEditor::style(cx)
-            }
-        };
-        //tab.animator.default = tab.anim_default(cx);
-        rust_editor
+impl Tokenizer for JSTokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        JSTokenizer::next_token(self, state, chunk, token_chunks)
     }
     
-    pub fn handle_js_editor(&mut self, cx: &mut Cx, event: &mut Event,  text_buffer: &mut TextBuffer) -> CodeEditorEvent {
-        let ce = self.code_editor.handle_code_editor(cx, event, text_buffer);
-        match ce {
-            CodeEditorEvent::AutoFormat => {
-                let formatted = JSTokenizer::auto_format(text_buffer).out_lines;
-                self.code_editor.cursors.replace_lines_formatted(formatted, text_buffer);
-                self.code_editor.view.redraw_view_area(cx);
-            },
-            _ => ()
-        }
-        ce
+    fn line_state(&self) -> u64 {
+        (self.comment_depth as u64) << 1 | self.comment_single as u64
     }
     
-    pub fn draw_js_editor(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer) {
-        if text_buffer.needs_token_chunks() && text_buffer.lines.len() >0{
-            let mut state = TokenizerState::new(&text_buffer.lines);
-            let mut tokenizer = JSTokenizer::new();
-            let mut pair_stack = Vec::new();
-            loop {
-                let offset = text_buffer.flat_text.len();
-                let token_type = tokenizer.next_token(&mut state, &mut text_buffer.flat_text, &text_buffer.token_chunks);
-                TokenChunk::push_with_pairing(&mut text_buffer.token_chunks, &mut pair_stack, state.next, offset, text_buffer.flat_text.len(), token_type);
-                if token_type == TokenType::Eof {
-                    break
-                }
-            }
-        }
-        
-        if let Err(_) = self.code_editor.begin_code_editor(cx, text_buffer) {
-            return
-        }
-        
-        for (index, token_chunk) in text_buffer.token_chunks.iter_mut().enumerate(){
-            self.code_editor.draw_chunk(cx, index, &text_buffer.flat_text, token_chunk, &text_buffer.messages.cursors);
-        }
-        
-        self.code_editor.end_code_editor(cx, text_buffer);
+    fn set_line_state(&mut self, line_state: u64) {
+        self.comment_single = line_state & 1 != 0;
+        self.comment_depth = (line_state >> 1) as usize;
     }
 }
 
-pub struct JSTokenizer {
-    pub comment_single: bool,
-    pub comment_depth: usize
-}
-
 impl JSTokenizer {
     pub fn new() -> JSTokenizer {
         JSTokenizer {
@@ -166,7 +131,7 @@ impl JSTokenizer {
                         return TokenType::CommentMultiBegin;
                     }
                     else {
-                        let is_regexp = match TokenChunk::scan_last_token(&token_chunks) {
+                        let is_regexp = match state.scan_last_token(token_chunks) {
                             TokenType::ParenOpen | TokenType::Keyword | TokenType::Operator
                                 | TokenType::Delimiter | TokenType::Colon | TokenType::Looping => true,
                             _ => false
@@ -0,0 +1,221 @@
+use crate::textbuffer::*;
+use crate::language::*;
+
+pub struct JsonLanguage;
+
+impl Language for JsonLanguage {
+    fn name(&self) -> &'static str {"json"}
+    fn extensions(&self) -> &'static [&'static str] {&["json"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(JsonTokenizer::new())}
+    fn auto_format(&self, text_buffer: &mut TextBuffer) -> Option<Vec<Vec<char>>> {
+        JsonTokenizer::auto_format(text_buffer, self.indent().width).map( | out | out.out_lines)
+    }
+}
+
+pub struct JsonTokenizer {
+    // a bit per open container, 1 for an object, on top of a leading 1
+    pub containers: u64
+}
+
+impl Tokenizer for JsonTokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        JsonTokenizer::next_token(self, state, chunk, token_chunks)
+    }
+    
+    fn line_state(&self) -> u64 {
+        self.containers
+    }
+    
+    fn set_line_state(&mut self, line_state: u64) {
+        self.containers = line_state.max(1);
+    }
+}
+
+impl JsonTokenizer {
+    pub fn new() -> JsonTokenizer {
+        JsonTokenizer {
+            containers: 1
+        }
+    }
+    
+    pub fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        state.advance_with_cur();
+        match state.cur {
+            '\0' => { // eof insert a terminating space and end
+                chunk.push(' ');
+                TokenType::Eof
+            },
+            '\n' => {
+                chunk.push('\n');
+                TokenType::Newline
+            },
+            ' ' | '\t' => {
+                chunk.push(state.cur);
+                while state.next == ' ' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Whitespace
+            },
+            '{' | '[' => {
+                chunk.push(state.cur);
+                // deeper than 63 we stop keeping track, it only colors the keys
+                if self.containers >> 63 == 0 {
+                    self.containers = self.containers << 1 | if state.cur == '{' {1} else {0};
+                }
+                TokenType::ParenOpen
+            },
+            '}' | ']' => {
+                chunk.push(state.cur);
+                self.containers = (self.containers >> 1).max(1);
+                TokenType::ParenClose
+            },
+            ':' => {
+                chunk.push(state.cur);
+                TokenType::Colon
+            },
+            ',' => {
+                chunk.push(state.cur);
+                TokenType::Delimiter
+            },
+            '"' => {
+                chunk.push(state.cur);
+                while state.next != '\n' && state.next != '\0' {
+                    let ch = state.next;
+                    chunk.push(ch);
+                    state.advance();
+                    if ch == '\\' && state.next != '\n' && state.next != '\0' {
+                        chunk.push(state.next);
+                        state.advance();
+                    }
+                    else if ch == '"' {
+                        break
+                    }
+                }
+                // a string right after { or , in an object is a key
+                let in_object = self.containers > 1 && self.containers & 1 == 1;
+                match state.scan_last_token(token_chunks) {
+                    TokenType::ParenOpen | TokenType::Delimiter if in_object => TokenType::Identifier,
+                    _ => TokenType::String
+                }
+            },
+            '0'..='9' | '-' => {
+                chunk.push(state.cur);
+                while state.next.is_ascii_digit() || state.next == '.' || state.next == 'e' || state.next == 'E' || state.next == '+' || state.next == '-' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Number
+            },
+            ch if ch.is_alphabetic() => {
+                let start = chunk.len();
+                chunk.push(ch);
+                while state.next.is_alphanumeric() {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                match chunk[start..].iter().collect::<String>().as_str() {
+                    "true" | "false" | "null" => TokenType::Bool,
+                    _ => TokenType::Unexpected
+                }
+            },
+            _ => {
+                chunk.push(state.cur);
+                TokenType::Unexpected
+            }
+        }
+    }
+    
+    // one value per line, empty {} and [] stay together. Leaves broken json alone
+    pub fn auto_format(text_buffer: &mut TextBuffer, indent: usize) -> Option<FormatOutput> {
+        let tokens: Vec<&TokenChunk> = text_buffer.token_chunks.iter().filter( | token | {
+            !token.token_type.should_ignore() && token.token_type != TokenType::Eof
+        }).collect();
+        if tokens.iter().any( | token | token.token_type == TokenType::Unexpected) {
+            return None
+        }
+        let mut out = FormatOutput::new();
+        out.new_line();
+        let mut depth = 0;
+        for (index, token) in tokens.iter().enumerate() {
+            let chunk = &text_buffer.flat_text[token.offset..(token.offset + token.len)];
+            match token.token_type {
+                TokenType::ParenOpen => {
+                    out.extend(chunk);
+                    if tokens.get(index + 1).map_or(false, | next | next.token_type == TokenType::ParenClose) {
+                        continue
+                    }
+                    depth += 1;
+                    out.new_line();
+                    out.indent(depth * indent);
+                },
+                TokenType::ParenClose => {
+                    if index == 0 || tokens[index - 1].token_type != TokenType::ParenOpen {
+                        depth = depth.max(1) - 1;
+                        out.new_line();
+                        out.indent(depth * indent);
+                    }
+                    out.extend(chunk);
+                },
+                TokenType::Delimiter => {
+                    out.extend(chunk);
+                    out.new_line();
+                    out.indent(depth * indent);
+                },
+                TokenType::Colon => {
+                    out.extend(chunk);
+                    out.add_space();
+                },
+                _ => out.extend(chunk)
+            }
+        }
+        // keep the newline at the end of the file
+        let line_count = text_buffer.lines.len();
+        if line_count > 1 && text_buffer.lines.line_len(line_count - 1) == 0 {
+            out.new_line();
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn colors_keys_and_formats() {
+        let mut text_buffer = TextBuffer::from_test_str("{\"a\": [1, \"x\"], \"b\": {}, \"c\": {\"d\": null}}\n");
+        text_buffer.update_token_chunks(&mut JsonTokenizer::new());
+        let strings: Vec<(String, TokenType)> = text_buffer.token_chunks.iter().filter( | token | {
+            token.token_type == TokenType::Identifier || token.token_type == TokenType::String
+        }).map( | token | {
+            (text_buffer.flat_text[token.offset..(token.offset + token.len)].iter().collect(), token.token_type)
+        }).collect();
+        assert_eq!(strings, vec![
+            ("\"a\"".to_string(), TokenType::Identifier),
+            ("\"x\"".to_string(), TokenType::String),
+            ("\"b\"".to_string(), TokenType::Identifier),
+            ("\"c\"".to_string(), TokenType::Identifier),
+            ("\"d\"".to_string(), TokenType::Identifier)
+        ]);
+        
+        let formatted: Vec<String> = JsonLanguage.auto_format(&mut text_buffer).unwrap().iter().map( | line | line.iter().collect()).collect();
+        assert_eq!(formatted, vec![
+            "{",
+            "    \"a\": [",
+            "        1,",
+            "        \"x\"",
+            "    ],",
+            "    \"b\": {},",
+            "    \"c\": {",
+            "        \"d\": null",
+            "    }",
+            "}",
+            ""
+        ]);
+        
+        text_buffer.load_bytes("{\"a\": nope}".as_bytes());
+        text_buffer.update_token_chunks(&mut JsonTokenizer::new());
+        assert!(JsonLanguage.auto_format(&mut text_buffer).is_none());
+    }
+}
@@ -0,0 +1,713 @@
+use render::*;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::language::*;
+use serde::*;
+use std::collections::HashMap;
+
+// Every key the CodeEditor and the app react to is a Command, the Keymap says which keys
+// (or sequences of keys like ctrl+k ctrl+c) give which command. The defaults are what the
+// editor always did, a makepad_keys.json in the project root can change or remove them.
+// The find bar commands only count while the bar is open, their keys can be bound to an
+// editor command as well.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    CursorWordLeft,
+    CursorWordRight,
+    CursorPageUp,
+    CursorPageDown,
+    CursorHome,
+    CursorEnd,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    SelectWordLeft,
+    SelectWordRight,
+    SelectPageUp,
+    SelectPageDown,
+    SelectHome,
+    SelectEnd,
+    SelectAll,
+    Backspace,
+    Delete,
+    Cut,
+    Undo,
+    Redo,
+    RedoNextBranch,
+    Indent,
+    Outdent,
+    Newline,
+    ToggleComment,
+    AddNextOccurrence,
+    AddPrevOccurrence,
+    SkipOccurrence,
+    SelectAllOccurrences,
+    SplitSelectionIntoLines,
+    AddCursorAbove,
+    AddCursorBelow,
+    UndoCursorAddition,
+    // the editor ones, they need more than the text
+    Find,
+    Replace,
+    ProjectSearch,
+    FindNext,
+    FindPrev,
+    FindSelectAll,
+    FindToggleCase,
+    FindToggleWholeWord,
+    FindToggleRegex,
+    FindSwitchField,
+    ReplaceNext,
+    ReplaceAll,
+    AutoFormat,
+    Save,
+    Complete,
+    FoldAtCursor,
+    UnfoldAtCursor,
+    FoldAll,
+    UnfoldAll,
+    ZoomFold,
+    ZoomFoldHalfway,
+    GotoDefinition,
+    Rename,
+    QuickFix,
+    ApplyAllFixes,
+    NextMessage,
+    PrevMessage,
+    ToggleMessageDetail,
+    ToggleVimMode,
+    ToggleSoftWrap,
+    ToggleMinimap,
+    ToggleFormatOnSave,
+    GitStageHunk,
+    GitRevertHunk,
+    GitDiffHead,
+    GitDiffIndex,
+    ConvertToUtf8,
+    ConvertToUtf8Bom,
+    ConvertToUtf16le,
+    ConvertToUtf16be,
+    ConvertToLatin1,
+    ConvertToHex,
+    UseLfLineEndings,
+    UseCrlfLineEndings,
+    // and the app ones
+    RunProgram,
+    OpenFolder,
+    RecentProjects,
+    NextSearchHit,
+    PrevSearchHit,
+    QuickOpen,
+    CommandPalette,
+    GotoLine,
+    GotoSymbol,
+    ShowOutline,
+    RedoBranches
+}
+
+// for the command palette, in the order of the enum
+pub const ALL_COMMANDS: &[Command] = &[
+    Command::CursorUp, Command::CursorDown, Command::CursorLeft, Command::CursorRight,
+    Command::CursorWordLeft, Command::CursorWordRight, Command::CursorPageUp, Command::CursorPageDown,
+    Command::CursorHome, Command::CursorEnd,
+    Command::SelectUp, Command::SelectDown, Command::SelectLeft, Command::SelectRight,
+    Command::SelectWordLeft, Command::SelectWordRight, Command::SelectPageUp, Command::SelectPageDown,
+    Command::SelectHome, Command::SelectEnd, Command::SelectAll,
+    Command::Backspace, Command::Delete, Command::Cut, Command::Undo, Command::Redo, Command::RedoNextBranch,
+    Command::Indent, Command::Outdent, Command::Newline, Command::ToggleComment,
+    Command::AddNextOccurrence, Command::AddPrevOccurrence, Command::SkipOccurrence, Command::SelectAllOccurrences,
+    Command::SplitSelectionIntoLines, Command::AddCursorAbove, Command::AddCursorBelow, Command::UndoCursorAddition,
+    Command::Find, Command::Replace, Command::ProjectSearch,
+    Command::FindNext, Command::FindPrev, Command::FindSelectAll, Command::FindToggleCase, Command::FindToggleWholeWord,
+    Command::FindToggleRegex, Command::FindSwitchField, Command::ReplaceNext, Command::ReplaceAll,
+    Command::AutoFormat, Command::Save, Command::Complete,
+    Command::FoldAtCursor, Command::UnfoldAtCursor, Command::FoldAll, Command::UnfoldAll,
+    Command::ZoomFold, Command::ZoomFoldHalfway,
+    Command::GotoDefinition, Command::Rename, Command::QuickFix, Command::ApplyAllFixes,
+    Command::NextMessage, Command::PrevMessage, Command::ToggleMessageDetail,
+    Command::ToggleVimMode, Command::ToggleSoftWrap, Command::ToggleMinimap, Command::ToggleFormatOnSave,
+    Command::GitStageHunk, Command::GitRevertHunk, Command::GitDiffHead, Command::GitDiffIndex,
+    Command::ConvertToUtf8, Command::ConvertToUtf8Bom, Command::ConvertToUtf16le, Command::ConvertToUtf16be,
+    Command::ConvertToLatin1, Command::ConvertToHex, Command::UseLfLineEndings, Command::UseCrlfLineEndings,
+    Command::RunProgram, Command::OpenFolder, Command::RecentProjects, Command::NextSearchHit, Command::PrevSearchHit,
+    Command::QuickOpen, Command::CommandPalette, Command::GotoLine, Command::GotoSymbol, Command::ShowOutline,
+    Command::RedoBranches
+];
+
+// mod is control or logo, whichever the platform uses. The modifier keys themselves can be
+// bound too, pressing alt then doesnt also need the alt modifier
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyChord {
+    pub key_code: KeyCode,
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+    pub logo: bool,
+    pub primary: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeymapEvent {
+    None,
+    // the start of a sequence, wait for the next key
+    Pending,
+    Command(Command)
+}
+
+#[derive(Clone, Default)]
+pub struct Keymap {
+    pub bindings: Vec<(Vec<KeyChord>, Command)>
+}
+
+const DEFAULT_BINDINGS: &[(&str, Command)] = &[
+    ("up", Command::CursorUp),
+    ("down", Command::CursorDown),
+    ("left", Command::CursorLeft),
+    ("right", Command::CursorRight),
+    ("mod+left", Command::CursorWordLeft),
+    ("mod+right", Command::CursorWordRight),
+    ("pageup", Command::CursorPageUp),
+    ("pagedown", Command::CursorPageDown),
+    ("home", Command::CursorHome),
+    ("end", Command::CursorEnd),
+    ("shift+up", Command::SelectUp),
+    ("shift+down", Command::SelectDown),
+    ("shift+left", Command::SelectLeft),
+    ("shift+right", Command::SelectRight),
+    ("mod+shift+left", Command::SelectWordLeft),
+    ("mod+shift+right", Command::SelectWordRight),
+    ("shift+pageup", Command::SelectPageUp),
+    ("shift+pagedown", Command::SelectPageDown),
+    ("shift+home", Command::SelectHome),
+    ("shift+end", Command::SelectEnd),
+    ("mod+a", Command::SelectAll),
+    ("backspace", Command::Backspace),
+    ("shift+backspace", Command::Backspace),
+    ("delete", Command::Delete),
+    ("mod+x", Command::Cut),
+    ("mod+z", Command::Undo),
+    ("mod+shift+z", Command::Redo),
+    ("mod+alt+z", Command::RedoNextBranch),
+    ("tab", Command::Indent),
+    ("shift+tab", Command::Outdent),
+    ("return", Command::Newline),
+    ("shift+return", Command::Newline),
+    ("mod+/", Command::ToggleComment),
+    ("mod+d", Command::AddNextOccurrence),
+    ("mod+shift+d", Command::AddPrevOccurrence),
+    ("mod+k mod+d", Command::SkipOccurrence),
+    ("mod+shift+l", Command::SelectAllOccurrences),
+    ("mod+shift+i", Command::SplitSelectionIntoLines),
+    ("mod+alt+up", Command::AddCursorAbove),
+    ("mod+alt+down", Command::AddCursorBelow),
+    ("mod+u", Command::UndoCursorAddition),
+    ("mod+f", Command::Find),
+    ("mod+h", Command::Replace),
+    ("mod+shift+f", Command::ProjectSearch),
+    ("return", Command::FindNext),
+    ("shift+return", Command::FindPrev),
+    ("alt+return", Command::FindSelectAll),
+    ("alt+c", Command::FindToggleCase),
+    ("alt+w", Command::FindToggleWholeWord),
+    ("alt+r", Command::FindToggleRegex),
+    ("tab", Command::FindSwitchField),
+    ("mod+shift+1", Command::ReplaceNext),
+    ("mod+alt+return", Command::ReplaceAll),
+    ("mod+return", Command::AutoFormat),
+    ("mod+shift+return", Command::AutoFormat),
+    ("mod+s", Command::Save),
+    ("mod+space", Command::Complete),
+    ("mod+shift+[", Command::FoldAtCursor),
+    ("mod+shift+]", Command::UnfoldAtCursor),
+    ("mod+shift+-", Command::FoldAll),
+    ("mod+shift+=", Command::UnfoldAll),
+    ("escape", Command::ZoomFold),
+    ("shift+escape", Command::ZoomFoldHalfway),
+    ("alt", Command::ZoomFold),
+    ("shift+alt", Command::ZoomFoldHalfway),
+    ("f12", Command::GotoDefinition),
+    ("f2", Command::Rename),
+    ("mod+.", Command::QuickFix),
+    ("mod+shift+.", Command::ApplyAllFixes),
+    ("f8", Command::NextMessage),
+    ("shift+f8", Command::PrevMessage),
+    ("mod+f8", Command::ToggleMessageDetail),
+    ("mod+alt+v", Command::ToggleVimMode),
+    ("mod+alt+w", Command::ToggleSoftWrap),
+    ("mod+alt+m", Command::ToggleMinimap),
+    ("mod+alt+f", Command::ToggleFormatOnSave),
+    ("mod+alt+s", Command::GitStageHunk),
+    ("mod+alt+r", Command::GitRevertHunk),
+    ("mod+alt+g", Command::GitDiffHead),
+    ("mod+alt+shift+g", Command::GitDiffIndex),
+    ("f9", Command::RunProgram),
+    ("mod+o", Command::OpenFolder),
+    ("mod+alt+o", Command::RecentProjects),
+    ("f4", Command::NextSearchHit),
+    ("shift+f4", Command::PrevSearchHit),
+    ("mod+p", Command::QuickOpen),
+    ("mod+shift+p", Command::CommandPalette),
+    ("mod+g", Command::GotoLine),
+    ("mod+shift+o", Command::GotoSymbol),
+    ("mod+alt+l", Command::ShowOutline),
+    ("mod+alt+shift+z", Command::RedoBranches)
+];
+
+// the first name of a key is how it gets shown, the ones after it are what else parses
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("a", KeyCode::KeyA), ("b", KeyCode::KeyB), ("c", KeyCode::KeyC), ("d", KeyCode::KeyD),
+    ("e", KeyCode::KeyE), ("f", KeyCode::KeyF), ("g", KeyCode::KeyG), ("h", KeyCode::KeyH),
+    ("i", KeyCode::KeyI), ("j", KeyCode::KeyJ), ("k", KeyCode::KeyK), ("l", KeyCode::KeyL),
+    ("m", KeyCode::KeyM), ("n", KeyCode::KeyN), ("o", KeyCode::KeyO), ("p", KeyCode::KeyP),
+    ("q", KeyCode::KeyQ), ("r", KeyCode::KeyR), ("s", KeyCode::KeyS), ("t", KeyCode::KeyT),
+    ("u", KeyCode::KeyU), ("v", KeyCode::KeyV), ("w", KeyCode::KeyW), ("x", KeyCode::KeyX),
+    ("y", KeyCode::KeyY), ("z", KeyCode::KeyZ),
+    ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2), ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4), ("5", KeyCode::Key5), ("6", KeyCode::Key6), ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8), ("9", KeyCode::Key9),
+    ("f1", KeyCode::F1), ("f2", KeyCode::F2), ("f3", KeyCode::F3), ("f4", KeyCode::F4),
+    ("f5", KeyCode::F5), ("f6", KeyCode::F6), ("f7", KeyCode::F7), ("f8", KeyCode::F8),
+    ("f9", KeyCode::F9), ("f10", KeyCode::F10), ("f11", KeyCode::F11), ("f12", KeyCode::F12),
+    ("`", KeyCode::Backtick), ("-", KeyCode::Minus), ("=", KeyCode::Equals),
+    ("[", KeyCode::LBracket), ("]", KeyCode::RBracket), (";", KeyCode::Semicolon),
+    ("'", KeyCode::Quote), ("\\", KeyCode::Backslash), (",", KeyCode::Comma),
+    (".", KeyCode::Period), ("/", KeyCode::Slash),
+    ("up", KeyCode::ArrowUp), ("down", KeyCode::ArrowDown),
+    ("left", KeyCode::ArrowLeft), ("right", KeyCode::ArrowRight),
+    ("pageup", KeyCode::PageUp), ("pagedown", KeyCode::PageDown),
+    ("home", KeyCode::Home), ("end", KeyCode::End),
+    ("insert", KeyCode::Insert), ("delete", KeyCode::Delete),
+    ("backspace", KeyCode::Backspace), ("tab", KeyCode::Tab),
+    ("return", KeyCode::Return), ("enter", KeyCode::Return),
+    ("escape", KeyCode::Escape), ("esc", KeyCode::Escape),
+    ("space", KeyCode::Space),
+    ("alt", KeyCode::Alt), ("shift", KeyCode::Shift),
+    ("ctrl", KeyCode::Control), ("control", KeyCode::Control),
+    ("logo", KeyCode::Logo), ("cmd", KeyCode::Logo)
+];
+
+fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    KEY_NAMES.iter().find( | (key_name, _) | *key_name == name).map( | (_, key_code) | key_code.clone())
+}
+
+fn key_code_name(key_code: &KeyCode) -> Option<&'static str> {
+    KEY_NAMES.iter().find( | (_, named) | named == key_code).map( | (name, _) | *name)
+}
+
+impl KeyChord {
+    // like ctrl+shift+z, the last part is the key
+    pub fn parse(chord: &str) -> Result<KeyChord, String> {
+        let parts: Vec<&str> = chord.split('+').collect();
+        let (mods, key) = (&parts[0..parts.len() - 1], parts[parts.len() - 1]);
+        let key_code = match key_code_from_name(&key.to_lowercase()) {
+            Some(key_code) => key_code,
+            None => return Err(format!("Unknown key {} in {}", key, chord))
+        };
+        let mut key_chord = KeyChord {key_code: key_code, shift: false, control: false, alt: false, logo: false, primary: false};
+        for modifier in mods {
+            match modifier.to_lowercase().as_ref() {
+                "shift" => key_chord.shift = true,
+                "ctrl" | "control" => key_chord.control = true,
+                "alt" | "option" => key_chord.alt = true,
+                "logo" | "cmd" | "super" => key_chord.logo = true,
+                "mod" => key_chord.primary = true,
+                _ => return Err(format!("Unknown modifier {} in {}", modifier, chord))
+            }
+        }
+        Ok(key_chord)
+    }
+    
+    // what parse takes, mod+shift+z and the like
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        for (is_set, modifier) in &[(self.primary, "mod+"), (self.control, "ctrl+"), (self.alt, "alt+"), (self.shift, "shift+"), (self.logo, "logo+")] {
+            if *is_set {
+                name.push_str(modifier);
+            }
+        }
+        name.push_str(key_code_name(&self.key_code).unwrap_or("?"));
+        name
+    }
+    
+    pub fn matches(&self, ke: &KeyEvent) -> bool {
+        if self.key_code != ke.key_code {
+            return false
+        }
+        let m = &ke.modifiers;
+        let shift = self.key_code == KeyCode::Shift || self.shift == m.shift;
+        let alt = self.key_code == KeyCode::Alt || self.alt == m.alt;
+        let control_logo = if self.key_code == KeyCode::Control || self.key_code == KeyCode::Logo {
+            true
+        }
+        else if self.primary {
+            m.control || m.logo
+        }
+        else {
+            self.control == m.control && self.logo == m.logo
+        };
+        shift && alt && control_logo
+    }
+}
+
+fn parse_sequence(keys: &str) -> Result<Vec<KeyChord>, String> {
+    let sequence = keys.split_whitespace().map( | chord | KeyChord::parse(chord)).collect::<Result<Vec<KeyChord>, String>>() ?;
+    if sequence.len() == 0 {
+        return Err("Empty key binding".to_string())
+    }
+    Ok(sequence)
+}
+
+fn is_modifier_key(key_code: &KeyCode) -> bool {
+    match key_code {
+        KeyCode::Shift | KeyCode::Control | KeyCode::Alt | KeyCode::Logo => true,
+        _ => false
+    }
+}
+
+impl Keymap {
+    // the last binding wins, so the defaults go in back to front for the first of them to count
+    pub fn default_keymap() -> Keymap {
+        let mut keymap = Keymap::default();
+        for (keys, command) in DEFAULT_BINDINGS.iter().rev() {
+            keymap.bind(parse_sequence(keys).unwrap(), *command);
+        }
+        keymap
+    }
+    
+    // the defaults with the file on top, a key set to null takes the default away
+    pub fn from_json(json: &str) -> Result<Keymap, String> {
+        let user: HashMap<String, Option<Command>> = serde_json::from_str(json).map_err( | err | err.to_string()) ?;
+        let mut keymap = Keymap::default_keymap();
+        for (keys, command) in user {
+            let sequence = parse_sequence(&keys) ?;
+            match command {
+                Some(command) => keymap.bind(sequence, command),
+                None => keymap.bindings.retain( | (bound, _) | *bound != sequence)
+            }
+        }
+        Ok(keymap)
+    }
+    
+    // a find bar command only takes the keys from another find bar command
+    pub fn bind(&mut self, sequence: Vec<KeyChord>, command: Command) {
+        self.bindings.retain( | (bound, bound_command) | *bound != sequence || bound_command.is_find_bar() != command.is_find_bar());
+        self.bindings.push((sequence, command));
+    }
+    
+    // pending holds the keys of a sequence typed so far, it belongs to whoever has the focus
+    pub fn handle_key(&self, pending: &mut Vec<KeyEvent>, ke: &KeyEvent) -> KeymapEvent {
+        self.handle_key_for(pending, ke, false)
+    }
+    
+    // the open find bar gets to see the keys first, with its own pending keys
+    pub fn handle_find_bar_key(&self, pending: &mut Vec<KeyEvent>, ke: &KeyEvent) -> KeymapEvent {
+        self.handle_key_for(pending, ke, true)
+    }
+    
+    fn handle_key_for(&self, pending: &mut Vec<KeyEvent>, ke: &KeyEvent, find_bar: bool) -> KeymapEvent {
+        pending.push(ke.clone());
+        match self.lookup(pending, find_bar) {
+            KeymapEvent::None => {
+                if pending.len() == 1 {
+                    pending.truncate(0);
+                    return KeymapEvent::None
+                }
+                // pressing ctrl on the way to ctrl+k ctrl+c doesnt break the sequence
+                if is_modifier_key(&ke.key_code) {
+                    pending.pop();
+                    return KeymapEvent::Pending
+                }
+                // a broken sequence, the key can still start something
+                pending.truncate(0);
+                self.handle_key_for(pending, ke, find_bar)
+            },
+            KeymapEvent::Pending => KeymapEvent::Pending,
+            KeymapEvent::Command(command) => {
+                pending.truncate(0);
+                KeymapEvent::Command(command)
+            }
+        }
+    }
+    
+    fn lookup(&self, keys: &[KeyEvent], find_bar: bool) -> KeymapEvent {
+        let mut is_prefix = false;
+        // the user's bindings come after the defaults, whichever way they spell the keys
+        for (sequence, command) in self.bindings.iter().rev() {
+            if command.is_find_bar() != find_bar || sequence.len() < keys.len() || !keys.iter().zip(sequence.iter()).all( | (ke, chord) | chord.matches(ke)) {
+                continue
+            }
+            if sequence.len() == keys.len() {
+                return KeymapEvent::Command(*command)
+            }
+            is_prefix = true;
+        }
+        if is_prefix {KeymapEvent::Pending} else {KeymapEvent::None}
+    }
+    
+    // the keys that win for command, like the keys file writes them
+    pub fn keys_for(&self, command: Command) -> Option<String> {
+        let (sequence, _) = self.bindings.iter().rev().find( | (_, bound) | *bound == command) ?;
+        Some(sequence.iter().map( | chord | chord.name()).collect::<Vec<String>>().join(" "))
+    }
+    
+    // for the panels that only bind single keys
+    pub fn single_command(&self, ke: &KeyEvent) -> Option<Command> {
+        match self.lookup(&[ke.clone()], false) {
+            KeymapEvent::Command(command) => Some(command),
+            _ => None
+        }
+    }
+}
+
+impl Command {
+    // ToggleVimMode is Toggle Vim Mode
+    pub fn label(&self) -> String {
+        let mut label = String::new();
+        for c in format!("{:?}", self).chars() {
+            if c.is_uppercase() && label.len() > 0 {
+                label.push(' ');
+            }
+            label.push(c);
+        }
+        label
+    }
+    
+    pub fn is_find_bar(&self) -> bool {
+        match self {
+            Command::FindNext | Command::FindPrev | Command::FindSelectAll | Command::FindToggleCase | Command::FindToggleWholeWord |
+            Command::FindToggleRegex | Command::FindSwitchField | Command::ReplaceNext | Command::ReplaceAll => true,
+            _ => false
+        }
+    }
+    
+    // runs the commands that only need the cursors and the text. None if the editor has to do it,
+    // otherwise whether the cursors moved enough to scroll them into view
+    pub fn apply_to_text(self, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, indent: &LanguageIndent, line_comment: Option<&str>, page_lines: usize) -> Option<bool> {
+        match self {
+            Command::CursorUp | Command::SelectUp => cursors.move_up(1, self == Command::SelectUp, text_buffer),
+            Command::CursorDown | Command::SelectDown => cursors.move_down(1, self == Command::SelectDown, text_buffer),
+            Command::CursorLeft | Command::SelectLeft => cursors.move_left(1, self == Command::SelectLeft, text_buffer),
+            Command::CursorRight | Command::SelectRight => cursors.move_right(1, self == Command::SelectRight, text_buffer),
+            Command::CursorWordLeft | Command::SelectWordLeft => cursors.move_left_nearest_token(self == Command::SelectWordLeft, text_buffer),
+            Command::CursorWordRight | Command::SelectWordRight => cursors.move_right_nearest_token(self == Command::SelectWordRight, text_buffer),
+            Command::CursorPageUp | Command::SelectPageUp => cursors.move_up(page_lines, self == Command::SelectPageUp, text_buffer),
+            Command::CursorPageDown | Command::SelectPageDown => cursors.move_down(page_lines, self == Command::SelectPageDown, text_buffer),
+            Command::CursorHome | Command::SelectHome => cursors.move_home(self == Command::SelectHome, text_buffer),
+            Command::CursorEnd | Command::SelectEnd => cursors.move_end(self == Command::SelectEnd, text_buffer),
+            Command::SelectAll => {
+                cursors.select_all(text_buffer);
+                // don't scroll!
+                return Some(false)
+            },
+            Command::Backspace => cursors.backspace(text_buffer),
+            Command::Delete => cursors.delete(text_buffer),
+            // the actual copy comes from the TextCopy event from the platform layer
+            Command::Cut => cursors.replace_text("", text_buffer),
+            Command::Undo => text_buffer.undo(true, cursors),
+            Command::Redo => text_buffer.redo(true, cursors),
+            // redo into the edits made after the last undo before this one, or the next branch after those
+            Command::RedoNextBranch => if text_buffer.undo_tree.next_redo_branch() {
+                text_buffer.redo(true, cursors)
+            }
+            else {
+                return Some(false)
+            },
+            Command::Indent => cursors.insert_tab(text_buffer, &" ".repeat(indent.width)),
+            Command::Outdent => cursors.remove_tab(text_buffer, indent.width),
+            Command::Newline => cursors.insert_newline_with_indent(indent.width, indent.brackets, text_buffer),
+            Command::ToggleComment => match line_comment {
+                Some(comment) => cursors.toggle_line_comment(comment, text_buffer),
+                None => return Some(false)
+            },
+            Command::AddNextOccurrence | Command::AddPrevOccurrence => if !cursors.add_next_occurrence(self == Command::AddNextOccurrence, text_buffer) {
+                return Some(false)
+            },
+            Command::SkipOccurrence => if !cursors.skip_occurrence(text_buffer) {
+                return Some(false)
+            },
+            Command::SelectAllOccurrences => if !cursors.select_all_occurrences(text_buffer) {
+                return Some(false)
+            },
+            Command::SplitSelectionIntoLines => if !cursors.split_into_lines(text_buffer) {
+                return Some(false)
+            },
+            Command::AddCursorAbove | Command::AddCursorBelow => if !cursors.add_cursor_vertical(self == Command::AddCursorAbove, text_buffer) {
+                return Some(false)
+            },
+            Command::UndoCursorAddition => if !cursors.undo_cursor_addition(text_buffer) {
+                return Some(false)
+            },
+            _ => return None
+        }
+        Some(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn key(chord: &str) -> KeyEvent {
+        let chord = KeyChord::parse(chord).unwrap();
+        KeyEvent {
+            key_code: chord.key_code,
+            is_repeat: false,
+            modifiers: KeyModifiers {shift: chord.shift, control: chord.control || chord.primary, alt: chord.alt, logo: chord.logo},
+            time: 0.
+        }
+    }
+    
+    fn run_keys(keymap: &Keymap, keys: &[&str], cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) {
+        let mut pending = Vec::new();
+        for chord in keys {
+            if let KeymapEvent::Command(command) = keymap.handle_key(&mut pending, &key(chord)) {
+                command.apply_to_text(cursors, text_buffer, &LanguageIndent::default(), Some("//"), 10);
+            }
+        }
+    }
+    
+    #[test]
+    fn default_map_matches_either_primary_modifier() {
+        let keymap = Keymap::default_keymap();
+        assert_eq!(keymap.single_command(&key("ctrl+z")), Some(Command::Undo));
+        assert_eq!(keymap.single_command(&key("logo+z")), Some(Command::Undo));
+        assert_eq!(keymap.single_command(&key("ctrl+shift+z")), Some(Command::Redo));
+        assert_eq!(keymap.single_command(&key("alt+z")), None);
+        assert_eq!(keymap.single_command(&key("mod+shift+[")), Some(Command::FoldAtCursor));
+        // alt down comes with the alt modifier set
+        assert_eq!(keymap.single_command(&key("alt+alt")), Some(Command::ZoomFold));
+        assert!(KeyChord::parse("ctrl+nope").is_err());
+        assert!(KeyChord::parse("hyper+a").is_err());
+    }
+    
+    #[test]
+    fn commands_show_their_keys() {
+        let keymap = Keymap::from_json(r#"{"ctrl+k ctrl+c": "ToggleComment", "mod+/": null, "mod+shift+return": null}"#).unwrap();
+        assert_eq!(keymap.keys_for(Command::ToggleComment), Some("ctrl+k ctrl+c".to_string()));
+        assert_eq!(keymap.keys_for(Command::AutoFormat), Some("mod+return".to_string()));
+        assert_eq!(keymap.keys_for(Command::Backspace), Some("backspace".to_string()));
+        assert_eq!(keymap.keys_for(Command::ZoomFoldHalfway), Some("shift+escape".to_string()));
+        assert_eq!(Command::ToggleVimMode.label(), "Toggle Vim Mode");
+        assert!(ALL_COMMANDS.iter().all( | command | keymap.keys_for(*command).map_or(true, | keys | Keymap::from_json(&format!("{{\"{}\": \"Undo\"}}", keys)).is_ok())));
+    }
+    
+    // no _ arm, so a new command doesnt build until it is in here, which knows which one is last
+    fn is_last_command(command: Command) -> bool {
+        match command {
+            Command::RedoBranches => true,
+            Command::CursorUp | Command::CursorDown | Command::CursorLeft | Command::CursorRight |
+            Command::CursorWordLeft | Command::CursorWordRight | Command::CursorPageUp | Command::CursorPageDown |
+            Command::CursorHome | Command::CursorEnd |
+            Command::SelectUp | Command::SelectDown | Command::SelectLeft | Command::SelectRight |
+            Command::SelectWordLeft | Command::SelectWordRight | Command::SelectPageUp | Command::SelectPageDown |
+            Command::SelectHome | Command::SelectEnd | Command::SelectAll |
+            Command::Backspace | Command::Delete | Command::Cut | Command::Undo | Command::Redo | Command::RedoNextBranch |
+            Command::Indent | Command::Outdent | Command::Newline | Command::ToggleComment |
+            Command::AddNextOccurrence | Command::AddPrevOccurrence | Command::SkipOccurrence | Command::SelectAllOccurrences |
+            Command::SplitSelectionIntoLines | Command::AddCursorAbove | Command::AddCursorBelow | Command::UndoCursorAddition |
+            Command::Find | Command::Replace | Command::ProjectSearch |
+            Command::FindNext | Command::FindPrev | Command::FindSelectAll | Command::FindToggleCase | Command::FindToggleWholeWord |
+            Command::FindToggleRegex | Command::FindSwitchField | Command::ReplaceNext | Command::ReplaceAll |
+            Command::AutoFormat | Command::Save | Command::Complete |
+            Command::FoldAtCursor | Command::UnfoldAtCursor | Command::FoldAll | Command::UnfoldAll |
+            Command::ZoomFold | Command::ZoomFoldHalfway |
+            Command::GotoDefinition | Command::Rename | Command::QuickFix | Command::ApplyAllFixes |
+            Command::NextMessage | Command::PrevMessage | Command::ToggleMessageDetail |
+            Command::ToggleVimMode | Command::ToggleSoftWrap | Command::ToggleMinimap | Command::ToggleFormatOnSave |
+            Command::GitStageHunk | Command::GitRevertHunk | Command::GitDiffHead | Command::GitDiffIndex |
+            Command::ConvertToUtf8 | Command::ConvertToUtf8Bom | Command::ConvertToUtf16le | Command::ConvertToUtf16be |
+            Command::ConvertToLatin1 | Command::ConvertToHex | Command::UseLfLineEndings | Command::UseCrlfLineEndings |
+            Command::RunProgram | Command::OpenFolder | Command::RecentProjects | Command::NextSearchHit | Command::PrevSearchHit |
+            Command::QuickOpen | Command::CommandPalette | Command::GotoLine | Command::GotoSymbol | Command::ShowOutline => false
+        }
+    }
+    
+    #[test]
+    fn all_commands_has_the_whole_enum() {
+        // each one in its place means none missing or twice up to the last
+        for (index, command) in ALL_COMMANDS.iter().enumerate() {
+            assert_eq!(*command as usize, index, "{:?} is out of place", command);
+        }
+        assert!(is_last_command(*ALL_COMMANDS.last().unwrap()));
+        assert!(DEFAULT_BINDINGS.iter().all( | (_, command) | ALL_COMMANDS.contains(command)));
+    }
+    
+    #[test]
+    fn sequences_and_user_overrides() {
+        let keymap = Keymap::from_json(r#"{"ctrl+k ctrl+c": "ToggleComment", "mod+/": null, "ctrl+k ctrl+u": "Undo"}"#).unwrap();
+        let mut pending = Vec::new();
+        assert_eq!(keymap.handle_key(&mut pending, &key("ctrl+k")), KeymapEvent::Pending);
+        // holding ctrl again on the way doesnt break it
+        assert_eq!(keymap.handle_key(&mut pending, &key("ctrl+control")), KeymapEvent::Pending);
+        assert_eq!(keymap.handle_key(&mut pending, &key("ctrl+c")), KeymapEvent::Command(Command::ToggleComment));
+        assert_eq!(pending.len(), 0);
+        // a broken sequence lets the key through on its own
+        assert_eq!(keymap.handle_key(&mut pending, &key("ctrl+k")), KeymapEvent::Pending);
+        assert_eq!(keymap.handle_key(&mut pending, &key("ctrl+z")), KeymapEvent::Command(Command::Undo));
+        assert_eq!(keymap.single_command(&key("ctrl+/")), None);
+        assert_eq!(keymap.single_command(&key("ctrl+a")), Some(Command::SelectAll));
+        assert!(Keymap::from_json(r#"{"ctrl+k": "NoSuchCommand"}"#).is_err());
+        assert!(Keymap::from_json(r#"{"ctrl+wat": "Undo"}"#).is_err());
+    }
+    
+    #[test]
+    fn find_bar_keys_dont_take_the_editor_ones() {
+        let keymap = Keymap::default_keymap();
+        let mut pending = Vec::new();
+        assert_eq!(keymap.handle_key(&mut pending, &key("return")), KeymapEvent::Command(Command::Newline));
+        assert_eq!(keymap.handle_find_bar_key(&mut pending, &key("return")), KeymapEvent::Command(Command::FindNext));
+        assert_eq!(keymap.handle_find_bar_key(&mut pending, &key("alt+c")), KeymapEvent::Command(Command::FindToggleCase));
+        assert_eq!(keymap.handle_find_bar_key(&mut pending, &key("ctrl+z")), KeymapEvent::None);
+        assert_eq!(keymap.single_command(&key("alt+c")), None);
+        // rebinding one leaves the other, null takes both
+        let keymap = Keymap::from_json(r#"{"return": "FindSelectAll", "tab": null}"#).unwrap();
+        assert_eq!(keymap.handle_key(&mut pending, &key("return")), KeymapEvent::Command(Command::Newline));
+        assert_eq!(keymap.handle_find_bar_key(&mut pending, &key("return")), KeymapEvent::Command(Command::FindSelectAll));
+        assert_eq!(keymap.handle_key(&mut pending, &key("tab")), KeymapEvent::None);
+        assert_eq!(keymap.handle_find_bar_key(&mut pending, &key("tab")), KeymapEvent::None);
+        assert_eq!(keymap.keys_for(Command::FindNext), None);
+    }
+    
+    #[test]
+    fn user_chords_win_over_defaults_spelled_differently() {
+        // mod+z is bound by default, ctrl+z is the same key on linux
+        let keymap = Keymap::from_json(r#"{"ctrl+z": "Redo", "ctrl+shift+k": "Undo"}"#).unwrap();
+        assert_eq!(keymap.single_command(&key("ctrl+z")), Some(Command::Redo));
+        assert_eq!(keymap.keys_for(Command::Redo), Some("ctrl+z".to_string()));
+        assert_eq!(keymap.keys_for(Command::Undo), Some("ctrl+shift+k".to_string()));
+        // the defaults keep the first of their keys
+        assert_eq!(Keymap::default_keymap().keys_for(Command::Undo), Some("mod+z".to_string()));
+        assert_eq!(Keymap::default_keymap().single_command(&key("ctrl+z")), Some(Command::Undo));
+    }
+    
+    #[test]
+    fn commands_edit_without_a_window() {
+        let keymap = Keymap::from_json(r#"{"ctrl+k ctrl+c": "ToggleComment"}"#).unwrap();
+        let mut text_buffer = TextBuffer::from_test_str("fn a() {}\nfn b() {}\n");
+        let mut cursors = TextCursorSet::new();
+        run_keys(&keymap, &["ctrl+k", "ctrl+c"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "// fn a() {}\nfn b() {}\n");
+        run_keys(&keymap, &["down", "home", "shift+end", "ctrl+x"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "// fn a() {}\n\n");
+        run_keys(&keymap, &["tab"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "// fn a() {}\n    \n");
+        run_keys(&keymap, &["ctrl+z", "ctrl+z"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "// fn a() {}\nfn b() {}\n");
+        run_keys(&keymap, &["ctrl+shift+z"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "// fn a() {}\n\n");
+        // an edit after undo starts a branch, the old one is still a redo away
+        run_keys(&keymap, &["ctrl+z", "ctrl+a", "ctrl+x"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "");
+        run_keys(&keymap, &["ctrl+z"], &mut cursors, &mut text_buffer);
+        run_keys(&keymap, &["ctrl+alt+z"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "// fn a() {}\n\n");
+        run_keys(&keymap, &["ctrl+z", "ctrl+alt+z"], &mut cursors, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "");
+    }
+}
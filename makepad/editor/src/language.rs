@@ -0,0 +1,320 @@
+use std::rc::Rc;
+use crate::textbuffer::*;
+use crate::textformat::*;
+use crate::rusteditor::*;
+use crate::jseditor::*;
+use crate::tomltokenizer::*;
+use crate::jsontokenizer::*;
+use crate::markdowntokenizer::*;
+
+// Everything the editor needs to know about a kind of file. The LanguageRegistry picks one
+// for a file from a modeline (vim: ft=toml or -*- mode: toml -*-), a #! line or the file name,
+// in that order, and falls back on plain text.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LanguageIndent {
+    pub width: usize,
+    // indent after an open bracket and split bracket pairs on return
+    pub brackets: bool
+}
+
+impl Default for LanguageIndent {
+    fn default() -> Self {
+        LanguageIndent {width: 4, brackets: true}
+    }
+}
+
+pub trait Language {
+    // what modelines call it, lowercase
+    fn name(&self) -> &'static str;
+    // other names modelines might use
+    fn aliases(&self) -> &'static [&'static str] {&[]}
+    // lowercase, without the dot
+    fn extensions(&self) -> &'static [&'static str] {&[]}
+    // whole file names, for the ones that dont go by extension
+    fn file_names(&self) -> &'static [&'static str] {&[]}
+    // interpreters a #! line can name
+    fn shebangs(&self) -> &'static [&'static str] {&[]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer>;
+    // the formatted lines, needs fresh token chunks
+    fn auto_format(&self, _text_buffer: &mut TextBuffer) -> Option<Vec<Vec<char>>> {None}
+    // an outside formatter that goes before auto_format
+    fn format_provider(&self) -> Option<Rc<dyn FormatProvider>> {None}
+    fn line_comment(&self) -> Option<&'static str> {None}
+    fn indent(&self) -> LanguageIndent {LanguageIndent::default()}
+    // indent levels that stay full size when the code folds
+    fn folding_depth(&self) -> usize {2}
+}
+
+pub struct PlainTextLanguage;
+
+impl Language for PlainTextLanguage {
+    fn name(&self) -> &'static str {"text"}
+    fn aliases(&self) -> &'static [&'static str] {&["plain", "txt"]}
+    fn extensions(&self) -> &'static [&'static str] {&["txt", "log"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(PlainTextTokenizer)}
+    fn indent(&self) -> LanguageIndent {LanguageIndent {width: 4, brackets: false}}
+}
+
+// words and everything between them, no state across lines
+pub struct PlainTextTokenizer;
+
+impl Tokenizer for PlainTextTokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, _token_chunks: &Vec<TokenChunk>) -> TokenType {
+        state.advance_with_cur();
+        match state.cur {
+            '\0' => {
+                chunk.push(' ');
+                TokenType::Eof
+            },
+            '\n' => {
+                chunk.push('\n');
+                TokenType::Newline
+            },
+            ' ' | '\t' => {
+                chunk.push(state.cur);
+                while state.next == ' ' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Whitespace
+            },
+            ch if ch.is_alphanumeric() || ch == '_' => {
+                chunk.push(ch);
+                while state.next.is_alphanumeric() || state.next == '_' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Identifier
+            },
+            ch => {
+                chunk.push(ch);
+                TokenType::Delimiter
+            }
+        }
+    }
+    
+    fn line_state(&self) -> u64 {0}
+    
+    fn set_line_state(&mut self, _line_state: u64) {}
+}
+
+#[derive(Clone)]
+pub struct LanguageRegistry {
+    // later ones win when two claim the same file
+    pub languages: Vec<Rc<dyn Language>>,
+    pub plain_text: Rc<dyn Language>
+}
+
+impl LanguageRegistry {
+    pub fn new() -> Self {
+        LanguageRegistry {
+            languages: Vec::new(),
+            plain_text: Rc::new(PlainTextLanguage)
+        }
+    }
+    
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register(registry.plain_text.clone());
+        registry.register(Rc::new(RustLanguage));
+        registry.register(Rc::new(ShaderLanguage));
+        registry.register(Rc::new(JSLanguage));
+        registry.register(Rc::new(TomlLanguage));
+        registry.register(Rc::new(JsonLanguage));
+        registry.register(Rc::new(MarkdownLanguage));
+        registry
+    }
+    
+    pub fn register(&mut self, language: Rc<dyn Language>) {
+        self.languages.push(language);
+    }
+    
+    pub fn by_name(&self, name: &str) -> Option<Rc<dyn Language>> {
+        let name = name.to_lowercase();
+        self.languages.iter().rev().find( | l | l.name() == name || l.aliases().contains(&name.as_str())).cloned()
+    }
+    
+    pub fn by_path(&self, path: &str) -> Option<Rc<dyn Language>> {
+        let file_name = path.rsplit( | ch | ch == '/' || ch == '\\').next().unwrap_or(path);
+        if let Some(language) = self.languages.iter().rev().find( | l | l.file_names().contains(&file_name)) {
+            return Some(language.clone())
+        }
+        let dot = file_name.rfind('.') ?;
+        let ext = file_name[dot + 1..].to_lowercase();
+        self.languages.iter().rev().find( | l | l.extensions().contains(&ext.as_str())).cloned()
+    }
+    
+    pub fn by_shebang(&self, first_line: &str) -> Option<Rc<dyn Language>> {
+        let interpreter = parse_shebang(first_line) ?;
+        self.languages.iter().rev().find( | l | l.shebangs().contains(&interpreter.as_str())).cloned()
+    }
+    
+    pub fn by_modeline(&self, lines: &[String]) -> Option<Rc<dyn Language>> {
+        lines.iter().filter_map( | line | parse_modeline(line)).find_map( | name | self.by_name(&name))
+    }
+    
+    pub fn find(&self, path: &str, text_buffer: &TextBuffer) -> Rc<dyn Language> {
+        // modelines sit in the first or last few lines, like vim looks for them
+        let line_count = text_buffer.lines.len();
+        let lines: Vec<String> = (0..line_count).filter( | row | *row < 5 || *row + 5 >= line_count).map( | row | {
+            text_buffer.lines[row].iter().collect()
+        }).collect();
+        self.by_modeline(&lines)
+            .or_else( | | lines.first().and_then( | line | self.by_shebang(line)))
+            .or_else( | | self.by_path(path))
+            .unwrap_or_else( | | self.plain_text.clone())
+    }
+}
+
+// the interpreter name of a #! line, looking through env
+pub fn parse_shebang(line: &str) -> Option<String> {
+    if !line.starts_with("#!") {
+        return None
+    }
+    let mut words = line[2..].split_whitespace();
+    let mut program = words.next() ?.rsplit('/').next() ?;
+    if program == "env" {
+        program = words.find( | word | !word.starts_with('-')) ?;
+    }
+    Some(program.to_string())
+}
+
+// the language of a vim (vim: set ft=x:) or emacs (-*- mode: x -*-) modeline
+pub fn parse_modeline(line: &str) -> Option<String> {
+    let is_name_char = | ch: char | ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == '+';
+    if let Some(start) = line.find("-*-") {
+        let rest = &line[start + 3..];
+        let inner = rest[..rest.find("-*-") ?].trim();
+        let name = if inner.contains(':') {
+            let mode = inner.split(';').find( | var | var.trim().to_lowercase().starts_with("mode:")) ?;
+            mode[mode.find(':') ? + 1..].trim()
+        }
+        else {
+            inner
+        };
+        return if name.len() > 0 {Some(name.to_lowercase())} else {None}
+    }
+    for marker in &["vim:", "vi:", "ex:"] {
+        if let Some(start) = line.find(marker) {
+            // vi: has to start a word, not end one like in navi:
+            if start > 0 && !line[..start].ends_with(char::is_whitespace) {
+                continue
+            }
+            let rest = &line[start + marker.len()..];
+            for option in rest.split( | ch: char | ch == ':' || ch.is_whitespace()) {
+                for key in &["ft=", "filetype=", "syntax=", "syn="] {
+                    if option.starts_with(key) {
+                        let name: String = option[key.len()..].chars().take_while( | ch | is_name_char(*ch)).collect();
+                        if name.len() > 0 {
+                            return Some(name.to_lowercase())
+                        }
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textcursor::*;
+    
+    #[test]
+    fn picks_language_from_modeline_shebang_and_path() {
+        let registry = LanguageRegistry::with_defaults();
+        let find = | path: &str, text: &str | registry.find(path, &TextBuffer::from_test_str(text)).name();
+        assert_eq!(find("src/main.rs", "fn main() {}\n"), "rust");
+        assert_eq!(find("Cargo.toml", "[package]\n"), "toml");
+        assert_eq!(find("Cargo.lock", ""), "toml");
+        assert_eq!(find("data/x.JSON", "{}"), "json");
+        assert_eq!(find("README.md", "# hi\n"), "markdown");
+        assert_eq!(find("shaders/blur.frag", "void main() {}\n"), "glsl");
+        assert_eq!(find("src/pass.wgsl", "fn main() {}\n"), "glsl");
+        assert_eq!(find("notes", "// vim: ft=shader\n"), "glsl");
+        assert_eq!(find("notes", "hello\n"), "text");
+        assert_eq!(find("bin/tool", "#!/usr/bin/env node\nconsole.log(1);\n"), "js");
+        assert_eq!(find("bin/tool", "#!/usr/local/bin/rust-script\nfn main() {}\n"), "rust");
+        assert_eq!(find("src/main.rs", "#![allow(dead_code)]\nfn main() {}\n"), "rust");
+        assert_eq!(find("notes.txt", "some notes\n\n\n\n\n\n\n// vim: set ft=rust ts=4:\n"), "rust");
+        assert_eq!(find("config", "# -*- mode: toml; coding: utf-8 -*-\n"), "toml");
+        assert_eq!(find("config", "-*- json -*-\n"), "json");
+        assert_eq!(find("a.rs", "// vim: ft=markdown\n"), "markdown");
+        // not a modeline, not a known language
+        assert_eq!(find("a.rs", "// navi:ft=json\n// vim: ft=cobol\n"), "rust");
+    }
+    
+    #[test]
+    fn plain_text_splits_words() {
+        let mut text_buffer = TextBuffer::from_test_str("hi, there\n");
+        text_buffer.update_token_chunks(&mut *PlainTextLanguage.new_tokenizer());
+        let types: Vec<TokenType> = text_buffer.token_chunks.iter().map( | t | t.token_type).collect();
+        assert_eq!(types, vec![
+            TokenType::Identifier,
+            TokenType::Delimiter,
+            TokenType::Whitespace,
+            TokenType::Identifier,
+            TokenType::Newline,
+            TokenType::Eof
+        ]);
+    }
+    
+    fn tokens(language: &dyn Language, text: &str) -> Vec<(String, TokenType)> {
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        text_buffer.update_token_chunks(&mut *language.new_tokenizer());
+        text_buffer.token_chunks.iter().filter( | t | t.token_type != TokenType::Whitespace && t.token_type != TokenType::Newline).map( | t | {
+            (text_buffer.flat_text[t.offset..(t.offset + t.len)].iter().collect(), t.token_type)
+        }).collect()
+    }
+    
+    #[test]
+    fn toml_and_markdown_carry_state_over_lines() {
+        let toml = tokens(&TomlLanguage, "[a.b]
+x = \"\"\"one\n[two]\"\"\" # c\ny = [\n  [1, true],\n]\n");
+        assert_eq!(&toml[0..7], &[
+            ("[a.b]".to_string(), TokenType::TypeDef),
+            ("x".to_string(), TokenType::Identifier),
+            ("=".to_string(), TokenType::Operator),
+            ("\"\"\"one".to_string(), TokenType::String),
+            ("[two]\"\"\"".to_string(), TokenType::String),
+            ("# c".to_string(), TokenType::CommentLine),
+            ("y".to_string(), TokenType::Identifier)
+        ]);
+        // an array inside an array isnt a table header
+        assert_eq!(toml[9], ("[".to_string(), TokenType::ParenOpen));
+        
+        let markdown = tokens(&MarkdownLanguage, "# Title\n```rust\nfn a() {}\n```\n- `b` *c*\n");
+        let types: Vec<TokenType> = markdown.iter().map( | t | t.1).collect();
+        assert_eq!(types, vec![
+            TokenType::TypeDef,
+            TokenType::Keyword,
+            TokenType::String,
+            TokenType::Keyword,
+            TokenType::Operator,
+            TokenType::String,
+            TokenType::Operator,
+            TokenType::Identifier,
+            TokenType::Operator,
+            TokenType::Eof
+        ]);
+    }
+    
+    #[test]
+    fn toggles_line_comments() {
+        let mut text_buffer = TextBuffer::from_test_str("a = 1\n  b = 2\n\nc = 3\n");
+        let comment = TomlLanguage.line_comment().unwrap();
+        let mut cursors = TextCursorSet::new();
+        cursors.set[0].head = 8;
+        cursors.set[0].tail = 0;
+        cursors.toggle_line_comment(comment, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_lf_string(), "# a = 1\n#   b = 2\n\nc = 3\n");
+        assert_eq!((cursors.set[0].tail, cursors.set[0].head), (2, 12));
+        cursors.toggle_line_comment(comment, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_lf_string(), "a = 1\n  b = 2\n\nc = 3\n");
+        assert_eq!((cursors.set[0].tail, cursors.set[0].head), (0, 8));
+        text_buffer.undo(true, &mut cursors);
+        assert_eq!(text_buffer.get_as_lf_string(), "# a = 1\n#   b = 2\n\nc = 3\n");
+    }
+}
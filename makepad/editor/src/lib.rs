@@ -1,9 +1,35 @@
+mod textrope;
+pub use crate::textrope::*;
+mod textundo;
+pub use crate::textundo::*;
+mod textmerge;
+pub use crate::textmerge::*;
+mod textencoding;
+pub use crate::textencoding::*;
 mod textbuffer;
 pub use crate::textbuffer::*;
+mod textfold;
+pub use crate::textfold::*;
+mod textwrap;
+pub use crate::textwrap::*;
+mod textgit;
+pub use crate::textgit::*;
+mod textformat;
+pub use crate::textformat::*;
+mod textsymbol;
+pub use crate::textsymbol::*;
 mod codeeditor;
 pub use crate::codeeditor::*;
+mod textsearch;
+pub use crate::textsearch::*;
 mod textcursor;
 pub use crate::textcursor::*;
+mod findbar;
+pub use crate::findbar::*;
+mod lsppopup;
+pub use crate::lsppopup::*;
+mod codecompletion;
+pub use crate::codecompletion::*;
 mod rusteditor;
 pub use crate::rusteditor::*;
 pub mod codeicon;
@@ -12,5 +38,21 @@ pub mod keyboard;
 pub use crate::keyboard::*;
 mod jseditor;
 pub use crate::jseditor::*;
+mod tomltokenizer;
+pub use crate::tomltokenizer::*;
+mod jsontokenizer;
+pub use crate::jsontokenizer::*;
+mod markdowntokenizer;
+pub use crate::markdowntokenizer::*;
+mod language;
+pub use crate::language::*;
+mod lspclient;
+pub use crate::lspclient::*;
+mod keymap;
+pub use crate::keymap::*;
+mod vim;
+pub use crate::vim::*;
+mod minimap;
+pub use crate::minimap::*;
 //mod texteditor;
 //pub use crate::texteditor::*;
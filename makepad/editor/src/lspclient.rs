@@ -0,0 +1,820 @@
+use render::*;
+use process::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::textsearch::*;
+
+// A language server client, JSON-RPC over the stdin/stdout of a child process.
+// LspClient only speaks the protocol and turns what comes back into LspEvents, so it runs
+// fine without an event loop. LspClients owns a client per configured server, starts them
+// when a file they handle shows up, keeps the TextBuffers in sync and puts the results where
+// the editors pick them up: diagnostics go into TextBufferMessages, hover and completion into
+// TextBuffer::lsp_response and go-to-definition through SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET.
+
+// character counts utf16 code units, as the protocol does
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LspPosition {
+    pub line: usize,
+    pub character: usize
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LspDiagnostic {
+    pub range: LspRange,
+    pub level: TextBufferMessageLevel,
+    pub message: String
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LspCompletionItem {
+    pub label: String,
+    pub detail: String,
+    pub insert_text: String,
+    pub is_snippet: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LspTextEdit {
+    pub range: LspRange,
+    pub new_text: String
+}
+
+// what an editor can ask about the text at an offset
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspQuery {
+    Hover {offset: usize},
+    Definition {offset: usize},
+    Completion {offset: usize},
+    Rename {offset: usize, new_name: String}
+}
+
+// the answers of a query, set on the TextBuffer that asked
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspResponse {
+    Hover {offset: usize, text: String},
+    Completion {offset: usize, items: Vec<LspCompletionItem>},
+    Error {message: String}
+}
+
+// paths are relative to the root like the TextBuffers paths, locations outside it are None
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspEvent {
+    Diagnostics {path: String, diagnostics: Vec<LspDiagnostic>},
+    Hover {path: String, offset: usize, text: String},
+    Definition {path: Option<String>, range: LspRange},
+    Completion {path: String, offset: usize, items: Vec<LspCompletionItem>},
+    Rename {edits: Vec<(String, Vec<LspTextEdit>)>},
+    Error {path: String, message: String},
+    Exited
+}
+
+pub fn lsp_frame(body: &str) -> Vec<u8> {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+}
+
+// collects stdout data and cuts it into messages on the Content-Length headers
+#[derive(Clone, Default)]
+pub struct LspReader {
+    pub data: Vec<u8>
+}
+
+impl LspReader {
+    pub fn push(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+    
+    pub fn next_message(&mut self) -> Option<String> {
+        loop {
+            let header_end = self.data.windows(4).position( | w | w == b"\r\n\r\n") ?;
+            let header = String::from_utf8_lossy(&self.data[0..header_end]).to_string();
+            let content_length = header.lines().filter_map( | line | {
+                let mut parts = line.splitn(2, ':');
+                let name = parts.next()?.trim();
+                if name.eq_ignore_ascii_case("content-length") {parts.next()?.trim().parse::<usize>().ok()} else {None}
+            }).next();
+            let body_start = header_end + 4;
+            match content_length {
+                None => { // garbage, skip it
+                    self.data.drain(0..body_start);
+                },
+                Some(len) => {
+                    if self.data.len() < body_start + len {
+                        return None
+                    }
+                    let body = String::from_utf8_lossy(&self.data[body_start..body_start + len]).to_string();
+                    self.data.drain(0..body_start + len);
+                    return Some(body)
+                }
+            }
+        }
+    }
+}
+
+pub fn lsp_position(text_buffer: &TextBuffer, offset: usize) -> LspPosition {
+    let pos = text_buffer.offset_to_text_pos(offset);
+    let line = &text_buffer.lines[pos.row];
+    LspPosition {
+        line: pos.row,
+        character: line[0..pos.col.min(line.len())].iter().map( | ch | ch.len_utf16()).sum()
+    }
+}
+
+pub fn lsp_offset(text_buffer: &TextBuffer, position: LspPosition) -> usize {
+    if position.line >= text_buffer.lines.len() {
+        return text_buffer.lines.char_count()
+    }
+    let line = &text_buffer.lines[position.line];
+    let mut units = 0;
+    let mut col = 0;
+    while col < line.len() && units < position.character {
+        units += line[col].len_utf16();
+        col += 1;
+    }
+    text_buffer.text_pos_to_offset(TextPos {row: position.line, col: col})
+}
+
+pub fn path_to_uri(abs_path: &str) -> String {
+    let mut uri = "file://".to_string();
+    for byte in abs_path.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<String> {
+    let rest = uri.strip_prefix("file://") ?;
+    let mut bytes = Vec::new();
+    let mut iter = rest.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex: String = iter.by_ref().take(2).map( | b | b as char).collect();
+            bytes.push(u8::from_str_radix(&hex, 16).ok() ?);
+        }
+        else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_position(value: &Value) -> LspPosition {
+    LspPosition {
+        line: value["line"].as_u64().unwrap_or(0) as usize,
+        character: value["character"].as_u64().unwrap_or(0) as usize
+    }
+}
+
+fn parse_range(value: &Value) -> LspRange {
+    LspRange {
+        start: parse_position(&value["start"]),
+        end: parse_position(&value["end"])
+    }
+}
+
+fn position_json(position: LspPosition) -> Value {
+    json!({"line": position.line, "character": position.character})
+}
+
+fn parse_text_edits(value: &Value) -> Vec<LspTextEdit> {
+    value.as_array().map( | edits | edits.iter().map( | edit | LspTextEdit {
+        range: parse_range(&edit["range"]),
+        new_text: edit["newText"].as_str().unwrap_or("").to_string()
+    }).collect()).unwrap_or(Vec::new())
+}
+
+// MarkedString, MarkedString[] or MarkupContent
+fn hover_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().map(hover_text).filter( | text | text.len() > 0).collect::<Vec<_>>().join("\n\n"),
+        Value::Object(_) => value["value"].as_str().unwrap_or("").to_string(),
+        _ => String::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LspDocument {
+    pub version: i64,
+    pub mutation_id: u64
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspPending {
+    Initialize,
+    Query {path: String, query: LspQuery},
+    Shutdown
+}
+
+pub struct LspClient {
+    pub language_id: String,
+    // absolute, ends with a /
+    pub root_path: String,
+    pub process: Option<StdioProcess>,
+    pub reader: LspReader,
+    pub next_id: u64,
+    pub pending: HashMap<u64, LspPending>,
+    pub is_initialized: bool,
+    // everything but initialize waits for the server to answer it
+    pub held_back: Vec<Value>,
+    pub documents: HashMap<String, LspDocument>
+}
+
+impl LspClient {
+    pub fn start(command: &str, args: &[&str], root_path: &str, language_id: &str, signal: Signal, signal_value: usize) -> Result<LspClient, String> {
+        let root_path = match std::fs::canonicalize(root_path) {
+            Ok(path) => format!("{}/", path.to_string_lossy().trim_end_matches('/')),
+            Err(err) => return Err(format!("Cannot find {}: {}", root_path, err))
+        };
+        let process = StdioProcess::spawn(command, args, &root_path, signal, signal_value)
+            .map_err( | err | format!("Cannot start {}: {}", command, err)) ?;
+        let mut client = LspClient {
+            language_id: language_id.to_string(),
+            root_path: root_path,
+            process: Some(process),
+            reader: LspReader::default(),
+            next_id: 1,
+            pending: HashMap::new(),
+            is_initialized: false,
+            held_back: Vec::new(),
+            documents: HashMap::new()
+        };
+        let root_uri = path_to_uri(client.root_path.trim_end_matches('/'));
+        client.request(LspPending::Initialize, "initialize", json!({
+            "processId": std::process::id(),
+            "rootUri": root_uri,
+            "capabilities": {
+                "textDocument": {
+                    "synchronization": {"didSave": true},
+                    "hover": {"contentFormat": ["plaintext", "markdown"]},
+                    "completion": {"completionItem": {"snippetSupport": true}},
+                    "definition": {},
+                    "rename": {},
+                    "publishDiagnostics": {}
+                }
+            }
+        }));
+        Ok(client)
+    }
+    
+    pub fn uri(&self, path: &str) -> String {
+        path_to_uri(&format!("{}{}", self.root_path, path))
+    }
+    
+    pub fn path(&self, uri: &str) -> Option<String> {
+        let abs_path = uri_to_path(uri) ?;
+        abs_path.strip_prefix(&self.root_path).map( | path | path.to_string())
+    }
+    
+    fn send(&mut self, message: Value) {
+        let is_initialize = message["method"] == "initialize";
+        if !self.is_initialized && !is_initialize {
+            self.held_back.push(message);
+            return
+        }
+        if let Some(process) = &mut self.process {
+            if process.write(&lsp_frame(&message.to_string())).is_err() {
+                self.process = None;
+            }
+        }
+    }
+    
+    fn request(&mut self, pending: LspPending, method: &str, params: Value) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(id, pending);
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        id
+    }
+    
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+    
+    // opens the document on the server or sends the whole text again when it changed
+    pub fn sync_document(&mut self, path: &str, text_buffer: &TextBuffer) {
+        let uri = self.uri(path);
+        match self.documents.get_mut(path) {
+            None => {
+                self.documents.insert(path.to_string(), LspDocument {version: 1, mutation_id: text_buffer.mutation_id});
+                let language_id = self.language_id.clone();
+                self.notify("textDocument/didOpen", json!({
+                    "textDocument": {"uri": uri, "languageId": language_id, "version": 1, "text": text_buffer.get_as_lf_string()}
+                }));
+            },
+            Some(document) => if document.mutation_id != text_buffer.mutation_id {
+                document.mutation_id = text_buffer.mutation_id;
+                document.version += 1;
+                let version = document.version;
+                self.notify("textDocument/didChange", json!({
+                    "textDocument": {"uri": uri, "version": version},
+                    "contentChanges": [{"text": text_buffer.get_as_lf_string()}]
+                }));
+            }
+        }
+    }
+    
+    pub fn did_save(&mut self, path: &str) {
+        if self.documents.contains_key(path) {
+            let uri = self.uri(path);
+            self.notify("textDocument/didSave", json!({"textDocument": {"uri": uri}}));
+        }
+    }
+    
+    pub fn did_close(&mut self, path: &str) {
+        if self.documents.remove(path).is_some() {
+            let uri = self.uri(path);
+            self.notify("textDocument/didClose", json!({"textDocument": {"uri": uri}}));
+        }
+    }
+    
+    pub fn query(&mut self, path: &str, text_buffer: &TextBuffer, query: LspQuery) -> u64 {
+        self.sync_document(path, text_buffer);
+        let (method, offset) = match &query {
+            LspQuery::Hover {offset} => ("textDocument/hover", *offset),
+            LspQuery::Definition {offset} => ("textDocument/definition", *offset),
+            LspQuery::Completion {offset} => ("textDocument/completion", *offset),
+            LspQuery::Rename {offset, ..} => ("textDocument/rename", *offset)
+        };
+        let mut params = json!({
+            "textDocument": {"uri": self.uri(path)},
+            "position": position_json(lsp_position(text_buffer, offset))
+        });
+        if let LspQuery::Rename {new_name, ..} = &query {
+            params["newName"] = json!(new_name);
+        }
+        self.request(LspPending::Query {path: path.to_string(), query: query}, method, params)
+    }
+    
+    pub fn shutdown(&mut self) {
+        if self.is_initialized {
+            self.request(LspPending::Shutdown, "shutdown", Value::Null);
+        }
+        else {
+            self.process = None;
+        }
+    }
+    
+    pub fn poll(&mut self) -> Vec<LspEvent> {
+        let datas = match &mut self.process {
+            Some(process) => process.read(),
+            None => return Vec::new()
+        };
+        self.handle_datas(datas)
+    }
+    
+    // for when there is no event loop to wake us up
+    pub fn poll_timeout(&mut self, timeout: std::time::Duration) -> Vec<LspEvent> {
+        let datas = match &mut self.process {
+            Some(process) => process.read_timeout(timeout),
+            None => return Vec::new()
+        };
+        self.handle_datas(datas)
+    }
+    
+    fn handle_datas(&mut self, datas: Vec<Vec<u8>>) -> Vec<LspEvent> {
+        let mut events = Vec::new();
+        for data in datas {
+            if data.len() == 0 {
+                self.process = None;
+                events.push(LspEvent::Exited);
+                break;
+            }
+            self.reader.push(&data);
+            while let Some(body) = self.reader.next_message() {
+                if let Ok(message) = serde_json::from_str::<Value>(&body) {
+                    if let Some(event) = self.handle_message(message) {
+                        events.push(event);
+                    }
+                }
+            }
+        }
+        events
+    }
+    
+    fn handle_message(&mut self, message: Value) -> Option<LspEvent> {
+        if let Some(method) = message["method"].as_str() {
+            if !message["id"].is_null() { // the server asks us something, we dont have anything to tell
+                let result = if method == "workspace/configuration" {
+                    Value::Array(vec![Value::Null; message["params"]["items"].as_array().map_or(0, | items | items.len())])
+                }
+                else {
+                    Value::Null
+                };
+                self.send(json!({"jsonrpc": "2.0", "id": message["id"].clone(), "result": result}));
+                return None
+            }
+            if method == "textDocument/publishDiagnostics" {
+                let params = &message["params"];
+                let path = self.path(params["uri"].as_str().unwrap_or("")) ?;
+                let diagnostics = params["diagnostics"].as_array().map( | diagnostics | diagnostics.iter().map( | diagnostic | LspDiagnostic {
+                    range: parse_range(&diagnostic["range"]),
+                    level: match diagnostic["severity"].as_u64() {
+                        Some(1) => TextBufferMessageLevel::Error,
+                        Some(2) => TextBufferMessageLevel::Warning,
+                        _ => TextBufferMessageLevel::Log
+                    },
+                    message: diagnostic["message"].as_str().unwrap_or("").to_string()
+                }).collect()).unwrap_or(Vec::new());
+                return Some(LspEvent::Diagnostics {path: path, diagnostics: diagnostics})
+            }
+            return None
+        }
+        
+        let pending = self.pending.remove(&message["id"].as_u64() ?) ?;
+        let result = &message["result"];
+        match pending {
+            LspPending::Initialize => {
+                self.is_initialized = true;
+                self.notify("initialized", json!({}));
+                for message in std::mem::replace(&mut self.held_back, Vec::new()) {
+                    self.send(message);
+                }
+                None
+            },
+            LspPending::Shutdown => {
+                self.notify("exit", Value::Null);
+                None
+            },
+            LspPending::Query {path, query} => {
+                if let Some(message) = message["error"]["message"].as_str() {
+                    return Some(LspEvent::Error {path: path, message: message.to_string()})
+                }
+                match query {
+                    LspQuery::Hover {offset} => Some(LspEvent::Hover {path: path, offset: offset, text: hover_text(&result["contents"])}),
+                    LspQuery::Definition {..} => {
+                        // Location, Location[] or LocationLink[]
+                        let location = if result.is_array() {&result[0]} else {result};
+                        if location.is_null() {
+                            return None
+                        }
+                        let (uri, range) = if location["targetUri"].is_string() {
+                            (&location["targetUri"], &location["targetSelectionRange"])
+                        }
+                        else {
+                            (&location["uri"], &location["range"])
+                        };
+                        Some(LspEvent::Definition {path: self.path(uri.as_str().unwrap_or("")), range: parse_range(range)})
+                    },
+                    LspQuery::Completion {offset} => {
+                        let items = if result.is_array() {result} else {&result["items"]};
+                        let items = items.as_array().map( | items | items.iter().map( | item | {
+                            let label = item["label"].as_str().unwrap_or("").to_string();
+                            let insert_text = item["textEdit"]["newText"].as_str().or(item["insertText"].as_str()).unwrap_or(&label).to_string();
+                            LspCompletionItem {
+                                detail: item["detail"].as_str().unwrap_or("").to_string(),
+                                is_snippet: item["insertTextFormat"].as_u64() == Some(2),
+                                insert_text: insert_text,
+                                label: label
+                            }
+                        }).collect()).unwrap_or(Vec::new());
+                        Some(LspEvent::Completion {path: path, offset: offset, items: items})
+                    },
+                    LspQuery::Rename {..} => {
+                        let mut edits = Vec::new();
+                        if let Some(changes) = result["changes"].as_object() {
+                            for (uri, text_edits) in changes {
+                                if let Some(path) = self.path(uri) {
+                                    edits.push((path, parse_text_edits(text_edits)));
+                                }
+                            }
+                        }
+                        if let Some(document_changes) = result["documentChanges"].as_array() {
+                            for change in document_changes {
+                                if let Some(path) = self.path(change["textDocument"]["uri"].as_str().unwrap_or("")) {
+                                    edits.push((path, parse_text_edits(&change["edits"])));
+                                }
+                            }
+                        }
+                        Some(LspEvent::Rename {edits: edits})
+                    }
+                }
+            }
+        }
+    }
+    
+    // the diagnostics replace the ones the server sent before, the compiler messages stay next to them
+    pub fn set_diagnostics(text_buffer: &mut TextBuffer, diagnostics: &[LspDiagnostic]) {
+        let messages = diagnostics.iter().map( | diagnostic | (TextCursor {
+            head: lsp_offset(text_buffer, diagnostic.range.end),
+            tail: lsp_offset(text_buffer, diagnostic.range.start),
+            max: 0
+        }, TextBufferMessage {
+            level: diagnostic.level.clone(),
+            body: diagnostic.message.clone(),
+            detail: None,
+            fixes: Vec::new(),
+            source: TextBufferMessageSource::Lsp
+        })).collect();
+        text_buffer.messages.set_source(TextBufferMessageSource::Lsp, text_buffer.mutation_id, messages);
+    }
+    
+    // all edits of one file as a single undo step
+    pub fn apply_edits(text_buffer: &mut TextBuffer, edits: &[LspTextEdit]) {
+        let mut matches: Vec<TextSearchMatch> = edits.iter().map( | edit | TextSearchMatch {
+            start: lsp_offset(text_buffer, edit.range.start),
+            end: lsp_offset(text_buffer, edit.range.end),
+            replace: edit.new_text.replace("\r\n", "\n")
+        }).collect();
+        matches.sort_by_key( | m | m.start);
+        TextCursorSet::new().replace_matches(&matches, text_buffer);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LspServerConfig {
+    pub language_id: String,
+    pub extensions: Vec<String>,
+    pub command: String,
+    pub args: Vec<String>
+}
+
+impl LspServerConfig {
+    pub fn defaults() -> Vec<LspServerConfig> {
+        vec![LspServerConfig {
+            language_id: "rust".to_string(),
+            extensions: vec!["rs".to_string()],
+            command: "rust-analyzer".to_string(),
+            args: Vec::new()
+        }]
+    }
+    
+    pub fn handles_path(&self, path: &str) -> bool {
+        match path.rfind('.') {
+            Some(pos) => self.extensions.iter().any( | ext | *ext == path[pos + 1..]),
+            None => false
+        }
+    }
+}
+
+// results for files that were still loading
+#[derive(Clone, Debug)]
+pub enum LspDeferred {
+    Jump(LspPosition),
+    Edits(Vec<LspTextEdit>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspClientsEvent {
+    None,
+    // a definition lives in another file, the app should show it
+    OpenFile {path: String}
+}
+
+// restarts of a server that exits before we give up on it
+pub const LSP_MAX_EXITS: usize = 3;
+
+#[derive(Default)]
+pub struct LspClients {
+    pub configs: Vec<LspServerConfig>,
+    // same index as configs, None until a file for it gets opened
+    pub clients: Vec<Option<LspClient>>,
+    pub failed: Vec<bool>,
+    // how often each server went away, one that keeps exiting isnt started again
+    pub exits: Vec<usize>,
+    pub signal: Signal,
+    pub deferred: Vec<(String, LspDeferred)>,
+    // servers that wouldnt start or gave up, for the app to show as notices
+    pub notices: Vec<String>
+}
+
+impl LspClients {
+    pub fn init(&mut self, cx: &mut Cx, configs: Vec<LspServerConfig>) {
+        self.signal = cx.new_signal();
+        self.clients = configs.iter().map( | _ | None).collect();
+        self.failed = configs.iter().map( | _ | false).collect();
+        self.exits = configs.iter().map( | _ | 0).collect();
+        self.configs = configs;
+    }
+    
+    fn client_for_path(&mut self, root_path: &str, path: &str) -> Option<&mut LspClient> {
+        let index = self.configs.iter().position( | config | config.handles_path(path)) ?;
+        if self.clients[index].is_none() && !self.failed[index] {
+            let config = &self.configs[index];
+            let args: Vec<&str> = config.args.iter().map( | arg | arg.as_str()).collect();
+            match LspClient::start(&config.command, &args, root_path, &config.language_id, self.signal, index) {
+                Ok(client) => self.clients[index] = Some(client),
+                Err(err) => { // no server installed is fine, we just dont have the features
+                    self.notices.push(err);
+                    self.failed[index] = true;
+                }
+            }
+        }
+        self.clients[index].as_mut()
+    }
+    
+    // call after buffers load or change and when editors close, sends what the servers dont have
+    // yet. Only the buffers with an editor in open_paths are open on the servers
+    pub fn sync_text_buffers(&mut self, cx: &mut Cx, text_buffers: &mut TextBuffers, open_paths: &[String]) {
+        if !cx.platform_type.is_desktop() {
+            return
+        }
+        for client in self.clients.iter_mut().flatten() {
+            let closed: Vec<String> = client.documents.keys().filter( | path | !open_paths.contains(path)).cloned().collect();
+            for path in closed {
+                client.did_close(&path);
+            }
+        }
+        let root_path = text_buffers.root_path.clone();
+        for (path, text_buffer) in &text_buffers.storage {
+            if text_buffer.load_file_read.is_pending() || !open_paths.contains(path) {
+                continue
+            }
+            if let Some(client) = self.client_for_path(&root_path, path) {
+                client.sync_document(path, text_buffer);
+            }
+        }
+        for (path, deferred) in std::mem::replace(&mut self.deferred, Vec::new()) {
+            self.apply_or_defer(cx, text_buffers, path, deferred);
+        }
+    }
+    
+    fn apply_or_defer(&mut self, cx: &mut Cx, text_buffers: &mut TextBuffers, path: String, deferred: LspDeferred) {
+        let text_buffer = text_buffers.from_path(cx, &path);
+        if text_buffer.load_file_read.is_pending() {
+            self.deferred.push((path, deferred));
+            return
+        }
+        match deferred {
+            LspDeferred::Jump(position) => {
+                text_buffer.messages.jump_to_offset = lsp_offset(text_buffer, position);
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET);
+            },
+            LspDeferred::Edits(edits) => {
+                let was_dirty = text_buffer.is_dirty();
+                LspClient::apply_edits(text_buffer, &edits);
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_RELOADED);
+                // renames touch files nobody has open, nothing else would save those. A buffer
+                // with edits of its own keeps the rename unsaved next to them
+                if !was_dirty {
+                    text_buffers.save_file(cx, &path);
+                }
+            }
+        }
+    }
+    
+    // one that never got going, like the rustup proxy without the component, or that keeps
+    // going away would be started again on every sync
+    fn client_exited(&mut self, index: usize) {
+        let was_initialized = self.clients[index].as_ref().map_or(false, | client | client.is_initialized);
+        self.clients[index] = None;
+        self.exits[index] += 1;
+        if !was_initialized || self.exits[index] >= LSP_MAX_EXITS {
+            self.notices.push(format!("{} exited, not starting it again", self.configs[index].command));
+            self.failed[index] = true;
+        }
+    }
+    
+    pub fn did_save(&mut self, path: &str) {
+        if let Some(index) = self.configs.iter().position( | config | config.handles_path(path)) {
+            if let Some(client) = &mut self.clients[index] {
+                client.did_save(path);
+            }
+        }
+    }
+    
+    pub fn query(&mut self, cx: &mut Cx, text_buffers: &mut TextBuffers, path: &str, query: LspQuery) {
+        let root_path = text_buffers.root_path.clone();
+        let text_buffer = match text_buffers.storage.get_mut(path) {
+            Some(text_buffer) => text_buffer,
+            None => return
+        };
+        match self.client_for_path(&root_path, path) {
+            Some(client) => {
+                client.query(path, text_buffer, query);
+            },
+            // hovering over files without a server shouldnt pop up errors
+            None => if !matches!(query, LspQuery::Hover {..}) {
+                text_buffer.set_lsp_response(LspResponse::Error {message: "No language server for this file".to_string()});
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_LSP_RESPONSE);
+            }
+        }
+    }
+    
+    pub fn handle_lsp_clients(&mut self, cx: &mut Cx, event: &mut Event, text_buffers: &mut TextBuffers) -> LspClientsEvent {
+        let index = match event {
+            Event::Signal(se) if self.signal.is_signal(se) => se.value,
+            _ => return LspClientsEvent::None
+        };
+        let events = match self.clients.get_mut(index) {
+            Some(Some(client)) => client.poll(),
+            _ => return LspClientsEvent::None
+        };
+        let mut clients_event = LspClientsEvent::None;
+        for event in events {
+            match event {
+                LspEvent::Diagnostics {path, diagnostics} => {
+                    if let Some(text_buffer) = text_buffers.storage.get_mut(&path) {
+                        LspClient::set_diagnostics(text_buffer, &diagnostics);
+                        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                    }
+                },
+                LspEvent::Hover {path, offset, text} => {
+                    if let Some(text_buffer) = text_buffers.storage.get_mut(&path) {
+                        if text.len() > 0 {
+                            text_buffer.set_lsp_response(LspResponse::Hover {offset: offset, text: text});
+                            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_LSP_RESPONSE);
+                        }
+                    }
+                },
+                LspEvent::Completion {path, offset, items} => {
+                    if let Some(text_buffer) = text_buffers.storage.get_mut(&path) {
+                        text_buffer.set_lsp_response(LspResponse::Completion {offset: offset, items: items});
+                        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_LSP_RESPONSE);
+                    }
+                },
+                LspEvent::Error {path, message} => {
+                    if let Some(text_buffer) = text_buffers.storage.get_mut(&path) {
+                        text_buffer.set_lsp_response(LspResponse::Error {message: message});
+                        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_LSP_RESPONSE);
+                    }
+                },
+                LspEvent::Definition {path: Some(path), range} => {
+                    self.apply_or_defer(cx, text_buffers, path.clone(), LspDeferred::Jump(range.start));
+                    clients_event = LspClientsEvent::OpenFile {path: path};
+                },
+                LspEvent::Rename {edits} => {
+                    for (path, edits) in edits {
+                        self.apply_or_defer(cx, text_buffers, path, LspDeferred::Edits(edits));
+                    }
+                },
+                LspEvent::Exited => self.client_exited(index),
+                LspEvent::Definition {path: None, ..} => ()
+            }
+        }
+        clients_event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn reader_splits_messages() {
+        let mut reader = LspReader::default();
+        let mut data = lsp_frame("{\"id\":1}");
+        data.extend(b"Content-Type: x\r\nContent-Length: 8\r\n\r\n{\"id\":2}Content-Len".iter());
+        // a message can arrive in any number of pieces
+        reader.push(&data[0..5]);
+        assert_eq!(reader.next_message(), None);
+        reader.push(&data[5..]);
+        assert_eq!(reader.next_message().unwrap(), "{\"id\":1}");
+        assert_eq!(reader.next_message().unwrap(), "{\"id\":2}");
+        assert_eq!(reader.next_message(), None);
+        reader.push(b"gth: 2\r\n\r\n{}");
+        assert_eq!(reader.next_message().unwrap(), "{}");
+    }
+    
+    #[test]
+    fn servers_that_keep_exiting_are_not_restarted() {
+        let mut clients = LspClients::default();
+        clients.configs = LspServerConfig::defaults();
+        clients.clients = vec![None];
+        clients.failed = vec![false];
+        clients.exits = vec![0];
+        // exiting before it answered initialize
+        clients.client_exited(0);
+        assert!(clients.failed[0]);
+        assert_eq!(clients.notices, vec!["rust-analyzer exited, not starting it again".to_string()]);
+        
+        clients.notices.clear();
+        clients.failed[0] = false;
+        clients.exits[0] = 0;
+        for exit in 1..=LSP_MAX_EXITS {
+            let client = LspClient {
+                language_id: "rust".to_string(),
+                root_path: "/".to_string(),
+                process: None,
+                reader: LspReader::default(),
+                next_id: 1,
+                pending: HashMap::new(),
+                is_initialized: true,
+                held_back: Vec::new(),
+                documents: HashMap::new()
+            };
+            clients.clients[0] = Some(client);
+            clients.client_exited(0);
+            assert_eq!(clients.failed[0], exit == LSP_MAX_EXITS);
+            assert_eq!(clients.notices.len(), if exit == LSP_MAX_EXITS {1} else {0});
+        }
+    }
+    
+    #[test]
+    fn uris_round_trip() {
+        let uri = path_to_uri("/home/me/my project/caf\u{e9}.rs");
+        assert_eq!(uri, "file:///home/me/my%20project/caf%C3%A9.rs");
+        assert_eq!(uri_to_path(&uri).unwrap(), "/home/me/my project/caf\u{e9}.rs");
+        assert_eq!(uri_to_path("untitled:1"), None);
+    }
+}
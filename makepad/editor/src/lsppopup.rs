@@ -0,0 +1,232 @@
+use render::*;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::lspclient::*;
+
+// The little box the CodeEditor shows language server answers in: hover text under the mouse
+// the rename input and the compiler quick fixes at the cursor. Like the FindBar it takes the keyboard while it needs it
+// and draws on top of the editor view. Completions have their own list in CodeCompletion.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspPopupKind {
+    Hover {text: String},
+    Rename {offset: usize, new_name: String},
+    QuickFix {fixes: Vec<TextQuickFix>, selected: usize},
+    Error {message: String}
+}
+
+#[derive(Clone)]
+pub struct LspPopup {
+    pub bg: Quad,
+    pub text: Text,
+    pub padding: f32,
+    pub max_lines: usize,
+    pub max_cols: usize,
+    pub bg_color: Color,
+    pub text_color: Color,
+    pub selected_color: Color,
+    pub error_color: Color,
+    
+    pub kind: Option<LspPopupKind>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LspPopupEvent {
+    None,
+    Handled,
+    Query(LspQuery),
+    ApplyFix(TextQuickFix)
+}
+
+// the identifier around offset
+pub fn word_range_at(text_buffer: &TextBuffer, offset: usize) -> (usize, usize) {
+    let pos = text_buffer.offset_to_text_pos(offset);
+    let line = &text_buffer.lines[pos.row];
+    let is_word = | ch: char | ch.is_alphanumeric() || ch == '_';
+    let mut start = pos.col.min(line.len());
+    while start > 0 && is_word(line[start - 1]) {
+        start -= 1;
+    }
+    let mut end = pos.col.min(line.len());
+    while end < line.len() && is_word(line[end]) {
+        end += 1;
+    }
+    (offset + start - pos.col, offset + end - pos.col)
+}
+
+impl LspPopup {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            bg: Quad {
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 20.,
+                ..Quad::style(cx)
+            },
+            text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                do_dpi_dilate: true,
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 21.,
+                wrapping: Wrapping::Line,
+                ..Text::style(cx)
+            },
+            padding: 6.,
+            max_lines: 12,
+            max_cols: 80,
+            bg_color: color256(50, 50, 50),
+            text_color: color256(212, 212, 212),
+            selected_color: color256(255, 255, 255),
+            error_color: color256(200, 0, 0),
+            kind: None
+        }
+    }
+    
+    pub fn is_open(&self) -> bool {
+        self.kind.is_some()
+    }
+    
+    pub fn close(&mut self) {
+        self.kind = None;
+    }
+    
+    pub fn is_hover(&self) -> bool {
+        if let Some(LspPopupKind::Hover {..}) = self.kind {true} else {false}
+    }
+    
+    pub fn open_rename(&mut self, cursors: &TextCursorSet, text_buffer: &TextBuffer) {
+        let (start, end) = word_range_at(text_buffer, cursors.set[cursors.last_cursor].head);
+        let mut new_name = String::new();
+        text_buffer.get_range_as_string(start, end - start, &mut new_name);
+        self.kind = Some(LspPopupKind::Rename {offset: start, new_name: new_name});
+    }
+    
+    pub fn open_quick_fixes(&mut self, fixes: Vec<TextQuickFix>) {
+        if fixes.len() == 0 {
+            self.kind = Some(LspPopupKind::Error {message: "No quick fixes here".to_string()});
+            return
+        }
+        self.kind = Some(LspPopupKind::QuickFix {fixes: fixes, selected: 0});
+    }
+    
+    pub fn handle_key_down(&mut self, ke: &KeyEvent) -> LspPopupEvent {
+        match &mut self.kind {
+            None => LspPopupEvent::None,
+            Some(LspPopupKind::Rename {offset, new_name}) => match ke.key_code {
+                KeyCode::Escape => {
+                    self.close();
+                    LspPopupEvent::Handled
+                },
+                KeyCode::Return => {
+                    let query = LspQuery::Rename {offset: *offset, new_name: new_name.clone()};
+                    self.close();
+                    LspPopupEvent::Query(query)
+                },
+                KeyCode::Backspace => {
+                    new_name.pop();
+                    LspPopupEvent::Handled
+                },
+                _ => LspPopupEvent::Handled
+            },
+            Some(LspPopupKind::QuickFix {fixes, selected}) => match ke.key_code {
+                KeyCode::Escape => {
+                    self.close();
+                    LspPopupEvent::Handled
+                },
+                KeyCode::ArrowUp => {
+                    *selected = if *selected == 0 {fixes.len() - 1} else {*selected - 1};
+                    LspPopupEvent::Handled
+                },
+                KeyCode::ArrowDown => {
+                    *selected = (*selected + 1) % fixes.len();
+                    LspPopupEvent::Handled
+                },
+                KeyCode::Return => {
+                    let fix = fixes[*selected].clone();
+                    self.close();
+                    LspPopupEvent::ApplyFix(fix)
+                },
+                _ => LspPopupEvent::Handled
+            },
+            Some(_) => { // hover and errors go away on the next key
+                self.close();
+                if ke.key_code == KeyCode::Escape {LspPopupEvent::Handled} else {LspPopupEvent::None}
+            }
+        }
+    }
+    
+    pub fn handle_text_input(&mut self, te: &TextInputEvent) -> LspPopupEvent {
+        match &mut self.kind {
+            None => LspPopupEvent::None,
+            Some(LspPopupKind::Rename {new_name, ..}) => {
+                new_name.push_str(te.input.split('\n').next().unwrap_or(""));
+                LspPopupEvent::Handled
+            },
+            Some(LspPopupKind::QuickFix {..}) => LspPopupEvent::Handled,
+            Some(_) => {
+                self.close();
+                LspPopupEvent::None
+            }
+        }
+    }
+    
+    fn lines(&self) -> Vec<(Color, String)> {
+        let clip = | line: &str | line.chars().take(self.max_cols).collect::<String>();
+        match &self.kind {
+            None => Vec::new(),
+            Some(LspPopupKind::Hover {text}) => {
+                text.lines().filter( | line | !line.starts_with("```")).take(self.max_lines).map( | line | (self.text_color, clip(line))).collect()
+            },
+            Some(LspPopupKind::Rename {new_name, ..}) => {
+                vec![(self.text_color, format!("Rename  {}_", new_name))]
+            },
+            Some(LspPopupKind::QuickFix {fixes, selected}) => {
+                fixes.iter().take(self.max_lines).enumerate().map( | (index, fix) | {
+                    if index == *selected {
+                        (self.selected_color, clip(&format!("> {}", fix.label)))
+                    }
+                    else {
+                        (self.text_color, clip(&format!("  {}", fix.label)))
+                    }
+                }).collect()
+            },
+            Some(LspPopupKind::Error {message}) => {
+                message.lines().take(self.max_lines).map( | line | (self.error_color, clip(line))).collect()
+            }
+        }
+    }
+    
+    // pos is where the top left goes, relative to the editor view
+    pub fn draw_lsp_popup(&mut self, cx: &mut Cx, pos: Vec2) {
+        let lines = self.lines();
+        if lines.len() == 0 {
+            return
+        }
+        let origin = cx.get_turtle_origin();
+        let glyph = self.text.get_monospace_base(cx);
+        let glyph_w = glyph.x * self.text.font_size;
+        let line_height = glyph.y * self.text.font_size;
+        let cols = lines.iter().map( | (_, line) | line.chars().count()).max().unwrap_or(0);
+        let w = cols as f32 * glyph_w + 2. * self.padding;
+        let h = lines.len() as f32 * line_height + 2. * self.padding;
+        // stay inside the view, flipping above the position when there is no room below
+        let x = pos.x.min(cx.get_width_total() - w).max(0.);
+        let y = if pos.y + h > cx.get_height_total() {(pos.y - h - line_height).max(0.)} else {pos.y};
+        let rect = Rect {x: origin.x + x, y: origin.y + y, w: w, h: h};
+        
+        cx.new_instance_draw_call(&self.bg.shader, 0);
+        self.bg.color = self.bg_color;
+        self.bg.draw_quad_abs(cx, rect);
+        
+        cx.new_instance_draw_call(&self.text.shader, 0);
+        let mut aligned = self.text.begin_text(cx);
+        for (index, (color, line)) in lines.iter().enumerate() {
+            let chunk: Vec<char> = line.chars().collect();
+            self.text.color = *color;
+            self.text.add_text(cx, rect.x + self.padding, rect.y + self.padding + index as f32 * line_height, 0, &mut aligned, &chunk, | _, _, _, _ | {0.});
+        }
+        self.text.end_text(cx, &aligned);
+    }
+}
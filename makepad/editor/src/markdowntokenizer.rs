@@ -0,0 +1,155 @@
+use crate::textbuffer::*;
+use crate::language::*;
+
+pub struct MarkdownLanguage;
+
+impl Language for MarkdownLanguage {
+    fn name(&self) -> &'static str {"markdown"}
+    fn aliases(&self) -> &'static [&'static str] {&["md"]}
+    fn extensions(&self) -> &'static [&'static str] {&["md", "markdown"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(MarkdownTokenizer::new())}
+    fn indent(&self) -> LanguageIndent {LanguageIndent {width: 4, brackets: false}}
+}
+
+pub struct MarkdownTokenizer {
+    // the ` or ~ of the fenced code block we are in, or \0
+    pub fence: char
+}
+
+impl Tokenizer for MarkdownTokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        MarkdownTokenizer::next_token(self, state, chunk, token_chunks)
+    }
+    
+    fn line_state(&self) -> u64 {
+        self.fence as u64
+    }
+    
+    fn set_line_state(&mut self, line_state: u64) {
+        self.fence = line_state as u8 as char;
+    }
+}
+
+impl MarkdownTokenizer {
+    pub fn new() -> MarkdownTokenizer {
+        MarkdownTokenizer {
+            fence: '\0'
+        }
+    }
+    
+    pub fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        let start = chunk.len();
+        let line_start = TokenChunk::is_line_start(token_chunks);
+        state.advance_with_cur();
+        match state.cur {
+            '\0' => { // eof insert a terminating space and end
+                chunk.push(' ');
+                return TokenType::Eof
+            },
+            '\n' => {
+                chunk.push('\n');
+                return TokenType::Newline
+            },
+            ' ' | '\t' => {
+                chunk.push(state.cur);
+                while state.next == ' ' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                return TokenType::Whitespace
+            },
+            _ => ()
+        }
+        chunk.push(state.cur);
+        if self.fence != '\0' { // the rest of a line of code, or the closing fence
+            Self::parse_to_line_end(state, chunk);
+            let fence = self.fence;
+            if line_start && chunk.len() - start >= 3 && chunk[start..start + 3].iter().all( | ch | *ch == fence) {
+                self.fence = '\0';
+                return TokenType::Keyword
+            }
+            return TokenType::String
+        }
+        match state.cur {
+            '`' | '~' => {
+                let fence = state.cur;
+                while state.next == fence {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                if line_start && chunk.len() - start >= 3 { // opening fence with its info string
+                    Self::parse_to_line_end(state, chunk);
+                    self.fence = fence;
+                    return TokenType::Keyword
+                }
+                if fence == '~' {
+                    return TokenType::Operator
+                }
+                // inline code up to the next backtick
+                while state.next != '`' && state.next != '\n' && state.next != '\0' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                while state.next == '`' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::String
+            },
+            '#' if line_start && (state.next == '#' || state.next == ' ' || state.next == '\n' || state.next == '\0') => {
+                Self::parse_to_line_end(state, chunk);
+                TokenType::TypeDef
+            },
+            '>' if line_start => { // quotes read like comments
+                Self::parse_to_line_end(state, chunk);
+                TokenType::CommentLine
+            },
+            '-' | '+' | '*' | '_' => {
+                let ch = state.cur;
+                while state.next == ch {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Operator
+            },
+            '0'..='9' => {
+                while state.next.is_ascii_digit() {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                // numbered list item
+                if line_start && (state.next == '.' || state.next == ')') {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Number
+            },
+            '<' if state.next.is_alphabetic() || state.next == '/' || state.next == '!' => { // html tags and autolinks
+                while state.next != '>' && state.next != '\n' && state.next != '\0' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                if state.next == '>' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::TypeName
+            },
+            ch if ch.is_alphanumeric() => {
+                while state.next.is_alphanumeric() {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Identifier
+            },
+            _ => TokenType::Delimiter
+        }
+    }
+    
+    fn parse_to_line_end<'a>(state: &mut TokenizerState<'a>, chunk: &mut Vec<char>) {
+        while state.next != '\n' && state.next != '\0' {
+            chunk.push(state.next);
+            state.advance();
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use render::*;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::codeeditor::*;
+
+// A scaled down view of the text on the right of the editor, every token a little bar in its color.
+// Only the rows that fit in it get drawn, on long files it scrolls along with the editor so drawing
+// it costs the same on any file. The markers on its right edge are for the whole file.
+
+#[derive(Clone)]
+pub struct Minimap {
+    pub enabled: bool,
+    pub quad: Quad,
+    pub width: f32,
+    pub row_height: f32,
+    pub col_width: f32,
+    pub marker_width: f32,
+    // keeps it clear of the scrollbar
+    pub right_pad: f32,
+    pub bg_color: Color,
+    pub view_box_color: Color,
+    pub selection_color: Color,
+    pub _rect: Rect,
+    pub _first_row: usize,
+    pub _total_rows: usize,
+    pub _visible_rows: usize,
+    pub _box_top: f32,
+    // where in the view box the finger holds it
+    pub _drag: Option<f32>
+}
+
+// what the editor shows, so the minimap can follow it
+#[derive(Clone, Copy, Default)]
+pub struct MinimapRows {
+    pub first_visible: usize,
+    pub visible: usize
+}
+
+impl Minimap {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            enabled: false,
+            quad: Quad {
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 15.,
+                ..Quad::style(cx)
+            },
+            width: 100.,
+            row_height: 2.,
+            col_width: 1.,
+            marker_width: 4.,
+            right_pad: 12.,
+            bg_color: color256(35, 35, 35),
+            view_box_color: color256a(255, 255, 255, 24),
+            selection_color: color256a(38, 79, 120, 200),
+            _rect: Rect::zero(),
+            _first_row: 0,
+            _total_rows: 0,
+            _visible_rows: 0,
+            _box_top: 0.,
+            _drag: None
+        }
+    }
+    
+    // how many rows it has room for
+    pub fn map_rows(&self) -> usize {
+        (self._rect.h / self.row_height) as usize
+    }
+    
+    // the top row of the minimap, it moves along with the editor when the file doesnt fit
+    pub fn compute_first_row(total_rows: usize, map_rows: usize, first_visible: usize, visible_rows: usize) -> usize {
+        if total_rows <= map_rows {
+            return 0
+        }
+        let scrollable = total_rows.saturating_sub(visible_rows).max(1);
+        let fraction = (first_visible as f32 / scrollable as f32).min(1.);
+        (fraction * (total_rows - map_rows) as f32) as usize
+    }
+    
+    // the other way around, which first visible row puts the top of the view box at box_top rows
+    pub fn compute_first_visible(box_top: f32, total_rows: usize, map_rows: usize, visible_rows: usize) -> f32 {
+        let box_top = box_top.max(0.);
+        if total_rows <= map_rows || map_rows <= visible_rows {
+            return box_top.min(total_rows as f32)
+        }
+        let scrollable = total_rows.saturating_sub(visible_rows) as f32;
+        (box_top * scrollable / (map_rows - visible_rows) as f32).min(scrollable)
+    }
+    
+    pub fn hit(&self, abs: Vec2) -> bool {
+        self.enabled && self._rect.contains(abs.x, abs.y)
+    }
+    
+    // a finger down on the minimap, the first visible row the editor should scroll to
+    pub fn handle_finger_down(&mut self, abs: Vec2) -> f32 {
+        let box_h = self._visible_rows as f32 * self.row_height;
+        let y = abs.y - self._rect.y;
+        // grab the box where it was hit, otherwise center it on the finger
+        let grab = if y >= self._box_top && y < self._box_top + box_h {y - self._box_top} else {0.5 * box_h};
+        self._drag = Some(grab);
+        self.handle_finger_move(abs)
+    }
+    
+    pub fn handle_finger_move(&mut self, abs: Vec2) -> f32 {
+        let grab = self._drag.unwrap_or(0.);
+        let box_top = (abs.y - self._rect.y - grab) / self.row_height;
+        Minimap::compute_first_visible(box_top, self._total_rows, self.map_rows(), self._visible_rows)
+    }
+    
+    pub fn draw_minimap(&mut self, cx: &mut Cx, text_buffer: &TextBuffer, colors: &CodeEditorColors, rows: MinimapRows, cursors: &TextCursorSet, search_cursors: &Vec<TextCursor>) {
+        if !self.enabled {
+            return
+        }
+        let origin = cx.get_turtle_origin();
+        self._rect = Rect {
+            x: origin.x + (cx.get_width_total() - self.width - self.right_pad).max(0.),
+            y: origin.y,
+            w: self.width,
+            h: cx.get_height_total()
+        };
+        let rect = self._rect;
+        let total_rows = text_buffer.get_line_count();
+        let map_rows = self.map_rows();
+        let first_row = Minimap::compute_first_row(total_rows, map_rows, rows.first_visible, rows.visible);
+        let last_row = (first_row + map_rows).min(total_rows);
+        self._first_row = first_row;
+        self._total_rows = total_rows;
+        self._visible_rows = rows.visible;
+        
+        cx.new_instance_draw_call(&self.quad.shader, 0);
+        self.quad.color = self.bg_color;
+        self.quad.draw_quad_abs(cx, rect);
+        let row_height = self.row_height;
+        let row_y = | row: usize | rect.y + (row - first_row) as f32 * row_height;
+        
+        // the selected rows under the text
+        self.quad.color = self.selection_color;
+        for cursor in &cursors.set {
+            if !cursor.has_selection() {
+                continue
+            }
+            let (start, end) = cursor.order();
+            let start_row = text_buffer.offset_to_text_pos(start).row.max(first_row);
+            let end_row = text_buffer.offset_to_text_pos(end).row.min(last_row.max(1) - 1);
+            if start_row <= end_row && start_row < last_row {
+                self.quad.draw_quad_abs(cx, Rect {x: rect.x, y: row_y(start_row), w: rect.w, h: (end_row + 1 - start_row) as f32 * self.row_height});
+            }
+        }
+        
+        // the tokens of the rows that fit, one bar for a run of the same token type
+        cx.new_instance_draw_call(&self.quad.shader, 0);
+        let max_cols = ((rect.w - self.marker_width) / self.col_width) as usize;
+        for row in first_row..last_row {
+            let mut index = match text_buffer.token_checkpoints.get(row) {
+                Some(checkpoint) => checkpoint.token,
+                None => break
+            };
+            let mut col = 0;
+            let mut bar: Option<(usize, usize, TokenType)> = None;
+            while index < text_buffer.token_chunks.len() && col < max_cols {
+                let token_chunk = &text_buffer.token_chunks[index];
+                index += 1;
+                match token_chunk.token_type {
+                    TokenType::Newline | TokenType::Eof => break,
+                    TokenType::Whitespace => (),
+                    token_type => {
+                        bar = match bar {
+                            Some((start, end, bar_type)) if end == col && bar_type == token_type => Some((start, col + token_chunk.len, token_type)),
+                            Some(last_bar) => {
+                                self.draw_bar(cx, rect, row_y(row), last_bar, colors, max_cols);
+                                Some((col, col + token_chunk.len, token_type))
+                            },
+                            None => Some((col, col + token_chunk.len, token_type))
+                        };
+                    }
+                }
+                col += token_chunk.len;
+            }
+            if let Some(last_bar) = bar {
+                self.draw_bar(cx, rect, row_y(row), last_bar, colors, max_cols);
+            }
+        }
+        
+        // what the editor shows
+        cx.new_instance_draw_call(&self.quad.shader, 0);
+        let box_top_row = rows.first_visible.max(first_row).min(last_row);
+        self._box_top = (box_top_row - first_row) as f32 * self.row_height;
+        self.quad.color = self.view_box_color;
+        self.quad.draw_quad_abs(cx, Rect {
+            x: rect.x,
+            y: rect.y + self._box_top,
+            w: rect.w,
+            h: (rows.visible as f32 * self.row_height).min(rect.h - self._box_top)
+        });
+        
+        // and the markers for the whole file
+        let marker_x = rect.x + rect.w - self.marker_width;
+        let marker_y = | row: usize | rect.y + (row as f32 / total_rows.max(1) as f32) * rect.h;
+        if text_buffer.messages.mutation_id == text_buffer.mutation_id {
+            for (cursor, body) in text_buffer.messages.cursors.iter().zip(text_buffer.messages.bodies.iter()) {
+                self.quad.color = match body.level {
+                    TextBufferMessageLevel::Error => colors.marker_error,
+                    TextBufferMessageLevel::Warning => colors.marker_warning,
+                    TextBufferMessageLevel::Log => colors.marker_log
+                };
+                let row = text_buffer.offset_to_text_pos(cursor.head).row;
+                self.quad.draw_quad_abs(cx, Rect {x: marker_x, y: marker_y(row), w: self.marker_width, h: 2.});
+            }
+        }
+        self.quad.color = colors.search_match;
+        for cursor in search_cursors {
+            let row = text_buffer.offset_to_text_pos(cursor.head).row;
+            self.quad.draw_quad_abs(cx, Rect {x: marker_x, y: marker_y(row), w: self.marker_width * 0.5, h: 2.});
+        }
+        self.quad.color = colors.cursor;
+        for cursor in &cursors.set {
+            let row = text_buffer.offset_to_text_pos(cursor.head).row;
+            self.quad.draw_quad_abs(cx, Rect {x: marker_x + self.marker_width * 0.5, y: marker_y(row), w: self.marker_width * 0.5, h: 2.});
+        }
+    }
+    
+    fn draw_bar(&mut self, cx: &mut Cx, rect: Rect, y: f32, bar: (usize, usize, TokenType), colors: &CodeEditorColors, max_cols: usize) {
+        let (start, end, token_type) = bar;
+        self.quad.color = colors.token_color(token_type);
+        self.quad.draw_quad_abs(cx, Rect {
+            x: rect.x + start as f32 * self.col_width,
+            y: y,
+            w: (end.min(max_cols) - start) as f32 * self.col_width,
+            h: self.row_height * 0.75
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn map_follows_the_editor_on_long_files() {
+        // fits, nothing moves
+        assert_eq!(Minimap::compute_first_row(100, 300, 40, 50), 0);
+        assert_eq!(Minimap::compute_first_visible(40., 100, 300, 50), 40.);
+        // 50k rows in a 300 row map
+        assert_eq!(Minimap::compute_first_row(50000, 300, 0, 50), 0);
+        assert_eq!(Minimap::compute_first_row(50000, 300, 49950, 50), 49700);
+        let first_row = Minimap::compute_first_row(50000, 300, 24975, 50);
+        assert_eq!(first_row, 24850);
+        // the view box is 125 rows down then, dragging it there gives the same scroll back
+        let first_visible = Minimap::compute_first_visible((24975 - first_row) as f32, 50000, 300, 50);
+        assert!((first_visible - 24975.).abs() < 1.);
+        assert_eq!(Minimap::compute_first_visible(1000., 50000, 300, 50), 49950.);
+        assert_eq!(Minimap::compute_first_visible(-5., 50000, 300, 50), 0.);
+    }
+}
@@ -1,67 +1,32 @@
-use render::*;
-
+use std::rc::Rc;
 use crate::textbuffer::*;
-use crate::codeeditor::*;
+use crate::language::*;
+use crate::textformat::*;
 
-#[derive(Clone)]
-pub struct RustEditor {
-    pub code_editor: CodeEditor,
-}
+pub struct RustLanguage;
 
-#[derive(Clone, PartialEq)]
-pub enum RustEditorEvent {
-    None,
-    Change
+impl Language for RustLanguage {
+    fn name(&self) -> &'static str {"rust"}
+    fn extensions(&self) -> &'static [&'static str] {&["rs"]}
+    fn shebangs(&self) -> &'static [&'static str] {&["rust-script", "run-cargo-script"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(RustTokenizer::new())}
+    fn auto_format(&self, text_buffer: &mut TextBuffer) -> Option<Vec<Vec<char>>> {
+        Some(RustTokenizer::auto_format(text_buffer).out_lines)
+    }
+    fn format_provider(&self) -> Option<Rc<dyn FormatProvider>> {Some(Rc::new(Rustfmt::default()))}
+    fn line_comment(&self) -> Option<&'static str> {Some("//")}
 }
 
-impl RustEditor {
-    pub fn style(cx: &mut Cx) -> Self {
-        let rust_editor = Self {
-            code_editor: CodeEditor::style(cx),
-        };
-        //tab.animator.default = tab.anim_default(cx);
-        rust_editor
-    }
+// glsl, wgsl and friends are close enough to rust for its tokenizer to color them,
+// but rustfmt and the rust auto_format would mangle them
+pub struct ShaderLanguage;
 
-    
-    pub fn handle_rust_editor(&mut self, cx: &mut Cx, event: &mut Event, text_buffer: &mut TextBuffer) -> CodeEditorEvent {
-        let ce = self.code_editor.handle_code_editor(cx, event, text_buffer);
-        match ce {
-            CodeEditorEvent::AutoFormat => {
-                let formatted = RustTokenizer::auto_format(text_buffer).out_lines;
-                self.code_editor.cursors.replace_lines_formatted(formatted, text_buffer);
-                self.code_editor.view.redraw_view_area(cx);
-            },
-            _ => ()
-        }
-        ce
-    }
-    
-    pub fn draw_rust_editor(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer) {
-        if text_buffer.needs_token_chunks() && text_buffer.lines.len() >0 {
-            let mut state = TokenizerState::new(&text_buffer.lines);
-            let mut tokenizer = RustTokenizer::new();
-            let mut pair_stack = Vec::new();
-            loop {
-                let offset = text_buffer.flat_text.len();
-                let token_type = tokenizer.next_token(&mut state, &mut text_buffer.flat_text, &text_buffer.token_chunks);
-                TokenChunk::push_with_pairing(&mut text_buffer.token_chunks, &mut pair_stack, state.next, offset, text_buffer.flat_text.len(), token_type);
-                if token_type == TokenType::Eof {
-                    break
-                }
-            }
-        }
-        
-        if let Err(_) = self.code_editor.begin_code_editor(cx, text_buffer) {
-            return
-        }
-        
-        for (index, token_chunk) in text_buffer.token_chunks.iter_mut().enumerate() {
-            self.code_editor.draw_chunk(cx, index, &text_buffer.flat_text, token_chunk, &text_buffer.messages.cursors);
-        }
-        
-        self.code_editor.end_code_editor(cx, text_buffer);
-    }
+impl Language for ShaderLanguage {
+    fn name(&self) -> &'static str {"glsl"}
+    fn aliases(&self) -> &'static [&'static str] {&["shader", "wgsl", "hlsl"]}
+    fn extensions(&self) -> &'static [&'static str] {&["glsl", "vert", "frag", "geom", "comp", "wgsl", "hlsl", "metal", "shader"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(RustTokenizer::new())}
+    fn line_comment(&self) -> Option<&'static str> {Some("//")}
 }
 
 pub struct RustTokenizer {
@@ -69,6 +34,21 @@ pub struct RustTokenizer {
     pub comment_depth: usize
 }
 
+impl Tokenizer for RustTokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        RustTokenizer::next_token(self, state, chunk, token_chunks)
+    }
+    
+    fn line_state(&self) -> u64 {
+        (self.comment_depth as u64) << 1 | self.comment_single as u64
+    }
+    
+    fn set_line_state(&mut self, line_state: u64) {
+        self.comment_single = line_state & 1 != 0;
+        self.comment_depth = (line_state >> 1) as usize;
+    }
+}
+
 impl RustTokenizer {
     pub fn new() -> RustTokenizer {
         RustTokenizer {
@@ -1,39 +1,266 @@
  use render::*;
 
 use crate::textcursor::*;
+use crate::textrope::*;
+use crate::textundo::*;
+use crate::textmerge::*;
+use crate::textencoding::*;
+use crate::lspclient::*;
+use crate::textfold::*;
+use crate::textgit::*;
 use std::collections::HashMap;
+use std::sync::mpsc;
+use serde::*;
+use git::*;
 
 #[derive(Clone, Default)]
 pub struct TextBuffer {
-    // Vec<Vec<char>> was chosen because, for all practical use (code) most lines are short
-    // Concatenating the total into a utf8 string is trivial, and O(1) for windowing into the lines is handy.
-    // Also inserting lines is pretty cheap even approaching 100k lines.
-    // If you want to load a 100 meg single line file or something with >100k lines
-    // other options are better. But these are not usecases for this editor.
-    pub lines: Vec<Vec<char>>,
-    pub undo_stack: Vec<TextUndo>,
-    pub redo_stack: Vec<TextUndo>,
+    // The lines live in a chunked rope so offset/row lookups and edits stay O(log n)
+    // on large files and in long lines, while lines[row] still gives you a plain Vec<char> to window into.
+    pub lines: TextRope,
+    pub undo_tree: TextUndoTree,
     pub load_file_read: FileRead,
+    pub reload_file_read: FileRead,
+    // the text as we last read it from or wrote it to disk, the base when merging changes made on disk
+    pub disk_text: String,
+    pub pending_saves: Vec<TextBufferSave>,
     pub signal: Signal,
     pub mutation_id: u64,
-    pub is_crlf: bool,
+    // how the file looked on disk, get_as_bytes writes it back the same way
+    pub encoding: TextEncoding,
+    pub line_endings: TextLineEndings,
     pub messages: TextBufferMessages,
     pub flat_text: Vec<char>,
     pub token_chunks: Vec<TokenChunk>,
     pub token_chunks_id: u64,
+    // tokenizer state at the start of every line, and the rows touched since the last tokenize
+    pub token_checkpoints: Vec<TokenCheckpoint>,
+    pub token_damage: Option<TokenDamage>,
     pub keyboard: TextBufferKeyboard,
+    pub folds: TextFolds,
+    pub git: TextGit,
+    // the last hover/completion answer from a language server, lsp_response_id goes up with every one
+    pub lsp_response: Option<LspResponse>,
+    pub lsp_response_id: u64,
 } 
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenCheckpoint {
+    pub token: usize,
+    pub offset: usize,
+    // how many parens are open, TokenChunk::open_pairs finds them
+    pub pair_depth: usize,
+    pub line_state: u64,
+    pub last_token: TokenType
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TokenDamage {
+    pub start_row: usize,
+    pub end_row: usize,
+    pub line_delta: isize
+}
+
 impl TextBuffer {
     pub fn needs_token_chunks(&mut self) -> bool {
         if self.token_chunks_id != self.mutation_id && !self.load_file_read.is_pending() {
             self.token_chunks_id = self.mutation_id;
-            self.token_chunks.truncate(0);
-            self.flat_text.truncate(0);
             return true
         }
         return false
     }
+    
+    // rows start_row..=old_end_row got replaced by start_row..=new_end_row
+    fn mark_token_damage(&mut self, start_row: usize, old_end_row: usize, new_end_row: usize) {
+        let line_delta = new_end_row as isize - old_end_row as isize;
+        self.token_damage = Some(match self.token_damage {
+            None => TokenDamage {
+                start_row: start_row,
+                end_row: new_end_row,
+                line_delta: line_delta
+            },
+            Some(damage) => {
+                let end_row = if damage.end_row > old_end_row {
+                    (damage.end_row as isize + line_delta) as usize
+                }
+                else {
+                    damage.end_row
+                };
+                TokenDamage {
+                    start_row: damage.start_row.min(start_row),
+                    end_row: end_row.max(new_end_row),
+                    line_delta: damage.line_delta + line_delta
+                }
+            }
+        });
+    }
+    
+    pub fn reset_token_chunks(&mut self) {
+        self.token_chunks.truncate(0);
+        self.flat_text.truncate(0);
+        self.token_checkpoints.truncate(0);
+        self.token_damage = None;
+    }
+    
+    pub fn update_token_chunks<T: Tokenizer + ?Sized>(&mut self, tokenizer: &mut T) {
+        if let Some((start_row, end_row)) = self.tokenize(tokenizer) {
+            self.folds.update_ranges(&self.flat_text, &self.token_chunks, &self.token_checkpoints, start_row, end_row);
+        }
+    }
+    
+    // Re-lexes from the first damaged line onwards until the tokenizer state at a line start
+    // matches the old checkpoint of that line again. Only the relexed rows get spliced in, the
+    // old tokens after them stay where they are and just get their offsets and pairing shifted.
+    // Gives back the rows that got lexed again, if any.
+    fn tokenize<T: Tokenizer + ?Sized>(&mut self, tokenizer: &mut T) -> Option<(usize, usize)> {
+        if self.lines.len() == 0 {
+            self.reset_token_chunks();
+            return Some((0, 0))
+        }
+        let damage = self.token_damage.take();
+        let start_row = match damage {
+            Some(damage) if damage.start_row < self.token_checkpoints.len() => damage.start_row,
+            None if self.token_checkpoints.len() > 0 => return None,
+            _ => {
+                self.reset_token_chunks();
+                0
+            }
+        };
+        
+        let mut state = TokenizerState::new_at_row(&self.lines, start_row);
+        let mut pair_stack = Vec::new();
+        let (start_token, start_offset) = if let Some(start) = self.token_checkpoints.get(start_row) {
+            // parens still open at the restart point were paired with tokens that get lexed again
+            pair_stack = TokenChunk::open_pairs(&self.token_chunks, start.token, start.pair_depth);
+            for open in &pair_stack {
+                self.token_chunks[*open].pair_token = *open;
+            }
+            // the newline in front of the restart row remembers the first char of the row
+            if start.token > 0 {
+                self.token_chunks[start.token - 1].next = state.next;
+            }
+            tokenizer.set_line_state(start.line_state);
+            state.last_token = start.last_token;
+            (start.token, start.offset)
+        }
+        else {
+            (0, 0)
+        };
+        
+        // the relexed rows go in here first, the tokenizer only looks back as far as the line start
+        let mut tokens = Vec::new();
+        let mut text = Vec::new();
+        let mut checkpoints = vec![TokenCheckpoint {
+            token: start_token,
+            offset: start_offset,
+            pair_depth: pair_stack.len(),
+            line_state: tokenizer.line_state(),
+            last_token: state.last_token
+        }];
+        loop {
+            let offset = start_offset + text.len();
+            let token_type = tokenizer.next_token(&mut state, &mut text, &tokens);
+            let pair_token = start_token + tokens.len();
+            let pair_token = match token_type {
+                TokenType::ParenOpen => {
+                    pair_stack.push(pair_token);
+                    pair_token
+                },
+                TokenType::ParenClose => match pair_stack.pop() {
+                    Some(open) if open >= start_token => {
+                        tokens[open - start_token].pair_token = pair_token;
+                        open
+                    },
+                    Some(open) => {
+                        self.token_chunks[open].pair_token = pair_token;
+                        open
+                    },
+                    None => pair_token
+                },
+                _ => pair_token
+            };
+            tokens.push(TokenChunk {
+                offset: offset,
+                pair_token: pair_token,
+                len: start_offset + text.len() - offset,
+                next: state.next,
+                token_type: token_type
+            });
+            if token_type == TokenType::Eof {
+                self.token_chunks.truncate(start_token);
+                self.token_chunks.append(&mut tokens);
+                self.flat_text.truncate(start_offset);
+                self.flat_text.append(&mut text);
+                self.token_checkpoints.truncate(start_row);
+                self.token_checkpoints.append(&mut checkpoints);
+                return Some((start_row, self.token_checkpoints.len()))
+            }
+            if token_type != TokenType::Newline {
+                continue
+            }
+            let row = start_row + checkpoints.len();
+            let checkpoint = TokenCheckpoint {
+                token: start_token + tokens.len(),
+                offset: start_offset + text.len(),
+                pair_depth: pair_stack.len(),
+                line_state: tokenizer.line_state(),
+                last_token: state.scan_last_token(&tokens)
+            };
+            if let Some(damage) = damage {
+                if row > damage.end_row {
+                    let old_row = (row as isize - damage.line_delta) as usize;
+                    if let Some(old) = self.token_checkpoints.get(old_row) {
+                        if old.line_state == checkpoint.line_state
+                            && old.last_token == checkpoint.last_token
+                            && old.pair_depth == checkpoint.pair_depth {
+                            let (old_token, old_offset) = (old.token, old.offset);
+                            self.token_chunks.splice(start_token..old_token, tokens);
+                            self.flat_text.splice(start_offset..old_offset, text);
+                            self.token_checkpoints.splice(start_row..old_row, checkpoints);
+                            self.shift_old_tokens(row, old_token, old_offset, checkpoint.token, checkpoint.offset, pair_stack);
+                            return Some((start_row, row))
+                        }
+                    }
+                }
+            }
+            checkpoints.push(checkpoint);
+        }
+    }
+    
+    // the old tokens from row onwards lex the same, they started at old_token and old_offset
+    // and now start at new_token and new_offset. They only move over, and the parens closing
+    // the ones still open in pair_stack get paired again
+    fn shift_old_tokens(&mut self, row: usize, old_token: usize, old_offset: usize, new_token: usize, new_offset: usize, mut pair_stack: Vec<usize>) {
+        if old_token != new_token || old_offset != new_offset {
+            for token_chunk in &mut self.token_chunks[new_token..] {
+                token_chunk.offset = token_chunk.offset - old_offset + new_offset;
+                if token_chunk.pair_token >= old_token {
+                    token_chunk.pair_token = token_chunk.pair_token - old_token + new_token;
+                }
+            }
+            for checkpoint in &mut self.token_checkpoints[row..] {
+                checkpoint.token = checkpoint.token - old_token + new_token;
+                checkpoint.offset = checkpoint.offset - old_offset + new_offset;
+            }
+        }
+        // skip the groups that open and close in the old tokens, the parens closing outside
+        // of them are the ones that close what is still open
+        let mut index = new_token;
+        while pair_stack.len() > 0 && index < self.token_chunks.len() {
+            let token_chunk = &self.token_chunks[index];
+            match token_chunk.token_type {
+                TokenType::ParenOpen if token_chunk.pair_token > index => index = token_chunk.pair_token,
+                TokenType::ParenOpen => break,
+                TokenType::ParenClose => {
+                    let open = pair_stack.pop().unwrap();
+                    self.token_chunks[open].pair_token = index;
+                    self.token_chunks[index].pair_token = open;
+                },
+                _ => ()
+            }
+            index += 1;
+        }
+    }
 }
 
 pub const SIGNAL_TEXTBUFFER_LOADED: usize = 1;
@@ -41,6 +268,17 @@ pub const SIGNAL_TEXTBUFFER_MESSAGE_UPDATE: usize = 2;
 pub const SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET: usize = 3;
 pub const SIGNAL_TEXTBUFFER_DATA_UPDATE: usize = 4;
 pub const SIGNAL_TEXTBUFFER_KEYBOARD_UPDATE: usize = 5;
+pub const SIGNAL_TEXTBUFFER_RELOADED: usize = 6;
+pub const SIGNAL_TEXTBUFFER_LSP_RESPONSE: usize = 7;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextDiskChange {
+    Unchanged,
+    Reloaded,
+    Merged,
+    // row ranges of the conflict blocks
+    Conflicted(Vec<(usize, usize)>)
+}
 
 #[derive(Clone, Default)]
 pub struct TextBufferKeyboard {
@@ -51,37 +289,326 @@ pub struct TextBufferKeyboard {
 
 #[derive(Clone, Default)]
 pub struct TextBufferMessages {
-    pub gc_id: u64,
-    // gc id for the update pass
     pub mutation_id: u64,
     // only if this matches the textbuffer mutation id are the messages valid
     pub cursors: Vec<TextCursor>,
     pub bodies: Vec<TextBufferMessage>,
-    pub jump_to_offset: usize
+    pub jump_to_offset: usize,
+    // a failed read or write of the file, shown as the first message
+    pub file_error: Option<String>
 }
 
-#[derive(Clone, PartialEq)]
+impl TextBufferMessages {
+    // drops the messages of every source, a file error stays until the file reads or writes fine again
+    pub fn clear(&mut self) {
+        self.cursors.truncate(0);
+        self.bodies.truncate(0);
+        if let Some(file_error) = &self.file_error {
+            self.cursors.push(TextCursor {head: 0, tail: 0, max: 0});
+            self.bodies.push(TextBufferMessage::file_error(file_error));
+        }
+    }
+    
+    // the messages of source get replaced, the ones from other sources stay as long as they
+    // are for this text. They go in order of where they start, the file error first
+    pub fn set_source(&mut self, source: TextBufferMessageSource, mutation_id: u64, messages: Vec<(TextCursor, TextBufferMessage)>) {
+        if self.mutation_id != mutation_id { // whatever is there is stale anyway
+            self.mutation_id = mutation_id;
+            self.clear();
+        }
+        let mut merged: Vec<(TextCursor, TextBufferMessage)> = self.cursors.drain(..).zip(self.bodies.drain(..)).filter( | (_, body) | body.source != source).collect();
+        merged.extend(messages.into_iter().map( | (cursor, mut body) | {
+            body.source = source;
+            (cursor, body)
+        }));
+        merged.sort_by_key( | (cursor, body) | (body.source != TextBufferMessageSource::File, cursor.order().0));
+        for (cursor, body) in merged {
+            self.cursors.push(cursor);
+            self.bodies.push(body);
+        }
+    }
+    
+    pub fn set_file_error(&mut self, file_error: Option<String>, mutation_id: u64) {
+        self.file_error = file_error;
+        let messages = self.file_error.iter().map( | file_error | {
+            (TextCursor {head: 0, tail: 0, max: 0}, TextBufferMessage::file_error(file_error))
+        }).collect();
+        self.set_source(TextBufferMessageSource::File, mutation_id, messages);
+    }
+}
+
+// a save thats written but not confirmed, if the write fails the file on disk is still
+// the old one so we go back to that
+#[derive(Clone)]
+pub struct TextBufferSave {
+    pub write_id: u64,
+    pub prev_save_point: usize,
+    pub prev_disk_text: String
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TextBufferMessageLevel {
     Error,
     Warning,
     Log
 }
 
+// who put a message there, each of them only replaces its own
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextBufferMessageSource {
+    File,
+    Compiler,
+    Lsp,
+    Format,
+    Merge
+}
+
 #[derive(Clone)]
 pub struct TextBufferMessage {
     pub level: TextBufferMessageLevel,
-    pub body: String
+    pub body: String,
+    // the full compiler output with its notes, shown under the line on request
+    pub detail: Option<String>,
+    // what the compiler suggested to fix it
+    pub fixes: Vec<TextQuickFix>,
+    pub source: TextBufferMessageSource
 }
 
+impl TextBufferMessage {
+    fn file_error(file_error: &str) -> TextBufferMessage {
+        TextBufferMessage {
+            level: TextBufferMessageLevel::Error,
+            body: file_error.to_string(),
+            detail: None,
+            fixes: Vec::new(),
+            source: TextBufferMessageSource::File
+        }
+    }
+}
+
+// a compiler suggestion, its edits go in together
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextQuickFix {
+    pub label: String,
+    // rustc is sure it is right, so it can go in without looking
+    pub machine_applicable: bool,
+    pub edits: Vec<TextQuickFixEdit>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextQuickFixEdit {
+    pub start: TextPos,
+    pub end: TextPos,
+    pub replacement: String
+}
+
+#[derive(Default)]
 pub struct TextBuffers {
     pub root_path: String,
-    pub storage: HashMap<String, TextBuffer>
+    pub storage: HashMap<String, TextBuffer>,
+    pub undo_histories: HashMap<String, TextUndoHistory>,
+    pub undo_file_read: FileRead,
+    pub undo_save_timer: Timer,
+    pub fold_states: HashMap<String, TextFoldState>,
+    pub fold_file_read: FileRead,
+    // the repository root_path is in, if any
+    pub git_repo: Option<GitRepo>,
+    pub git_loader: Option<TextGitLoader>,
+    pub git_receiver: Option<mpsc::Receiver<TextGitLoad>>
 }
 
 impl TextBuffers {
+    // what the editor remembers per project goes in ~/.makepad next to the recent projects,
+    // one file per project and kind so the project folder itself stays clean
+    fn project_state_path(&self, kind: &str) -> Option<String> {
+        let home = std::env::var_os("HOME").or_else( || std::env::var_os("USERPROFILE")) ?;
+        let dir = std::path::PathBuf::from(home).join(".makepad").join("projects");
+        std::fs::create_dir_all(&dir).ok() ?;
+        let name = format!("{:016x}_{}.json", text_content_hash(&self.root_path), kind);
+        Some(dir.join(name).to_string_lossy().to_string())
+    }
+    
+    pub fn load_undo_histories(&mut self, cx: &mut Cx) {
+        if let Some(path) = self.project_state_path("undo") {
+            self.undo_file_read = cx.file_read(&path);
+        }
+    }
+    
+    fn save_undo_histories(&mut self, cx: &mut Cx) {
+        cx.stop_timer(&mut self.undo_save_timer);
+        if let (Some(path), Ok(json)) = (self.project_state_path("undo"), serde_json::to_string(&self.undo_histories)) {
+            cx.file_write(&path, json.as_bytes());
+        }
+    }
+    
+    // every save moves a save point and autosaves come on every pause in typing, writing all
+    // the histories for each of those is a lot. They go out once the saving settles down
+    fn save_undo_histories_later(&mut self, cx: &mut Cx) {
+        cx.stop_timer(&mut self.undo_save_timer);
+        self.undo_save_timer = cx.start_timer(2.0, false);
+    }
+    
+    pub fn handle_undo_save_timer(&mut self, cx: &mut Cx, te: &TimerEvent) {
+        if self.undo_save_timer.is_timer(te) {
+            self.save_undo_histories(cx);
+        }
+    }
+    
+    // writes a waiting history right away, for when the app might go away
+    pub fn flush_undo_histories(&mut self, cx: &mut Cx) {
+        if !self.undo_save_timer.is_empty() {
+            self.save_undo_histories(cx);
+        }
+    }
+    
+    pub fn load_fold_states(&mut self, cx: &mut Cx) {
+        if let Some(path) = self.project_state_path("folds") {
+            self.fold_file_read = cx.file_read(&path);
+        }
+    }
+    
+    // remembers which blocks of path are folded, for the text as it is now
+    pub fn save_fold_state(&mut self, cx: &mut Cx, path: &str) {
+        if let Some(text_buffer) = self.storage.get(path) {
+            if text_buffer.folds.folded.len() == 0 {
+                if self.fold_states.remove(path).is_none() {
+                    return
+                }
+            }
+            else {
+                let content_hash = text_content_hash(&text_buffer.get_as_string());
+                self.fold_states.insert(path.to_string(), text_buffer.folds.get_state(content_hash));
+            }
+            self.save_fold_states(cx);
+        }
+    }
+    
+    fn save_fold_states(&mut self, cx: &mut Cx) {
+        if let (Some(path), Ok(json)) = (self.project_state_path("folds"), serde_json::to_string(&self.fold_states)) {
+            cx.file_write(&path, json.as_bytes());
+        }
+    }
+    
+    // a file or folder moved on disk, the buffers under it go along with their undo history and folds
+    pub fn rename_path(&mut self, cx: &mut Cx, from: &str, to: &str) {
+        let paths: Vec<String> = self.storage.keys().filter( | path | renamed_path(path, from, to).is_some()).cloned().collect();
+        for old_path in paths {
+            let new_path = renamed_path(&old_path, from, to).unwrap();
+            let mut text_buffer = self.storage.remove(&old_path).unwrap();
+            cx.file_unwatch(&format!("{}{}", self.root_path, old_path));
+            cx.file_watch(&format!("{}{}", self.root_path, new_path));
+            if let Some(git_loader) = &self.git_loader {
+                text_buffer.git.load(git_loader, &new_path);
+            }
+            self.storage.insert(new_path, text_buffer);
+        }
+        if rename_keys(&mut self.undo_histories, from, to) {
+            self.save_undo_histories(cx);
+        }
+        if rename_keys(&mut self.fold_states, from, to) {
+            self.save_fold_states(cx);
+        }
+    }
+    
+    // the file or folder went to the trash. Buffers without edits go with it, the others stay
+    // with a message so their edits arent lost, saving one puts the file back. Returns the paths
+    // of the buffers that went
+    pub fn close_deleted_path(&mut self, cx: &mut Cx, deleted: &str) -> Vec<String> {
+        let paths: Vec<String> = self.storage.keys().filter( | path | renamed_path(path, deleted, deleted).is_some()).cloned().collect();
+        let mut closed = Vec::new();
+        for path in paths {
+            let text_buffer = self.storage.get_mut(&path).unwrap();
+            if text_buffer.is_dirty() {
+                text_buffer.messages.set_file_error(Some("Deleted, saving puts the file back".to_string()), text_buffer.mutation_id);
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                continue;
+            }
+            self.storage.remove(&path);
+            cx.file_unwatch(&format!("{}{}", self.root_path, path));
+            closed.push(path);
+        }
+        closed
+    }
+    
+    pub fn open_git_repo(&mut self, cx: &mut Cx) {
+        self.git_repo = GitRepo::open(&self.root_path);
+        if let Some(git_repo) = &self.git_repo {
+            let (sender, receiver) = mpsc::channel();
+            self.git_loader = Some(TextGitLoader {repo: git_repo.clone(), sender: sender, signal: cx.new_signal()});
+            self.git_receiver = Some(receiver);
+        }
+    }
+    
+    // HEAD or the index changed underneath us
+    pub fn reload_git(&mut self) {
+        if let Some(git_loader) = &self.git_loader {
+            for (path, text_buffer) in &mut self.storage {
+                text_buffer.git.load(git_loader, path);
+            }
+        }
+    }
+    
+    // the git reads that finished on their threads go to their buffers
+    pub fn handle_git_loads(&mut self, cx: &mut Cx, event: &Event) {
+        let is_git_signal = match (event, &self.git_loader) {
+            (Event::Signal(se), Some(git_loader)) => git_loader.signal.is_signal(se),
+            _ => false
+        };
+        if !is_git_signal {
+            return
+        }
+        if let Some(receiver) = &self.git_receiver {
+            while let Ok(load) = receiver.try_recv() {
+                let path = match &load {
+                    TextGitLoad::Texts {path, ..} | TextGitLoad::Blame {path, ..} => path.clone()
+                };
+                if let Some(text_buffer) = self.storage.get_mut(&path) {
+                    if text_buffer.git.apply_load(load) {
+                        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_DATA_UPDATE);
+                    }
+                }
+            }
+        }
+    }
+    
+    // puts the change at row of what the buffer shows into the index, the file on disk stays as it is
+    pub fn stage_git_hunk(&mut self, path: &str, row: usize) -> Result<(), String> {
+        let git_repo = self.git_repo.as_ref().ok_or("Not in a git repository".to_string()) ?;
+        let text_buffer = self.storage.get_mut(path).ok_or("File is not open".to_string()) ?;
+        let staged = text_buffer.git.stage_hunk(row, &text_buffer.lines, text_buffer.encoding, &text_buffer.line_endings) ?;
+        git_repo.stage_blob(path, &staged) ?;
+        if let Some(git_loader) = &self.git_loader {
+            text_buffer.git.load(git_loader, path);
+        }
+        Ok(())
+    }
+    
+    // the message after (or before) offset in path, going on through the other files in path order
+    pub fn next_message(&self, path: &str, offset: usize, forward: bool) -> Option<(String, usize)> {
+        let mut all = Vec::new();
+        for (message_path, text_buffer) in &self.storage {
+            if text_buffer.messages.mutation_id != text_buffer.mutation_id {
+                continue
+            }
+            for cursor in &text_buffer.messages.cursors {
+                all.push((message_path.as_str(), cursor.order().0));
+            }
+        }
+        all.sort();
+        all.dedup();
+        let found = if forward {
+            all.iter().find( | (p, o) | (*p, *o) > (path, offset)).or(all.first())
+        }
+        else {
+            all.iter().rev().find( | (p, o) | (*p, *o) < (path, offset)).or(all.last())
+        };
+        found.map( | (p, o) | (p.to_string(), *o))
+    }
+    
     pub fn from_path(&mut self, cx: &mut Cx, path: &str) -> &mut TextBuffer {
         let root_path = &self.root_path;
         self.storage.entry(path.to_string()).or_insert_with( || {
+            cx.file_watch(&format!("{}{}", root_path, path));
             TextBuffer {
                 signal: cx.new_signal(),
                 mutation_id: 1,
@@ -92,23 +619,146 @@ impl TextBuffers {
     }
     
     pub fn save_file(&mut self, cx: &mut Cx, path: &str) {
-        let text_buffer = self.storage.get(path);
+        let text_buffer = self.storage.get_mut(path);
         if let Some(text_buffer) = text_buffer {
             let string = text_buffer.get_as_string();
-            cx.file_write(&format!("{}{}", self.root_path, path), string.as_bytes());
+            let bytes = match text_buffer.get_as_bytes() {
+                Ok(bytes) => bytes,
+                Err(err) => { // the text doesnt fit the encoding, dont write something else than asked
+                    text_buffer.messages.set_file_error(Some(err), text_buffer.mutation_id);
+                    cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                    return
+                }
+            };
+            let write_id = cx.file_write(&format!("{}{}", self.root_path, path), &bytes);
             //cx.http_send("POST", path, "192.168.0.20", "2001", &string);
-            
+            text_buffer.pending_saves.push(TextBufferSave {
+                write_id: write_id,
+                prev_save_point: text_buffer.undo_tree.save_point,
+                prev_disk_text: text_buffer.disk_text.clone()
+            });
+            text_buffer.save_buffer();
+            text_buffer.disk_text = text_buffer.get_as_lf_string();
+            self.undo_histories.insert(path.to_string(), TextUndoHistory {
+                content_hash: text_content_hash(&string),
+                undo_tree: text_buffer.undo_tree.trimmed(TEXT_UNDO_STORED_NODES)
+            });
+            self.save_undo_histories_later(cx);
+            // the folded rows are only good for the text they were saved with
+            self.save_fold_state(cx, path);
         }
     }
     
     pub fn handle_file_read(&mut self, cx: &mut Cx, fr: &FileReadEvent) -> bool {
-        for (_path, text_buffer) in &mut self.storage {
-            if let Some(utf8_data) = text_buffer.load_file_read.resolve_utf8(fr) {
-                if let Ok(utf8_data) = utf8_data {
-                    // TODO HANDLE ERROR CASE
-                    text_buffer.is_crlf = !utf8_data.find("\r\n").is_none();
-                    text_buffer.lines = TextBuffer::split_string_to_lines(&utf8_data.to_string());
-                    cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_LOADED);
+        if let Some(utf8_data) = self.undo_file_read.resolve_utf8(fr) {
+            if let Ok(utf8_data) = utf8_data {
+                if let Ok(undo_histories) = serde_json::from_str(&utf8_data) {
+                    self.undo_histories = undo_histories;
+                    // buffers that loaded before the history did can still pick it up
+                    for (path, text_buffer) in &mut self.storage {
+                        if !text_buffer.load_file_read.is_pending() && !text_buffer.undo_tree.can_undo() && !text_buffer.undo_tree.can_redo() {
+                            let content_hash = text_content_hash(&text_buffer.get_as_string());
+                            text_buffer.restore_undo_history(self.undo_histories.get(path), content_hash);
+                        }
+                    }
+                }
+            }
+            return true
+        }
+        if let Some(utf8_data) = self.fold_file_read.resolve_utf8(fr) {
+            if let Ok(utf8_data) = utf8_data {
+                if let Ok(fold_states) = serde_json::from_str(&utf8_data) {
+                    self.fold_states = fold_states;
+                    for (path, text_buffer) in &mut self.storage {
+                        if !text_buffer.load_file_read.is_pending() && text_buffer.folds.folded.len() == 0 {
+                            let content_hash = text_content_hash(&text_buffer.get_as_string());
+                            text_buffer.folds.restore_state(self.fold_states.get(path), content_hash);
+                        }
+                    }
+                }
+            }
+            return true
+        }
+        for (path, text_buffer) in &mut self.storage {
+            if let Some(data) = text_buffer.load_file_read.resolve(fr) {
+                match data {
+                    Ok(data) => {
+                        // messages that came in while loading were made against this same text
+                        let messages_fresh = text_buffer.messages.mutation_id == text_buffer.mutation_id;
+                        text_buffer.load_bytes(data);
+                        if messages_fresh {
+                            text_buffer.messages.mutation_id = text_buffer.mutation_id;
+                        }
+                        text_buffer.undo_tree = TextUndoTree::default();
+                        let content_hash = text_content_hash(&text_buffer.get_as_string());
+                        text_buffer.restore_undo_history(self.undo_histories.get(path), content_hash);
+                        text_buffer.folds.restore_state(self.fold_states.get(path), content_hash);
+                        text_buffer.disk_text = text_buffer.get_as_lf_string();
+                        if let Some(git_loader) = &self.git_loader {
+                            text_buffer.git.load(git_loader, path);
+                        }
+                        if text_buffer.messages.file_error.is_some() {
+                            text_buffer.messages.set_file_error(None, text_buffer.mutation_id);
+                            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                        }
+                    },
+                    Err(err) => {
+                        // leave the buffer empty but tell the user why
+                        text_buffer.messages.set_file_error(Some(format!("Could not load file: {}", err)), text_buffer.mutation_id);
+                        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                    }
+                }
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_LOADED);
+                return true
+            }
+            if let Some(data) = text_buffer.reload_file_read.resolve(fr) {
+                match data {
+                    Err(err) => { // deleted or unreadable, keep what we have so it can be saved again
+                        text_buffer.messages.set_file_error(Some(format!("Could not reload file: {}", err)), text_buffer.mutation_id);
+                        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                    },
+                    Ok(data) => {
+                        if text_buffer.messages.file_error.is_some() {
+                            text_buffer.messages.set_file_error(None, text_buffer.mutation_id);
+                            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                        }
+                        let decoded = decode_text(data);
+                        let change = text_buffer.apply_disk_text(&decoded.text);
+                        // a checkout or commit changes the file, so HEAD might have too
+                        if let Some(git_loader) = &self.git_loader {
+                            text_buffer.git.load(git_loader, path);
+                        }
+                        // a clean buffer matches the file row for row so it can take over how its stored
+                        if !text_buffer.is_dirty() {
+                            text_buffer.encoding = decoded.encoding;
+                            text_buffer.line_endings = decoded.line_endings;
+                        }
+                        match change {
+                            TextDiskChange::Unchanged => (),
+                            TextDiskChange::Conflicted(conflicts) => {
+                                // point at the conflicts the same way compiler errors are shown
+                                let mut cursors = Vec::new();
+                                for (start_row, end_row) in conflicts {
+                                    let start = text_buffer.text_pos_to_offset(TextPos {row: start_row, col: 0});
+                                    let end = text_buffer.text_pos_to_offset(TextPos {row: end_row - 1, col: 0});
+                                    cursors.push(TextCursor {head: end, tail: start, max: 0});
+                                }
+                                let messages = cursors.into_iter().map( | cursor | (cursor, TextBufferMessage {
+                                    level: TextBufferMessageLevel::Error,
+                                    body: "Conflicts with changes made on disk".to_string(),
+                                    detail: None,
+                                    fixes: Vec::new(),
+                                    source: TextBufferMessageSource::Merge
+                                })).collect();
+                                text_buffer.messages.set_source(TextBufferMessageSource::Merge, text_buffer.mutation_id, messages);
+                                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_RELOADED);
+                            },
+                            _ => {
+                                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_RELOADED);
+                            }
+                        }
+                    }
                 }
                 return true
             }
@@ -116,9 +766,44 @@ impl TextBuffers {
         return false;
     }
     
+    pub fn handle_file_write(&mut self, cx: &mut Cx, fw: &FileWriteEvent) -> bool {
+        for (_, text_buffer) in &mut self.storage {
+            if let Some(index) = text_buffer.pending_saves.iter().position( | save | save.write_id == fw.write_id) {
+                let save = text_buffer.pending_saves.remove(index);
+                match &fw.result {
+                    Ok(()) => if text_buffer.messages.file_error.is_some() {
+                        text_buffer.messages.set_file_error(None, text_buffer.mutation_id);
+                    },
+                    Err(err) => {
+                        text_buffer.undo_tree.save_point = save.prev_save_point;
+                        text_buffer.disk_text = save.prev_disk_text;
+                        text_buffer.messages.set_file_error(Some(err.message.clone()), text_buffer.mutation_id);
+                    }
+                }
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+                return true
+            }
+        }
+        false
+    }
+    
+    pub fn handle_file_changed(&mut self, cx: &mut Cx, fc: &FileChangedEvent) -> bool {
+        if !fc.path.starts_with(&self.root_path) {
+            return false
+        }
+        if let Some(text_buffer) = self.storage.get_mut(&fc.path[self.root_path.len()..]) {
+            // our own saves come by here too, the reload ignores a file that matches disk_text
+            if !text_buffer.load_file_read.is_pending() {
+                text_buffer.reload_file_read = cx.file_read(&fc.path);
+            }
+            return true
+        }
+        false
+    }
+    
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TextPos {
     pub row: usize,
     pub col: usize
@@ -136,7 +821,7 @@ impl TextPos {
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum TextUndoGrouping {
     Space,
     Newline,
@@ -173,21 +858,41 @@ impl TextUndoGrouping {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TextUndo {
     pub ops: Vec<TextOp>,
     pub grouping: TextUndoGrouping,
     pub cursors: TextCursorSet
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TextOp {
     pub start: usize,
     pub len: usize,
     pub lines: Vec<Vec<char>>,
 }
 
-fn calc_char_count(lines: &Vec<Vec<char>>) -> usize {
+// where path ends up when from is renamed to to, None if its not from or inside it
+pub fn renamed_path(path: &str, from: &str, to: &str) -> Option<String> {
+    if path == from {
+        return Some(to.to_string())
+    }
+    if path.starts_with(from) && path[from.len()..].starts_with('/') {
+        return Some(format!("{}{}", to, &path[from.len()..]))
+    }
+    None
+}
+
+fn rename_keys<T>(map: &mut HashMap<String, T>, from: &str, to: &str) -> bool {
+    let paths: Vec<String> = map.keys().filter( | path | renamed_path(path, from, to).is_some()).cloned().collect();
+    for path in &paths {
+        let value = map.remove(path).unwrap();
+        map.insert(renamed_path(path, from, to).unwrap(), value);
+    }
+    paths.len() > 0
+}
+
+fn lines_char_count(lines: &Vec<Vec<char>>) -> usize {
     let mut char_count = 0;
     for line in lines {
         char_count += line.len()
@@ -200,44 +905,62 @@ fn calc_char_count(lines: &Vec<Vec<char>>) -> usize {
 impl TextBuffer {
     
     pub fn offset_to_text_pos(&self, char_offset: usize) -> TextPos {
-        let mut char_count = 0;
-        for (row, line) in self.lines.iter().enumerate() {
-            let next_char_count = char_count + line.len() + 1;
-            if next_char_count > char_offset {
-                return TextPos {row: row, col: char_offset - char_count}
-            }
-            char_count = next_char_count;
+        if let Some((row, col)) = self.lines.offset_to_row_col(char_offset) {
+            return TextPos {row: row, col: col}
         }
         TextPos {row: self.lines.len().max(1) - 1, col: 0}
     }
     
     pub fn offset_to_text_pos_next(&self, query_off: usize, old_pos: TextPos, old_off: usize) -> TextPos {
-        let mut row = old_pos.row;
-        let mut iter_off = old_off - old_pos.col;
-        while row < self.lines.len() {
-            let line = &self.lines[row];
-            let next_off = iter_off + line.len() + 1;
-            if next_off > query_off {
-                return TextPos {row: row, col: query_off - iter_off}
+        // walk forward from the previous position if its on the same line, otherwise do a rope lookup
+        if query_off >= old_off && old_pos.row < self.lines.len() {
+            let line_start = old_off - old_pos.col;
+            if query_off - line_start <= self.lines.line_len(old_pos.row) {
+                return TextPos {row: old_pos.row, col: query_off - line_start}
             }
-            iter_off = next_off;
-            row += 1;
         }
-        TextPos {row: self.lines.len().max(1) - 1, col: 0}
+        self.offset_to_text_pos(query_off)
     }
     
     pub fn text_pos_to_offset(&self, pos: TextPos) -> usize {
-        let mut char_count = 0;
         if pos.row >= self.lines.len() {
-            return self.calc_char_count()
+            return self.lines.char_count()
+        }
+        self.lines.row_offset(pos.row) + self.lines.line_len(pos.row).min(pos.col)
+    }
+    
+    // the fixes of the messages that touch offset, nothing if the messages are stale
+    pub fn quick_fixes_at(&self, offset: usize) -> Vec<TextQuickFix> {
+        let mut fixes = Vec::new();
+        if self.messages.mutation_id != self.mutation_id {
+            return fixes
         }
-        for (ln_row, line) in self.lines.iter().enumerate() {
-            if ln_row == pos.row {
-                return char_count + (line.len()).min(pos.col);
+        for (index, cursor) in self.messages.cursors.iter().enumerate() {
+            let (start, end) = cursor.order();
+            if offset >= start && offset <= end {
+                for fix in &self.messages.bodies[index].fixes {
+                    if !fixes.contains(fix) {
+                        fixes.push(fix.clone());
+                    }
+                }
+            }
+        }
+        fixes
+    }
+    
+    pub fn machine_applicable_fixes(&self) -> Vec<TextQuickFix> {
+        let mut fixes = Vec::new();
+        if self.messages.mutation_id != self.mutation_id {
+            return fixes
+        }
+        for body in &self.messages.bodies {
+            for fix in &body.fixes {
+                if fix.machine_applicable && !fixes.contains(fix) {
+                    fixes.push(fix.clone());
+                }
             }
-            char_count += line.len() + 1;
         }
-        0
+        fixes
     }
     
     pub fn get_nearest_line_range(&self, offset: usize) -> (usize, usize) {
@@ -353,8 +1076,8 @@ impl TextBuffer {
         return Some((offset - pos.col, line1_ws, line1.len()));
     }
     
-    pub fn calc_char_count(&self) -> usize {
-        calc_char_count(&self.lines)
+    pub fn lines_char_count(&self) -> usize {
+        self.lines.char_count()
     }
     
     pub fn get_line_count(&self) -> usize {
@@ -401,11 +1124,11 @@ impl TextBuffer {
                 ret.push(*ch);
             }
             if i != self.lines.len() - 1 {
-                if self.is_crlf{
+                if self.line_endings.is_crlf(i) {
                     ret.push('\r');
                     ret.push('\n');
                 }
-                else{
+                else {
                     ret.push('\n');
                 }
             }
@@ -413,96 +1136,111 @@ impl TextBuffer {
         return ret
     }
     
-    pub fn replace_line(&mut self, row: usize, start_col: usize, len: usize, rep_line: Vec<char>) -> Vec<char> {
+    pub fn get_as_bytes(&self) -> Result<Vec<u8>, String> {
+        encode_text(&self.get_as_string(), self.encoding)
+    }
+    
+    pub fn load_bytes(&mut self, bytes: &[u8]) {
+        self.load_decoded(decode_text(bytes));
+    }
+    
+    // what the tests start from, text loaded the way a file would be
+    #[cfg(test)]
+    pub fn from_test_str(text: &str) -> TextBuffer {
+        let mut text_buffer = TextBuffer::default();
+        text_buffer.load_bytes(text.as_bytes());
+        text_buffer
+    }
+    
+    fn load_decoded(&mut self, decoded: TextDecoded) {
+        // decoding took the \r of the line endings off already, the ones left are part of the text
+        self.lines = TextRope::from_lines(decoded.text.split('\n').map( | line | line.chars().collect()).collect());
+        self.encoding = decoded.encoding;
+        self.line_endings = decoded.line_endings;
         self.mutation_id += 1;
-        self.lines[row].splice(start_col..(start_col + len), rep_line).collect()
+        self.reset_token_chunks();
+        self.folds = TextFolds::default();
     }
     
-    pub fn copy_line(&self, row: usize, start_col: usize, len: usize) -> Vec<char> {
-        let line = &self.lines[row];
-        if start_col >= line.len() {
-            return vec![]
+    // explicit conversions, these mark the buffer dirty to get it saved. Between text encodings
+    // the text stays as it is and has to fit the new one, to or from hex the bytes get read again
+    pub fn convert_encoding(&mut self, encoding: TextEncoding) -> Result<(), String> {
+        if self.encoding == encoding {
+            return Ok(())
         }
-        if start_col + len > line.len() {
-            self.lines[row][start_col..line.len()].iter().cloned().collect()
+        if self.encoding == TextEncoding::Hex || encoding == TextEncoding::Hex {
+            let decoded = decode_text_as(&self.get_as_bytes() ?, encoding) ?;
+            self.load_decoded(decoded);
+            // the edits so far were to other text, undoing them would garble this one
+            self.undo_tree = TextUndoTree::default();
         }
         else {
-            self.lines[row][start_col..(start_col + len)].iter().cloned().collect()
+            encode_text(&self.get_as_string(), encoding) ?;
+            self.encoding = encoding;
+        }
+        self.undo_tree.save_point = std::usize::MAX;
+        Ok(())
+    }
+    
+    pub fn convert_line_endings(&mut self, crlf: bool) {
+        let line_endings = if crlf {TextLineEndings::CrLf} else {TextLineEndings::Lf};
+        if self.line_endings != line_endings && self.encoding != TextEncoding::Hex {
+            self.line_endings = line_endings;
+            self.undo_tree.save_point = std::usize::MAX;
         }
     }
     
+    // the text the way offsets see it, with a single \n between lines
+    pub fn get_as_lf_string(&self) -> String {
+        let mut ret = String::with_capacity(self.lines.char_count());
+        for (i, line) in self.lines.iter().enumerate() {
+            if i != 0 {
+                ret.push('\n');
+            }
+            ret.extend(line.iter());
+        }
+        ret
+    }
+    
+    pub fn replace_line(&mut self, row: usize, start_col: usize, len: usize, rep_line: Vec<char>) -> Vec<char> {
+        self.mutation_id += 1;
+        self.mark_token_damage(row, row, row);
+        self.lines.splice_line(row, start_col..(start_col + len), rep_line)
+    }
+    
+    pub fn copy_line(&self, row: usize, start_col: usize, len: usize) -> Vec<char> {
+        let line_len = self.lines.line_len(row);
+        if start_col >= line_len {
+            return vec![]
+        }
+        self.lines.line_slice(row, start_col..line_len.min(start_col + len))
+    }
+    
     pub fn replace_range(&mut self, start: usize, len: usize, mut rep_lines: Vec<Vec<char>>) -> Vec<Vec<char>> {
         self.mutation_id += 1;
         let start_pos = self.offset_to_text_pos(start);
         let end_pos = self.offset_to_text_pos_next(start + len, start_pos, start);
+        self.mark_token_damage(start_pos.row, end_pos.row, start_pos.row + rep_lines.len() - 1);
+        self.folds.shift_rows(start_pos.row, end_pos.row, start_pos.row + rep_lines.len() - 1);
         
         if start_pos.row == end_pos.row && rep_lines.len() == 1 { // replace in one line
             let rep_line_zero = rep_lines.drain(0..1).next().unwrap();
-            let line = self.lines[start_pos.row].splice(start_pos.col..end_pos.col, rep_line_zero).collect();
+            let line = self.lines.splice_line(start_pos.row, start_pos.col..end_pos.col, rep_line_zero);
             return vec![line];
         }
-        else {
-            if rep_lines.len() == 1 { // we are replacing multiple lines with one line
-                // drain first line
-                let rep_line_zero = rep_lines.drain(0..1).next().unwrap();
-                
-                // replace it in the first
-                let first = self.lines[start_pos.row].splice(start_pos.col.., rep_line_zero).collect();
-                
-                // collect the middle ones
-                let mut middle: Vec<Vec<char>> = self.lines.drain((start_pos.row + 1)..(end_pos.row)).collect();
-                
-                // cut out the last bit
-                let last: Vec<char> = self.lines[start_pos.row + 1].drain(0..end_pos.col).collect();
-                
-                // last line bit
-                let mut last_line = self.lines.drain((start_pos.row + 1)..(start_pos.row + 2)).next().unwrap();
-                
-                // merge start_row+1 into start_row
-                self.lines[start_pos.row].append(&mut last_line);
-                
-                // concat it all together
-                middle.insert(0, first);
-                middle.push(last);
-                
-                return middle
-            }
-            else if start_pos.row == end_pos.row { // replacing single line with multiple lines
-                let mut last_bit: Vec<char> = self.lines[start_pos.row].drain(end_pos.col..).collect();
-                // but we have co drain end_col..
-                
-                // replaced first line
-                let rep_lines_len = rep_lines.len();
-                let rep_line_first: Vec<char> = rep_lines.drain(0..1).next().unwrap();
-                let line = self.lines[start_pos.row].splice(start_pos.col.., rep_line_first).collect();
-                
-                // splice in middle rest
-                let rep_line_mid = rep_lines.drain(0..(rep_lines.len()));
-                self.lines.splice((start_pos.row + 1)..(start_pos.row + 1), rep_line_mid);
-                
-                // append last bit
-                self.lines[start_pos.row + rep_lines_len - 1].append(&mut last_bit);
-                
-                return vec![line];
-            }
-            else { // replaceing multiple lines with multiple lines
-                // drain and replace last line
-                let rep_line_last = rep_lines.drain((rep_lines.len() - 1)..(rep_lines.len())).next().unwrap();
-                let last = self.lines[end_pos.row].splice(..end_pos.col, rep_line_last).collect();
-                
-                // swap out middle lines and drain them
-                let rep_line_mid = rep_lines.drain(1..(rep_lines.len()));
-                let mut middle: Vec<Vec<char>> = self.lines.splice((start_pos.row + 1)..end_pos.row, rep_line_mid).collect();
-                
-                // drain and replace first line
-                let rep_line_zero = rep_lines.drain(0..1).next().unwrap();
-                let first = self.lines[start_pos.row].splice(start_pos.col.., rep_line_zero).collect();
-                
-                // concat it all together
-                middle.insert(0, first);
-                middle.push(last);
-                return middle
-            }
+        else { // stitch the untouched head and tail onto the replacement and swap out the rows
+            let head: Vec<char> = self.lines.line_slice(start_pos.row, 0..start_pos.col);
+            let mut tail: Vec<char> = self.lines.line_slice(end_pos.row, end_pos.col..self.lines.line_len(end_pos.row));
+            rep_lines[0].splice(0..0, head);
+            rep_lines.last_mut().unwrap().append(&mut tail);
+            
+            self.line_endings.splice_rows(start_pos.row, end_pos.row, rep_lines.len());
+            let mut removed = self.lines.splice(start_pos.row..(end_pos.row + 1), rep_lines);
+            
+            // cut the removed lines back to the replaced range
+            removed.last_mut().unwrap().truncate(end_pos.col);
+            removed[0].drain(0..start_pos.col);
+            return removed
         }
     }
     
@@ -510,7 +1248,7 @@ impl TextBuffer {
         let start = self.text_pos_to_offset(TextPos {row: start_row, col: 0});
         let end = self.text_pos_to_offset(TextPos {row: end_row, col: 0});
         let end_mark = if end_row >= self.lines.len() {0}else {1};
-        let rep_lines_chars = calc_char_count(&rep_lines);
+        let rep_lines_chars = lines_char_count(&rep_lines);
         let lines = self.replace_range(start, end - start - end_mark, rep_lines);
         TextOp {
             start: start,
@@ -520,17 +1258,16 @@ impl TextBuffer {
     }
     
     pub fn split_string_to_lines(string: &str) -> Vec<Vec<char>> {
-        if !string.find("\r\n").is_none(){
-            return string.split("\r\n").map( | s | s.chars().collect()).collect()
-        }
-        else{
-            return string.split("\n").map( | s | s.chars().collect()).collect()
-        }
+        // takes \r\n, \n or a mix of both, a \r with no \n after it stays
+        let pieces: Vec<&str> = string.split('\n').collect();
+        pieces.iter().enumerate().map( | (row, s) | {
+            if row + 1 < pieces.len() {s.strip_suffix('\r').unwrap_or(s)} else {s}.chars().collect()
+        }).collect()
     }
     
     pub fn replace_lines_with_string(&mut self, start: usize, len: usize, string: &str) -> TextOp {
         let rep_lines = Self::split_string_to_lines(string);
-        let rep_lines_chars = calc_char_count(&rep_lines);
+        let rep_lines_chars = lines_char_count(&rep_lines);
         let lines = self.replace_range(start, len, rep_lines);
         TextOp {
             start: start,
@@ -551,7 +1288,7 @@ impl TextBuffer {
     }
     
     pub fn replace_with_textop(&mut self, text_op: TextOp) -> TextOp {
-        let rep_lines_chars = calc_char_count(&text_op.lines);
+        let rep_lines_chars = lines_char_count(&text_op.lines);
         let lines = self.replace_range(text_op.start, text_op.len, text_op.lines);
         TextOp {
             start: text_op.start,
@@ -560,8 +1297,115 @@ impl TextBuffer {
         }
     }
     
+    pub fn set_lsp_response(&mut self, lsp_response: LspResponse) {
+        self.lsp_response = Some(lsp_response);
+        self.lsp_response_id += 1;
+    }
+    
     pub fn save_buffer(&mut self) {
-        //let out = self.lines.join("\n");
+        self.undo_tree.mark_saved();
+    }
+    
+    // true if the text changed since the last save_buffer, undoing back to it makes it clean again
+    pub fn is_dirty(&self) -> bool {
+        self.undo_tree.is_dirty()
+    }
+    
+    pub fn push_undo(&mut self, text_undo: TextUndo) {
+        self.undo_tree.push(text_undo);
+    }
+    
+    // puts the HEAD version of the change at row back, as one undo step. returns where it starts
+    pub fn revert_git_hunk(&mut self, row: usize, cursors: &TextCursorSet) -> Option<usize> {
+        // the rows can lag behind the last edits
+        self.git.update(&self.lines, self.mutation_id);
+        let (start_row, end_row, head_lines) = self.git.revert_lines(row) ?;
+        let total = self.lines.char_count();
+        let (start, end, string) = if end_row < self.lines.len() {
+            let string: String = head_lines.iter().map( | line | format!("{}\n", line)).collect();
+            (self.text_pos_to_offset(TextPos {row: start_row, col: 0}), self.text_pos_to_offset(TextPos {row: end_row, col: 0}), string)
+        }
+        else if start_row > 0 { // the change runs to the end, take the newline in front of it instead
+            let string: String = head_lines.iter().map( | line | format!("\n{}", line)).collect();
+            (self.text_pos_to_offset(TextPos {row: start_row, col: 0}) - 1, total, string)
+        }
+        else {
+            (0, total, head_lines.join("\n"))
+        };
+        let op = self.replace_lines_with_string(start, end - start, &string);
+        self.push_undo(TextUndo {
+            ops: vec![op],
+            grouping: TextUndoGrouping::Other,
+            cursors: cursors.clone()
+        });
+        Some(self.text_pos_to_offset(TextPos {row: start_row.min(self.lines.len() - 1), col: 0}))
+    }
+    
+    // The file changed on disk. Without edits of our own we just take the new text, otherwise
+    // it gets merged with our edits against disk_text. Either way its one undo step.
+    pub fn apply_disk_text(&mut self, new_disk_text: &str) -> TextDiskChange {
+        let new_disk_text = new_disk_text.replace("\r\n", "\n");
+        if new_disk_text == self.disk_text {
+            return TextDiskChange::Unchanged
+        }
+        let was_dirty = self.is_dirty();
+        let current = self.get_as_lf_string();
+        let (target, conflicts) = if was_dirty {
+            let merge = merge_three_way(&self.disk_text, &current, &new_disk_text);
+            (merge.text, merge.conflicts)
+        }
+        else {
+            (new_disk_text.clone(), Vec::new())
+        };
+        self.disk_text = new_disk_text;
+        
+        // only replace the part that actually differs so undo and the tokenizer stay cheap
+        let current: Vec<char> = current.chars().collect();
+        let target: Vec<char> = target.chars().collect();
+        let mut head = 0;
+        while head < current.len() && head < target.len() && current[head] == target[head] {
+            head += 1;
+        }
+        let mut tail = 0;
+        while tail < current.len() - head && tail < target.len() - head && current[current.len() - 1 - tail] == target[target.len() - 1 - tail] {
+            tail += 1;
+        }
+        if head != current.len() || head != target.len() {
+            let rep: String = target[head..target.len() - tail].iter().collect();
+            let mut cursors = TextCursorSet::new();
+            cursors.set[0] = TextCursor {head: head, tail: head, max: 0};
+            let op = self.replace_with_textop(TextOp {
+                start: head,
+                len: current.len() - tail - head,
+                lines: TextBuffer::split_string_to_lines(&rep)
+            });
+            self.push_undo(TextUndo {
+                ops: vec![op],
+                grouping: TextUndoGrouping::Other,
+                cursors: cursors
+            });
+        }
+        if target.iter().cloned().eq(self.disk_text.chars()) {
+            self.undo_tree.mark_saved();
+        }
+        if !was_dirty {
+            TextDiskChange::Reloaded
+        }
+        else if conflicts.len() > 0 {
+            TextDiskChange::Conflicted(conflicts)
+        }
+        else {
+            TextDiskChange::Merged
+        }
+    }
+    
+    // only take a stored history if it was saved for exactly this text
+    pub fn restore_undo_history(&mut self, history: Option<&TextUndoHistory>, content_hash: u64) {
+        if let Some(history) = history {
+            if history.content_hash == content_hash && history.undo_tree.current == history.undo_tree.save_point {
+                self.undo_tree = history.undo_tree.clone();
+            }
+        }
     }
     
     pub fn undoredo(&mut self, mut text_undo: TextUndo, cursor_set: &mut TextCursorSet) -> TextUndo {
@@ -583,25 +1427,21 @@ impl TextBuffer {
     
     // todo make more reuse in these functions
     pub fn undo(&mut self, grouped: bool, cursor_set: &mut TextCursorSet) {
-        
-        if self.undo_stack.len() == 0 {
-            return;
-        }
         let mut last_grouping = TextUndoGrouping::Other;
         let mut first = true;
-        while self.undo_stack.len() > 0 {
+        while self.undo_tree.can_undo() {
             if !first && !grouped {
                 break
             }
-            if self.undo_stack.last().unwrap().grouping != last_grouping && !first {
+            if self.undo_tree.undo_grouping() != Some(&last_grouping) && !first {
                 break
             }
             first = false;
-            let text_undo = self.undo_stack.pop().unwrap();
+            let text_undo = self.undo_tree.begin_undo().unwrap();
             let wants_grouping = text_undo.grouping.wants_grouping();
             last_grouping = text_undo.grouping.clone();
             let text_redo = self.undoredo(text_undo, cursor_set);
-            self.redo_stack.push(text_redo);
+            self.undo_tree.finish_undo(text_redo);
             if !wants_grouping {
                 break;
             }
@@ -609,23 +1449,20 @@ impl TextBuffer {
     }
     
     pub fn redo(&mut self, grouped: bool, cursor_set: &mut TextCursorSet) {
-        if self.redo_stack.len() == 0 {
-            return;
-        }
         let mut last_grouping = TextUndoGrouping::Other;
         let mut first = true;
-        while self.redo_stack.len() > 0 {
+        while self.undo_tree.can_redo() {
             if !first {
-                if self.redo_stack.last().unwrap().grouping != last_grouping || !grouped {
+                if self.undo_tree.redo_grouping() != Some(&last_grouping) || !grouped {
                     break
                 }
             }
             first = false;
-            let text_redo = self.redo_stack.pop().unwrap();
+            let text_redo = self.undo_tree.begin_redo().unwrap();
             let wants_grouping = text_redo.grouping.wants_grouping();
             last_grouping = text_redo.grouping.clone();
             let text_undo = self.undoredo(text_redo, cursor_set);
-            self.undo_stack.push(text_undo);
+            self.undo_tree.finish_redo(text_undo);
             if !wants_grouping {
                 break;
             }
@@ -714,27 +1551,57 @@ pub struct TokenizerState<'a> {
     pub prev: char,
     pub cur: char,
     pub next: char,
-    pub lines: &'a Vec<Vec<char>>,
+    pub lines: &'a TextRope,
     pub line_counter: usize,
     pub offset: usize,
-    iter: std::slice::Iter<'a, char>
+    // the last token that isnt whitespace or a comment in front of the row lexing started at
+    pub last_token: TokenType,
+    iter: LineChars<'a>
 }
 
 impl<'a> TokenizerState<'a> {
-    pub fn new(lines: &'a Vec<Vec<char>>) -> Self {
+    pub fn new(lines: &'a TextRope) -> Self {
         let mut ret = Self {
             lines: lines,
             line_counter: 0,
             offset: 0,
+            last_token: TokenType::Unexpected,
             prev: '\0',
             cur: '\0',
             next: '\0',
-            iter: lines[0].iter()
+            iter: lines.line_chars(0)
         };
         ret.advance_with_cur();
         ret
     }
     
+    // state as the tokenizer sees it right after the newline token in front of row
+    pub fn new_at_row(lines: &'a TextRope, row: usize) -> Self {
+        if row == 0 {
+            return Self::new(lines)
+        }
+        let mut ret = Self {
+            lines: lines,
+            line_counter: row,
+            offset: lines.row_offset(row),
+            last_token: TokenType::Unexpected,
+            prev: '\0',
+            cur: '\n',
+            next: '\0',
+            iter: lines.line_chars(row)
+        };
+        ret.advance();
+        ret
+    }
+    
+    // token_chunks only holds what got lexed since the row lexing started at
+    pub fn scan_last_token(&self, token_chunks: &[TokenChunk]) -> TokenType {
+        match token_chunks.iter().rev().find( | token_chunk | !token_chunk.token_type.should_ignore()) {
+            Some(token_chunk) => token_chunk.token_type,
+            None => self.last_token
+        }
+    }
+    
     pub fn advance(&mut self) {
         if let Some(next) = self.iter.next() {
             self.next = *next;
@@ -749,7 +1616,7 @@ impl<'a> TokenizerState<'a> {
         if self.line_counter < self.lines.len() - 1 {
             self.line_counter += 1;
             self.offset += 1;
-            self.iter = self.lines[self.line_counter].iter();
+            self.iter = self.lines.line_chars(self.line_counter);
             self.next = '\n'
         }
         else {
@@ -857,7 +1724,15 @@ impl TokenType {
     }
 }
 
-#[derive(Clone)]
+// Tokenizers carry some state across lines (comment nesting and such), packed in a u64
+// so the textbuffer can checkpoint it at every line start.
+pub trait Tokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType;
+    fn line_state(&self) -> u64;
+    fn set_line_state(&mut self, line_state: u64);
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct TokenChunk {
     pub token_type: TokenType,
     pub offset: usize,
@@ -880,33 +1755,36 @@ impl TokenChunk {
         return TokenType::Unexpected
     }
     
-    pub fn push_with_pairing(token_chunks: &mut Vec<TokenChunk>, pair_stack: &mut Vec<usize>, next: char, offset: usize, offset2: usize, token_type: TokenType) {
-        let pair_token = if token_type == TokenType::ParenOpen {
-            pair_stack.push(token_chunks.len());
-            token_chunks.len()
-        }
-        else if token_type == TokenType::ParenClose {
-            if pair_stack.len() > 0 {
-                let other = pair_stack.pop().unwrap();
-                token_chunks[other].pair_token = token_chunks.len();
-                other
+    // nothing but whitespace since the last newline
+    pub fn is_line_start(token_chunks: &Vec<TokenChunk>) -> bool {
+        for token_chunk in token_chunks.iter().rev() {
+            match token_chunk.token_type {
+                TokenType::Whitespace => (),
+                TokenType::Newline => return true,
+                _ => return false
             }
-            else {
-                token_chunks.len()
+        }
+        true
+    }
+    
+    // the parens still open in front of token index before, outermost first. Walks back over
+    // the groups that close in front of it, a paren closing nothing means none were open there
+    pub fn open_pairs(token_chunks: &[TokenChunk], before: usize, depth: usize) -> Vec<usize> {
+        let mut open_pairs = Vec::new();
+        let mut index = before;
+        while open_pairs.len() < depth && index > 0 {
+            index -= 1;
+            let token_chunk = &token_chunks[index];
+            match token_chunk.token_type {
+                TokenType::ParenOpen => open_pairs.push(index),
+                TokenType::ParenClose if token_chunk.pair_token < index => index = token_chunk.pair_token,
+                TokenType::ParenClose => break,
+                _ => ()
             }
         }
-        else {
-            token_chunks.len()
-        };
-        token_chunks.push(TokenChunk {
-            offset: offset,
-            pair_token: pair_token,
-            len: offset2 - offset,
-            next: next,
-            token_type: token_type.clone()
-        })
+        open_pairs.reverse();
+        open_pairs
     }
-    
 }
 
 pub struct TokenParserItem {
@@ -1047,3 +1925,457 @@ impl FormatOutput {
     }
     
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rusteditor::RustTokenizer;
+    use crate::jseditor::JSTokenizer;
+    
+    const RUST_SOURCE: &str = "use std::fmt;\n\n/* block /* nested */ comment\nstill comment */\nfn main() {\n    let x = \"str(\";\n    // line comment {\n    for i in 0..10 {\n        println!(\"{}\", i);\n    }\n}\n\nimpl Foo {\n    fn bar(&self) -> u32 { 'a' as u32 }\n}\n";
+    const JS_SOURCE: &str = "function f(a, b) {\n    var r = /ab+c/g;\n    /* multi\n    line */\n    if (a) {\n        return b / 2;\n    }\n    // done )\n}\nvar s = 'x(' + \"y{\";\n";
+    const INSERTS: [&str; 14] = ["{", "}", "(", ")", "/*", "*/", "//", "\n", "\"", "x", "for ", " / ", "\n}\n", "/* a\nb */"];
+    
+    fn assert_same_as_full_lex<T: Tokenizer>(text_buffer: &TextBuffer, tokenizer: &mut T) {
+        let mut full = TextBuffer::from_test_str(&text_buffer.get_as_string());
+        full.update_token_chunks(tokenizer);
+        assert!(text_buffer.flat_text == full.flat_text);
+        assert_eq!(text_buffer.token_chunks, full.token_chunks);
+        assert_eq!(text_buffer.token_checkpoints, full.token_checkpoints);
+        assert_eq!(text_buffer.token_checkpoints.len(), text_buffer.lines.len());
+    }
+    
+    fn run_random_edits<T: Tokenizer, F: Fn() -> T>(source: &str, new_tokenizer: F) {
+        let mut text_buffer = TextBuffer::from_test_str(&source.repeat(20));
+        text_buffer.update_token_chunks(&mut new_tokenizer());
+        assert_same_as_full_lex(&text_buffer, &mut new_tokenizer());
+        
+        let mut seed = 12345u64;
+        let mut rand = | max: usize | {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % max.max(1)
+        };
+        for step in 0..500 {
+            // a few edits per tokenize, so damage from separate edits has to merge
+            for _ in 0..(1 + step % 3) {
+                let total = text_buffer.lines.char_count();
+                let start = rand(total + 1);
+                let len = if rand(3) == 0 {rand((total - start).min(40) + 1)} else {0};
+                let insert = if len > 0 && rand(2) == 0 {""} else {INSERTS[rand(INSERTS.len())]};
+                text_buffer.replace_lines_with_string(start, len, insert);
+            }
+            text_buffer.update_token_chunks(&mut new_tokenizer());
+            assert_same_as_full_lex(&text_buffer, &mut new_tokenizer());
+        }
+    }
+    
+    #[test]
+    fn incremental_rust_matches_full_lex() {
+        run_random_edits(RUST_SOURCE, RustTokenizer::new);
+    }
+    
+    #[test]
+    fn incremental_js_matches_full_lex() {
+        run_random_edits(JS_SOURCE, JSTokenizer::new);
+    }
+    
+    #[test]
+    fn edit_only_relexes_damaged_lines() {
+        let mut text_buffer = TextBuffer::from_test_str(&RUST_SOURCE.repeat(50));
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        let start = text_buffer.text_pos_to_offset(TextPos {row: 5, col: 12});
+        text_buffer.replace_lines_with_string(start, 0, "abc");
+        // checkpoints of untouched lines before the edit are left alone
+        let before = text_buffer.token_checkpoints[0..5].to_vec();
+        let mut tokenizer = RowCountingTokenizer {tokenizer: RustTokenizer::new(), rows: Vec::new()};
+        text_buffer.update_token_chunks(&mut tokenizer);
+        assert_eq!(before, text_buffer.token_checkpoints[0..5].to_vec());
+        // and the tokenizer never gets to see the lines after the edit
+        assert_eq!(tokenizer.rows, vec![5, 6]);
+        assert_same_as_full_lex(&text_buffer, &mut RustTokenizer::new());
+    }
+    
+    // remembers the rows it got asked to lex
+    struct RowCountingTokenizer {
+        tokenizer: RustTokenizer,
+        rows: Vec<usize>
+    }
+    
+    impl Tokenizer for RowCountingTokenizer {
+        fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+            if self.rows.last() != Some(&state.line_counter) {
+                self.rows.push(state.line_counter);
+            }
+            self.tokenizer.next_token(state, chunk, token_chunks)
+        }
+        fn line_state(&self) -> u64 {self.tokenizer.line_state()}
+        fn set_line_state(&mut self, line_state: u64) {self.tokenizer.set_line_state(line_state)}
+    }
+    
+    #[test]
+    fn offsets_round_trip_through_text_pos() {
+        // enough lines to span a few rope chunks
+        let mut text_buffer = TextBuffer::from_test_str(&RUST_SOURCE.repeat(60));
+        text_buffer.replace_lines_with_string(text_buffer.text_pos_to_offset(TextPos {row: 250, col: 3}), 3000, "a\nb");
+        let total = text_buffer.lines.char_count();
+        assert_eq!(total, text_buffer.get_as_string().chars().count());
+        for offset in 0..=total {
+            let pos = text_buffer.offset_to_text_pos(offset);
+            assert_eq!(text_buffer.text_pos_to_offset(pos), offset);
+        }
+        // past the end clamps
+        let last = TextPos {row: text_buffer.lines.len(), col: 0};
+        assert_eq!(text_buffer.text_pos_to_offset(last), total);
+    }
+    
+    #[test]
+    fn unterminated_comment_relexes_to_the_end() {
+        let mut text_buffer = TextBuffer::from_test_str(RUST_SOURCE);
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        text_buffer.replace_lines_with_string(0, 0, "/*");
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        assert_same_as_full_lex(&text_buffer, &mut RustTokenizer::new());
+        text_buffer.replace_lines_with_string(0, 2, "");
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        assert_same_as_full_lex(&text_buffer, &mut RustTokenizer::new());
+    }
+    
+    fn edit(text_buffer: &mut TextBuffer, cursors: &TextCursorSet, start: usize, len: usize, text: &str) {
+        let op = text_buffer.replace_lines_with_string(start, len, text);
+        text_buffer.push_undo(TextUndo {
+            ops: vec![op],
+            grouping: TextUndoGrouping::Other,
+            cursors: cursors.clone()
+        });
+    }
+    
+    #[test]
+    fn undo_tree_keeps_branches_and_save_point() {
+        let mut text_buffer = TextBuffer::from_test_str("abc");
+        let mut cursors = TextCursorSet::new();
+        edit(&mut text_buffer, &cursors, 3, 0, "d");
+        text_buffer.save_buffer();
+        edit(&mut text_buffer, &cursors, 4, 0, "e");
+        assert!(text_buffer.is_dirty());
+        
+        text_buffer.undo(false, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), "abcd");
+        assert!(!text_buffer.is_dirty());
+        
+        // a new edit after undo branches off instead of dropping "e"
+        edit(&mut text_buffer, &cursors, 0, 1, "x");
+        assert_eq!(text_buffer.get_as_string(), "xbcd");
+        text_buffer.undo(false, &mut cursors);
+        text_buffer.redo(false, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), "xbcd");
+        text_buffer.undo(false, &mut cursors);
+        let branches = text_buffer.undo_tree.redo_branches();
+        assert_eq!(branches.len(), 2);
+        assert!(text_buffer.undo_tree.select_redo_branch(branches[0]));
+        text_buffer.redo(false, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), "abcde");
+        
+        // the history survives a roundtrip through json, but only for the same text
+        text_buffer.undo(false, &mut cursors);
+        let history = TextUndoHistory {
+            content_hash: text_content_hash(&text_buffer.get_as_string()),
+            undo_tree: text_buffer.undo_tree.clone()
+        };
+        let history: TextUndoHistory = serde_json::from_str(&serde_json::to_string(&history).unwrap()).unwrap();
+        let mut reloaded = TextBuffer::from_test_str("abcd");
+        reloaded.restore_undo_history(Some(&history), text_content_hash("abcd"));
+        reloaded.undo(false, &mut cursors);
+        assert_eq!(reloaded.get_as_string(), "abc");
+        let mut changed = TextBuffer::from_test_str("abcz");
+        changed.restore_undo_history(Some(&history), text_content_hash("abcz"));
+        assert!(!changed.undo_tree.can_undo());
+    }
+    
+    #[test]
+    fn stored_undo_tree_is_capped() {
+        let mut text_buffer = TextBuffer::from_test_str("");
+        let mut cursors = TextCursorSet::new();
+        for (index, chr) in "abcdef".chars().enumerate() {
+            edit(&mut text_buffer, &cursors, index, 0, &chr.to_string());
+        }
+        text_buffer.undo(false, &mut cursors);
+        text_buffer.undo(false, &mut cursors);
+        text_buffer.save_buffer();
+        // a side branch off "abc" that doesnt fit
+        text_buffer.undo(false, &mut cursors);
+        edit(&mut text_buffer, &cursors, 3, 0, "x");
+        text_buffer.undo(false, &mut cursors);
+        assert!(text_buffer.undo_tree.select_redo_branch(4));
+        text_buffer.redo(false, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), "abcd");
+        
+        // the oldest edits go first, "a" is where undo stops now
+        let tree = text_buffer.undo_tree.trimmed(4);
+        assert_eq!(tree.nodes.len(), 4);
+        assert!(!tree.is_dirty());
+        let mut reloaded = TextBuffer::from_test_str("abcd");
+        reloaded.undo_tree = tree;
+        for _ in 0..5 {
+            reloaded.undo(false, &mut cursors);
+        }
+        assert_eq!(reloaded.get_as_string(), "a");
+        
+        // then the redo chain, then the side branch that doesnt fit
+        let tree = text_buffer.undo_tree.trimmed(6);
+        let mut reloaded = TextBuffer::from_test_str("abcd");
+        reloaded.undo_tree = tree;
+        for _ in 0..3 {
+            reloaded.redo(false, &mut cursors);
+        }
+        assert_eq!(reloaded.get_as_string(), "abcde");
+        reloaded.undo(false, &mut cursors);
+        reloaded.undo(false, &mut cursors);
+        assert_eq!(reloaded.undo_tree.redo_branches().len(), 1);
+        
+        let small = text_buffer.undo_tree.trimmed(100);
+        assert_eq!(small.nodes.len(), text_buffer.undo_tree.nodes.len());
+    }
+    
+    #[test]
+    fn disk_changes_reload_or_merge() {
+        let base = "fn a() {}\nfn b() {}\nfn c() {}";
+        let mut text_buffer = TextBuffer::from_test_str(base);
+        text_buffer.disk_text = base.to_string();
+        let mut cursors = TextCursorSet::new();
+        
+        // our own save coming back is a no-op
+        assert_eq!(text_buffer.apply_disk_text(base), TextDiskChange::Unchanged);
+        
+        // clean buffers take the new text and stay clean
+        let formatted = "fn a() {}\r\nfn b() {}\r\nfn c() {\r\n}";
+        assert_eq!(text_buffer.apply_disk_text(formatted), TextDiskChange::Reloaded);
+        assert_eq!(text_buffer.get_as_lf_string(), "fn a() {}\nfn b() {}\nfn c() {\n}");
+        assert!(!text_buffer.is_dirty());
+        
+        // edits of our own get merged with the change on disk
+        edit(&mut text_buffer, &cursors, 3, 1, "x");
+        assert!(text_buffer.is_dirty());
+        let on_disk = "fn a() {}\nfn b() {}\nfn c() {\n    1\n}";
+        assert_eq!(text_buffer.apply_disk_text(on_disk), TextDiskChange::Merged);
+        assert_eq!(text_buffer.get_as_lf_string(), "fn x() {}\nfn b() {}\nfn c() {\n    1\n}");
+        text_buffer.undo(false, &mut cursors);
+        assert_eq!(text_buffer.get_as_lf_string(), "fn x() {}\nfn b() {}\nfn c() {\n}");
+        text_buffer.redo(false, &mut cursors);
+        
+        // both sides touching the same line is a conflict
+        edit(&mut text_buffer, &cursors, 13, 1, "y");
+        let on_disk = "fn a() {}\nfn z() {}\nfn c() {\n    1\n}";
+        match text_buffer.apply_disk_text(on_disk) {
+            TextDiskChange::Conflicted(conflicts) => assert_eq!(conflicts, vec![(0, 7)]),
+            other => panic!("expected a conflict, got {:?}", other)
+        }
+        assert!(text_buffer.get_as_lf_string().starts_with("<<<<<<< mine\nfn x() {}\nfn y() {}\n=======\nfn a() {}\nfn z() {}\n>>>>>>> on disk\n"));
+    }
+    
+    fn message(source: TextBufferMessageSource, offset: usize, body: &str) -> (TextCursor, TextBufferMessage) {
+        (TextCursor {head: offset, tail: offset, max: 0}, TextBufferMessage {
+            level: TextBufferMessageLevel::Warning,
+            body: body.to_string(),
+            detail: None,
+            fixes: Vec::new(),
+            source: source
+        })
+    }
+    
+    fn bodies(messages: &TextBufferMessages) -> Vec<&str> {
+        messages.bodies.iter().map( | body | body.body.as_str()).collect()
+    }
+    
+    #[test]
+    fn file_error_survives_compiler_messages() {
+        let mut messages = TextBufferMessages::default();
+        messages.set_file_error(Some("Cannot write a.rs: permission denied".to_string()), 0);
+        assert_eq!(messages.bodies.len(), 1);
+        // a compile run replaces its own messages but keeps the file error up front
+        messages.set_source(TextBufferMessageSource::Compiler, 0, vec![message(TextBufferMessageSource::Compiler, 5, "unused")]);
+        assert!(messages.bodies[0].level == TextBufferMessageLevel::Error);
+        assert_eq!(messages.bodies.len(), 2);
+        // a successful write takes only the file error away
+        messages.set_file_error(None, 0);
+        assert_eq!(bodies(&messages), vec!["unused"]);
+        assert_eq!(messages.cursors[0].head, 5);
+    }
+    
+    #[test]
+    fn messages_merge_by_source() {
+        use TextBufferMessageSource::*;
+        let mut messages = TextBufferMessages::default();
+        messages.set_source(Compiler, 1, vec![message(Compiler, 9, "cargo b"), message(Compiler, 2, "cargo a")]);
+        messages.set_source(Lsp, 1, vec![message(Lsp, 5, "lsp a")]);
+        messages.set_file_error(Some("file".to_string()), 1);
+        assert_eq!(bodies(&messages), vec!["file", "cargo a", "lsp a", "cargo b"]);
+        assert_eq!(messages.cursors.iter().map( | cursor | cursor.head).collect::<Vec<_>>(), vec![0, 2, 5, 9]);
+        
+        // new diagnostics only replace the old diagnostics
+        messages.set_source(Lsp, 1, vec![message(Lsp, 12, "lsp b")]);
+        assert_eq!(bodies(&messages), vec!["file", "cargo a", "cargo b", "lsp b"]);
+        messages.set_source(Compiler, 1, Vec::new());
+        assert_eq!(bodies(&messages), vec!["file", "lsp b"]);
+        
+        // for another text the rest is stale and goes, the file error stays
+        messages.set_source(Format, 2, vec![message(Format, 0, "format")]);
+        assert_eq!(bodies(&messages), vec!["file", "format"]);
+        assert_eq!(messages.mutation_id, 2);
+    }
+    
+    #[test]
+    fn next_message_goes_through_all_files() {
+        let mut text_buffers = TextBuffers::default();
+        let message = | offset | (TextCursor {head: offset + 2, tail: offset, max: 0}, TextBufferMessage {level: TextBufferMessageLevel::Error, body: String::new(), detail: None, fixes: Vec::new(), source: TextBufferMessageSource::Compiler});
+        for (path, offsets) in &[("a.rs", vec![10, 3]), ("b.rs", vec![]), ("c.rs", vec![7])] {
+            let mut text_buffer = TextBuffer::default();
+            for offset in offsets {
+                let (cursor, body) = message(*offset);
+                text_buffer.messages.cursors.push(cursor);
+                text_buffer.messages.bodies.push(body);
+            }
+            text_buffers.storage.insert(path.to_string(), text_buffer);
+        }
+        assert_eq!(text_buffers.next_message("a.rs", 0, true), Some(("a.rs".to_string(), 3)));
+        assert_eq!(text_buffers.next_message("a.rs", 3, true), Some(("a.rs".to_string(), 10)));
+        assert_eq!(text_buffers.next_message("a.rs", 10, true), Some(("c.rs".to_string(), 7)));
+        assert_eq!(text_buffers.next_message("b.rs", 0, true), Some(("c.rs".to_string(), 7)));
+        // and around
+        assert_eq!(text_buffers.next_message("c.rs", 7, true), Some(("a.rs".to_string(), 3)));
+        assert_eq!(text_buffers.next_message("a.rs", 3, false), Some(("c.rs".to_string(), 7)));
+        assert_eq!(text_buffers.next_message("c.rs", 0, false), Some(("a.rs".to_string(), 10)));
+        // stale messages dont count
+        text_buffers.storage.get_mut("c.rs").unwrap().mutation_id = 1;
+        assert_eq!(text_buffers.next_message("a.rs", 10, true), Some(("a.rs".to_string(), 3)));
+    }
+    
+    #[test]
+    fn quick_fixes_go_in_as_one_undo_step() {
+        let text = "let mut a = 1;\nlet b = a+1;\n";
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        let edit = | row, col, end_col, replacement: &str | TextQuickFixEdit {
+            start: TextPos {row: row, col: col},
+            end: TextPos {row: row, col: end_col},
+            replacement: replacement.to_string()
+        };
+        let unused_mut = TextQuickFix {label: "remove this `mut`".to_string(), machine_applicable: true, edits: vec![edit(0, 4, 8, "")]};
+        let spacing = TextQuickFix {label: "spacing".to_string(), machine_applicable: false, edits: vec![edit(1, 8, 11, "a + 1")]};
+        // overlaps the first one, so it is dropped
+        let clash = TextQuickFix {label: "clash".to_string(), machine_applicable: true, edits: vec![edit(0, 6, 10, "x")]};
+        text_buffer.messages.mutation_id = text_buffer.mutation_id;
+        text_buffer.messages.cursors.push(TextCursor {head: 8, tail: 4, max: 0});
+        text_buffer.messages.bodies.push(TextBufferMessage {level: TextBufferMessageLevel::Warning, body: "unused mut".to_string(), detail: None, fixes: vec![unused_mut.clone(), spacing.clone()], source: TextBufferMessageSource::Compiler});
+        assert_eq!(text_buffer.quick_fixes_at(6), vec![unused_mut.clone(), spacing.clone()]);
+        assert_eq!(text_buffer.quick_fixes_at(20).len(), 0);
+        assert_eq!(text_buffer.machine_applicable_fixes(), vec![unused_mut.clone()]);
+        
+        let mut cursors = crate::textcursor::TextCursorSet::new();
+        assert!(cursors.apply_quick_fixes(&[unused_mut, spacing, clash], &mut text_buffer));
+        assert_eq!(text_buffer.get_as_string(), "let a = 1;\nlet b = a + 1;\n");
+        assert_eq!(cursors.set[0].head, 24);
+        // the messages are stale now
+        assert_eq!(text_buffer.quick_fixes_at(6).len(), 0);
+        text_buffer.undo(true, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), text);
+        assert!(!text_buffer.undo_tree.can_undo());
+    }
+    
+    #[test]
+    fn quick_fix_that_clashes_is_dropped_whole() {
+        let text = "let mut a = 1;\nlet b = a+1;\n";
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        let edit = | row, col, end_col, replacement: &str | TextQuickFixEdit {
+            start: TextPos {row: row, col: col},
+            end: TextPos {row: row, col: end_col},
+            replacement: replacement.to_string()
+        };
+        let unused_mut = TextQuickFix {label: "remove this `mut`".to_string(), machine_applicable: true, edits: vec![edit(0, 4, 8, "")]};
+        // the first edit is fine on its own, the second one overlaps the fix before it
+        let clash = TextQuickFix {label: "clash".to_string(), machine_applicable: true, edits: vec![edit(1, 8, 9, "c"), edit(0, 6, 9, "x")]};
+        let mut cursors = crate::textcursor::TextCursorSet::new();
+        assert!(cursors.apply_quick_fixes(&[unused_mut, clash.clone()], &mut text_buffer));
+        assert_eq!(text_buffer.get_as_string(), "let a = 1;\nlet b = a+1;\n");
+        // on its own it goes in
+        text_buffer.undo(true, &mut cursors);
+        assert!(cursors.apply_quick_fixes(&[clash], &mut text_buffer));
+        assert_eq!(text_buffer.get_as_string(), "let mux = 1;\nlet b = c+1;\n");
+    }
+    
+    #[test]
+    fn saves_back_the_bytes_it_loaded() {
+        let mut text_buffer = TextBuffer::default();
+        let original: &[u8] = b"caf\xe9\r\nfn a() {}\nfn b() {}\r\n";
+        text_buffer.load_bytes(original);
+        assert_eq!(text_buffer.encoding, TextEncoding::Latin1);
+        assert_eq!(text_buffer.get_as_lf_string(), "caf\u{e9}\nfn a() {}\nfn b() {}\n");
+        assert_eq!(text_buffer.get_as_bytes().unwrap(), original);
+        
+        // edits keep the line endings of the rows around them
+        text_buffer.replace_lines_with_string(4, 0, "x\ny\n");
+        let fn_b = text_buffer.text_pos_to_offset(TextPos {row: 4, col: 0});
+        text_buffer.replace_lines_with_string(fn_b, 9, "");
+        assert_eq!(text_buffer.get_as_bytes().unwrap(), b"caf\xe9x\r\ny\r\n\r\nfn a() {}\n\r\n".to_vec());
+        
+        text_buffer.replace_lines_with_string(0, 0, "\u{1f600}");
+        assert!(text_buffer.get_as_bytes().is_err());
+        assert!(text_buffer.convert_encoding(TextEncoding::Latin1).is_ok());
+        text_buffer.convert_encoding(TextEncoding::Utf8).unwrap();
+        text_buffer.convert_line_endings(false);
+        assert!(text_buffer.is_dirty());
+        assert_eq!(text_buffer.get_as_bytes().unwrap(), "\u{1f600}caf\u{e9}x\ny\n\nfn a() {}\n\n".as_bytes());
+        // it doesnt fit latin-1 anymore
+        assert!(text_buffer.convert_encoding(TextEncoding::Latin1).is_err());
+        assert_eq!(text_buffer.encoding, TextEncoding::Utf8);
+    }
+    
+    #[test]
+    fn keeps_stray_carriage_returns() {
+        for original in &[&b"a\r"[..], b"a\r\nb\nc\r\n\r", b"a\r\r\nb\n\r\n\r", b"\r"] {
+            let mut text_buffer = TextBuffer::default();
+            text_buffer.load_bytes(original);
+            assert_eq!(text_buffer.get_as_bytes().unwrap(), original.to_vec());
+        }
+        let mut text_buffer = TextBuffer::default();
+        text_buffer.load_bytes(b"a\r\nb\nc\r\n\r");
+        assert_eq!(text_buffer.line_endings, TextLineEndings::Mixed(vec![true, false, true, false]));
+        // a pasted \r\n is a line ending, a pasted \r at the end isnt
+        text_buffer.replace_lines_with_string(1, 0, "x\r\ny\r");
+        assert_eq!(text_buffer.get_as_bytes().unwrap(), b"ax\r\ny\r\r\nb\nc\r\n\r".to_vec());
+    }
+    
+    #[test]
+    fn converting_from_hex_reads_the_bytes_again() {
+        let mut text_buffer = TextBuffer::default();
+        text_buffer.load_bytes(b"a\x00b");
+        assert_eq!(text_buffer.encoding, TextEncoding::Hex);
+        // edit the nul byte into a newline
+        text_buffer.replace_lines_with_string(3, 2, "0d 0a");
+        text_buffer.push_undo(TextUndo {ops: Vec::new(), grouping: TextUndoGrouping::Other, cursors: TextCursorSet::new()});
+        text_buffer.convert_encoding(TextEncoding::Utf8).unwrap();
+        assert_eq!(text_buffer.get_as_lf_string(), "a\nb");
+        assert_eq!(text_buffer.line_endings, TextLineEndings::CrLf);
+        assert_eq!(text_buffer.get_as_bytes().unwrap(), b"a\r\nb");
+        assert!(text_buffer.is_dirty());
+        assert!(!text_buffer.undo_tree.can_undo());
+        
+        text_buffer.convert_encoding(TextEncoding::Hex).unwrap();
+        assert_eq!(text_buffer.get_as_string(), "61 0d 0a 62");
+        assert_eq!(text_buffer.get_as_bytes().unwrap(), b"a\r\nb");
+    }
+    
+    #[test]
+    fn renames_paths_inside_a_folder() {
+        assert_eq!(renamed_path("src/main.rs", "src/main.rs", "src/app.rs"), Some("src/app.rs".to_string()));
+        assert_eq!(renamed_path("src/main.rs", "src", "lib/src"), Some("lib/src/main.rs".to_string()));
+        assert_eq!(renamed_path("srcs/main.rs", "src", "lib"), None);
+        assert_eq!(renamed_path("src", "src/main.rs", "x"), None);
+        
+        let mut map = HashMap::new();
+        map.insert("a/b.rs".to_string(), 1);
+        map.insert("ab.rs".to_string(), 2);
+        assert!(rename_keys(&mut map, "a", "c"));
+        assert_eq!(map.get("c/b.rs"), Some(&1));
+        assert_eq!(map.get("ab.rs"), Some(&2));
+        assert!(!rename_keys(&mut map, "a", "c"));
+    }
+}
@@ -1,8 +1,14 @@
 use render::*;
 
 use crate::textbuffer::*;
+use crate::textsearch::*;
+use crate::textmerge::*;
+use crate::textformat::*;
+use crate::textwrap::*;
+use serde::*;
+use std::rc::Rc;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TextCursor {
     pub head: usize,
     pub tail: usize,
@@ -49,9 +55,10 @@ impl TextCursor {
     }
     
     
-    pub fn calc_max(&mut self, text_buffer: &TextBuffer, old: (TextPos, usize)) -> (TextPos, usize) {
+    pub fn calc_max(&mut self, text_buffer: &TextBuffer, wraps: &TextWraps, old: (TextPos, usize)) -> (TextPos, usize) {
         let pos = text_buffer.offset_to_text_pos_next(self.head, old.0, old.1);
-        self.max = pos.col;
+        // soft wrapped rows want the column on the visual line
+        self.max = wraps.visual_pos(pos.row, pos.col).1;
         return (pos, self.head)
     }
     
@@ -70,7 +77,7 @@ impl TextCursor {
     pub fn move_end(&mut self, text_buffer: &TextBuffer) {
         let pos = text_buffer.offset_to_text_pos(self.head);
         // alright lets walk the line from the left till its no longer 9 or 32
-        self.head = text_buffer.text_pos_to_offset(TextPos {row: pos.row, col: text_buffer.lines[pos.row].len()});
+        self.head = text_buffer.text_pos_to_offset(TextPos {row: pos.row, col: text_buffer.lines.line_len(pos.row)});
     }
     
     pub fn move_left(&mut self, char_count: usize, _text_buffer: &TextBuffer) {
@@ -91,22 +98,48 @@ impl TextCursor {
         }
     }
     
-    pub fn move_up(&mut self, line_count: usize, text_buffer: &TextBuffer) {
+    // folded rows dont count as lines, the cursor steps over them
+    // these go by visual line, a soft wrapped row has more than one
+    pub fn move_up(&mut self, line_count: usize, wraps: &TextWraps, text_buffer: &TextBuffer) {
         let pos = text_buffer.offset_to_text_pos(self.head);
-        if pos.row >= line_count {
-            self.head = text_buffer.text_pos_to_offset(TextPos {row: pos.row - line_count, col: self.max});
-        }
-        else {
-            self.head = 0;
+        let mut row = pos.row;
+        let mut line = wraps.visual_pos(pos.row, pos.col).0;
+        for _ in 0..line_count {
+            if line > 0 {
+                line -= 1;
+                continue
+            }
+            if row == 0 {
+                self.head = 0;
+                return
+            }
+            row -= 1;
+            while row > 0 && text_buffer.folds.is_hidden(row) {
+                row -= 1;
+            }
+            line = wraps.visual_lines(row) - 1;
         }
+        self.head = text_buffer.text_pos_to_offset(TextPos {row: row, col: wraps.col_at_visual(row, line, self.max)});
     }
     
-    pub fn move_down(&mut self, line_count: usize, total_char_count: usize, text_buffer: &TextBuffer) {
+    pub fn move_down(&mut self, line_count: usize, total_char_count: usize, wraps: &TextWraps, text_buffer: &TextBuffer) {
         let pos = text_buffer.offset_to_text_pos(self.head);
+        let mut row = pos.row;
+        let mut line = wraps.visual_pos(pos.row, pos.col).0;
+        for _ in 0..line_count {
+            if line + 1 < wraps.visual_lines(row) {
+                line += 1;
+                continue
+            }
+            row += 1;
+            while text_buffer.folds.is_hidden(row) {
+                row += 1;
+            }
+            line = 0;
+        }
+        if row < text_buffer.get_line_count() - 1 || line > 0 {
         
-        if pos.row + line_count < text_buffer.get_line_count() - 1 {
-            
-            self.head = text_buffer.text_pos_to_offset(TextPos {row: pos.row + line_count, col: self.max});
+            self.head = text_buffer.text_pos_to_offset(TextPos {row: row, col: wraps.col_at_visual(row, line, self.max)});
         }
         else {
             self.head = total_char_count;
@@ -114,12 +147,18 @@ impl TextCursor {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TextCursorSet {
     pub set: Vec<TextCursor>,
     pub last_cursor: usize,
     pub insert_undo_group: u64,
-    pub last_clamp_range: Option<(usize, usize)>
+    pub last_clamp_range: Option<(usize, usize)>,
+    // the sets from before each keyboard added cursor, undo_cursor_addition goes back one
+    #[serde(skip)]
+    pub cursor_history: Vec<(Vec<TextCursor>, usize)>,
+    // the soft wraps of the editor these cursors are in, up and down go by visual line
+    #[serde(skip)]
+    pub wraps: Rc<TextWraps>
 }
 
 impl TextCursorSet {
@@ -128,8 +167,38 @@ impl TextCursorSet {
             set: vec![TextCursor {head: 0, tail: 0, max: 0}],
             last_cursor: 0,
             insert_undo_group: 0,
-            last_clamp_range: None
+            last_clamp_range: None,
+            cursor_history: Vec::new(),
+            wraps: Rc::new(TextWraps::default())
+        }
+    }
+    
+    // the text changed under us, keep the cursors where they are as far as the text still goes
+    pub fn clamp_to_text_buffer(&mut self, text_buffer: &TextBuffer) {
+        let char_count = text_buffer.lines.char_count();
+        for cursor in &mut self.set {
+            cursor.head = cursor.head.min(char_count);
+            cursor.tail = cursor.tail.min(char_count);
+        }
+    }
+    
+    // a block just folded over some cursors, they go to the end of the row it folded into
+    pub fn move_out_of_folds(&mut self, text_buffer: &TextBuffer) {
+        let fold_end = | offset: usize | {
+            let mut row = text_buffer.offset_to_text_pos(offset).row;
+            if !text_buffer.folds.is_hidden(row) {
+                return offset
+            }
+            while row > 0 && text_buffer.folds.is_hidden(row) {
+                row -= 1;
+            }
+            text_buffer.text_pos_to_offset(TextPos {row: row, col: 1 << 31})
+        };
+        for cursor in &mut self.set {
+            cursor.head = fold_end(cursor.head);
+            cursor.tail = fold_end(cursor.tail);
         }
+        self.fuse_adjacent(text_buffer)
     }
     
     pub fn get_all_as_string(&self, text_buffer: &TextBuffer) -> String {
@@ -162,7 +231,7 @@ impl TextCursorSet {
                         self.set[index].head = next_end;
                         self.set[index].tail = my_start;
                     }
-                    old_calc = self.set[index].calc_max(text_buffer, old_calc);
+                    old_calc = self.set[index].calc_max(text_buffer, &self.wraps, old_calc);
                     // remove the next item
                 }
                 if self.last_cursor > index {
@@ -218,7 +287,7 @@ impl TextCursorSet {
         // clamp its head/tail to min range
         cursor.clamp_range(&self.last_clamp_range);
         // recompute maximum h pos
-        cursor.calc_max(text_buffer, (TextPos {row: 0, col: 0}, 0));
+        cursor.calc_max(text_buffer, &self.wraps, (TextPos {row: 0, col: 0}, 0));
         // insert it back into the set
         self.set.insert(index, cursor);
         self.last_cursor = index;
@@ -241,10 +310,265 @@ impl TextCursorSet {
     
     pub fn clear_and_set_last_cursor_head_and_tail(&mut self, offset: usize, text_buffer: &TextBuffer) {
         self.insert_undo_group += 1;
+        self.cursor_history.truncate(0);
         self.set.truncate(0);
         self.set_last_cursor(offset, offset, text_buffer);
     }
     
+    // replaces the set with a selection per range, ranges have to be ordered and not overlap
+    pub fn select_ranges(&mut self, ranges: &[(usize, usize)], last_cursor: usize, text_buffer: &TextBuffer) {
+        if ranges.len() == 0 {
+            return
+        }
+        self.insert_undo_group += 1;
+        self.last_clamp_range = None;
+        self.set.truncate(0);
+        let mut old_max = (TextPos {row: 0, col: 0}, 0);
+        for (start, end) in ranges {
+            let mut cursor = TextCursor {
+                head: *end,
+                tail: *start,
+                max: 0
+            };
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
+            self.set.push(cursor);
+        }
+        self.last_cursor = last_cursor.min(self.set.len() - 1);
+    }
+    
+    fn push_cursor_history(&mut self) {
+        if self.cursor_history.len() >= 100 {
+            self.cursor_history.remove(0);
+        }
+        self.cursor_history.push((self.set.clone(), self.last_cursor));
+    }
+    
+    // adds a cursor in order without touching its neighbours, even adjacent ones
+    fn insert_cursor(&mut self, mut cursor: TextCursor, text_buffer: &TextBuffer) {
+        self.insert_undo_group += 1;
+        let start = cursor.order().0;
+        let index = self.set.iter().position( | other | other.order().0 > start).unwrap_or(self.set.len());
+        cursor.calc_max(text_buffer, &self.wraps, (TextPos {row: 0, col: 0}, 0));
+        self.set.insert(index, cursor);
+        self.last_cursor = index;
+    }
+    
+    // the selection of the last cursor. An empty one grows over the token under it instead,
+    // the error says if it did
+    fn occurrence_query(&mut self, text_buffer: &TextBuffer) -> Result<String, bool> {
+        let cursor = self.set[self.last_cursor].clone();
+        if !cursor.has_selection() {
+            let (offset, len) = TextCursorSet::get_nearest_token_chunk(cursor.head, text_buffer).ok_or(false) ?;
+            self.push_cursor_history();
+            self.set[self.last_cursor] = TextCursor {head: offset + len, tail: offset, max: 0};
+            self.set[self.last_cursor].calc_max(text_buffer, &self.wraps, (TextPos {row: 0, col: 0}, 0));
+            return Err(true)
+        }
+        let (start, end) = cursor.order();
+        let mut query = String::new();
+        text_buffer.get_range_as_string(start, end - start, &mut query);
+        Ok(query)
+    }
+    
+    // the first match after (or before) the last cursor that no cursor has yet, wrapping around
+    fn next_occurrence(&self, query: &str, forward: bool, text_buffer: &TextBuffer) -> Option<(usize, usize)> {
+        let search = TextSearch {query: query.to_string(), case_sensitive: true, ..Default::default()};
+        let (start, end) = self.set[self.last_cursor].order();
+        let free: Vec<(usize, usize)> = search.find_in_text_buffer(text_buffer, "").ok() ?.iter().map( | m | (m.start, m.end)).filter( | (m_start, m_end) | {
+            !self.set.iter().any( | cursor | {
+                let (c_start, c_end) = cursor.order();
+                *m_start < c_end && *m_end > c_start
+            })
+        }).collect();
+        if forward {
+            free.iter().find( | (m_start, _) | *m_start >= end).or(free.first()).cloned()
+        }
+        else {
+            free.iter().rev().find( | (_, m_end) | *m_end <= start).or(free.last()).cloned()
+        }
+    }
+    
+    pub fn add_next_occurrence(&mut self, forward: bool, text_buffer: &TextBuffer) -> bool {
+        let query = match self.occurrence_query(text_buffer) {
+            Ok(query) => query,
+            Err(grew) => return grew
+        };
+        if let Some((start, end)) = self.next_occurrence(&query, forward, text_buffer) {
+            self.push_cursor_history();
+            self.insert_cursor(TextCursor {head: end, tail: start, max: 0}, text_buffer);
+            return true
+        }
+        false
+    }
+    
+    // moves the last cursor on to the next occurrence instead of keeping it
+    pub fn skip_occurrence(&mut self, text_buffer: &TextBuffer) -> bool {
+        let query = match self.occurrence_query(text_buffer) {
+            Ok(query) => query,
+            Err(grew) => return grew
+        };
+        if let Some((start, end)) = self.next_occurrence(&query, true, text_buffer) {
+            self.push_cursor_history();
+            self.set.remove(self.last_cursor);
+            self.insert_cursor(TextCursor {head: end, tail: start, max: 0}, text_buffer);
+            return true
+        }
+        false
+    }
+    
+    pub fn select_all_occurrences(&mut self, text_buffer: &TextBuffer) -> bool {
+        let query = match self.occurrence_query(text_buffer) {
+            Ok(query) => query,
+            Err(true) => self.occurrence_query(text_buffer).unwrap_or(String::new()),
+            Err(false) => return false
+        };
+        let search = TextSearch {query: query, case_sensitive: true, ..Default::default()};
+        let ranges: Vec<(usize, usize)> = match search.find_in_text_buffer(text_buffer, "") {
+            Ok(matches) => matches.iter().map( | m | (m.start, m.end)).collect(),
+            Err(_) => return false
+        };
+        let last_start = self.set[self.last_cursor].order().0;
+        let last_cursor = ranges.iter().position( | (start, _) | *start == last_start).unwrap_or(0);
+        self.push_cursor_history();
+        self.select_ranges(&ranges, last_cursor, text_buffer);
+        true
+    }
+    
+    // a selection over several lines becomes a selection per line
+    pub fn split_into_lines(&mut self, text_buffer: &TextBuffer) -> bool {
+        let mut ranges = Vec::new();
+        let mut last_cursor = 0;
+        for (index, cursor) in self.set.iter().enumerate() {
+            let (start, end) = cursor.order();
+            let start_row = text_buffer.offset_to_text_pos(start).row;
+            let end_row = text_buffer.offset_to_text_pos(end).row;
+            for row in start_row..=end_row {
+                let line_start = text_buffer.text_pos_to_offset(TextPos {row: row, col: 0});
+                let line_end = line_start + text_buffer.lines.line_len(row);
+                let (range_start, range_end) = (start.max(line_start), end.min(line_end));
+                // a selection ending at the start of a line doesnt take that line, empty lines
+                // in the middle get a cursor like any other
+                if row > start_row && row == end_row && end == line_start {
+                    continue
+                }
+                ranges.push((range_start, range_end));
+            }
+            if index == self.last_cursor {
+                last_cursor = ranges.len() - 1;
+            }
+        }
+        if ranges.len() == self.set.len() {
+            return false
+        }
+        self.push_cursor_history();
+        self.select_ranges(&ranges, last_cursor, text_buffer);
+        true
+    }
+    
+    // a new cursor a line above or below the last one, in the column it wants to be
+    pub fn add_cursor_vertical(&mut self, up: bool, text_buffer: &TextBuffer) -> bool {
+        let cursor = &self.set[self.last_cursor];
+        let pos = text_buffer.offset_to_text_pos(cursor.head);
+        if up && pos.row == 0 || !up && pos.row + 1 >= text_buffer.get_line_count() {
+            return false
+        }
+        let row = if up {pos.row - 1} else {pos.row + 1};
+        let max = cursor.max;
+        let offset = text_buffer.text_pos_to_offset(TextPos {row: row, col: max});
+        if self.set.iter().any( | cursor | cursor.head == offset && cursor.tail == offset) {
+            return false
+        }
+        self.push_cursor_history();
+        self.insert_cursor(TextCursor {head: offset, tail: offset, max: 0}, text_buffer);
+        // keep wanting the column of the cursor we came from
+        self.set[self.last_cursor].max = max;
+        true
+    }
+    
+    pub fn undo_cursor_addition(&mut self, text_buffer: &TextBuffer) -> bool {
+        if let Some((set, last_cursor)) = self.cursor_history.pop() {
+            self.insert_undo_group += 1;
+            self.set = set;
+            self.last_cursor = last_cursor.min(self.set.len() - 1);
+            self.clamp_to_text_buffer(text_buffer);
+            return true
+        }
+        false
+    }
+    
+    // replaces all matches as a single undo step and selects the replacements
+    pub fn replace_matches(&mut self, matches: &[TextSearchMatch], text_buffer: &mut TextBuffer) {
+        if matches.len() == 0 {
+            return
+        }
+        let cursors_clone = self.clone();
+        let mut ops = Vec::new();
+        // back to front so the offsets of the matches still to do stay valid
+        for m in matches.iter().rev() {
+            ops.push(text_buffer.replace_with_textop(TextOp {
+                start: m.start,
+                len: m.end - m.start,
+                lines: TextBuffer::split_string_to_lines(&m.replace)
+            }));
+        }
+        text_buffer.push_undo(TextUndo {
+            ops: ops,
+            grouping: TextUndoGrouping::Other,
+            cursors: cursors_clone
+        });
+        
+        let mut ranges = Vec::new();
+        let mut delta: isize = 0;
+        for m in matches {
+            let start = (m.start as isize + delta) as usize;
+            let len = m.replace.chars().count();
+            ranges.push((start, start + len));
+            delta += len as isize - (m.end - m.start) as isize;
+        }
+        self.select_ranges(&ranges, ranges.len() - 1, text_buffer);
+    }
+    
+    // all edits of all fixes go in as one undo step, a fix with an edit that overlaps an edit
+    // of a fix before it is dropped as a whole
+    pub fn apply_quick_fixes(&mut self, fixes: &[TextQuickFix], text_buffer: &mut TextBuffer) -> bool {
+        let overlaps = | a: &(usize, usize, &str), b: &(usize, usize, &str) | a.0 == b.0 || a.0 < b.1 && b.0 < a.1;
+        let mut kept: Vec<(usize, usize, &str)> = Vec::new();
+        for fix in fixes {
+            let edits: Vec<(usize, usize, &str)> = fix.edits.iter().map( | edit | {
+                let start = text_buffer.text_pos_to_offset(edit.start);
+                let end = text_buffer.text_pos_to_offset(edit.end).max(start);
+                (start, end, edit.replacement.as_str())
+            }).collect();
+            if edits.iter().any( | edit | kept.iter().any( | other | overlaps(edit, other))) {
+                continue
+            }
+            kept.extend(edits);
+        }
+        kept.sort_by_key( | (start, end, _) | (*start, *end));
+        if kept.len() == 0 {
+            return false
+        }
+        let cursors_clone = self.clone();
+        let mut ops = Vec::new();
+        for (start, end, replacement) in kept.iter().rev() {
+            ops.push(text_buffer.replace_lines_with_string(*start, end - start, replacement));
+        }
+        text_buffer.push_undo(TextUndo {
+            ops: ops,
+            grouping: TextUndoGrouping::Other,
+            cursors: cursors_clone
+        });
+        // cursor goes after the last edit
+        let mut delta: isize = 0;
+        for (start, end, replacement) in &kept {
+            delta += replacement.chars().count() as isize - (end - start) as isize;
+        }
+        let (_, end, _) = kept[kept.len() - 1];
+        let offset = (end as isize + delta) as usize;
+        self.select_ranges(&[(offset, offset)], 0, text_buffer);
+        true
+    }
+    
     pub fn set_last_cursor_head(&mut self, offset: usize, text_buffer: &TextBuffer) -> bool {
         self.insert_undo_group += 1;
         if self.set[self.last_cursor].head != offset {
@@ -359,7 +683,8 @@ impl TextCursorSet {
         self.last_clamp_range = None;
     }
     
-    pub fn insert_newline_with_indent(&mut self, text_buffer: &mut TextBuffer) {
+    // tabsize is the indent after an open bracket, brackets false leaves the bracket pairs alone
+    pub fn insert_newline_with_indent(&mut self, tabsize: usize, brackets: bool, text_buffer: &mut TextBuffer) {
         let mut delta: isize = 0;
         // rolling delta to displace cursors
         let mut ops = Vec::new();
@@ -370,18 +695,18 @@ impl TextCursorSet {
             // lets find where we are as a cursor in the textbuffer
             if start == end && start > 0 && start < text_buffer.flat_text.len(){
                 // insert spaces till indent level
-                let (pre_base, pre_spaces) = text_buffer.calc_next_line_indent_depth(start, 4);
+                let (pre_base, pre_spaces) = text_buffer.calc_next_line_indent_depth(start, if brackets {tabsize} else {0});
                 
                 let pch = text_buffer.flat_text[start-1];
                 let nch = text_buffer.flat_text[start];
                 // we have to insert more newlines and spaces because we were between () {} or []
-                if pch == '{' && nch == '}' || pch == '(' && nch == ')' || pch == '[' && nch == ']' {
+                if brackets && (pch == '{' && nch == '}' || pch == '(' && nch == ')' || pch == '[' && nch == ']') {
                     let mut text = String::new();
                     text.push_str("\n");
                     for _ in 0..pre_spaces {
                         text.push_str(" ");
                     }
-                    let post_spaces = pre_spaces.max(4) - 4;
+                    let post_spaces = pre_spaces.max(tabsize) - tabsize;
                     text.push_str("\n");
                     for _ in 0..post_spaces {
                         text.push_str(" ");
@@ -392,10 +717,10 @@ impl TextCursorSet {
                     delta += (pre_spaces + post_spaces + 2) as isize;
                     ops.push(op);
                 }
-                else if pre_spaces != (start-pre_base) && nch == '}' || nch == ')' || nch == ']'{ // deindent next one
+                else if brackets && (pre_spaces != (start-pre_base) && nch == '}' || nch == ')' || nch == ']'){ // deindent next one
                     let mut text = String::new();
                     text.push_str("\n");
-                    for _ in 0..(pre_spaces.max(4)-4) {
+                    for _ in 0..(pre_spaces.max(tabsize)-tabsize) {
                         text.push_str(" ");
                     }
                     let op = text_buffer.replace_lines_with_string(start, end - start, &text);
@@ -419,10 +744,9 @@ impl TextCursorSet {
                 ops.push(op);
             };
             
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Newline,
             cursors: cursors_clone
@@ -460,10 +784,9 @@ impl TextCursorSet {
             let op = text_buffer.replace_lines_with_string(start, end - start, text);
             delta += cursor.collapse(start, end, op.len);
             ops.push(op);
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: grouping,
             cursors: cursors_clone
@@ -517,10 +840,9 @@ impl TextCursorSet {
                     ops.push(op);
                 }
             }
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Block,
             cursors: cursors_clone
@@ -579,10 +901,9 @@ impl TextCursorSet {
                     ops.push(op);
                 }
             };
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Block,
             cursors: cursors_clone
@@ -628,11 +949,10 @@ impl TextCursorSet {
                 ops.push(op);
                 delta += cursor.collapse(start, end, 0);
             }
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
         let del_pos = self.set[self.last_cursor].head;
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Delete(del_pos),
             cursors: cursors_clone
@@ -660,10 +980,9 @@ impl TextCursorSet {
                 ops.push(op);
                 delta += cursor.collapse(start, end, 0);
             }
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Backspace,
             cursors: cursors_clone
@@ -704,7 +1023,7 @@ impl TextCursorSet {
             for row in start_pos.row..(end_pos.row + last_line) {
                 // ok so how do we compute the actual op offset of this line
                 let op = text_buffer.replace_line_with_string(off, row, 0, 0, tab_str);
-                off += text_buffer.lines[row].len() + 1;
+                off += text_buffer.lines.line_len(row) + 1;
                 ops.push(op);
             }
             // figure out which way the cursor is
@@ -719,10 +1038,9 @@ impl TextCursorSet {
             delta += ((end_pos.row - start_pos.row) + 1) * tab_str_chars;
             // }
             //}
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Tab,
             cursors: cursors_clone
@@ -748,14 +1066,68 @@ impl TextCursorSet {
             
             let cursors_clone = self.clone();
             let op = text_buffer.replace_lines(top_row, bottom_row_old + 1, changed);
-            text_buffer.redo_stack.truncate(0);
-            text_buffer.undo_stack.push(TextUndo {
+            text_buffer.push_undo(TextUndo {
                 ops: vec![op],
                 grouping: TextUndoGrouping::Format,
                 cursors: cursors_clone
             })
         }
     }
+    
+    // puts in the formatted text by only replacing the lines that differ, as one undo, the
+    // cursors stay on the code they were on. Returns the changed ranges.
+    pub fn replace_lines_diffed(&mut self, formatted: &str, text_buffer: &mut TextBuffer) -> Vec<DiffHunk> {
+        let formatted = formatted.replace("\r\n", "\n");
+        let old_lines: Vec<String> = text_buffer.lines.iter().map( | line | line.iter().collect()).collect();
+        let base: Vec<&str> = old_lines.iter().map( | line | line.as_str()).collect();
+        let other: Vec<&str> = formatted.split('\n').collect();
+        let hunks = diff_lines(&base, &other);
+        if hunks.len() == 0 {
+            return hunks
+        }
+        let positions: Vec<(TextPos, TextPos)> = self.set.iter().map( | cursor | {
+            (text_buffer.offset_to_text_pos(cursor.tail), text_buffer.offset_to_text_pos(cursor.head))
+        }).collect();
+        
+        let cursors_clone = self.clone();
+        let char_count = text_buffer.lines.char_count();
+        let line_start = | row: usize | if row < base.len() {text_buffer.text_pos_to_offset(TextPos {row: row, col: 0})} else {char_count};
+        let mut edits = Vec::new();
+        for hunk in &hunks {
+            let lines = &other[hunk.other_start..hunk.other_end];
+            if hunk.base_end < base.len() {
+                let text: String = lines.iter().map( | line | format!("{}\n", line)).collect();
+                edits.push((line_start(hunk.base_start), line_start(hunk.base_end), text));
+            }
+            else if hunk.base_start == 0 {
+                edits.push((0, char_count, lines.join("\n")));
+            }
+            else {
+                // the last line has no newline of its own, take the one in front along
+                let start = if hunk.base_start < base.len() {line_start(hunk.base_start) - 1} else {char_count};
+                let text: String = lines.iter().map( | line | format!("\n{}", line)).collect();
+                edits.push((start, char_count, text));
+            }
+        }
+        let mut ops = Vec::new();
+        for (start, end, text) in edits.iter().rev() {
+            ops.push(text_buffer.replace_lines_with_string(*start, end - start, text));
+        }
+        text_buffer.push_undo(TextUndo {
+            ops: ops,
+            grouping: TextUndoGrouping::Format,
+            cursors: cursors_clone
+        });
+        
+        let ranges: Vec<(usize, usize)> = positions.iter().map( | (tail, head) | {
+            let tail = map_pos_through_hunks(&hunks, &base, &other, *tail);
+            let head = map_pos_through_hunks(&hunks, &base, &other, *head);
+            (text_buffer.text_pos_to_offset(tail), text_buffer.text_pos_to_offset(head))
+        }).collect();
+        let last_cursor = self.last_cursor;
+        self.select_ranges(&ranges, last_cursor, text_buffer);
+        hunks
+    }
     /*
     pub fn toggle_comment(&mut self, text_buffer:&mut TextBuffer, comment_str:&str){
         let mut delta:usize = 0; // rolling delta to displace cursors
@@ -774,7 +1146,7 @@ impl TextCursorSet {
         for row in start_pos.row..(end_pos.row+last_line){
         // ok so how do we compute the actual op offset of this line
         let op = text_buffer.replace_line_with_string(off, row, 0, 0, tab_str);
-        off += text_buffer.lines[row].len() + 1;
+        off += text_buffer.lines.line_len(row) + 1;
         ops.push(op);
         }
         // figure out which way the cursor is
@@ -787,10 +1159,9 @@ impl TextCursorSet {
         cursor.head += tab_str_chars + delta;
         }
         delta += ((end_pos.row - start_pos.row) + 1) * tab_str_chars;
-        old_max = cursor.calc_max(text_buffer, old_max);
+        old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo{
+        text_buffer.push_undo(TextUndo{
         ops:ops,
         grouping:TextUndoGrouping::Tab,
         cursors:cursors_clone
@@ -826,15 +1197,14 @@ impl TextCursorSet {
                     }
                     ops.push(op);
                 }
-                off += text_buffer.lines[row].len() + 1;
+                off += text_buffer.lines.line_len(row) + 1;
             }
             cursor.head -= delta;
             cursor.tail -= delta;
             delta += total_cut_len;
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
-        text_buffer.redo_stack.truncate(0);
-        text_buffer.undo_stack.push(TextUndo {
+        text_buffer.push_undo(TextUndo {
             ops: ops,
             grouping: TextUndoGrouping::Tab,
             cursors: cursors_clone
@@ -843,16 +1213,71 @@ impl TextCursorSet {
     }
     
     
+    // comments the lines under the cursors out, or back in when they all already are
+    pub fn toggle_line_comment(&mut self, comment: &str, text_buffer: &mut TextBuffer) {
+        let comment_chars: Vec<char> = comment.chars().collect();
+        let is_blank = | line: &[char] | line.iter().all( | ch | *ch == ' ');
+        let is_commented = | line: &[char] | {
+            let indent = line.iter().take_while( | ch | **ch == ' ').count();
+            line[indent..].starts_with(&comment_chars)
+        };
+        let mut delta: isize = 0;
+        // rolling delta to displace cursors
+        let mut ops = Vec::new();
+        let cursors_clone = self.clone();
+        let mut old_max = (TextPos {row: 0, col: 0}, 0);
+        let mut next_row = 0;
+        for cursor in &mut self.set {
+            let (start, end) = cursor.delta(delta);
+            cursor.head = (cursor.head as isize + delta) as usize;
+            cursor.tail = (cursor.tail as isize + delta) as usize;
+            let start_pos = text_buffer.offset_to_text_pos_next(start, old_max.0, old_max.1);
+            let end_pos = text_buffer.offset_to_text_pos_next(end, start_pos, start);
+            let last_line = if start_pos.row == end_pos.row || end_pos.col>0 {1}else {0};
+            // rows an earlier cursor already did are skipped
+            let rows = start_pos.row.max(next_row)..(end_pos.row + last_line).max(next_row);
+            next_row = rows.end;
+            
+            let lines = &text_buffer.lines;
+            let filled: Vec<usize> = rows.clone().filter( | row | !is_blank(&lines[*row])).collect();
+            let uncomment = filled.len() > 0 && filled.iter().all( | row | is_commented(&lines[*row]));
+            let indent = filled.iter().map( | row | text_buffer.calc_line_indent_depth(*row)).min().unwrap_or(0);
+            for row in filled {
+                let (col, len, insert) = if uncomment {
+                    let col = text_buffer.calc_line_indent_depth(row);
+                    let space = text_buffer.lines[row].get(col + comment_chars.len()) == Some(&' ');
+                    (col, comment_chars.len() + if space {1} else {0}, String::new())
+                }
+                else {
+                    (indent, 0, format!("{} ", comment))
+                };
+                let at = text_buffer.lines.row_offset(row) + col;
+                let inserted = insert.chars().count();
+                let shift = | pos: usize | if pos < at {pos} else if pos < at + len {at} else {pos + inserted - len};
+                cursor.head = shift(cursor.head);
+                cursor.tail = shift(cursor.tail);
+                ops.push(text_buffer.replace_line_with_string(at, row, col, len, &insert));
+                delta += inserted as isize - len as isize;
+            }
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
+        }
+        text_buffer.push_undo(TextUndo {
+            ops: ops,
+            grouping: TextUndoGrouping::Other,
+            cursors: cursors_clone
+        })
+    }
+    
     pub fn select_all(&mut self, text_buffer: &mut TextBuffer) {
         self.set.truncate(0);
         self.insert_undo_group += 1;
         let mut cursor = TextCursor {
             head: 0,
-            tail: text_buffer.calc_char_count(),
+            tail: text_buffer.lines.char_count(),
             max: 0
         };
         self.last_cursor = 0;
-        cursor.calc_max(text_buffer, (TextPos {row: 0, col: 0}, 0));
+        cursor.calc_max(text_buffer, &self.wraps, (TextPos {row: 0, col: 0}, 0));
         self.set.push(cursor);
     }
     
@@ -877,7 +1302,7 @@ impl TextCursorSet {
     pub fn move_up(&mut self, line_count: usize, only_head: bool, text_buffer: &TextBuffer) {
         self.insert_undo_group += 1;
         for cursor in &mut self.set {
-            cursor.move_up(line_count, text_buffer);
+            cursor.move_up(line_count, &self.wraps, text_buffer);
             if !only_head {cursor.tail = cursor.head}
         }
         self.fuse_adjacent(text_buffer)
@@ -885,9 +1310,9 @@ impl TextCursorSet {
     
     pub fn move_down(&mut self, line_count: usize, only_head: bool, text_buffer: &TextBuffer) {
         self.insert_undo_group += 1;
-        let total_char_count = text_buffer.calc_char_count();
+        let total_char_count = text_buffer.lines.char_count();
         for cursor in &mut self.set {
-            cursor.move_down(line_count, total_char_count, text_buffer);
+            cursor.move_down(line_count, total_char_count, &self.wraps, text_buffer);
             if !only_head {cursor.tail = cursor.head}
         }
         self.fuse_adjacent(text_buffer)
@@ -899,18 +1324,18 @@ impl TextCursorSet {
         for cursor in &mut self.set {
             cursor.move_left(char_count, text_buffer);
             if !only_head {cursor.tail = cursor.head}
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
         self.fuse_adjacent(text_buffer)
     }
     
     pub fn move_right(&mut self, char_count: usize, only_head: bool, text_buffer: &TextBuffer) {
         let mut old_max = (TextPos {row: 0, col: 0}, 0);
-        let total_char_count = text_buffer.calc_char_count();
+        let total_char_count = text_buffer.lines.char_count();
         for cursor in &mut self.set {
             cursor.move_right(char_count, total_char_count, text_buffer);
             if !only_head {cursor.tail = cursor.head}
-            old_max = cursor.calc_max(text_buffer, old_max);
+            old_max = cursor.calc_max(text_buffer, &self.wraps, old_max);
         }
         self.fuse_adjacent(text_buffer)
     }
@@ -1300,4 +1725,74 @@ impl DrawCursors {
         }
         return mark_spaces
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::*;
+    use crate::rusteditor::*;
+    
+    fn cursor_ranges(cursors: &TextCursorSet) -> Vec<(usize, usize)> {
+        cursors.set.iter().map( | cursor | cursor.order()).collect()
+    }
+    
+    #[test]
+    fn multi_cursor_commands() {
+        let mut text_buffer = TextBuffer::from_test_str("let foo = foo_bar;
+foo(foo);
+foo\n");
+        text_buffer.needs_token_chunks();
+        text_buffer.update_token_chunks(&mut *RustLanguage.new_tokenizer());
+        let mut cursors = TextCursorSet::new();
+        cursors.set[0] = TextCursor {head: 5, tail: 5, max: 0};
+        // the first one only grows over the token
+        cursors.add_next_occurrence(true, &text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(4, 7)]);
+        cursors.add_next_occurrence(true, &text_buffer);
+        cursors.add_next_occurrence(true, &text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(4, 7), (10, 13), (19, 22)]);
+        cursors.skip_occurrence(&text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(4, 7), (10, 13), (23, 26)]);
+        cursors.undo_cursor_addition(&text_buffer);
+        cursors.undo_cursor_addition(&text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(4, 7), (10, 13)]);
+        // backwards wraps around to the end
+        cursors.add_next_occurrence(true, &text_buffer);
+        cursors.add_next_occurrence(false, &text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(4, 7), (10, 13), (19, 22), (29, 32)]);
+        cursors.select_all_occurrences(&text_buffer);
+        assert_eq!(cursor_ranges(&cursors).len(), 5);
+        cursors.undo_cursor_addition(&text_buffer);
+        assert_eq!(cursor_ranges(&cursors).len(), 4);
+        
+        cursors.set = vec![TextCursor {head: 30, tail: 2, max: 0}];
+        cursors.last_cursor = 0;
+        cursors.split_into_lines(&text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(2, 18), (19, 28), (29, 30)]);
+        
+        cursors.clear_and_set_last_cursor_head_and_tail(6, &text_buffer);
+        cursors.add_cursor_vertical(false, &text_buffer);
+        cursors.add_cursor_vertical(false, &text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(6, 6), (25, 25), (32, 32)]);
+        assert!(!cursors.add_cursor_vertical(true, &text_buffer));
+        assert_eq!(cursor_ranges(&cursors).len(), 3);
+        cursors.undo_cursor_addition(&text_buffer);
+        assert_eq!(cursor_ranges(&cursors), vec![(6, 6), (25, 25)]);
+    }
+    
+    #[test]
+    fn split_into_lines_keeps_empty_lines() {
+        let mut text_buffer = TextBuffer::from_test_str("ab\n\n\ncd\nef");
+        let mut cursors = TextCursorSet::new();
+        cursors.set = vec![TextCursor {head: 6, tail: 1, max: 0}];
+        assert!(cursors.split_into_lines(&text_buffer));
+        assert_eq!(cursor_ranges(&cursors), vec![(1, 2), (3, 3), (4, 4), (5, 6)]);
+        assert_eq!(cursors.last_cursor, 3);
+        // ending at the start of a line leaves that one out
+        cursors.set = vec![TextCursor {head: 5, tail: 0, max: 0}];
+        cursors.last_cursor = 0;
+        assert!(cursors.split_into_lines(&text_buffer));
+        assert_eq!(cursor_ranges(&cursors), vec![(0, 2), (3, 3), (4, 4)]);
+    }
+}
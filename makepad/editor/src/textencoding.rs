@@ -0,0 +1,347 @@
+// How a file looked on disk, so saving it writes back the same bytes. Text is decoded to
+// plain \n lines for the editor, the encoding and line endings get kept next to it.
+// Bytes that arent valid utf8 are read as latin-1 which maps every byte to a char and
+// back. Files with nul bytes in them are utf16 without a BOM if the nuls sit in every other
+// byte, the rest are shown as a hex dump you can edit.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Utf16LeNoBom,
+    Utf16BeNoBom,
+    Latin1,
+    Hex
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        TextEncoding::Utf8
+    }
+}
+
+impl TextEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf8Bom => "UTF-8 with BOM",
+            TextEncoding::Utf16Le => "UTF-16 LE",
+            TextEncoding::Utf16Be => "UTF-16 BE",
+            TextEncoding::Utf16LeNoBom => "UTF-16 LE without BOM",
+            TextEncoding::Utf16BeNoBom => "UTF-16 BE without BOM",
+            TextEncoding::Latin1 => "Latin-1",
+            TextEncoding::Hex => "Hex"
+        }
+    }
+    
+    // whether its little endian, for the utf16 ones
+    fn utf16_le(&self) -> Option<bool> {
+        match self {
+            TextEncoding::Utf16Le | TextEncoding::Utf16LeNoBom => Some(true),
+            TextEncoding::Utf16Be | TextEncoding::Utf16BeNoBom => Some(false),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextLineEndings {
+    Lf,
+    CrLf,
+    // per row whether its followed by \r\n, kept in step with the rows by TextBuffer::replace_range
+    Mixed(Vec<bool>)
+}
+
+impl Default for TextLineEndings {
+    fn default() -> Self {
+        TextLineEndings::Lf
+    }
+}
+
+impl TextLineEndings {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextLineEndings::Lf => "LF",
+            TextLineEndings::CrLf => "CRLF",
+            TextLineEndings::Mixed(_) => "Mixed"
+        }
+    }
+    
+    pub fn is_crlf(&self, row: usize) -> bool {
+        match self {
+            TextLineEndings::Lf => false,
+            TextLineEndings::CrLf => true,
+            TextLineEndings::Mixed(crlf) => crlf.get(row).cloned().unwrap_or(false)
+        }
+    }
+    
+    // rows start_row..=end_row got replaced by new_rows rows
+    pub fn splice_rows(&mut self, start_row: usize, end_row: usize, new_rows: usize) {
+        if let TextLineEndings::Mixed(crlf) = self {
+            // the last row keeps the line ending that came after the replaced range,
+            // new line breaks take the one of the row the edit started in
+            let first = crlf[start_row];
+            let last = crlf[end_row];
+            crlf.splice(start_row..(end_row + 1), (0..new_rows).map( | i | if i == new_rows - 1 {last} else {first}));
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextDecoded {
+    pub text: String,
+    pub encoding: TextEncoding,
+    pub line_endings: TextLineEndings
+}
+
+pub fn decode_text(bytes: &[u8]) -> TextDecoded {
+    let (text, encoding) = decode_chars(bytes);
+    split_line_endings(text, encoding)
+}
+
+// the bytes read as encoding instead of the one we would guess, for converting a file explicitly
+pub fn decode_text_as(bytes: &[u8], encoding: TextEncoding) -> Result<TextDecoded, String> {
+    let text = match encoding {
+        TextEncoding::Utf8 | TextEncoding::Utf8Bom => {
+            let bytes = if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {&bytes[3..]} else {bytes};
+            std::str::from_utf8(bytes).map_err( | _ | format!("Cannot read the file as {}", encoding.name())) ?.to_string()
+        },
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be | TextEncoding::Utf16LeNoBom | TextEncoding::Utf16BeNoBom => {
+            let le = encoding.utf16_le() == Some(true);
+            let bom: &[u8] = if le {&[0xff, 0xfe]} else {&[0xfe, 0xff]};
+            let bytes = if bytes.starts_with(bom) {&bytes[2..]} else {bytes};
+            if bytes.len() % 2 != 0 {
+                return Err(format!("Cannot read the file as {}", encoding.name()))
+            }
+            String::from_utf16(&utf16_units(bytes, le)).map_err( | _ | format!("Cannot read the file as {}", encoding.name())) ?
+        },
+        TextEncoding::Latin1 => bytes.iter().map( | b | *b as char).collect(),
+        TextEncoding::Hex => hex_dump(bytes)
+    };
+    Ok(split_line_endings(text, encoding))
+}
+
+fn split_line_endings(text: String, encoding: TextEncoding) -> TextDecoded {
+    if encoding == TextEncoding::Hex {
+        return TextDecoded {text: text, encoding: encoding, line_endings: TextLineEndings::Lf}
+    }
+    let pieces: Vec<&str> = text.split('\n').collect();
+    let mut lines = Vec::new();
+    let mut crlf = Vec::new();
+    for (row, piece) in pieces.iter().enumerate() {
+        // a \r at the very end of the file isnt a line ending
+        if row + 1 < pieces.len() && piece.ends_with('\r') {
+            lines.push(&piece[0..piece.len() - 1]);
+            crlf.push(true);
+        }
+        else {
+            lines.push(piece);
+            crlf.push(false);
+        }
+    }
+    let breaks = &crlf[0..crlf.len() - 1];
+    let line_endings = if breaks.iter().all( | c | !c) {
+        TextLineEndings::Lf
+    }
+    else if breaks.iter().all( | c | *c) {
+        TextLineEndings::CrLf
+    }
+    else {
+        TextLineEndings::Mixed(crlf)
+    };
+    TextDecoded {
+        text: lines.join("\n"),
+        encoding: encoding,
+        line_endings: line_endings
+    }
+}
+
+fn decode_chars(bytes: &[u8]) -> (String, TextEncoding) {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        if let Ok(text) = std::str::from_utf8(&bytes[3..]) {
+            return (text.to_string(), TextEncoding::Utf8Bom)
+        }
+    }
+    if bytes.len() % 2 == 0 && (bytes.starts_with(&[0xff, 0xfe]) || bytes.starts_with(&[0xfe, 0xff])) {
+        let le = bytes[0] == 0xff;
+        if let Ok(text) = String::from_utf16(&utf16_units(&bytes[2..], le)) {
+            return (text, if le {TextEncoding::Utf16Le} else {TextEncoding::Utf16Be})
+        }
+        // broken surrogates, we wouldnt be able to write it back so fall through to hex
+    }
+    else if !bytes.contains(&0) {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            return (text.to_string(), TextEncoding::Utf8)
+        }
+        return (bytes.iter().map( | b | *b as char).collect(), TextEncoding::Latin1)
+    }
+    else if let Some(le) = guess_utf16_order(bytes) {
+        if let Ok(text) = String::from_utf16(&utf16_units(bytes, le)) {
+            // binary that happens to have its nuls lined up comes out as control chars
+            if !text.chars().any( | ch | ch.is_control() && !ch.is_whitespace()) {
+                return (text, if le {TextEncoding::Utf16LeNoBom} else {TextEncoding::Utf16BeNoBom})
+            }
+        }
+    }
+    (hex_dump(bytes), TextEncoding::Hex)
+}
+
+// utf16 without a BOM, mostly ascii text has a nul in every other byte and hardly any in the
+// bytes in between. Which side the nuls are on gives the byte order
+fn guess_utf16_order(bytes: &[u8]) -> Option<bool> {
+    if bytes.len() < 2 || bytes.len() % 2 != 0 {
+        return None
+    }
+    let sample = &bytes[0..bytes.len().min(4096)];
+    let units = sample.len() / 2;
+    let low_nuls = sample.iter().step_by(2).filter( | b | **b == 0).count();
+    let high_nuls = sample.iter().skip(1).step_by(2).filter( | b | **b == 0).count();
+    if high_nuls * 2 > units && low_nuls * 10 < units {
+        Some(true)
+    }
+    else if low_nuls * 2 > units && high_nuls * 10 < units {
+        Some(false)
+    }
+    else {
+        None
+    }
+}
+
+fn utf16_units(bytes: &[u8], le: bool) -> Vec<u16> {
+    bytes.chunks(2).map( | c | if le {u16::from_le_bytes([c[0], c[1]])} else {u16::from_be_bytes([c[0], c[1]])}).collect()
+}
+
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for (i, byte) in bytes.iter().enumerate() {
+        if i != 0 {
+            out.push(if i % 16 == 0 {'\n'} else {' '});
+        }
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+// text is with its line endings already put back, as TextBuffer::get_as_string gives it
+pub fn encode_text(text: &str, encoding: TextEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        TextEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        TextEncoding::Utf8Bom => {
+            let mut out = vec![0xef, 0xbb, 0xbf];
+            out.extend_from_slice(text.as_bytes());
+            Ok(out)
+        },
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be | TextEncoding::Utf16LeNoBom | TextEncoding::Utf16BeNoBom => {
+            let mut out = Vec::with_capacity(text.len() * 2 + 2);
+            let bom = if encoding == TextEncoding::Utf16Le || encoding == TextEncoding::Utf16Be {Some(0xfeff)} else {None};
+            for unit in bom.into_iter().chain(text.encode_utf16()) {
+                if encoding.utf16_le() == Some(true) {
+                    out.extend_from_slice(&unit.to_le_bytes());
+                }
+                else {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            Ok(out)
+        },
+        TextEncoding::Latin1 => {
+            let mut out = Vec::with_capacity(text.len());
+            for ch in text.chars() {
+                if ch as u32 > 0xff {
+                    return Err(format!("Cannot save as Latin-1, '{}' has no Latin-1 byte. Convert the file to UTF-8 first", ch))
+                }
+                out.push(ch as u8);
+            }
+            Ok(out)
+        },
+        TextEncoding::Hex => {
+            let mut out = Vec::with_capacity(text.len() / 3 + 1);
+            for word in text.split_whitespace() {
+                match u8::from_str_radix(word, 16) {
+                    Ok(byte) => out.push(byte),
+                    Err(_) => return Err(format!("Cannot save hex, '{}' is not a byte", word))
+                }
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn round_trip(bytes: &[u8]) -> TextDecoded {
+        let decoded = decode_text(bytes);
+        let mut text = String::new();
+        for (row, line) in decoded.text.split('\n').enumerate() {
+            if row != 0 {
+                text.push_str(if decoded.line_endings.is_crlf(row - 1) {"\r\n"} else {"\n"});
+            }
+            text.push_str(line);
+        }
+        assert_eq!(encode_text(&text, decoded.encoding).unwrap(), bytes);
+        decoded
+    }
+    
+    #[test]
+    fn detects_and_round_trips() {
+        let decoded = round_trip(b"\xef\xbb\xbfa\r\nb\r\n");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("a\nb\n", TextEncoding::Utf8Bom));
+        assert_eq!(decoded.line_endings, TextLineEndings::CrLf);
+        
+        let decoded = round_trip(b"a\r\nb\nc\r\n\r");
+        assert_eq!(decoded.text, "a\nb\nc\n\r");
+        assert_eq!(decoded.line_endings, TextLineEndings::Mixed(vec![true, false, true, false]));
+        
+        let decoded = round_trip(b"\xff\xfeh\x00\xe9\x00\n\x00");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("h\u{e9}\n", TextEncoding::Utf16Le));
+        let decoded = round_trip(b"\xfe\xff\xd8\x3d\xde\x00");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("\u{1f600}", TextEncoding::Utf16Be));
+        // without a BOM it goes by where the nuls are
+        let decoded = round_trip(b"h\x00i\x00\r\x00\n\x00\xe9\x00");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("hi\n\u{e9}", TextEncoding::Utf16LeNoBom));
+        let decoded = round_trip(b"\x00{\x00\n\x00\xe9\x00}");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("{\n\u{e9}}", TextEncoding::Utf16BeNoBom));
+        
+        let decoded = round_trip(b"caf\xe9\r\n");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("caf\u{e9}\n", TextEncoding::Latin1));
+        
+        let bytes: Vec<u8> = (0..40u8).collect();
+        let decoded = round_trip(&bytes);
+        assert_eq!(decoded.encoding, TextEncoding::Hex);
+        assert!(decoded.text.starts_with("00 01 02"));
+        assert_eq!(decoded.text.lines().count(), 3);
+        
+        assert_eq!(round_trip(b"").encoding, TextEncoding::Utf8);
+    }
+    
+    #[test]
+    fn decodes_as_a_given_encoding() {
+        let decoded = decode_text_as(b"caf\xc3\xa9\r\n", TextEncoding::Latin1).unwrap();
+        assert_eq!((decoded.text.as_str(), decoded.line_endings), ("caf\u{c3}\u{a9}\n", TextLineEndings::CrLf));
+        assert_eq!(decode_text_as(b"caf\xc3\xa9", TextEncoding::Utf8).unwrap().text, "caf\u{e9}");
+        assert!(decode_text_as(b"caf\xe9", TextEncoding::Utf8).is_err());
+        assert_eq!(decode_text_as(b"\xff\xfea\x00", TextEncoding::Utf16Le).unwrap().text, "a");
+        assert!(decode_text_as(b"a", TextEncoding::Utf16Be).is_err());
+        assert_eq!(decode_text_as(b"a\n", TextEncoding::Hex).unwrap().text, "61 0a");
+    }
+    
+    #[test]
+    fn unencodable_text_is_an_error() {
+        assert!(encode_text("\u{1f600}", TextEncoding::Latin1).is_err());
+        assert!(encode_text("00 zz", TextEncoding::Hex).is_err());
+    }
+    
+    #[test]
+    fn mixed_line_endings_follow_edits() {
+        let mut endings = TextLineEndings::Mixed(vec![true, false, true, false]);
+        // rows 1..=2 become 3 rows: the new breaks take row 1s ending, the last keeps row 2s
+        endings.splice_rows(1, 2, 3);
+        assert_eq!(endings, TextLineEndings::Mixed(vec![true, false, false, true, false]));
+        endings.splice_rows(0, 1, 1);
+        assert_eq!(endings, TextLineEndings::Mixed(vec![false, false, true, false]));
+    }
+}
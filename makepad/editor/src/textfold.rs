@@ -0,0 +1,395 @@
+use crate::textbuffer::*;
+use serde::*;
+
+// Per block code folding. The blocks come from the token chunks: a { whose pair closes on a
+// later row, or a run of comment lines. Folding a block hides the rows after its first row,
+// up to the row with the closing } which stays visible. A comment run hides all but its first row.
+// Folds are kept by the row they start on and move along with edits in TextBuffer::replace_range.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextFoldRange {
+    pub start_row: usize,
+    // rows start_row + 1..end_row hide when its folded
+    pub end_row: usize
+}
+
+#[derive(Clone, Default)]
+pub struct TextFolds {
+    // the foldable blocks sorted on start_row, one per row
+    pub ranges: Vec<TextFoldRange>,
+    // start rows of the folded blocks, sorted
+    pub folded: Vec<usize>,
+    // the rows hidden by the folded blocks as sorted start..end ranges that dont overlap
+    pub hidden: Vec<(usize, usize)>,
+    // what update_ranges keeps between tokenizes, in the rows of the last tokenize
+    _rows: Vec<TextFoldRow>,
+    _blocks: Vec<TextFoldRange>,
+    _comments: Vec<TextFoldRange>
+}
+
+#[derive(Clone, Copy, Default)]
+struct TextFoldRow {
+    // starts with a comment or sits inside a multiline one
+    comment: bool,
+    // a multiline comment is still open at the end of the row
+    in_comment: bool
+}
+
+// what gets remembered per file, only used again if the file is still exactly the same
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TextFoldState {
+    pub content_hash: u64,
+    pub folded: Vec<usize>
+}
+
+impl TextFolds {
+    // rows start_row..end_row just got tokenized again, the rows from end_row on are the old ones
+    // moved by the change in line count. Only the blocks and comment runs touching the
+    // relexed rows get scanned again, the rest is kept from the last time.
+    pub fn update_ranges(&mut self, flat_text: &[char], token_chunks: &[TokenChunk], checkpoints: &[TokenCheckpoint], start_row: usize, end_row: usize) {
+        let line_delta = checkpoints.len() as isize - self._rows.len() as isize;
+        let old_end_row = (end_row as isize - line_delta) as usize;
+        let shift = | range: &TextFoldRange | TextFoldRange {
+            start_row: (range.start_row as isize + line_delta) as usize,
+            end_row: (range.end_row as isize + line_delta) as usize
+        };
+        let row_token = | row: usize | checkpoints.get(row).map_or(token_chunks.len(), | checkpoint | checkpoint.token);
+        let token_row = | token: usize | checkpoints.partition_point( | checkpoint | checkpoint.token <= token) - 1;
+        
+        let mut in_comment = start_row > 0 && self._rows.get(start_row - 1).map_or(false, | row | row.in_comment);
+        let mut rows = Vec::with_capacity(end_row - start_row);
+        for row in start_row..end_row {
+            let mut comment = None;
+            for token_chunk in &token_chunks[row_token(row)..row_token(row + 1)] {
+                let token_type = token_chunk.token_type;
+                if comment.is_none() && token_type != TokenType::Whitespace {
+                    // rows inside a multiline comment count, even empty ones
+                    comment = Some(in_comment || match token_type {
+                        TokenType::CommentLine | TokenType::CommentMultiBegin | TokenType::CommentChunk | TokenType::CommentMultiEnd => true,
+                        _ => false
+                    });
+                }
+                match token_type {
+                    TokenType::CommentMultiBegin => in_comment = true,
+                    TokenType::CommentMultiEnd => in_comment = false,
+                    _ => ()
+                }
+            }
+            rows.push(TextFoldRow {comment: comment.unwrap_or(in_comment), in_comment: in_comment});
+        }
+        self._rows.splice(start_row..old_end_row.min(self._rows.len()), rows);
+        
+        // blocks closing above the relexed rows stay and the ones opening below them only move,
+        // the others are open at start_row or open in the relexed rows
+        let mut blocks = Vec::new();
+        for range in &self._blocks {
+            if range.end_row < start_row {
+                blocks.push(*range);
+            }
+            else if range.start_row >= old_end_row {
+                blocks.push(shift(range));
+            }
+        }
+        let open_at_start = checkpoints.get(start_row).map_or(Vec::new(), | checkpoint | TokenChunk::open_pairs(token_chunks, checkpoint.token, checkpoint.pair_depth));
+        for index in open_at_start.into_iter().chain(row_token(start_row)..row_token(end_row)) {
+            let token_chunk = &token_chunks[index];
+            if token_chunk.token_type != TokenType::ParenOpen || flat_text[token_chunk.offset] != '{' {
+                continue
+            }
+            let pair = token_chunk.pair_token;
+            if pair == index || pair >= token_chunks.len() || token_chunks[pair].token_type != TokenType::ParenClose {
+                continue
+            }
+            let (open_row, close_row) = (token_row(index), token_row(pair));
+            if close_row > open_row + 1 {
+                blocks.push(TextFoldRange {start_row: open_row, end_row: close_row});
+            }
+        }
+        blocks.sort_by_key( | range | range.start_row);
+        self._blocks = blocks;
+        
+        // the comment runs going through the relexed rows are found again, the rows
+        // just outside lo..hi are no comment so no other run can reach in
+        let mut lo = start_row.min(self._rows.len());
+        while lo > 0 && self._rows[lo - 1].comment {
+            lo -= 1;
+        }
+        let mut hi = end_row;
+        while hi < self._rows.len() && self._rows[hi].comment {
+            hi += 1;
+        }
+        let old_hi = hi as isize - line_delta;
+        let mut comments = Vec::new();
+        for range in &self._comments {
+            if range.end_row < lo {
+                comments.push(*range);
+            }
+        }
+        let mut run_start = lo;
+        for row in lo..=hi {
+            if row < hi && self._rows[row].comment {
+                continue
+            }
+            if row > run_start + 1 {
+                comments.push(TextFoldRange {start_row: run_start, end_row: row});
+            }
+            run_start = row + 1;
+        }
+        for range in &self._comments {
+            if range.start_row as isize > old_hi {
+                comments.push(shift(range));
+            }
+        }
+        self._comments = comments;
+        
+        // one block per row, the biggest
+        let mut ranges: Vec<TextFoldRange> = self._blocks.iter().chain(self._comments.iter()).cloned().collect();
+        ranges.sort_by( | a, b | a.start_row.cmp(&b.start_row).then(b.end_row.cmp(&a.end_row)));
+        ranges.dedup_by_key( | range | range.start_row);
+        self.set_ranges(ranges);
+    }
+    
+    // new blocks after tokenizing, folds whose block went away are dropped
+    pub fn set_ranges(&mut self, ranges: Vec<TextFoldRange>) {
+        self.ranges = ranges;
+        let ranges = &self.ranges;
+        self.folded.retain( | row | ranges.binary_search_by_key(row, | range | range.start_row).is_ok());
+        self.update_hidden();
+    }
+    
+    pub fn range_at(&self, start_row: usize) -> Option<TextFoldRange> {
+        match self.ranges.binary_search_by_key(&start_row, | range | range.start_row) {
+            Ok(index) => Some(self.ranges[index]),
+            Err(_) => None
+        }
+    }
+    
+    // the smallest block row sits in, that matches pred
+    fn innermost_range(&self, row: usize, pred: impl Fn(&TextFoldRange) -> bool) -> Option<TextFoldRange> {
+        self.ranges.iter().filter( | range | range.start_row <= row && row < range.end_row && pred(range)).last().cloned()
+    }
+    
+    pub fn is_folded(&self, start_row: usize) -> bool {
+        self.folded.binary_search(&start_row).is_ok()
+    }
+    
+    pub fn is_hidden(&self, row: usize) -> bool {
+        match self.hidden.binary_search_by_key(&row, | (start, _) | *start) {
+            Ok(_) => true,
+            Err(0) => false,
+            Err(index) => row < self.hidden[index - 1].1
+        }
+    }
+    
+    pub fn fold(&mut self, start_row: usize) -> bool {
+        if self.range_at(start_row).is_none() {
+            return false
+        }
+        match self.folded.binary_search(&start_row) {
+            Ok(_) => false,
+            Err(index) => {
+                self.folded.insert(index, start_row);
+                self.update_hidden();
+                true
+            }
+        }
+    }
+    
+    pub fn unfold(&mut self, start_row: usize) -> bool {
+        match self.folded.binary_search(&start_row) {
+            Ok(index) => {
+                self.folded.remove(index);
+                self.update_hidden();
+                true
+            },
+            Err(_) => false
+        }
+    }
+    
+    pub fn toggle(&mut self, start_row: usize) -> bool {
+        if self.is_folded(start_row) {self.unfold(start_row)} else {self.fold(start_row)}
+    }
+    
+    // folds the innermost open block around row
+    pub fn fold_at(&mut self, row: usize) -> bool {
+        let folded = &self.folded;
+        match self.innermost_range(row, | range | folded.binary_search(&range.start_row).is_err()) {
+            Some(range) => self.fold(range.start_row),
+            None => false
+        }
+    }
+    
+    // opens the innermost folded block around row
+    pub fn unfold_at(&mut self, row: usize) -> bool {
+        let folded = &self.folded;
+        match self.innermost_range(row, | range | folded.binary_search(&range.start_row).is_ok()) {
+            Some(range) => self.unfold(range.start_row),
+            None => false
+        }
+    }
+    
+    pub fn fold_all(&mut self) -> bool {
+        let folded: Vec<usize> = self.ranges.iter().map( | range | range.start_row).collect();
+        if folded == self.folded {
+            return false
+        }
+        self.folded = folded;
+        self.update_hidden();
+        true
+    }
+    
+    pub fn unfold_all(&mut self) -> bool {
+        if self.folded.len() == 0 {
+            return false
+        }
+        self.folded.truncate(0);
+        self.update_hidden();
+        true
+    }
+    
+    // opens every fold that hides row
+    pub fn reveal(&mut self, row: usize) -> bool {
+        if !self.is_hidden(row) {
+            return false
+        }
+        let ranges = &self.ranges;
+        self.folded.retain( | start_row | {
+            match ranges.binary_search_by_key(start_row, | range | range.start_row) {
+                Ok(index) => !(*start_row < row && row < ranges[index].end_row),
+                Err(_) => true
+            }
+        });
+        self.update_hidden();
+        true
+    }
+    
+    // rows start_row..=old_end_row got replaced by start_row..=new_end_row
+    pub fn shift_rows(&mut self, start_row: usize, old_end_row: usize, new_end_row: usize) {
+        if self.ranges.len() == 0 && self.folded.len() == 0 {
+            return
+        }
+        let shift = | row: usize | if row > old_end_row {
+            Some((row as isize + new_end_row as isize - old_end_row as isize) as usize)
+        }
+        else if row > start_row {
+            None
+        }
+        else {
+            Some(row)
+        };
+        self.folded = self.folded.iter().filter_map( | row | shift(*row)).collect();
+        // the tokenizer fixes up the ranges on the next draw, until then keep them roughly right
+        self.ranges = self.ranges.iter().filter_map( | range | {
+            let start = shift(range.start_row) ?;
+            let end = shift(range.end_row).unwrap_or(new_end_row);
+            Some(TextFoldRange {start_row: start, end_row: end.max(start + 1)})
+        }).collect();
+        self.update_hidden();
+    }
+    
+    pub fn get_state(&self, content_hash: u64) -> TextFoldState {
+        TextFoldState {
+            content_hash: content_hash,
+            folded: self.folded.clone()
+        }
+    }
+    
+    pub fn restore_state(&mut self, state: Option<&TextFoldState>, content_hash: u64) {
+        if let Some(state) = state {
+            if state.content_hash == content_hash {
+                self.folded = state.folded.clone();
+                self.update_hidden();
+            }
+        }
+    }
+    
+    fn update_hidden(&mut self) {
+        self.hidden.truncate(0);
+        for start_row in &self.folded {
+            if let Some(range) = self.range_at(*start_row) {
+                if range.end_row <= range.start_row + 1 {
+                    continue
+                }
+                match self.hidden.last_mut() {
+                    Some(last) if last.1 >= range.start_row + 1 => last.1 = last.1.max(range.end_row),
+                    _ => self.hidden.push((range.start_row + 1, range.end_row))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textcursor::*;
+    use crate::rusteditor::RustTokenizer;
+    
+    const SOURCE: &str = "// one\n// two\nfn main() {\n    if x {\n        a();\n    }\n    /* multi\n\n    line */\n    b();\n}\nstruct A {x: u32}\n";
+    
+    fn folds_for(text: &str) -> TextBuffer {
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        text_buffer
+    }
+    
+    #[test]
+    fn ranges_come_from_braces_and_comments() {
+        let text_buffer = folds_for(SOURCE);
+        let ranges: Vec<(usize, usize)> = text_buffer.folds.ranges.iter().map( | range | (range.start_row, range.end_row)).collect();
+        assert_eq!(ranges, vec![(0, 2), (2, 10), (3, 5), (6, 9)]);
+    }
+    
+    #[test]
+    fn edits_only_rescan_and_still_match_a_full_scan() {
+        let mut text_buffer = folds_for(SOURCE);
+        let edits: &[(&str, usize, &str)] = &[
+            ("    if x {", 0, "    let y = {\n        1\n    };\n"),
+            ("        a();", 0, "/* open\n"),
+            ("    b();", 0, "*/\n"),
+            ("/* open", 8, ""),
+            ("struct A", 0, "// three\n// four\n"),
+            ("fn main", 12, "}\n"),
+            ("// one", 0, "{\n\n"),
+        ];
+        for (at, len, string) in edits {
+            let offset = text_buffer.get_as_string().find(at).unwrap();
+            text_buffer.replace_lines_with_string(offset, *len, string);
+            text_buffer.update_token_chunks(&mut RustTokenizer::new());
+            let full = folds_for(&text_buffer.get_as_string());
+            assert_eq!(text_buffer.folds.ranges, full.folds.ranges, "after {:?}", string);
+        }
+    }
+    
+    #[test]
+    fn folds_hide_rows_and_follow_edits() {
+        let mut text_buffer = folds_for(SOURCE);
+        assert!(text_buffer.folds.fold_at(4));
+        assert_eq!(text_buffer.folds.folded, vec![3]);
+        assert!(text_buffer.folds.is_hidden(4) && !text_buffer.folds.is_hidden(5));
+        assert!(text_buffer.folds.fold(2));
+        assert_eq!(text_buffer.folds.hidden, vec![(3, 10)]);
+        
+        // cursors skip the folded rows
+        let mut cursors = TextCursorSet::new();
+        cursors.move_down(2, false, &text_buffer);
+        cursors.move_down(1, false, &text_buffer);
+        assert_eq!(text_buffer.offset_to_text_pos(cursors.set[0].head).row, 10);
+        cursors.move_up(1, false, &text_buffer);
+        assert_eq!(text_buffer.offset_to_text_pos(cursors.set[0].head).row, 2);
+        
+        // two lines on top move the folds down, retokenizing keeps them
+        text_buffer.replace_lines_with_string(0, 0, "use a;\nuse b;\n");
+        assert_eq!(text_buffer.folds.folded, vec![4, 5]);
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        assert_eq!(text_buffer.folds.folded, vec![4, 5]);
+        assert!(text_buffer.folds.reveal(6));
+        assert_eq!(text_buffer.folds.folded, Vec::<usize>::new());
+        
+        let state = TextFoldState {content_hash: 1, folded: vec![2]};
+        text_buffer.folds.restore_state(Some(&state), 2);
+        assert!(!text_buffer.folds.is_folded(2));
+        text_buffer.folds.restore_state(Some(&state), 1);
+        assert!(text_buffer.folds.is_folded(2));
+    }
+}
@@ -0,0 +1,293 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::textmerge::*;
+use crate::language::*;
+
+// Formatting a whole buffer. A FormatProvider hands back all of the text formatted, the way
+// the tool everyone else uses does it, TextCursorSet::replace_lines_diffed then only swaps
+// the lines that changed. When there is no provider or its tool isnt installed the built-in
+// auto_format of the Language is used. When the tool refuses the text, usually because it
+// doesnt parse, its errors show up as messages in the buffer and nothing gets formatted.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatError {
+    NotInstalled(String),
+    // the output of the tool, rustc style errors for rustfmt
+    Failed(String)
+}
+
+pub trait FormatProvider {
+    // path is where the file lives, tools look for their config from there
+    fn format(&self, path: &str, text: &str) -> Result<String, FormatError>;
+}
+
+// rustfmt on stdin, run in the directory of the file so it finds rustfmt.toml like cargo fmt does
+pub struct Rustfmt {
+    pub command: String
+}
+
+impl Default for Rustfmt {
+    fn default() -> Rustfmt {
+        Rustfmt {command: "rustfmt".to_string()}
+    }
+}
+
+impl FormatProvider for Rustfmt {
+    fn format(&self, path: &str, text: &str) -> Result<String, FormatError> {
+        let dir = Path::new(path).parent().filter( | dir | dir.is_dir()).unwrap_or(Path::new("."));
+        let mut command = Command::new(&self.command);
+        command.args(&["--emit", "stdout"]);
+        // on stdin rustfmt assumes 2015, cargo fmt passes the one of the crate
+        if let Some(edition) = cargo_edition(dir) {
+            command.args(&["--edition", &edition]);
+        }
+        let mut child = command
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err( | err | {
+                let message = format!("Cannot run {}: {}", self.command, err);
+                if err.kind() == std::io::ErrorKind::NotFound {FormatError::NotInstalled(message)} else {FormatError::Failed(message)}
+            }) ?;
+        {
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(text.as_bytes()).map_err( | err | FormatError::Failed(format!("Cannot write to {}: {}", self.command, err))) ?;
+        }
+        let output = child.wait_with_output().map_err( | err | FormatError::Failed(format!("Cannot run {}: {}", self.command, err))) ?;
+        if !output.status.success() {
+            return Err(FormatError::Failed(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+        }
+        String::from_utf8(output.stdout).map_err( | _ | FormatError::Failed(format!("{} gave back something that isnt utf8", self.command)))
+    }
+}
+
+// the edition in the nearest Cargo.toml up from dir, 2015 if it doesnt say
+pub fn cargo_edition(dir: &Path) -> Option<String> {
+    for dir in dir.ancestors() {
+        if let Ok(toml) = std::fs::read_to_string(dir.join("Cargo.toml")) {
+            for line in toml.lines() {
+                let mut parts = line.splitn(2, '=');
+                if parts.next().map( | key | key.trim()) == Some("edition") {
+                    let value = parts.next().unwrap_or("").trim().trim_matches('"');
+                    if value.len() > 0 && value.chars().all( | c | c.is_ascii_digit()) {
+                        return Some(value.to_string())
+                    }
+                }
+            }
+            return Some("2015".to_string())
+        }
+    }
+    None
+}
+
+// the buffer formatted by the provider of the language, or its built-in formatter otherwise.
+// None when there was nothing to format with or the provider refused, then the buffer has
+// messages saying why
+pub fn format_text_buffer(language: &dyn Language, path: &str, text_buffer: &mut TextBuffer) -> Option<String> {
+    format_with(language.format_provider().as_deref(), language, path, text_buffer)
+}
+
+fn format_with(provider: Option<&dyn FormatProvider>, language: &dyn Language, path: &str, text_buffer: &mut TextBuffer) -> Option<String> {
+    if let Some(provider) = provider {
+        match provider.format(path, &text_buffer.get_as_lf_string()) {
+            Ok(text) => return Some(text),
+            Err(FormatError::NotInstalled(_)) => (),
+            Err(FormatError::Failed(output)) => {
+                set_format_messages(&output, text_buffer);
+                return None
+            }
+        }
+    }
+    let out_lines = language.auto_format(text_buffer) ?;
+    let out_lines: Vec<String> = out_lines.iter().map( | line | line.iter().collect()).collect();
+    Some(out_lines.join("\n"))
+}
+
+// the errors of a rustc style output on the rows they point at, next to messages still valid for
+// this text. Output without a location goes on the first row as a whole
+fn set_format_messages(output: &str, text_buffer: &mut TextBuffer) {
+    let mut messages: Vec<(TextPos, TextBufferMessage)> = Vec::new();
+    for line in output.lines() {
+        // error: or error[E0000]: starts one
+        let level = if line.starts_with("error") {Some(TextBufferMessageLevel::Error)}
+        else if line.starts_with("warning") {Some(TextBufferMessageLevel::Warning)} else {None};
+        if let (Some(level), Some(colon)) = (level, line.find(": ")) {
+            messages.push((TextPos {row: 0, col: 0}, TextBufferMessage {
+                level: level,
+                body: line[colon + 2..].to_string(),
+                detail: Some(line.to_string()),
+                fixes: Vec::new(),
+                source: TextBufferMessageSource::Format
+            }));
+            continue;
+        }
+        if let Some((pos, message)) = messages.last_mut() {
+            if let Some(location) = line.trim_start().strip_prefix("--> ") {
+                // --> <stdin>:2:12, rows and columns from 1
+                let mut parts = location.rsplitn(3, ':');
+                let col = parts.next().and_then( | col | col.parse::<usize>().ok());
+                let row = parts.next().and_then( | row | row.parse::<usize>().ok());
+                if let (Some(row), Some(col)) = (row, col) {
+                    *pos = TextPos {row: row.max(1) - 1, col: col.max(1) - 1};
+                }
+            }
+            if let Some(detail) = &mut message.detail {
+                detail.push('\n');
+                detail.push_str(line);
+            }
+        }
+    }
+    if messages.len() == 0 {
+        messages.push((TextPos {row: 0, col: 0}, TextBufferMessage {
+            level: TextBufferMessageLevel::Error,
+            body: output.lines().next().unwrap_or("Cannot format").to_string(),
+            detail: if output.lines().count() > 1 {Some(output.to_string())} else {None},
+            fixes: Vec::new(),
+            source: TextBufferMessageSource::Format
+        }));
+    }
+    let messages = messages.into_iter().map( | (pos, message) | {
+        let offset = text_buffer.text_pos_to_offset(pos);
+        (TextCursor {head: offset, tail: offset, max: 0}, message)
+    }).collect();
+    text_buffer.messages.set_source(TextBufferMessageSource::Format, text_buffer.mutation_id, messages);
+}
+
+// where a row ends up after the hunks got applied, rows inside a change stay at the same
+// line of it as far as it goes
+pub fn map_row_through_hunks(hunks: &[DiffHunk], row: usize) -> usize {
+    let mut delta = 0isize;
+    for hunk in hunks {
+        if row < hunk.base_start {
+            break
+        }
+        if row < hunk.base_end {
+            let other_len = hunk.other_end - hunk.other_start;
+            return hunk.other_start + (row - hunk.base_start).min(other_len.max(1) - 1)
+        }
+        delta = hunk.other_end as isize - hunk.base_end as isize;
+    }
+    (row as isize + delta) as usize
+}
+
+// the same for a position, a line that got re-indented keeps the cursor on the same char
+pub fn map_pos_through_hunks(hunks: &[DiffHunk], base: &[&str], other: &[&str], pos: TextPos) -> TextPos {
+    let row = map_row_through_hunks(hunks, pos.row).min(other.len().max(1) - 1);
+    let (old_line, new_line) = match (base.get(pos.row), other.get(row)) {
+        (Some(old_line), Some(new_line)) => (*old_line, *new_line),
+        _ => return TextPos {row: row, col: 0}
+    };
+    let indent = | line: &str | line.chars().take_while( | c | *c == ' ' || *c == '\t').count();
+    let old_indent = indent(old_line);
+    let col = if pos.col >= old_indent {pos.col - old_indent + indent(new_line)} else {pos.col};
+    TextPos {row: row, col: col.min(new_line.chars().count())}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rusteditor::*;
+    
+    fn buffer_with_cursor(text: &str, pos: TextPos) -> (TextBuffer, TextCursorSet) {
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        let mut cursors = TextCursorSet::new();
+        let offset = text_buffer.text_pos_to_offset(pos);
+        cursors.select_ranges(&[(offset, offset)], 0, &text_buffer);
+        (text_buffer, cursors)
+    }
+    
+    #[test]
+    fn replaces_only_changed_lines_as_one_undo() {
+        let text = "fn a() {\nlet x = 1;\n}\n\nfn b() {\n    let y  =  2;\n}";
+        let formatted = "fn a() {\n    let x = 1;\n}\n\nfn b() {\n    let y = 2;\n}\n";
+        // the cursor sits on the 1 of line 1
+        let (mut text_buffer, mut cursors) = buffer_with_cursor(text, TextPos {row: 1, col: 8});
+        let hunks = cursors.replace_lines_diffed(formatted, &mut text_buffer);
+        assert_eq!(hunks.len(), 3);
+        assert_eq!(text_buffer.get_as_string(), formatted);
+        assert_eq!(text_buffer.offset_to_text_pos(cursors.set[0].head), TextPos {row: 1, col: 12});
+        
+        assert!(cursors.replace_lines_diffed(formatted, &mut text_buffer).is_empty());
+        text_buffer.undo(true, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), text);
+        assert!(!text_buffer.undo_tree.can_undo());
+        
+        // whole buffer and removed tail lines
+        for (text, formatted) in &[("x", "y"), ("a\nb\nc", "a"), ("a", "a\nb\n"), ("a\nb", "b\nb")] {
+            let (mut text_buffer, mut cursors) = buffer_with_cursor(text, TextPos {row: 0, col: 1});
+            cursors.replace_lines_diffed(formatted, &mut text_buffer);
+            assert_eq!(text_buffer.get_as_string(), *formatted);
+        }
+    }
+    
+    #[test]
+    fn maps_rows_past_changes() {
+        let hunks = vec![
+            DiffHunk {base_start: 1, base_end: 3, other_start: 1, other_end: 2},
+            DiffHunk {base_start: 5, base_end: 5, other_start: 4, other_end: 7}
+        ];
+        let rows: Vec<usize> = (0..7).map( | row | map_row_through_hunks(&hunks, row)).collect();
+        assert_eq!(rows, vec![0, 1, 1, 2, 3, 7, 8]);
+    }
+    
+    fn rust_buffer(text: &str) -> TextBuffer {
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        text_buffer.update_token_chunks(&mut RustTokenizer::new());
+        text_buffer
+    }
+    
+    #[test]
+    fn builtin_formatter_is_the_fallback_without_rustfmt() {
+        let missing = Rustfmt {command: "makepad-no-such-rustfmt".to_string()};
+        assert!(matches!(missing.format("lib.rs", "fn a() {}"), Err(FormatError::NotInstalled(_))));
+        let mut text_buffer = rust_buffer("fn a() {\nlet x = 1;\n}");
+        let formatted = format_with(Some(&missing), &RustLanguage, "lib.rs", &mut text_buffer).unwrap();
+        assert_eq!(formatted, "fn a() {\n    let x = 1;\n}");
+    }
+    
+    struct Refuses(&'static str);
+    
+    impl FormatProvider for Refuses {
+        fn format(&self, _path: &str, _text: &str) -> Result<String, FormatError> {
+            Err(FormatError::Failed(self.0.to_string()))
+        }
+    }
+    
+    #[test]
+    fn refused_text_gets_messages_and_no_formatting() {
+        let output = "error: this file contains an unclosed delimiter\n --> <stdin>:2:11\n  |\n1 | fn a() {\n  |        - unclosed delimiter\n2 | let x = 1;\n  |           ^\n";
+        let mut text_buffer = rust_buffer("fn a() {\nlet x = 1;\n");
+        assert_eq!(format_with(Some(&Refuses(output)), &RustLanguage, "lib.rs", &mut text_buffer), None);
+        let messages = &text_buffer.messages;
+        assert_eq!(messages.mutation_id, text_buffer.mutation_id);
+        assert_eq!(messages.cursors.len(), 1);
+        assert_eq!(messages.cursors[0].head, 19);
+        assert_eq!(messages.bodies[0].body, "this file contains an unclosed delimiter");
+        assert_eq!(messages.bodies[0].level, TextBufferMessageLevel::Error);
+        assert!(messages.bodies[0].detail.as_ref().unwrap().contains("unclosed delimiter\n --> <stdin>:2:11"));
+        // without a location it goes on top
+        let mut text_buffer = rust_buffer("fn a() {}\n");
+        assert_eq!(format_with(Some(&Refuses("Cannot run rustfmt: denied")), &RustLanguage, "lib.rs", &mut text_buffer), None);
+        assert_eq!(text_buffer.messages.cursors[0].head, 0);
+        assert_eq!(text_buffer.messages.bodies[0].body, "Cannot run rustfmt: denied");
+    }
+    
+    #[test]
+    #[ignore = "needs rustfmt, run with --ignored"]
+    fn rustfmt_respects_rustfmt_toml() {
+        let dir = std::env::temp_dir().join(format!("makepad_rustfmt_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"a\"\nedition = \"2018\"\n").unwrap();
+        std::fs::write(dir.join("rustfmt.toml"), "hard_tabs = true\n").unwrap();
+        let path = dir.join("src").join("lib.rs");
+        assert_eq!(cargo_edition(&dir.join("src")), Some("2018".to_string()));
+        let result = Rustfmt::default().format(path.to_str().unwrap(), "async fn a(){if x {1}}\n");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(result, Ok("async fn a() {\n\tif x {\n\t\t1\n\t}\n}\n".to_string()));
+    }
+}
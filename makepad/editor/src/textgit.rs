@@ -0,0 +1,373 @@
+use render::*;
+use git::*;
+use crate::textencoding::*;
+use crate::textmerge::*;
+use crate::textrope::*;
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+// What git knows about the file of a TextBuffer. The text in HEAD and the index get read on a
+// thread when the file loads, TextBuffers picks them up when the signal comes. The rows are
+// diffed against HEAD again after the text changed, the blame is asked for a line at a time
+// once the cursor sits on it.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextGitRow {
+    Unchanged,
+    Added,
+    Modified,
+    // rows of HEAD went missing right above this one
+    DeletedAbove
+}
+
+impl Default for TextGitRow {
+    fn default() -> TextGitRow {
+        TextGitRow::Unchanged
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TextGit {
+    // None when the file is not in HEAD or not in a repository at all, decoded to \n lines
+    pub head_text: Option<String>,
+    pub index_text: Option<String>,
+    // how the index copy was stored, staging a hunk writes the lines it keeps back the same way
+    pub index_encoding: TextEncoding,
+    pub index_line_endings: TextLineEndings,
+    // by line of head_text, None while git is still busy with it or has nothing to say
+    pub blame: HashMap<usize, Option<GitBlameLine>>,
+    // head_text lines to buffer rows
+    pub hunks: Vec<DiffHunk>,
+    pub rows: Vec<TextGitRow>,
+    pub mutation_id: u64,
+    // where the reads go, results of an older load get dropped
+    pub loader: Option<TextGitLoader>,
+    pub path: String,
+    pub load_id: u64
+}
+
+#[derive(Clone)]
+pub struct TextGitLoader {
+    pub repo: GitRepo,
+    pub sender: mpsc::Sender<TextGitLoad>,
+    pub signal: Signal
+}
+
+pub enum TextGitLoad {
+    Texts {path: String, load_id: u64, head: Option<TextDecoded>, index: Option<TextDecoded>},
+    Blame {path: String, load_id: u64, head_row: usize, blame: Option<GitBlameLine>}
+}
+
+impl TextGitLoader {
+    fn spawn<F>(&self, read: F)
+    where F: FnOnce(&GitRepo) -> TextGitLoad + Send + 'static
+    {
+        let loader = self.clone();
+        std::thread::spawn(move || {
+            let _ = loader.sender.send(read(&loader.repo));
+            Cx::post_signal(loader.signal, 0);
+        });
+    }
+}
+
+impl TextGit {
+    pub fn load(&mut self, loader: &TextGitLoader, path: &str) {
+        self.loader = Some(loader.clone());
+        self.path = path.to_string();
+        self.load_id += 1;
+        let (path, load_id) = (self.path.clone(), self.load_id);
+        loader.spawn(move | repo | TextGitLoad::Texts {
+            head: repo.head_blob(&path).map( | data | decode_text(&data)),
+            index: repo.index_blob(&path).map( | data | decode_text(&data)),
+            path: path,
+            load_id: load_id
+        });
+    }
+    
+    // what a load came back with, false if its for an older one
+    pub fn apply_load(&mut self, load: TextGitLoad) -> bool {
+        match load {
+            TextGitLoad::Texts {load_id, head, index, ..} => {
+                if load_id != self.load_id {
+                    return false
+                }
+                self.head_text = head.map( | head | head.text);
+                match index {
+                    Some(index) => {
+                        self.index_text = Some(index.text);
+                        self.index_encoding = index.encoding;
+                        self.index_line_endings = index.line_endings;
+                    },
+                    None => {
+                        self.index_text = None;
+                        self.index_encoding = TextEncoding::default();
+                        self.index_line_endings = TextLineEndings::default();
+                    }
+                }
+                self.blame.clear();
+                // diff again on the next update
+                self.mutation_id = 0;
+            },
+            TextGitLoad::Blame {load_id, head_row, blame, ..} => {
+                if load_id != self.load_id {
+                    return false
+                }
+                self.blame.insert(head_row, blame);
+            }
+        }
+        true
+    }
+    
+    // the rows are from before the last edit
+    pub fn is_stale(&self, mutation_id: u64) -> bool {
+        self.mutation_id != mutation_id
+    }
+    
+    pub fn update(&mut self, lines: &TextRope, mutation_id: u64) {
+        if self.mutation_id == mutation_id {
+            return
+        }
+        self.mutation_id = mutation_id;
+        self.hunks.truncate(0);
+        self.rows.truncate(0);
+        if let Some(head_text) = &self.head_text {
+            let rows = rope_strings(lines);
+            let rows: Vec<&str> = rows.iter().map( | row | row.as_str()).collect();
+            let head: Vec<&str> = head_text.split('\n').collect();
+            self.hunks = diff_lines(&head, &rows);
+            self.rows = TextGit::compute_rows(&self.hunks, rows.len());
+        }
+    }
+    
+    pub fn compute_rows(hunks: &[DiffHunk], row_count: usize) -> Vec<TextGitRow> {
+        let mut rows = vec![TextGitRow::Unchanged; row_count];
+        for hunk in hunks {
+            let status = if hunk.base_start == hunk.base_end {TextGitRow::Added} else {TextGitRow::Modified};
+            for row in hunk.other_start..hunk.other_end {
+                rows[row] = status;
+            }
+            if hunk.other_start == hunk.other_end && hunk.other_start < row_count {
+                rows[hunk.other_start] = TextGitRow::DeletedAbove;
+            }
+        }
+        rows
+    }
+    
+    pub fn row(&self, row: usize) -> TextGitRow {
+        self.rows.get(row).cloned().unwrap_or_default()
+    }
+    
+    // the change the row is part of, a deletion belongs to the row under it
+    pub fn hunk_at(&self, row: usize) -> Option<DiffHunk> {
+        find_hunk(&self.hunks, row)
+    }
+    
+    // the line in HEAD an unchanged row came from
+    pub fn head_row(&self, row: usize) -> Option<usize> {
+        let mut delta = 0isize;
+        for hunk in &self.hunks {
+            if row < hunk.other_start {
+                break
+            }
+            if row < hunk.other_end {
+                return None
+            }
+            delta = hunk.base_end as isize - hunk.other_end as isize;
+        }
+        Some((row as isize + delta) as usize)
+    }
+    
+    // asks git the first time, the blame is there after the load signal
+    pub fn blame_at(&mut self, row: usize) -> Option<GitBlameLine> {
+        if self.head_text.is_none() {
+            return None
+        }
+        let head_row = self.head_row(row) ?;
+        if !self.blame.contains_key(&head_row) {
+            self.blame.insert(head_row, None);
+            if let Some(loader) = &self.loader {
+                let (path, load_id) = (self.path.clone(), self.load_id);
+                loader.spawn(move | repo | TextGitLoad::Blame {
+                    blame: repo.blame_line(&path, head_row).ok().flatten(),
+                    path: path,
+                    load_id: load_id,
+                    head_row: head_row
+                });
+            }
+        }
+        self.blame.get(&head_row).cloned().flatten()
+    }
+    
+    // the rows of the change at row and the HEAD lines that go in their place
+    pub fn revert_lines(&self, row: usize) -> Option<(usize, usize, Vec<String>)> {
+        let hunk = self.hunk_at(row) ?;
+        let head_text = self.head_text.as_ref() ?;
+        let head_lines = head_text.split('\n').skip(hunk.base_start).take(hunk.base_end - hunk.base_start).map( | line | line.to_string()).collect();
+        Some((hunk.other_start, hunk.other_end, head_lines))
+    }
+    
+    // the index with only the change at row taken over from the buffer, as the bytes to stage.
+    // Lines kept from the index keep their line endings, the ones from the buffer take the buffers,
+    // and all of it is written in the buffers encoding the way saving the file would
+    pub fn stage_hunk(&self, row: usize, lines: &TextRope, encoding: TextEncoding, line_endings: &TextLineEndings) -> Result<Vec<u8>, String> {
+        if self.index_encoding == TextEncoding::Hex || encoding == TextEncoding::Hex {
+            return Err("Cannot stage part of a binary file".to_string())
+        }
+        let rows = rope_strings(lines);
+        let rows: Vec<&str> = rows.iter().map( | row | row.as_str()).collect();
+        // a file that isnt staged at all yet starts out empty
+        let index_text = self.index_text.as_ref().map( | text | text.as_str()).unwrap_or("");
+        let index: Vec<&str> = if index_text.len() == 0 {Vec::new()} else {index_text.split('\n').collect()};
+        let hunk = find_hunk(&diff_lines(&index, &rows), row).ok_or("No unstaged change here".to_string()) ?;
+        let mut staged: Vec<(&str, bool)> = Vec::new();
+        staged.extend((0..hunk.base_start).map( | i | (index[i], self.index_line_endings.is_crlf(i))));
+        staged.extend((hunk.other_start..hunk.other_end).map( | i | (rows[i], line_endings.is_crlf(i))));
+        staged.extend((hunk.base_end..index.len()).map( | i | (index[i], self.index_line_endings.is_crlf(i))));
+        let mut text = String::new();
+        for (i, (line, crlf)) in staged.iter().enumerate() {
+            text.push_str(line);
+            if i != staged.len() - 1 {
+                text.push_str(if *crlf {"\r\n"} else {"\n"});
+            }
+        }
+        encode_text(&text, encoding)
+    }
+}
+
+fn find_hunk(hunks: &[DiffHunk], row: usize) -> Option<DiffHunk> {
+    hunks.iter().find( | hunk | row >= hunk.other_start && row < hunk.other_end || row == hunk.other_start && hunk.other_start == hunk.other_end).cloned()
+}
+
+fn rope_strings(lines: &TextRope) -> Vec<String> {
+    lines.iter().map( | line | line.iter().collect()).collect()
+}
+
+// how long ago a commit was, the way people say it
+pub fn format_git_age(seconds: u64) -> String {
+    let units = [(365 * 24 * 3600, "year"), (30 * 24 * 3600, "month"), (7 * 24 * 3600, "week"), (24 * 3600, "day"), (3600, "hour"), (60, "minute")];
+    for (size, name) in units.iter() {
+        let count = seconds / size;
+        if count > 0 {
+            return format!("{} {}{} ago", count, name, if count > 1 {"s"} else {""})
+        }
+    }
+    "just now".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbuffer::*;
+    use crate::textcursor::*;
+    
+    fn text_git(head: &str, index: &str, blame: &[&str]) -> TextGit {
+        TextGit {
+            head_text: Some(head.to_string()),
+            index_text: Some(index.to_string()),
+            blame: blame.iter().enumerate().map( | (row, summary) | (row, Some(GitBlameLine {summary: summary.to_string(), ..Default::default()}))).collect(),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn rows_follow_the_edits_against_head() {
+        let head = "a\nb\nc\nd\n";
+        let mut git = text_git(head, head, &["1", "2", "3", "4", ""]);
+        let mut text_buffer = TextBuffer::from_test_str("a\nB\nc\nd\nnew\n");
+        git.update(&text_buffer.lines, 1);
+        use TextGitRow::*;
+        assert_eq!(git.rows, vec![Unchanged, Modified, Unchanged, Unchanged, Added, Unchanged]);
+        assert_eq!(git.blame_at(0).unwrap().summary, "1");
+        assert_eq!(git.blame_at(1), None);
+        assert_eq!(git.blame_at(3).unwrap().summary, "4");
+        assert_eq!(git.blame_at(4), None);
+        assert_eq!(git.head_row(5), Some(4));
+        
+        text_buffer.load_bytes("a\nc\nd\n".as_bytes());
+        git.update(&text_buffer.lines, 2);
+        assert_eq!(git.rows, vec![Unchanged, DeletedAbove, Unchanged, Unchanged]);
+        assert_eq!(git.blame_at(1).unwrap().summary, "3");
+        assert_eq!(git.revert_lines(1), Some((1, 1, vec!["b".to_string()])));
+        
+        // not in HEAD, nothing to show
+        git.head_text = None;
+        git.update(&text_buffer.lines, 3);
+        assert_eq!(git.row(0), Unchanged);
+        assert_eq!(git.blame_at(0), None);
+    }
+    
+    #[test]
+    fn stage_hunk_takes_only_that_change() {
+        let head = "a\nb\nc\nd\ne\n";
+        let mut text_buffer = TextBuffer::from_test_str("A\nb\nc\nd\nE\nf\n");
+        let git = text_git(head, head, &[]);
+        let stage = | git: &TextGit, row | git.stage_hunk(row, &text_buffer.lines, text_buffer.encoding, &text_buffer.line_endings);
+        assert_eq!(stage(&git, 0), Ok(b"A\nb\nc\nd\ne\n".to_vec()));
+        assert_eq!(stage(&git, 5), Ok(b"a\nb\nc\nd\nE\nf\n".to_vec()));
+        assert!(stage(&git, 2).is_err());
+        // the index already has the first one, staging is against it
+        let git = text_git(head, "A\nb\nc\nd\ne\n", &[]);
+        assert!(stage(&git, 0).is_err());
+        
+        let mut git = text_git(head, head, &[]);
+        git.update(&text_buffer.lines, 1);
+        assert_eq!(git.revert_lines(5), Some((4, 6, vec!["e".to_string()])));
+        assert_eq!(format_git_age(90), "1 minute ago");
+        assert_eq!(format_git_age(3 * 24 * 3600 + 5), "3 days ago");
+    }
+    
+    // loads head and index the way a read from git would
+    fn loaded_git(head: &[u8], index: &[u8]) -> TextGit {
+        let mut git = TextGit::default();
+        git.apply_load(TextGitLoad::Texts {path: String::new(), load_id: 0, head: Some(decode_text(head)), index: Some(decode_text(index))});
+        git
+    }
+    
+    #[test]
+    fn stage_hunk_keeps_crlf_and_encoding() {
+        let mut text_buffer = TextBuffer::default();
+        text_buffer.load_bytes(b"a\r\nB\r\nc\r\nD\r\n");
+        let git = loaded_git(b"a\r\nb\r\nc\r\nd\r\n", b"a\r\nb\r\nc\r\nd\r\n");
+        assert_eq!(git.stage_hunk(1, &text_buffer.lines, text_buffer.encoding, &text_buffer.line_endings), Ok(b"a\r\nB\r\nc\r\nd\r\n".to_vec()));
+        
+        // a latin-1 index isnt mistaken for an empty one, the bytes outside the hunk stay
+        text_buffer.load_bytes(b"caf\xe9\nna\xefve\nX\n");
+        assert_eq!(text_buffer.encoding, TextEncoding::Latin1);
+        let git = loaded_git(b"caf\xe9\nna\xefve\nx\n", b"caf\xe9\nna\xefve\nx\n");
+        assert_eq!(git.stage_hunk(2, &text_buffer.lines, text_buffer.encoding, &text_buffer.line_endings), Ok(b"caf\xe9\nna\xefve\nX\n".to_vec()));
+        
+        // a binary index cant be staged a hunk at a time
+        let git = loaded_git(b"a\n", b"\x00\x01");
+        assert!(git.stage_hunk(0, &text_buffer.lines, text_buffer.encoding, &text_buffer.line_endings).is_err());
+    }
+    
+    #[test]
+    fn loads_of_an_older_read_get_dropped() {
+        let mut git = text_git("a\n", "a\n", &[]);
+        git.load_id = 2;
+        let texts = | load_id | TextGitLoad::Texts {path: String::new(), load_id: load_id, head: Some(decode_text(b"b\n")), index: None};
+        assert!(!git.apply_load(texts(1)));
+        assert_eq!(git.head_text, Some("a\n".to_string()));
+        assert!(git.apply_load(TextGitLoad::Blame {path: String::new(), load_id: 2, head_row: 0, blame: Some(GitBlameLine::default())}));
+        assert_eq!(git.blame_at(0), Some(GitBlameLine::default()));
+        // new texts mean new blame and a new diff
+        git.mutation_id = 5;
+        assert!(git.apply_load(texts(2)));
+        assert_eq!(git.blame.len(), 0);
+        assert!(git.is_stale(5));
+    }
+    
+    #[test]
+    fn revert_puts_head_back() {
+        let head = "a\nb\nc";
+        let cursors = TextCursorSet::new();
+        for (text, row) in &[("a\nB\nc", 1), ("a\nc", 1), ("a\nb\nc\nd\ne", 4), ("x\ny", 0), ("b\nc", 0)] {
+            let mut text_buffer = TextBuffer::from_test_str(text);
+            text_buffer.git = text_git(head, head, &[]);
+            text_buffer.git.update(&text_buffer.lines, 1);
+            assert!(text_buffer.revert_git_hunk(*row, &cursors).is_some(), "{}", text);
+            assert_eq!(text_buffer.get_as_string(), head);
+            assert!(text_buffer.undo_tree.can_undo());
+        }
+    }
+}
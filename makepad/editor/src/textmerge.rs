@@ -0,0 +1,233 @@
+// Line based diffing and three way merging, used when a file changes on disk while its
+// TextBuffer has edits of its own. The diff is plain Myers on lines after stripping the
+// common head and tail, the merge is diff3 style with git conflict markers.
+
+// base[base_start..base_end] got replaced by other[other_start..other_end]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffHunk {
+    pub base_start: usize,
+    pub base_end: usize,
+    pub other_start: usize,
+    pub other_end: usize
+}
+
+pub fn diff_lines(base: &[&str], other: &[&str]) -> Vec<DiffHunk> {
+    let mut head = 0;
+    while head < base.len() && head < other.len() && base[head] == other[head] {
+        head += 1;
+    }
+    let mut tail = 0;
+    while tail < base.len() - head && tail < other.len() - head && base[base.len() - 1 - tail] == other[other.len() - 1 - tail] {
+        tail += 1;
+    }
+    let a = &base[head..base.len() - tail];
+    let b = &other[head..other.len() - tail];
+    let mut hunks = Vec::new();
+    if a.len() == 0 && b.len() == 0 {
+        return hunks
+    }
+    
+    // forward pass, keeping every v so we can walk the path back
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = (n + m) as usize;
+    let off = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+    'outer: for d in 0..=max as isize {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || k != d && v[(k - 1 + off) as usize] < v[(k + 1 + off) as usize] {
+                v[(k + 1 + off) as usize]
+            }
+            else {
+                v[(k - 1 + off) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + off) as usize] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+            k += 2;
+        }
+    }
+    
+    // walk back collecting the matching lines, then turn the gaps between them into hunks
+    let mut matched = Vec::new();
+    let mut x = n;
+    let mut y = m;
+    for d in (1..trace.len() as isize).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k = if k == -d || k != d && v[(k - 1 + off) as usize] < v[(k + 1 + off) as usize] {k + 1} else {k - 1};
+        let prev_x = v[(prev_k + off) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matched.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        matched.push((x as usize, y as usize));
+    }
+    matched.reverse();
+    matched.push((a.len(), b.len()));
+    
+    let mut ai = 0;
+    let mut bi = 0;
+    for (ma, mb) in matched {
+        if ma > ai || mb > bi {
+            hunks.push(DiffHunk {
+                base_start: ai + head,
+                base_end: ma + head,
+                other_start: bi + head,
+                other_end: mb + head
+            });
+        }
+        ai = ma + 1;
+        bi = mb + 1;
+    }
+    hunks
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextMerge {
+    pub text: String,
+    // line ranges of the conflict blocks in text, markers included
+    pub conflicts: Vec<(usize, usize)>
+}
+
+pub fn merge_three_way(base: &str, ours: &str, theirs: &str) -> TextMerge {
+    let base_lines: Vec<&str> = base.split('\n').collect();
+    let our_lines: Vec<&str> = ours.split('\n').collect();
+    let their_lines: Vec<&str> = theirs.split('\n').collect();
+    let our_hunks = diff_lines(&base_lines, &our_lines);
+    let their_hunks = diff_lines(&base_lines, &their_lines);
+    
+    let mut out: Vec<&str> = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut base_pos = 0;
+    let mut oi = 0;
+    let mut ti = 0;
+    while oi < our_hunks.len() || ti < their_hunks.len() {
+        // start a region at the first hunk and grow it while hunks from either side touch it,
+        // touching changes from both sides count as a conflict just like in git
+        let start = match (our_hunks.get(oi), their_hunks.get(ti)) {
+            (Some(o), Some(t)) => o.base_start.min(t.base_start),
+            (Some(o), None) => o.base_start,
+            (None, Some(t)) => t.base_start,
+            (None, None) => break
+        };
+        let mut end = start;
+        let (o_first, t_first) = (oi, ti);
+        loop {
+            if oi < our_hunks.len() && our_hunks[oi].base_start <= end {
+                end = end.max(our_hunks[oi].base_end);
+                oi += 1;
+            }
+            else if ti < their_hunks.len() && their_hunks[ti].base_start <= end {
+                end = end.max(their_hunks[ti].base_end);
+                ti += 1;
+            }
+            else {
+                break
+            }
+        }
+        out.extend_from_slice(&base_lines[base_pos..start]);
+        base_pos = end;
+        
+        let ours_region = apply_region(&base_lines, &our_lines, &our_hunks[o_first..oi], start, end);
+        let theirs_region = apply_region(&base_lines, &their_lines, &their_hunks[t_first..ti], start, end);
+        if oi == o_first {
+            out.extend(theirs_region);
+        }
+        else if ti == t_first || ours_region == theirs_region {
+            out.extend(ours_region);
+        }
+        else {
+            let conflict_start = out.len();
+            out.push("<<<<<<< mine");
+            out.extend(ours_region);
+            out.push("=======");
+            out.extend(theirs_region);
+            out.push(">>>>>>> on disk");
+            conflicts.push((conflict_start, out.len()));
+        }
+    }
+    out.extend_from_slice(&base_lines[base_pos..]);
+    TextMerge {
+        text: out.join("\n"),
+        conflicts: conflicts
+    }
+}
+
+// what one side turned base[start..end] into
+fn apply_region<'a>(base: &[&'a str], other: &[&'a str], hunks: &[DiffHunk], start: usize, end: usize) -> Vec<&'a str> {
+    let mut region = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        region.extend_from_slice(&base[pos..hunk.base_start]);
+        region.extend_from_slice(&other[hunk.other_start..hunk.other_end]);
+        pos = hunk.base_end;
+    }
+    region.extend_from_slice(&base[pos..end]);
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn apply_hunks(base: &[&str], other: &[&str], hunks: &[DiffHunk]) -> Vec<String> {
+        apply_region(base, other, hunks, 0, base.len()).iter().map( | s | s.to_string()).collect()
+    }
+    
+    #[test]
+    fn diff_reproduces_other() {
+        let mut seed = 7u64;
+        let mut rand = | n: u64 | {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        let words = ["a", "b", "c", "d", "e"];
+        for _ in 0..300 {
+            let base: Vec<&str> = (0..rand(12)).map( | _ | words[rand(5) as usize]).collect();
+            let other: Vec<&str> = (0..rand(12)).map( | _ | words[rand(5) as usize]).collect();
+            let hunks = diff_lines(&base, &other);
+            assert_eq!(apply_hunks(&base, &other, &hunks), other.iter().map( | s | s.to_string()).collect::<Vec<_>>());
+            for pair in hunks.windows(2) {
+                assert!(pair[0].base_end < pair[1].base_start || pair[0].other_end < pair[1].other_start);
+            }
+        }
+    }
+    
+    #[test]
+    fn merge_takes_both_sides_when_apart() {
+        let base = "fn a() {}\n\nfn b() {}\n\nfn c() {}\n";
+        let ours = "fn a() { 1 }\n\nfn b() {}\n\nfn c() {}\n";
+        let theirs = "fn a() {}\n\nfn b() {}\n\nfn c() { 3 }\n// end\n";
+        let merge = merge_three_way(base, ours, theirs);
+        assert_eq!(merge.text, "fn a() { 1 }\n\nfn b() {}\n\nfn c() { 3 }\n// end\n");
+        assert_eq!(merge.conflicts.len(), 0);
+        // the same change on both sides is not a conflict
+        assert_eq!(merge_three_way(base, ours, ours).text, ours);
+    }
+    
+    #[test]
+    fn merge_marks_conflicts() {
+        let base = "one\ntwo\nthree\n";
+        let merge = merge_three_way(base, "one\n2\nthree\n", "one\nTWO\nthree\n");
+        assert_eq!(merge.text, "one\n<<<<<<< mine\n2\n=======\nTWO\n>>>>>>> on disk\nthree\n");
+        assert_eq!(merge.conflicts, vec![(1, 6)]);
+    }
+}
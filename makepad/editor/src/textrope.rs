@@ -0,0 +1,586 @@
+use std::ops::{Index, Range};
+use std::cell::OnceCell;
+
+// TextRope stores the lines of a TextBuffer in chunks of up to ROPE_CHUNK_MAX lines.
+// Two fenwick trees over the chunks hold the line counts and char counts (including the
+// invisible newline of every line), so row and offset lookups are O(log n) in the
+// number of chunks plus a short scan inside one chunk.
+// Edits only touch the chunks they overlap, the trees are rebuilt when chunks split or vanish.
+// A line is a plain Vec<char> until it grows past LINE_RUN_MAX chars (minified js, a json dump),
+// then it is kept as runs of about LINE_RUN_SIZE chars with a fenwick tree of their lengths,
+// so an edit in it only moves the chars of the runs it touches.
+// lines[row] still hands out a Vec<char>, for a long line that is joined on first use and
+// dropped again on the next edit, so hot paths should use line_len and line_chars instead.
+
+const ROPE_CHUNK_SIZE: usize = 256;
+const ROPE_CHUNK_MAX: usize = 1024;
+const LINE_RUN_SIZE: usize = 4096;
+const LINE_RUN_MAX: usize = 8192;
+
+#[derive(Clone)]
+enum RopeLine {
+    Flat(Vec<char>),
+    Runs(Box<LongLine>)
+}
+
+impl RopeLine {
+    fn new(line: Vec<char>) -> RopeLine {
+        if line.len() > LINE_RUN_MAX {
+            RopeLine::Runs(Box::new(LongLine::new(line)))
+        }
+        else {
+            RopeLine::Flat(line)
+        }
+    }
+    
+    fn len(&self) -> usize {
+        match self {
+            RopeLine::Flat(line) => line.len(),
+            RopeLine::Runs(long_line) => long_line.len
+        }
+    }
+    
+    fn as_vec(&self) -> &Vec<char> {
+        match self {
+            RopeLine::Flat(line) => line,
+            RopeLine::Runs(long_line) => long_line.joined.get_or_init( | | long_line.runs.concat())
+        }
+    }
+    
+    fn into_vec(self) -> Vec<char> {
+        match self {
+            RopeLine::Flat(line) => line,
+            RopeLine::Runs(long_line) => {
+                let long_line = *long_line;
+                let runs = long_line.runs;
+                long_line.joined.into_inner().unwrap_or_else( | | runs.concat())
+            }
+        }
+    }
+    
+    fn chars(&self) -> LineChars<'_> {
+        let runs = match self {
+            RopeLine::Flat(line) => std::slice::from_ref(line),
+            RopeLine::Runs(long_line) => &long_line.runs[..]
+        };
+        LineChars {runs: runs[1..].iter(), chars: runs[0].iter()}
+    }
+    
+    fn slice(&self, range: Range<usize>) -> Vec<char> {
+        match self {
+            RopeLine::Flat(line) => line[range].to_vec(),
+            RopeLine::Runs(long_line) => long_line.slice(range)
+        }
+    }
+    
+    fn splice(&mut self, range: Range<usize>, rep_line: Vec<char>) -> Vec<char> {
+        let removed = match self {
+            RopeLine::Flat(line) => line.splice(range, rep_line).collect(),
+            RopeLine::Runs(long_line) => long_line.splice(range, rep_line)
+        };
+        // switch over when the line crosses the limits, with some room in between so it doesnt flip back and forth
+        match self {
+            RopeLine::Flat(line) if line.len() > LINE_RUN_MAX => {
+                *self = RopeLine::new(std::mem::replace(line, Vec::new()))
+            },
+            RopeLine::Runs(long_line) if long_line.len <= LINE_RUN_SIZE => {
+                *self = RopeLine::Flat(long_line.runs.concat())
+            },
+            _ => ()
+        }
+        removed
+    }
+}
+
+#[derive(Clone, Default)]
+struct LongLine {
+    runs: Vec<Vec<char>>,
+    run_tree: Fenwick,
+    len: usize,
+    // all runs in one Vec for lines[row], made on demand
+    joined: OnceCell<Vec<char>>
+}
+
+impl LongLine {
+    fn new(line: Vec<char>) -> LongLine {
+        let mut long_line = LongLine::default();
+        long_line.runs = Self::chunk_chars(&line);
+        long_line.rebuild();
+        long_line
+    }
+    
+    fn chunk_chars(chars: &[char]) -> Vec<Vec<char >> {
+        chars.chunks(LINE_RUN_SIZE).map( | run | run.to_vec()).collect()
+    }
+    
+    fn rebuild(&mut self) {
+        self.runs.retain( | run | run.len() > 0);
+        self.run_tree = Fenwick::build(self.runs.iter().map( | run | run.len()));
+        self.len = self.runs.iter().map( | run | run.len()).sum();
+    }
+    
+    // the run holding col and the col of its first char, the end of the line is in the last run
+    fn find_run(&self, col: usize) -> (usize, usize) {
+        if col >= self.len {
+            let last = self.runs.len() - 1;
+            return (last, self.len - self.runs[last].len())
+        }
+        self.run_tree.find(col)
+    }
+    
+    fn slice(&self, range: Range<usize>) -> Vec<char> {
+        let mut out = Vec::with_capacity(range.len());
+        let (mut run, mut run_start) = self.find_run(range.start);
+        while out.len() < range.len() {
+            let chars = &self.runs[run];
+            let start = (range.start + out.len()) - run_start;
+            let end = chars.len().min(range.end - run_start);
+            out.extend_from_slice(&chars[start..end]);
+            run_start += chars.len();
+            run += 1;
+        }
+        out
+    }
+    
+    fn splice(&mut self, range: Range<usize>, rep_line: Vec<char>) -> Vec<char> {
+        self.joined = OnceCell::new();
+        let (first, first_start) = self.find_run(range.start);
+        let last = if range.end > range.start {self.find_run(range.end - 1).0} else {first};
+        let local = (range.start - first_start)..(range.end - first_start);
+        if first == last && self.runs[first].len() - local.len() + rep_line.len() <= LINE_RUN_MAX { // edit stays inside one run
+            let delta = rep_line.len() as isize - local.len() as isize;
+            let removed: Vec<char> = self.runs[first].splice(local, rep_line).collect();
+            self.len = (self.len as isize + delta) as usize;
+            if self.runs[first].len() == 0 {
+                self.rebuild();
+            }
+            else {
+                self.run_tree.add(first, delta);
+            }
+            return removed
+        }
+        // the edit spans runs or overflows one, join the touched runs and cut them up again
+        let mut chars = Vec::new();
+        for run in self.runs.drain(first..(last + 1)) {
+            chars.extend(run);
+        }
+        let removed = chars.splice(local, rep_line).collect();
+        self.runs.splice(first..first, Self::chunk_chars(&chars));
+        self.rebuild();
+        removed
+    }
+}
+
+// the chars of one line, going over the runs of a long one
+pub struct LineChars<'a> {
+    runs: std::slice::Iter<'a, Vec<char >>,
+    chars: std::slice::Iter<'a, char>
+}
+
+impl<'a> Iterator for LineChars<'a> {
+    type Item = &'a char;
+    
+    fn next(&mut self) -> Option<&'a char> {
+        loop {
+            if let Some(ch) = self.chars.next() {
+                return Some(ch)
+            }
+            self.chars = self.runs.next() ?.iter();
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct RopeChunk {
+    lines: Vec<RopeLine>,
+    char_count: usize
+}
+
+impl RopeChunk {
+    fn new(lines: Vec<RopeLine>) -> RopeChunk {
+        let char_count = lines.iter().map( | line | line.len() + 1).sum();
+        RopeChunk {
+            lines: lines,
+            char_count: char_count
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Fenwick {
+    tree: Vec<usize>
+}
+
+impl Fenwick {
+    fn build<I>(values: I) -> Fenwick where I: Iterator<Item = usize> {
+        let mut tree = vec![0];
+        tree.extend(values);
+        let len = tree.len();
+        for i in 1..len {
+            let parent = i + (i & i.wrapping_neg());
+            if parent < len {
+                tree[parent] += tree[i];
+            }
+        }
+        Fenwick {tree: tree}
+    }
+
+    fn add(&mut self, index: usize, delta: isize) {
+        let mut i = index + 1;
+        while i < self.tree.len() {
+            self.tree[i] = (self.tree[i] as isize + delta) as usize;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    // sum of the first `index` values
+    fn prefix(&self, index: usize) -> usize {
+        let mut sum = 0;
+        let mut i = index;
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+
+    // returns the index of the value containing `target` and the sum of all values before it
+    fn find(&self, target: usize) -> (usize, usize) {
+        let len = self.tree.len() - 1;
+        let mut step = 1;
+        while step * 2 <= len {
+            step *= 2;
+        }
+        let mut pos = 0;
+        let mut sum = 0;
+        while step > 0 {
+            if pos + step <= len && sum + self.tree[pos + step] <= target {
+                pos += step;
+                sum += self.tree[pos];
+            }
+            step >>= 1;
+        }
+        (pos, sum)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TextRope {
+    chunks: Vec<RopeChunk>,
+    line_tree: Fenwick,
+    char_tree: Fenwick,
+    line_count: usize,
+    char_count: usize
+}
+
+impl TextRope {
+    pub fn from_lines(lines: Vec<Vec<char>>) -> TextRope {
+        let mut rope = TextRope::default();
+        rope.chunks = Self::chunk_lines(lines.into_iter().map(RopeLine::new).collect());
+        rope.rebuild();
+        rope
+    }
+
+    fn chunk_lines(mut lines: Vec<RopeLine>) -> Vec<RopeChunk> {
+        let mut chunks = Vec::new();
+        while lines.len() > ROPE_CHUNK_SIZE {
+            let rest = lines.split_off(ROPE_CHUNK_SIZE);
+            chunks.push(RopeChunk::new(lines));
+            lines = rest;
+        }
+        if lines.len() > 0 {
+            chunks.push(RopeChunk::new(lines));
+        }
+        chunks
+    }
+
+    fn rebuild(&mut self) {
+        self.chunks.retain( | chunk | chunk.lines.len() > 0);
+        self.line_tree = Fenwick::build(self.chunks.iter().map( | chunk | chunk.lines.len()));
+        self.char_tree = Fenwick::build(self.chunks.iter().map( | chunk | chunk.char_count));
+        self.line_count = self.chunks.iter().map( | chunk | chunk.lines.len()).sum();
+        self.char_count = self.chunks.iter().map( | chunk | chunk.char_count).sum();
+    }
+
+    // number of lines
+    pub fn len(&self) -> usize {
+        self.line_count
+    }
+
+    // number of chars, counting the newlines between lines
+    pub fn char_count(&self) -> usize {
+        self.char_count.max(1) - 1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vec<char >> {
+        self.chunks.iter().flat_map( | chunk | chunk.lines.iter().map( | line | line.as_vec()))
+    }
+
+    pub fn to_lines(&self) -> Vec<Vec<char >> {
+        self.iter().cloned().collect()
+    }
+
+    fn find_row(&self, row: usize) -> (usize, usize) {
+        let (chunk, lines_before) = self.line_tree.find(row);
+        (chunk, row - lines_before)
+    }
+    
+    fn line(&self, row: usize) -> &RopeLine {
+        if row >= self.line_count {
+            panic!("TextRope row {} out of bounds {}", row, self.line_count);
+        }
+        let (chunk, local) = self.find_row(row);
+        &self.chunks[chunk].lines[local]
+    }
+    
+    // these dont join a long line like lines[row] does
+    pub fn line_len(&self, row: usize) -> usize {
+        self.line(row).len()
+    }
+    
+    pub fn line_chars(&self, row: usize) -> LineChars<'_> {
+        self.line(row).chars()
+    }
+    
+    pub fn line_slice(&self, row: usize, cols: Range<usize>) -> Vec<char> {
+        self.line(row).slice(cols)
+    }
+
+    // char offset of the first char of a row
+    pub fn row_offset(&self, row: usize) -> usize {
+        if row >= self.line_count {
+            return self.char_count
+        }
+        let (chunk, local) = self.find_row(row);
+        let mut offset = self.char_tree.prefix(chunk);
+        for line in &self.chunks[chunk].lines[0..local] {
+            offset += line.len() + 1;
+        }
+        offset
+    }
+
+    // returns (row, col) of a char offset, or None if its past the end
+    pub fn offset_to_row_col(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset >= self.char_count {
+            return None
+        }
+        let (chunk, chars_before) = self.char_tree.find(offset);
+        let mut row = self.line_tree.prefix(chunk);
+        let mut line_start = chars_before;
+        for line in &self.chunks[chunk].lines {
+            let next_start = line_start + line.len() + 1;
+            if next_start > offset {
+                return Some((row, offset - line_start))
+            }
+            line_start = next_start;
+            row += 1;
+        }
+        None
+    }
+
+    pub fn splice_line(&mut self, row: usize, range: Range<usize>, rep_line: Vec<char>) -> Vec<char> {
+        let (chunk, local) = self.find_row(row);
+        let old_len = self.chunks[chunk].lines[local].len();
+        let removed = self.chunks[chunk].lines[local].splice(range, rep_line);
+        let delta = self.chunks[chunk].lines[local].len() as isize - old_len as isize;
+        self.chunks[chunk].char_count = (self.chunks[chunk].char_count as isize + delta) as usize;
+        self.char_tree.add(chunk, delta);
+        self.char_count = (self.char_count as isize + delta) as usize;
+        removed
+    }
+
+    // replaces whole rows, returning the removed ones
+    pub fn splice(&mut self, rows: Range<usize>, rep_lines: Vec<Vec<char >>) -> Vec<Vec<char >> {
+        let rep_lines: Vec<RopeLine> = rep_lines.into_iter().map(RopeLine::new).collect();
+        if self.chunks.len() == 0 {
+            self.chunks = Self::chunk_lines(rep_lines);
+            self.rebuild();
+            return Vec::new()
+        }
+        // find the chunk range we touch, inserting at the very end goes into the last chunk
+        let (first_chunk, first_local) = if rows.start >= self.line_count {
+            (self.chunks.len() - 1, self.chunks.last().unwrap().lines.len())
+        }
+        else {
+            self.find_row(rows.start)
+        };
+
+        if rows.end <= rows.start + (self.chunks[first_chunk].lines.len() - first_local) { // edit stays inside one chunk
+            let chunk = &mut self.chunks[first_chunk];
+            let old_lines = chunk.lines.len();
+            let old_chars = chunk.char_count;
+            let removed: Vec<Vec<char >> = chunk.lines.splice(first_local..(first_local + rows.end - rows.start), rep_lines).map(RopeLine::into_vec).collect();
+            chunk.char_count = chunk.lines.iter().map( | line | line.len() + 1).sum();
+            let line_delta = chunk.lines.len() as isize - old_lines as isize;
+            let char_delta = chunk.char_count as isize - old_chars as isize;
+            if chunk.lines.len() == 0 || chunk.lines.len() > ROPE_CHUNK_MAX {
+                let lines = std::mem::replace(&mut chunk.lines, Vec::new());
+                let new_chunks = Self::chunk_lines(lines);
+                self.chunks.splice(first_chunk..(first_chunk + 1), new_chunks);
+                self.rebuild();
+            }
+            else {
+                self.line_tree.add(first_chunk, line_delta);
+                self.char_tree.add(first_chunk, char_delta);
+                self.line_count = (self.line_count as isize + line_delta) as usize;
+                self.char_count = (self.char_count as isize + char_delta) as usize;
+            }
+            return removed
+        }
+
+        // the edit spans chunks, collect the lines of all touched chunks and rechunk them
+        let (last_chunk, _) = self.find_row(rows.end - 1);
+        let mut lines = Vec::new();
+        for chunk in self.chunks.drain(first_chunk..(last_chunk + 1)) {
+            lines.extend(chunk.lines);
+        }
+        let end_local = first_local + rows.end - rows.start;
+        let removed = lines.splice(first_local..end_local, rep_lines).map(RopeLine::into_vec).collect();
+        let new_chunks = Self::chunk_lines(lines);
+        self.chunks.splice(first_chunk..first_chunk, new_chunks);
+        self.rebuild();
+        removed
+    }
+}
+
+impl Index<usize> for TextRope {
+    type Output = Vec<char>;
+
+    fn index(&self, row: usize) -> &Vec<char> {
+        self.line(row).as_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn line(row: usize) -> Vec<char> {
+        format!("line {}", row).chars().collect()
+    }
+    
+    // the same text as plain lines, what the rope has to agree with
+    fn check(rope: &TextRope, lines: &Vec<Vec<char>>) {
+        assert_eq!(rope.len(), lines.len());
+        assert_eq!(rope.to_lines(), *lines);
+        let char_count: usize = lines.iter().map( | line | line.len() + 1).sum();
+        assert_eq!(rope.char_count(), char_count.max(1) - 1);
+        let mut offset = 0;
+        for (row, line) in lines.iter().enumerate() {
+            assert_eq!(rope[row], *line);
+            assert_eq!(rope.row_offset(row), offset);
+            for col in 0..=line.len() {
+                assert_eq!(rope.offset_to_row_col(offset + col), Some((row, col)));
+            }
+            offset += line.len() + 1;
+        }
+        assert_eq!(rope.row_offset(lines.len()), char_count);
+        assert_eq!(rope.offset_to_row_col(char_count), None);
+    }
+    
+    #[test]
+    fn fenwick_sums_and_finds() {
+        let values = [3, 0, 5, 1, 7, 2, 2];
+        let mut fenwick = Fenwick::build(values.iter().cloned());
+        for index in 0..=values.len() {
+            assert_eq!(fenwick.prefix(index), values[0..index].iter().sum::<usize>());
+        }
+        // the value holding target, skipping the empty one
+        assert_eq!(fenwick.find(0), (0, 0));
+        assert_eq!(fenwick.find(2), (0, 0));
+        assert_eq!(fenwick.find(3), (2, 3));
+        assert_eq!(fenwick.find(8), (3, 8));
+        assert_eq!(fenwick.find(19), (6, 18));
+        fenwick.add(2, -5);
+        fenwick.add(6, 3);
+        assert_eq!(fenwick.prefix(3), 3);
+        assert_eq!(fenwick.prefix(7), 18);
+        assert_eq!(fenwick.find(3), (3, 3));
+    }
+    
+    #[test]
+    fn edits_across_chunk_boundaries() {
+        let mut lines: Vec<Vec<char>> = (0..1000).map(line).collect();
+        let mut rope = TextRope::from_lines(lines.clone());
+        assert_eq!(rope.chunks.len(), 4);
+        check(&rope, &lines);
+        
+        // (rows, new lines) going over the 256 line chunk edges, growing a chunk past the max
+        // so it splits, and emptying whole chunks
+        let edits: Vec<(Range<usize>, usize)> = vec![
+            (250..262, 3),
+            (255..255, 1200),
+            (0..700, 0),
+            (100..101, 2),
+            (400..1400, 5),
+            (0..0, 1),
+        ];
+        for (rows, count) in edits {
+            let new: Vec<Vec<char>> = (0..count).map( | index | line(10000 + index)).collect();
+            let expect: Vec<Vec<char>> = lines.splice(rows.clone(), new.clone()).collect();
+            assert_eq!(rope.splice(rows, new), expect);
+            check(&rope, &lines);
+        }
+        
+        // inside one line, next to a chunk edge
+        let removed = rope.splice_line(255, 0..4, "LINE".chars().collect());
+        assert_eq!(removed, lines[255][0..4].to_vec());
+        lines[255].splice(0..4, "LINE".chars());
+        check(&rope, &lines);
+        rope.splice_line(256, 2..2, "longer".chars().collect());
+        lines[256].splice(2..2, "longer".chars());
+        check(&rope, &lines);
+        
+        // all of it away and back into an empty rope
+        let all = rope.len();
+        rope.splice(0..all, Vec::new());
+        assert_eq!(rope.len(), 0);
+        rope.splice(0..0, vec![line(1), line(2)]);
+        check(&rope, &vec![line(1), line(2)]);
+    }
+    
+    #[test]
+    fn edits_inside_one_huge_line() {
+        // a 4MB minified file, one line between two short ones
+        let huge: Vec<char> = (0..4 * 1024 * 1024).map( | index | (b'a' + (index % 26) as u8) as char).collect();
+        let mut lines = vec![line(0), huge, line(2)];
+        let mut rope = TextRope::from_lines(lines.clone());
+        
+        // inserts, deletes and replaces all over it, some of them wider than a run
+        let mut seed = 12345usize;
+        for step in 0..1000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+            let len = lines[1].len();
+            let start = seed % (len + 1);
+            let end = (start + [0, 3, 100, 5000][step % 4]).min(len);
+            let new: Vec<char> = "x\ty".chars().cycle().take([5, 0, 50, 1][step % 4]).collect();
+            let expect: Vec<char> = lines[1].splice(start..end, new.iter().cloned()).collect();
+            assert_eq!(rope.splice_line(1, start..end, new), expect);
+        }
+        match &rope.chunks[0].lines[1] {
+            RopeLine::Runs(long_line) => assert!(long_line.runs.iter().all( | run | run.len() > 0 && run.len() <= LINE_RUN_MAX)),
+            RopeLine::Flat(_) => panic!("a 4MB line should be kept in runs")
+        }
+        assert_eq!(rope.line_len(1), lines[1].len());
+        assert!(rope.line_chars(1).eq(lines[1].iter()));
+        assert_eq!(rope.line_slice(1, 5000..13000), lines[1][5000..13000].to_vec());
+        assert_eq!(rope[1], lines[1]);
+        let offset = rope.row_offset(1) + 2_000_000;
+        assert_eq!(rope.offset_to_row_col(offset), Some((1, 2_000_000)));
+        assert_eq!(rope.row_offset(2), line(0).len() + 1 + lines[1].len() + 1);
+        
+        // shrunk back down it is a plain line again
+        let len = lines[1].len();
+        let removed = rope.splice_line(1, 100..len, Vec::new());
+        assert_eq!(removed, lines[1][100..].to_vec());
+        lines[1].truncate(100);
+        assert!(match &rope.chunks[0].lines[1] {RopeLine::Flat(_) => true, _ => false});
+        check(&rope, &lines);
+        
+        // and split up and put back together through whole row edits
+        let removed = rope.splice(1..2, vec![lines[1].repeat(200), Vec::new()]);
+        assert_eq!(removed, vec![lines[1].clone()]);
+        lines.splice(1..2, vec![lines[1].repeat(200), Vec::new()]);
+        assert_eq!(rope.to_lines(), lines);
+    }
+}
@@ -0,0 +1,125 @@
+use regex::{Regex, RegexBuilder};
+use crate::textbuffer::*;
+
+// A search query as typed in the find bar or the project search. Plain queries are escaped
+// and go through the same regex engine, whole-word wraps the pattern in word boundaries.
+// All results are char offsets into text with plain \n line endings, same as TextBuffer.
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct TextSearch {
+    pub query: String,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub is_regex: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSearchMatch {
+    pub start: usize,
+    pub end: usize,
+    // the replacement text, with $1 style groups expanded for regex searches
+    pub replace: String
+}
+
+impl TextSearch {
+    pub fn compile(&self) -> Result<Regex, String> {
+        let pattern = if self.is_regex {self.query.clone()} else {regex::escape(&self.query)};
+        let pattern = if self.whole_word {format!("\\b(?:{})\\b", pattern)} else {pattern};
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+            .map_err( | err | err.to_string())
+    }
+    
+    pub fn find_all(&self, text: &str) -> Result<Vec<TextSearchMatch>, String> {
+        self.find_all_with_replace(text, "")
+    }
+    
+    pub fn find_all_with_replace(&self, text: &str, replace: &str) -> Result<Vec<TextSearchMatch>, String> {
+        if self.query.len() == 0 {
+            return Ok(Vec::new())
+        }
+        let regex = self.compile() ?;
+        let mut matches = Vec::new();
+        // walk byte offsets forward once to turn them into char offsets
+        let mut byte_pos = 0;
+        let mut char_pos = 0;
+        for caps in regex.captures_iter(text) {
+            let m = caps.get(0).unwrap();
+            if m.start() == m.end() { // empty matches are useless as cursors
+                continue
+            }
+            char_pos += text[byte_pos..m.start()].chars().count();
+            let start = char_pos;
+            char_pos += text[m.start()..m.end()].chars().count();
+            byte_pos = m.end();
+            let mut rep = String::new();
+            if self.is_regex {
+                caps.expand(replace, &mut rep);
+            }
+            else {
+                rep.push_str(replace);
+            }
+            matches.push(TextSearchMatch {
+                start: start,
+                end: char_pos,
+                replace: rep
+            });
+        }
+        Ok(matches)
+    }
+    
+    pub fn find_in_text_buffer(&self, text_buffer: &TextBuffer, replace: &str) -> Result<Vec<TextSearchMatch>, String> {
+        self.find_all_with_replace(&text_buffer.get_as_lf_string(), replace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ranges(search: &TextSearch, text: &str) -> Vec<(usize, usize)> {
+        search.find_all(text).unwrap().iter().map( | m | (m.start, m.end)).collect()
+    }
+    
+    #[test]
+    fn plain_case_and_whole_word() {
+        let mut search = TextSearch {query: "foo".to_string(), ..Default::default()};
+        assert_eq!(ranges(&search, "Foo foobar foo"), vec![(0, 3), (4, 7), (11, 14)]);
+        search.case_sensitive = true;
+        assert_eq!(ranges(&search, "Foo foobar foo"), vec![(4, 7), (11, 14)]);
+        search.whole_word = true;
+        assert_eq!(ranges(&search, "Foo foobar foo"), vec![(11, 14)]);
+        // plain queries dont get interpreted as a regex
+        let search = TextSearch {query: "a.b".to_string(), ..Default::default()};
+        assert_eq!(ranges(&search, "axb a.b"), vec![(4, 7)]);
+    }
+    
+    #[test]
+    fn regex_offsets_are_chars_and_groups_expand() {
+        let search = TextSearch {query: "(\\w+)=(\\d+)".to_string(), is_regex: true, ..Default::default()};
+        let matches = search.find_all_with_replace("é\nab=12 cd=3", "$2=$1").unwrap();
+        assert_eq!(matches, vec![
+            TextSearchMatch {start: 2, end: 7, replace: "12=ab".to_string()},
+            TextSearchMatch {start: 8, end: 12, replace: "3=cd".to_string()}
+        ]);
+        let bad = TextSearch {query: "(".to_string(), is_regex: true, ..Default::default()};
+        assert!(bad.find_all("(").is_err());
+    }
+    
+    #[test]
+    fn replace_all_is_one_undo_step() {
+        let text = "let a = 1;\nlet bb = 2;\nlet a = 3;\n";
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        let mut cursors = crate::textcursor::TextCursorSet::new();
+        let search = TextSearch {query: "let (\\w+)".to_string(), is_regex: true, ..Default::default()};
+        let matches = search.find_in_text_buffer(&text_buffer, "var $1").unwrap();
+        cursors.replace_matches(&matches, &mut text_buffer);
+        assert_eq!(text_buffer.get_as_string(), "var a = 1;\nvar bb = 2;\nvar a = 3;\n");
+        assert_eq!(cursors.set.len(), 3);
+        text_buffer.undo(true, &mut cursors);
+        assert_eq!(text_buffer.get_as_string(), text);
+        assert!(!text_buffer.undo_tree.can_undo());
+    }
+}
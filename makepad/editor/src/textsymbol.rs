@@ -0,0 +1,282 @@
+use crate::textbuffer::*;
+
+// The functions, types and modules of a buffer, read off its token chunks so every language
+// with a tokenizer gets them without a parser. A symbol is a definition keyword followed by
+// a name, or a name with ( right inside a class for the methods of js. What comes between the
+// name and the { } block after it is skipped over with the token pairs, the block says which
+// symbols sit inside it. The chunks have to be up to date.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextSymbolKind {
+    Fn,
+    Struct,
+    Enum,
+    Trait,
+    Impl,
+    Mod,
+    Const,
+    Static,
+    Type,
+    Class
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSymbol {
+    pub kind: TextSymbolKind,
+    pub name: String,
+    // where the keyword starts
+    pub offset: usize,
+    // just past the } of its block, or where it ends without one
+    pub end: usize,
+    // how many symbols it sits in
+    pub depth: usize
+}
+
+impl TextSymbolKind {
+    fn from_keyword(keyword: &str) -> Option<TextSymbolKind> {
+        let kind = match keyword {
+            "fn" | "function" => TextSymbolKind::Fn,
+            "struct" => TextSymbolKind::Struct,
+            "enum" => TextSymbolKind::Enum,
+            "trait" => TextSymbolKind::Trait,
+            "impl" => TextSymbolKind::Impl,
+            "mod" => TextSymbolKind::Mod,
+            "const" => TextSymbolKind::Const,
+            "static" => TextSymbolKind::Static,
+            "type" => TextSymbolKind::Type,
+            "class" => TextSymbolKind::Class,
+            _ => return None
+        };
+        Some(kind)
+    }
+    
+    pub fn label(&self) -> &'static str {
+        match self {
+            TextSymbolKind::Fn => "fn",
+            TextSymbolKind::Struct => "struct",
+            TextSymbolKind::Enum => "enum",
+            TextSymbolKind::Trait => "trait",
+            TextSymbolKind::Impl => "impl",
+            TextSymbolKind::Mod => "mod",
+            TextSymbolKind::Const => "const",
+            TextSymbolKind::Static => "static",
+            TextSymbolKind::Type => "type",
+            TextSymbolKind::Class => "class"
+        }
+    }
+}
+
+fn token_text(flat_text: &[char], token_chunk: &TokenChunk) -> String {
+    flat_text[token_chunk.offset..(token_chunk.offset + token_chunk.len).min(flat_text.len())].iter().collect()
+}
+
+fn is_definition_token(token_type: TokenType) -> bool {
+    match token_type {
+        TokenType::Fn | TokenType::TypeDef | TokenType::Keyword => true,
+        _ => false
+    }
+}
+
+fn is_name_token(token_type: TokenType) -> bool {
+    match token_type {
+        TokenType::Identifier | TokenType::TypeName | TokenType::Call => true,
+        _ => false
+    }
+}
+
+pub fn text_symbols(text_buffer: &TextBuffer) -> Vec<TextSymbol> {
+    let flat_text = &text_buffer.flat_text;
+    let token_chunks = &text_buffer.token_chunks;
+    let mut symbols: Vec<TextSymbol> = Vec::new();
+    // the symbols the tokens are in, as indices into symbols
+    let mut parents: Vec<usize> = Vec::new();
+    // after a : -> , or ( its a type like impl Trait, a new line starts over
+    let mut in_type = false;
+    for (index, token_chunk) in token_chunks.iter().enumerate() {
+        let token_type = token_chunk.token_type;
+        if token_type == TokenType::Newline {
+            in_type = false;
+        }
+        if token_type.should_ignore() {
+            continue;
+        }
+        let at_start = !in_type;
+        in_type = match token_type {
+            TokenType::Colon | TokenType::Operator | TokenType::ParenOpen => true,
+            TokenType::Delimiter => token_text(flat_text, token_chunk) == ",",
+            _ => false
+        };
+        if !at_start {
+            continue;
+        }
+        while parents.last().map_or(false, | parent | token_chunk.offset >= symbols[*parent].end) {
+            parents.pop();
+        }
+        let parent_kind = parents.last().map( | parent | symbols[*parent].kind);
+        let (kind, name) = if is_definition_token(token_type) {
+            let kind = if let Some(kind) = TextSymbolKind::from_keyword(&token_text(flat_text, token_chunk)) {kind} else {continue};
+            match (kind, parent_kind) {
+                // the locals of a function, and static in a class goes with the method after it
+                (TextSymbolKind::Const, Some(TextSymbolKind::Fn)) | (TextSymbolKind::Static, Some(TextSymbolKind::Fn)) => continue,
+                (TextSymbolKind::Static, Some(TextSymbolKind::Class)) => continue,
+                _ => ()
+            }
+            let name = if kind == TextSymbolKind::Impl {
+                impl_name(flat_text, token_chunks, index)
+            }
+            else {
+                token_chunks[index + 1..].iter().find( | next | !next.token_type.should_ignore()).and_then( | next | {
+                    if is_name_token(next.token_type) {Some(token_text(flat_text, next))} else {None}
+                })
+            };
+            (kind, name)
+        }
+        else if token_type == TokenType::Call && parent_kind == Some(TextSymbolKind::Class) {
+            (TextSymbolKind::Fn, Some(token_text(flat_text, token_chunk)))
+        }
+        else {
+            continue
+        };
+        let name = if let Some(name) = name {name} else {continue};
+        let end = symbol_end(flat_text, token_chunks, index, kind);
+        symbols.push(TextSymbol {kind: kind, name: name, offset: token_chunk.offset, end: end, depth: parents.len()});
+        parents.push(symbols.len() - 1);
+    }
+    symbols
+}
+
+// past the } of the first { block after the keyword, skipping the ( ) < > and [ ] on the way.
+// A ; ends it sooner, and so does a new line for the ones that are just a value
+fn symbol_end(flat_text: &[char], token_chunks: &[TokenChunk], index: usize, kind: TextSymbolKind) -> usize {
+    let ends_on_newline = match kind {
+        TextSymbolKind::Const | TextSymbolKind::Static | TextSymbolKind::Type => true,
+        _ => false
+    };
+    let mut i = index + 1;
+    while i < token_chunks.len() {
+        let token_chunk = &token_chunks[i];
+        match token_chunk.token_type {
+            TokenType::ParenOpen => {
+                let pair = token_chunk.pair_token;
+                // an unclosed one runs to the end of the text
+                if pair <= i {
+                    return flat_text.len()
+                }
+                if flat_text.get(token_chunk.offset) == Some(&'{') {
+                    return token_chunks[pair].offset + token_chunks[pair].len
+                }
+                i = pair;
+            },
+            TokenType::Delimiter if token_text(flat_text, token_chunk) == ";" => return token_chunk.offset + 1,
+            TokenType::Newline if ends_on_newline => return token_chunk.offset,
+            _ => ()
+        }
+        i += 1;
+    }
+    flat_text.len()
+}
+
+// the innermost symbol offset is in
+pub fn symbol_at(symbols: &[TextSymbol], offset: usize) -> Option<usize> {
+    let mut found = None;
+    for (index, symbol) in symbols.iter().enumerate() {
+        if symbol.offset > offset {
+            break;
+        }
+        if offset < symbol.end {
+            found = Some(index);
+        }
+    }
+    found
+}
+
+// impl<T> Display for Foo<T> where .. { is named Display for Foo<T>
+fn impl_name(flat_text: &[char], token_chunks: &[TokenChunk], index: usize) -> Option<String> {
+    let mut name = String::new();
+    let mut generics_depth = 0;
+    for token_chunk in &token_chunks[index + 1..] {
+        let text = token_text(flat_text, token_chunk);
+        match token_chunk.token_type {
+            TokenType::ParenOpen | TokenType::Delimiter => break,
+            TokenType::Keyword if text == "where" => break,
+            TokenType::Whitespace | TokenType::Newline => {
+                if name.len() > 0 && !name.ends_with(' ') && generics_depth == 0 {
+                    name.push(' ');
+                }
+                continue;
+            },
+            _ => ()
+        }
+        // the generics of the impl itself come before any name
+        if name.len() == 0 && (text == "<" || generics_depth > 0) {
+            generics_depth += text.matches('<').count();
+            generics_depth -= text.matches('>').count().min(generics_depth);
+            continue;
+        }
+        name.push_str(&text);
+    }
+    let name = name.trim().to_string();
+    if name.len() > 0 {Some(name)} else {None}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rusteditor::RustTokenizer;
+    use crate::jseditor::JSTokenizer;
+    
+    fn buffer_for(text: &str, tokenizer: &mut dyn Tokenizer) -> TextBuffer {
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        text_buffer.update_token_chunks(tokenizer);
+        text_buffer
+    }
+    
+    fn symbols_for(text: &str) -> Vec<(TextSymbolKind, String)> {
+        let text_buffer = buffer_for(text, &mut RustTokenizer::new());
+        text_symbols(&text_buffer).into_iter().map( | symbol | (symbol.kind, symbol.name)).collect()
+    }
+    
+    #[test]
+    fn finds_rust_definitions() {
+        let symbols = symbols_for("mod a {\n    pub struct Foo<T> {x: T}\n}\nimpl<T: Clone> Display for Foo<T> where T: Debug {\n    pub const fn new(x: impl Into<T>) -> Self {}\n}\nconst MAX: usize = 4;\nenum Bar {A}\n");
+        assert_eq!(symbols, vec![
+            (TextSymbolKind::Mod, "a".to_string()),
+            (TextSymbolKind::Struct, "Foo".to_string()),
+            (TextSymbolKind::Impl, "Display for Foo<T>".to_string()),
+            (TextSymbolKind::Fn, "new".to_string()),
+            (TextSymbolKind::Const, "MAX".to_string()),
+            (TextSymbolKind::Enum, "Bar".to_string())
+        ]);
+    }
+    
+    #[test]
+    fn nests_rust_blocks() {
+        let text = "trait T {\n    fn a(&self);\n    fn b() -> [u8; 2] {[0; 2]}\n}\nfn main() {\n    const LOCAL: u32 = 1;\n    struct Inner;\n}\n";
+        let text_buffer = buffer_for(text, &mut RustTokenizer::new());
+        let symbols = text_symbols(&text_buffer);
+        let outline: Vec<(usize, &str)> = symbols.iter().map( | symbol | (symbol.depth, symbol.name.as_str())).collect();
+        assert_eq!(outline, vec![(0, "T"), (1, "a"), (1, "b"), (0, "main"), (1, "Inner")]);
+        // a without a body ends at its ;
+        assert_eq!(&text[symbols[1].offset..symbols[1].end], "fn a(&self);");
+        assert_eq!(&text[symbols[2].offset..symbols[2].end], "fn b() -> [u8; 2] {[0; 2]}");
+        assert_eq!(symbol_at(&symbols, text.find("[0;").unwrap()), Some(2));
+        assert_eq!(symbol_at(&symbols, text.find("LOCAL").unwrap()), Some(3));
+        assert_eq!(symbol_at(&symbols, text.len() - 1), None);
+    }
+    
+    #[test]
+    fn finds_js_functions_and_methods() {
+        let text = "const SIZE = 4\nfunction draw(cx) {\n    const x = f(1)\n}\nclass Shape extends Base {\n    constructor(w) {\n        this.w = area(w)\n    }\n    static make() {\n    }\n}\n";
+        let text_buffer = buffer_for(text, &mut JSTokenizer::new());
+        let symbols = text_symbols(&text_buffer);
+        let outline: Vec<(usize, TextSymbolKind, &str)> = symbols.iter().map( | symbol | (symbol.depth, symbol.kind, symbol.name.as_str())).collect();
+        assert_eq!(outline, vec![
+            (0, TextSymbolKind::Const, "SIZE"),
+            (0, TextSymbolKind::Fn, "draw"),
+            (0, TextSymbolKind::Class, "Shape"),
+            (1, TextSymbolKind::Fn, "constructor"),
+            (1, TextSymbolKind::Fn, "make")
+        ]);
+        assert_eq!(&text[symbols[0].offset..symbols[0].end], "const SIZE = 4");
+    }
+}
@@ -0,0 +1,297 @@
+use serde::*;
+use crate::textbuffer::*;
+
+// The undo history is a tree. Node 0 is the text as it was loaded, every other node is an edit
+// on top of its parent. An applied node (on the path from the root to current) holds the
+// TextUndo that reverts it, an unapplied one holds the TextUndo that redoes it.
+// Editing after an undo adds a sibling branch, so nothing is ever thrown away.
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TextUndoNode {
+    pub parent: usize,
+    // the branch redo walks into, which is the child we most recently came from or created
+    pub redo_child: Option<usize>,
+    pub text_undo: Option<TextUndo>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TextUndoTree {
+    pub nodes: Vec<TextUndoNode>,
+    pub current: usize,
+    pub save_point: usize
+}
+
+impl Default for TextUndoTree {
+    fn default() -> TextUndoTree {
+        TextUndoTree {
+            nodes: vec![TextUndoNode {
+                parent: 0,
+                redo_child: None,
+                text_undo: None
+            }],
+            current: 0,
+            save_point: 0
+        }
+    }
+}
+
+impl TextUndoTree {
+    pub fn push(&mut self, text_undo: TextUndo) {
+        let index = self.nodes.len();
+        self.nodes.push(TextUndoNode {
+            parent: self.current,
+            redo_child: None,
+            text_undo: Some(text_undo)
+        });
+        self.nodes[self.current].redo_child = Some(index);
+        self.current = index;
+    }
+    
+    pub fn can_undo(&self) -> bool {
+        self.current != 0
+    }
+    
+    pub fn can_redo(&self) -> bool {
+        self.nodes[self.current].redo_child.is_some()
+    }
+    
+    // the children of the current node, oldest first
+    pub fn redo_branches(&self) -> Vec<usize> {
+        (0..self.nodes.len()).filter( | index | *index != 0 && self.nodes[*index].parent == self.current).collect()
+    }
+    
+    pub fn select_redo_branch(&mut self, child: usize) -> bool {
+        if child == 0 || child >= self.nodes.len() || self.nodes[child].parent != self.current {
+            return false
+        }
+        self.nodes[self.current].redo_child = Some(child);
+        true
+    }
+    
+    // makes redo go into the branch after the one it takes now, wrapping around
+    pub fn next_redo_branch(&mut self) -> bool {
+        let branches = self.redo_branches();
+        if branches.len() < 2 {
+            return false
+        }
+        let at = self.nodes[self.current].redo_child.and_then( | child | branches.iter().position( | branch | *branch == child)).unwrap_or(branches.len() - 1);
+        self.nodes[self.current].redo_child = Some(branches[(at + 1) % branches.len()]);
+        true
+    }
+    
+    // the first edit redoing into child would make, to tell the branches apart
+    pub fn redo_branch_op(&self, child: usize) -> Option<&TextOp> {
+        self.nodes.get(child) ?.text_undo.as_ref() ?.ops.first()
+    }
+    
+    // grouping of the node undo would revert
+    pub fn undo_grouping(&self) -> Option<&TextUndoGrouping> {
+        self.nodes[self.current].text_undo.as_ref().map( | text_undo | &text_undo.grouping)
+    }
+    
+    // grouping of the node redo would apply
+    pub fn redo_grouping(&self) -> Option<&TextUndoGrouping> {
+        self.nodes[self.current].redo_child.and_then( | child | self.nodes[child].text_undo.as_ref().map( | text_undo | &text_undo.grouping))
+    }
+    
+    // takes out the TextUndo that steps back to the parent, put its inverse back with finish_undo
+    pub fn begin_undo(&mut self) -> Option<TextUndo> {
+        if self.current == 0 {
+            return None
+        }
+        self.nodes[self.current].text_undo.take()
+    }
+    
+    pub fn finish_undo(&mut self, text_redo: TextUndo) {
+        let node = self.current;
+        self.nodes[node].text_undo = Some(text_redo);
+        self.current = self.nodes[node].parent;
+        self.nodes[self.current].redo_child = Some(node);
+    }
+    
+    pub fn begin_redo(&mut self) -> Option<TextUndo> {
+        let child = self.nodes[self.current].redo_child?;
+        self.nodes[child].text_undo.take()
+    }
+    
+    pub fn finish_redo(&mut self, text_undo: TextUndo) {
+        let child = self.nodes[self.current].redo_child.unwrap();
+        self.nodes[child].text_undo = Some(text_undo);
+        self.current = child;
+    }
+    
+    pub fn mark_saved(&mut self) {
+        self.save_point = self.current;
+    }
+    
+    pub fn is_dirty(&self) -> bool {
+        self.current != self.save_point
+    }
+    
+    // a copy of at most max_nodes nodes to store. Too big a tree keeps the newest edits up to
+    // current and the redo chain after it, the oldest of those becomes the new root and side
+    // branches are dropped.
+    pub fn trimmed(&self, max_nodes: usize) -> TextUndoTree {
+        if self.nodes.len() <= max_nodes {
+            return self.clone()
+        }
+        let mut kept = vec![self.current];
+        while kept.len() < max_nodes && *kept.last().unwrap() != 0 {
+            kept.push(self.nodes[*kept.last().unwrap()].parent);
+        }
+        kept.reverse();
+        let mut redo = self.nodes[self.current].redo_child;
+        while let Some(child) = redo {
+            if kept.len() >= max_nodes {
+                break
+            }
+            kept.push(child);
+            redo = self.nodes[child].redo_child;
+        }
+        
+        let new_index = | old: usize | kept.iter().position( | index | *index == old);
+        let nodes = kept.iter().enumerate().map( | (index, old) | {
+            let node = &self.nodes[*old];
+            TextUndoNode {
+                parent: if index == 0 {0} else {new_index(node.parent).unwrap()},
+                redo_child: node.redo_child.and_then( | child | new_index(child)),
+                text_undo: if index == 0 {None} else {node.text_undo.clone()}
+            }
+        }).collect();
+        TextUndoTree {
+            nodes: nodes,
+            current: new_index(self.current).unwrap(),
+            save_point: new_index(self.save_point).unwrap_or(std::usize::MAX)
+        }
+    }
+}
+
+// how many undo steps per file survive a restart
+pub const TEXT_UNDO_STORED_NODES: usize = 500;
+
+// what gets written to disk, the hash is of the text at the save point so a file
+// that changed behind our back doesnt get the wrong history
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TextUndoHistory {
+    pub content_hash: u64,
+    pub undo_tree: TextUndoTree
+}
+
+// fnv-1a, its stable across runs and platforms unlike the std hasher
+pub fn text_content_hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textcursor::*;
+    
+    fn text_undo(start: usize) -> TextUndo {
+        TextUndo {
+            ops: vec![TextOp {start: start, len: 0, lines: vec![vec!['x']]}],
+            grouping: TextUndoGrouping::Other,
+            cursors: TextCursorSet::new()
+        }
+    }
+    
+    // undo and redo without a buffer, the ops just get handed back
+    fn undo(tree: &mut TextUndoTree) {
+        let text_undo = tree.begin_undo().unwrap();
+        tree.finish_undo(text_undo);
+    }
+    
+    fn redo(tree: &mut TextUndoTree) {
+        let text_redo = tree.begin_redo().unwrap();
+        tree.finish_redo(text_redo);
+    }
+    
+    #[test]
+    fn save_point_follows_the_branches() {
+        let mut tree = TextUndoTree::default();
+        tree.push(text_undo(1));
+        tree.push(text_undo(2));
+        tree.mark_saved();
+        assert!(!tree.is_dirty());
+        // a new branch next to the saved one
+        undo(&mut tree);
+        assert!(tree.is_dirty());
+        tree.push(text_undo(3));
+        assert!(tree.is_dirty());
+        undo(&mut tree);
+        assert_eq!(tree.redo_branches(), vec![2, 3]);
+        // redo takes the branch we came from, next_redo_branch the other one
+        assert_eq!(tree.redo_branch_op(tree.nodes[tree.current].redo_child.unwrap()).unwrap().start, 3);
+        assert!(tree.next_redo_branch());
+        redo(&mut tree);
+        assert_eq!(tree.current, 2);
+        assert!(!tree.is_dirty());
+        undo(&mut tree);
+        assert!(tree.next_redo_branch());
+        assert!(tree.select_redo_branch(3));
+        assert!(!tree.select_redo_branch(1));
+        redo(&mut tree);
+        assert!(tree.is_dirty());
+        // one child is nothing to switch between
+        undo(&mut tree);
+        undo(&mut tree);
+        assert!(!tree.next_redo_branch());
+    }
+    
+    #[test]
+    fn trimming_keeps_the_newest_path() {
+        let mut tree = TextUndoTree::default();
+        for i in 0..TEXT_UNDO_STORED_NODES - 10 {
+            tree.push(text_undo(i));
+        }
+        tree.mark_saved();
+        for _ in 0..5 {
+            undo(&mut tree);
+        }
+        // a side branch makes it too big, trimming drops it and keeps the redo chain
+        let branch_point = tree.current;
+        for i in 0..20 {
+            tree.push(text_undo(1000 + i));
+        }
+        for _ in 0..20 {
+            undo(&mut tree);
+        }
+        assert!(tree.select_redo_branch(branch_point + 1));
+        assert!(tree.nodes.len() > TEXT_UNDO_STORED_NODES);
+        
+        let mut trimmed = tree.trimmed(TEXT_UNDO_STORED_NODES);
+        assert_eq!(trimmed.nodes.len(), TEXT_UNDO_STORED_NODES - 9);
+        assert_eq!(trimmed.redo_branches().len(), 1);
+        assert!(trimmed.is_dirty());
+        for _ in 0..5 {
+            redo(&mut trimmed);
+        }
+        assert!(!trimmed.is_dirty());
+        assert!(!trimmed.can_redo());
+        
+        // too long a history loses its oldest edits, and a save point that went with them
+        let mut tree = TextUndoTree::default();
+        tree.mark_saved();
+        for i in 0..TEXT_UNDO_STORED_NODES + 10 {
+            tree.push(text_undo(i));
+        }
+        let mut trimmed = tree.trimmed(TEXT_UNDO_STORED_NODES);
+        assert_eq!(trimmed.nodes.len(), TEXT_UNDO_STORED_NODES);
+        assert!(trimmed.nodes[0].text_undo.is_none());
+        assert_eq!(trimmed.save_point, std::usize::MAX);
+        assert!(trimmed.is_dirty());
+        let mut undone = 0;
+        while trimmed.can_undo() {
+            undo(&mut trimmed);
+            undone += 1;
+        }
+        assert_eq!(undone, TEXT_UNDO_STORED_NODES - 1);
+        // small trees are kept as they are
+        assert_eq!(TextUndoTree::default().trimmed(TEXT_UNDO_STORED_NODES).nodes.len(), 1);
+    }
+}
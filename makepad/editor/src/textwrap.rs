@@ -0,0 +1,163 @@
+use crate::textrope::*;
+
+// Soft wrapping. A row longer than the wrap width is drawn as several visual lines, it breaks
+// after whitespace where it can and anywhere when a word doesnt fit. The continuation lines
+// start at the indent of the row. Its all in columns, the code font is monospaced.
+// A visual column is where on its visual line something is, counting the indent of continuation lines.
+
+#[derive(Clone, Default)]
+pub struct TextWraps {
+    // in columns, 0 doesnt wrap
+    pub width: usize,
+    // per row the columns its continuation lines start at, empty if it fits
+    pub breaks: Vec<Vec<usize>>,
+    // per row the indent of its continuation lines
+    pub indents: Vec<usize>,
+    pub mutation_id: u64
+}
+
+impl TextWraps {
+    pub fn update(&mut self, width: usize, lines: &TextRope, mutation_id: u64) {
+        if self.width == width && self.mutation_id == mutation_id && self.breaks.len() == lines.len() {
+            return
+        }
+        self.width = width;
+        self.mutation_id = mutation_id;
+        self.breaks.truncate(0);
+        self.indents.truncate(0);
+        if width == 0 {
+            return
+        }
+        for line in lines.iter() {
+            let (breaks, indent) = TextWraps::compute_breaks(line, width);
+            self.breaks.push(breaks);
+            self.indents.push(indent);
+        }
+    }
+    
+    pub fn compute_breaks(line: &[char], width: usize) -> (Vec<usize>, usize) {
+        let mut breaks = Vec::new();
+        if width == 0 || line.len() <= width {
+            return (breaks, 0)
+        }
+        let is_space = | ch: char | ch == ' ' || ch == '\t';
+        let lead = line.iter().take_while( | ch | is_space(**ch)).count();
+        // deep indents leave too little room, those go back to the left
+        let indent = if lead > width / 2 {0} else {lead};
+        let mut start = 0;
+        let mut avail = width;
+        while line.len() - start > avail {
+            let limit = start + avail;
+            // the first line never breaks inside its own indent
+            let min = if start == 0 {lead + 1} else {start + 1};
+            let mut brk = limit;
+            let mut pos = limit;
+            while pos > min {
+                if is_space(line[pos - 1]) && !is_space(line[pos]) {
+                    brk = pos;
+                    break
+                }
+                pos -= 1;
+            }
+            breaks.push(brk);
+            start = brk;
+            avail = (width - indent).max(1);
+        }
+        (breaks, indent)
+    }
+    
+    pub fn row_breaks(&self, row: usize) -> &[usize] {
+        if row < self.breaks.len() {&self.breaks[row]} else {&[]}
+    }
+    
+    pub fn visual_lines(&self, row: usize) -> usize {
+        self.row_breaks(row).len() + 1
+    }
+    
+    // the column a visual line of the row starts at
+    pub fn line_start(&self, row: usize, line: usize) -> usize {
+        if line == 0 {0} else {self.row_breaks(row)[line - 1]}
+    }
+    
+    pub fn line_indent(&self, row: usize, line: usize) -> usize {
+        if line == 0 || row >= self.indents.len() {0} else {self.indents[row]}
+    }
+    
+    // the visual line and visual column of a column of a row
+    pub fn visual_pos(&self, row: usize, col: usize) -> (usize, usize) {
+        let line = self.row_breaks(row).iter().take_while( | brk | **brk <= col).count();
+        (line, col - self.line_start(row, line) + self.line_indent(row, line))
+    }
+    
+    // the column at a visual column of a visual line, it stays on that line
+    pub fn col_at_visual(&self, row: usize, line: usize, visual_col: usize) -> usize {
+        let breaks = self.row_breaks(row);
+        let line = line.min(breaks.len());
+        let start = self.line_start(row, line);
+        let col = start + visual_col.max(self.line_indent(row, line)) - self.line_indent(row, line);
+        if line < breaks.len() {
+            col.min(breaks[line] - 1)
+        }
+        else {
+            col
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbuffer::*;
+    use crate::textcursor::*;
+    use std::rc::Rc;
+    
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+    
+    #[test]
+    fn breaks_after_whitespace_and_keep_the_indent() {
+        assert_eq!(TextWraps::compute_breaks(&chars("short"), 10), (vec![], 0));
+        assert_eq!(TextWraps::compute_breaks(&chars("aaa bbb ccc ddd"), 10), (vec![8], 0));
+        // continuation lines get 10 - 2 columns
+        assert_eq!(TextWraps::compute_breaks(&chars("  aa bb cc dd ee ff"), 10), (vec![8, 14], 2));
+        // a word that doesnt fit is cut
+        assert_eq!(TextWraps::compute_breaks(&chars("abcdefghijklmnopq"), 8), (vec![8, 16], 0));
+        // and the indent itself never ends up on a line of its own
+        assert_eq!(TextWraps::compute_breaks(&chars("      abcdefghij"), 8), (vec![8], 0));
+    }
+    
+    #[test]
+    fn cursors_move_by_visual_line() {
+        let mut text_buffer = TextBuffer::from_test_str("fn a() {}\n    let x = aaa + bbb + ccc;\nb\n");
+        let mut wraps = TextWraps::default();
+        wraps.update(12, &text_buffer.lines, text_buffer.mutation_id);
+        assert_eq!(wraps.row_breaks(1), &[12, 18, 24]);
+        assert_eq!(wraps.visual_pos(1, 16), (1, 8));
+        assert_eq!(wraps.col_at_visual(1, 0, 40), 11);
+        assert_eq!(wraps.col_at_visual(1, 1, 2), 12);
+        
+        let mut cursors = TextCursorSet::new();
+        cursors.wraps = Rc::new(wraps);
+        cursors.clear_and_set_last_cursor_head_and_tail(6, &text_buffer);
+        // another editor on the same buffer that doesnt wrap
+        let mut other_cursors = TextCursorSet::new();
+        other_cursors.clear_and_set_last_cursor_head_and_tail(6, &text_buffer);
+        let mut rows_cols = Vec::new();
+        for _ in 0..5 {
+            cursors.move_down(1, false, &text_buffer);
+            let pos = text_buffer.offset_to_text_pos(cursors.set[0].head);
+            rows_cols.push((pos.row, pos.col));
+        }
+        assert_eq!(rows_cols, vec![(1, 6), (1, 14), (1, 20), (1, 26), (2, 1)]);
+        cursors.move_up(2, false, &text_buffer);
+        assert_eq!(text_buffer.offset_to_text_pos(cursors.set[0].head), TextPos {row: 1, col: 20});
+        other_cursors.move_down(2, false, &text_buffer);
+        assert_eq!(text_buffer.offset_to_text_pos(other_cursors.set[0].head), TextPos {row: 2, col: 1});
+        
+        // without a width its rows again
+        cursors.wraps = Rc::new(TextWraps::default());
+        cursors.move_up(1, false, &text_buffer);
+        assert_eq!(text_buffer.offset_to_text_pos(cursors.set[0].head), TextPos {row: 0, col: 6});
+    }
+}
@@ -0,0 +1,185 @@
+use crate::textbuffer::*;
+use crate::language::*;
+
+pub struct TomlLanguage;
+
+impl Language for TomlLanguage {
+    fn name(&self) -> &'static str {"toml"}
+    fn extensions(&self) -> &'static [&'static str] {&["toml"]}
+    fn file_names(&self) -> &'static [&'static str] {&["Cargo.lock"]}
+    fn new_tokenizer(&self) -> Box<dyn Tokenizer> {Box::new(TomlTokenizer::new())}
+    fn line_comment(&self) -> Option<&'static str> {Some("#")}
+}
+
+pub struct TomlTokenizer {
+    // the quote of the """ or ''' string we are in, or \0
+    pub multiline: char,
+    // open arrays and inline tables, a [ at the start of a line inside one isnt a table header
+    pub depth: usize
+}
+
+impl Tokenizer for TomlTokenizer {
+    fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        TomlTokenizer::next_token(self, state, chunk, token_chunks)
+    }
+    
+    fn line_state(&self) -> u64 {
+        (self.depth as u64) << 8 | self.multiline as u64
+    }
+    
+    fn set_line_state(&mut self, line_state: u64) {
+        self.multiline = (line_state & 0xff) as u8 as char;
+        self.depth = (line_state >> 8) as usize;
+    }
+}
+
+impl TomlTokenizer {
+    pub fn new() -> TomlTokenizer {
+        TomlTokenizer {
+            multiline: '\0',
+            depth: 0
+        }
+    }
+    
+    pub fn next_token<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, token_chunks: &Vec<TokenChunk>) -> TokenType {
+        if self.multiline != '\0' && state.next != '\n' && state.next != '\0' {
+            let quote = self.multiline;
+            self.parse_string_tail(state, chunk, quote, true);
+            return TokenType::String
+        }
+        state.advance_with_cur();
+        match state.cur {
+            '\0' => { // eof insert a terminating space and end
+                chunk.push(' ');
+                TokenType::Eof
+            },
+            '\n' => {
+                chunk.push('\n');
+                TokenType::Newline
+            },
+            ' ' | '\t' => {
+                chunk.push(state.cur);
+                while state.next == ' ' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Whitespace
+            },
+            '#' => {
+                chunk.push(state.cur);
+                while state.next != '\n' && state.next != '\0' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::CommentLine
+            },
+            '"' | '\'' => {
+                let quote = state.cur;
+                chunk.push(quote);
+                if state.next == quote {
+                    chunk.push(state.next);
+                    state.advance();
+                    if state.next != quote { // empty string
+                        return TokenType::String
+                    }
+                    chunk.push(state.next);
+                    state.advance();
+                    self.multiline = quote;
+                    self.parse_string_tail(state, chunk, quote, true);
+                    return TokenType::String
+                }
+                self.parse_string_tail(state, chunk, quote, false);
+                TokenType::String
+            },
+            '[' => {
+                chunk.push(state.cur);
+                if self.depth == 0 && TokenChunk::is_line_start(token_chunks) { // [table] or [[array.of.tables]]
+                    while state.next != ']' && state.next != '\n' && state.next != '\0' {
+                        chunk.push(state.next);
+                        state.advance();
+                    }
+                    while state.next == ']' {
+                        chunk.push(state.next);
+                        state.advance();
+                    }
+                    return TokenType::TypeDef
+                }
+                self.depth += 1;
+                TokenType::ParenOpen
+            },
+            '{' => {
+                chunk.push(state.cur);
+                self.depth += 1;
+                TokenType::ParenOpen
+            },
+            ']' | '}' => {
+                chunk.push(state.cur);
+                self.depth = self.depth.max(1) - 1;
+                TokenType::ParenClose
+            },
+            '=' | '.' => {
+                chunk.push(state.cur);
+                TokenType::Operator
+            },
+            ',' => {
+                chunk.push(state.cur);
+                TokenType::Delimiter
+            },
+            '0'..='9' | '+' | '-' => { // numbers and dates
+                chunk.push(state.cur);
+                while state.next.is_alphanumeric() || state.next == '_' || state.next == '.' || state.next == ':' || state.next == '+' || state.next == '-' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                TokenType::Number
+            },
+            ch if ch.is_alphanumeric() || ch == '_' => { // bare keys
+                let start = chunk.len();
+                chunk.push(ch);
+                while state.next.is_alphanumeric() || state.next == '_' || state.next == '-' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+                match chunk[start..].iter().collect::<String>().as_str() {
+                    "true" | "false" => TokenType::Bool,
+                    "inf" | "nan" => TokenType::Number,
+                    _ => TokenType::Identifier
+                }
+            },
+            _ => {
+                chunk.push(state.cur);
+                TokenType::Unexpected
+            }
+        }
+    }
+    
+    // eats string chars up to the closing quote(s) or the end of the line
+    fn parse_string_tail<'a>(&mut self, state: &mut TokenizerState<'a>, chunk: &mut Vec<char>, quote: char, triple: bool) {
+        while state.next != '\n' && state.next != '\0' {
+            let ch = state.next;
+            chunk.push(ch);
+            state.advance();
+            if ch == '\\' && quote == '"' {
+                if state.next != '\n' && state.next != '\0' {
+                    chunk.push(state.next);
+                    state.advance();
+                }
+            }
+            else if ch == quote {
+                if !triple {
+                    return
+                }
+                if state.next == quote {
+                    chunk.push(state.next);
+                    state.advance();
+                    if state.next == quote {
+                        chunk.push(state.next);
+                        state.advance();
+                        self.multiline = '\0';
+                        return
+                    }
+                }
+            }
+        }
+    }
+}
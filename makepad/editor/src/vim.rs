@@ -0,0 +1,1388 @@
+use render::*;
+use std::collections::HashMap;
+use crate::textbuffer::*;
+use crate::textcursor::*;
+use crate::textsearch::*;
+use crate::language::*;
+
+// Vim style modal editing on top of the TextCursorSet. Normal mode keys come in as TextInput
+// so the keyboard layout decides what a key is, only escape, return and backspace come from
+// KeyDown. Word motions go by the token chunks like ctrl+left/right do and % follows the
+// pair_token of a bracket, so it all needs fresh tokens, which is why we get the Language.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VimMode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    CommandLine
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VimKey {
+    Char(char),
+    Escape,
+    Return,
+    Backspace
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VimEvent {
+    // not ours, the editor does what it always does with it
+    None,
+    // ate the key, only needs a redraw
+    Handled,
+    // cursors moved or text changed, scroll to the last cursor
+    CursorsChanged,
+    // :w
+    Save
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VimMotion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    // gg and G, with a count they both go to that line
+    FileStart,
+    FileEnd,
+    FindChar {ch: char, till: bool, back: bool},
+    MatchPair,
+    // iw and aw, only after an operator or in visual mode
+    InnerWord,
+    AroundWord
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VimOperator {
+    Delete,
+    Change,
+    Yank
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VimAction {
+    Move(VimMotion),
+    Operator(VimOperator, VimMotion),
+    // dd cc yy
+    OperatorLine(VimOperator),
+    // d c y x on the visual selection
+    VisualOperator(VimOperator),
+    // i a I A o O
+    Insert(char),
+    DeleteChar,
+    DeleteToEnd,
+    ChangeToEnd,
+    Paste {before: bool},
+    Undo,
+    Repeat,
+    Visual,
+    VisualLine,
+    CommandLine
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct VimCommand {
+    register: Option<char>,
+    count: Option<usize>,
+    action: VimAction
+}
+
+enum VimParse<T> {
+    Incomplete,
+    Invalid,
+    Done(T)
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VimRegister {
+    pub text: String,
+    pub linewise: bool
+}
+
+// what . does again, the keys of the command and what got typed in insert mode after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct VimChange {
+    pub keys: Vec<char>,
+    pub insert: Option<String>
+}
+
+#[derive(Clone)]
+pub struct Vim {
+    pub enabled: bool,
+    pub mode: VimMode,
+    pub registers: HashMap<char, VimRegister>,
+    pub command_line: String,
+    // the complaint of the last command, shown until the next key
+    pub error: Option<String>,
+    
+    pub _pending: Vec<char>,
+    pub _last_change: Option<VimChange>,
+    // the change thats still in insert mode
+    pub _recording: Option<VimChange>,
+    pub _replaying: bool,
+    pub _insert_start: usize,
+    pub _visual_anchor: usize,
+    pub _visual_head: usize,
+    // for :'<,'>
+    pub _last_visual_rows: Option<(usize, usize)>
+}
+
+impl Vim {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            mode: VimMode::Normal,
+            registers: HashMap::new(),
+            command_line: String::new(),
+            error: None,
+            _pending: Vec::new(),
+            _last_change: None,
+            _recording: None,
+            _replaying: false,
+            _insert_start: 0,
+            _visual_anchor: 0,
+            _visual_head: 0,
+            _last_visual_rows: None
+        }
+    }
+    
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.mode = VimMode::Normal;
+        self._pending.truncate(0);
+        self._recording = None;
+        self.error = None;
+    }
+    
+    pub fn handle_key_down(&mut self, ke: &KeyEvent, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) -> VimEvent {
+        if !self.enabled {
+            return VimEvent::None
+        }
+        let cmd = ke.modifiers.control || ke.modifiers.logo;
+        let typing = self.mode == VimMode::Insert;
+        match ke.key_code {
+            KeyCode::Escape => self.handle_key(VimKey::Escape, cursors, text_buffer, language),
+            KeyCode::Return if !typing && !cmd => self.handle_key(VimKey::Return, cursors, text_buffer, language),
+            KeyCode::Backspace if !typing && !cmd => self.handle_key(VimKey::Backspace, cursors, text_buffer, language),
+            KeyCode::Delete if !typing && !cmd => self.handle_key(VimKey::Char('x'), cursors, text_buffer, language),
+            KeyCode::Tab if !typing => VimEvent::Handled,
+            KeyCode::KeyR if self.mode == VimMode::Normal && ke.modifiers.control => {
+                text_buffer.redo(true, cursors);
+                Self::collapse_to_start(cursors, text_buffer);
+                VimEvent::CursorsChanged
+            },
+            _ => VimEvent::None
+        }
+    }
+    
+    pub fn handle_text_input(&mut self, te: &TextInputEvent, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) -> VimEvent {
+        if !self.enabled || self.mode == VimMode::Insert {
+            return VimEvent::None
+        }
+        if te.was_paste && self.mode != VimMode::CommandLine {
+            return VimEvent::Handled
+        }
+        let mut event = VimEvent::Handled;
+        for ch in te.input.chars() {
+            match self.handle_key(VimKey::Char(ch), cursors, text_buffer, language) {
+                VimEvent::Handled | VimEvent::None => (),
+                key_event => if event != VimEvent::Save {
+                    event = key_event
+                }
+            }
+        }
+        event
+    }
+    
+    pub fn handle_key(&mut self, key: VimKey, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) -> VimEvent {
+        if !self.enabled {
+            return VimEvent::None
+        }
+        self.error = None;
+        match self.mode {
+            VimMode::Insert => match key {
+                VimKey::Escape => {
+                    self.leave_insert(cursors, text_buffer);
+                    VimEvent::CursorsChanged
+                },
+                _ => VimEvent::None
+            },
+            VimMode::CommandLine => match key {
+                VimKey::Escape => {
+                    self.mode = VimMode::Normal;
+                    self.command_line.truncate(0);
+                    VimEvent::Handled
+                },
+                VimKey::Backspace => {
+                    if self.command_line.pop().is_none() {
+                        self.mode = VimMode::Normal;
+                    }
+                    VimEvent::Handled
+                },
+                VimKey::Char(ch) => {
+                    self.command_line.push(ch);
+                    VimEvent::Handled
+                },
+                VimKey::Return => {
+                    self.mode = VimMode::Normal;
+                    let line = std::mem::replace(&mut self.command_line, String::new());
+                    match self.run_command_line(&line, cursors, text_buffer) {
+                        Ok(event) => event,
+                        Err(err) => {
+                            self.error = Some(err);
+                            VimEvent::Handled
+                        }
+                    }
+                }
+            },
+            _ => match key {
+                VimKey::Escape => {
+                    self._pending.truncate(0);
+                    if self.mode != VimMode::Normal {
+                        self.leave_visual(cursors, text_buffer);
+                        return VimEvent::CursorsChanged
+                    }
+                    VimEvent::Handled
+                },
+                VimKey::Return => self.pending_key('j', cursors, text_buffer, language),
+                VimKey::Backspace => self.pending_key('h', cursors, text_buffer, language),
+                VimKey::Char(ch) => self.pending_key(ch, cursors, text_buffer, language)
+            }
+        }
+    }
+    
+    fn pending_key(&mut self, ch: char, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) -> VimEvent {
+        self._pending.push(ch);
+        let visual = self.mode == VimMode::Visual || self.mode == VimMode::VisualLine;
+        match parse_command(&self._pending, visual) {
+            VimParse::Incomplete => VimEvent::Handled,
+            VimParse::Invalid => {
+                self._pending.truncate(0);
+                VimEvent::Handled
+            },
+            VimParse::Done(command) => {
+                let keys = std::mem::replace(&mut self._pending, Vec::new());
+                if text_buffer.needs_token_chunks() {
+                    text_buffer.update_token_chunks(&mut *language.new_tokenizer());
+                }
+                self.run(command, keys, cursors, text_buffer, language)
+            }
+        }
+    }
+    
+    fn run(&mut self, command: VimCommand, keys: Vec<char>, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) -> VimEvent {
+        let count = command.count.unwrap_or(1);
+        let is_change = match command.action {
+            VimAction::Operator(op, _) | VimAction::OperatorLine(op) => op != VimOperator::Yank,
+            VimAction::Insert(_) | VimAction::DeleteChar | VimAction::DeleteToEnd | VimAction::ChangeToEnd | VimAction::Paste {..} => true,
+            _ => false
+        };
+        if is_change && !self._replaying {
+            self._recording = Some(VimChange {keys: keys, insert: None});
+        }
+        match command.action {
+            VimAction::Move(motion) => {
+                if self.mode != VimMode::Normal {
+                    match motion {
+                        VimMotion::InnerWord | VimMotion::AroundWord => if let Some((start, end)) = word_object(self._visual_head, motion == VimMotion::AroundWord, text_buffer) {
+                            self._visual_anchor = start;
+                            self._visual_head = end.max(start + 1) - 1;
+                        },
+                        _ => if let Some(target) = motion_target(self._visual_head, motion, command.count, text_buffer) {
+                            self._visual_head = clamp_normal(target, text_buffer);
+                        }
+                    }
+                    self.select_visual(cursors, text_buffer);
+                }
+                else if motion == VimMotion::Up || motion == VimMotion::Down {
+                    // through the cursor set so the column sticks
+                    if motion == VimMotion::Up {
+                        cursors.move_up(count, false, text_buffer);
+                    }
+                    else {
+                        cursors.move_down(count, false, text_buffer);
+                    }
+                    for cursor in &mut cursors.set {
+                        cursor.head = clamp_normal(cursor.head, text_buffer);
+                        cursor.tail = cursor.head;
+                    }
+                }
+                else {
+                    let heads = cursors.set.iter().map( | cursor | {
+                        motion_target(cursor.head, motion, command.count, text_buffer).map_or(cursor.head, | target | clamp_normal(target, text_buffer))
+                    }).collect();
+                    set_heads(cursors, heads, text_buffer);
+                }
+            },
+            VimAction::Operator(op, motion) => {
+                let mut linewise = false;
+                let mut ranges = Vec::new();
+                for cursor in &cursors.set {
+                    if let Some((start, end, is_line)) = operator_range(cursor.head, op, motion, command.count, text_buffer) {
+                        linewise = is_line;
+                        ranges.push((start, end));
+                    }
+                }
+                if ranges.len() == 0 {
+                    self._recording = None;
+                    return VimEvent::Handled
+                }
+                self.apply_operator(op, ranges, linewise, command.register, cursors, text_buffer, language);
+            },
+            VimAction::OperatorLine(op) => {
+                let ranges = cursors.set.iter().map( | cursor | {
+                    let row = text_buffer.offset_to_text_pos(cursor.head).row;
+                    line_range(row, row + count - 1, text_buffer)
+                }).collect();
+                self.apply_operator(op, ranges, true, command.register, cursors, text_buffer, language);
+            },
+            VimAction::VisualOperator(op) => {
+                let linewise = self.mode == VimMode::VisualLine;
+                let range = if linewise {
+                    let (start_row, end_row) = self.visual_rows(text_buffer);
+                    line_range(start_row, end_row, text_buffer)
+                }
+                else {
+                    let start = self._visual_anchor.min(self._visual_head);
+                    let end = self._visual_anchor.max(self._visual_head) + 1;
+                    (start, end.min(text_buffer.lines.char_count()))
+                };
+                self._last_visual_rows = Some(self.visual_rows(text_buffer));
+                self.mode = VimMode::Normal;
+                self.apply_operator(op, vec![range], linewise, command.register, cursors, text_buffer, language);
+            },
+            VimAction::Insert(ch) => {
+                self.enter_insert(ch, cursors, text_buffer, language);
+            },
+            VimAction::DeleteChar => {
+                let ranges: Vec<(usize, usize)> = cursors.set.iter().map( | cursor | {
+                    let (_, line_end) = row_range(text_buffer.offset_to_text_pos(cursor.head).row, text_buffer);
+                    (cursor.head, (cursor.head + count).min(line_end))
+                }).filter( | (start, end) | end > start).collect();
+                if ranges.len() == 0 {
+                    self._recording = None;
+                    return VimEvent::Handled
+                }
+                self.apply_operator(VimOperator::Delete, ranges, false, command.register, cursors, text_buffer, language);
+            },
+            VimAction::DeleteToEnd | VimAction::ChangeToEnd => {
+                let op = if command.action == VimAction::DeleteToEnd {VimOperator::Delete} else {VimOperator::Change};
+                let ranges = cursors.set.iter().filter_map( | cursor | {
+                    operator_range(cursor.head, op, VimMotion::LineEnd, command.count, text_buffer)
+                }).map( | (start, end, _) | (start, end)).collect();
+                self.apply_operator(op, ranges, false, command.register, cursors, text_buffer, language);
+            },
+            VimAction::Paste {before} => {
+                self.paste(before, count, command.register, cursors, text_buffer);
+            },
+            VimAction::Undo => {
+                for _ in 0..count {
+                    text_buffer.undo(true, cursors);
+                }
+                Self::collapse_to_start(cursors, text_buffer);
+            },
+            VimAction::Repeat => {
+                if let Some(change) = self._last_change.clone() {
+                    self._replaying = true;
+                    for _ in 0..count {
+                        for ch in &change.keys {
+                            self.pending_key(*ch, cursors, text_buffer, language);
+                        }
+                        if self.mode == VimMode::Insert {
+                            if let Some(insert) = &change.insert {
+                                cursors.replace_text(insert, text_buffer);
+                            }
+                            self.leave_insert(cursors, text_buffer);
+                        }
+                    }
+                    self._replaying = false;
+                }
+            },
+            VimAction::Visual | VimAction::VisualLine => {
+                let mode = if command.action == VimAction::Visual {VimMode::Visual} else {VimMode::VisualLine};
+                if self.mode == mode {
+                    self.leave_visual(cursors, text_buffer);
+                }
+                else {
+                    if self.mode == VimMode::Normal {
+                        let head = cursors.set[cursors.last_cursor].head;
+                        self._visual_anchor = head;
+                        self._visual_head = head;
+                    }
+                    self.mode = mode;
+                    self.select_visual(cursors, text_buffer);
+                }
+            },
+            VimAction::CommandLine => {
+                if self.mode != VimMode::Normal {
+                    self._last_visual_rows = Some(self.visual_rows(text_buffer));
+                    self.leave_visual(cursors, text_buffer);
+                    self.command_line = "'<,'>".to_string();
+                }
+                self.mode = VimMode::CommandLine;
+                return VimEvent::Handled
+            }
+        }
+        if self.mode != VimMode::Insert {
+            if let Some(change) = self._recording.take() {
+                self._last_change = Some(change);
+            }
+        }
+        VimEvent::CursorsChanged
+    }
+    
+    fn apply_operator(&mut self, op: VimOperator, ranges: Vec<(usize, usize)>, linewise: bool, register: Option<char>, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) {
+        let mut text = String::new();
+        for (index, (start, end)) in ranges.iter().enumerate() {
+            if index > 0 {
+                text.push('\n');
+            }
+            text_buffer.get_range_as_string(*start, end - start, &mut text);
+        }
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.store_register(register, text, linewise, op == VimOperator::Yank);
+        
+        match op {
+            VimOperator::Yank => {
+                let heads = ranges.iter().map( | (start, _) | *start).collect();
+                set_heads(cursors, heads, text_buffer);
+            },
+            VimOperator::Delete => {
+                cursors.select_ranges(&ranges, cursors.last_cursor, text_buffer);
+                cursors.replace_text("", text_buffer);
+                if linewise {
+                    let heads = cursors.set.iter().map( | cursor | first_non_blank(text_buffer.offset_to_text_pos(cursor.head).row, text_buffer)).collect();
+                    set_heads(cursors, heads, text_buffer);
+                }
+                Self::collapse_normal(cursors, text_buffer);
+            },
+            VimOperator::Change => {
+                // cc keeps the indent and the line itself
+                let ranges: Vec<(usize, usize)> = if linewise {
+                    ranges.iter().map( | (start, end) | {
+                        let start_row = text_buffer.offset_to_text_pos(*start).row;
+                        let end_row = text_buffer.offset_to_text_pos(end.max(&(start + 1)) - 1).row;
+                        (first_non_blank(start_row, text_buffer), row_range(end_row, text_buffer).1)
+                    }).collect()
+                }
+                else {
+                    ranges
+                };
+                cursors.select_ranges(&ranges, cursors.last_cursor, text_buffer);
+                cursors.replace_text("", text_buffer);
+                self.enter_insert('i', cursors, text_buffer, language);
+            }
+        }
+    }
+    
+    fn store_register(&mut self, register: Option<char>, text: String, linewise: bool, yank: bool) {
+        let value = VimRegister {text: text, linewise: linewise};
+        match register {
+            // uppercase appends to the lowercase one
+            Some(name) if name.is_ascii_uppercase() => {
+                let named = self.registers.entry(name.to_ascii_lowercase()).or_insert_with(VimRegister::default);
+                named.text.push_str(&value.text);
+                named.linewise |= value.linewise;
+            },
+            Some(name) if name != '"' => {
+                self.registers.insert(name, value.clone());
+            },
+            _ => ()
+        }
+        if yank {
+            self.registers.insert('0', value.clone());
+        }
+        self.registers.insert('"', value);
+    }
+    
+    fn paste(&mut self, before: bool, count: usize, register: Option<char>, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) {
+        let register = match self.registers.get(&register.unwrap_or('"')) {
+            Some(register) => register.clone(),
+            None => {
+                self.error = Some("Nothing in register".to_string());
+                return
+            }
+        };
+        let text = register.text.repeat(count);
+        let char_count = text_buffer.lines.char_count();
+        let mut inserts = Vec::new();
+        for cursor in &cursors.set {
+            let row = text_buffer.offset_to_text_pos(cursor.head).row;
+            let (line_start, line_end) = row_range(row, text_buffer);
+            inserts.push(if register.linewise {
+                if before {
+                    (line_start, text.clone())
+                }
+                else if line_end == char_count {
+                    // the last line has no newline to go after
+                    (line_end, format!("\n{}", text.strip_suffix('\n').unwrap_or(&text)))
+                }
+                else {
+                    (line_end + 1, text.clone())
+                }
+            }
+            else {
+                let at = if before || line_end == line_start {cursor.head} else {(cursor.head + 1).min(line_end)};
+                (at, text.clone())
+            });
+        }
+        let ranges: Vec<(usize, usize)> = inserts.iter().map( | (at, _) | (*at, *at)).collect();
+        cursors.select_ranges(&ranges, cursors.last_cursor, text_buffer);
+        // the cursors all get the same text, so one replace does them all
+        cursors.replace_text(&inserts[0].1, text_buffer);
+        let len = inserts[0].1.chars().count();
+        let heads = cursors.set.iter().map( | cursor | {
+            if register.linewise {
+                let start = cursor.head - len + if inserts[0].1.starts_with('\n') {1} else {0};
+                first_non_blank(text_buffer.offset_to_text_pos(start).row, text_buffer)
+            }
+            else {
+                cursor.head.max(1) - 1
+            }
+        }).collect();
+        set_heads(cursors, heads, text_buffer);
+    }
+    
+    fn enter_insert(&mut self, ch: char, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, language: &dyn Language) {
+        match ch {
+            'a' => {
+                let heads = cursors.set.iter().map( | cursor | {
+                    let (_, line_end) = row_range(text_buffer.offset_to_text_pos(cursor.head).row, text_buffer);
+                    (cursor.head + 1).min(line_end)
+                }).collect();
+                set_heads(cursors, heads, text_buffer);
+            },
+            'I' => {
+                let heads = cursors.set.iter().map( | cursor | first_non_blank(text_buffer.offset_to_text_pos(cursor.head).row, text_buffer)).collect();
+                set_heads(cursors, heads, text_buffer);
+            },
+            'A' => cursors.move_end(false, text_buffer),
+            'o' => {
+                cursors.move_end(false, text_buffer);
+                let indent = language.indent();
+                cursors.insert_newline_with_indent(indent.width, indent.brackets, text_buffer);
+            },
+            'O' => {
+                // the new line gets the indent of the one its put above
+                let row = cursors.get_last_cursor_text_pos(text_buffer).row;
+                let (line_start, _) = row_range(row, text_buffer);
+                let indent = first_non_blank(row, text_buffer) - line_start;
+                cursors.move_home(false, text_buffer);
+                let heads = cursors.set.iter().map( | cursor | row_range(text_buffer.offset_to_text_pos(cursor.head).row, text_buffer).0).collect();
+                set_heads(cursors, heads, text_buffer);
+                cursors.replace_text(&format!("{}\n", " ".repeat(indent)), text_buffer);
+                cursors.move_left(1, false, text_buffer);
+            },
+            _ => ()
+        }
+        for cursor in &mut cursors.set {
+            cursor.tail = cursor.head;
+        }
+        self._insert_start = cursors.set[cursors.last_cursor].head;
+        self.mode = VimMode::Insert;
+    }
+    
+    fn leave_insert(&mut self, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) {
+        if let Some(mut change) = self._recording.take() {
+            if !self._replaying {
+                let head = cursors.set[cursors.last_cursor].head;
+                let mut insert = String::new();
+                if head > self._insert_start {
+                    text_buffer.get_range_as_string(self._insert_start, head - self._insert_start, &mut insert);
+                }
+                change.insert = Some(insert);
+                self._last_change = Some(change);
+            }
+        }
+        self.mode = VimMode::Normal;
+        // the cursor goes back onto the last typed char
+        let heads = cursors.set.iter().map( | cursor | {
+            let col = text_buffer.offset_to_text_pos(cursor.head).col;
+            if col > 0 {cursor.head - 1} else {cursor.head}
+        }).collect();
+        set_heads(cursors, heads, text_buffer);
+    }
+    
+    fn visual_rows(&self, text_buffer: &TextBuffer) -> (usize, usize) {
+        let anchor_row = text_buffer.offset_to_text_pos(self._visual_anchor).row;
+        let head_row = text_buffer.offset_to_text_pos(self._visual_head).row;
+        (anchor_row.min(head_row), anchor_row.max(head_row))
+    }
+    
+    // our cursors sit between chars and vim ones on a char, so the selection takes in the head
+    fn select_visual(&self, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) {
+        let (anchor, head) = (self._visual_anchor, self._visual_head);
+        let (tail, head) = if self.mode == VimMode::VisualLine {
+            let (start_row, end_row) = self.visual_rows(text_buffer);
+            let start = row_range(start_row, text_buffer).0;
+            let end = row_range(end_row, text_buffer).1;
+            if head >= anchor {(start, end)} else {(end, start)}
+        }
+        else {
+            let char_count = text_buffer.lines.char_count();
+            if head >= anchor {(anchor, (head + 1).min(char_count))} else {((anchor + 1).min(char_count), head)}
+        };
+        let mut cursor = TextCursor {head: head, tail: tail, max: 0};
+        cursor.calc_max(text_buffer, &cursors.wraps, (TextPos {row: 0, col: 0}, 0));
+        cursors.set = vec![cursor];
+        cursors.last_cursor = 0;
+    }
+    
+    fn leave_visual(&mut self, cursors: &mut TextCursorSet, text_buffer: &TextBuffer) {
+        self.mode = VimMode::Normal;
+        set_heads(cursors, vec![self._visual_head], text_buffer);
+    }
+    
+    fn collapse_normal(cursors: &mut TextCursorSet, text_buffer: &TextBuffer) {
+        let heads = cursors.set.iter().map( | cursor | clamp_normal(cursor.head, text_buffer)).collect();
+        set_heads(cursors, heads, text_buffer);
+    }
+    
+    // undo gives back the selections of before the change, vim goes to where it starts
+    fn collapse_to_start(cursors: &mut TextCursorSet, text_buffer: &TextBuffer) {
+        let heads = cursors.set.iter().map( | cursor | clamp_normal(cursor.order().0, text_buffer)).collect();
+        set_heads(cursors, heads, text_buffer);
+    }
+    
+    fn run_command_line(&mut self, line: &str, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> Result<VimEvent, String> {
+        let line = line.trim();
+        if line.len() == 0 {
+            return Ok(VimEvent::Handled)
+        }
+        if line == "w" || line == "write" {
+            return Ok(VimEvent::Save)
+        }
+        if let Ok(row) = line.parse::<usize>() {
+            let row = row.max(1).min(text_buffer.get_line_count()) - 1;
+            set_heads(cursors, vec![first_non_blank(row, text_buffer)], text_buffer);
+            return Ok(VimEvent::CursorsChanged)
+        }
+        let (rows, rest) = self.parse_range(line, cursors, text_buffer) ?;
+        if rest.starts_with('s') && rest.len() > 1 {
+            return substitute(rows, &rest[1..], cursors, text_buffer)
+        }
+        Err(format!("Not an editor command: {}", line))
+    }
+    
+    // % is all lines, '<,'> the last visual selection, 3,5 line numbers, nothing the cursor line
+    fn parse_range<'a>(&self, line: &'a str, cursors: &TextCursorSet, text_buffer: &TextBuffer) -> Result<((usize, usize), &'a str), String> {
+        let last_row = text_buffer.get_line_count().max(1) - 1;
+        if line.starts_with('%') {
+            return Ok(((0, last_row), &line[1..]))
+        }
+        if line.starts_with("'<,'>") {
+            return match self._last_visual_rows {
+                Some(rows) => Ok((rows, &line[5..])),
+                None => Err("No visual selection".to_string())
+            }
+        }
+        let digits = line.find( | ch: char | !ch.is_ascii_digit()).unwrap_or(line.len());
+        if digits == 0 {
+            let row = cursors.get_last_cursor_text_pos(text_buffer).row;
+            return Ok(((row, row), line))
+        }
+        let start: usize = line[0..digits].parse().map_err( | _ | "Invalid range".to_string()) ?;
+        let rest = &line[digits..];
+        let (end, rest) = if rest.starts_with(',') {
+            let digits = rest[1..].find( | ch: char | !ch.is_ascii_digit()).unwrap_or(rest.len() - 1) + 1;
+            let end: usize = rest[1..digits].parse().map_err( | _ | "Invalid range".to_string()) ?;
+            (end, &rest[digits..])
+        }
+        else {
+            (start, rest)
+        };
+        if start == 0 || end < start {
+            return Err("Invalid range".to_string())
+        }
+        Ok(((start - 1, (end - 1).min(last_row)), rest))
+    }
+    
+    pub fn status(&self) -> Option<String> {
+        match self.mode {
+            VimMode::Insert => Some("-- INSERT --".to_string()),
+            VimMode::Visual => Some("-- VISUAL --".to_string()),
+            VimMode::VisualLine => Some("-- VISUAL LINE --".to_string()),
+            VimMode::CommandLine => Some(format!(":{}_", self.command_line)),
+            VimMode::Normal => if let Some(error) = &self.error {
+                Some(error.clone())
+            }
+            else if self._pending.len() > 0 {
+                Some(self._pending.iter().collect())
+            }
+            else {
+                None
+            }
+        }
+    }
+}
+
+// the mode line in the bottom left corner of the editor
+#[derive(Clone)]
+pub struct VimStatus {
+    pub bg: Quad,
+    pub text: Text,
+    pub padding: f32,
+    pub bg_color: Color,
+    pub label_color: Color,
+    pub error_color: Color
+}
+
+impl VimStatus {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            bg: Quad {
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 20.,
+                ..Quad::style(cx)
+            },
+            text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                do_dpi_dilate: true,
+                do_h_scroll: false,
+                do_v_scroll: false,
+                z: 21.,
+                wrapping: Wrapping::Line,
+                ..Text::style(cx)
+            },
+            padding: 4.,
+            bg_color: color256(50, 50, 50),
+            label_color: color256(212, 212, 212),
+            error_color: color256(200, 0, 0)
+        }
+    }
+    
+    pub fn draw_vim_status(&mut self, cx: &mut Cx, vim: &Vim) {
+        if !vim.enabled {
+            return
+        }
+        let status = match vim.status() {
+            Some(status) => status,
+            None => return
+        };
+        let chunk: Vec<char> = status.chars().collect();
+        let origin = cx.get_turtle_origin();
+        let glyph_w = self.text.get_monospace_base(cx).x * self.text.font_size;
+        let line_height = self.text.get_monospace_base(cx).y * self.text.font_size;
+        let rect = Rect {
+            x: origin.x,
+            y: origin.y + cx.get_height_total() - line_height - 2. * self.padding,
+            w: chunk.len() as f32 * glyph_w + 2. * self.padding,
+            h: line_height + 2. * self.padding
+        };
+        cx.new_instance_draw_call(&self.bg.shader, 0);
+        self.bg.color = self.bg_color;
+        self.bg.draw_quad_abs(cx, rect);
+        
+        cx.new_instance_draw_call(&self.text.shader, 0);
+        let mut aligned = self.text.begin_text(cx);
+        self.text.color = if vim.error.is_some() && vim.mode == VimMode::Normal {self.error_color} else {self.label_color};
+        self.text.add_text(cx, rect.x + self.padding, rect.y + self.padding, 0, &mut aligned, &chunk, | _, _, _, _ | {0.});
+        self.text.end_text(cx, &aligned);
+    }
+}
+
+fn parse_count(keys: &[char], index: &mut usize) -> Option<usize> {
+    let start = *index;
+    // a 0 on its own is a motion
+    while *index < keys.len() && keys[*index].is_ascii_digit() && (keys[*index] != '0' || *index > start) {
+        *index += 1;
+    }
+    if *index == start {
+        return None
+    }
+    keys[start..*index].iter().collect::<String>().parse().ok()
+}
+
+fn parse_motion(keys: &[char], text_object: bool) -> VimParse<VimMotion> {
+    let first = match keys.get(0) {
+        Some(ch) => *ch,
+        None => return VimParse::Incomplete
+    };
+    let (motion, len) = match first {
+        'h' => (VimMotion::Left, 1),
+        'l' | ' ' => (VimMotion::Right, 1),
+        'j' => (VimMotion::Down, 1),
+        'k' => (VimMotion::Up, 1),
+        'w' => (VimMotion::WordForward, 1),
+        'b' => (VimMotion::WordBackward, 1),
+        'e' => (VimMotion::WordEnd, 1),
+        '0' => (VimMotion::LineStart, 1),
+        '^' => (VimMotion::FirstNonBlank, 1),
+        '$' => (VimMotion::LineEnd, 1),
+        'G' => (VimMotion::FileEnd, 1),
+        '%' => (VimMotion::MatchPair, 1),
+        'g' | 'f' | 't' | 'F' | 'T' | 'i' | 'a' => {
+            let second = match keys.get(1) {
+                Some(ch) => *ch,
+                None => return VimParse::Incomplete
+            };
+            let motion = match first {
+                'g' if second == 'g' => VimMotion::FileStart,
+                'f' => VimMotion::FindChar {ch: second, till: false, back: false},
+                't' => VimMotion::FindChar {ch: second, till: true, back: false},
+                'F' => VimMotion::FindChar {ch: second, till: false, back: true},
+                'T' => VimMotion::FindChar {ch: second, till: true, back: true},
+                'i' if text_object && second == 'w' => VimMotion::InnerWord,
+                'a' if text_object && second == 'w' => VimMotion::AroundWord,
+                _ => return VimParse::Invalid
+            };
+            (motion, 2)
+        },
+        _ => return VimParse::Invalid
+    };
+    if keys.len() > len {
+        return VimParse::Invalid
+    }
+    VimParse::Done(motion)
+}
+
+// ["x][count](operator[count]motion | operator twice | action | motion)
+fn parse_command(keys: &[char], visual: bool) -> VimParse<VimCommand> {
+    let mut index = 0;
+    let mut register = None;
+    if keys.get(0) == Some(&'"') {
+        match keys.get(1) {
+            None => return VimParse::Incomplete,
+            Some(ch) if ch.is_ascii_alphanumeric() || *ch == '"' => register = Some(*ch),
+            _ => return VimParse::Invalid
+        }
+        index = 2;
+    }
+    let count = parse_count(keys, &mut index);
+    let rest = &keys[index..];
+    let first = match rest.get(0) {
+        Some(ch) => *ch,
+        None => return VimParse::Incomplete
+    };
+    let mut count2 = None;
+    let action = match first {
+        'd' | 'c' | 'y' | 'x' if visual => VimAction::VisualOperator(match first {
+            'c' => VimOperator::Change,
+            'y' => VimOperator::Yank,
+            _ => VimOperator::Delete
+        }),
+        'd' | 'c' | 'y' => {
+            let op = match first {
+                'd' => VimOperator::Delete,
+                'c' => VimOperator::Change,
+                _ => VimOperator::Yank
+            };
+            let mut motion_index = 1;
+            count2 = parse_count(rest, &mut motion_index);
+            match rest.get(motion_index) {
+                None => return VimParse::Incomplete,
+                Some(ch) if *ch == first => {
+                    if rest.len() > motion_index + 1 {
+                        return VimParse::Invalid
+                    }
+                    VimAction::OperatorLine(op)
+                },
+                _ => match parse_motion(&rest[motion_index..], true) {
+                    VimParse::Done(motion) => VimAction::Operator(op, motion),
+                    VimParse::Incomplete => return VimParse::Incomplete,
+                    VimParse::Invalid => return VimParse::Invalid
+                }
+            }
+        },
+        'x' => VimAction::DeleteChar,
+        'D' => VimAction::DeleteToEnd,
+        'C' => VimAction::ChangeToEnd,
+        'p' => VimAction::Paste {before: false},
+        'P' => VimAction::Paste {before: true},
+        'u' => VimAction::Undo,
+        '.' => VimAction::Repeat,
+        'v' => VimAction::Visual,
+        'V' => VimAction::VisualLine,
+        ':' => VimAction::CommandLine,
+        'i' | 'a' | 'I' | 'A' | 'o' | 'O' if !visual => VimAction::Insert(first),
+        _ => match parse_motion(rest, visual) {
+            VimParse::Done(motion) => VimAction::Move(motion),
+            VimParse::Incomplete => return VimParse::Incomplete,
+            VimParse::Invalid => return VimParse::Invalid
+        }
+    };
+    let count = match (count, count2) {
+        (None, None) => None,
+        (count, count2) => Some(count.unwrap_or(1) * count2.unwrap_or(1))
+    };
+    VimParse::Done(VimCommand {register: register, count: count, action: action})
+}
+
+fn row_range(row: usize, text_buffer: &TextBuffer) -> (usize, usize) {
+    if row >= text_buffer.lines.len() {
+        let char_count = text_buffer.lines.char_count();
+        return (char_count, char_count)
+    }
+    let start = text_buffer.lines.row_offset(row);
+    (start, start + text_buffer.lines.line_len(row))
+}
+
+// whole lines with their newline, the last line takes the newline in front of it instead
+fn line_range(start_row: usize, end_row: usize, text_buffer: &TextBuffer) -> (usize, usize) {
+    let last_row = text_buffer.get_line_count().max(1) - 1;
+    let end_row = end_row.min(last_row);
+    let start = row_range(start_row, text_buffer).0;
+    let end = row_range(end_row, text_buffer).1;
+    if end_row < last_row {
+        (start, end + 1)
+    }
+    else if start > 0 {
+        (start - 1, end)
+    }
+    else {
+        (start, end)
+    }
+}
+
+fn first_non_blank(row: usize, text_buffer: &TextBuffer) -> usize {
+    let (start, _) = row_range(row, text_buffer);
+    if row >= text_buffer.lines.len() {
+        return start
+    }
+    start + text_buffer.lines[row].iter().take_while( | ch | **ch == ' ' || **ch == '\t').count()
+}
+
+// normal mode cursors sit on a char, so not past the end of a line that has chars
+fn clamp_normal(offset: usize, text_buffer: &TextBuffer) -> usize {
+    let pos = text_buffer.offset_to_text_pos(offset);
+    let len = text_buffer.lines.len().max(1);
+    let line_len = if pos.row < len {text_buffer.lines.line_len(pos.row)} else {0};
+    if line_len > 0 && pos.col >= line_len {
+        offset - (pos.col - (line_len - 1))
+    }
+    else {
+        offset
+    }
+}
+
+fn set_heads(cursors: &mut TextCursorSet, mut heads: Vec<usize>, text_buffer: &TextBuffer) {
+    if heads.len() == 0 {
+        return
+    }
+    let last = heads[cursors.last_cursor.min(heads.len() - 1)];
+    heads.sort();
+    heads.dedup();
+    let last_cursor = heads.iter().position( | head | *head == last).unwrap_or(0);
+    let ranges: Vec<(usize, usize)> = heads.iter().map( | head | (*head, *head)).collect();
+    cursors.select_ranges(&ranges, last_cursor, text_buffer);
+}
+
+// the chunks are sorted by offset, so the first one ending past offset is the one to look at
+fn chunk_index_at(offset: usize, text_buffer: &TextBuffer) -> Option<usize> {
+    let chunks = &text_buffer.token_chunks;
+    let index = chunks.partition_point( | chunk | chunk.offset + chunk.len <= offset);
+    chunks.get(index).filter( | chunk | offset >= chunk.offset).map( | _ | index)
+}
+
+fn is_blank_at(offset: usize, text_buffer: &TextBuffer) -> bool {
+    match chunk_index_at(offset, text_buffer) {
+        Some(index) => match text_buffer.token_chunks[index].token_type {
+            TokenType::Whitespace | TokenType::Newline | TokenType::Eof => true,
+            _ => false
+        },
+        None => true
+    }
+}
+
+fn word_forward(offset: usize, text_buffer: &TextBuffer) -> usize {
+    let char_count = text_buffer.lines.char_count();
+    let mut pos = if is_blank_at(offset, text_buffer) {
+        offset
+    }
+    else {
+        TextCursorSet::get_nearest_token_chunk_boundary(false, offset, text_buffer)
+    };
+    // over the whitespace to the next token, an empty line counts as a word
+    while let Some(index) = chunk_index_at(pos, text_buffer) {
+        let chunk = &text_buffer.token_chunks[index];
+        match chunk.token_type {
+            TokenType::Whitespace => pos = chunk.offset + chunk.len,
+            TokenType::Newline => {
+                pos = chunk.offset + chunk.len;
+                if text_buffer.get_char(pos) == '\n' {
+                    break
+                }
+            },
+            _ => break
+        }
+    }
+    pos.min(char_count)
+}
+
+fn word_end(offset: usize, text_buffer: &TextBuffer) -> usize {
+    let char_count = text_buffer.lines.char_count();
+    let mut pos = offset + 1;
+    while pos < char_count && is_blank_at(pos, text_buffer) {
+        pos += 1;
+    }
+    if pos >= char_count {
+        return offset
+    }
+    TextCursorSet::get_nearest_token_chunk_boundary(false, pos, text_buffer).max(pos + 1) - 1
+}
+
+fn word_backward(offset: usize, text_buffer: &TextBuffer) -> usize {
+    let mut pos = TextCursorSet::get_nearest_token_chunk_boundary(true, offset, text_buffer);
+    while pos > 0 && is_blank_at(pos, text_buffer) {
+        pos = TextCursorSet::get_nearest_token_chunk_boundary(true, pos, text_buffer);
+    }
+    pos
+}
+
+// the bracket at or after the cursor on its line, to its pair
+fn match_pair(offset: usize, text_buffer: &TextBuffer) -> Option<usize> {
+    let (_, line_end) = row_range(text_buffer.offset_to_text_pos(offset).row, text_buffer);
+    let chunks = &text_buffer.token_chunks;
+    let start = chunk_index_at(offset, text_buffer) ?;
+    for index in start..chunks.len() {
+        if chunks[index].offset >= line_end {
+            break
+        }
+        let pair = chunks[index].pair_token;
+        match chunks[index].token_type {
+            TokenType::ParenOpen if pair > index => return Some(chunks[pair].offset),
+            TokenType::ParenClose if pair < index => return Some(chunks[pair].offset),
+            _ => ()
+        }
+    }
+    None
+}
+
+fn find_char(offset: usize, ch: char, till: bool, back: bool, count: usize, text_buffer: &TextBuffer) -> Option<usize> {
+    let pos = text_buffer.offset_to_text_pos(offset);
+    let line = &text_buffer.lines[pos.row];
+    let mut col = pos.col;
+    for step in 0..count {
+        // t again right in front of the char would stay put, so it looks one further
+        let skip = if till && step == 0 {1} else {0};
+        col = if back {
+            (0..col.saturating_sub(skip)).rev().find( | col | line[*col] == ch) ?
+        }
+        else {
+            (col + 1 + skip..line.len()).find( | col | line[*col] == ch) ?
+        };
+    }
+    let col = if till {if back {col + 1} else {col - 1}} else {col};
+    Some(offset - pos.col + col)
+}
+
+fn motion_target(offset: usize, motion: VimMotion, count: Option<usize>, text_buffer: &TextBuffer) -> Option<usize> {
+    let n = count.unwrap_or(1);
+    let pos = text_buffer.offset_to_text_pos(offset);
+    let last_row = text_buffer.get_line_count().max(1) - 1;
+    let (line_start, line_end) = row_range(pos.row, text_buffer);
+    let target = match motion {
+        VimMotion::Left => offset - pos.col.min(n),
+        VimMotion::Right => (offset + n).min(line_end),
+        VimMotion::Up | VimMotion::Down => {
+            let row = if motion == VimMotion::Up {pos.row.saturating_sub(n)} else {(pos.row + n).min(last_row)};
+            let (start, end) = row_range(row, text_buffer);
+            (start + pos.col).min(end)
+        },
+        VimMotion::WordForward => (0..n).fold(offset, | pos, _ | word_forward(pos, text_buffer)),
+        VimMotion::WordBackward => (0..n).fold(offset, | pos, _ | word_backward(pos, text_buffer)),
+        VimMotion::WordEnd => (0..n).fold(offset, | pos, _ | word_end(pos, text_buffer)),
+        VimMotion::LineStart => line_start,
+        VimMotion::FirstNonBlank => first_non_blank(pos.row, text_buffer),
+        VimMotion::LineEnd => row_range((pos.row + n - 1).min(last_row), text_buffer).1,
+        VimMotion::FileStart => first_non_blank(count.map_or(0, | row | row.max(1) - 1).min(last_row), text_buffer),
+        VimMotion::FileEnd => first_non_blank(count.map_or(last_row, | row | row.max(1) - 1).min(last_row), text_buffer),
+        VimMotion::FindChar {ch, till, back} => find_char(offset, ch, till, back, n, text_buffer) ?,
+        VimMotion::MatchPair => match_pair(offset, text_buffer) ?,
+        VimMotion::InnerWord | VimMotion::AroundWord => return None
+    };
+    Some(target)
+}
+
+fn word_object(offset: usize, around: bool, text_buffer: &TextBuffer) -> Option<(usize, usize)> {
+    let index = chunk_index_at(offset, text_buffer) ?;
+    let chunk = &text_buffer.token_chunks[index];
+    let (start, mut end) = (chunk.offset, chunk.offset + chunk.len);
+    if around {
+        if let Some(next) = text_buffer.token_chunks.get(index + 1) {
+            if next.token_type == TokenType::Whitespace {
+                end = next.offset + next.len;
+            }
+        }
+    }
+    Some((start, end))
+}
+
+// start, end and whether its whole lines
+fn operator_range(offset: usize, op: VimOperator, motion: VimMotion, count: Option<usize>, text_buffer: &TextBuffer) -> Option<(usize, usize, bool)> {
+    let row = text_buffer.offset_to_text_pos(offset).row;
+    match motion {
+        VimMotion::InnerWord | VimMotion::AroundWord => {
+            let (start, end) = word_object(offset, motion == VimMotion::AroundWord, text_buffer) ?;
+            return Some((start, end, false))
+        },
+        VimMotion::Up | VimMotion::Down | VimMotion::FileStart | VimMotion::FileEnd => {
+            let target_row = text_buffer.offset_to_text_pos(motion_target(offset, motion, count, text_buffer) ?).row;
+            let (start, end) = line_range(row.min(target_row), row.max(target_row), text_buffer);
+            return Some((start, end, true))
+        },
+        // cw on a word is ce
+        VimMotion::WordForward if op == VimOperator::Change && !is_blank_at(offset, text_buffer) => {
+            return operator_range(offset, op, VimMotion::WordEnd, count, text_buffer)
+        },
+        VimMotion::WordForward => {
+            // the last word doesnt take the line break with it
+            let mut pos = offset;
+            let n = count.unwrap_or(1);
+            for step in 0..n {
+                let next = word_forward(pos, text_buffer);
+                let pos_row = text_buffer.offset_to_text_pos(pos).row;
+                let line_end = row_range(pos_row, text_buffer).1;
+                pos = if step == n - 1 && text_buffer.offset_to_text_pos(next).row > pos_row && line_end > pos {line_end} else {next};
+            }
+            return if pos > offset {Some((offset, pos, false))} else {None}
+        },
+        _ => ()
+    }
+    let target = motion_target(offset, motion, count, text_buffer) ?;
+    let inclusive = match motion {
+        VimMotion::WordEnd | VimMotion::FindChar {..} | VimMotion::MatchPair => true,
+        _ => false
+    };
+    let (start, end) = if target < offset {(target, offset)} else {(offset, target)};
+    let end = if inclusive {(end + 1).min(text_buffer.lines.char_count())} else {end};
+    if end > start {Some((start, end, false))} else {None}
+}
+
+// :s/pattern/replacement/flags with a regex pattern. \1 and & in the replacement are groups
+// like in vim, g does every match on a line instead of the first and i ignores case
+fn substitute(rows: (usize, usize), spec: &str, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer) -> Result<VimEvent, String> {
+    let sep = spec.chars().next().unwrap();
+    if sep.is_alphanumeric() || sep == '\\' || sep.is_whitespace() {
+        return Err(format!("Not an editor command: s{}", spec))
+    }
+    let mut parts = vec![String::new()];
+    let mut chars = spec[sep.len_utf8()..].chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some(next) if next == sep => parts.last_mut().unwrap().push(next),
+                Some(next) => {
+                    parts.last_mut().unwrap().push(ch);
+                    parts.last_mut().unwrap().push(next);
+                },
+                None => parts.last_mut().unwrap().push(ch)
+            }
+        }
+        else if ch == sep {
+            parts.push(String::new());
+        }
+        else {
+            parts.last_mut().unwrap().push(ch);
+        }
+    }
+    if parts.len() > 3 {
+        return Err("Trailing characters".to_string())
+    }
+    let pattern = &parts[0];
+    if pattern.len() == 0 {
+        return Err("No previous regular expression".to_string())
+    }
+    let flags = parts.get(2).cloned().unwrap_or(String::new());
+    let mut replace = String::new();
+    let mut chars = parts.get(1).cloned().unwrap_or(String::new()).chars().collect::<Vec<char>>().into_iter().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(digit) if digit.is_ascii_digit() => replace.push_str(&format!("${{{}}}", digit)),
+                Some('n') => replace.push('\n'),
+                Some('t') => replace.push('\t'),
+                Some(other) => replace.push(other),
+                None => replace.push('\\')
+            },
+            '&' => replace.push_str("${0}"),
+            '$' => replace.push_str("$$"),
+            ch => replace.push(ch)
+        }
+    }
+    let search = TextSearch {
+        query: pattern.clone(),
+        case_sensitive: !flags.contains('i'),
+        whole_word: false,
+        is_regex: true
+    };
+    let start = row_range(rows.0, text_buffer).0;
+    let end = row_range(rows.1, text_buffer).1;
+    let mut last_row = None;
+    let mut matches = Vec::new();
+    for m in search.find_in_text_buffer(text_buffer, &replace) ? {
+        if m.start < start || m.start > end {
+            continue
+        }
+        let row = text_buffer.offset_to_text_pos(m.start).row;
+        if !flags.contains('g') && last_row == Some(row) {
+            continue
+        }
+        last_row = Some(row);
+        matches.push(m);
+    }
+    if matches.len() == 0 {
+        return Err(format!("Pattern not found: {}", pattern))
+    }
+    let row = text_buffer.offset_to_text_pos(matches[matches.len() - 1].start).row;
+    cursors.replace_matches(&matches, text_buffer);
+    set_heads(cursors, vec![first_non_blank(row, text_buffer)], text_buffer);
+    Ok(VimEvent::CursorsChanged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rusteditor::*;
+    
+    fn session(text: &str) -> (Vim, TextCursorSet, TextBuffer) {
+        let mut text_buffer = TextBuffer::from_test_str(text);
+        let mut vim = Vim::new();
+        vim.set_enabled(true);
+        (vim, TextCursorSet::new(), text_buffer)
+    }
+    
+    // escape is \x1b, anything vim doesnt take gets typed like the editor would
+    fn keys(vim: &mut Vim, cursors: &mut TextCursorSet, text_buffer: &mut TextBuffer, keys: &str) -> VimEvent {
+        let mut last = VimEvent::None;
+        for ch in keys.chars() {
+            let key = match ch {
+                '\x1b' => VimKey::Escape,
+                '\n' => VimKey::Return,
+                ch => VimKey::Char(ch)
+            };
+            match vim.handle_key(key, cursors, text_buffer, &RustLanguage) {
+                VimEvent::None => cursors.replace_text(&ch.to_string(), text_buffer),
+                event => last = event
+            }
+        }
+        last
+    }
+    
+    fn head(cursors: &TextCursorSet) -> usize {
+        cursors.set[cursors.last_cursor].head
+    }
+    
+    #[test]
+    fn motions_follow_tokens_and_pairs() {
+        let (mut vim, mut cursors, mut text_buffer) = session("fn main() {\n    let abc = foo(1, 2);\n}\n");
+        let mut at = | vim: &mut Vim, cursors: &mut TextCursorSet, motion: &str | {
+            keys(vim, cursors, &mut text_buffer, motion);
+            head(cursors)
+        };
+        assert_eq!(at(&mut vim, &mut cursors, "w"), 3);
+        assert_eq!(at(&mut vim, &mut cursors, "w"), 7);
+        assert_eq!(at(&mut vim, &mut cursors, "e"), 8);
+        assert_eq!(at(&mut vim, &mut cursors, "$"), 10);
+        assert_eq!(at(&mut vim, &mut cursors, "%"), 37);
+        assert_eq!(at(&mut vim, &mut cursors, "%"), 10);
+        assert_eq!(at(&mut vim, &mut cursors, "j"), 22);
+        assert_eq!(at(&mut vim, &mut cursors, "0"), 12);
+        assert_eq!(at(&mut vim, &mut cursors, "^"), 16);
+        assert_eq!(at(&mut vim, &mut cursors, "f("), 29);
+        assert_eq!(at(&mut vim, &mut cursors, "t)"), 33);
+        assert_eq!(at(&mut vim, &mut cursors, "F="), 24);
+        assert_eq!(at(&mut vim, &mut cursors, "b"), 20);
+        // over the line break onto the bracket
+        assert_eq!(at(&mut vim, &mut cursors, "2b"), 10);
+        assert_eq!(at(&mut vim, &mut cursors, "gg"), 0);
+        assert_eq!(at(&mut vim, &mut cursors, "G"), 39);
+        assert_eq!(at(&mut vim, &mut cursors, "2G"), 16);
+        // a motion that goes nowhere leaves the cursor be
+        assert_eq!(at(&mut vim, &mut cursors, "fz"), 16);
+    }
+    
+    #[test]
+    fn operators_counts_registers_and_repeat() {
+        let (mut vim, mut cursors, mut text_buffer) = session("one two three\nfour five\nsix\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "dw");
+        assert_eq!(text_buffer.get_as_string(), "two three\nfour five\nsix\n");
+        assert_eq!(vim.registers[&'"'].text, "one ");
+        // the last word stops at the end of the line
+        keys(&mut vim, &mut cursors, &mut text_buffer, "d2w");
+        assert_eq!(text_buffer.get_as_string(), "\nfour five\nsix\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "uu");
+        assert_eq!(text_buffer.get_as_string(), "one two three\nfour five\nsix\n");
+        
+        keys(&mut vim, &mut cursors, &mut text_buffer, "gg\"add");
+        assert_eq!(text_buffer.get_as_string(), "four five\nsix\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "\"ap");
+        assert_eq!(text_buffer.get_as_string(), "four five\none two three\nsix\n");
+        assert_eq!(head(&cursors), 10);
+        keys(&mut vim, &mut cursors, &mut text_buffer, "yyGP");
+        assert_eq!(text_buffer.get_as_string(), "four five\none two three\nsix\none two three\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "gg2dd");
+        assert_eq!(text_buffer.get_as_string(), "six\none two three\n");
+        
+        keys(&mut vim, &mut cursors, &mut text_buffer, "jcwONE\x1b");
+        assert_eq!(text_buffer.get_as_string(), "six\nONE two three\n");
+        assert_eq!(vim.mode, VimMode::Normal);
+        keys(&mut vim, &mut cursors, &mut text_buffer, "w.");
+        assert_eq!(text_buffer.get_as_string(), "six\nONE ONE three\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "0x2.");
+        assert_eq!(text_buffer.get_as_string(), "six\n ONE three\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "AX\x1bkoY\x1b");
+        assert_eq!(text_buffer.get_as_string(), "six\nY\n ONE threeX\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "jci\x1b");
+        assert_eq!(vim.mode, VimMode::Normal);
+        keys(&mut vim, &mut cursors, &mut text_buffer, "wdiw");
+        assert_eq!(text_buffer.get_as_string(), "six\nY\n  threeX\n");
+    }
+    
+    #[test]
+    fn linewise_paste_after_the_last_line() {
+        let (mut vim, mut cursors, mut text_buffer) = session("one\ntwo");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "yyGp");
+        assert_eq!(text_buffer.get_as_string(), "one\ntwo\none");
+        // a register without its newline, like one set from outside
+        vim.registers.insert('a', VimRegister {text: "three".to_string(), linewise: true});
+        keys(&mut vim, &mut cursors, &mut text_buffer, "G\"ap");
+        assert_eq!(text_buffer.get_as_string(), "one\ntwo\none\nthree");
+        vim.registers.insert('b', VimRegister {text: String::new(), linewise: true});
+        keys(&mut vim, &mut cursors, &mut text_buffer, "G\"bp");
+        assert_eq!(text_buffer.get_as_string(), "one\ntwo\none\nthree\n");
+    }
+    
+    #[test]
+    fn visual_mode_and_command_line() {
+        let (mut vim, mut cursors, mut text_buffer) = session("let a = 1;\nlet b = 2;\nlet c = 3;\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "vey");
+        assert_eq!(vim.registers[&'0'].text, "let");
+        assert_eq!(vim.mode, VimMode::Normal);
+        keys(&mut vim, &mut cursors, &mut text_buffer, "v$");
+        assert_eq!(cursors.set[0].order(), (0, 10));
+        keys(&mut vim, &mut cursors, &mut text_buffer, "\x1bVjd");
+        assert_eq!(text_buffer.get_as_string(), "let c = 3;\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "u");
+        
+        keys(&mut vim, &mut cursors, &mut text_buffer, ":s/let/var/\n");
+        assert_eq!(text_buffer.get_as_string(), "var a = 1;\nlet b = 2;\nlet c = 3;\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, ":2,3s/(\\w) = (\\d)/\\2 = \\1/\n");
+        assert_eq!(text_buffer.get_as_string(), "var a = 1;\nlet 2 = b;\nlet 3 = c;\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, "ggVj:s/ /_/g\n");
+        assert_eq!(text_buffer.get_as_string(), "var_a_=_1;\nlet_2_=_b;\nlet 3 = c;\n");
+        keys(&mut vim, &mut cursors, &mut text_buffer, ":%s/;/&&/\n");
+        assert_eq!(text_buffer.get_as_string(), "var_a_=_1;;\nlet_2_=_b;;\nlet 3 = c;;\n");
+        
+        assert_eq!(keys(&mut vim, &mut cursors, &mut text_buffer, ":w\n"), VimEvent::Save);
+        keys(&mut vim, &mut cursors, &mut text_buffer, ":s/nope/x/\n");
+        assert_eq!(vim.status(), Some("Pattern not found: nope".to_string()));
+        keys(&mut vim, &mut cursors, &mut text_buffer, ":wat\n");
+        assert_eq!(vim.status(), Some("Not an editor command: wat".to_string()));
+        keys(&mut vim, &mut cursors, &mut text_buffer, "3");
+        assert_eq!(vim.status(), Some("3".to_string()));
+    }
+}
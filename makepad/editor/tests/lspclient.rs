@@ -0,0 +1,104 @@
+use editor::*;
+use render::*;
+use std::time::Duration;
+
+const SCRIPT: &str = r#"
+textDocument/didOpen -> textDocument/publishDiagnostics {"uri": "$uri", "diagnostics": [{"range": {"start": {"line": 1, "character": 18}, "end": {"line": 1, "character": 23}}, "severity": 1, "message": "cannot find function `hello`"}]}
+textDocument/hover => {"contents": {"kind": "markdown", "value": "fn main()"}}
+textDocument/definition => [{"uri": "$uri", "range": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 7}}}]
+textDocument/completion => {"isIncomplete": false, "items": [{"label": "println!", "insertText": "println!($0)", "insertTextFormat": 2}, {"label": "print", "detail": "fn print()"}]}
+textDocument/rename => {"changes": {"$uri": [{"range": {"start": {"line": 1, "character": 18}, "end": {"line": 1, "character": 23}}, "newText": "greet"}, {"range": {"start": {"line": 2, "character": 4}, "end": {"line": 2, "character": 9}}, "newText": "greet"}]}}
+"#;
+
+fn wait_for(client: &mut LspClient, pred: impl Fn(&LspEvent) -> bool) -> LspEvent {
+    for _ in 0..100 {
+        for event in client.poll_timeout(Duration::from_millis(100)) {
+            if pred(&event) {
+                return event
+            }
+        }
+    }
+    panic!("the fake server never answered")
+}
+
+#[test]
+fn talks_to_a_scripted_server() {
+    let root = std::env::temp_dir().join(format!("makepad_lspclient_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let script_path = root.join("script.txt");
+    std::fs::write(&script_path, SCRIPT).unwrap();
+    
+    let mut text_buffer = TextBuffer::default();
+    text_buffer.load_bytes("fn main() {\n    let s = \"\u{1f600}\"; hello();\n    hello();\n}\n".as_bytes());
+    
+    let mut client = LspClient::start(
+        env!("CARGO_BIN_EXE_fake_lsp_server"),
+        &[script_path.to_str().unwrap()],
+        root.to_str().unwrap(),
+        "rust",
+        Signal::empty(),
+        0
+    ).unwrap();
+    let path = "src/main.rs";
+    
+    // diagnostics come back in utf16 columns, the emoji before hello counts twice
+    client.sync_document(path, &text_buffer);
+    match wait_for(&mut client, | e | if let LspEvent::Diagnostics {..} = e {true} else {false}) {
+        LspEvent::Diagnostics {path: diag_path, diagnostics} => {
+            assert_eq!(diag_path, path);
+            LspClient::set_diagnostics(&mut text_buffer, &diagnostics);
+        },
+        _ => unreachable!()
+    }
+    let messages = &text_buffer.messages;
+    assert_eq!(messages.mutation_id, text_buffer.mutation_id);
+    assert_eq!(messages.bodies[0].level, TextBufferMessageLevel::Error);
+    let (start, end) = messages.cursors[0].order();
+    let mut flagged = String::new();
+    text_buffer.get_range_as_string(start, end - start, &mut flagged);
+    assert_eq!(flagged, "hello");
+    
+    client.query(path, &text_buffer, LspQuery::Hover {offset: 4});
+    assert_eq!(
+        wait_for(&mut client, | e | if let LspEvent::Hover {..} = e {true} else {false}),
+        LspEvent::Hover {path: path.to_string(), offset: 4, text: "fn main()".to_string()}
+    );
+    
+    client.query(path, &text_buffer, LspQuery::Definition {offset: 30});
+    match wait_for(&mut client, | e | if let LspEvent::Definition {..} = e {true} else {false}) {
+        LspEvent::Definition {path: Some(def_path), range} => {
+            assert_eq!(def_path, path);
+            assert_eq!(lsp_offset(&text_buffer, range.start), 3);
+        },
+        event => panic!("unexpected {:?}", event)
+    }
+    
+    client.query(path, &text_buffer, LspQuery::Completion {offset: 12});
+    match wait_for(&mut client, | e | if let LspEvent::Completion {..} = e {true} else {false}) {
+        LspEvent::Completion {items, ..} => {
+            assert_eq!(items.len(), 2);
+            assert!(items[0].is_snippet);
+            assert_eq!(items[0].insert_text, "println!($0)");
+            assert_eq!((items[1].insert_text.as_str(), items[1].detail.as_str()), ("print", "fn print()"));
+        },
+        _ => unreachable!()
+    }
+    
+    // a rename is one undo step over all its edits
+    client.query(path, &text_buffer, LspQuery::Rename {offset: 30, new_name: "greet".to_string()});
+    match wait_for(&mut client, | e | if let LspEvent::Rename {..} = e {true} else {false}) {
+        LspEvent::Rename {edits} => {
+            assert_eq!(edits.len(), 1);
+            assert_eq!(edits[0].0, path);
+            LspClient::apply_edits(&mut text_buffer, &edits[0].1);
+        },
+        _ => unreachable!()
+    }
+    assert_eq!(text_buffer.get_as_lf_string(), "fn main() {\n    let s = \"\u{1f600}\"; greet();\n    greet();\n}\n");
+    text_buffer.undo(true, &mut TextCursorSet::new());
+    assert_eq!(text_buffer.get_as_lf_string(), "fn main() {\n    let s = \"\u{1f600}\"; hello();\n    hello();\n}\n");
+    
+    client.shutdown();
+    assert_eq!(wait_for(&mut client, | e | *e == LspEvent::Exited), LspEvent::Exited);
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -0,0 +1,588 @@
+use widget::*;
+use std::path::{Path, PathBuf};
+
+// What the file tree can do to the disk, on paths the way TextBuffers has them. Deleting moves
+// things to the trash, that way the last operation can always be undone. Windows has no trash
+// folder to move things into, there it is one of makepad's own and the menu says so.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileTreeAction {
+    NewFile {folder: String},
+    NewFolder {folder: String},
+    Rename {path: String},
+    Duplicate {path: String},
+    Delete {path: String},
+    Undo
+}
+
+#[derive(Clone, Debug)]
+pub enum FileOp {
+    // something we made, undo trashes it
+    Create {path: String},
+    Rename {from: String, to: String},
+    Trash {path: String, trash_path: PathBuf}
+}
+
+impl FileOp {
+    // the folders whose listing changed
+    pub fn folders(&self) -> Vec<String> {
+        match self {
+            FileOp::Create {path} | FileOp::Trash {path, ..} => vec![parent_path(path)],
+            FileOp::Rename {from, to} => vec![parent_path(from), parent_path(to)]
+        }
+    }
+}
+
+// what an operation did, when a step failed the ones before it stay done and error says why
+#[derive(Debug, Default)]
+pub struct FileOpsDone {
+    pub ops: Vec<FileOp>,
+    pub error: Option<String>
+}
+
+impl FileOpsDone {
+    pub fn failed(error: String) -> FileOpsDone {
+        FileOpsDone {ops: Vec::new(), error: Some(error)}
+    }
+}
+
+#[derive(Default)]
+pub struct FileOps {
+    pub root_path: String,
+    // the other workspace roots, they only show up in the tree and dont get moved around
+    pub fixed_paths: Vec<String>,
+    // what the last operation did, with its name for the menu
+    pub last: Option<(String, Vec<FileOp>)>,
+    // None is the trash of the user, the tests have one of their own
+    pub trash_dir: Option<PathBuf>
+}
+
+impl FileOps {
+    fn disk_path(&self, path: &str) -> PathBuf {
+        PathBuf::from(format!("{}{}", self.root_path, path))
+    }
+    
+    fn done(&mut self, label: &str, ops: Vec<FileOp>, error: Option<String>) -> FileOpsDone {
+        if ops.len() > 0 {
+            self.last = Some((label.to_string(), ops.clone()));
+        }
+        FileOpsDone {ops: ops, error: error}
+    }
+    
+    fn move_to_trash(&self, path: &str) -> Result<PathBuf, String> {
+        move_to_trash(&self.disk_path(path), self.trash_dir.as_ref().map( | dir | dir.as_path()))
+    }
+    
+    fn check_not_fixed(&self, path: &str) -> Result<(), String> {
+        if path.len() == 0 || self.fixed_paths.iter().any( | fixed | fixed == path) {
+            return Err(format!("{} is a workspace root", if path.len() == 0 {"/"} else {path}))
+        }
+        Ok(())
+    }
+    
+    pub fn undo_label(&self) -> Option<String> {
+        self.last.as_ref().map( | (label, _) | format!("Undo {}", label))
+    }
+    
+    // an empty file or folder named untitled in folder
+    pub fn create(&mut self, folder: &str, is_folder: bool) -> FileOpsDone {
+        let dir = self.disk_path(folder);
+        let name = unique_name(&dir, if is_folder {"untitled folder"} else {"untitled"}, "");
+        let path = join_path(folder, &name);
+        let result = if is_folder {
+            std::fs::create_dir(dir.join(&name))
+        }
+        else {
+            std::fs::OpenOptions::new().write(true).create_new(true).open(dir.join(&name)).map( | _ | ())
+        };
+        match result {
+            Ok(()) => self.done(if is_folder {"New Folder"} else {"New File"}, vec![FileOp::Create {path: path}], None),
+            Err(err) => FileOpsDone::failed(format!("Cannot create {}: {}", path, err))
+        }
+    }
+    
+    pub fn rename(&mut self, path: &str, new_name: &str) -> FileOpsDone {
+        if new_name == "." || new_name == ".." || new_name.contains('/') || new_name.contains('\\') {
+            return FileOpsDone::failed(format!("{} is not a valid name", new_name))
+        }
+        let to = join_path(&parent_path(path), new_name);
+        match self.check_not_fixed(path).and_then( | _ | self.move_path(path, &to)) {
+            Ok(op) => self.done("Rename", vec![op], None),
+            Err(err) => FileOpsDone::failed(err)
+        }
+    }
+    
+    // into folder, things already there stay where they are. When one fails the ones before
+    // it stay moved, so they can be undone
+    pub fn move_paths(&mut self, paths: &[String], folder: &str) -> FileOpsDone {
+        let mut ops = Vec::new();
+        let mut error = None;
+        for path in paths {
+            if parent_path(path) == folder {
+                continue;
+            }
+            let name = path.rsplit('/').next().unwrap_or(path);
+            let result = self.check_not_fixed(path).and_then( | _ | {
+                if folder == path || folder.starts_with(&format!("{}/", path)) {
+                    return Err(format!("Cannot move {} into itself", path))
+                }
+                self.move_path(path, &join_path(folder, name))
+            });
+            match result {
+                Ok(op) => ops.push(op),
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            }
+        }
+        self.done("Move", ops, error)
+    }
+    
+    fn move_path(&self, from: &str, to: &str) -> Result<FileOp, String> {
+        let to_disk = self.disk_path(to);
+        if to_disk.exists() {
+            return Err(format!("Cannot move {} to {}, it already exists", from, to))
+        }
+        std::fs::rename(self.disk_path(from), &to_disk).map_err( | err | format!("Cannot move {} to {}: {}", from, to, err)) ?;
+        Ok(FileOp::Rename {from: from.to_string(), to: to.to_string()})
+    }
+    
+    // a copy next to it, named like "main copy.rs"
+    pub fn duplicate(&mut self, path: &str) -> FileOpsDone {
+        let from = self.disk_path(path);
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let (stem, ext) = split_extension(name, from.is_dir());
+        let folder = parent_path(path);
+        let copy_name = unique_name(&self.disk_path(&folder), &format!("{} copy", stem), ext);
+        let copy_path = join_path(&folder, &copy_name);
+        match copy_recursive(&from, &self.disk_path(&copy_path)) {
+            Ok(()) => self.done("Duplicate", vec![FileOp::Create {path: copy_path}], None),
+            Err(err) => FileOpsDone::failed(format!("Cannot duplicate {}: {}", path, err))
+        }
+    }
+    
+    // like move_paths what got trashed before a failure stays there
+    pub fn delete(&mut self, paths: &[String]) -> FileOpsDone {
+        let mut ops = Vec::new();
+        let mut error = None;
+        for path in paths {
+            match self.check_not_fixed(path).and_then( | _ | self.move_to_trash(path)) {
+                Ok(trash_path) => ops.push(FileOp::Trash {path: path.clone(), trash_path: trash_path}),
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            }
+        }
+        self.done("Delete", ops, error)
+    }
+    
+    // puts back what the last operation did, returns what that took. What couldnt be put back
+    // is lost for undo, the rest is undone
+    pub fn undo(&mut self) -> FileOpsDone {
+        let ops = match self.last.take() {
+            Some((_, ops)) => ops,
+            None => return FileOpsDone::failed("Nothing to undo".to_string())
+        };
+        let mut undone = Vec::new();
+        let mut error = None;
+        for op in ops.into_iter().rev() {
+            let result = match op {
+                FileOp::Create {path} => self.move_to_trash(&path).map( | trash_path | {
+                    FileOp::Trash {path: path, trash_path: trash_path}
+                }),
+                FileOp::Rename {from, to} => self.move_path(&to, &from),
+                FileOp::Trash {path, trash_path} => restore_from_trash(&trash_path, &self.disk_path(&path)).map( | _ | {
+                    FileOp::Create {path: path}
+                })
+            };
+            match result {
+                Ok(op) => undone.push(op),
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            }
+        }
+        FileOpsDone {ops: undone, error: error}
+    }
+}
+
+const DELETE_LABEL: &str = if cfg!(target_os = "windows") {"Delete (to makepad trash, not the Recycle Bin)"} else {"Move to Trash"};
+
+// what the right click menu of the file tree offers for path
+pub fn file_tree_menu(path: &str, is_folder: bool, undo_label: Option<String>) -> Vec<ContextMenuItem<FileTreeAction>> {
+    let folder = if is_folder {path.to_string()} else {parent_path(path)};
+    let item = | label: &str, action: FileTreeAction | ContextMenuItem {label: label.to_string(), item: action};
+    let mut items = vec![
+        item("New File", FileTreeAction::NewFile {folder: folder.clone()}),
+        item("New Folder", FileTreeAction::NewFolder {folder: folder})
+    ];
+    if path.len() > 0 {
+        items.push(item("Rename", FileTreeAction::Rename {path: path.to_string()}));
+        items.push(item("Duplicate", FileTreeAction::Duplicate {path: path.to_string()}));
+        items.push(item(DELETE_LABEL, FileTreeAction::Delete {path: path.to_string()}));
+    }
+    if let Some(label) = undo_label {
+        items.push(item(&label, FileTreeAction::Undo));
+    }
+    items
+}
+
+pub fn parent_path(path: &str) -> String {
+    path.rsplitn(2, '/').nth(1).unwrap_or("").to_string()
+}
+
+pub fn join_path(folder: &str, name: &str) -> String {
+    if folder.len() == 0 {name.to_string()} else {format!("{}/{}", folder, name)}
+}
+
+// "main" and ".rs", folders and dotfiles dont have an extension
+fn split_extension(name: &str, is_dir: bool) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(pos) if pos > 0 && !is_dir => (&name[..pos], &name[pos..]),
+        _ => (name, "")
+    }
+}
+
+// stem + ext, or with a number after the stem when that is taken
+fn unique_name(dir: &Path, stem: &str, ext: &str) -> String {
+    let mut name = format!("{}{}", stem, ext);
+    let mut counter = 2;
+    while dir.join(&name).symlink_metadata().is_ok() {
+        name = format!("{} {}{}", stem, counter, ext);
+        counter += 1;
+    }
+    name
+}
+
+// symlinks get copied as links to the same place, following one to a folder above would never end
+fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    let file_type = from.symlink_metadata() ?.file_type();
+    if file_type.is_symlink() {
+        copy_symlink(from, to)
+    }
+    else if file_type.is_dir() {
+        std::fs::create_dir(to) ?;
+        for entry in std::fs::read_dir(from) ? {
+            let entry = entry ?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name())) ?;
+        }
+        Ok(())
+    }
+    else {
+        std::fs::copy(from, to).map( | _ | ())
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from) ?, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(from) ?;
+    if std::fs::metadata(from).map_or(false, | meta | meta.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, to)
+    }
+    else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+// the freedesktop trash on linux and ~/.Trash on macos. Windows has no folder we can just move
+// things into, there it is .makepad/trash in the home directory
+fn home_trash_dir() -> Option<PathBuf> {
+    let home = home_dir() ?;
+    if cfg!(target_os = "macos") {
+        Some(home.join(".Trash"))
+    }
+    else if cfg!(target_os = "windows") {
+        Some(home.join(".makepad").join("trash"))
+    }
+    else {
+        let data_home = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).unwrap_or(home.join(".local").join("share"));
+        Some(data_home.join("Trash").join("files"))
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").or_else( || std::env::var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+// the home trash cant take things from another disk, those go to the trash at the top of their
+// own disk. That is .Trash-uid on linux and .Trashes/uid on macos, only we may look inside
+#[cfg(unix)]
+fn volume_trash_dir(path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::{MetadataExt, DirBuilderExt};
+    let mut top = std::fs::canonicalize(path.parent() ?).ok() ?;
+    let dev = top.metadata().ok() ?.dev();
+    while let Some(up) = top.parent().filter( | up | up.metadata().map_or(false, | meta | meta.dev() == dev)) {
+        top = up.to_path_buf();
+    }
+    // whoever owns home is us, without going to libc for it
+    let uid = home_dir() ?.metadata().ok() ?.uid();
+    let (trash_dir, files_dir) = if cfg!(target_os = "macos") {
+        let trash_dir = top.join(".Trashes").join(uid.to_string());
+        (trash_dir.clone(), trash_dir)
+    }
+    else {
+        let trash_dir = top.join(format!(".Trash-{}", uid));
+        (trash_dir.clone(), trash_dir.join("files"))
+    };
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(&trash_dir).ok() ?;
+    Some(files_dir)
+}
+
+#[cfg(not(unix))]
+fn volume_trash_dir(path: &Path) -> Option<PathBuf> {
+    let dir = std::fs::canonicalize(path.parent() ?).ok() ?;
+    Some(dir.ancestors().last() ?.join(".makepad").join("trash"))
+}
+
+fn is_cross_device(err: &std::io::Error) -> bool {
+    // EXDEV, and ERROR_NOT_SAME_DEVICE on windows
+    err.raw_os_error() == Some(if cfg!(target_os = "windows") {17} else {18})
+}
+
+fn trash_info_path(trash_path: &Path) -> Option<PathBuf> {
+    if cfg!(target_os = "linux") {
+        let name = trash_path.file_name() ?.to_string_lossy().to_string();
+        Some(trash_path.parent() ?.parent() ?.join("info").join(format!("{}.trashinfo", name)))
+    }
+    else {
+        None
+    }
+}
+
+// into trash_dir when there is one, otherwise the trash of the user
+pub fn move_to_trash(path: &Path, trash_dir: Option<&Path>) -> Result<PathBuf, String> {
+    let fail = | err: std::io::Error | format!("Cannot move {} to the trash: {}", path.display(), err);
+    let files_dir = match trash_dir {
+        Some(trash_dir) => trash_dir.to_path_buf(),
+        None => home_trash_dir().ok_or("Cannot find the trash".to_string()) ?
+    };
+    match trash_into(path, &files_dir) {
+        Err(err) if trash_dir.is_none() && is_cross_device(&err) => {
+            let files_dir = volume_trash_dir(path).ok_or_else( || fail(err)) ?;
+            trash_into(path, &files_dir).map_err(fail)
+        },
+        result => result.map_err(fail)
+    }
+}
+
+fn trash_into(path: &Path, files_dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(files_dir) ?;
+    let name = path.file_name().map( | name | name.to_string_lossy().to_string()).unwrap_or_default();
+    let (stem, ext) = split_extension(&name, path.is_dir());
+    let trash_path = files_dir.join(unique_name(files_dir, stem, ext));
+    std::fs::rename(path, &trash_path) ?;
+    if let Some(info_path) = trash_info_path(&trash_path) {
+        let abs_path = std::fs::canonicalize(path.parent().unwrap_or(Path::new("."))).map( | dir | dir.join(&name)).unwrap_or(path.to_path_buf());
+        let info = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", abs_path.display(), trash_date());
+        let _ = std::fs::create_dir_all(info_path.parent().unwrap());
+        let _ = std::fs::write(info_path, info);
+    }
+    Ok(trash_path)
+}
+
+fn restore_from_trash(trash_path: &Path, path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return Err(format!("Cannot restore {}, it exists again", path.display()))
+    }
+    std::fs::rename(trash_path, path).map_err( | err | format!("Cannot restore {}: {}", path.display(), err)) ?;
+    if let Some(info_path) = trash_info_path(trash_path) {
+        let _ = std::fs::remove_file(info_path);
+    }
+    Ok(())
+}
+
+// YYYY-MM-DDThh:mm:ss in UTC, the trash spec wants local time but UTC is close enough to sort by
+fn trash_date() -> String {
+    trash_date_at(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map( | d | d.as_secs()).unwrap_or(0))
+}
+
+fn trash_date_at(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+    // days to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // a root to work in with a trash next to it, emptied from an earlier run
+    fn file_ops_in(name: &str) -> (FileOps, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("makepad_fileops_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("root")).unwrap();
+        let file_ops = FileOps {
+            root_path: format!("{}/", dir.join("root").display()),
+            trash_dir: Some(dir.join("trash").join("files")),
+            ..Default::default()
+        };
+        (file_ops, dir.join("root"))
+    }
+    
+    fn write(root: &Path, path: &str, text: &str) {
+        std::fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+        std::fs::write(root.join(path), text).unwrap();
+    }
+    
+    fn paths(done: &FileOpsDone) -> Vec<String> {
+        done.ops.iter().map( | op | match op {
+            FileOp::Create {path} | FileOp::Trash {path, ..} => path.clone(),
+            FileOp::Rename {from, to} => format!("{}>{}", from, to)
+        }).collect()
+    }
+    
+    #[test]
+    fn splits_names_and_finds_free_ones() {
+        assert_eq!(split_extension("main.rs", false), ("main", ".rs"));
+        assert_eq!(split_extension("a.tar.gz", false), ("a.tar", ".gz"));
+        assert_eq!(split_extension(".gitignore", false), (".gitignore", ""));
+        assert_eq!(split_extension("src.old", true), ("src.old", ""));
+        let (_, root) = file_ops_in("unique");
+        assert_eq!(unique_name(&root, "main copy", ".rs"), "main copy.rs");
+        write(&root, "main copy.rs", "");
+        write(&root, "main copy 2.rs", "");
+        assert_eq!(unique_name(&root, "main copy", ".rs"), "main copy 3.rs");
+    }
+    
+    #[test]
+    fn dates_the_trash_in_utc() {
+        assert_eq!(trash_date_at(0), "1970-01-01T00:00:00");
+        assert_eq!(trash_date_at(951782400), "2000-02-29T00:00:00");
+        assert_eq!(trash_date_at(1700000000), "2023-11-14T22:13:20");
+        assert_eq!(trash_date_at(4102444799), "2099-12-31T23:59:59");
+    }
+    
+    #[test]
+    fn creates_and_undoes_that() {
+        let (mut file_ops, root) = file_ops_in("create");
+        assert_eq!(paths(&file_ops.create("", false)), vec!["untitled"]);
+        assert_eq!(paths(&file_ops.create("", false)), vec!["untitled 2"]);
+        assert_eq!(paths(&file_ops.create("", true)), vec!["untitled folder"]);
+        assert_eq!(paths(&file_ops.create("untitled folder", false)), vec!["untitled folder/untitled"]);
+        assert!(root.join("untitled folder/untitled").is_file());
+        assert_eq!(file_ops.undo_label(), Some("Undo New File".to_string()));
+        let undone = file_ops.undo();
+        assert!(undone.error.is_none());
+        assert_eq!(paths(&undone), vec!["untitled folder/untitled"]);
+        assert!(!root.join("untitled folder/untitled").exists());
+        assert!(file_ops.create("missing", false).error.is_some());
+    }
+    
+    #[test]
+    fn renames_and_undoes_that() {
+        let (mut file_ops, root) = file_ops_in("rename");
+        write(&root, "src/a.rs", "a");
+        write(&root, "src/b.rs", "b");
+        assert_eq!(paths(&file_ops.rename("src/a.rs", "c.rs")), vec!["src/a.rs>src/c.rs"]);
+        assert_eq!(std::fs::read_to_string(root.join("src/c.rs")).unwrap(), "a");
+        // the name is all there is to change, and what is there stays
+        assert!(file_ops.rename("src/c.rs", "../c.rs").error.is_some());
+        assert!(file_ops.rename("src/c.rs", "..").error.is_some());
+        assert!(file_ops.rename("src/c.rs", "b.rs").error.is_some());
+        assert_eq!(std::fs::read_to_string(root.join("src/b.rs")).unwrap(), "b");
+        assert_eq!(paths(&file_ops.undo()), vec!["src/c.rs>src/a.rs"]);
+        assert!(root.join("src/a.rs").is_file());
+        assert!(file_ops.undo().error.is_some());
+    }
+    
+    #[test]
+    fn leaves_the_workspace_roots_alone() {
+        let (mut file_ops, root) = file_ops_in("fixed");
+        file_ops.fixed_paths = vec!["other".to_string()];
+        write(&root, "other/a.rs", "");
+        assert!(file_ops.rename("other", "x").error.is_some());
+        assert!(file_ops.delete(&["".to_string()]).error.is_some());
+        assert!(root.join("other/a.rs").is_file());
+    }
+    
+    #[test]
+    fn moves_until_something_goes_into_itself() {
+        let (mut file_ops, root) = file_ops_in("move");
+        write(&root, "a.rs", "");
+        write(&root, "src/b.rs", "");
+        write(&root, "src/sub/c.rs", "");
+        // one already in the folder gets skipped, the folder into its own child stops it
+        let paths_to_move = vec!["src/sub/c.rs".to_string(), "a.rs".to_string(), "src".to_string(), "src/b.rs".to_string()];
+        let done = file_ops.move_paths(&paths_to_move, "src/sub");
+        assert_eq!(paths(&done), vec!["a.rs>src/sub/a.rs"]);
+        assert_eq!(done.error, Some("Cannot move src into itself".to_string()));
+        assert!(root.join("src/sub/a.rs").is_file());
+        assert!(root.join("src/b.rs").is_file());
+        assert_eq!(paths(&file_ops.undo()), vec!["src/sub/a.rs>a.rs"]);
+        assert!(root.join("a.rs").is_file());
+        let done = file_ops.move_paths(&["src/sub".to_string()], "");
+        assert_eq!(paths(&done), vec!["src/sub>sub"]);
+        assert!(root.join("sub/c.rs").is_file());
+    }
+    
+    #[test]
+    fn duplicates_next_to_the_original() {
+        let (mut file_ops, root) = file_ops_in("duplicate");
+        write(&root, "src/main.rs", "fn main() {}");
+        write(&root, "src/.gitignore", "");
+        assert_eq!(paths(&file_ops.duplicate("src/main.rs")), vec!["src/main copy.rs"]);
+        assert_eq!(paths(&file_ops.duplicate("src/main.rs")), vec!["src/main copy 2.rs"]);
+        assert_eq!(paths(&file_ops.duplicate("src/.gitignore")), vec!["src/.gitignore copy"]);
+        assert_eq!(paths(&file_ops.duplicate("src")), vec!["src copy"]);
+        assert_eq!(std::fs::read_to_string(root.join("src copy/main copy.rs")).unwrap(), "fn main() {}");
+        assert_eq!(paths(&file_ops.undo()), vec!["src copy"]);
+        assert!(!root.join("src copy").exists());
+        assert!(root.join("src/main.rs").is_file());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn duplicates_symlinks_as_links() {
+        let (mut file_ops, root) = file_ops_in("duplicate_link");
+        write(&root, "src/main.rs", "fn main() {}");
+        std::os::unix::fs::symlink("..", root.join("src/up")).unwrap();
+        std::os::unix::fs::symlink("main.rs", root.join("src/lib.rs")).unwrap();
+        assert_eq!(paths(&file_ops.duplicate("src")), vec!["src copy"]);
+        assert_eq!(std::fs::read_link(root.join("src copy/up")).unwrap(), PathBuf::from(".."));
+        assert_eq!(std::fs::read_link(root.join("src copy/lib.rs")).unwrap(), PathBuf::from("main.rs"));
+        assert_eq!(std::fs::read_to_string(root.join("src copy/lib.rs")).unwrap(), "fn main() {}");
+    }
+    
+    #[test]
+    fn deletes_to_the_trash_and_back() {
+        let (mut file_ops, root) = file_ops_in("delete");
+        write(&root, "a.rs", "a");
+        write(&root, "src/a.rs", "src a");
+        let done = file_ops.delete(&["a.rs".to_string(), "src".to_string(), "gone.rs".to_string()]);
+        assert_eq!(paths(&done), vec!["a.rs", "src"]);
+        assert!(done.error.is_some());
+        assert!(!root.join("a.rs").exists() && !root.join("src").exists());
+        let trash_dir = file_ops.trash_dir.clone().unwrap();
+        assert!(trash_dir.join("a.rs").is_file() && trash_dir.join("src/a.rs").is_file());
+        // undo goes backwards, the folder comes back first
+        let undone = file_ops.undo();
+        assert_eq!(paths(&undone), vec!["src", "a.rs"]);
+        assert!(matches!(undone.ops[0], FileOp::Create {..}));
+        assert_eq!(std::fs::read_to_string(root.join("a.rs")).unwrap(), "a");
+        assert_eq!(std::fs::read_to_string(root.join("src/a.rs")).unwrap(), "src a");
+        // what is there again doesnt get overwritten
+        file_ops.delete(&["a.rs".to_string()]);
+        write(&root, "a.rs", "new a");
+        let undone = file_ops.undo();
+        assert!(undone.ops.len() == 0 && undone.error.is_some());
+        assert_eq!(std::fs::read_to_string(root.join("a.rs")).unwrap(), "new a");
+    }
+}
@@ -0,0 +1,202 @@
+use widget::*;
+use git::*;
+use crate::workspace::*;
+use std::path::Path;
+
+// Lists the directories of the workspace for the file tree as folders get opened, instead of
+// the index.json build_index.js makes, which the web build still uses. Tree paths are the
+// TextBuffers paths of the directories, the first root is "" and other roots sit under their
+// root_prefix. What .gitignore or the exclude list matches stays out of the tree, for a root
+// in a git work tree that includes the .gitignore files above the root.
+
+// what build_index.js always left out
+pub const DEFAULT_FILE_TREE_EXCLUDE: &[&str] = &[".git", "target", "bin", "edit_repo"];
+
+// the file system directory a tree path lists, with the root it is in and the path below that root
+pub fn tree_path_to_dir(workspace: &Workspace, tree_path: &str) -> Option<(String, String, String)> {
+    let tree_dir = if tree_path.len() > 0 {format!("{}/", tree_path.trim_end_matches('/'))} else {String::new()};
+    // the longest prefix, a root can sit inside another one
+    let mut best: Option<(usize, String)> = None;
+    for index in 0..workspace.roots.len() {
+        let prefix = workspace.root_prefix(index);
+        if tree_dir.starts_with(&prefix) && best.as_ref().map_or(true, | (_, best) | prefix.len() > best.len()) {
+            best = Some((index, prefix));
+        }
+    }
+    let (index, prefix) = best ?;
+    let root = workspace.roots[index].clone();
+    let rel_dir = tree_dir[prefix.len()..].to_string();
+    Some((format!("{}{}", root, rel_dir), root, rel_dir))
+}
+
+// the git work tree of every root, None for the ones that arent in one
+pub fn open_workspace_repos(workspace: &Workspace) -> Vec<Option<GitRepo>> {
+    workspace.roots.iter().map( | root | GitRepo::open(root)).collect()
+}
+
+// the entries of the directory at tree path, None when it cant be read
+pub fn scan_folder(workspace: &Workspace, repos: &[Option<GitRepo>], exclude: &[String], tree_path: &str) -> Option<Vec<FileTreeEntry>> {
+    let (dir, root, rel_dir) = tree_path_to_dir(workspace, tree_path) ?;
+    let index = workspace.roots.iter().position( | other | *other == root) ?;
+    let (git_ignore, git_prefix) = load_git_ignore(&root, repos.get(index).and_then( | repo | repo.as_ref()), &rel_dir, exclude);
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&dir).ok() ? {
+        let entry = if let Ok(entry) = entry {entry} else {continue};
+        let name = entry.file_name().to_string_lossy().to_string();
+        // a symlink counts as what it points to
+        let is_folder = std::fs::metadata(entry.path()).map( | m | m.is_dir()).unwrap_or(false);
+        if git_ignore.is_ignored(&format!("{}{}{}", git_prefix, rel_dir, name), is_folder) {
+            continue;
+        }
+        entries.push(FileTreeEntry {name: name, is_folder: is_folder});
+    }
+    // the other roots show up as folders in the first one
+    if tree_path.len() == 0 {
+        for index in 1..workspace.roots.len() {
+            let name = workspace.root_prefix(index).trim_end_matches('/').to_string();
+            if !entries.iter().any( | entry | entry.name == name) {
+                entries.push(FileTreeEntry {name: name, is_folder: true});
+            }
+        }
+    }
+    Some(entries)
+}
+
+// quick open doesnt wait on a home directory full of stuff
+const MAX_WORKSPACE_FILES: usize = 100000;
+
+// every file of every root the tree would show when opened all the way, sorted
+pub fn scan_workspace_files(workspace: &Workspace, repos: &[Option<GitRepo>], exclude: &[String]) -> Vec<String> {
+    let mut paths = Vec::new();
+    for index in 0..workspace.roots.len() {
+        let root = &workspace.roots[index];
+        let (git_ignore, git_prefix) = load_git_ignore(root, repos.get(index).and_then( | repo | repo.as_ref()), "", exclude);
+        scan_files(root, "", &workspace.root_prefix(index), &git_prefix, &git_ignore, &mut paths);
+    }
+    // a root inside another one gets listed twice
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn scan_files(root: &str, rel_dir: &str, prefix: &str, git_prefix: &str, git_ignore: &GitIgnore, paths: &mut Vec<String>) {
+    let entries = if let Ok(entries) = std::fs::read_dir(format!("{}{}", root, rel_dir)) {entries} else {return};
+    for entry in entries {
+        if paths.len() >= MAX_WORKSPACE_FILES {
+            return
+        }
+        let entry = if let Ok(entry) = entry {entry} else {continue};
+        let rel_path = format!("{}{}", rel_dir, entry.file_name().to_string_lossy());
+        // symlinked directories dont get followed, they can loop
+        let is_folder = entry.file_type().map( | t | t.is_dir()).unwrap_or(false);
+        if git_ignore.is_ignored(&format!("{}{}", git_prefix, rel_path), is_folder) {
+            continue;
+        }
+        if is_folder {
+            let sub_dir = format!("{}/", rel_path);
+            if let Ok(text) = std::fs::read_to_string(format!("{}{}.gitignore", root, sub_dir)) {
+                let mut git_ignore = git_ignore.clone();
+                git_ignore.add_patterns(&format!("{}{}", git_prefix, sub_dir), &text);
+                scan_files(root, &sub_dir, prefix, git_prefix, &git_ignore, paths);
+            }
+            else {
+                scan_files(root, &sub_dir, prefix, git_prefix, git_ignore, paths);
+            }
+        }
+        else {
+            paths.push(format!("{}{}", prefix, rel_path));
+        }
+    }
+}
+
+// the exclude list matches like a .gitignore at the root. The ignore paths start at the top of
+// the work tree root is in, so the .gitignore files from there down to rel_dir count and come
+// after the exclude list so they can take things back with a !. Without a repo the root is the top.
+// Also gives where root is below the top, that goes in front of the paths asked about.
+fn load_git_ignore(root: &str, repo: Option<&GitRepo>, rel_dir: &str, exclude: &[String]) -> (GitIgnore, String) {
+    let (top, git_prefix) = match repo {
+        Some(repo) => (format!("{}/", repo.root.trim_end_matches('/')), repo.prefix.clone()),
+        None => (root.to_string(), String::new())
+    };
+    let mut git_ignore = GitIgnore::default();
+    git_ignore.add_patterns(&git_prefix, &exclude.join("\n"));
+    if let Ok(text) = std::fs::read_to_string(Path::new(&top).join(".git/info/exclude")) {
+        git_ignore.add_patterns("", &text);
+    }
+    let mut base = String::new();
+    for part in format!("{}{}", git_prefix, rel_dir).split('/') {
+        if let Ok(text) = std::fs::read_to_string(format!("{}{}.gitignore", top, base)) {
+            git_ignore.add_patterns(&base, &text);
+        }
+        if part.len() == 0 {
+            break;
+        }
+        base.push_str(part);
+        base.push('/');
+    }
+    (git_ignore, git_prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn write(dir: &Path, path: &str, text: &str) {
+        std::fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+        std::fs::write(dir.join(path), text).unwrap();
+    }
+    
+    fn names(entries: Option<Vec<FileTreeEntry>>) -> Vec<String> {
+        let mut names: Vec<String> = entries.unwrap().into_iter().map( | entry | {
+            if entry.is_folder {format!("{}/", entry.name)} else {entry.name}
+        }).collect();
+        names.sort();
+        names
+    }
+    
+    #[test]
+    fn tree_paths_go_to_the_root_they_are_in() {
+        let workspace = Workspace {roots: vec!["/w/app/".to_string(), "/w/lib/".to_string(), "/w/app/vendor/".to_string()]};
+        let dir = | tree_path: &str | tree_path_to_dir(&workspace, tree_path).unwrap();
+        assert_eq!(dir(""), ("/w/app/".to_string(), "/w/app/".to_string(), "".to_string()));
+        assert_eq!(dir("src"), ("/w/app/src/".to_string(), "/w/app/".to_string(), "src/".to_string()));
+        assert_eq!(dir("../lib/src/"), ("/w/lib/src/".to_string(), "/w/lib/".to_string(), "src/".to_string()));
+        // a root inside another one wins over it
+        assert_eq!(dir("vendor"), ("/w/app/vendor/".to_string(), "/w/app/vendor/".to_string(), "".to_string()));
+        assert_eq!(dir("vendor/x"), ("/w/app/vendor/x/".to_string(), "/w/app/vendor/".to_string(), "x/".to_string()));
+    }
+    
+    #[test]
+    fn scan_folder_ignores_from_the_top_of_the_work_tree() {
+        let dir = std::env::temp_dir().join(format!("makepad_filescan_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        write(&dir, "repo/.gitignore", "*.log\nbuild/\n/ws/top_only\n");
+        write(&dir, "repo/.git/info/exclude", "secret\n");
+        write(&dir, "repo/ws/.gitignore", "!keep.log\n");
+        for path in &["a.rs", "b.log", "keep.log", "secret", "top_only", "build/x", "target/x", "src/c.rs", "src/gen/d.rs", "src/top_only"] {
+            write(&dir, &format!("repo/ws/{}", path), "");
+        }
+        write(&dir, "repo/ws/src/.gitignore", "gen/\n");
+        write(&dir, "other/e.rs", "");
+        let root = | name: &str | format!("{}/", dir.join(name).display());
+        let workspace = Workspace {roots: vec![root("repo/ws"), root("other")]};
+        let repos = vec![Some(GitRepo {root: dir.join("repo").display().to_string(), prefix: "ws/".to_string()}), None];
+        let exclude = vec!["target".to_string()];
+        
+        assert_eq!(names(scan_folder(&workspace, &repos, &exclude, "")), vec![
+            "../../other/", ".gitignore", "a.rs", "keep.log", "src/"
+        ]);
+        assert_eq!(names(scan_folder(&workspace, &repos, &exclude, "src")), vec![".gitignore", "c.rs", "top_only"]);
+        assert_eq!(names(scan_folder(&workspace, &repos, &exclude, "../../other")), vec!["e.rs"]);
+        assert!(scan_folder(&workspace, &repos, &exclude, "gone").is_none());
+        assert_eq!(scan_workspace_files(&workspace, &repos, &exclude), vec![
+            "../../other/e.rs", ".gitignore", "a.rs", "keep.log", "src/.gitignore", "src/c.rs", "src/top_only"
+        ]);
+        
+        // outside a work tree only the .gitignore files from the root down count
+        assert_eq!(names(scan_folder(&workspace, &[None, None], &exclude, "")), vec![
+            "../../other/", ".gitignore", "a.rs", "b.log", "build/", "keep.log", "secret", "src/", "top_only"
+        ]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,280 @@
+use render::*;
+use widget::*;
+use editor::*;
+
+// Side by side diff of a file against HEAD or the index. The left is what git has, the right
+// the TextBuffer with its unsaved edits, it follows along while you type. Only the rows in
+// view get drawn, clicking one puts the editor there.
+
+pub struct GitDiff {
+    pub view: View<ScrollBar>,
+    pub text: Text,
+    pub item_bg: Quad,
+    pub row_height: f32,
+    pub header_color: Color,
+    pub line_number_color: Color,
+    pub text_color: Color,
+    pub removed_bg: Color,
+    pub added_bg: Color,
+    pub missing_bg: Color,
+    pub path: Option<String>,
+    // against the index instead of HEAD
+    pub index: bool,
+    // what went wrong with the last git action on path, shown on top until the next one
+    pub error: Option<String>,
+    pub error_color: Color,
+    pub _base_text: Option<String>,
+    pub _mutation_id: u64,
+    pub _base_lines: Vec<String>,
+    pub _lines: Vec<String>,
+    pub _rows: Vec<GitDiffRow>,
+    pub _changes: usize,
+    pub _row_areas: Vec<(usize, Area)>
+}
+
+// a row of the diff, the line on either side if there is one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GitDiffRow {
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub changed: bool
+}
+
+#[derive(Clone)]
+pub enum GitDiffEvent {
+    SelectRow {path: String},
+    None,
+}
+
+impl GitDiff {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            item_bg: Quad::style(cx),
+            text: Text {
+                font: cx.load_font_style("mono_font"),
+                font_size: 8.0,
+                wrapping: Wrapping::None,
+                ..Text::style(cx)
+            },
+            view: View {
+                scroll_h: Some(ScrollBar::style(cx)),
+                scroll_v: Some(ScrollBar {
+                    smoothing: Some(0.15),
+                    ..ScrollBar::style(cx)
+                }),
+                ..View::style(cx)
+            },
+            row_height: 16.0,
+            header_color: color("#999"),
+            line_number_color: color("#777"),
+            text_color: color("#bbb"),
+            removed_bg: color256(75, 35, 35),
+            added_bg: color256(35, 65, 35),
+            missing_bg: color256(38, 38, 38),
+            path: None,
+            index: false,
+            error: None,
+            error_color: color("#f44"),
+            _base_text: None,
+            _mutation_id: 0,
+            _base_lines: Vec::new(),
+            _lines: Vec::new(),
+            _rows: Vec::new(),
+            _changes: 0,
+            _row_areas: Vec::new()
+        }
+    }
+    
+    pub fn show(&mut self, cx: &mut Cx, path: &str, index: bool) {
+        self.path = Some(path.to_string());
+        self.index = index;
+        self.error = None;
+        // diff again on the next draw
+        self._mutation_id = 0;
+        self.view.set_scroll_pos(cx, Vec2::zero());
+        self.view.redraw_view_area(cx);
+    }
+    
+    pub fn show_error(&mut self, cx: &mut Cx, path: &str, error: String) {
+        if self.path.as_ref().map_or(true, | old | old != path) {
+            self.show(cx, path, false);
+        }
+        self.error = Some(error);
+        self.view.redraw_view_area(cx);
+    }
+    
+    fn update_rows(&mut self, cx: &mut Cx, text_buffers: &mut TextBuffers) {
+        let path = if let Some(path) = &self.path {path} else {return};
+        let text_buffer = text_buffers.from_path(cx, path);
+        let base_text = if self.index {&text_buffer.git.index_text} else {&text_buffer.git.head_text};
+        if self._mutation_id == text_buffer.mutation_id && self._base_text == *base_text {
+            return
+        }
+        self._mutation_id = text_buffer.mutation_id;
+        self._base_text = base_text.clone();
+        self._base_lines = match base_text {
+            Some(base_text) => base_text.split('\n').map( | line | line.replace('\t', "    ")).collect(),
+            None => Vec::new()
+        };
+        self._lines = text_buffer.get_as_lf_string().split('\n').map( | line | line.replace('\t', "    ")).collect();
+        let base: Vec<&str> = self._base_lines.iter().map( | line | line.as_str()).collect();
+        let other: Vec<&str> = self._lines.iter().map( | line | line.as_str()).collect();
+        self._changes = diff_lines(&base, &other).len();
+        self._rows = git_diff_rows(&base, &other);
+    }
+    
+    pub fn handle_git_diff(&mut self, cx: &mut Cx, event: &mut Event, text_buffers: &mut TextBuffers) -> GitDiffEvent {
+        if self.view.handle_scroll_bars(cx, event) {
+            // only the rows in view are drawn
+            self.view.redraw_view_area(cx);
+        }
+        let path = if let Some(path) = &self.path {path.clone()} else {return GitDiffEvent::None};
+        if let Some(text_buffer) = text_buffers.storage.get(&path) {
+            let git_changed = match event {
+                Event::Signal(se) => text_buffer.signal.is_signal(se),
+                _ => false
+            };
+            if git_changed || text_buffer.mutation_id != self._mutation_id {
+                self.view.redraw_view_area(cx);
+            }
+        }
+        
+        let mut row_to_select = None;
+        for (row, area) in &self._row_areas {
+            match event.hits(cx, *area, HitOpt::default()) {
+                Event::FingerDown(_fe) => {
+                    cx.set_down_mouse_cursor(MouseCursor::Hand);
+                    row_to_select = Some(*row);
+                },
+                Event::FingerHover(_fe) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                },
+                _ => ()
+            }
+        }
+        if let Some(row_to_select) = row_to_select {
+            // a removed line goes to where it would have been
+            let line = self._rows[row_to_select..].iter().find_map( | row | row.right).unwrap_or(self._lines.len().max(1) - 1);
+            let text_buffer = text_buffers.from_path(cx, &path);
+            text_buffer.messages.jump_to_offset = text_buffer.text_pos_to_offset(TextPos {row: line, col: 0});
+            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET);
+            return GitDiffEvent::SelectRow {path: path}
+        }
+        GitDiffEvent::None
+    }
+    
+    pub fn draw_git_diff(&mut self, cx: &mut Cx, text_buffers: &mut TextBuffers) {
+        if let Err(_) = self.view.begin_view(cx, Layout::default()) {
+            return
+        }
+        self.update_rows(cx, text_buffers);
+        self._row_areas.truncate(0);
+        
+        let bg_odd = cx.color("bg_odd");
+        let row_layout = Layout {
+            width: Bounds::Fill,
+            height: Bounds::Fix(self.row_height),
+            padding: Padding {l: 4., t: 3., b: 0., r: 4.},
+            ..Default::default()
+        };
+        
+        // what we are looking at on top
+        self.item_bg.color = bg_odd;
+        let bg_inst = self.item_bg.begin_quad(cx, &row_layout);
+        self.text.color = self.header_color;
+        self.text.wrapping = Wrapping::None;
+        let against = if self.index {"the index"} else {"HEAD"};
+        match &self.path {
+            None => {
+                self.text.draw_text(cx, "Press Ctrl+Alt+G in an editor to diff its file against HEAD");
+            },
+            Some(path) => if self._base_text.is_none() {
+                self.text.draw_text(cx, &format!("{} is not in {}", path, against));
+            }
+            else {
+                self.text.draw_text(cx, &format!("{} against {} - {} changes", path, against, self._changes));
+            }
+        }
+        self.item_bg.end_quad(cx, &bg_inst);
+        cx.turtle_new_line();
+        if let Some(error) = &self.error {
+            let bg_inst = self.item_bg.begin_quad(cx, &row_layout);
+            self.text.color = self.error_color;
+            self.text.draw_text(cx, error);
+            self.item_bg.end_quad(cx, &bg_inst);
+            cx.turtle_new_line();
+        }
+        
+        let rows = if self._base_text.is_some() {self._rows.len()} else {0};
+        let scroll = self.view.get_scroll_pos(cx);
+        let rect = cx.get_turtle_rect();
+        let first = ((scroll.y / self.row_height) as usize).saturating_sub(1).min(rows);
+        let last = (((scroll.y + rect.h) / self.row_height) as usize + 1).min(rows);
+        if first > 0 {
+            cx.walk_turtle(Bounds::Fill, Bounds::Fix(first as f32 * self.row_height), Margin::zero(), None);
+            cx.turtle_new_line();
+        }
+        let number_width = 6. * self.text.get_monospace_base(cx).x * self.text.font_size;
+        let text_width = (0.5 * rect.w - number_width - 8.).max(0.);
+        for index in first..last {
+            let row = self._rows[index];
+            for side in 0..2 {
+                let (line, lines, changed_bg) = if side == 0 {
+                    (row.left, &self._base_lines, self.removed_bg)
+                }
+                else {
+                    (row.right, &self._lines, self.added_bg)
+                };
+                self.item_bg.color = if !row.changed {bg_odd} else if line.is_some() {changed_bg} else {self.missing_bg};
+                let bg_inst = self.item_bg.begin_quad(cx, &Layout {
+                    width: if side == 0 {Bounds::Scale(0.5)} else {Bounds::Fill},
+                    ..row_layout.clone()
+                });
+                if let Some(line) = line {
+                    self.text.color = self.line_number_color;
+                    self.text.wrapping = Wrapping::None;
+                    self.text.draw_text(cx, &format!("{:>5} ", line + 1));
+                    self.text.color = self.text_color;
+                    self.text.wrapping = Wrapping::Ellipsis(text_width);
+                    self.text.draw_text(cx, &lines[line]);
+                }
+                let area = self.item_bg.end_quad(cx, &bg_inst);
+                self._row_areas.push((index, area));
+            }
+            cx.turtle_new_line();
+        }
+        if last < rows {
+            cx.walk_turtle(Bounds::Fill, Bounds::Fix((rows - last) as f32 * self.row_height), Margin::zero(), None);
+            cx.turtle_new_line();
+        }
+        
+        self.view.end_view(cx);
+    }
+}
+
+// lines that are the same sit next to each other, changed ones are paired up as far as they go
+pub fn git_diff_rows(base: &[&str], other: &[&str]) -> Vec<GitDiffRow> {
+    let mut rows = Vec::new();
+    let mut base_pos = 0;
+    let mut other_pos = 0;
+    let end = DiffHunk {base_start: base.len(), base_end: base.len(), other_start: other.len(), other_end: other.len()};
+    for hunk in diff_lines(base, other).iter().chain(std::iter::once(&end)) {
+        while base_pos < hunk.base_start {
+            rows.push(GitDiffRow {left: Some(base_pos), right: Some(other_pos), changed: false});
+            base_pos += 1;
+            other_pos += 1;
+        }
+        let base_len = hunk.base_end - hunk.base_start;
+        let other_len = hunk.other_end - hunk.other_start;
+        for i in 0..base_len.max(other_len) {
+            rows.push(GitDiffRow {
+                left: if i < base_len {Some(hunk.base_start + i)} else {None},
+                right: if i < other_len {Some(hunk.other_start + i)} else {None},
+                changed: true
+            });
+        }
+        base_pos = hunk.base_end;
+        other_pos = hunk.other_end;
+    }
+    rows
+}
@@ -3,17 +3,40 @@ use render::*;
 use widget::*; 
 use editor::*; 
 use terminal::*;
+use git::*;
 mod rustcompiler; 
 pub use crate::rustcompiler::*;
+mod projectsearch;
+pub use crate::projectsearch::*;
+mod gitdiff;
+pub use crate::gitdiff::*;
+mod workspace;
+pub use crate::workspace::*;
+mod projectlist;
+pub use crate::projectlist::*;
+mod filescan;
+pub use crate::filescan::*;
+mod fileops;
+pub use crate::fileops::*;
+mod quickopen;
+pub use crate::quickopen::*;
+mod outline;
+pub use crate::outline::*;
 use std::collections::HashMap; 
+use std::rc::Rc;
+use std::sync::mpsc;
 //use std::borrow::Cow;
 use serde::*;
 
 #[derive(Clone, Serialize, Deserialize)]
 enum Panel {
     RustCompiler,
+    ProjectSearch,
+    GitDiff,
+    Projects,
     Keyboard,
     FileTree,
+    Outline,
     FileEditorTarget,
     FileEditor {path: String, editor_id: u64},
     LocalTerminal {start_path: String, terminal_id: u64}
@@ -23,6 +46,12 @@ enum Panel {
 struct AppWindow {
     desktop_window: DesktopWindow,
     file_tree: FileTree,
+    file_tree_menu: ContextMenu<FileTreeAction>,
+    palette: Palette<PaletteAction>,
+    // the file of the editor that had the focus when the palette opened
+    palette_target: Option<String>,
+    outline: Outline,
+    notices: Notices,
     keyboard: Keyboard,
     file_editors: Elements<u64, FileEditor, FileEditorTemplates>,
     local_terminals: Elements<u64, LocalTerminal, LocalTerminal>,
@@ -30,13 +59,35 @@ struct AppWindow {
 }
 
 struct AppGlobal {
-    file_tree_data: String,
+    workspace: Workspace,
+    // the git work tree of each root, for the .gitignore files above it
+    workspace_repos: Vec<Option<GitRepo>>,
+    recent_projects: RecentProjects,
+    // the index.json of every root, for the web build
+    file_tree_data: Vec<String>,
     file_tree_reload_signal: Signal,
+    // directory -> tree path of the folders the file tree listed
+    watched_folders: HashMap<String, String>,
+    file_ops: FileOps,
+    // what quick open lists, scanned on a thread when a palette opens
+    workspace_files: Option<Vec<String>>,
+    workspace_files_signal: Signal,
+    workspace_files_rx: Option<mpsc::Receiver<Vec<String>>>,
+    // shown on top of every window until clicked away
+    notices: Vec<String>,
     text_buffers: TextBuffers,
     rust_compiler: RustCompiler,
+    project_search: ProjectSearch,
+    git_diff: GitDiff,
+    project_list: ProjectList,
+    lsp_clients: LspClients,
     state: AppState,
-    index_file_read: FileRead,
+    index_file_reads: Vec<FileRead>,
     app_state_file_read: FileRead,
+    keymap: Rc<Keymap>,
+    keymap_file_read: FileRead,
+    // moving or resizing a window changes its geometry every frame, it gets saved once that stops
+    save_state_timer: Timer,
 }
 
 struct App {
@@ -55,7 +106,22 @@ struct AppWindowState {
 
 #[derive(Default, Clone, Serialize, Deserialize)]
 struct AppState {
-    windows: Vec<AppWindowState>
+    windows: Vec<AppWindowState>,
+    #[serde(default)]
+    vim_mode: bool,
+    #[serde(default)]
+    soft_wrap: bool,
+    // wraps at the view width without it
+    #[serde(default)]
+    wrap_column: Option<usize>,
+    #[serde(default)]
+    minimap: bool,
+    // rustfmt and the like on mod+s and :w, not on the save after typing
+    #[serde(default)]
+    format_on_save: bool,
+    // patterns like in a .gitignore that the file tree leaves out, None is DEFAULT_FILE_TREE_EXCLUDE
+    #[serde(default)]
+    file_tree_exclude: Option<Vec<String>>
 }
 
 main_app!(App);
@@ -72,12 +138,20 @@ impl AppWindow {
                 ..DesktopWindow::style(cx)
             },
             file_editors: Elements::new(FileEditorTemplates {
-                rust_editor: RustEditor::style(cx),
-                js_editor: JSEditor::style(cx)
+                code_editor: CodeEditor::style(cx),
+                languages: Rc::new(LanguageRegistry::with_defaults())
             }),
             local_terminals: Elements::new(LocalTerminal::style(cx)),
             keyboard: Keyboard::style(cx),
             file_tree: FileTree::style(cx),
+            file_tree_menu: ContextMenu::style(cx),
+            palette: Palette {
+                placeholder: PALETTE_PLACEHOLDER.to_string(),
+                ..Palette::style(cx)
+            },
+            palette_target: None,
+            outline: Outline::style(cx),
+            notices: Notices::style(cx),
             dock: Dock ::style(cx),
         }
     }
@@ -96,30 +170,143 @@ impl AppWindow {
                     // store our new window geom
                     app_global.state.windows[window_index].window_position = wc.new_geom.position;
                     app_global.state.windows[window_index].window_inner_size = wc.new_geom.inner_size;
-                    app_global.save_state(cx);
+                    app_global.save_state_later(cx);
                 }
             },
             _ => ()
         }
         
+        self.notices.handle_notices(cx, event, &mut app_global.notices);
+        // the menu sits on top of everything so it gets to see the events first
+        let mut file_tree_action = None;
+        if let ContextMenuEvent::Select(action) = self.file_tree_menu.handle_context_menu(cx, event) {
+            file_tree_action = Some(action);
+        }
+        // and so does the palette, a command picked there runs as if its keys were pressed
+        let mut palette_command = None;
+        let mut palette_action = None;
+        match self.palette.handle_palette(cx, event) {
+            PaletteEvent::QueryChanged => self.update_palette_items(cx, app_global),
+            PaletteEvent::Select(PaletteAction::RunCommand(command)) => palette_command = Some(command),
+            PaletteEvent::Select(action) => palette_action = Some(action),
+            _ => ()
+        }
+        
+        let mut show_project_list = false;
+        let mut show_outline = false;
+        let mut open_workspace = None;
+        let mut file_tree_event = FileTreeEvent::None;
+        let mut app_command = palette_command;
         match event {
-            Event::Signal(se) => if app_global.file_tree_reload_signal.is_signal(se) {
-                self.file_tree.load_from_json(cx, &app_global.file_tree_data);
+            Event::Signal(se) => if app_global.workspace_files_signal.is_signal(se) {
+                if self.palette.is_open() && PaletteMode::from_query(&self.palette.query).0 == PaletteMode::Files {
+                    self.update_palette_items(cx, app_global);
+                }
+            }
+            else if app_global.file_tree_reload_signal.is_signal(se) {
+                if cx.platform_type.is_desktop() {
+                    if let Some(entries) = app_global.scan_folder(cx, "") {
+                        self.file_tree.set_folder(cx, "", entries);
+                    }
+                }
+                for (index, json) in app_global.file_tree_data.iter().enumerate() {
+                    if index == 0 {
+                        self.file_tree.load_from_json(cx, json);
+                    }
+                    else {
+                        let prefix = app_global.workspace.root_prefix(index);
+                        self.file_tree.add_root_from_json(cx, prefix.trim_end_matches('/'), json);
+                    }
+                }
+            },
+            Event::KeyDown(ke) => app_command = app_global.keymap.single_command(ke),
+            _ => ()
+        }
+        // the panels and editors handle their own keys, from the palette they get them here
+        let mut editor_command = None;
+        match app_command {
+            Some(Command::OpenFolder) => app_global.project_list.pick_folder(),
+            Some(Command::RecentProjects) => show_project_list = true,
+            Some(Command::QuickOpen) => self.open_palette(cx, window_index, app_global, PaletteMode::Files),
+            Some(Command::CommandPalette) => self.open_palette(cx, window_index, app_global, PaletteMode::Commands),
+            Some(Command::GotoLine) => self.open_palette(cx, window_index, app_global, PaletteMode::Line),
+            Some(Command::GotoSymbol) => self.open_palette(cx, window_index, app_global, PaletteMode::Symbol),
+            Some(Command::ShowOutline) => show_outline = true,
+            Some(Command::RedoBranches) => self.open_palette(cx, window_index, app_global, PaletteMode::RedoBranches),
+            Some(command) if palette_command.is_some() => match command {
+                Command::RunProgram => app_global.rust_compiler.run_program_when_built(cx),
+                Command::NextSearchHit | Command::PrevSearchHit => {
+                    let back = command == Command::PrevSearchHit;
+                    if let ProjectSearchEvent::SelectHit {path} = app_global.project_search.select_next_hit(cx, back, &mut app_global.text_buffers) {
+                        file_tree_event = FileTreeEvent::SelectFile {path: path};
+                    }
+                },
+                _ => editor_command = Some(command)
+            },
+            _ => ()
+        }
+        match palette_action {
+            Some(PaletteAction::OpenFile {path}) => file_tree_event = FileTreeEvent::SelectFile {path: path},
+            Some(PaletteAction::GotoOffset {offset}) => if let Some(path) = &self.palette_target {
+                let text_buffer = app_global.text_buffers.from_path(cx, path);
+                text_buffer.messages.jump_to_offset = offset;
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET);
+            },
+            // the editor does the redo, it has the cursors
+            Some(PaletteAction::RedoBranch {child}) => if let Some(path) = &self.palette_target {
+                if app_global.text_buffers.from_path(cx, path).undo_tree.select_redo_branch(child) {
+                    editor_command = Some(Command::Redo);
+                }
             },
             _ => ()
         }
         
+        // the walk holds on to the dock, editors dont open or close while it goes
+        let open_paths = app_global.open_file_paths();
         let dock_items = &mut app_global.state.windows[window_index].dock_items;
         let mut dock_walker = self.dock.walker(dock_items);
-        let mut file_tree_event = FileTreeEvent::None;
+        let mut show_project_search = false;
+        let mut show_git_diff = false;
+        let mut state_changed = false;
         while let Some(item) = dock_walker.walk_handle_dock(cx, event) {
             match item {
                 Panel::RustCompiler => {
-                    match app_global.rust_compiler.handle_rust_compiler(cx, event, &mut app_global.text_buffers) {
+                    match app_global.rust_compiler.handle_rust_compiler(cx, event, &mut app_global.text_buffers, &app_global.keymap) {
                         RustCompilerEvent::SelectMessage {path} => {
                             // just make it open an editor
                             file_tree_event = FileTreeEvent::SelectFile {path: path};
                         },
+                        RustCompilerEvent::FixApplied {path, save} => {
+                            app_global.lsp_clients.sync_text_buffers(cx, &mut app_global.text_buffers, &open_paths);
+                            // the file might not be open, so save it here and check again
+                            if save {
+                                app_global.text_buffers.save_file(cx, &path);
+                                app_global.lsp_clients.did_save(&path);
+                                app_global.rust_compiler.restart_rust_checker(cx, &mut app_global.text_buffers);
+                            }
+                        },
+                        _ => ()
+                    }
+                },
+                Panel::ProjectSearch => {
+                    match app_global.project_search.handle_project_search(cx, event, &mut app_global.text_buffers, &app_global.keymap) {
+                        ProjectSearchEvent::SelectHit {path} => {
+                            file_tree_event = FileTreeEvent::SelectFile {path: path};
+                        },
+                        _ => ()
+                    }
+                },
+                Panel::GitDiff => {
+                    match app_global.git_diff.handle_git_diff(cx, event, &mut app_global.text_buffers) {
+                        GitDiffEvent::SelectRow {path} => {
+                            file_tree_event = FileTreeEvent::SelectFile {path: path};
+                        },
+                        _ => ()
+                    }
+                },
+                Panel::Projects => {
+                    match app_global.project_list.handle_project_list(cx, event, &app_global.recent_projects) {
+                        ProjectListEvent::Open(workspace) => open_workspace = Some(workspace),
                         _ => ()
                     }
                 },
@@ -136,22 +323,123 @@ impl AppWindow {
                 Panel::FileTree => {
                     file_tree_event = self.file_tree.handle_file_tree(cx, event);
                 },
+                Panel::Outline => {
+                    match self.outline.handle_outline(cx, event) {
+                        OutlineEvent::JumpTo {path, offset} => {
+                            let text_buffer = app_global.text_buffers.from_path(cx, &path);
+                            text_buffer.messages.jump_to_offset = offset;
+                            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET);
+                            // back to the editor
+                            file_tree_event = FileTreeEvent::SelectFile {path: path};
+                        },
+                        _ => ()
+                    }
+                },
                 Panel::FileEditor {path, editor_id} => {
                     if let Some(file_editor) = &mut self.file_editors.get(*editor_id) {
+                        file_editor.code_editor.set_keymap(&app_global.keymap);
+                        file_editor.code_editor.set_vim_mode(app_global.state.vim_mode);
+                        file_editor.code_editor.set_soft_wrap(app_global.state.soft_wrap, app_global.state.wrap_column);
+                        file_editor.code_editor.set_minimap(app_global.state.minimap);
+                        file_editor.code_editor.set_format_on_save(app_global.state.format_on_save);
+                        let file_path = format!("{}{}", app_global.text_buffers.root_path, path);
                         let text_buffer = app_global.text_buffers.from_path(cx, path);
-                        match file_editor.handle_file_editor(cx, event, text_buffer) {
+                        let file_editor_event = match editor_command {
+                            Some(command) if file_editor.code_editor.has_key_focus(cx) => {
+                                editor_command = None;
+                                file_editor.run_command(cx, command, &file_path, text_buffer)
+                            },
+                            _ => file_editor.handle_file_editor(cx, event, &file_path, text_buffer)
+                        };
+                        if file_editor.code_editor.has_key_focus(cx) {
+                            let cursors = &file_editor.code_editor.cursors;
+                            self.outline.set_target(cx, path, *editor_id, cursors.set[cursors.last_cursor].head, text_buffer.mutation_id);
+                        }
+                        match file_editor_event {
+                            FileEditorEvent::ProjectSearch(search) => {
+                                app_global.project_search.start_search(cx, search, &app_global.text_buffers);
+                                show_project_search = true;
+                            },
                             FileEditorEvent::LagChange => {
                                 app_global.text_buffers.save_file(cx, path);
+                                app_global.lsp_clients.sync_text_buffers(cx, &mut app_global.text_buffers, &open_paths);
+                                app_global.lsp_clients.did_save(path);
                                 // lets save the textbuffer to disk
                                 // lets re-trigger the rust compiler
                                 app_global.rust_compiler.restart_rust_checker(cx, &mut app_global.text_buffers);
                             },
+                            FileEditorEvent::Lsp(query) => {
+                                app_global.lsp_clients.query(cx, &mut app_global.text_buffers, path, query);
+                            },
+                            FileEditorEvent::FoldChange => {
+                                app_global.text_buffers.save_fold_state(cx, path);
+                            },
+                            FileEditorEvent::VimModeChange(enabled) => {
+                                // every editor follows, they pick it up on their next event
+                                app_global.state.vim_mode = enabled;
+                                state_changed = true;
+                            },
+                            FileEditorEvent::SoftWrapChange(soft_wrap) => {
+                                app_global.state.soft_wrap = soft_wrap;
+                                state_changed = true;
+                            },
+                            FileEditorEvent::MinimapChange(enabled) => {
+                                app_global.state.minimap = enabled;
+                                state_changed = true;
+                            },
+                            FileEditorEvent::FormatOnSaveChange(enabled) => {
+                                app_global.state.format_on_save = enabled;
+                                state_changed = true;
+                            },
+                            FileEditorEvent::GitStageHunk(row) => {
+                                if let Err(err) = app_global.text_buffers.stage_git_hunk(path, row) {
+                                    app_global.git_diff.show_error(cx, path, format!("Cannot stage: {}", err));
+                                    show_git_diff = true;
+                                }
+                            },
+                            FileEditorEvent::GitDiff {index} => {
+                                app_global.git_diff.show(cx, path, index);
+                                show_git_diff = true;
+                            },
+                            FileEditorEvent::NextMessage {offset, forward} => {
+                                if let Some((next_path, next_offset)) = app_global.text_buffers.next_message(path, offset, forward) {
+                                    let text_buffer = app_global.text_buffers.from_path(cx, &next_path);
+                                    text_buffer.messages.jump_to_offset = next_offset;
+                                    cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET);
+                                    if next_path != *path {
+                                        file_tree_event = FileTreeEvent::SelectFile {path: next_path};
+                                    }
+                                }
+                            },
                             _ => ()
                         }
                     }
                 }
             }
         }
+        if let Some(workspace) = open_workspace {
+            app_global.open_workspace(cx, workspace);
+        }
+        if show_project_search {
+            self.focus_or_new_panel(cx, window_index, app_global, Panel::ProjectSearch, "Search");
+            app_global.save_state(cx);
+        }
+        else if show_git_diff {
+            self.focus_or_new_panel(cx, window_index, app_global, Panel::GitDiff, "Diff");
+            app_global.save_state(cx);
+        }
+        else if show_project_list {
+            self.focus_or_new_panel(cx, window_index, app_global, Panel::Projects, "Projects");
+            app_global.save_state(cx);
+        }
+        else if show_outline {
+            self.focus_or_new_panel(cx, window_index, app_global, Panel::Outline, "Outline");
+            self.outline.set_key_focus(cx);
+            app_global.save_state(cx);
+        }
+        else if state_changed {
+            app_global.save_state(cx);
+        }
         match file_tree_event {
             FileTreeEvent::DragMove {fe, ..} => {
                 self.dock.dock_drag_move(cx, fe);
@@ -162,6 +450,12 @@ impl AppWindow {
             FileTreeEvent::DragOut => {
                 self.dock.dock_drag_out(cx);
             },
+            // the web build has all of it from index.json
+            FileTreeEvent::LoadFolder {path} => if cx.platform_type.is_desktop() {
+                if let Some(entries) = app_global.scan_folder(cx, &path) {
+                    self.file_tree.set_folder(cx, &path, entries);
+                }
+            },
             FileTreeEvent::DragEnd {fe, paths} => {
                 let mut tabs = Vec::new();
                 for path in paths {
@@ -176,16 +470,65 @@ impl AppWindow {
                     app_global.save_state(cx);
                 }
             },
+            // the web build cant touch the disk
+            FileTreeEvent::ContextMenu {path, is_folder, abs} => if cx.platform_type.is_desktop() {
+                let items = file_tree_menu(&path, is_folder, app_global.file_ops.undo_label());
+                self.file_tree_menu.open(cx, abs, items);
+            },
+            FileTreeEvent::Rename {path, new_name} => if cx.platform_type.is_desktop() {
+                let done = app_global.file_ops.rename(&path, &new_name);
+                self.apply_file_ops(cx, app_global, done);
+            },
+            FileTreeEvent::Move {paths, folder} => if cx.platform_type.is_desktop() {
+                let done = app_global.file_ops.move_paths(&paths, &folder);
+                self.apply_file_ops(cx, app_global, done);
+            },
+            FileTreeEvent::Delete {paths} => if cx.platform_type.is_desktop() {
+                let done = app_global.file_ops.delete(&paths);
+                self.apply_file_ops(cx, app_global, done);
+            },
+            FileTreeEvent::UndoFileOp => if cx.platform_type.is_desktop() {
+                file_tree_action = Some(FileTreeAction::Undo);
+            },
             _ => {}
         }
+        if let Some(action) = file_tree_action {
+            self.run_file_tree_action(cx, app_global, action);
+        }
         
         let dock_items = &mut app_global.state.windows[window_index].dock_items;
         match self.dock.handle_dock(cx, event, dock_items) {
             DockEvent::DockChanged => { // thats a bit bland event. lets let the thing know which file closed
                 app_global.save_state(cx);
+                app_global.sync_lsp_clients(cx);
             },
             _ => ()
         }
+        
+        self.update_dirty_markers(cx, window_index, app_global);
+    }
+    
+    // file editor tabs get a * behind the name while their buffer has unsaved changes
+    fn update_dirty_markers(&mut self, cx: &mut Cx, window_index: usize, app_global: &mut AppGlobal) {
+        let dock_items = &mut app_global.state.windows[window_index].dock_items;
+        let mut dock_walker = self.dock.walker(dock_items);
+        while let Some(dock_item) = dock_walker.walk_dock_item() {
+            if let DockItem::TabControl {tabs, ..} = dock_item {
+                for tab in tabs.iter_mut() {
+                    if let Panel::FileEditor {path, ..} = &tab.item {
+                        let is_dirty = match app_global.text_buffers.storage.get(path) {
+                            Some(text_buffer) => text_buffer.is_dirty(),
+                            None => false
+                        };
+                        let title = if is_dirty {format!("{}*", path_file_name(path))} else {path_file_name(path)};
+                        if tab.title != title {
+                            tab.title = title;
+                            cx.redraw_child_area(Area::All);
+                        }
+                    }
+                }
+            }
+        }
     }
     
     fn draw_app_window(&mut self, cx: &mut Cx, window_index: usize, app_global: &mut AppGlobal) {
@@ -201,6 +544,15 @@ impl AppWindow {
                 Panel::RustCompiler => {
                     app_global.rust_compiler.draw_rust_compiler(cx);
                 },
+                Panel::ProjectSearch => {
+                    app_global.project_search.draw_project_search(cx);
+                },
+                Panel::GitDiff => {
+                    app_global.git_diff.draw_git_diff(cx, &mut app_global.text_buffers);
+                },
+                Panel::Projects => {
+                    app_global.project_list.draw_project_list(cx, &app_global.recent_projects, &app_global.workspace);
+                },
                 Panel::Keyboard => {
                     self.keyboard.draw_keyboard(cx);
                 },
@@ -209,6 +561,20 @@ impl AppWindow {
                 Panel::FileTree => {
                     self.file_tree.draw_file_tree(cx);
                 },
+                Panel::Outline => {
+                    let text_buffer = match self.outline.path.clone() {
+                        Some(path) => {
+                            let text_buffer = app_global.text_buffers.from_path(cx, &path);
+                            // the outline can be drawn before the editor gets to it
+                            if let Some(file_editor) = self.file_editors.get(self.outline.editor_id) {
+                                file_editor.update_token_chunks(&path, text_buffer);
+                            }
+                            Some(&*text_buffer)
+                        },
+                        None => None
+                    };
+                    self.outline.draw_outline(cx, text_buffer);
+                },
                 Panel::LocalTerminal {terminal_id, ..} => {
                     let local_terminal = self.local_terminals.get_draw(cx, *terminal_id, | cx, tmpl | {
                         let mut new_terminal = tmpl.clone();
@@ -222,18 +588,154 @@ impl AppWindow {
                     let mut set_key_focus = false;
                     let file_editor = self.file_editors.get_draw(cx, *editor_id, | _cx, tmpl | {
                         set_key_focus = true;
-                        FileEditor::create_file_editor_for_path(path, tmpl)
+                        FileEditor::new(tmpl)
                     });
-                    file_editor.draw_file_editor(cx, text_buffer);
+                    file_editor.draw_file_editor(cx, path, text_buffer);
                     if set_key_focus {
                         file_editor.set_key_focus(cx);
                     }
                 }
             }
         }
+        self.file_tree_menu.draw_context_menu(cx);
+        self.palette.draw_palette(cx);
+        self.notices.draw_notices(cx, &app_global.notices);
         self.desktop_window.end_desktop_window(cx);
     }
     
+    fn open_palette(&mut self, cx: &mut Cx, window_index: usize, app_global: &mut AppGlobal, mode: PaletteMode) {
+        self.palette_target = self.focused_file_editor(cx, window_index, app_global);
+        app_global.workspace_files = None;
+        self.palette.open(cx, mode.prefix());
+        self.update_palette_items(cx, app_global);
+    }
+    
+    fn update_palette_items(&mut self, cx: &mut Cx, app_global: &mut AppGlobal) {
+        let (mode, filter) = PaletteMode::from_query(&self.palette.query);
+        let filter = filter.to_string();
+        let items = match mode {
+            PaletteMode::Files => match &app_global.workspace_files {
+                Some(files) => file_palette_items(files),
+                None => {
+                    // the web build only has what index.json gave the tree
+                    if cx.platform_type.is_desktop() {
+                        app_global.scan_workspace_files();
+                    }
+                    file_palette_items(&self.file_tree.file_paths())
+                }
+            },
+            PaletteMode::Commands => command_palette_items(&app_global.keymap),
+            PaletteMode::Line | PaletteMode::Symbol => match &self.palette_target {
+                Some(path) => {
+                    let text_buffer = app_global.text_buffers.from_path(cx, path);
+                    if mode == PaletteMode::Line {line_palette_items(&filter, text_buffer)} else {symbol_palette_items(text_buffer)}
+                },
+                None => Vec::new()
+            },
+            PaletteMode::RedoBranches => match &self.palette_target {
+                Some(path) => redo_branch_palette_items(app_global.text_buffers.from_path(cx, path)),
+                None => Vec::new()
+            }
+        };
+        // the line number isnt something to match
+        let filter = if mode == PaletteMode::Line {""} else {&filter};
+        self.palette.set_items(cx, items, filter);
+    }
+    
+    fn focused_file_editor(&mut self, cx: &mut Cx, window_index: usize, app_global: &mut AppGlobal) -> Option<String> {
+        let dock_items = &mut app_global.state.windows[window_index].dock_items;
+        let mut dock_walker = self.dock.walker(dock_items);
+        while let Some(dock_item) = dock_walker.walk_dock_item() {
+            if let DockItem::TabControl {tabs, ..} = dock_item {
+                for tab in tabs.iter() {
+                    if let Panel::FileEditor {path, editor_id} = &tab.item {
+                        if let Some(file_editor) = self.file_editors.get(*editor_id) {
+                            if file_editor.code_editor.has_key_focus(cx) {
+                                return Some(path.clone())
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+    
+    fn run_file_tree_action(&mut self, cx: &mut Cx, app_global: &mut AppGlobal, action: FileTreeAction) {
+        let done = match &action {
+            FileTreeAction::NewFile {folder} => app_global.file_ops.create(folder, false),
+            FileTreeAction::NewFolder {folder} => app_global.file_ops.create(folder, true),
+            FileTreeAction::Rename {path} => {
+                self.file_tree.start_rename(cx, path);
+                return
+            },
+            FileTreeAction::Duplicate {path} => app_global.file_ops.duplicate(path),
+            FileTreeAction::Delete {path} => app_global.file_ops.delete(&[path.clone()]),
+            FileTreeAction::Undo => app_global.file_ops.undo()
+        };
+        let ops = self.apply_file_ops(cx, app_global, done);
+        // something new gets its name typed right away
+        if let FileTreeAction::NewFile {folder} | FileTreeAction::NewFolder {folder} = &action {
+            if let Some(FileOp::Create {path}) = ops.first() {
+                self.file_tree.open_folder(cx, folder);
+                self.file_tree.start_rename(cx, path);
+            }
+        }
+    }
+    
+    // moves the open buffers and tabs along with their files, closes those of deleted files and
+    // lists the folders that changed
+    fn apply_file_ops(&mut self, cx: &mut Cx, app_global: &mut AppGlobal, done: FileOpsDone) -> Vec<FileOp> {
+        let FileOpsDone {ops, error} = done;
+        if let Some(error) = error {
+            app_global.notify(cx, error);
+        }
+        let mut folders: Vec<String> = Vec::new();
+        for op in &ops {
+            if let FileOp::Trash {path: deleted, ..} = op {
+                let closed = app_global.text_buffers.close_deleted_path(cx, deleted);
+                let is_closed = | panel: &Panel | if let Panel::FileEditor {path, ..} = panel {closed.contains(path)} else {false};
+                for window_state in &mut app_global.state.windows {
+                    Dock::close_tabs(&mut window_state.dock_items, &is_closed);
+                }
+                if self.outline.path.as_ref().map_or(false, | path | closed.contains(path)) {
+                    self.outline.path = None;
+                }
+            }
+            if let FileOp::Rename {from, to} = op {
+                app_global.text_buffers.rename_path(cx, from, to);
+                let mut editor_ids = Vec::new();
+                for window_state in &mut app_global.state.windows {
+                    rename_file_editor_panels(&mut window_state.dock_items, from, to, &mut editor_ids);
+                }
+                if let Some(new_path) = self.outline.path.as_ref().and_then( | path | renamed_path(path, from, to)) {
+                    self.outline.path = Some(new_path);
+                }
+                // another extension can mean another language
+                for editor_id in editor_ids {
+                    if let Some(file_editor) = self.file_editors.get(editor_id) {
+                        file_editor.language = None;
+                    }
+                }
+            }
+            for folder in op.folders() {
+                if !folders.contains(&folder) {
+                    folders.push(folder);
+                }
+            }
+        }
+        for folder in folders {
+            if let Some(entries) = app_global.scan_folder(cx, &folder) {
+                self.file_tree.set_folder(cx, &folder, entries);
+            }
+        }
+        if ops.len() > 0 {
+            app_global.save_state(cx);
+            cx.redraw_child_area(Area::All);
+        }
+        ops
+    }
+    
     fn new_file_editor_tab(&mut self, window_index: usize, app_global: &mut AppGlobal, path: &str) -> DockTab<Panel> {
         let mut max_id = 0;
         let dock_items = &mut app_global.state.windows[window_index].dock_items;
@@ -316,21 +818,179 @@ impl AppWindow {
         }
         return false
     }
+    
+    // shows a panel like the search, it goes next to the rust compiler when the layout doesnt have one yet.
+    // The outline goes next to the file tree instead
+    fn focus_or_new_panel(&mut self, cx: &mut Cx, window_index: usize, app_global: &mut AppGlobal, panel: Panel, title: &str) {
+        let beside = if let Panel::Outline = panel {Panel::FileTree} else {Panel::RustCompiler};
+        let dock_items = &mut app_global.state.windows[window_index].dock_items;
+        let mut dock_walker = self.dock.walker(dock_items);
+        let mut target_ctrl_id = 0;
+        let mut ctrl_id = 1;
+        while let Some(dock_item) = dock_walker.walk_dock_item() {
+            if let DockItem::TabControl {current, tabs} = dock_item {
+                for (id, tab) in tabs.iter().enumerate() {
+                    if std::mem::discriminant(&tab.item) == std::mem::discriminant(&panel) {
+                        *current = id;
+                        cx.redraw_child_area(Area::All);
+                        return
+                    }
+                    if std::mem::discriminant(&tab.item) == std::mem::discriminant(&beside) {
+                        target_ctrl_id = ctrl_id;
+                    }
+                }
+            }
+            ctrl_id += 1;
+        }
+        let mut dock_walker = self.dock.walker(dock_items);
+        let mut ctrl_id = 1;
+        while let Some(dock_item) = dock_walker.walk_dock_item() {
+            if ctrl_id == target_ctrl_id || target_ctrl_id == 0 {
+                if let DockItem::TabControl {current, tabs} = dock_item {
+                    tabs.push(DockTab {
+                        closeable: true,
+                        title: title.to_string(),
+                        item: panel
+                    });
+                    *current = tabs.len() - 1;
+                    cx.redraw_child_area(Area::All);
+                    return
+                }
+            }
+            ctrl_id += 1;
+        }
+    }
 }
 
 impl AppGlobal {
+    // the buffers shown in an editor in any window
+    fn open_file_paths(&self) -> Vec<String> {
+        let mut open_paths = Vec::new();
+        for window_state in &self.state.windows {
+            file_editor_paths(&window_state.dock_items, &mut open_paths);
+        }
+        open_paths
+    }
+    
+    // the servers get the open buffers, the others are closed on them
+    fn sync_lsp_clients(&mut self, cx: &mut Cx) {
+        let open_paths = self.open_file_paths();
+        self.lsp_clients.sync_text_buffers(cx, &mut self.text_buffers, &open_paths);
+    }
+    
     fn handle_construct(&mut self, cx: &mut Cx) {
         if cx.platform_type.is_desktop() {
-            self.text_buffers.root_path = "./edit_repo/".to_string();
+            self.recent_projects = RecentProjects::load();
+            let mut errors = Vec::new();
+            self.workspace = Workspace::from_command_line(&self.recent_projects, &mut errors);
+            self.recent_projects.add(&self.workspace);
+            if let Err(err) = self.recent_projects.save() {
+                errors.push(err);
+            }
+            for err in errors {
+                self.notify(cx, err);
+            }
+            self.workspace_repos = open_workspace_repos(&self.workspace);
+            self.text_buffers.root_path = self.workspace.root_path().to_string();
+            self.file_ops.root_path = self.workspace.root_path().to_string();
+            self.file_ops.fixed_paths = (1..self.workspace.roots.len()).map( | index | {
+                self.workspace.root_prefix(index).trim_end_matches('/').to_string()
+            }).collect();
+            self.text_buffers.open_git_repo(cx);
+            self.text_buffers.load_undo_histories(cx);
+            self.text_buffers.load_fold_states(cx);
+            self.lsp_clients.init(cx, LspServerConfig::defaults());
+            self.keymap_file_read = cx.file_read(&format!("{}makepad_keys.json", self.text_buffers.root_path));
         }
         
         self.rust_compiler.init(cx, &mut self.text_buffers);
+        self.project_search.init(cx);
+        self.project_list.init(cx);
+        if !cx.platform_type.is_desktop() {
+            self.file_tree_data = vec![String::new(); self.workspace.roots.len()];
+            self.index_file_reads = self.workspace.roots.iter().map( | root | cx.file_read(&format!("{}index.json", root))).collect();
+        }
+    }
+    
+    // a home directory full of stuff takes a while, the palette shows what the tree has till then
+    fn scan_workspace_files(&mut self) {
+        if self.workspace_files_rx.is_some() {
+            return
+        }
+        let (tx, rx) = mpsc::channel();
+        let signal = self.workspace_files_signal;
+        let workspace = self.workspace.clone();
+        let repos = self.workspace_repos.clone();
+        let exclude = self.file_tree_exclude();
+        std::thread::spawn(move || {
+            let _ = tx.send(scan_workspace_files(&workspace, &repos, &exclude));
+            Cx::post_signal(signal, 0);
+        });
+        self.workspace_files_rx = Some(rx);
+    }
+    
+    fn handle_workspace_files(&mut self, event: &mut Event) {
+        if let Event::Signal(se) = event {
+            if self.workspace_files_signal.is_signal(se) {
+                if let Some(files) = self.workspace_files_rx.take().and_then( | rx | rx.try_recv().ok()) {
+                    self.workspace_files = Some(files);
+                }
+            }
+        }
+    }
+    
+    fn file_tree_exclude(&self) -> Vec<String> {
+        self.state.file_tree_exclude.clone().unwrap_or_else( || {
+            DEFAULT_FILE_TREE_EXCLUDE.iter().map( | pattern | pattern.to_string()).collect()
+        })
+    }
+    
+    // lists a folder for the file tree and watches it from then on
+    fn scan_folder(&mut self, cx: &mut Cx, tree_path: &str) -> Option<Vec<FileTreeEntry>> {
+        let (dir, _, _) = tree_path_to_dir(&self.workspace, tree_path) ?;
+        let entries = scan_folder(&self.workspace, &self.workspace_repos, &self.file_tree_exclude(), tree_path);
+        if entries.is_none() {
+            // its gone, the listing of its parent drops it
+            if self.watched_folders.remove(&dir).is_some() {
+                cx.dir_unwatch(&dir);
+            }
+        }
+        else if !self.watched_folders.contains_key(&dir) {
+            cx.dir_watch(&dir);
+            self.watched_folders.insert(dir, tree_path.to_string());
+        }
+        entries
+    }
+    
+    // every project gets a makepad of its own, this one stays on its workspace
+    fn open_workspace(&mut self, cx: &mut Cx, workspace: Workspace) {
+        self.recent_projects.add(&workspace);
+        if let Err(err) = self.recent_projects.save() {
+            self.notify(cx, err);
+        }
+        self.project_list.view.redraw_view_area(cx);
+        if workspace != self.workspace {
+            if let Err(err) = workspace.open_in_new_window() {
+                self.notify(cx, err);
+            }
+        }
+    }
+    
+    fn notify(&mut self, cx: &mut Cx, notice: String) {
+        self.notices.push(notice);
+        cx.redraw_child_area(Area::All);
     }
     
     fn save_state(&mut self, cx: &mut Cx) {
+        cx.stop_timer(&mut self.save_state_timer);
         let json = serde_json::to_string(&self.state).unwrap();
         cx.file_write(&format!("{}makepad_state.json", self.text_buffers.root_path), json.as_bytes());
     }
+    
+    fn save_state_later(&mut self, cx: &mut Cx) {
+        cx.stop_timer(&mut self.save_state_timer);
+        self.save_state_timer = cx.start_timer(0.5, false);
+    }
 }
 
 impl App {
@@ -352,6 +1012,10 @@ impl App {
                             closeable: false,
                             title: "Files".to_string(),
                             item: Panel::FileTree
+                        }, DockTab {
+                            closeable: true,
+                            title: "Outline".to_string(),
+                            item: Panel::Outline
                         }]
                     }),
                     last: Box::new(DockItem::Splitter {
@@ -359,17 +1023,12 @@ impl App {
                         align: SplitterAlign::Last,
                         pos: 150.0,
                         first: Box::new(DockItem::TabControl {
-                            current: 1,
+                            current: 0,
                             tabs: vec![
                                 DockTab {
                                     closeable: false,
                                     title: "Edit".to_string(),
                                     item: Panel::FileEditorTarget
-                                },
-                                DockTab {
-                                    closeable: true,
-                                    title: "main.rs".to_string(),
-                                    item: Panel::FileEditor {path: "examples/quad_example/src/main.rs".to_string(), editor_id: 1}
                                 }
                             ],
                         }),
@@ -399,14 +1058,38 @@ impl App {
             windows: vec![],
             app_global: AppGlobal {
                 rust_compiler: RustCompiler::style(cx),
+                project_search: ProjectSearch::style(cx),
+                git_diff: GitDiff::style(cx),
+                project_list: ProjectList::style(cx),
+                lsp_clients: LspClients::default(),
                 text_buffers: TextBuffers {
                     root_path: "./".to_string(),
-                    storage: HashMap::new()
+                    storage: HashMap::new(),
+                    undo_histories: HashMap::new(),
+                    undo_file_read: FileRead::default(),
+                    undo_save_timer: Timer::empty(),
+                    fold_states: HashMap::new(),
+                    fold_file_read: FileRead::default(),
+                    git_repo: None,
+                    git_loader: None,
+                    git_receiver: None
                 },
-                index_file_read: FileRead::default(),
+                index_file_reads: Vec::new(),
                 app_state_file_read: FileRead::default(),
-                file_tree_data: String::new(),
+                keymap: Rc::new(Keymap::default_keymap()),
+                keymap_file_read: FileRead::default(),
+                save_state_timer: Timer::empty(),
+                workspace: Workspace {roots: vec!["./".to_string()]},
+                workspace_repos: Vec::new(),
+                recent_projects: RecentProjects::default(),
+                file_tree_data: Vec::new(),
                 file_tree_reload_signal: cx.new_signal(),
+                watched_folders: HashMap::new(),
+                file_ops: FileOps::default(),
+                workspace_files: None,
+                workspace_files_signal: cx.new_signal(),
+                workspace_files_rx: None,
+                notices: Vec::new(),
                 state: AppState::default()
             }
         }
@@ -424,7 +1107,6 @@ impl App {
         match event {
             Event::Construct => {
                 self.app_global.handle_construct(cx);
-                self.app_global.index_file_read = cx.file_read(&format!("{}index.json", self.app_global.text_buffers.root_path));
                 if cx.platform_type.is_desktop() {
                     self.app_global.app_state_file_read = cx.file_read(&format!("{}makepad_state.json", self.app_global.text_buffers.root_path));
                 }
@@ -434,9 +1116,9 @@ impl App {
             },
             Event::FileRead(fr) => {
                 // lets see which file we loaded
-                if let Some(utf8_data) = self.app_global.index_file_read.resolve_utf8(fr) {
+                if let Some((index, utf8_data)) = self.app_global.index_file_reads.iter_mut().enumerate().find_map( | (index, read) | Some((index, read.resolve_utf8(fr) ?))) {
                     if let Ok(utf8_data) = utf8_data {
-                        self.app_global.file_tree_data = utf8_data.to_string();
+                        self.app_global.file_tree_data[index] = utf8_data.to_string();
                         cx.send_signal(self.app_global.file_tree_reload_signal, 0);
                     }
                 }
@@ -480,18 +1162,79 @@ impl App {
                         self.default_layout(cx);
                     }
                 }
+                else if let Some(utf8_data) = self.app_global.keymap_file_read.resolve_utf8(fr) {
+                    // no keys file just means the defaults
+                    if let Ok(utf8_data) = utf8_data {
+                        match Keymap::from_json(&utf8_data) {
+                            Ok(keymap) => self.app_global.keymap = Rc::new(keymap),
+                            Err(err) => self.app_global.notify(cx, format!("Cannot load makepad_keys.json: {}", err))
+                        }
+                    }
+                }
                 else if self.app_global.text_buffers.handle_file_read(cx, &fr) {
                     // this should work already
                     //cx.redraw_child_area(Area::All);
+                    self.app_global.sync_lsp_clients(cx);
                 }
             },
+            Event::Timer(te) => {
+                if self.app_global.save_state_timer.is_timer(te) {
+                    self.app_global.save_state(cx);
+                }
+                self.app_global.text_buffers.handle_undo_save_timer(cx, te);
+            },
+            Event::FileChanged(fc) => {
+                if let Some(tree_path) = self.app_global.watched_folders.get(&fc.path).cloned() {
+                    // entries came or went
+                    if let Some(entries) = self.app_global.scan_folder(cx, &tree_path) {
+                        for window in &mut self.windows {
+                            window.file_tree.set_folder(cx, &tree_path, entries.clone());
+                        }
+                    }
+                }
+                else {
+                    self.app_global.text_buffers.handle_file_changed(cx, &fc);
+                }
+            },
+            Event::FileWrite(fw) => {
+                self.app_global.text_buffers.handle_file_write(cx, &fw);
+            },
+            Event::AppFocus => {
+                // commits and checkouts happen in other programs
+                self.app_global.text_buffers.reload_git();
+            },
+            Event::AppFocusLost => {
+                self.app_global.text_buffers.flush_undo_histories(cx);
+            },
             
             _ => ()
         }
+        match self.app_global.project_list.handle_folder_pick(event) {
+            Some(Ok(workspace)) => self.app_global.open_workspace(cx, workspace),
+            Some(Err(err)) => self.app_global.notify(cx, err),
+            None => ()
+        }
+        self.app_global.text_buffers.handle_git_loads(cx, event);
+        self.app_global.handle_workspace_files(event);
+        if let Some(err) = self.app_global.rust_compiler.handle_cargo_workspace(cx, event, &mut self.app_global.text_buffers) {
+            self.app_global.notify(cx, err);
+        }
+        match self.app_global.lsp_clients.handle_lsp_clients(cx, event, &mut self.app_global.text_buffers) {
+            LspClientsEvent::OpenFile {path} => if let Some(window) = self.windows.get_mut(0) {
+                if window.focus_or_new_editor(cx, 0, &mut self.app_global, &path) {
+                    self.app_global.save_state(cx);
+                }
+            },
+            _ => ()
+        }
         for (window_index, window) in self.windows.iter_mut().enumerate() {
             window.handle_app_window(cx, event, window_index, &mut self.app_global);
             // break;
         }
+        // the windows start language servers when they open files
+        for notice in std::mem::replace(&mut self.app_global.lsp_clients.notices, Vec::new()) {
+            self.app_global.notify(cx, notice);
+        }
     }
     
     fn draw_app(&mut self, cx: &mut Cx) {
@@ -505,79 +1248,165 @@ impl App {
 
 #[derive(Clone)]
 struct FileEditorTemplates {
-    rust_editor: RustEditor,
-    js_editor: JSEditor,
-    //text_editor: TextEditor
+    code_editor: CodeEditor,
+    languages: Rc<LanguageRegistry>
 }
 
 #[derive(Clone)]
-enum FileEditor {
-    Rust(RustEditor),
-    JS(JSEditor),
-    //Text(TextEditor)
+struct FileEditor {
+    code_editor: CodeEditor,
+    languages: Rc<LanguageRegistry>,
+    // picked on the first draw after the file loaded, so a modeline or #! line can have a say
+    language: Option<Rc<dyn Language>>
 }
 
 #[derive(Clone)]
 enum FileEditorEvent {
     None,
     LagChange,
-    Change
+    Change,
+    ProjectSearch(TextSearch),
+    Lsp(LspQuery),
+    FoldChange,
+    NextMessage {offset: usize, forward: bool},
+    VimModeChange(bool),
+    SoftWrapChange(bool),
+    MinimapChange(bool),
+    FormatOnSaveChange(bool),
+    GitStageHunk(usize),
+    GitDiff {index: bool}
 }
 
 impl FileEditor {
-    fn handle_file_editor(&mut self, cx: &mut Cx, event: &mut Event, text_buffer: &mut TextBuffer) -> FileEditorEvent {
-        match self {
-            FileEditor::Rust(re) => {
-                match re.handle_rust_editor(cx, event, text_buffer) {
-                    CodeEditorEvent::Change => FileEditorEvent::Change,
-                    CodeEditorEvent::LagChange => FileEditorEvent::LagChange,
-                    _ => FileEditorEvent::None
-                }
+    fn new(template: &FileEditorTemplates) -> FileEditor {
+        FileEditor {
+            code_editor: template.code_editor.clone(),
+            languages: template.languages.clone(),
+            language: None
+        }
+    }
+    
+    fn handle_file_editor(&mut self, cx: &mut Cx, event: &mut Event, file_path: &str, text_buffer: &mut TextBuffer) -> FileEditorEvent {
+        let code_editor_event = self.code_editor.handle_code_editor(cx, event, text_buffer);
+        self.file_editor_event(cx, code_editor_event, file_path, text_buffer)
+    }
+    
+    // a command that didnt come from the keys, like one picked in the palette
+    fn run_command(&mut self, cx: &mut Cx, command: Command, file_path: &str, text_buffer: &mut TextBuffer) -> FileEditorEvent {
+        let code_editor_event = self.code_editor.run_command(cx, command, text_buffer);
+        self.file_editor_event(cx, code_editor_event, file_path, text_buffer)
+    }
+    
+    fn file_editor_event(&mut self, cx: &mut Cx, code_editor_event: CodeEditorEvent, file_path: &str, text_buffer: &mut TextBuffer) -> FileEditorEvent {
+        match code_editor_event {
+            CodeEditorEvent::AutoFormat => {
+                self.format(cx, file_path, text_buffer);
+                FileEditorEvent::None
             },
-            FileEditor::JS(re) => {
-                match re.handle_js_editor(cx, event, text_buffer) {
-                    CodeEditorEvent::Change => FileEditorEvent::Change,
-                    CodeEditorEvent::LagChange => FileEditorEvent::LagChange,
-                    _ => FileEditorEvent::None
+            CodeEditorEvent::Save => {
+                if self.code_editor.format_on_save {
+                    self.format(cx, file_path, text_buffer);
                 }
+                FileEditorEvent::LagChange
             },
+            CodeEditorEvent::Change => FileEditorEvent::Change,
+            CodeEditorEvent::LagChange => FileEditorEvent::LagChange,
+            CodeEditorEvent::ProjectSearch(search) => FileEditorEvent::ProjectSearch(search),
+            CodeEditorEvent::Lsp(query) => FileEditorEvent::Lsp(query),
+            CodeEditorEvent::FoldChange => FileEditorEvent::FoldChange,
+            CodeEditorEvent::NextMessage {offset, forward} => FileEditorEvent::NextMessage {offset, forward},
+            CodeEditorEvent::VimModeChange(enabled) => FileEditorEvent::VimModeChange(enabled),
+            CodeEditorEvent::SoftWrapChange(soft_wrap) => FileEditorEvent::SoftWrapChange(soft_wrap),
+            CodeEditorEvent::MinimapChange(enabled) => FileEditorEvent::MinimapChange(enabled),
+            CodeEditorEvent::FormatOnSaveChange(enabled) => FileEditorEvent::FormatOnSaveChange(enabled),
+            CodeEditorEvent::GitStageHunk(row) => FileEditorEvent::GitStageHunk(row),
+            CodeEditorEvent::GitDiff {index} => FileEditorEvent::GitDiff {index},
+            _ => FileEditorEvent::None
         }
     }
     
-    fn set_key_focus(&mut self, cx: &mut Cx) {
-        match self {
-            FileEditor::Rust(re) => re.code_editor.set_key_focus(cx),
-            FileEditor::JS(re) => re.code_editor.set_key_focus(cx),
+    fn format(&mut self, cx: &mut Cx, file_path: &str, text_buffer: &mut TextBuffer) {
+        if let Some(language) = self.language.clone() {
+            if let Some(formatted) = format_text_buffer(&*language, file_path, text_buffer) {
+                self.code_editor.apply_formatted(cx, &formatted, text_buffer);
+            }
+            else { // it can have told why in the messages
+                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+            }
         }
     }
     
-    fn draw_file_editor(&mut self, cx: &mut Cx, text_buffer: &mut TextBuffer) {
-        match self {
-            FileEditor::Rust(re) => re.draw_rust_editor(cx, text_buffer),
-            FileEditor::JS(re) => re.draw_js_editor(cx, text_buffer),
+    fn set_key_focus(&mut self, cx: &mut Cx) {
+        self.code_editor.set_key_focus(cx);
+    }
+    
+    fn update_token_chunks(&mut self, path: &str, text_buffer: &mut TextBuffer) {
+        if self.language.is_none() && !text_buffer.load_file_read.is_pending() {
+            let language = self.languages.find(path, text_buffer);
+            self.code_editor.set_language(&language);
+            self.language = Some(language);
+        }
+        if let Some(language) = &self.language {
+            if text_buffer.needs_token_chunks() {
+                text_buffer.update_token_chunks(&mut *language.new_tokenizer());
+            }
         }
     }
     
-    fn create_file_editor_for_path(path: &str, template: &FileEditorTemplates) -> FileEditor {
-        // check which file extension we have to spawn a new editor
-        if path.ends_with(".rs") {
-            FileEditor::Rust(RustEditor {
-                ..template.rust_editor.clone()
-            })
+    fn draw_file_editor(&mut self, cx: &mut Cx, path: &str, text_buffer: &mut TextBuffer) {
+        self.update_token_chunks(path, text_buffer);
+    
+        if let Err(_) = self.code_editor.begin_code_editor(cx, text_buffer) {
+            return
         }
-        else if path.ends_with(".js") {
-            FileEditor::JS(JSEditor {
-                ..template.js_editor.clone()
-            })
+        
+        for (index, token_chunk) in text_buffer.token_chunks.iter_mut().enumerate() {
+            self.code_editor.draw_chunk(cx, index, &text_buffer.flat_text, token_chunk, &text_buffer.messages.cursors);
         }
-        else {
-            FileEditor::Rust(RustEditor {
-                ..template.rust_editor.clone()
-            })
+        
+        self.code_editor.end_code_editor(cx, text_buffer);
+    }
+}
+
+// the tabs of files below from that now live below to
+fn file_editor_paths(dock_item: &DockItem<Panel>, paths: &mut Vec<String>) {
+    match dock_item {
+        DockItem::Single(Panel::FileEditor {path, ..}) => paths.push(path.clone()),
+        DockItem::Single(_) => (),
+        DockItem::TabControl {tabs, ..} => for tab in tabs {
+            if let Panel::FileEditor {path, ..} = &tab.item {
+                paths.push(path.clone());
+            }
+        },
+        DockItem::Splitter {first, last, ..} => {
+            file_editor_paths(first, paths);
+            file_editor_paths(last, paths);
         }
     }
 }
 
+fn rename_file_editor_panels(dock_item: &mut DockItem<Panel>, from: &str, to: &str, editor_ids: &mut Vec<u64>) {
+    match dock_item {
+        DockItem::Single(Panel::FileEditor {path, editor_id}) => if let Some(new_path) = renamed_path(path, from, to) {
+            *path = new_path;
+            editor_ids.push(*editor_id);
+        },
+        DockItem::Single(_) => (),
+        DockItem::TabControl {tabs, ..} => for tab in tabs {
+            if let Panel::FileEditor {path, editor_id} = &mut tab.item {
+                if let Some(new_path) = renamed_path(path, from, to) {
+                    tab.title = path_file_name(&new_path);
+                    *path = new_path;
+                    editor_ids.push(*editor_id);
+                }
+            }
+        },
+        DockItem::Splitter {first, last, ..} => {
+            rename_file_editor_panels(first, from, to, editor_ids);
+            rename_file_editor_panels(last, from, to, editor_ids);
+        }
+    }
+}
 
 fn path_file_name(path: &str) -> String {
     if let Some(pos) = path.rfind('/') {
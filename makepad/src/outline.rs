@@ -0,0 +1,323 @@
+use render::*;
+use widget::*;
+use editor::*;
+use std::collections::HashSet;
+
+// The symbols of the buffer that had the focus last, nested the way their blocks are. It keeps
+// showing that buffer while the focus is on the outline itself, and marks and scrolls to the
+// symbol the cursor is in. Typing filters on the names, a symbol that matches keeps its
+// parents in the list so it is clear where it lives.
+
+#[derive(Clone)]
+pub struct Outline {
+    pub view: View<ScrollBar>,
+    pub text: Text,
+    pub item_bg: Quad,
+    pub row_height: f32,
+    pub indent_width: f32,
+    pub name_color: Color,
+    pub kind_color: Color,
+    pub filter_color: Color,
+    // the file and the editor followed, with where its cursor is
+    pub path: Option<String>,
+    pub editor_id: u64,
+    pub cursor: usize,
+    pub filter: String,
+    pub _mutation_id: u64,
+    // folded symbols by their names and those of their parents, so edits above dont unfold them
+    pub _collapsed: HashSet<String>,
+    // the symbols of path as of token_chunks_id, with what every row needs of the tree
+    pub _symbols_of: Option<(String, u64)>,
+    pub _symbols: Vec<TextSymbol>,
+    pub _parents: Vec<Option<usize>>,
+    pub _has_children: Vec<bool>,
+    pub _keys: Vec<String>,
+    pub _row_areas: Vec<(usize, Area)>,
+    pub _view_area: Area,
+    // the symbol last scrolled into view
+    pub _scrolled_to: Option<usize>
+}
+
+#[derive(Clone)]
+pub enum OutlineEvent {
+    JumpTo {path: String, offset: usize},
+    None,
+}
+
+impl Outline {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            item_bg: Quad::style(cx),
+            text: Text {
+                wrapping: Wrapping::None,
+                ..Text::style(cx)
+            },
+            view: View {
+                scroll_h: Some(ScrollBar::style(cx)),
+                scroll_v: Some(ScrollBar {
+                    smoothing: Some(0.15),
+                    ..ScrollBar::style(cx)
+                }),
+                ..View::style(cx)
+            },
+            row_height: 20.0,
+            indent_width: 12.0,
+            name_color: color("#bbb"),
+            kind_color: color("#777"),
+            filter_color: color("#fc0"),
+            path: None,
+            editor_id: 0,
+            cursor: 0,
+            filter: String::new(),
+            _mutation_id: 0,
+            _collapsed: HashSet::new(),
+            _symbols_of: None,
+            _symbols: Vec::new(),
+            _parents: Vec::new(),
+            _has_children: Vec::new(),
+            _keys: Vec::new(),
+            _row_areas: Vec::new(),
+            _view_area: Area::Empty,
+            _scrolled_to: None
+        }
+    }
+    
+    // called with the editor that has the key focus, redraws when the cursor or the text moved
+    pub fn set_target(&mut self, cx: &mut Cx, path: &str, editor_id: u64, cursor: usize, mutation_id: u64) {
+        if self.path.as_ref().map_or(false, | old | old == path) && self.editor_id == editor_id && self.cursor == cursor && self._mutation_id == mutation_id {
+            return
+        }
+        if self.path.as_ref().map_or(true, | old | old != path) {
+            self._scrolled_to = None;
+        }
+        self.path = Some(path.to_string());
+        self.editor_id = editor_id;
+        self.cursor = cursor;
+        self._mutation_id = mutation_id;
+        self.view.redraw_view_area(cx);
+    }
+    
+    pub fn set_key_focus(&mut self, cx: &mut Cx) {
+        if self._view_area != Area::Empty {
+            cx.set_key_focus(self._view_area);
+        }
+    }
+    
+    pub fn handle_outline(&mut self, cx: &mut Cx, event: &mut Event) -> OutlineEvent {
+        self.view.handle_scroll_bars(cx, event);
+        let mut row_down = None;
+        for (index, area) in &self._row_areas {
+            match event.hits(cx, *area, HitOpt::default()) {
+                Event::FingerDown(fe) => {
+                    cx.set_down_mouse_cursor(MouseCursor::Hand);
+                    row_down = Some((*index, fe.rel.x));
+                },
+                Event::FingerHover(_fe) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                },
+                _ => ()
+            }
+        }
+        if let Some((index, x)) = row_down {
+            // the + or - in front of the name folds, the rest of the row jumps
+            if self._has_children[index] && x < self.toggle_end(self._symbols[index].depth) {
+                let key = self._keys[index].clone();
+                if !self._collapsed.remove(&key) {
+                    self._collapsed.insert(key);
+                }
+                self.view.redraw_view_area(cx);
+                return OutlineEvent::None
+            }
+            return self.jump_to(index)
+        }
+        match event.hits(cx, self._view_area, HitOpt::default()) {
+            Event::FingerDown(_fe) => {
+                cx.set_key_focus(self._view_area);
+                self.view.redraw_view_area(cx);
+            },
+            Event::KeyDown(ke) => {
+                match ke.key_code {
+                    KeyCode::Return => if let Some(index) = self.visible_rows().into_iter().find( | index | self.matches_filter(*index)) {
+                        return self.jump_to(index)
+                    },
+                    KeyCode::Escape => self.filter.truncate(0),
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                    },
+                    _ => ()
+                }
+                self._scrolled_to = None;
+                self.view.redraw_view_area(cx);
+            },
+            Event::TextInput(te) => {
+                if te.replace_last {
+                    self.filter.pop();
+                }
+                self.filter.extend(te.input.chars().filter( | c | !c.is_control()));
+                self._scrolled_to = None;
+                self.view.redraw_view_area(cx);
+            },
+            Event::KeyFocusLost(_) => {
+                self.view.redraw_view_area(cx);
+            },
+            _ => ()
+        }
+        OutlineEvent::None
+    }
+    
+    fn jump_to(&mut self, index: usize) -> OutlineEvent {
+        match &self.path {
+            Some(path) => OutlineEvent::JumpTo {path: path.clone(), offset: self._symbols[index].offset},
+            None => OutlineEvent::None
+        }
+    }
+    
+    fn toggle_end(&self, depth: usize) -> f32 {
+        4. + (depth + 1) as f32 * self.indent_width
+    }
+    
+    fn matches_filter(&self, index: usize) -> bool {
+        self.filter.len() > 0 && self._symbols[index].name.to_lowercase().contains(&self.filter.to_lowercase())
+    }
+    
+    // without a filter everything not folded away, with one the matches and their parents
+    fn visible_rows(&self) -> Vec<usize> {
+        let symbols = &self._symbols;
+        if self.filter.len() > 0 {
+            let mut shown = vec![false; symbols.len()];
+            for index in 0..symbols.len() {
+                if self.matches_filter(index) {
+                    let mut next = Some(index);
+                    while let Some(index) = next.filter( | index | !shown[*index]) {
+                        shown[index] = true;
+                        next = self._parents[index];
+                    }
+                }
+            }
+            return (0..symbols.len()).filter( | index | shown[*index]).collect()
+        }
+        let mut rows = Vec::new();
+        let mut folded_depth = None;
+        for (index, symbol) in symbols.iter().enumerate() {
+            if folded_depth.map_or(false, | depth | symbol.depth > depth) {
+                continue;
+            }
+            folded_depth = None;
+            rows.push(index);
+            if self._has_children[index] && self._collapsed.contains(&self._keys[index]) {
+                folded_depth = Some(symbol.depth);
+            }
+        }
+        rows
+    }
+    
+    // the tokens of a file still loading or without a language yet arent there
+    fn update_symbols(&mut self, text_buffer: Option<&TextBuffer>) {
+        let symbols_of = match (&self.path, text_buffer) {
+            (Some(path), Some(text_buffer)) if text_buffer.token_chunks_id == text_buffer.mutation_id => Some((path.clone(), text_buffer.token_chunks_id)),
+            _ => None
+        };
+        if symbols_of.is_some() && symbols_of == self._symbols_of {
+            return
+        }
+        self._symbols = match (&symbols_of, text_buffer) {
+            (Some(_), Some(text_buffer)) => text_symbols(text_buffer),
+            _ => Vec::new()
+        };
+        self._symbols_of = symbols_of;
+        // the symbols come in tree order, so the open ones are a stack
+        let symbols = &self._symbols;
+        self._parents = Vec::with_capacity(symbols.len());
+        self._keys = Vec::with_capacity(symbols.len());
+        let mut open: Vec<usize> = Vec::new();
+        for (index, symbol) in symbols.iter().enumerate() {
+            while open.last().map_or(false, | last | symbols[*last].depth >= symbol.depth) {
+                open.pop();
+            }
+            let parent = open.last().cloned();
+            let key = format!("{} {}", symbol.kind.label(), symbol.name);
+            self._keys.push(match parent {
+                Some(parent) => format!("{}/{}", self._keys[parent], key),
+                None => key
+            });
+            self._parents.push(parent);
+            open.push(index);
+        }
+        self._has_children = (0..symbols.len()).map( | index | {
+            symbols.get(index + 1).map_or(false, | next | next.depth > symbols[index].depth)
+        }).collect();
+    }
+    
+    pub fn draw_outline(&mut self, cx: &mut Cx, text_buffer: Option<&TextBuffer>) {
+        self.update_symbols(text_buffer);
+        if let Err(_) = self.view.begin_view(cx, Layout::default()) {
+            return
+        }
+        let view_area = self.view.get_view_area(cx);
+        cx.update_area_refs(self._view_area, view_area);
+        self._view_area = view_area;
+        self._row_areas.truncate(0);
+        
+        let bg_even = cx.color("bg_selected");
+        let bg_odd = cx.color("bg_odd");
+        let bg_current = cx.color("bg_marked");
+        let row_layout = Layout {
+            width: Bounds::Fill,
+            height: Bounds::Fix(self.row_height),
+            padding: Padding {l: 4., t: 5., b: 0., r: 4.},
+            ..Default::default()
+        };
+        
+        self.item_bg.color = bg_odd;
+        let bg_inst = self.item_bg.begin_quad(cx, &row_layout);
+        if self.filter.len() > 0 || cx.has_key_focus(self._view_area) {
+            self.text.color = self.filter_color;
+            self.text.draw_text(cx, &format!("{}_", self.filter));
+        }
+        else {
+            self.text.color = self.kind_color;
+            let file_name = self.path.as_ref().map( | path | path.rsplit('/').next().unwrap_or("").to_string()).unwrap_or_default();
+            self.text.draw_text(cx, &format!("Type to filter {}", file_name));
+        }
+        self.item_bg.end_quad(cx, &bg_inst);
+        cx.turtle_new_line();
+        
+        let rows = self.visible_rows();
+        let mut is_row = vec![false; self._symbols.len()];
+        for index in &rows {
+            is_row[*index] = true;
+        }
+        // the cursor can be in something folded away, then its the fold that gets marked
+        let mut current = symbol_at(&self._symbols, self.cursor);
+        while let Some(index) = current.filter( | index | !is_row[*index]) {
+            current = self._parents[index];
+        }
+        let mut current_row = None;
+        for (row, index) in rows.iter().enumerate() {
+            let symbol = &self._symbols[*index];
+            self.item_bg.color = if current == Some(*index) {
+                current_row = Some(row);
+                bg_current
+            } else if row & 1 == 0 {bg_even} else {bg_odd};
+            let bg_inst = self.item_bg.begin_quad(cx, &row_layout);
+            cx.walk_turtle(Bounds::Fix(symbol.depth as f32 * self.indent_width), Bounds::Fix(0.), Margin::zero(), None);
+            self.text.color = self.kind_color;
+            let toggle = if !self._has_children[*index] {" "}
+            else if self.filter.len() == 0 && self._collapsed.contains(&self._keys[*index]) {"+"} else {"-"};
+            self.text.draw_text(cx, &format!("{} {} ", toggle, symbol.kind.label()));
+            self.text.color = if self.matches_filter(*index) {self.filter_color} else {self.name_color};
+            self.text.draw_text(cx, &symbol.name);
+            let area = self.item_bg.end_quad(cx, &bg_inst);
+            self._row_areas.push((*index, area));
+            cx.turtle_new_line();
+        }
+        
+        // follow the cursor, but leave the scrolling alone while it stays in the same symbol
+        if let Some(row) = current_row.filter( | _ | current != self._scrolled_to) {
+            let y = (row + 1) as f32 * self.row_height;
+            self.view.scroll_into_view(cx, Rect {x: 0., y: y, w: 0., h: self.row_height});
+        }
+        self._scrolled_to = current;
+        self.view.end_view(cx);
+    }
+}
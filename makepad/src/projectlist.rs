@@ -0,0 +1,148 @@
+use render::*;
+use widget::*;
+use crate::workspace::*;
+
+use std::sync::mpsc;
+
+// The recent projects and an Open Folder row on top. Picking a folder asks the desktop on a
+// thread since the dialog blocks, either way the project opens in a makepad of its own.
+
+pub struct ProjectList {
+    pub view: View<ScrollBar>,
+    pub text: Text,
+    pub item_bg: Quad,
+    pub row_height: f32,
+    pub name_color: Color,
+    pub path_color: Color,
+    pub current_color: Color,
+    pub _pick_signal: Signal,
+    pub _pick_rx: Option<mpsc::Receiver<Result<Option<String>, String>>>,
+    // 0 is Open Folder, the projects follow
+    pub _row_areas: Vec<(usize, Area)>
+}
+
+#[derive(Clone)]
+pub enum ProjectListEvent {
+    Open(Workspace),
+    None,
+}
+
+impl ProjectList {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            item_bg: Quad::style(cx),
+            text: Text {
+                wrapping: Wrapping::None,
+                ..Text::style(cx)
+            },
+            view: View {
+                scroll_h: Some(ScrollBar::style(cx)),
+                scroll_v: Some(ScrollBar {
+                    smoothing: Some(0.15),
+                    ..ScrollBar::style(cx)
+                }),
+                ..View::style(cx)
+            },
+            row_height: 20.0,
+            name_color: color("#bbb"),
+            path_color: color("#777"),
+            current_color: color("#fc0"),
+            _pick_signal: Signal::empty(),
+            _pick_rx: None,
+            _row_areas: Vec::new()
+        }
+    }
+    
+    pub fn init(&mut self, cx: &mut Cx) {
+        self._pick_signal = cx.new_signal();
+    }
+    
+    pub fn pick_folder(&mut self) {
+        if self._pick_rx.is_some() {
+            return
+        }
+        let (tx, rx) = mpsc::channel();
+        let signal = self._pick_signal;
+        std::thread::spawn(move || {
+            let _ = tx.send(pick_folder());
+            Cx::post_signal(signal, 0);
+        });
+        self._pick_rx = Some(rx);
+    }
+    
+    // the folder the dialog came back with, also when the list isnt showing
+    pub fn handle_folder_pick(&mut self, event: &mut Event) -> Option<Result<Workspace, String>> {
+        if let Event::Signal(se) = event {
+            if self._pick_signal.is_signal(se) {
+                let folder = match self._pick_rx.take() ?.try_recv().ok() ? {
+                    Ok(folder) => folder ?,
+                    Err(err) => return Some(Err(err))
+                };
+                let mut errors = Vec::new();
+                return Some(Workspace::from_dirs(&[folder], &mut errors).ok_or_else( || errors.join("\n")))
+            }
+        }
+        None
+    }
+    
+    pub fn handle_project_list(&mut self, cx: &mut Cx, event: &mut Event, recent_projects: &RecentProjects) -> ProjectListEvent {
+        self.view.handle_scroll_bars(cx, event);
+        let mut row_to_open = None;
+        for (row, area) in &self._row_areas {
+            match event.hits(cx, *area, HitOpt::default()) {
+                Event::FingerDown(_fe) => {
+                    cx.set_down_mouse_cursor(MouseCursor::Hand);
+                    row_to_open = Some(*row);
+                },
+                Event::FingerHover(_fe) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                },
+                _ => ()
+            }
+        }
+        match row_to_open {
+            Some(0) => self.pick_folder(),
+            Some(row) => if let Some(workspace) = recent_projects.projects.get(row - 1) {
+                return ProjectListEvent::Open(workspace.clone())
+            },
+            None => ()
+        }
+        ProjectListEvent::None
+    }
+    
+    pub fn draw_project_list(&mut self, cx: &mut Cx, recent_projects: &RecentProjects, current: &Workspace) {
+        if let Err(_) = self.view.begin_view(cx, Layout::default()) {
+            return
+        }
+        self._row_areas.truncate(0);
+        let bg_even = cx.color("bg_selected");
+        let bg_odd = cx.color("bg_odd");
+        let row_layout = Layout {
+            width: Bounds::Fill,
+            height: Bounds::Fix(self.row_height),
+            padding: Padding {l: 4., t: 5., b: 0., r: 4.},
+            ..Default::default()
+        };
+        
+        self.item_bg.color = bg_odd;
+        let bg_inst = self.item_bg.begin_quad(cx, &row_layout);
+        self.text.color = self.name_color;
+        self.text.draw_text(cx, if self._pick_rx.is_some() {"Open Folder... (waiting for the dialog)"} else {"Open Folder..."});
+        let area = self.item_bg.end_quad(cx, &bg_inst);
+        self._row_areas.push((0, area));
+        cx.turtle_new_line();
+        
+        for (index, workspace) in recent_projects.projects.iter().enumerate() {
+            self.item_bg.color = if index & 1 == 0 {bg_even} else {bg_odd};
+            let bg_inst = self.item_bg.begin_quad(cx, &row_layout);
+            self.text.color = if workspace == current {self.current_color} else {self.name_color};
+            self.text.draw_text(cx, &format!("{} ", workspace.name()));
+            self.text.color = self.path_color;
+            self.text.draw_text(cx, &workspace.roots.join(" "));
+            let area = self.item_bg.end_quad(cx, &bg_inst);
+            self._row_areas.push((index + 1, area));
+            cx.turtle_new_line();
+        }
+        self.view.end_view(cx);
+    }
+}
@@ -0,0 +1,438 @@
+use render::*;
+use widget::*;
+use editor::*;
+
+use std::sync::mpsc;
+use std::collections::HashSet;
+
+// Project wide search, walks the files under TextBuffers.root_path on a thread and lists
+// the hits like the rust compiler messages. Open buffers are searched in memory instead
+// so unsaved edits are found too.
+
+pub struct ProjectSearch {
+    pub view: View<ScrollBar>,
+    pub text: Text,
+    pub item_bg: Quad,
+    pub code_icon: CodeIcon,
+    pub row_height: f32,
+    pub path_color: Color,
+    pub line_color: Color,
+    pub max_hits: usize,
+    pub search: TextSearch,
+    pub _signal: Signal,
+    pub _rx: Option<mpsc::Receiver<ProjectSearchResult>>,
+    pub _thread: Option<std::thread::JoinHandle<()>>,
+    pub _items: Vec<ProjectSearchItem>,
+    pub _files_with_hits: usize,
+    pub _error: Option<String>,
+    pub _search_done: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProjectSearchHit {
+    pub path: String,
+    pub row: usize,
+    pub col: usize,
+    pub start: usize,
+    pub end: usize,
+    pub line: String
+}
+
+pub enum ProjectSearchResult {
+    Hits(Vec<ProjectSearchHit>),
+    Done
+}
+
+#[derive(Clone)]
+pub struct ProjectSearchItem {
+    animator: Animator,
+    hit: ProjectSearchHit,
+    is_selected: bool
+}
+
+#[derive(Clone)]
+pub enum ProjectSearchEvent {
+    SelectHit {path: String},
+    None,
+}
+
+const SIGNAL_SEARCH_RESULTS: usize = 1;
+
+impl ProjectSearch {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            item_bg: Quad::style(cx),
+            text: Text {
+                wrapping: Wrapping::Word,
+                ..Text::style(cx)
+            },
+            view: View {
+                scroll_h: Some(ScrollBar::style(cx)),
+                scroll_v: Some(ScrollBar {
+                    smoothing: Some(0.15),
+                    ..ScrollBar::style(cx)
+                }),
+                ..View::style(cx)
+            },
+            code_icon: CodeIcon {
+                ..CodeIcon::style(cx)
+            },
+            path_color: color("#999"),
+            line_color: color("#bbb"),
+            row_height: 20.0,
+            max_hits: 5000,
+            search: TextSearch::default(),
+            _signal: Signal::empty(),
+            _rx: None,
+            _thread: None,
+            _items: Vec::new(),
+            _files_with_hits: 0,
+            _error: None,
+            _search_done: true
+        }
+    }
+    
+    pub fn init(&mut self, cx: &mut Cx) {
+        self._signal = cx.new_signal();
+    }
+    
+    pub fn get_default_anim(cx: &Cx, counter: usize, marked: bool) -> Anim {
+        Anim::new(Play::Chain {duration: 0.01}, vec![
+            Track::color("bg.color", Ease::Lin, vec![(1.0, if marked {cx.color("bg_marked")} else if counter & 1 == 0 {cx.color("bg_selected")}else {cx.color("bg_odd")})])
+        ])
+    }
+    
+    pub fn get_over_anim(cx: &Cx, counter: usize, marked: bool) -> Anim {
+        let over_color = if marked {cx.color("bg_marked_over")} else if counter & 1 == 0 {cx.color("bg_selected_over")}else {cx.color("bg_odd_over")};
+        Anim::new(Play::Cut {duration: 0.02}, vec![
+            Track::color("bg.color", Ease::Lin, vec![
+                (0., over_color),
+                (1., over_color)
+            ])
+        ])
+    }
+    
+    pub fn start_search(&mut self, cx: &mut Cx, search: TextSearch, text_buffers: &TextBuffers) {
+        // dropping the receiver makes a running search thread stop at its next send
+        self._rx = None;
+        self._thread = None;
+        self._items.truncate(0);
+        self._files_with_hits = 0;
+        self._error = None;
+        self._search_done = false;
+        self.search = search.clone();
+        self.view.redraw_view_area(cx);
+        
+        if let Err(err) = search.compile() {
+            self._error = Some(err);
+            self._search_done = true;
+            return
+        }
+        
+        let mut skip_paths = HashSet::new();
+        let mut open_hits = Vec::new();
+        for (path, text_buffer) in &text_buffers.storage {
+            if text_buffer.load_file_read.is_pending() {
+                continue
+            }
+            skip_paths.insert(path.clone());
+            open_hits.push(search_text_hits(&search, path, &text_buffer.get_as_lf_string()));
+        }
+        open_hits.sort_by( | a, b | a.first().map( | h | &h.path).cmp(&b.first().map( | h | &h.path)));
+        for hits in open_hits {
+            self.add_hits(cx, hits);
+        }
+        
+        let (tx, rx) = mpsc::channel();
+        let signal = self._signal;
+        let root_path = text_buffers.root_path.clone();
+        let thread = std::thread::spawn(move || {
+            let mut files = Vec::new();
+            collect_files(&root_path, "", &mut files);
+            files.sort();
+            for path in files {
+                if skip_paths.contains(&path) {
+                    continue
+                }
+                if let Some(text) = read_text_file(&format!("{}{}", root_path, path)) {
+                    let hits = search_text_hits(&search, &path, &text);
+                    if hits.len() > 0 {
+                        if tx.send(ProjectSearchResult::Hits(hits)).is_err() {
+                            return
+                        }
+                        Cx::post_signal(signal, SIGNAL_SEARCH_RESULTS);
+                    }
+                }
+            }
+            let _ = tx.send(ProjectSearchResult::Done);
+            Cx::post_signal(signal, SIGNAL_SEARCH_RESULTS);
+        });
+        self._rx = Some(rx);
+        self._thread = Some(thread);
+    }
+    
+    fn add_hits(&mut self, cx: &mut Cx, hits: Vec<ProjectSearchHit>) {
+        if hits.len() == 0 {
+            return
+        }
+        self._files_with_hits += 1;
+        for hit in hits {
+            if self._items.len() >= self.max_hits {
+                return
+            }
+            self._items.push(ProjectSearchItem {
+                animator: Animator::new(Self::get_default_anim(cx, self._items.len(), false)),
+                hit: hit,
+                is_selected: false
+            });
+        }
+    }
+    
+    pub fn handle_project_search(&mut self, cx: &mut Cx, event: &mut Event, text_buffers: &mut TextBuffers, keymap: &Keymap) -> ProjectSearchEvent {
+        self.view.handle_scroll_bars(cx, event);
+        
+        let mut item_to_select = None;
+        
+        match event {
+            Event::Signal(se) => if self._signal.is_signal(se) {
+                let mut results = Vec::new();
+                if let Some(rx) = &self._rx {
+                    while let Ok(result) = rx.try_recv() {
+                        results.push(result);
+                    }
+                }
+                for result in results {
+                    match result {
+                        ProjectSearchResult::Hits(hits) => self.add_hits(cx, hits),
+                        ProjectSearchResult::Done => self._search_done = true
+                    }
+                }
+                self.view.redraw_view_area(cx);
+            },
+            Event::KeyDown(ke) => match keymap.single_command(ke) {
+                Some(command @ Command::NextSearchHit) | Some(command @ Command::PrevSearchHit) => {
+                    item_to_select = self.next_item_index(command == Command::PrevSearchHit);
+                },
+                _ => ()
+            },
+            _ => ()
+        }
+        
+        for (counter, item) in self._items.iter_mut().enumerate() {
+            match event.hits(cx, item.animator.area, HitOpt::default()) {
+                Event::Animate(ae) => {
+                    item.animator.write_area(cx, item.animator.area, "bg.", ae.time);
+                },
+                Event::FingerDown(_fe) => {
+                    cx.set_down_mouse_cursor(MouseCursor::Hand);
+                    item_to_select = Some(counter);
+                },
+                Event::FingerHover(fe) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                    match fe.hover_state {
+                        HoverState::In => {
+                            item.animator.play_anim(cx, Self::get_over_anim(cx, counter, item.is_selected));
+                        },
+                        HoverState::Out => {
+                            item.animator.play_anim(cx, Self::get_default_anim(cx, counter, item.is_selected));
+                        },
+                        _ => ()
+                    }
+                },
+                _ => ()
+            }
+        }
+        
+        if let Some(item_to_select) = item_to_select {
+            return self.select_item(cx, item_to_select, text_buffers)
+        }
+        ProjectSearchEvent::None
+    }
+    
+    // the hit after or before the selected one, going round at the ends
+    fn next_item_index(&self, back: bool) -> Option<usize> {
+        if self._items.len() == 0 {
+            return None
+        }
+        let selected = self._items.iter().position( | item | item.is_selected);
+        Some(match selected {
+            Some(index) => if back {
+                if index > 0 {index - 1} else {self._items.len() - 1}
+            }
+            else {
+                (index + 1) % self._items.len()
+            },
+            None => if back {self._items.len() - 1} else {0}
+        })
+    }
+    
+    // what NextSearchHit and PrevSearchHit do when the search panel doesnt have the keys
+    pub fn select_next_hit(&mut self, cx: &mut Cx, back: bool, text_buffers: &mut TextBuffers) -> ProjectSearchEvent {
+        match self.next_item_index(back) {
+            Some(index) => self.select_item(cx, index, text_buffers),
+            None => ProjectSearchEvent::None
+        }
+    }
+    
+    fn select_item(&mut self, cx: &mut Cx, item_to_select: usize, text_buffers: &mut TextBuffers) -> ProjectSearchEvent {
+        for (counter, item) in self._items.iter_mut().enumerate() {
+            if counter != item_to_select && item.is_selected {
+                item.is_selected = false;
+                item.animator.play_anim(cx, Self::get_default_anim(cx, counter, false));
+            }
+        }
+        let item = &mut self._items[item_to_select];
+        item.is_selected = true;
+        item.animator.play_anim(cx, Self::get_over_anim(cx, item_to_select, true));
+        
+        let text_buffer = text_buffers.from_path(cx, &item.hit.path);
+        text_buffer.messages.jump_to_offset = item.hit.start;
+        cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_JUMP_TO_OFFSET);
+        ProjectSearchEvent::SelectHit {path: item.hit.path.clone()}
+    }
+    
+    pub fn draw_project_search(&mut self, cx: &mut Cx) {
+        if let Err(_) = self.view.begin_view(cx, Layout::default()) {
+            return
+        }
+        
+        let bg_even = cx.color("bg_selected");
+        let bg_odd = cx.color("bg_odd");
+        
+        // status line on top
+        self.item_bg.color = bg_odd;
+        let bg_inst = self.item_bg.begin_quad(cx, &Layout {
+            width: Bounds::Fill,
+            height: Bounds::Compute,
+            padding: Padding {l: 2., t: 3., b: 2., r: 0.},
+            ..Default::default()
+        });
+        self.text.color = self.path_color;
+        if let Some(error) = &self._error {
+            self.code_icon.draw_icon_walk(cx, CodeIconType::Error);
+            self.text.draw_text(cx, error);
+        }
+        else if self.search.query.len() == 0 {
+            self.code_icon.draw_icon_walk(cx, CodeIconType::Ok);
+            self.text.draw_text(cx, "Press Ctrl+Shift+F in an editor to search the project");
+        }
+        else {
+            self.code_icon.draw_icon_walk(cx, if self._search_done {CodeIconType::Ok} else {CodeIconType::Wait});
+            let more = if self._items.len() >= self.max_hits {"+"} else {""};
+            self.text.draw_text(cx, &format!(
+                "{} - {}{} hits in {} files{}",
+                self.search.query,
+                self._items.len(),
+                more,
+                self._files_with_hits,
+                if self._search_done {""} else {" (searching)"}
+            ));
+        }
+        self.item_bg.end_quad(cx, &bg_inst);
+        cx.turtle_new_line();
+        
+        let mut counter = 0;
+        for item in &mut self._items {
+            self.item_bg.color = item.animator.last_color("bg.color");
+            
+            let bg_inst = self.item_bg.begin_quad(cx, &Layout {
+                width: Bounds::Fill,
+                height: Bounds::Compute,
+                padding: Padding {l: 2., t: 3., b: 2., r: 0.},
+                line_wrap: LineWrap::NewLine,
+                ..Default::default()
+            });
+            
+            self.text.color = self.path_color;
+            self.text.draw_text(cx, &format!("{}:{}:{} - ", item.hit.path, item.hit.row + 1, item.hit.col + 1));
+            let walk = cx.get_rel_turtle_walk();
+            cx.set_turtle_padding(Padding {l: walk.x, t: 3., b: 2., r: 0.});
+            self.text.color = self.line_color;
+            self.text.draw_text(cx, &item.hit.line);
+            
+            let bg_area = self.item_bg.end_quad(cx, &bg_inst);
+            item.animator.update_area_refs(cx, bg_area);
+            
+            cx.turtle_new_line();
+            counter += 1;
+        }
+        
+        // draw filler nodes
+        let view_total = cx.get_turtle_bounds();
+        let rect_now = cx.get_turtle_rect();
+        let mut y = view_total.y;
+        while y < rect_now.h {
+            self.item_bg.color = if counter & 1 == 0 {bg_even}else {bg_odd};
+            self.item_bg.draw_quad_walk(cx, Bounds::Fill, Bounds::Fix((rect_now.h - y).min(self.row_height)), Margin::zero());
+            cx.turtle_new_line();
+            y += self.row_height;
+            counter += 1;
+        }
+        
+        self.view.end_view(cx);
+    }
+}
+
+// relative paths of everything under root, skipping dotfiles and build output
+fn collect_files(root_path: &str, rel_path: &str, files: &mut Vec<String>) {
+    let entries = if let Ok(entries) = std::fs::read_dir(format!("{}{}", root_path, rel_path)) {entries} else {return};
+    for entry in entries {
+        let entry = if let Ok(entry) = entry {entry} else {continue};
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || name == "target" {
+            continue
+        }
+        let path = format!("{}{}", rel_path, name);
+        if let Ok(file_type) = entry.file_type() {
+            if file_type.is_dir() {
+                collect_files(root_path, &format!("{}/", path), files);
+            }
+            else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+}
+
+fn read_text_file(path: &str) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+    // binaries and huge files are not worth searching
+    if data.len() > 4 * 1024 * 1024 || data.contains(&0) {
+        return None
+    }
+    let text = String::from_utf8(data).ok()?;
+    Some(text.replace("\r\n", "\n"))
+}
+
+pub fn search_text_hits(search: &TextSearch, path: &str, text: &str) -> Vec<ProjectSearchHit> {
+    let matches = if let Ok(matches) = search.find_all(text) {matches} else {return Vec::new()};
+    let mut hits = Vec::new();
+    let mut lines = text.split('\n');
+    let mut line = lines.next().unwrap_or("");
+    let mut line_len = line.chars().count();
+    let mut line_start = 0;
+    let mut row = 0;
+    for m in matches {
+        // matches are sorted so we only ever move forward to the line the match starts on
+        while m.start > line_start + line_len {
+            if let Some(next) = lines.next() {
+                line_start += line_len + 1;
+                line = next;
+                line_len = line.chars().count();
+                row += 1;
+            }
+            else {
+                break
+            }
+        }
+        hits.push(ProjectSearchHit {
+            path: path.to_string(),
+            row: row,
+            col: m.start - line_start,
+            start: m.start,
+            end: m.end,
+            line: line.trim().chars().take(200).collect()
+        });
+    }
+    hits
+}
@@ -0,0 +1,100 @@
+use widget::*;
+use editor::*;
+
+// What the palette lists goes by how the query starts: the files of the workspace, > for the
+// commands, : for a line and @ for a symbol of the buffer that had the focus when it opened.
+// ~ lists the branches redo can go into there, the RedoBranches command opens it.
+
+pub const PALETTE_PLACEHOLDER: &str = "Open a file, > for commands, : for a line, @ for a symbol";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaletteAction {
+    OpenFile {path: String},
+    RunCommand(Command),
+    // in the buffer the palette was opened from
+    GotoOffset {offset: usize},
+    RedoBranch {child: usize}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaletteMode {
+    Files,
+    Commands,
+    Line,
+    Symbol,
+    RedoBranches
+}
+
+impl PaletteMode {
+    // the mode with what is left to filter on
+    pub fn from_query(query: &str) -> (PaletteMode, &str) {
+        let mode = match query.chars().next() {
+            Some('>') => PaletteMode::Commands,
+            Some(':') => PaletteMode::Line,
+            Some('@') => PaletteMode::Symbol,
+            Some('~') => PaletteMode::RedoBranches,
+            _ => return (PaletteMode::Files, query.trim())
+        };
+        (mode, query[1..].trim())
+    }
+    
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            PaletteMode::Files => "",
+            PaletteMode::Commands => ">",
+            PaletteMode::Line => ":",
+            PaletteMode::Symbol => "@",
+            PaletteMode::RedoBranches => "~"
+        }
+    }
+}
+
+fn palette_item(label: String, detail: String, action: PaletteAction) -> PaletteItem<PaletteAction> {
+    PaletteItem {label: label, detail: detail, item: action}
+}
+
+pub fn file_palette_items(paths: &[String]) -> Vec<PaletteItem<PaletteAction>> {
+    paths.iter().map( | path | palette_item(path.clone(), String::new(), PaletteAction::OpenFile {path: path.clone()})).collect()
+}
+
+pub fn command_palette_items(keymap: &Keymap) -> Vec<PaletteItem<PaletteAction>> {
+    ALL_COMMANDS.iter().map( | command | {
+        palette_item(command.label(), keymap.keys_for(*command).unwrap_or_default(), PaletteAction::RunCommand(*command))
+    }).collect()
+}
+
+// one item for the line typed, past the end goes to the last one
+pub fn line_palette_items(filter: &str, text_buffer: &TextBuffer) -> Vec<PaletteItem<PaletteAction>> {
+    let line = if let Ok(line) = filter.parse::<usize>() {line} else {return Vec::new()};
+    let line_count = text_buffer.get_line_count();
+    let row = line.max(1).min(line_count.max(1)) - 1;
+    let offset = text_buffer.text_pos_to_offset(TextPos {row: row, col: 0});
+    vec![palette_item(format!("Go to line {}", row + 1), format!("of {}", line_count), PaletteAction::GotoOffset {offset: offset})]
+}
+
+pub fn symbol_palette_items(text_buffer: &TextBuffer) -> Vec<PaletteItem<PaletteAction>> {
+    text_symbols(text_buffer).into_iter().map( | symbol | {
+        let row = text_buffer.offset_to_text_pos(symbol.offset).row;
+        palette_item(symbol.name, format!("{} line {}", symbol.kind.label(), row + 1), PaletteAction::GotoOffset {offset: symbol.offset})
+    }).collect()
+}
+
+// what the first edit of every branch puts in, the one redo takes now is marked
+pub fn redo_branch_palette_items(text_buffer: &TextBuffer) -> Vec<PaletteItem<PaletteAction>> {
+    let undo_tree = &text_buffer.undo_tree;
+    let redo_child = undo_tree.nodes[undo_tree.current].redo_child;
+    undo_tree.redo_branches().into_iter().enumerate().filter_map( | (index, child) | {
+        let op = undo_tree.redo_branch_op(child) ?;
+        let inserted: String = op.lines.iter().map( | line | line.iter().collect::<String>()).collect::<Vec<String>>().join(" ");
+        let inserted = inserted.trim();
+        let edit = if inserted.len() == 0 {
+            format!("delete {} chars", op.len)
+        }
+        else {
+            format!("\"{}\"", inserted.chars().take(40).collect::<String>())
+        };
+        let row = text_buffer.offset_to_text_pos(op.start).row;
+        let detail = format!("line {}{}", row + 1, if redo_child == Some(child) {", redo"} else {""});
+        Some(palette_item(format!("Branch {}: {}", index + 1, edit), detail, PaletteAction::RedoBranch {child: child}))
+    }).collect()
+}
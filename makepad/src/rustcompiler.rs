@@ -4,7 +4,10 @@ use editor::*;
 
 use std::io::Read;
 use std::sync::mpsc;
-use std::process::{Child};
+use std::collections::HashMap;
+use std::process::{Child, Stdio};
+
+use crate::workspace::*;
 
 use serde_json::{Result};
 use serde::*;
@@ -17,8 +20,15 @@ pub struct RustCompiler {
     pub item_bg: Quad,
     pub code_icon: CodeIcon,
     pub row_height: f32,
+    pub fix_bg: Quad,
     pub path_color: Color,
     pub message_color: Color,
+    pub fix_color: Color,
+    pub fix_bg_color: Color,
+    // the cargo workspace the first root is in, None when it isnt a rust project
+    pub cargo: Option<CargoWorkspace>,
+    pub root_path: String,
+    pub _cargo_rx: Option<mpsc::Receiver<std::result::Result<CargoWorkspace, String>>>,
     pub _check_signal: Signal,
     
     pub _check_child: Option<Child>,
@@ -45,6 +55,7 @@ pub struct RustCompiler {
 const SIGNAL_RUST_CHECKER: usize = 1;
 const SIGNAL_BUILD_COMPLETE: usize = 2;
 const SIGNAL_RUN_OUTPUT: usize = 3;
+const SIGNAL_CARGO_WORKSPACE: usize = 4;
 
 #[derive(PartialEq, Debug)]
 pub enum BuildStage {
@@ -59,17 +70,22 @@ pub struct RustDrawMessage {
     path: String,
     body: String,
     more_lines: Vec<String>,
+    detail: Option<String>,
     row: usize,
     col: usize,
     head: usize,
     tail: usize,
     level: TextBufferMessageLevel,
+    fixes: Vec<TextQuickFix>,
+    fix_areas: Vec<Area>,
     is_selected: bool
 }
 
 #[derive(Clone)]
 pub enum RustCompilerEvent {
     SelectMessage {path: String},
+    // save when the fix is the only change, other edits in the buffer are left for the user to save
+    FixApplied {path: String, save: bool},
     None,
 }
 
@@ -78,6 +94,7 @@ impl RustCompiler {
         Self {
             bg: Quad ::style(cx),
             item_bg: Quad::style(cx),
+            fix_bg: Quad::style(cx),
             text: Text {
                 wrapping: Wrapping::Word,
                 ..Text::style(cx)
@@ -95,7 +112,12 @@ impl RustCompiler {
             },
             path_color: color("#999"),
             message_color: color("#bbb"),
+            fix_color: color("#fc0"),
+            fix_bg_color: color("#3a3a3a"),
             row_height: 20.0,
+            cargo: None,
+            root_path: String::new(),
+            _cargo_rx: None,
             _check_signal: Signal::empty(),
             _check_child: None,
             _build_child: None,
@@ -118,7 +140,43 @@ impl RustCompiler {
     }
     pub fn init(&mut self, cx: &mut Cx, text_buffers: &mut TextBuffers) {
         self._check_signal = cx.new_signal();
+        self.root_path = text_buffers.root_path.clone();
         self.restart_rust_checker(cx, text_buffers);
+        if cx.platform_type.is_desktop() {
+            self.load_cargo_workspace();
+        }
+    }
+    
+    // cargo metadata can take a while, the checker starts when its back
+    fn load_cargo_workspace(&mut self) {
+        // not a rust project, nothing to complain about
+        if !std::path::Path::new(&self.root_path).ancestors().any( | dir | dir.join("Cargo.toml").is_file()) {
+            return
+        }
+        let (tx, rx) = mpsc::channel();
+        let signal = self._check_signal;
+        let root_path = self.root_path.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(CargoWorkspace::load(&root_path));
+            Cx::post_signal(signal, SIGNAL_CARGO_WORKSPACE);
+        });
+        self._cargo_rx = Some(rx);
+    }
+    
+    // gives back why cargo metadata failed, cargo commands do nothing without a workspace
+    pub fn handle_cargo_workspace(&mut self, cx: &mut Cx, event: &mut Event, text_buffers: &mut TextBuffers) -> Option<String> {
+        if let Event::Signal(se) = event {
+            if self._check_signal.is_signal(se) && se.value == SIGNAL_CARGO_WORKSPACE {
+                match self._cargo_rx.take().and_then( | rx | rx.try_recv().ok()) ? {
+                    Ok(cargo) => {
+                        self.cargo = Some(cargo);
+                        self.restart_rust_checker(cx, text_buffers);
+                    },
+                    Err(err) => return Some(err)
+                }
+            }
+        }
+        None
     }
     
     pub fn get_default_anim(cx: &Cx, counter: usize, marked: bool) -> Anim {
@@ -138,63 +196,57 @@ impl RustCompiler {
     }
     
     fn clear_textbuffer_messages(&self, cx: &mut Cx, text_buffers: &mut TextBuffers) {
-        // clear all files we missed
         for (_, text_buffer) in &mut text_buffers.storage {
-            if text_buffer.messages.gc_id != cx.event_id {
-                text_buffer.messages.cursors.truncate(0);
-                text_buffer.messages.bodies.truncate(0);
-                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
-            }
-            else {
-                cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
-            }
+            text_buffer.messages.set_source(TextBufferMessageSource::Compiler, text_buffer.mutation_id, Vec::new());
+            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
         }
     }
     
+    // the compiler messages of every file replace the ones from the last export, what the
+    // language server or the formatter said stays
     pub fn export_messages(&self, cx: &mut Cx, text_buffers: &mut TextBuffers) {
-        
+        let mut exported: HashMap<String, Vec<(TextCursor, TextBufferMessage)>> = HashMap::new();
         for dm in &self._draw_messages {
-            
-            let text_buffer = text_buffers.from_path(cx, &dm.path);
-            
-            let messages = &mut text_buffer.messages;
-            messages.mutation_id = text_buffer.mutation_id;
-            if messages.gc_id != cx.event_id {
-                messages.gc_id = cx.event_id;
-                messages.cursors.truncate(0);
-                messages.bodies.truncate(0);
-            }
-            
             if dm.level == TextBufferMessageLevel::Log {
                 break
             }
-            if dm.head == dm.tail {
-                messages.cursors.push(TextCursor {
-                    head: dm.head as usize,
-                    tail: dm.tail as usize,
-                    max: 0
-                })
-            }
-            else {
-                messages.cursors.push(TextCursor {
-                    head: dm.head,
-                    tail: dm.tail,
-                    max: 0
-                })
-            }
-            
-            //println!("PROCESING MESSAGES FOR {} {} {}", span.byte_start, span.byte_end+1, path);
-            text_buffer.messages.bodies.push(TextBufferMessage {
+            exported.entry(dm.path.clone()).or_insert_with(Vec::new).push((TextCursor {
+                head: dm.head,
+                tail: dm.tail,
+                max: 0
+            }, TextBufferMessage {
                 body: dm.body.clone(),
-                level: dm.level.clone()
-            });
-            //}
+                level: dm.level.clone(),
+                detail: dm.detail.clone(),
+                fixes: dm.fixes.clone(),
+                source: TextBufferMessageSource::Compiler
+            }));
         }
-        self.clear_textbuffer_messages(cx, text_buffers);
-        
+        for path in exported.keys() {
+            text_buffers.from_path(cx, path);
+        }
+        for (path, text_buffer) in &mut text_buffers.storage {
+            let messages = exported.remove(path).unwrap_or_default();
+            text_buffer.messages.set_source(TextBufferMessageSource::Compiler, text_buffer.mutation_id, messages);
+            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_MESSAGE_UPDATE);
+        }
+    }
+    
+    // the message after or before the selected one, going round at the ends
+    fn next_message(&self, forward: bool) -> Option<usize> {
+        let len = self._draw_messages.len();
+        if len == 0 {
+            return None
+        }
+        Some(match self._draw_messages.iter().position( | dm | dm.is_selected) {
+            Some(index) if forward => (index + 1) % len,
+            Some(index) => (index + len - 1) % len,
+            None if forward => 0,
+            None => len - 1
+        })
     }
     
-    pub fn handle_rust_compiler(&mut self, cx: &mut Cx, event: &mut Event, text_buffers: &mut TextBuffers) -> RustCompilerEvent {
+    pub fn handle_rust_compiler(&mut self, cx: &mut Cx, event: &mut Event, text_buffers: &mut TextBuffers, keymap: &Keymap) -> RustCompilerEvent {
         // do shit here
         if self.view.handle_scroll_bars(cx, event) {
             // do zshit.
@@ -203,60 +255,11 @@ impl RustCompiler {
         let mut dm_to_select = None;
         
         match event {
-            Event::KeyDown(ke) => match ke.key_code {
-                KeyCode::F9 => {
-                    if self._rustc_build_stages == BuildStage::Complete {
-                        self.run_program();
-                        println!("RUNNING PROGRAM!");
-                    }
-                    else {
-                        println!("BUILDSTAGE {:?}", self._rustc_build_stages);
-                        self._run_when_done = true;
-                    }
-                    self.view.redraw_view_area(cx);
-                },
-                KeyCode::F8 => { // next error
-                    if self._draw_messages.len() > 0 {
-                        if ke.modifiers.shift {
-                            let mut selected_index = None;
-                            for (counter, item) in self._draw_messages.iter_mut().enumerate() {
-                                if item.is_selected {
-                                    selected_index = Some(counter);
-                                }
-                            }
-                            if let Some(selected_index) = selected_index {
-                                if selected_index > 0 {
-                                    dm_to_select = Some(selected_index - 1);
-                                }
-                                else {
-                                    dm_to_select = Some(self._draw_messages.len() - 1);
-                                }
-                            }
-                            else {
-                                dm_to_select = Some(self._draw_messages.len() - 1);
-                            }
-                        }
-                        else {
-                            let mut selected_index = None;
-                            for (counter, dm) in self._draw_messages.iter_mut().enumerate() {
-                                if dm.is_selected {
-                                    selected_index = Some(counter);
-                                }
-                            }
-                            if let Some(selected_index) = selected_index {
-                                if selected_index + 1 < self._draw_messages.len() {
-                                    dm_to_select = Some(selected_index + 1);
-                                }
-                                else {
-                                    dm_to_select = Some(0);
-                                }
-                            }
-                            else {
-                                dm_to_select = Some(0);
-                            }
-                        }
-                    }
-                },
+            Event::KeyDown(ke) => match keymap.single_command(ke) {
+                Some(Command::RunProgram) => self.run_program_when_built(cx),
+                // with an editor focused the editor steps through the messages
+                Some(Command::NextMessage) if cx.has_key_focus(self.view.get_view_area(cx)) => dm_to_select = self.next_message(true),
+                Some(Command::PrevMessage) if cx.has_key_focus(self.view.get_view_area(cx)) => dm_to_select = self.next_message(false),
                 _ => ()
             },
             Event::Signal(se) => {
@@ -293,6 +296,27 @@ impl RustCompiler {
             _ => ()
         }
         
+        // the fix buttons sit on top of the rows, so they get the click first
+        if let Event::FingerDown(_) = event {
+            for dm in &self._draw_messages {
+                for (index, area) in dm.fix_areas.iter().enumerate() {
+                    if let Event::FingerDown(_) = event.hits(cx, *area, HitOpt::default()) {
+                        let text_buffer = text_buffers.from_path(cx, &dm.path);
+                        // the positions are from the last build, after an edit they can point anywhere
+                        if text_buffer.load_file_read.is_pending() || text_buffer.messages.mutation_id != text_buffer.mutation_id {
+                            return RustCompilerEvent::None
+                        }
+                        let was_dirty = text_buffer.is_dirty();
+                        if TextCursorSet::new().apply_quick_fixes(&dm.fixes[index..index + 1], text_buffer) {
+                            cx.send_signal(text_buffer.signal, SIGNAL_TEXTBUFFER_RELOADED);
+                            return RustCompilerEvent::FixApplied {path: dm.path.clone(), save: !was_dirty}
+                        }
+                        return RustCompilerEvent::None
+                    }
+                }
+            }
+        }
+        
         //let mut unmark_nodes = false;
         for (counter, dm) in self._draw_messages.iter_mut().enumerate() {
             match event.hits(cx, dm.animator.area, HitOpt::default()) {
@@ -301,6 +325,7 @@ impl RustCompiler {
                 },
                 Event::FingerDown(_fe) => {
                     cx.set_down_mouse_cursor(MouseCursor::Hand);
+                    cx.set_key_focus(self.view.get_view_area(cx));
                     // mark ourselves, unmark others
                     dm_to_select = Some(counter);
                 },
@@ -397,6 +422,21 @@ impl RustCompiler {
                 self.text.draw_text(cx, line);
             }
             
+            dm.fix_areas.truncate(0);
+            for fix in &dm.fixes {
+                self.fix_bg.color = self.fix_bg_color;
+                let fix_inst = self.fix_bg.begin_quad(cx, &Layout {
+                    width: Bounds::Compute,
+                    height: Bounds::Compute,
+                    padding: Padding {l: 4., t: 1., b: 1., r: 4.},
+                    margin: Margin {l: 0., t: 0., b: 0., r: 4.},
+                    ..Default::default()
+                });
+                self.text.color = self.fix_color;
+                self.text.draw_text(cx, &format!("fix: {}", fix.label));
+                dm.fix_areas.push(self.fix_bg.end_quad(cx, &fix_inst));
+            }
+            
             let bg_area = self.item_bg.end_quad(cx, &bg_inst);
             dm.animator.update_area_refs(cx, bg_area);
             
@@ -486,7 +526,8 @@ impl RustCompiler {
         // start a release build
         self._rustc_build_stages = BuildStage::Building;
         
-        let mut _child = spawn_process_command("cargo", &["build", /*"--release", "--target=wasm32-unknown-unknown",*/ "--message-format=json","--manifest-path=./webgl/Cargo.toml"], "./edit_repo");
+        let cargo = if let Some(cargo) = &self.cargo {cargo} else {return};
+        let mut _child = cargo.spawn(&["build", "--workspace", "--message-format=json"]);
         
         if let Err(_) = _child {
             return;
@@ -510,6 +551,16 @@ impl RustCompiler {
         self._build_child = Some(child);
     }
     
+    pub fn run_program_when_built(&mut self, cx: &mut Cx) {
+        if self._rustc_build_stages == BuildStage::Complete {
+            self.run_program();
+        }
+        else {
+            self._run_when_done = true;
+        }
+        self.view.redraw_view_area(cx);
+    }
+    
     pub fn run_program(&mut self) {
         self._run_when_done = false;
         self._program_running = true;
@@ -517,9 +568,13 @@ impl RustCompiler {
             let _ = child.kill();
         }
         
-        let mut _child = spawn_process_command("cargo", &["run", "--release"], "./edit_repo");
+        let cargo = if let Some(cargo) = &self.cargo {cargo} else {return};
+        let mut _child = match cargo.run_package() {
+            Some(package) => cargo.spawn(&["run", "--release", "-p", package]),
+            None => cargo.spawn(&["run", "--release"])
+        };
         
-        let mut child = _child.unwrap();
+        let mut child = if let Ok(child) = _child {child} else {return};
         
         let stdout_opt = child.stdout.take();
         
@@ -566,7 +621,8 @@ impl RustCompiler {
             let _ = child.kill();
         }
         
-        let mut _child = spawn_process_command("cargo", &["check", /*"--target=wasm32-unknown-unknown",*/ "--message-format=json", "--manifest-path=./webgl/Cargo.toml"], "./edit_repo");
+        let cargo = if let Some(cargo) = &self.cargo {cargo} else {return};
+        let mut _child = cargo.spawn(&["check", "--workspace", "--message-format=json"]);
         
         if let Err(_) = _child {
             return;
@@ -654,18 +710,25 @@ impl RustCompiler {
                                             for child in &parsed.message.children {
                                                 more_lines.push(child.message.clone());
                                             }
+                                            let fixes = parsed.message.quick_fixes(&span.file_name);
                                             //span.file_name = format!("/{}",span.file_name);
                                             span.level = Some(parsed.message.level.clone());
                                             self._draw_messages.push(RustDrawMessage {
                                                 animator: Animator::new(Self::get_default_anim(cx, self._draw_messages.len(), false)),
                                                 is_selected: false,
-                                                path: span.file_name,
+                                                path: match &self.cargo {
+                                                    Some(cargo) => cargo.text_buffer_path(&self.root_path, &span.file_name),
+                                                    None => span.file_name
+                                                },
                                                 row: span.line_start as usize,
                                                 col: span.column_start as usize,
                                                 tail: span.byte_start as usize,
                                                 head: span.byte_end as usize,
                                                 body: parsed.message.message.clone(),
                                                 more_lines: more_lines,
+                                                detail: Some(parsed.message.detail()),
+                                                fixes: fixes,
+                                                fix_areas: Vec::new(),
                                                 level: match parsed.message.level.as_ref() {
                                                     "warning" => TextBufferMessageLevel::Warning,
                                                     "error" => TextBufferMessageLevel::Error,
@@ -739,6 +802,9 @@ impl RustCompiler {
                             animator: Animator::new(Self::get_default_anim(cx, self._draw_messages.len(), false)),
                             is_selected: false,
                             more_lines: Vec::new(),
+                            detail: None,
+                            fixes: Vec::new(),
+                            fix_areas: Vec::new(),
                             path: path,
                             row: row,
                             col: col,
@@ -760,7 +826,86 @@ impl RustCompiler {
     }
 }
 
+// what cargo metadata says about the workspace, cargo runs in its root on its manifest
+#[derive(Clone, Debug)]
+pub struct CargoWorkspace {
+    pub root: String,
+    pub manifest_path: String,
+    pub members: Vec<CargoMember>
+}
+
+#[derive(Clone, Debug)]
+pub struct CargoMember {
+    pub name: String,
+    pub manifest_path: String,
+    pub has_bin: bool
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata {
+    workspace_root: String,
+    packages: Vec<CargoMetadataPackage>
+}
 
+#[derive(Deserialize)]
+struct CargoMetadataPackage {
+    name: String,
+    manifest_path: String,
+    targets: Vec<CargoMetadataTarget>
+}
+
+#[derive(Deserialize)]
+struct CargoMetadataTarget {
+    kind: Vec<String>
+}
+
+impl CargoWorkspace {
+    // the workspace dir is in, it can be further up
+    pub fn load(dir: &str) -> std::result::Result<CargoWorkspace, String> {
+        let output = std::process::Command::new("cargo")
+            .args(&["metadata", "--no-deps", "--format-version", "1"])
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err( | err | format!("Cannot run cargo metadata: {}", err)) ?;
+        if !output.status.success() {
+            return Err(format!("cargo metadata failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+        }
+        let metadata: CargoMetadata = serde_json::from_slice(&output.stdout).map_err( | err | format!("Cannot read cargo metadata: {}", err)) ?;
+        let root = metadata.workspace_root.replace('\\', "/");
+        Ok(CargoWorkspace {
+            manifest_path: format!("{}/Cargo.toml", root),
+            root: root,
+            // with --no-deps the packages are the members
+            members: metadata.packages.into_iter().map( | package | CargoMember {
+                has_bin: package.targets.iter().any( | target | target.kind.iter().any( | kind | kind == "bin")),
+                name: package.name,
+                manifest_path: package.manifest_path
+            }).collect()
+        })
+    }
+    
+    pub fn spawn(&self, args: &[&str]) -> std::result::Result<Child, std::io::Error> {
+        let manifest_path = format!("--manifest-path={}", self.manifest_path);
+        let mut args = args.to_vec();
+        args.push(&manifest_path);
+        spawn_process_command("cargo", &args, &self.root)
+    }
+    
+    // what cargo run runs, the first member with a binary
+    pub fn run_package(&self) -> Option<&str> {
+        self.members.iter().find( | member | member.has_bin).map( | member | member.name.as_str())
+    }
+    
+    // rustc gives paths from the workspace root, TextBuffers wants them from root_path
+    pub fn text_buffer_path(&self, root_path: &str, file_name: &str) -> String {
+        if std::path::Path::new(file_name).is_absolute() {
+            return relative_path(root_path, file_name)
+        }
+        relative_path(root_path, &format!("{}/{}", self.root, file_name))
+    }
+}
 
 #[derive(Clone, Deserialize, Default)]
 pub struct RustcTarget {
@@ -791,7 +936,7 @@ pub struct RustcSpan {
     text: Vec<RustcText>,
     label: Option<String>,
     suggested_replacement: Option<String>,
-    sugggested_applicability: Option<String>,
+    suggestion_applicability: Option<String>,
     expansion: Option<Box<RustcExpansion>>,
     level: Option<String>
 }
@@ -819,6 +964,64 @@ pub struct RustcMessage {
     rendered: Option<String>
 }
 
+impl RustcMessage {
+    // what rustc prints on the terminal, or else the message with its notes
+    fn detail(&self) -> String {
+        if let Some(rendered) = &self.rendered {
+            return rendered.clone()
+        }
+        let mut detail = format!("{}: {}", self.level, self.message);
+        for child in &self.children {
+            detail.push_str(&format!("\n  = {}: {}", child.level, child.message));
+        }
+        detail
+    }
+    
+    // every suggestion of the message and its children we dare to offer, one fix per message.
+    // MaybeIncorrect ones are offered but never applied in bulk
+    fn quick_fixes(&self, file_name: &str) -> Vec<TextQuickFix> {
+        let mut fixes = Vec::new();
+        for message in std::iter::once(self).chain(self.children.iter()) {
+            let mut machine_applicable = true;
+            let mut edits = Vec::new();
+            for span in &message.spans {
+                let replacement = match &span.suggested_replacement {
+                    Some(replacement) if span.file_name == file_name => replacement,
+                    _ => continue
+                };
+                match span.suggestion_applicability.as_ref().map( | a | a.as_str()) {
+                    Some("MachineApplicable") => (),
+                    Some("MaybeIncorrect") => machine_applicable = false,
+                    _ => continue
+                }
+                edits.push(TextQuickFixEdit {
+                    start: TextPos {row: (span.line_start as usize).max(1) - 1, col: (span.column_start as usize).max(1) - 1},
+                    end: TextPos {row: (span.line_end as usize).max(1) - 1, col: (span.column_end as usize).max(1) - 1},
+                    replacement: replacement.clone()
+                });
+            }
+            if edits.len() == 0 {
+                continue
+            }
+            let label = if edits.len() == 1 && edits[0].replacement.len() == 0 {
+                format!("{} (remove)", message.message)
+            }
+            else if edits.len() == 1 && edits[0].replacement.len() < 40 && !edits[0].replacement.contains('\n') {
+                format!("{}: `{}`", message.message, edits[0].replacement)
+            }
+            else {
+                message.message.clone()
+            };
+            fixes.push(TextQuickFix {
+                label: label,
+                machine_applicable: machine_applicable,
+                edits: edits
+            });
+        }
+        fixes
+    }
+}
+
 #[derive(Clone, Deserialize, Default)]
 pub struct RustcProfile {
     opt_level: String,
@@ -847,3 +1050,34 @@ pub struct RustcCompilerArtifact {
     executable: Option<String>,
     fresh: bool
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn rustc_paths_become_text_buffer_paths() {
+        let cargo = CargoWorkspace {
+            root: "/home/me/ws".to_string(),
+            manifest_path: "/home/me/ws/Cargo.toml".to_string(),
+            members: Vec::new()
+        };
+        // makepad opened on a member, rustc talks from the workspace root
+        assert_eq!(cargo.text_buffer_path("/home/me/ws/app/", "app/src/main.rs"), "src/main.rs");
+        assert_eq!(cargo.text_buffer_path("/home/me/ws/app/", "lib/src/lib.rs"), "../lib/src/lib.rs");
+        assert_eq!(cargo.text_buffer_path("/home/me/ws/", "app/src/main.rs"), "app/src/main.rs");
+        // dependencies come with absolute paths
+        assert_eq!(cargo.text_buffer_path("/home/me/ws/", "/home/me/.cargo/registry/x/src/lib.rs"), "../.cargo/registry/x/src/lib.rs");
+    }
+    
+    #[test]
+    fn broken_manifest_says_why() {
+        let dir = std::env::temp_dir().join(format!("makepad_cargo_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "[package\n").unwrap();
+        let err = CargoWorkspace::load(&dir.to_string_lossy()).unwrap_err();
+        assert!(err.starts_with("cargo metadata failed: ") && err.contains("Cargo.toml"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,240 @@
+use serde::*;
+use std::path::{Path, PathBuf, Component};
+use std::process::{Command, Stdio};
+
+// The folders makepad works on. The first root holds makepad_state.json and the other makepad
+// files and TextBuffers paths are relative to it, more roots show up next to it in the file
+// tree under their path from the first one. They come from the command line, without any
+// it is the last project again. Recent projects are kept in the home directory.
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Workspace {
+    // absolute, ending in a /
+    pub roots: Vec<String>
+}
+
+impl Workspace {
+    // the directories that exist, None if there are none. The ones that dont go in errors
+    pub fn from_dirs(dirs: &[String], errors: &mut Vec<String>) -> Option<Workspace> {
+        let mut roots = Vec::new();
+        for dir in dirs {
+            match std::fs::canonicalize(dir) {
+                Ok(path) if path.is_dir() => {
+                    let root = dir_string(&path);
+                    if !roots.contains(&root) {
+                        roots.push(root);
+                    }
+                },
+                _ => errors.push(format!("Cannot open {}, its not a directory", dir))
+            }
+        }
+        if roots.len() == 0 {
+            return None
+        }
+        Some(Workspace {roots: roots})
+    }
+    
+    // whats on the command line, then the last project, then the current directory
+    pub fn from_command_line(recent_projects: &RecentProjects, errors: &mut Vec<String>) -> Workspace {
+        let args: Vec<String> = std::env::args().skip(1).filter( | arg | !arg.starts_with('-')).collect();
+        if let Some(workspace) = Workspace::from_dirs(&args, errors) {
+            return workspace
+        }
+        if args.len() == 0 {
+            // a recent project thats gone is no news
+            for workspace in &recent_projects.projects {
+                if let Some(workspace) = Workspace::from_dirs(&workspace.roots, &mut Vec::new()) {
+                    return workspace
+                }
+            }
+        }
+        Workspace::from_dirs(&[".".to_string()], errors).unwrap_or(Workspace {roots: vec!["./".to_string()]})
+    }
+    
+    pub fn root_path(&self) -> &str {
+        &self.roots[0]
+    }
+    
+    // where the files of a root start in TextBuffers paths, empty for the first one
+    pub fn root_prefix(&self, index: usize) -> String {
+        if index == 0 {
+            return String::new()
+        }
+        relative_path(self.root_path(), &self.roots[index])
+    }
+    
+    pub fn name(&self) -> String {
+        let names: Vec<&str> = self.roots.iter().map( | root | {
+            root.trim_end_matches('/').rsplit('/').next().filter( | name | name.len() > 0).unwrap_or("/")
+        }).collect();
+        names.join(" + ")
+    }
+    
+    // opens the workspace in a makepad of its own
+    pub fn open_in_new_window(&self) -> Result<(), String> {
+        let exe = std::env::current_exe().map_err( | err | format!("Cannot find makepad: {}", err)) ?;
+        Command::new(exe)
+            .args(&self.roots)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err( | err | format!("Cannot start makepad: {}", err)) ?;
+        Ok(())
+    }
+}
+
+fn dir_string(path: &Path) -> String {
+    let mut dir = path.to_string_lossy().replace('\\', "/");
+    if !dir.ends_with('/') {
+        dir.push('/');
+    }
+    dir
+}
+
+// path as seen from dir, both absolute. Directories keep their / at the end
+pub fn relative_path(dir: &str, path: &str) -> String {
+    let dir_parts: Vec<Component> = Path::new(dir).components().collect();
+    let path_parts: Vec<Component> = Path::new(path).components().collect();
+    let common = dir_parts.iter().zip(path_parts.iter()).take_while( | (a, b) | a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..dir_parts.len() {
+        relative.push("..");
+    }
+    for part in &path_parts[common..] {
+        relative.push(part.as_os_str());
+    }
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    if path.ends_with('/') && relative.len() > 0 {
+        format!("{}/", relative)
+    }
+    else {
+        relative
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecentProjects {
+    // the last one opened first
+    pub projects: Vec<Workspace>
+}
+
+impl RecentProjects {
+    fn file_path() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME").or_else( || std::env::var_os("USERPROFILE")) ?;
+        Some(PathBuf::from(home).join(".makepad").join("recent_projects.json"))
+    }
+    
+    pub fn load() -> RecentProjects {
+        let json = RecentProjects::file_path().and_then( | path | std::fs::read_to_string(path).ok());
+        json.and_then( | json | serde_json::from_str(&json).ok()).unwrap_or_default()
+    }
+    
+    pub fn save(&self) -> Result<(), String> {
+        let path = if let Some(path) = RecentProjects::file_path() {path} else {return Ok(())};
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        std::fs::write(&path, serde_json::to_string(self).unwrap()).map_err( | err | format!("Cannot write {}: {}", path.display(), err))
+    }
+    
+    pub fn add(&mut self, workspace: &Workspace) {
+        self.projects.retain( | project | project != workspace);
+        self.projects.insert(0, workspace.clone());
+        self.projects.truncate(16);
+    }
+}
+
+// asks the desktop for a folder, blocks until the user picked one or gave up
+pub fn pick_folder() -> Result<Option<String>, String> {
+    let (command, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("osascript", &["-e", "POSIX path of (choose folder with prompt \"Open Folder\")"])
+    }
+    else if cfg!(target_os = "windows") {
+        ("powershell", &["-NoProfile", "-Command", "Add-Type -AssemblyName System.Windows.Forms; $d = New-Object System.Windows.Forms.FolderBrowserDialog; if ($d.ShowDialog() -eq 'OK') { $d.SelectedPath }"])
+    }
+    else {
+        ("zenity", &["--file-selection", "--directory", "--title=Open Folder"])
+    };
+    let output = Command::new(command).args(args).stdin(Stdio::null()).output();
+    let output = match output {
+        Ok(output) => output,
+        // no zenity, maybe its kde
+        Err(err) if cfg!(target_os = "linux") => Command::new("kdialog").args(&["--getexistingdirectory", "."]).stdin(Stdio::null()).output()
+            .map_err( | _ | format!("Cannot ask for a folder with {} or kdialog: {}", command, err)) ?,
+        Err(err) => return Err(format!("Cannot ask for a folder with {}: {}", command, err))
+    };
+    let folder = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || folder.len() == 0 {
+        return Ok(None)
+    }
+    Ok(Some(folder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn workspace(roots: &[&str]) -> Workspace {
+        Workspace {roots: roots.iter().map( | root | root.to_string()).collect()}
+    }
+    
+    #[test]
+    fn from_dirs_keeps_the_dirs_that_exist_once() {
+        let dir = std::env::temp_dir().join(format!("makepad_workspace_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("file"), "").unwrap();
+        let path = | name: &str | dir.join(name).to_string_lossy().to_string();
+        
+        let mut errors = Vec::new();
+        let dirs = vec![path("a"), path("b"), format!("{}/../a", path("b")), path("file"), path("gone")];
+        let found = Workspace::from_dirs(&dirs, &mut errors).unwrap();
+        let canonical = | name: &str | dir_string(&std::fs::canonicalize(dir.join(name)).unwrap());
+        assert_eq!(found.roots, vec![canonical("a"), canonical("b")]);
+        assert!(found.roots.iter().all( | root | root.ends_with('/')));
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("file") && errors[1].contains("gone"));
+        
+        let mut errors = Vec::new();
+        assert_eq!(Workspace::from_dirs(&[path("gone")], &mut errors), None);
+        assert_eq!(errors.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn paths_between_roots() {
+        assert_eq!(relative_path("/home/me/app/", "/home/me/app/src/main.rs"), "src/main.rs");
+        assert_eq!(relative_path("/home/me/app/", "/home/me/lib/src/"), "../lib/src/");
+        assert_eq!(relative_path("/home/me/app/", "/home/me/app/"), "");
+        assert_eq!(relative_path("/home/me/app/", "/opt/x"), "../../../opt/x");
+        
+        let workspace = workspace(&["/home/me/app/", "/home/me/lib/", "/home/me/app/vendor/", "/opt/x/"]);
+        assert_eq!(workspace.root_path(), "/home/me/app/");
+        assert_eq!(workspace.root_prefix(0), "");
+        assert_eq!(workspace.root_prefix(1), "../lib/");
+        assert_eq!(workspace.root_prefix(2), "vendor/");
+        assert_eq!(workspace.root_prefix(3), "../../../opt/x/");
+        assert_eq!(workspace.name(), "app + lib + vendor + x");
+    }
+    
+    #[test]
+    fn recent_projects_move_to_the_front_and_stay_capped() {
+        let mut recent_projects = RecentProjects::default();
+        for index in 0..20 {
+            recent_projects.add(&workspace(&[&format!("/p{}/", index)]));
+        }
+        assert_eq!(recent_projects.projects.len(), 16);
+        assert_eq!(recent_projects.projects[0], workspace(&["/p19/"]));
+        assert_eq!(recent_projects.projects[15], workspace(&["/p4/"]));
+        
+        recent_projects.add(&workspace(&["/p10/"]));
+        assert_eq!(recent_projects.projects.len(), 16);
+        assert_eq!(recent_projects.projects[0], workspace(&["/p10/"]));
+        assert_eq!(recent_projects.projects.iter().filter( | project | **project == workspace(&["/p10/"])).count(), 1);
+        
+        // the same roots in another order are another project
+        recent_projects.add(&workspace(&["/a/", "/b/"]));
+        recent_projects.add(&workspace(&["/b/", "/a/"]));
+        assert_eq!(recent_projects.projects[1], workspace(&["/a/", "/b/"]));
+    }
+}
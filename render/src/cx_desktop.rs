@@ -1,4 +1,5 @@
 use crate::cx::*;
+use crate::cx_filewatch::*;
 use std::io::prelude::*;
 use std::fs::File;
 use std::io;
@@ -9,6 +10,9 @@ use time::precise_time_ns;
 pub struct CxDesktop {
     pub file_read_id: u64,
     pub file_reads: Vec<FileRead>,
+    pub file_write_id: u64,
+    pub file_writes: Vec<FileWriteEvent>,
+    pub file_watcher: CxFileWatcher,
     pub profiler_list: Vec<u64>,
     pub profiler_totals: Vec<u64>
 }
@@ -18,6 +22,9 @@ impl Default for CxDesktop {
         CxDesktop {
             file_read_id: 1,
             file_reads: Vec::new(),
+            file_write_id: 1,
+            file_writes: Vec::new(),
+            file_watcher: CxFileWatcher::default(),
             profiler_list: Vec::new(),
             profiler_totals: Vec::new()
         }
@@ -43,18 +50,20 @@ impl Cx {
     }
     
     pub fn file_write(&mut self, path: &str, data: &[u8]) -> u64 {
-        // just write it right now
-        if let Ok(mut file) = File::create(path) {
-            if let Ok(_) = file.write_all(&data) {
-            }
-            else {
-                println!("ERROR WRITING FILE {}", path);
-            }
-        }
-        else {
-            println!("ERROR WRITING FILE {}", path);
-        }
-        0
+        // write it right now, the result comes back as a FileWrite event
+        let desktop = &mut self.platform.desktop;
+        desktop.file_write_id += 1;
+        let write_id = desktop.file_write_id;
+        let result = write_file_atomic(path, data).map_err( | err | FileError {
+            kind: err.kind(),
+            message: format!("Cannot write {}: {}", path, err)
+        });
+        desktop.file_writes.push(FileWriteEvent {
+            write_id: write_id,
+            path: path.to_string(),
+            result: result
+        });
+        write_id
     }
     
     pub fn process_desktop_pre_event<F>(&mut self, event: &mut Event, mut event_handler: F)
@@ -91,6 +100,11 @@ impl Cx {
             Event::AppFocusLost => {
                 self.call_all_keys_up(&mut event_handler);
             },
+            Event::Signal(se) => {
+                if self.platform.desktop.file_watcher.signal.is_signal(se) {
+                    self.call_file_changed(&mut event_handler);
+                }
+            },
             _ => ()
         };
     }
@@ -139,6 +153,8 @@ impl Cx {
         
         self.process_desktop_file_reads(&mut event_handler);
         
+        self.process_desktop_file_writes(&mut event_handler);
+        
         self.call_signals(&mut event_handler);
         
         vsync
@@ -186,6 +202,18 @@ impl Cx {
         }
     }
     
+    pub fn process_desktop_file_writes<F>(&mut self, mut event_handler: F)
+    where F: FnMut(&mut Cx, &mut Event)
+    {
+        if self.platform.desktop.file_writes.len() == 0 {
+            return
+        }
+        let file_writes = std::mem::replace(&mut self.platform.desktop.file_writes, Vec::new());
+        for file_write in file_writes {
+            event_handler(self, &mut Event::FileWrite(file_write));
+        }
+    }
+    
     pub fn process_to_wasm<F>(&mut self, _msg: u32, mut _event_handler: F) -> u32 
     where F: FnMut(&mut Cx, &mut Event)
     {
@@ -276,4 +304,32 @@ impl Cx {
         desktop.profiler_totals[id] += delta;
     }
     
-}
\ No newline at end of file
+}
+
+// Writes a temp file next to the target and renames it over, so a crash halfway leaves
+// either the old or the new file and never a truncated one.
+fn write_file_atomic(path: &str, data: &[u8]) -> io::Result<()> {
+    // saves through a symlink should replace the file it points to, not the link
+    let path = std::fs::canonicalize(path).unwrap_or(std::path::PathBuf::from(path));
+    // the rename would happily replace a file we arent allowed to write, so ask first
+    let permissions = match std::fs::OpenOptions::new().write(true).open(&path) {
+        Ok(file) => Some(file.metadata()?.permissions()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err)
+    };
+    let file_name = path.file_name().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let tmp_path = path.with_file_name(format!(".{}.makepad_tmp", file_name.to_string_lossy()));
+    let result = (|| {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        if let Some(permissions) = permissions {
+            std::fs::set_permissions(&tmp_path, permissions)?;
+        }
+        std::fs::rename(&tmp_path, &path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
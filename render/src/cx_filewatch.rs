@@ -0,0 +1,242 @@
+use crate::cx::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Watches files for changes made behind our back (git checkout, cargo fmt, other editors).
+// On linux this is inotify on the parent directories, because tools that replace a file
+// by renaming a new one over it would kill a watch on the file itself. Elsewhere a thread
+// polls the modification times. Changed paths get queued and the event loop is poked with
+// a signal, which process_desktop_pre_event turns into Event::FileChanged. Watched directories
+// get one with their own path when entries come or go, with inotify also when their .gitignore
+// gets written. When the inotify queue overflows every watched path gets one, so whoever
+// watches it reads it again.
+
+#[derive(Default)]
+pub struct FileWatches {
+    // (directory, file name) -> the path as it was passed to file_watch
+    pub files: HashMap<(String, String), String>,
+    // directory -> the path as it was passed to dir_watch
+    pub dirs: HashMap<String, String>,
+    pub changed: Vec<String>,
+    pub started: bool,
+    pub inotify_fd: i32,
+    pub inotify_dirs: HashMap<i32, String>,
+    pub poll_mtimes: HashMap<String, Option<std::time::SystemTime>>
+}
+
+#[derive(Clone, Default)]
+pub struct CxFileWatcher {
+    pub signal: Signal,
+    pub watches: Arc<Mutex<FileWatches>>
+}
+
+impl Cx {
+    pub fn file_watch(&mut self, path: &str) {
+        if self.platform.desktop.file_watcher.signal.is_empty() {
+            self.platform.desktop.file_watcher.signal = self.new_signal();
+        }
+        let watcher = self.platform.desktop.file_watcher.clone();
+        let (dir, name) = split_watch_path(path);
+        if let Ok(mut watches) = watcher.watches.lock() {
+            if !watches.started {
+                watches.started = true;
+                watches.start_thread(watcher.signal, watcher.watches.clone());
+            }
+            watches.watch_dir(&dir);
+            watches.files.insert((dir, name), path.to_string());
+        };
+    }
+    
+    pub fn dir_watch(&mut self, path: &str) {
+        if self.platform.desktop.file_watcher.signal.is_empty() {
+            self.platform.desktop.file_watcher.signal = self.new_signal();
+        }
+        let watcher = self.platform.desktop.file_watcher.clone();
+        let dir = split_watch_dir(path);
+        if let Ok(mut watches) = watcher.watches.lock() {
+            if !watches.started {
+                watches.started = true;
+                watches.start_thread(watcher.signal, watcher.watches.clone());
+            }
+            watches.watch_dir(&dir);
+            watches.dirs.insert(dir, path.to_string());
+        };
+    }
+    
+    pub fn dir_unwatch(&mut self, path: &str) {
+        if let Ok(mut watches) = self.platform.desktop.file_watcher.watches.lock() {
+            let dir = split_watch_dir(path);
+            watches.dirs.remove(&dir);
+            watches.poll_mtimes.remove(path);
+            watches.unwatch_dir(&dir);
+        }
+    }
+    
+    pub fn file_unwatch(&mut self, path: &str) {
+        if let Ok(mut watches) = self.platform.desktop.file_watcher.watches.lock() {
+            let (dir, name) = split_watch_path(path);
+            watches.files.remove(&(dir.clone(), name));
+            watches.poll_mtimes.remove(path);
+            watches.unwatch_dir(&dir);
+        }
+    }
+    
+    pub fn call_file_changed<F>(&mut self, mut event_handler: F)
+    where F: FnMut(&mut Cx, &mut Event)
+    {
+        let changed = if let Ok(mut watches) = self.platform.desktop.file_watcher.watches.lock() {
+            std::mem::replace(&mut watches.changed, Vec::new())
+        }
+        else {
+            return
+        };
+        for path in changed {
+            self.call_event_handler(&mut event_handler, &mut Event::FileChanged(FileChangedEvent {path: path}));
+        }
+    }
+}
+
+fn split_watch_path(path: &str) -> (String, String) {
+    if let Some(pos) = path.rfind('/') {
+        (path[0..pos].to_string(), path[pos + 1..].to_string())
+    }
+    else {
+        (".".to_string(), path.to_string())
+    }
+}
+
+fn split_watch_dir(path: &str) -> String {
+    let dir = path.trim_end_matches('/');
+    if dir.len() == 0 {
+        if path.len() > 0 {"/".to_string()} else {".".to_string()}
+    }
+    else {
+        dir.to_string()
+    }
+}
+
+impl FileWatches {
+    fn push_changed(&mut self, path: String) -> bool {
+        if self.changed.contains(&path) {
+            return false
+        }
+        self.changed.push(path);
+        true
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FileWatches {
+    fn start_thread(&mut self, signal: Signal, watches: Arc<Mutex<FileWatches>>) {
+        let fd = unsafe {libc::inotify_init1(libc::IN_CLOEXEC)};
+        if fd < 0 {
+            return
+        }
+        self.inotify_fd = fd;
+        std::thread::spawn(move || {
+            let header = std::mem::size_of::<libc::inotify_event>();
+            let mut buf = vec![0u8; 16384];
+            loop {
+                let n = unsafe {libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())};
+                if n < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                    continue
+                }
+                if n <= 0 {
+                    return
+                }
+                let mut any_changed = false;
+                if let Ok(mut watches) = watches.lock() {
+                    let mut pos = 0;
+                    while pos + header <= n as usize {
+                        let event: libc::inotify_event = unsafe {std::ptr::read_unaligned(buf.as_ptr().add(pos) as *const _)};
+                        let name_bytes = &buf[pos + header..pos + header + event.len as usize];
+                        let name = String::from_utf8_lossy(name_bytes.split( | b | *b == 0).next().unwrap_or(&[])).to_string();
+                        pos += header + event.len as usize;
+                        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                            // events got lost, so any watched path might have changed
+                            let paths: Vec<String> = watches.files.values().chain(watches.dirs.values()).cloned().collect();
+                            for path in paths {
+                                any_changed |= watches.push_changed(path);
+                            }
+                            continue
+                        }
+                        let (path, dir_path) = if let Some(dir) = watches.inotify_dirs.get(&event.wd) {
+                            // writing a file doesnt change the listing
+                            let listing_changed = event.mask & libc::IN_CLOSE_WRITE == 0 || name == ".gitignore";
+                            (
+                                watches.files.get(&(dir.clone(), name)).cloned(),
+                                if listing_changed {watches.dirs.get(dir).cloned()} else {None}
+                            )
+                        }
+                        else {
+                            (None, None)
+                        };
+                        for path in path.into_iter().chain(dir_path) {
+                            any_changed |= watches.push_changed(path);
+                        }
+                    }
+                }
+                if any_changed {
+                    Cx::post_signal(signal, 0);
+                }
+            }
+        });
+    }
+    
+    fn watch_dir(&mut self, dir: &str) {
+        if self.inotify_fd <= 0 || self.inotify_dirs.values().any( | d | d == dir) {
+            return
+        }
+        if let Ok(c_dir) = std::ffi::CString::new(dir) {
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_CREATE | libc::IN_DELETE;
+            let wd = unsafe {libc::inotify_add_watch(self.inotify_fd, c_dir.as_ptr(), mask)};
+            if wd >= 0 {
+                self.inotify_dirs.insert(wd, dir.to_string());
+            }
+        }
+    }
+    
+    // the directory watch goes once no watched file lives there and the directory itself isnt watched
+    fn unwatch_dir(&mut self, dir: &str) {
+        if self.inotify_fd <= 0 || self.dirs.contains_key(dir) || self.files.keys().any( | (file_dir, _) | file_dir == dir) {
+            return
+        }
+        if let Some(wd) = self.inotify_dirs.iter().find( | (_, d) | *d == dir).map( | (wd, _) | *wd) {
+            unsafe {libc::inotify_rm_watch(self.inotify_fd, wd)};
+            self.inotify_dirs.remove(&wd);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl FileWatches {
+    fn start_thread(&mut self, signal: Signal, watches: Arc<Mutex<FileWatches>>) {
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                let mut any_changed = false;
+                if let Ok(mut watches) = watches.lock() {
+                    // a directory gets a new mtime when entries come or go
+                    let paths: Vec<String> = watches.files.values().chain(watches.dirs.values()).cloned().collect();
+                    for path in paths {
+                        let mtime = std::fs::metadata(&path).and_then( | m | m.modified()).ok();
+                        if let Some(old_mtime) = watches.poll_mtimes.insert(path.clone(), mtime) {
+                            if old_mtime != mtime {
+                                any_changed |= watches.push_changed(path);
+                            }
+                        }
+                    }
+                }
+                if any_changed {
+                    Cx::post_signal(signal, 0);
+                }
+            }
+        });
+    }
+    
+    fn watch_dir(&mut self, _dir: &str) {
+    }
+    
+    fn unwatch_dir(&mut self, _dir: &str) {
+    }
+}
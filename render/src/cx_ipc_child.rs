@@ -13,9 +13,19 @@ impl Cx {
     }
     
     // empty stub
-    pub fn event_loop<F>(&mut self, mut _event_handler: F)
+    pub fn event_loop<F>(&mut self, mut event_handler: F)
     where F: FnMut(&mut Cx, Event){
         // ok so. now what.
+        self.process_ipc_file_writes(&mut event_handler);
+    }
+    
+    // the writes that got turned down still answer with a FileWrite event, like everywhere else
+    pub fn process_ipc_file_writes<F>(&mut self, mut event_handler: F)
+    where F: FnMut(&mut Cx, Event){
+        let file_writes = std::mem::replace(&mut self.platform.file_writes, Vec::new());
+        for file_write in file_writes {
+            event_handler(self, Event::FileWrite(file_write));
+        }
     }
     
     pub fn write_log(&mut self, _data: &str) {
@@ -28,8 +38,31 @@ impl Cx {
         FileRead {read_id: 0, path: path.to_string()}
     }
     
-    pub fn file_write(&mut self, _path: &str, _data: &[u8]) -> u64 {
-        return 0
+    pub fn file_write(&mut self, path: &str, _data: &[u8]) -> u64 {
+        // the host doesnt do file writes over ipc, dont let callers think it worked
+        self.platform.file_write_id += 1;
+        let write_id = self.platform.file_write_id;
+        self.platform.file_writes.push(FileWriteEvent {
+            write_id: write_id,
+            path: path.to_string(),
+            result: Err(FileError {
+                kind: std::io::ErrorKind::Unsupported,
+                message: format!("Cannot write {}: not supported over ipc", path)
+            })
+        });
+        write_id
+    }
+    
+    pub fn file_watch(&mut self, _path: &str) {
+    }
+    
+    pub fn file_unwatch(&mut self, _path: &str) {
+    }
+    
+    pub fn dir_watch(&mut self, _path: &str) {
+    }
+    
+    pub fn dir_unwatch(&mut self, _path: &str) {
     }
     
     pub fn set_window_outer_size(&mut self, _size: Vec2) {
@@ -66,6 +99,8 @@ pub struct CxPlatform {
     pub window_geom: WindowGeom,
     pub fingers_down: Vec<bool>,
     pub file_read_id: u64,
+    pub file_write_id: u64,
+    pub file_writes: Vec<FileWriteEvent>,
 }
 
 impl Default for CxPlatform {
@@ -73,6 +108,8 @@ impl Default for CxPlatform {
         CxPlatform {
             window_geom: WindowGeom::default(),
             file_read_id: 1,
+            file_write_id: 0,
+            file_writes: Vec::new(),
             fingers_down: Vec::new()
         }
     }
@@ -342,6 +342,11 @@ impl Cx {
             };
         };
         
+        let file_writes = std::mem::replace(&mut self.platform.file_writes, Vec::new());
+        for file_write in file_writes {
+            self.call_event_handler(&mut event_handler, &mut Event::FileWrite(file_write));
+        }
+        
         self.call_signals(&mut event_handler);
         
         if is_animation_frame && (self.redraw_child_areas.len()>0 || self.redraw_parent_areas.len()>0) {
@@ -455,8 +460,31 @@ impl Cx {
         FileRead {read_id: id, path: path.to_string()}
     }
     
-    pub fn file_write(&mut self, _path: &str, _data: &[u8]) -> u64 {
-        return 0
+    pub fn file_write(&mut self, path: &str, _data: &[u8]) -> u64 {
+        // the browser has nowhere to write to, dont let callers think it worked
+        self.platform.file_write_id += 1;
+        let write_id = self.platform.file_write_id;
+        self.platform.file_writes.push(FileWriteEvent {
+            write_id: write_id,
+            path: path.to_string(),
+            result: Err(FileError {
+                kind: std::io::ErrorKind::Other,
+                message: format!("Cannot write {}: not supported in the browser", path)
+            })
+        });
+        write_id
+    }
+    
+    pub fn file_watch(&mut self, _path: &str) {
+    }
+    
+    pub fn file_unwatch(&mut self, _path: &str) {
+    }
+    
+    pub fn dir_watch(&mut self, _path: &str) {
+    }
+    
+    pub fn dir_unwatch(&mut self, _path: &str) {
     }
     
     pub fn set_window_outer_size(&mut self, _size: Vec2) {
@@ -644,6 +672,8 @@ pub struct CxPlatform {
     pub vaos_free: Vec<usize>,
     pub fingers_down: Vec<bool>,
     pub file_read_id: u64,
+    pub file_write_id: u64,
+    pub file_writes: Vec<FileWriteEvent>,
 }
 
 impl Default for CxPlatform {
@@ -659,6 +689,8 @@ impl Default for CxPlatform {
             vaos: 1,
             vaos_free: Vec::new(),
             file_read_id: 1,
+            file_write_id: 1,
+            file_writes: Vec::new(),
             fingers_down: Vec::new()
         }
     }
@@ -157,8 +157,7 @@ impl XlibApp {
     {
         unsafe {
             self.event_callback = Some(
-                &mut event_handler as *const dyn FnMut(&mut XlibApp, &mut Vec<Event>) -> bool
-                as *mut dyn FnMut(&mut XlibApp, &mut Vec<Event>) -> bool
+                std::mem::transmute::<*mut (dyn FnMut(&mut XlibApp, &mut Vec<Event>) -> bool + '_), *mut (dyn FnMut(&mut XlibApp, &mut Vec<Event>) -> bool + 'static)>(&mut event_handler as *mut _)
             );
             
             self.do_callback(&mut vec![
@@ -495,7 +494,7 @@ impl XlibApp {
                                         (self.xlib.XSendEvent)(self.display, root_window, 0, xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask, &mut xclient as *mut _ as *mut xlib::XEvent);
                                     }
                                     else {
-                                        window.send_finger_down(button.button as usize, self.xkeystate_to_modifiers(button.state))
+                                        window.send_finger_down(xbutton_to_digit(button.button), self.xkeystate_to_modifiers(button.state))
                                     }
                                 }
                             }
@@ -504,7 +503,7 @@ impl XlibApp {
                             let button = event.button;
                             if let Some(window_ptr) = self.window_map.get(&button.window) {
                                 let window = &mut (**window_ptr);
-                                window.send_finger_up(button.button as usize, self.xkeystate_to_modifiers(button.state))
+                                window.send_finger_up(xbutton_to_digit(button.button), self.xkeystate_to_modifiers(button.state))
                             }
                         },
                         xlib::KeyPress => {
@@ -1519,6 +1518,16 @@ impl XlibWindow {
     
 }
 
+// the same digits as on the other platforms, 0 is the left button, 1 the right one and 2 the middle
+fn xbutton_to_digit(button: c_uint) -> usize {
+    match button {
+        1 => 0,
+        2 => 2,
+        3 => 1,
+        button => button as usize
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(C)]
 struct MwmHints {
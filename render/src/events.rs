@@ -145,10 +145,24 @@ pub struct SignalEvent {
     pub value: usize
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileError {
+    pub kind: std::io::ErrorKind,
+    pub message: String
+}
+
+// the outcome of a cx.file_write, write_id is what file_write returned
 #[derive(Clone, Debug, PartialEq)]
 pub struct FileWriteEvent {
-    id: u64,
-    error: Option<String>
+    pub write_id: u64,
+    pub path: String,
+    pub result: Result<(), FileError>
+}
+
+// a file passed to cx.file_watch changed on disk, this includes our own writes
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileChangedEvent {
+    pub path: String
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -235,6 +249,7 @@ pub enum Event {
     FingerScroll(FingerScrollEvent),
     FileRead(FileReadEvent),
     FileWrite(FileWriteEvent),
+    FileChanged(FileChangedEvent),
     Timer(TimerEvent),
     Signal(SignalEvent),
     KeyFocus(KeyFocusEvent),
@@ -483,6 +498,17 @@ impl FileRead {
         self.read_id != 0
     }
     
+    pub fn resolve<'a>(&mut self, fr: &'a FileReadEvent) -> Option<Result<&'a [u8], String>> {
+        if fr.read_id == self.read_id {
+            self.read_id = 0;
+            return Some(match &fr.data {
+                Ok(data) => Ok(&data),
+                Err(err) => Err(format!("can't load file {} {}", self.path, err))
+            })
+        }
+        return None
+    }
+    
     pub fn resolve_utf8<'a>(&mut self, fr: &'a FileReadEvent) -> Option<Result<&'a str,
     String>> {
         if fr.read_id == self.read_id {
@@ -36,6 +36,9 @@ mod cx_glsl;
 #[cfg(all(not(feature="ipc"),any(target_os = "linux", target_os="macos", target_os="windows")))]
 mod cx_desktop; 
 
+#[cfg(all(not(feature="ipc"),any(target_os = "linux", target_os="macos", target_os="windows")))]
+mod cx_filewatch; 
+
 #[cfg(feature="ipc")]
 mod cx_ipc_child;
 
@@ -0,0 +1,202 @@
+use render::*;
+
+// A list of actions that pops up where the finger went down, on top of everything else. While
+// it is open it takes the keys and a finger down anywhere else closes it, so it has to get the
+// events before whatever it covers does.
+
+#[derive(Clone)]
+pub struct ContextMenu<TItem>
+where TItem: Clone
+{
+    pub view: View<NoScroll>,
+    pub bg: Quad,
+    pub bg_layout: Layout,
+    pub item_bg: Quad,
+    pub item_layout: Layout,
+    pub text: Text,
+    pub bg_color: Color,
+    pub hover_color: Color,
+    pub text_color: Color,
+    pub items: Vec<ContextMenuItem<TItem>>,
+    // where the top left goes, None when closed
+    pub _pos: Option<Vec2>,
+    // under the finger or picked with the arrow keys
+    pub _hover: Option<usize>,
+    pub _item_areas: Vec<Area>,
+    pub _bg_area: Area
+}
+
+#[derive(Clone)]
+pub struct ContextMenuItem<TItem>
+where TItem: Clone
+{
+    pub label: String,
+    pub item: TItem
+}
+
+#[derive(Clone, PartialEq)]
+pub enum ContextMenuEvent<TItem>
+where TItem: Clone
+{
+    None,
+    Select(TItem),
+    Closed
+}
+
+impl<TItem> ContextMenu<TItem>
+where TItem: Clone
+{
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            view: View {
+                is_overlay: true,
+                ..View::style(cx)
+            },
+            bg: Quad {
+                z: 10.,
+                ..Quad::style(cx)
+            },
+            bg_layout: Layout {
+                width: Bounds::Compute,
+                height: Bounds::Compute,
+                padding: Padding {l: 1., t: 3., r: 1., b: 3.},
+                ..Default::default()
+            },
+            item_bg: Quad {
+                z: 11.,
+                ..Quad::style(cx)
+            },
+            item_layout: Layout {
+                width: Bounds::Fix(160.),
+                height: Bounds::Fix(20.),
+                padding: Padding {l: 10., t: 5., r: 10., b: 0.},
+                ..Default::default()
+            },
+            text: Text {
+                z: 12.,
+                wrapping: Wrapping::None,
+                ..Text::style(cx)
+            },
+            bg_color: cx.color("bg_normal"),
+            hover_color: cx.color("bg_marked"),
+            text_color: cx.color("text_selected_focus"),
+            items: Vec::new(),
+            _pos: None,
+            _hover: None,
+            _item_areas: Vec::new(),
+            _bg_area: Area::Empty
+        }
+    }
+    
+    // pos is in window coordinates, like the abs of a finger event
+    pub fn open(&mut self, cx: &mut Cx, pos: Vec2, items: Vec<ContextMenuItem<TItem>>) {
+        self.items = items;
+        self._pos = Some(pos);
+        self._hover = None;
+        self._item_areas.truncate(0);
+        self.view.redraw_view_area(cx);
+        cx.redraw_child_area(Area::All);
+    }
+    
+    pub fn close(&mut self, cx: &mut Cx) {
+        if self._pos.take().is_some() {
+            self._item_areas.truncate(0);
+            self.view.redraw_view_area(cx);
+            cx.redraw_child_area(Area::All);
+        }
+    }
+    
+    pub fn is_open(&self) -> bool {
+        self._pos.is_some()
+    }
+    
+    fn select(&mut self, cx: &mut Cx, index: usize) -> ContextMenuEvent<TItem> {
+        let item = self.items.get(index).map( | item | item.item.clone());
+        self.close(cx);
+        if let Some(item) = item {ContextMenuEvent::Select(item)} else {ContextMenuEvent::Closed}
+    }
+    
+    pub fn handle_context_menu(&mut self, cx: &mut Cx, event: &mut Event) -> ContextMenuEvent<TItem> {
+        if !self.is_open() {
+            return ContextMenuEvent::None
+        }
+        for index in 0..self._item_areas.len() {
+            match event.hits(cx, self._item_areas[index], HitOpt::default()) {
+                Event::FingerHover(fe) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                    let hover = if let HoverState::Out = fe.hover_state {None} else {Some(index)};
+                    if hover.is_some() && self._hover != hover {
+                        self._hover = hover;
+                        self.view.redraw_view_area(cx);
+                    }
+                },
+                Event::FingerUp(fe) => if fe.is_over {
+                    return self.select(cx, index)
+                },
+                _ => ()
+            }
+        }
+        // the padding around the items doesnt close it
+        if let Event::FingerDown(_) = event.hits(cx, self._bg_area, HitOpt::default()) {
+            return ContextMenuEvent::None
+        }
+        match event {
+            Event::FingerDown(fe) if !fe.handled => {
+                self.close(cx);
+                return ContextMenuEvent::Closed
+            },
+            Event::KeyDown(ke) => {
+                let key_code = ke.key_code.clone();
+                *event = Event::None;
+                let len = self.items.len();
+                match key_code {
+                    KeyCode::Escape => {
+                        self.close(cx);
+                        return ContextMenuEvent::Closed
+                    },
+                    KeyCode::ArrowUp if len > 0 => {
+                        self._hover = Some(self._hover.map_or(len - 1, | hover | (hover + len - 1) % len));
+                        self.view.redraw_view_area(cx);
+                    },
+                    KeyCode::ArrowDown if len > 0 => {
+                        self._hover = Some(self._hover.map_or(0, | hover | (hover + 1) % len));
+                        self.view.redraw_view_area(cx);
+                    },
+                    KeyCode::Return => if let Some(hover) = self._hover {
+                        return self.select(cx, hover)
+                    },
+                    _ => ()
+                }
+            },
+            Event::TextInput(_) => {
+                *event = Event::None;
+            },
+            _ => ()
+        }
+        ContextMenuEvent::None
+    }
+    
+    pub fn draw_context_menu(&mut self, cx: &mut Cx) {
+        let pos = if let Some(pos) = self._pos {pos} else {return};
+        if let Err(()) = self.view.begin_view(cx, Layout {
+            abs_origin: Some(pos),
+            ..Default::default()
+        }) {
+            return
+        }
+        self._item_areas.truncate(0);
+        self.bg.color = self.bg_color;
+        let bg_inst = self.bg.begin_quad(cx, &self.bg_layout);
+        for (index, item) in self.items.iter().enumerate() {
+            self.item_bg.color = if self._hover == Some(index) {self.hover_color} else {self.bg_color};
+            let inst = self.item_bg.begin_quad(cx, &self.item_layout);
+            self.text.color = self.text_color;
+            self.text.draw_text(cx, &item.label);
+            let area = self.item_bg.end_quad(cx, &inst);
+            self._item_areas.push(area);
+            cx.turtle_new_line();
+        }
+        self._bg_area = self.bg.end_quad(cx, &bg_inst);
+        self.view.end_view(cx);
+    }
+}
@@ -432,6 +432,38 @@ where TItem: Clone
         None
     }
     
+    // closes every tab close_item says yes to, as if their close buttons were clicked
+    pub fn close_tabs<F>(dock_items: &mut DockItem<TItem>, close_item: &F) -> bool
+    where TItem: Clone, F: Fn(&TItem) -> bool
+    {
+        let closed = Self::recur_close_tabs(dock_items, close_item);
+        if closed {
+            Self::recur_collapse_empty(dock_items);
+        }
+        closed
+    }
+    
+    fn recur_close_tabs<F>(dock_walk: &mut DockItem<TItem>, close_item: &F) -> bool
+    where TItem: Clone, F: Fn(&TItem) -> bool
+    {
+        match dock_walk {
+            DockItem::Single(_) => false,
+            DockItem::TabControl {tabs, current} => {
+                let len = tabs.len();
+                // the current tab stays current when it is kept
+                let before_current = tabs[0..(*current).min(len)].iter().filter( | tab | close_item(&tab.item)).count();
+                tabs.retain( | tab | !close_item(&tab.item));
+                *current = (*current - before_current).min(tabs.len().max(1) - 1);
+                tabs.len() != len
+            },
+            DockItem::Splitter {first, last, ..} => {
+                let closed_first = Self::recur_close_tabs(first, close_item);
+                let closed_last = Self::recur_close_tabs(last, close_item);
+                closed_first || closed_last
+            }
+        }
+    }
+    
     fn recur_collapse_empty(dock_walk: &mut DockItem<TItem>) -> bool
     where TItem: Clone
     {
@@ -20,7 +20,18 @@ pub struct FileTree {
     pub animator: Animator,
     pub row_height: f32,
     pub font_size: f32,
-    pub row_padding: Padding
+    pub row_padding: Padding,
+    pub _view_area: Area,
+    // the name being typed, drawn instead of the one of the node
+    pub _rename: Option<FileTreeRename>,
+    // where files dragged inside the tree would go
+    pub _drop_folder: Option<String>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileTreeRename {
+    pub path: String,
+    pub name: String
 }
 
 #[derive(Clone, PartialEq)]
@@ -30,7 +41,23 @@ pub enum FileTreeEvent {
     DragCancel,
     DragEnd {fe: FingerUpEvent, paths: Vec<String>},
     DragOut,
-    SelectFile {path: String}
+    SelectFile {path: String},
+    // a folder started opening, its entries go in with set_folder
+    LoadFolder {path: String},
+    // right click on a node, or on the empty space below them for the root
+    ContextMenu {path: String, is_folder: bool, abs: Vec2},
+    Rename {path: String, new_name: String},
+    // files dragged onto a folder of the tree
+    Move {paths: Vec<String>, folder: String},
+    Delete {paths: Vec<String>},
+    UndoFileOp
+}
+
+// what a directory listing hands to set_folder
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileTreeEntry {
+    pub name: String,
+    pub is_folder: bool
 }
 
 #[derive(Clone)]
@@ -67,6 +94,31 @@ impl FileNode {
             FileNode::Folder {name, ..} => name.clone()
         }
     }
+    
+    // every file below this folder the tree has, with paths like find_node takes
+    fn collect_file_paths(&self, path: &str, paths: &mut Vec<String>) {
+        if let FileNode::Folder {folder, ..} = self {
+            for node in folder {
+                let node_path = if path.len() > 0 {format!("{}/{}", path, node.name())} else {node.name()};
+                match node {
+                    FileNode::File {..} => paths.push(node_path),
+                    FileNode::Folder {..} => node.collect_file_paths(&node_path, paths)
+                }
+            }
+        }
+    }
+    
+    // the node at path, same paths as FileWalker::current_path
+    fn find_node<'a>(&'a mut self, path: &str) -> Option<&'a mut FileNode> {
+        let mut node = self;
+        for part in path.split('/').filter( | part | part.len() > 0) {
+            node = match node {
+                FileNode::Folder {folder, ..} => folder.iter_mut().find( | node | node.name() == part) ?,
+                FileNode::File {..} => return None
+            };
+        }
+        Some(node)
+    }
 }
 
 struct StackEntry<'a> {
@@ -217,6 +269,9 @@ impl FileTree {
             },
             animator: Animator::new(Anim::empty()),
             _drag_move: None,
+            _view_area: Area::Empty,
+            _rename: None,
+            _drop_folder: None
         }
     }
     
@@ -281,6 +336,133 @@ impl FileTree {
         self.view.redraw_view_area(cx);
     }
     
+    // another workspace root as a folder under the first, name is its path from there
+    pub fn add_root_from_json(&mut self, cx: &mut Cx, name: &str, json_data: &str) {
+        let value: Result<JsonFolder> = serde_json::from_str(json_data);
+        if let Ok(mut value) = value {
+            value.name = name.to_string();
+            if let FileNode::Folder {folder, ..} = &mut self.root_node {
+                folder.retain( | node | node.name() != name);
+                folder.push(Self::json_to_file_node(value));
+            }
+        }
+        self.view.redraw_view_area(cx);
+    }
+    
+    
+    // swaps in a fresh listing of the folder at path, nodes that are still there keep their
+    // open state and contents. Folders come first, then files, each by name
+    pub fn set_folder(&mut self, cx: &mut Cx, path: &str, mut entries: Vec<FileTreeEntry>) {
+        let folder = if let Some(FileNode::Folder {folder, ..}) = self.root_node.find_node(path) {folder} else {return};
+        entries.sort_by_cached_key( | entry | (!entry.is_folder, entry.name.to_lowercase()));
+        let mut old_nodes = std::mem::replace(folder, Vec::new());
+        for entry in entries {
+            let old_index = old_nodes.iter().position( | node | {
+                node.name() == entry.name && entry.is_folder == if let FileNode::Folder {..} = node {true} else {false}
+            });
+            folder.push(if let Some(old_index) = old_index {
+                old_nodes.swap_remove(old_index)
+            }
+            else if entry.is_folder {
+                FileNode::Folder {name: entry.name, draw: None, state: NodeState::Closed, folder: Vec::new()}
+            }
+            else {
+                FileNode::File {name: entry.name, draw: None}
+            });
+        }
+        self.view.redraw_view_area(cx);
+    }
+    
+    // the files of the folders that have been listed, all of them when it came from index.json
+    pub fn file_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        self.root_node.collect_file_paths("", &mut paths);
+        paths
+    }
+    
+    pub fn open_folder(&mut self, cx: &mut Cx, path: &str) {
+        if let Some(FileNode::Folder {state, ..}) = self.root_node.find_node(path) {
+            *state = NodeState::Open;
+            self.view.redraw_view_area(cx);
+        }
+    }
+    
+    // edit the name of the node at path in place, Return hands it out as FileTreeEvent::Rename
+    pub fn start_rename(&mut self, cx: &mut Cx, path: &str) {
+        if let Some(node) = self.root_node.find_node(path) {
+            self._rename = Some(FileTreeRename {path: path.to_string(), name: node.name()});
+            cx.set_key_focus(self._view_area);
+            self.view.redraw_view_area(cx);
+        }
+    }
+    
+    fn end_rename(&mut self, cx: &mut Cx) -> FileTreeEvent {
+        self.view.redraw_view_area(cx);
+        match self._rename.take() {
+            Some(rename) if rename.name.len() > 0 && Some(rename.name.as_str()) != rename.path.rsplit('/').next() => {
+                FileTreeEvent::Rename {path: rename.path, new_name: rename.name}
+            },
+            _ => FileTreeEvent::None
+        }
+    }
+    
+    // the folder a drop at abs goes into, the one of the file when its on a file
+    fn drop_folder_at(&mut self, cx: &Cx, abs: Vec2) -> String {
+        let mut file_walker = FileWalker::new(&mut self.root_node);
+        while let Some((_depth, _index, _len, node)) = file_walker.walk() {
+            let is_folder = if let FileNode::Folder {..} = node {true} else {false};
+            let area = if let Some(node_draw) = node.get_draw() {node_draw.animator.area} else {continue};
+            if area.get_rect(cx, false).contains(abs.x, abs.y) {
+                let path = file_walker.current_path();
+                if is_folder {
+                    return path
+                }
+                return path.rsplitn(2, '/').nth(1).unwrap_or("").to_string()
+            }
+        }
+        String::new()
+    }
+    
+    fn handle_keys(&mut self, cx: &mut Cx, event: &mut Event) -> FileTreeEvent {
+        match event.hits(cx, self._view_area, HitOpt::default()) {
+            Event::KeyDown(ke) => {
+                if let Some(rename) = &mut self._rename {
+                    match ke.key_code {
+                        KeyCode::Return => return self.end_rename(cx),
+                        KeyCode::Escape => {
+                            self._rename = None;
+                        },
+                        KeyCode::Backspace => {
+                            rename.name.pop();
+                        },
+                        _ => ()
+                    }
+                    self.view.redraw_view_area(cx);
+                    return FileTreeEvent::None
+                }
+                let is_mod = ke.modifiers.logo || ke.modifiers.control;
+                match ke.key_code {
+                    KeyCode::F2 => if let Some(path) = Self::get_marked_paths(&mut self.root_node).pop() {
+                        self.start_rename(cx, &path);
+                    },
+                    KeyCode::Delete => return FileTreeEvent::Delete {paths: Self::get_marked_paths(&mut self.root_node)},
+                    KeyCode::Backspace if is_mod => return FileTreeEvent::Delete {paths: Self::get_marked_paths(&mut self.root_node)},
+                    KeyCode::KeyZ if is_mod => return FileTreeEvent::UndoFileOp,
+                    _ => ()
+                }
+            },
+            Event::TextInput(te) => if let Some(rename) = &mut self._rename {
+                rename.name.extend(te.input.chars().filter( | c | *c != '/' && *c != '\\' && !c.is_control()));
+                self.view.redraw_view_area(cx);
+            },
+            // clicking elsewhere keeps the name like Return does
+            Event::KeyFocusLost(_) => if self._rename.is_some() {
+                return self.end_rename(cx)
+            },
+            _ => ()
+        }
+        FileTreeEvent::None
+    }
     
     pub fn get_default_anim(cx: &Cx, counter: usize, marked: bool) -> Anim {
         Anim::new(Play::Chain {duration: 0.01}, vec![
@@ -313,6 +495,15 @@ impl FileTree {
     
     pub fn handle_file_tree(&mut self, cx: &mut Cx, event: &mut Event) -> FileTreeEvent {
         // alright. someone clicking on the tree items.
+        match self.handle_keys(cx, event) {
+            FileTreeEvent::None => (),
+            file_tree_event => return file_tree_event
+        }
+        if let Event::FingerDown(fe) = event {
+            if !fe.handled && self._rename.is_some() && self._view_area.get_rect(cx, false).contains(fe.abs.x, fe.abs.y) {
+                return self.end_rename(cx)
+            }
+        }
         let mut file_walker = FileWalker::new(&mut self.root_node);
         let mut counter = 0;
         self.view.handle_scroll_bars(cx, event);
@@ -322,6 +513,8 @@ impl FileTree {
         let mut drag_nodes = false;
         let mut drag_end: Option<FingerUpEvent> = None;
         let mut select_node = false;
+        let mut load_folder = None;
+        let mut context_menu = None;
         while let Some((_depth, _index, _len, node)) = file_walker.walk() {
             // alright we haz a node. so now what.
             let is_filenode = if let FileNode::File {..} = node {true} else {false};
@@ -332,9 +525,11 @@ impl FileTree {
                 Event::Animate(ae) => {
                     node_draw.animator.write_area(cx, node_draw.animator.area, "bg.", ae.time);
                 },
-                Event::FingerDown(_fe) => {
+                Event::FingerDown(fe) => {
+                    cx.set_key_focus(self._view_area);
                     // mark ourselves, unmark others
-                    if is_filenode {
+                    let is_right = fe.digit == 1;
+                    if is_filenode && !is_right {
                         select_node = true;
                     }
                     node_draw.marked = cx.event_id;
@@ -342,7 +537,10 @@ impl FileTree {
                     unmark_nodes = true;
                     node_draw.animator.play_anim(cx, Self::get_over_anim(cx, counter, node_draw.marked != 0));
                     
-                    if let FileNode::Folder {state, ..} = node {
+                    if is_right {
+                        context_menu = Some((file_walker.current_path(), !is_filenode, fe.abs));
+                    }
+                    else if let FileNode::Folder {state, ..} = node {
                         *state = match state {
                             NodeState::Opening(fac) => {
                                 NodeState::Closing(1.0 - *fac)
@@ -357,6 +555,9 @@ impl FileTree {
                                 NodeState::Opening(1.0)
                             }
                         };
+                        if let NodeState::Opening(_) = state {
+                            load_folder = Some(file_walker.current_path());
+                        }
                         // start the redraw loop
                         self.view.redraw_view_area(cx);
                     }
@@ -418,8 +619,19 @@ impl FileTree {
                 }
             }
         }
+        if let Some((path, is_folder, abs)) = context_menu {
+            return FileTreeEvent::ContextMenu {path: path, is_folder: is_folder, abs: abs}
+        }
+        // nothing below the nodes, thats the root
+        if let Event::FingerDown(fe) = event.hits(cx, self._view_area, HitOpt::default()) {
+            cx.set_key_focus(self._view_area);
+            if fe.digit == 1 {
+                return FileTreeEvent::ContextMenu {path: String::new(), is_folder: true, abs: fe.abs}
+            }
+        }
         if let Some(fe) = drag_end {
             self._drag_move = None;
+            let drop_folder = self._drop_folder.take();
             let paths = Self::get_marked_paths(&mut self.root_node);
             if !self.view.get_view_area(cx).get_rect(cx, true).contains(fe.abs.x, fe.abs.y){
                 return FileTreeEvent::DragEnd {
@@ -427,23 +639,36 @@ impl FileTree {
                     paths: paths
                 };
             }
+            else if let Some(folder) = drop_folder {
+                self.view.redraw_view_area(cx);
+                return FileTreeEvent::Move {paths: paths, folder: folder}
+            }
         }
         if drag_nodes {
-            if let Some(fe) = &self._drag_move {
+            if let Some(fe) = self._drag_move.clone() {
                 // lets check if we are over our own filetree
-                // ifso, we need to support moving files with directories
+                // ifso, dropping moves the files into the folder under the finger
                 let paths = Self::get_marked_paths(&mut self.root_node);
                 if !self.view.get_view_area(cx).get_rect(cx, true).contains(fe.abs.x, fe.abs.y){
+                    self._drop_folder = None;
                     return FileTreeEvent::DragMove {
-                        fe: fe.clone(),
+                        fe: fe,
                         paths: paths
                     };
                 }
                 else{
+                    let drop_folder = Some(self.drop_folder_at(cx, fe.abs));
+                    if drop_folder != self._drop_folder {
+                        self._drop_folder = drop_folder;
+                        self.view.redraw_view_area(cx);
+                    }
                     return FileTreeEvent::DragCancel;
                 }
             }
         };
+        if let Some(path) = load_folder {
+            return FileTreeEvent::LoadFolder {path: path}
+        }
         if select_node {
             let mut file_walker = FileWalker::new(&mut self.root_node);
             while let Some((_depth, _index, _len, node)) = file_walker.walk() {
@@ -463,6 +688,9 @@ impl FileTree {
             println!("SKIPPING FILETREE");
             return
         }
+        let view_area = self.view.get_view_area(cx);
+        cx.update_area_refs(self._view_area, view_area);
+        self._view_area = view_area;
         
         let mut file_walker = FileWalker::new(&mut self.root_node);
         
@@ -471,6 +699,9 @@ impl FileTree {
         let mut scale_stack = Vec::new();
         let mut last_stack = Vec::new();
         scale_stack.push(1.0f64);
+        // the path of the node, only kept while renaming or dropping needs it
+        let mut name_stack: Vec<String> = Vec::new();
+        let need_paths = self._rename.is_some() || self._drop_folder.is_some();
         
         while let Some((depth, index, len, node)) = file_walker.walk() {
             
@@ -481,6 +712,18 @@ impl FileTree {
                 scale_stack.pop();
                 last_stack.pop();
             }
+            let (is_drop_folder, rename) = if need_paths {
+                name_stack.truncate(depth - 1);
+                name_stack.push(node.name());
+                let path = name_stack.join("/");
+                (
+                    self._drop_folder.as_ref() == Some(&path),
+                    self._rename.as_ref().filter( | rename | rename.path == path).map( | rename | format!("{}_", rename.name))
+                )
+            }
+            else {
+                (false, None)
+            };
             let scale = scale_stack[depth - 1];
             
             // lets store the bg area in the tree
@@ -495,7 +738,7 @@ impl FileTree {
             
             // if we are NOT animating, we need to get change a default color.
             
-            self.node_bg.color = node_draw.animator.last_color("bg.color");
+            self.node_bg.color = if is_drop_folder {cx.color("bg_marked")} else {node_draw.animator.last_color("bg.color")};
             
             let inst = self.node_bg.begin_quad(cx, &Layout {
                 width: Bounds::Fill,
@@ -561,7 +804,7 @@ impl FileTree {
                     self.tree_text.color = self.tree_folder_color;
                     let wleft = cx.get_width_left() - 10.;
                     self.tree_text.wrapping = Wrapping::Ellipsis(wleft);
-                    self.tree_text.draw_text(cx, name);
+                    self.tree_text.draw_text(cx, rename.as_ref().unwrap_or(name));
                     
                     let (new_scale, new_state) = match state {
                         NodeState::Opening(fac) => {
@@ -602,7 +845,7 @@ impl FileTree {
                     else {
                         self.tree_file_color
                     };
-                    self.tree_text.draw_text(cx, name);
+                    self.tree_text.draw_text(cx, rename.as_ref().unwrap_or(name));
                 }
             }
             
@@ -16,6 +16,12 @@ mod style;
 pub use crate::style::*;
 mod filetree;
 pub use crate::filetree::*;
+mod contextmenu;
+pub use crate::contextmenu::*;
+mod palette;
+pub use crate::palette::*;
+mod notices;
+pub use crate::notices::*;
 mod desktopwindow;
 pub use crate::desktopwindow::*;
 mod desktopbutton;
@@ -0,0 +1,101 @@
+use render::*;
+
+// Things the user has to know about that dont belong to a panel, like a settings file that
+// didnt parse or a file operation that only got halfway. They stack up in the bottom right
+// corner on top of everything until clicked away. The owner keeps the messages, so they can
+// come in before there is a window to show them.
+
+#[derive(Clone)]
+pub struct Notices {
+    pub view: View<NoScroll>,
+    pub item_bg: Quad,
+    pub item_layout: Layout,
+    pub text: Text,
+    pub bg_color: Color,
+    pub text_color: Color,
+    // the newest ones when there are more
+    pub max_notices: usize,
+    pub _item_areas: Vec<Area>
+}
+
+impl Notices {
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            view: View {
+                is_overlay: true,
+                ..View::style(cx)
+            },
+            item_bg: Quad {
+                z: 10.,
+                ..Quad::style(cx)
+            },
+            item_layout: Layout {
+                width: Bounds::Fix(420.),
+                height: Bounds::Fix(22.),
+                margin: Margin {l: 0., t: 2., r: 0., b: 0.},
+                padding: Padding {l: 8., t: 6., r: 8., b: 0.},
+                ..Default::default()
+            },
+            text: Text {
+                z: 11.,
+                wrapping: Wrapping::Ellipsis(400.),
+                ..Text::style(cx)
+            },
+            bg_color: color256(110, 40, 40),
+            text_color: cx.color("text_selected_focus"),
+            max_notices: 5,
+            _item_areas: Vec::new()
+        }
+    }
+    
+    // a click takes a notice away
+    pub fn handle_notices(&mut self, cx: &mut Cx, event: &mut Event, notices: &mut Vec<String>) {
+        let first = notices.len().saturating_sub(self.max_notices);
+        for index in 0..self._item_areas.len() {
+            match event.hits(cx, self._item_areas[index], HitOpt::default()) {
+                Event::FingerHover(_) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                },
+                Event::FingerDown(_) => if first + index < notices.len() {
+                    notices.remove(first + index);
+                    self._item_areas.truncate(0);
+                    cx.redraw_child_area(Area::All);
+                    return
+                },
+                _ => ()
+            }
+        }
+    }
+    
+    pub fn draw_notices(&mut self, cx: &mut Cx, notices: &[String]) {
+        self._item_areas.truncate(0);
+        let first = notices.len().saturating_sub(self.max_notices);
+        if first == notices.len() {
+            return
+        }
+        let origin = cx.get_turtle_origin();
+        let width = if let Bounds::Fix(width) = self.item_layout.width {width} else {420.};
+        let height = if let Bounds::Fix(height) = self.item_layout.height {height} else {22.};
+        let total = (notices.len() - first) as f32 * (height + self.item_layout.margin.t);
+        let pos = Vec2 {
+            x: origin.x + (cx.get_width_total() - width - 10.).max(0.),
+            y: origin.y + (cx.get_height_total() - total - 10.).max(0.)
+        };
+        if let Err(()) = self.view.begin_view(cx, Layout {
+            abs_origin: Some(pos),
+            ..Default::default()
+        }) {
+            return
+        }
+        for notice in &notices[first..] {
+            self.item_bg.color = self.bg_color;
+            let inst = self.item_bg.begin_quad(cx, &self.item_layout);
+            self.text.color = self.text_color;
+            self.text.draw_text(cx, notice);
+            let area = self.item_bg.end_quad(cx, &inst);
+            self._item_areas.push(area);
+            cx.turtle_new_line();
+        }
+        self.view.end_view(cx);
+    }
+}
@@ -0,0 +1,475 @@
+use render::*;
+
+// A box on top of the window with a line to type in and the items that match it, best first.
+// What the items are is up to the owner, it gets QueryChanged on every key and hands back new
+// ones with set_items. Like the ContextMenu it takes the keys while it is open without taking
+// the key focus, so whatever had the focus still has it once a pick is made.
+
+#[derive(Clone)]
+pub struct Palette<TItem>
+where TItem: Clone
+{
+    pub view: View<NoScroll>,
+    pub bg: Quad,
+    pub bg_layout: Layout,
+    pub input_layout: Layout,
+    pub item_bg: Quad,
+    pub item_layout: Layout,
+    pub text: Text,
+    pub bg_color: Color,
+    pub input_color: Color,
+    pub selected_color: Color,
+    pub text_color: Color,
+    pub match_color: Color,
+    pub detail_color: Color,
+    // shown while nothing is typed
+    pub placeholder: String,
+    pub max_rows: usize,
+    pub query: String,
+    pub items: Vec<PaletteItem<TItem>>,
+    // the items that matched best first, with the chars of their label that matched
+    pub _ranked: Vec<(usize, Vec<usize>)>,
+    pub _is_open: bool,
+    pub _selected: usize,
+    // the first ranked item on screen
+    pub _scroll: usize,
+    pub _scroll_delta: f32,
+    pub _item_areas: Vec<Area>,
+    pub _bg_area: Area
+}
+
+#[derive(Clone)]
+pub struct PaletteItem<TItem>
+where TItem: Clone
+{
+    pub label: String,
+    // drawn dimmed after the label, a key binding or a line number
+    pub detail: String,
+    pub item: TItem
+}
+
+#[derive(Clone, PartialEq)]
+pub enum PaletteEvent<TItem>
+where TItem: Clone
+{
+    None,
+    QueryChanged,
+    Select(TItem),
+    Closed
+}
+
+impl<TItem> Palette<TItem>
+where TItem: Clone
+{
+    pub fn style(cx: &mut Cx) -> Self {
+        Self {
+            view: View {
+                is_overlay: true,
+                ..View::style(cx)
+            },
+            bg: Quad {
+                z: 10.,
+                ..Quad::style(cx)
+            },
+            bg_layout: Layout {
+                width: Bounds::Fix(500.),
+                height: Bounds::Compute,
+                padding: Padding {l: 4., t: 4., r: 4., b: 4.},
+                ..Default::default()
+            },
+            input_layout: Layout {
+                width: Bounds::Fill,
+                height: Bounds::Fix(24.),
+                padding: Padding {l: 8., t: 7., r: 8., b: 0.},
+                margin: Margin {l: 0., t: 0., r: 0., b: 4.},
+                ..Default::default()
+            },
+            item_bg: Quad {
+                z: 11.,
+                ..Quad::style(cx)
+            },
+            item_layout: Layout {
+                width: Bounds::Fill,
+                height: Bounds::Fix(20.),
+                padding: Padding {l: 8., t: 5., r: 8., b: 0.},
+                ..Default::default()
+            },
+            text: Text {
+                z: 12.,
+                wrapping: Wrapping::None,
+                ..Text::style(cx)
+            },
+            bg_color: cx.color("bg_normal"),
+            input_color: cx.color("bg_selected"),
+            selected_color: cx.color("bg_marked"),
+            text_color: cx.color("text_selected_focus"),
+            match_color: cx.color("code_function"),
+            detail_color: cx.color("text_deselected_focus"),
+            placeholder: String::new(),
+            max_rows: 14,
+            query: String::new(),
+            items: Vec::new(),
+            _ranked: Vec::new(),
+            _is_open: false,
+            _selected: 0,
+            _scroll: 0,
+            _scroll_delta: 0.,
+            _item_areas: Vec::new(),
+            _bg_area: Area::Empty
+        }
+    }
+    
+    // starts with query typed in, the owner sets the items right after
+    pub fn open(&mut self, cx: &mut Cx, query: &str) {
+        self._is_open = true;
+        self.query = query.to_string();
+        self.items.truncate(0);
+        self._ranked.truncate(0);
+        self._item_areas.truncate(0);
+        self.view.redraw_view_area(cx);
+        cx.redraw_child_area(Area::All);
+    }
+    
+    pub fn close(&mut self, cx: &mut Cx) {
+        if self._is_open {
+            self._is_open = false;
+            self._item_areas.truncate(0);
+            self.view.redraw_view_area(cx);
+            cx.redraw_child_area(Area::All);
+        }
+    }
+    
+    pub fn is_open(&self) -> bool {
+        self._is_open
+    }
+    
+    // ranks items on how well their label matches filter, an empty filter keeps their order
+    pub fn set_items(&mut self, cx: &mut Cx, items: Vec<PaletteItem<TItem>>, filter: &str) {
+        let mut ranked: Vec<(i32, usize, Vec<usize>)> = items.iter().enumerate().filter_map( | (index, item) | {
+            fuzzy_match(filter, &item.label).map( | (score, positions) | (score, index, positions))
+        }).collect();
+        if filter.len() > 0 {
+            // the shorter one wins a tie, its the closer match
+            ranked.sort_by( | a, b | b.0.cmp(&a.0).then(items[a.1].label.len().cmp(&items[b.1].label.len())));
+        }
+        self._ranked = ranked.into_iter().map( | (_, index, positions) | (index, positions)).collect();
+        self.items = items;
+        self._selected = 0;
+        self._scroll = 0;
+        self.view.redraw_view_area(cx);
+    }
+    
+    fn select(&mut self, cx: &mut Cx, rank: usize) -> PaletteEvent<TItem> {
+        let item = self._ranked.get(rank).map( | (index, _) | self.items[*index].item.clone());
+        self.close(cx);
+        if let Some(item) = item {PaletteEvent::Select(item)} else {PaletteEvent::Closed}
+    }
+    
+    fn move_selection(&mut self, cx: &mut Cx, delta: isize) {
+        let len = self._ranked.len();
+        if len == 0 {
+            return
+        }
+        self._selected = (self._selected as isize + delta).max(0).min(len as isize - 1) as usize;
+        if self._selected < self._scroll {
+            self._scroll = self._selected;
+        }
+        else if self._selected >= self._scroll + self.max_rows {
+            self._scroll = self._selected + 1 - self.max_rows;
+        }
+        self.view.redraw_view_area(cx);
+    }
+    
+    fn scroll_rows(&mut self, cx: &mut Cx, rows: isize) {
+        let max_scroll = self._ranked.len().max(self.max_rows) - self.max_rows;
+        let scroll = (self._scroll as isize + rows).max(0).min(max_scroll as isize) as usize;
+        if scroll != self._scroll {
+            self._scroll = scroll;
+            self.view.redraw_view_area(cx);
+        }
+    }
+    
+    pub fn handle_palette(&mut self, cx: &mut Cx, event: &mut Event) -> PaletteEvent<TItem> {
+        if !self._is_open {
+            return PaletteEvent::None
+        }
+        for index in 0..self._item_areas.len() {
+            match event.hits(cx, self._item_areas[index], HitOpt::default()) {
+                Event::FingerHover(fe) => {
+                    cx.set_hover_mouse_cursor(MouseCursor::Hand);
+                    let hover = if let HoverState::Out = fe.hover_state {None} else {Some(self._scroll + index)};
+                    if let Some(hover) = hover.filter( | hover | *hover != self._selected) {
+                        self._selected = hover;
+                        self.view.redraw_view_area(cx);
+                    }
+                },
+                Event::FingerUp(fe) => if fe.is_over {
+                    return self.select(cx, self._scroll + index)
+                },
+                _ => ()
+            }
+        }
+        match event.hits(cx, self._bg_area, HitOpt::default()) {
+            Event::FingerDown(_) => return PaletteEvent::None,
+            Event::FingerScroll(fe) => {
+                self._scroll_delta += fe.scroll.y;
+                let rows = (self._scroll_delta / 20.) as isize;
+                if rows != 0 {
+                    self._scroll_delta -= rows as f32 * 20.;
+                    self.scroll_rows(cx, rows);
+                }
+            },
+            _ => ()
+        }
+        match event {
+            Event::FingerDown(fe) if !fe.handled => {
+                self.close(cx);
+                return PaletteEvent::Closed
+            },
+            Event::KeyDown(ke) => {
+                let key_code = ke.key_code.clone();
+                *event = Event::None;
+                let page = self.max_rows as isize;
+                match key_code {
+                    KeyCode::Escape => {
+                        self.close(cx);
+                        return PaletteEvent::Closed
+                    },
+                    KeyCode::ArrowUp => self.move_selection(cx, -1),
+                    KeyCode::ArrowDown => self.move_selection(cx, 1),
+                    KeyCode::PageUp => self.move_selection(cx, -page),
+                    KeyCode::PageDown => self.move_selection(cx, page),
+                    KeyCode::Return => if self._ranked.len() > 0 {
+                        return self.select(cx, self._selected)
+                    },
+                    KeyCode::Backspace => if self.query.pop().is_some() {
+                        self.view.redraw_view_area(cx);
+                        return PaletteEvent::QueryChanged
+                    },
+                    _ => ()
+                }
+            },
+            Event::TextInput(te) => {
+                let input: String = te.input.chars().filter( | c | !c.is_control()).collect();
+                if te.replace_last {
+                    self.query.pop();
+                }
+                *event = Event::None;
+                if input.len() > 0 {
+                    self.query.push_str(&input);
+                    self.view.redraw_view_area(cx);
+                    return PaletteEvent::QueryChanged
+                }
+            },
+            _ => ()
+        }
+        PaletteEvent::None
+    }
+    
+    pub fn draw_palette(&mut self, cx: &mut Cx) {
+        if !self._is_open {
+            return
+        }
+        // centered at the top of what we are drawn in
+        let origin = cx.get_turtle_origin();
+        let width = if let Bounds::Fix(width) = self.bg_layout.width {width} else {500.};
+        let pos = Vec2 {x: origin.x + ((cx.get_width_total() - width) / 2.).max(0.), y: origin.y + 30.};
+        if let Err(()) = self.view.begin_view(cx, Layout {
+            abs_origin: Some(pos),
+            ..Default::default()
+        }) {
+            return
+        }
+        self._item_areas.truncate(0);
+        self.bg.color = self.bg_color;
+        let bg_inst = self.bg.begin_quad(cx, &self.bg_layout);
+        
+        self.item_bg.color = self.input_color;
+        let inst = self.item_bg.begin_quad(cx, &self.input_layout);
+        if self.query.len() == 0 {
+            self.text.color = self.detail_color;
+            self.text.draw_text(cx, &self.placeholder);
+        }
+        else {
+            self.text.color = self.text_color;
+            self.text.draw_text(cx, &format!("{}_", self.query));
+        }
+        self.item_bg.end_quad(cx, &inst);
+        cx.turtle_new_line();
+        
+        let end = (self._scroll + self.max_rows).min(self._ranked.len());
+        for rank in self._scroll..end {
+            let (index, positions) = &self._ranked[rank];
+            let item = &self.items[*index];
+            self.item_bg.color = if rank == self._selected {self.selected_color} else {self.bg_color};
+            let inst = self.item_bg.begin_quad(cx, &self.item_layout);
+            // the label in runs, what matched gets its own color
+            let chars: Vec<char> = item.label.chars().collect();
+            let mut start = 0;
+            while start < chars.len() {
+                let is_match = positions.contains(&start);
+                let mut end = start + 1;
+                while end < chars.len() && positions.contains(&end) == is_match {
+                    end += 1;
+                }
+                self.text.color = if is_match {self.match_color} else {self.text_color};
+                self.text.draw_text(cx, &chars[start..end].iter().collect::<String>());
+                start = end;
+            }
+            if item.detail.len() > 0 {
+                self.text.color = self.detail_color;
+                self.text.draw_text(cx, &format!("  {}", item.detail));
+            }
+            let area = self.item_bg.end_quad(cx, &inst);
+            self._item_areas.push(area);
+            cx.turtle_new_line();
+        }
+        if self._ranked.len() == 0 && self.query.len() > 0 {
+            let inst = self.item_bg.begin_quad(cx, &self.item_layout);
+            self.text.color = self.detail_color;
+            self.text.draw_text(cx, "Nothing matches");
+            self.item_bg.end_quad(cx, &inst);
+        }
+        self._bg_area = self.bg.end_quad(cx, &bg_inst);
+        self.view.end_view(cx);
+    }
+}
+
+// query as a subsequence of text, ignoring case and spaces. A match at the start of a word or
+// in the file name part of a path counts for more, and so do runs of matching chars and chars
+// in the same case. Returns the score with the char indices of text that matched, None if not
+// all of query is in there. Code completion ranks with this too
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i32, Vec<usize>)> {
+    let cased: Vec<char> = query.chars().filter( | c | !c.is_whitespace()).collect();
+    let query: Vec<char> = cased.iter().cloned().map(fold_case).collect();
+    if query.len() == 0 {
+        return Some((0, Vec::new()))
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars.iter().cloned().map(fold_case).collect();
+    // most texts fail here, before the table
+    let mut found = 0;
+    for c in &lower {
+        if found < query.len() && *c == query[found] {
+            found += 1;
+        }
+    }
+    if found < query.len() {
+        return None
+    }
+    let name_start = chars.iter().rposition( | c | *c == '/').map_or(0, | pos | pos + 1);
+    let len = chars.len();
+    // score[i * len + j] is the best for query[..=i] with query[i] on text[j], from[] where query[i - 1] went
+    let mut score = vec![i32::MIN; query.len() * len];
+    let mut from = vec![0; query.len() * len];
+    for i in 0..query.len() {
+        let mut best_prev = i32::MIN;
+        let mut best_prev_j = 0;
+        for j in 0..len {
+            if i > 0 && j > 0 && score[(i - 1) * len + j - 1] > best_prev {
+                best_prev = score[(i - 1) * len + j - 1];
+                best_prev_j = j - 1;
+            }
+            if lower[j] != query[i] {
+                continue;
+            }
+            let mut bonus = 1;
+            if j == 0 || "/_-. ".contains(chars[j - 1]) || chars[j - 1].is_lowercase() && chars[j].is_uppercase() {
+                bonus += 4;
+            }
+            if j >= name_start {
+                bonus += 2;
+            }
+            if chars[j] == cased[i] {
+                bonus += 1;
+            }
+            if i == 0 {
+                score[j] = bonus;
+                continue;
+            }
+            if best_prev != i32::MIN {
+                score[i * len + j] = best_prev + bonus;
+                from[i * len + j] = best_prev_j;
+            }
+            let run = if j > 0 {score[(i - 1) * len + j - 1]} else {i32::MIN};
+            if run != i32::MIN && run + bonus + 5 > score[i * len + j] {
+                score[i * len + j] = run + bonus + 5;
+                from[i * len + j] = j - 1;
+            }
+        }
+    }
+    let last = query.len() - 1;
+    let (mut j, best) = (0..len).map( | j | (j, score[last * len + j])).max_by_key( | (_, score) | *score) ?;
+    if best == i32::MIN {
+        return None
+    }
+    let mut positions = vec![0; query.len()];
+    for i in (0..query.len()).rev() {
+        positions[i] = j;
+        j = from[i * len + j];
+    }
+    Some((best, positions))
+}
+
+// one char for one char so the positions stay those of text, the same on both sides. An I with
+// a dot lowercases to an i and a combining dot, the i is what gets compared
+fn fold_case(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn matches_subsequences_only() {
+        assert!(fuzzy_match("abc", "a_b_c").is_some());
+        assert!(fuzzy_match("acb", "a_b_c").is_none());
+        assert!(fuzzy_match("abcd", "abc").is_none());
+        assert_eq!(fuzzy_match("", "abc"), Some((0, Vec::new())));
+        // spaces in the query dont count, case doesnt either
+        assert_eq!(fuzzy_match("F b", "foo_bar").map( | (_, positions) | positions), Some(vec![0, 4]));
+    }
+    
+    #[test]
+    fn folds_case_the_same_on_both_sides() {
+        assert_eq!(fuzzy_match("İ", "i").map( | (_, positions) | positions), Some(vec![0]));
+        assert_eq!(fuzzy_match("i", "İx").map( | (_, positions) | positions), Some(vec![0]));
+        assert_eq!(fuzzy_match("İx", "aİx").map( | (_, positions) | positions), Some(vec![1, 2]));
+    }
+    
+    #[test]
+    fn word_starts_count_more() {
+        let (word_start, positions) = fuzzy_match("b", "foo_bar").unwrap();
+        assert_eq!(positions, vec![4]);
+        let (inside, _) = fuzzy_match("b", "fooxbar").unwrap();
+        assert!(word_start > inside);
+        // and so does a capital after a lowercase one
+        let (camel, positions) = fuzzy_match("B", "fooBar").unwrap();
+        assert_eq!(positions, vec![3]);
+        assert_eq!(camel, word_start);
+        // the same case counts a little
+        assert!(camel > fuzzy_match("b", "fooBar").unwrap().0);
+        // the start of a word wins over an earlier char in the middle of one
+        assert_eq!(fuzzy_match("b", "abc_bcd").unwrap().1, vec![4]);
+    }
+    
+    #[test]
+    fn file_names_count_more_than_folders() {
+        let (in_name, positions) = fuzzy_match("m", "src/x/main.rs").unwrap();
+        assert_eq!(positions, vec![6]);
+        let (in_folder, _) = fuzzy_match("m", "m/x/a.rs").unwrap();
+        assert!(in_name > in_folder);
+        assert_eq!(fuzzy_match("lib", "lib/src/lib.rs").unwrap().1, vec![8, 9, 10]);
+    }
+    
+    #[test]
+    fn runs_count_more_than_scattered_chars() {
+        let (run, positions) = fuzzy_match("oba", "xobaxoxbxa").unwrap();
+        assert_eq!(positions, vec![1, 2, 3]);
+        let (scattered, _) = fuzzy_match("oba", "xoxbxa").unwrap();
+        assert!(run > scattered);
+        // a run further on beats the first chars that happen to fit
+        assert_eq!(fuzzy_match("ed", "e_x_xed").unwrap().1, vec![5, 6]);
+    }
+}